@@ -8,8 +8,12 @@ use std::env;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::iter;
 use std::path;
+use std::process;
 use std::str;
+use std::thread;
+use std::time;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -28,7 +32,10 @@ pub struct Options {
     #[structopt(subcommand)]
     pub subcmd: Option<Subcmd>,
 
-    /// A query indicating how to transform each record.
+    /// A query indicating how to transform each record.  Steps are separated by '|' and can be
+    /// paths like '.foo.bar[0]', 'map (x) => x.foo', 'select (x) => x.foo',
+    /// 'select (x) => x.foo == 1' or "proto_descriptor('.pkg.Type')", which outputs the field
+    /// metadata of an added protobuf message type.
     pub arg_query: Option<String>,
 
     /// Force stylistic output formatting.  Can be one of 'compact',
@@ -38,24 +45,244 @@ pub struct Options {
     pub flag_format: Option<Format>,
     #[structopt(long = "codec")]
     pub flag_codec: Option<String>,
+    /// A directory of Avro schema files (*.avsc) defining named types that the output Avro schema
+    /// may refer to.
+    #[structopt(long = "avro-schema-dir")]
+    pub flag_avro_schema_dir: Option<path::PathBuf>,
+    /// Read Avro input, including --input-avro-raw datums, with this schema instead of the one it
+    /// was written with, adding defaults for missing fields and promoting types, like int to long.
+    #[structopt(long = "avro-reader-schema", value_name = "FILE")]
+    pub flag_avro_reader_schema: Option<path::PathBuf>,
+    /// Output the underlying values of Avro logical types, like the number of milliseconds of a
+    /// timestamp-millis, instead of decoding them into strings.
+    #[structopt(long = "avro-raw-logical-types")]
+    pub flag_avro_raw_logical_types: bool,
+    /// Finish Avro output blocks once they reach this many bytes before compression.
+    #[structopt(long = "avro-block-size", value_name = "BYTES")]
+    pub flag_avro_block_size: Option<usize>,
+    /// Finish Avro output blocks once they contain this many records.
+    #[structopt(long = "avro-block-records", value_name = "COUNT")]
+    pub flag_avro_block_records: Option<usize>,
+    /// Add a KEY=VALUE pair to the metadata of the Avro output container.  Can be given multiple
+    /// times.
+    #[structopt(long = "avro-metadata", value_name = "KEY=VALUE", number_of_values = 1)]
+    pub flag_avro_metadata: Vec<AvroMetadata>,
+    /// Append the output as Avro to this container file instead of writing it to stdout, using
+    /// the schema, codec and sync marker in its header, so that repeated runs add to the same
+    /// file.  A file that doesn't exist yet is created with the schema of --output-avro.
+    #[structopt(long = "avro-append", value_name = "FILE")]
+    pub flag_avro_append: Option<path::PathBuf>,
+    /// Make output that would otherwise differ between runs, like the sync markers of Avro
+    /// files, deterministic by deriving it from this seed.
+    #[structopt(long = "seed", value_name = "SEED")]
+    pub flag_seed: Option<u64>,
+    /// Use the deterministic encoding of RFC 8949 for CBOR output, with sorted map keys.
+    #[structopt(long = "canonical-cbor")]
+    pub flag_canonical_cbor: bool,
+    /// Only use the string types of the original MessagePack spec for MessagePack output, also
+    /// for bytes, for compatibility with older decoders.
+    #[structopt(long = "message-pack-legacy-raw")]
+    pub flag_message_pack_legacy_raw: bool,
+    /// Convert map keys that are booleans or numbers into strings for MessagePack output, and fail
+    /// on other keys that aren't strings.
+    #[structopt(long = "message-pack-string-keys")]
+    pub flag_message_pack_string_keys: bool,
+
+    /// Read records from the given file instead of stdin, as FORMAT=PATH where FORMAT is one of
+    /// 'avro', 'bson', 'cbor', 'csv', 'frontmatter', 'json', 'jsonc', 'mail', 'message-pack',
+    /// 'parquet', 'pcap', 'raw', 'toml' or 'yaml'.  Can be given multiple times, in which case the
+    /// records of each file are read in order, and each file is only opened once the previous one
+    /// has been read.
+    #[structopt(long = "in", value_name = "FORMAT=PATH", number_of_values = 1)]
+    pub flag_in: Vec<InputFile>,
+
+    /// What to do with maps that have duplicate keys: 'error', or keep the 'first' or 'last' value,
+    /// or 'collect' all values into a sequence.  By default, all entries are kept.
+    #[structopt(long = "duplicate-keys", value_name = "POLICY")]
+    pub flag_duplicate_keys: Option<rq::value::duplicate_keys::Policy>,
+    /// Collapse runs of consecutive equal records, or of records with equal values at the given
+    /// path, into {"value": ..., "count": N} records with the first record of each run, like
+    /// 'uniq -c'.
+    #[structopt(long = "adjacent-count", value_name = "PATH")]
+    pub flag_adjacent_count: Option<Option<rq::value::path::Path>>,
+    /// Coerce input records to the types declared in this JSON Schema file, like strings to
+    /// integers, and fail on records that don't conform, or quarantine them with
+    /// --skip-bad-records or --errors-to.
+    #[structopt(long = "input-schema", value_name = "FILE")]
+    pub flag_input_schema: Option<path::PathBuf>,
+    /// Match the keys of the query path, of --output-protobuf-route paths and of --anonymize
+    /// pointers case-insensitively and after Unicode NFC normalization, so that '.id' also selects 'ID'.
+    #[structopt(long = "fold-keys")]
+    pub flag_fold_keys: bool,
+    /// Only keep the given fields of the records, like a protobuf FieldMask, as dot-separated
+    /// paths separated by commas, e.g. 'id,user.name'.
+    #[structopt(long = "field-mask", value_name = "PATHS")]
+    pub flag_field_mask: Option<rq::value::field_mask::FieldMask>,
+    /// Replace the values at the given JSON pointers, separated by commas like
+    /// '/user/email,/user/id', with their keyed hash, so that they can still be joined on but
+    /// not read.
+    #[structopt(long = "anonymize", value_name = "POINTERS")]
+    pub flag_anonymize: Option<rq::value::anonymize::Pointers>,
+    /// The secret key to hash values with for --anonymize, as 'env:NAME' to read it from an
+    /// environment variable, or 'file:PATH' to read it from a file.
+    #[structopt(long = "key", value_name = "KEY")]
+    pub flag_key: Option<String>,
+    /// Normalize all recognized date/time strings in the records, and the numeric epochs at the
+    /// paths given with --epoch-field, to one representation, either 'rfc3339' or 'epoch-millis'.
+    #[structopt(long = "normalize-timestamps", value_name = "REPRESENTATION")]
+    pub flag_normalize_timestamps: Option<rq::value::timestamp::Representation>,
+    /// The path of a value whose numbers are epochs in seconds or milliseconds, for
+    /// --normalize-timestamps.  Other numbers are left alone.  Can be given multiple times.
+    #[structopt(long = "epoch-field", value_name = "PATH", number_of_values = 1)]
+    pub flag_epoch_field: Vec<rq::value::path::Path>,
+    /// The time zone of timestamps without an explicit offset when normalizing timestamps, as
+    /// 'UTC' or an offset like '+02:00'.
+    #[structopt(long = "assume-tz", value_name = "TZ", default_value = "UTC")]
+    pub flag_assume_tz: String,
 
     /// Input is an Apache Avro container file.
     #[structopt(short = "a", long = "input-avro")]
     pub flag_input_avro: bool,
+    /// Input is a series of Avro datums without a container, like Kafka messages, either bare or
+    /// in the single-object encoding, all written with the schema in this file.
+    #[structopt(long = "input-avro-raw", value_name = "SCHEMA")]
+    pub flag_input_avro_raw: Option<path::PathBuf>,
+    /// Input is Kafka records in the Confluent wire format with Avro datums written with the schema
+    /// in this file, each prefixed with its length as a 4-byte big-endian number, like
+    /// "kcat -C -f '%R%s'" writes them.
+    #[structopt(long = "input-kafka-avro", value_name = "SCHEMA")]
+    pub flag_input_kafka_avro: Option<path::PathBuf>,
+    /// Input is Kafka records in the Confluent wire format with protobuf messages of the specified
+    /// type, each prefixed with its length as a 4-byte big-endian number, like
+    /// "kcat -C -f '%R%s'" writes them.
+    #[structopt(long = "input-kafka-protobuf", value_name = "TYPE")]
+    pub flag_input_kafka_protobuf: Option<String>,
+    /// Input is a series of Cap'n Proto messages of the specified struct type, like
+    /// 'Person' or 'person.capnp:Person', from the schemas added with 'rq capnp add'.
+    #[structopt(long = "input-capnp", value_name = "TYPE")]
+    pub flag_input_capnp: Option<String>,
+    /// Input is a series of Thrift structs encoded with a PROTOCOL, 'binary' or 'compact'.
+    /// Without --thrift-idl, structs are maps from field IDs to values.
+    #[structopt(long = "input-thrift", value_name = "PROTOCOL")]
+    pub flag_input_thrift: Option<rq::value::thrift::Protocol>,
+    /// The .thrift IDL file to name the fields and enum values of --input-thrift structs with.
+    #[structopt(long = "thrift-idl", value_name = "FILE")]
+    pub flag_thrift_idl: Option<path::PathBuf>,
+    /// The struct type of --input-thrift structs in the --thrift-idl, like 'Person'.
+    #[structopt(long = "thrift-struct", value_name = "NAME")]
+    pub flag_thrift_struct: Option<String>,
+    /// Input is a FlatBuffer of the specified table type, like 'Monster' or 'MyGame.Monster',
+    /// from the schemas added with 'rq flatbuffers add'.
+    #[structopt(long = "input-flatbuffers", value_name = "TYPE")]
+    pub flag_input_flatbuffers: Option<String>,
+    /// The --input-flatbuffers input is a series of FlatBuffers, each prefixed with its size as a
+    /// 4-byte little-endian number.
+    #[structopt(long = "flatbuffers-size-prefixed")]
+    pub flag_flatbuffers_size_prefixed: bool,
+    /// Input is a series of BSON documents, like the .bson files written by mongodump.
+    #[structopt(short = "b", long = "input-bson")]
+    pub flag_input_bson: bool,
     /// Input is a series of CBOR values.
     #[structopt(short = "c", long = "input-cbor")]
     pub flag_input_cbor: bool,
     /// Input is white-space separated JSON values (default).
     #[structopt(short = "j", long = "input-json")]
     pub flag_input_json: bool,
+    /// Input is JSON values that may contain comments, trailing commas, unquoted keys and
+    /// single-quoted strings.
+    #[structopt(long = "input-jsonc")]
+    pub flag_input_jsonc: bool,
+    /// Stream the elements of the array at this JSON pointer, like '/items', as individual
+    /// records, for JSON documents too large to fit in memory.
+    #[structopt(long = "json-pointer", value_name = "POINTER")]
+    pub flag_json_pointer: Option<String>,
+    /// Yield the elements of top-level arrays in JSON and YAML input as individual records.
+    #[structopt(long = "spread-top-level")]
+    pub flag_spread_top_level: bool,
     /// Input is CSV.
     #[structopt(short = "v", long = "input-csv")]
     pub flag_input_csv: bool,
+    /// Parse numbers in CSV input according to the conventions of a locale like 'de_DE', allowing
+    /// thousands separators, decimal commas and currency symbols.
+    #[structopt(long = "csv-locale", value_name = "LOCALE")]
+    pub flag_csv_locale: Option<String>,
+    /// Input is lines of fixed-width columns, as described by --layout.
+    #[structopt(long = "input-fixed-width")]
+    pub flag_input_fixed_width: bool,
+    /// A JSON file with the columns of --input-fixed-width records, like
+    /// '[{"name": "id", "offset": 0, "width": 6, "type": "integer"}]'.
+    #[structopt(long = "layout", value_name = "FILE")]
+    pub flag_layout: Option<path::PathBuf>,
+    /// Input is a document with YAML or TOML front matter, like a Markdown post, read as a record
+    /// with the front matter under 'meta' and the rest of the document under 'body'.
+    #[structopt(long = "input-frontmatter")]
+    pub flag_input_frontmatter: bool,
+    /// Read the history of the git repository at the given path, or the current directory, as
+    /// one record per commit with its hash, author, date, message and changed files, instead of
+    /// reading stdin.
+    #[structopt(long = "input-git-log", value_name = "REPO")]
+    pub flag_input_git_log: Option<Option<path::PathBuf>>,
+    /// Input is an mbox file or a single EML message, read as one record per message with its
+    /// headers, the metadata of its parts and its decoded text body.
+    #[structopt(long = "input-mail")]
+    pub flag_input_mail: bool,
     /// Input is formatted as MessagePack.
     #[structopt(short = "m", long = "input-message-pack")]
     pub flag_input_message_pack: bool,
     #[structopt(short = "p", long = "input-protobuf")]
     pub flag_input_protobuf: Option<String>,
+    /// Input is a stream of messages of the specified type that are each prefixed with their
+    /// length as a varint, like Java's 'writeDelimitedTo' writes them.
+    #[structopt(long = "input-protobuf-delimited", value_name = "TYPE")]
+    pub flag_input_protobuf_delimited: Option<String>,
+    /// Input is a single message of the specified type in the protobuf text format, like
+    /// 'protoc --decode' prints it.
+    #[structopt(long = "input-protobuf-text", value_name = "TYPE")]
+    pub flag_input_protobuf_text: Option<String>,
+    /// Input is a stream of gRPC length-prefixed frames holding the requests of the specified
+    /// method, like '/foo.Greeter/Hello'.
+    #[structopt(long = "input-grpc-frames", value_name = "METHOD")]
+    pub flag_input_grpc_frames: Option<String>,
+    /// Decode the responses of the --input-grpc-frames method instead of its requests.
+    #[structopt(long = "grpc-responses")]
+    pub flag_grpc_responses: bool,
+    /// Add the name of the active member of each protobuf oneof to messages, under the name of
+    /// the oneof.
+    #[structopt(long = "annotate-oneofs")]
+    pub flag_annotate_oneofs: bool,
+    /// Don't redact protobuf fields that are marked as sensitive or listed in redactions.toml.
+    #[structopt(long = "no-redaction")]
+    pub flag_no_redaction: bool,
+    /// What to do with protobuf enum numbers that are not declared in the schema: output the
+    /// 'number', a 'string' like "UNKNOWN(5)", or fail with an 'error'.
+    #[structopt(
+        long = "unknown-enums",
+        value_name = "POLICY",
+        default_value = "number"
+    )]
+    pub flag_unknown_enums: rq::value::protobuf::UnknownEnums,
+    /// How to output the values of protobuf and Avro enums: the bare 'symbol', the name
+    /// 'qualified' with the full name of the enum type, or the 'number'.
+    #[structopt(long = "enum-style", value_name = "STYLE", default_value = "symbol")]
+    pub flag_enum_style: rq::value::enums::Style,
+    /// Decode the protobuf well-known types to their natural values, like the canonical JSON
+    /// mapping of protobuf does: Timestamp and Duration as strings, Struct as a map, the
+    /// wrappers as the value they wrap and Any as the message it contains.
+    #[structopt(long = "unwrap-well-known-types")]
+    pub flag_unwrap_well_known_types: bool,
+    /// Keep the fields of protobuf messages that aren't in the schema in an "@unknown" map from
+    /// their numbers to their encoded bytes, so that schema drift can be detected and the
+    /// messages can be written as protobuf again without losing them.
+    #[structopt(long = "preserve-unknown-fields")]
+    pub flag_preserve_unknown_fields: bool,
+    /// Input is a Parquet file, read as one record per row.  The whole file is read into memory,
+    /// since Parquet keeps its metadata at the end.
+    #[structopt(long = "input-parquet")]
+    pub flag_input_parquet: bool,
+    /// Input is a pcap or pcapng packet capture, read as one record per packet with its link,
+    /// network and transport headers and its payload.
+    #[structopt(long = "input-pcap")]
+    pub flag_input_pcap: bool,
     /// Input is plain text.
     #[structopt(short = "r", long = "input-raw")]
     pub flag_input_raw: bool,
@@ -68,38 +295,203 @@ pub struct Options {
 
     #[structopt(short = "A", long = "output-avro")]
     pub flag_output_avro: Option<String>,
+    /// Output is a series of BSON documents, like the .bson files that mongorestore reads.
+    #[structopt(short = "B", long = "output-bson")]
+    pub flag_output_bson: bool,
     #[structopt(short = "C", long = "output-cbor")]
     pub flag_output_cbor: bool,
     #[structopt(short = "J", long = "output-json")]
     pub flag_output_json: bool,
+    /// Write records that are strings without JSON quotes or escapes, for use in shell scripts.
+    #[structopt(long = "raw-output")]
+    pub flag_raw_output: bool,
+    /// Output 64-bit integers as strings, like the canonical JSON mapping of protobuf, so that
+    /// JSON output is safe for JavaScript: all 64-bit fields of protobuf input, and integers that
+    /// JavaScript numbers can't represent exactly in JSON output.
+    #[structopt(long = "int64-as-string")]
+    pub flag_int64_as_string: bool,
+    /// Interpret and write control records like {"@rq":"error","message":"..."} and
+    /// {"@rq":"eof"} in JSON streams, so that errors propagate between rq instances chained in a
+    /// pipeline.
+    #[structopt(long = "control-records")]
+    pub flag_control_records: bool,
     #[structopt(short = "R", long = "output-raw")]
     pub flag_output_raw: bool,
     #[structopt(short = "V", long = "output-csv")]
     pub flag_output_csv: bool,
     #[structopt(short = "M", long = "output-message-pack")]
     pub flag_output_message_pack: bool,
+    /// Output is a Parquet file with one row per record, which must be maps.  The schema is
+    /// inferred from the first records unless --parquet-schema is given, and --codec can be
+    /// 'null', 'snappy' (the default) or 'gzip'.
+    #[structopt(long = "output-parquet")]
+    pub flag_output_parquet: bool,
+    /// A file with the schema of Parquet output in the text format of parquet-mr, like
+    /// 'message m { required int64 id; optional binary name (STRING); }'.
+    #[structopt(long = "parquet-schema", value_name = "FILE")]
+    pub flag_parquet_schema: Option<path::PathBuf>,
+    /// Infer the schema of Parquet output from this many records, which are kept in memory until
+    /// then.
+    #[structopt(
+        long = "parquet-infer-records",
+        value_name = "COUNT",
+        default_value = "1000"
+    )]
+    pub flag_parquet_infer_records: usize,
+    /// Output is a series of length-delimited protobuf messages of the specified type, like
+    /// '.foo.Bar', which must have been added with 'rq protobuf add'.
     #[structopt(short = "P", long = "output-protobuf")]
     pub flag_output_protobuf: Option<String>,
+    /// Output is a series of length-delimited google.protobuf.Struct messages, for producing
+    /// protobuf without a schema.
+    #[structopt(long = "output-protobuf-struct")]
+    pub flag_output_protobuf_struct: bool,
+    /// Output is a series of messages of the specified type in the protobuf text format,
+    /// separated by blank lines.
+    #[structopt(long = "output-protobuf-text", value_name = "TYPE")]
+    pub flag_output_protobuf_text: Option<String>,
+    /// Output is JSON in the proto3 JSON mapping of messages of the specified type, with
+    /// lowerCamelCase field names, 64-bit integers as strings, bytes as base64 and the special
+    /// forms of the well-known types, like RFC 3339 strings for timestamps.
+    #[structopt(long = "output-protobuf-json", value_name = "TYPE")]
+    pub flag_output_protobuf_json: Option<String>,
+    /// Output is a series of length-delimited protobuf messages whose type depends on the value
+    /// at this path in each record, like '.type', as mapped by --route-map.
+    #[structopt(long = "output-protobuf-route", value_name = "PATH")]
+    pub flag_output_protobuf_route: Option<rq::value::path::Path>,
+    /// Comma-separated VALUE=MESSAGE pairs that map route values to message types for
+    /// --output-protobuf-route, like 'click=.acme.Click,view=.acme.View'.
+    #[structopt(long = "route-map", value_name = "ROUTES", use_delimiter = true)]
+    pub flag_route_map: Vec<Route>,
     #[structopt(short = "T", long = "output-toml")]
     pub flag_output_toml: bool,
+    /// Output is an aligned plain-text table, for previewing records in a terminal.
+    #[structopt(long = "output-table")]
+    pub flag_output_table: bool,
+    /// Comma-separated columns to show in table output, in order.
+    #[structopt(long = "columns", value_name = "COLUMNS", use_delimiter = true)]
+    pub flag_columns: Option<Vec<String>>,
+    /// Truncate cells in table output that are wider than this many characters.
+    #[structopt(long = "max-column-width", value_name = "WIDTH")]
+    pub flag_max_column_width: Option<usize>,
     #[structopt(short = "Y", long = "output-yaml")]
     pub flag_output_yaml: bool,
+    /// Skip records that can't be written in the output format, for example because they don't
+    /// match the Avro schema, instead of stopping at the first one.
+    #[structopt(long = "skip-bad-records")]
+    pub flag_skip_bad_records: bool,
+    /// Write records that don't match --input-schema or can't be written in the output format to
+    /// this file as JSON lines, with the error and the record, for reprocessing later.  Only these
+    /// transform and output failures are captured: the records are written as they were decoded,
+    /// not as their original input bytes, and input that can't be decoded still stops rq.
+    /// Implies --skip-bad-records.
+    #[structopt(long = "errors-to", value_name = "FILE")]
+    pub flag_errors_to: Option<path::PathBuf>,
+    /// Read every record back from the output format and compare it with the original, and
+    /// print a summary of the changes, like rounded floats or re-encoded bytes, at the end.
+    #[structopt(long = "check-roundtrip")]
+    pub flag_check_roundtrip: bool,
+    /// Like --check-roundtrip, but fail if any record changed.
+    #[structopt(long = "strict-roundtrip")]
+    pub flag_strict_roundtrip: bool,
+    /// Turn warnings about the data into errors, for validating data in CI: protobuf schemas
+    /// with types that aren't defined, protobuf fields that aren't in the schema, lossy round
+    /// trips found by --check-roundtrip and records skipped by --skip-bad-records.
+    #[structopt(long = "strict")]
+    pub flag_strict: bool,
+    /// What to do with NaN and infinite floats in JSON, CSV, YAML and TOML output: 'error', 'null'
+    /// or 'string' (for "NaN", "Infinity" and "-Infinity").  By default, JSON outputs null and the
+    /// other formats use their own notation.
+    #[structopt(long = "nonfinite", value_name = "POLICY")]
+    pub flag_nonfinite: Option<rq::value::nonfinite::Policy>,
+    /// What to do with values that TOML or CSV output can't represent, like null in TOML or
+    /// nested maps in CSV: 'error', 'skip-field' to leave them out, or 'stringify' to output them
+    /// as JSON strings.
+    #[structopt(
+        long = "on-unrepresentable",
+        value_name = "POLICY",
+        default_value = "error"
+    )]
+    pub flag_on_unrepresentable: rq::value::unrepresentable::Policy,
+    /// Write repeated maps and sequences in YAML output only once, and refer back to them with
+    /// anchors and aliases elsewhere.
+    #[structopt(long = "yaml-anchors")]
+    pub flag_yaml_anchors: bool,
 
     #[structopt(short = "l", long = "log")]
     pub flag_log: Option<String>,
     #[structopt(short = "q", long = "quiet")]
     pub flag_quiet: bool,
+    /// Show the output in a pager when writing to a terminal; the pager is $RQ_PAGER, the
+    /// contents of the 'pager' config file, $PAGER or 'less', in that order.  Paging is also
+    /// enabled by creating the 'pager' config file.
+    #[structopt(long = "pager")]
+    pub flag_pager: bool,
+    /// Never show the output in a pager, even if --pager is given.
+    #[structopt(long = "no-pager")]
+    pub flag_no_pager: bool,
     #[structopt(long = "trace")]
     pub flag_trace: bool,
+    /// Write a report of the run at the end of it, with the number of records read, written and
+    /// skipped, the errors by category, the timing, the input and output sizes and the
+    /// fingerprints of the schemas used.  The only FORMAT is 'json'.
+    #[structopt(long = "report", value_name = "FORMAT")]
+    pub flag_report: Option<rq::report::Format>,
+    /// Write the --report to this file instead of stderr, like '/dev/fd/3'.
+    #[structopt(long = "report-to", value_name = "FILE")]
+    pub flag_report_to: Option<path::PathBuf>,
 }
 
 #[derive(Debug, StructOpt)]
 pub enum Subcmd {
+    #[structopt(name = "capnp")]
+    Capnp {
+        #[structopt(subcommand)]
+        subcmd: CapnpSubcmd,
+    },
+    #[structopt(name = "flatbuffers")]
+    Flatbuffers {
+        #[structopt(subcommand)]
+        subcmd: FlatbuffersSubcmd,
+    },
     #[structopt(name = "protobuf")]
     Protobuf {
         #[structopt(subcommand)]
         subcmd: ProtobufSubcmd,
     },
+    /// Run a recipe, a pipeline defined in the recipes.toml config file, on the given file or on
+    /// stdin.
+    #[structopt(name = "run")]
+    Run {
+        recipe: String,
+        file: Option<path::PathBuf>,
+    },
+    /// Watch a directory for new files, and convert each of them with a recipe into a file with
+    /// the same name in the output directory.
+    #[structopt(name = "watch")]
+    Watch(WatchOptions),
+    #[structopt(name = "schema")]
+    Schema {
+        #[structopt(subcommand)]
+        subcmd: SchemaSubcmd,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub struct WatchOptions {
+    dir: path::PathBuf,
+    /// The recipe to convert files with.
+    #[structopt(long = "recipe")]
+    recipe: String,
+    /// The directory to write converted files to.
+    #[structopt(long = "out-dir", value_name = "DIR")]
+    out_dir: path::PathBuf,
+    /// The file extension of converted files; the default is based on the output format.
+    #[structopt(long = "extension")]
+    extension: Option<String>,
+    /// How often to check for new files, in milliseconds.
+    #[structopt(long = "interval", value_name = "MILLIS", default_value = "1000")]
+    interval: u64,
 }
 
 #[derive(Debug, StructOpt)]
@@ -110,6 +502,98 @@ pub enum ProtobufSubcmd {
         #[structopt(short = "b", long = "base")]
         base: Option<path::PathBuf>,
     },
+    /// Output the default instance of a message type, like `.pkg.Type`, as a single record.
+    #[structopt(name = "default")]
+    Default { message: String },
+    /// Fetch the descriptors of all services of a gRPC server at an address like
+    /// `localhost:50051` with server reflection, so that their message types can be used without
+    /// adding proto files.  Only plaintext connections are supported.
+    #[structopt(name = "fetch")]
+    Fetch { address: String },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CapnpSubcmd {
+    /// Add a Cap'n Proto schema file, which is compiled with the capnp tool when it is used.
+    #[structopt(name = "add")]
+    Add {
+        schema: path::PathBuf,
+        #[structopt(short = "b", long = "base")]
+        base: Option<path::PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum FlatbuffersSubcmd {
+    /// Add a binary FlatBuffers schema file, like 'flatc --binary --schema' writes.
+    #[structopt(name = "add")]
+    Add {
+        schema: path::PathBuf,
+        #[structopt(short = "b", long = "base")]
+        base: Option<path::PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SchemaSubcmd {
+    /// Print a CREATE TABLE statement for an Avro record schema or a protobuf message type.
+    #[structopt(name = "ddl")]
+    Ddl(DdlOptions),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DdlOptions {
+    /// The Avro schema file to convert.
+    #[structopt(long = "avro", value_name = "FILE", conflicts_with = "protobuf")]
+    avro: Option<path::PathBuf>,
+    /// The protobuf message type to convert, like `.pkg.Type`, from the added schemas.
+    #[structopt(long = "protobuf", value_name = "TYPE", required_unless = "avro")]
+    protobuf: Option<String>,
+    /// The SQL dialect: 'postgres', 'mysql' or 'sqlite'.
+    #[structopt(long = "dialect", default_value = "postgres")]
+    dialect: rq::ddl::Dialect,
+    /// How to store nested records: 'flatten' into one column per field, or 'json'.
+    #[structopt(long = "nested", default_value = "flatten")]
+    nested: rq::ddl::Nested,
+    /// The table name; the default is the name of the record or message.
+    #[structopt(long = "table")]
+    table: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AvroMetadata {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Route {
+    pub value: String,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InputFile {
+    pub format: InputFormat,
+    pub path: path::PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum InputFormat {
+    Avro,
+    Bson,
+    Cbor,
+    Csv,
+    Frontmatter,
+    Json,
+    Jsonc,
+    Mail,
+    MessagePack,
+    Parquet,
+    Pcap,
+    Raw,
+    Toml,
+    Yaml,
 }
 
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
@@ -136,11 +620,51 @@ fn main() {
 
     setup_log(args.flag_log.as_ref().map(String::as_ref), args.flag_quiet);
 
-    main_with_args(&args).unwrap_or_else(|e| log_error(&args, &e));
+    let report = rq::report::Report::new();
+    let result = main_with_args(&args, &report);
+    if let Some(format) = args.flag_report {
+        write_report(&args, &report, format, &result).unwrap_or_else(|e| log_error(&args, &e));
+    }
+    result.unwrap_or_else(|e| log_error(&args, &e));
+}
+
+fn write_report(
+    args: &Options,
+    report: &rq::report::Report,
+    format: rq::report::Format,
+    result: &rq::error::Result<()>,
+) -> rq::error::Result<()> {
+    let mut w: Box<dyn io::Write> = match args.flag_report_to {
+        Some(ref path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::stderr()),
+    };
+    match format {
+        rq::report::Format::Json => serde_json::to_writer(&mut w, &report.to_json(result))?,
+    }
+    w.write_all(b"\n")?;
+    Ok(w.flush()?)
 }
 
-fn main_with_args(args: &Options) -> rq::error::Result<()> {
+fn main_with_args(args: &Options, report: &rq::report::Report) -> rq::error::Result<()> {
     match args.subcmd {
+        Some(Subcmd::Capnp { ref subcmd }) => match subcmd {
+            CapnpSubcmd::Add { schema, base } => {
+                let base = base
+                    .as_ref()
+                    .map_or_else(|| path::Path::new("."), |p| p.as_path());
+                let paths = rq::config::Paths::new()?;
+                rq::capnp_index::add_file(&paths, base, schema)
+            }
+        },
+        Some(Subcmd::Flatbuffers { ref subcmd }) => match subcmd {
+            FlatbuffersSubcmd::Add { schema, base } => {
+                let base = base
+                    .as_ref()
+                    .map_or_else(|| path::Path::new("."), |p| p.as_path());
+                let paths = rq::config::Paths::new()?;
+                rq::flatbuffers_index::add_file(&paths, base, schema)
+            }
+        },
         Some(Subcmd::Protobuf { ref subcmd }) => match subcmd {
             ProtobufSubcmd::Add { schema, base } => {
                 let base = base
@@ -149,39 +673,464 @@ fn main_with_args(args: &Options) -> rq::error::Result<()> {
                 let paths = rq::config::Paths::new()?;
                 rq::proto_index::add_file(&paths, base, schema)
             }
+            ProtobufSubcmd::Default { message } => {
+                let paths = rq::config::Paths::new()?;
+                let descriptors = load_descriptors(args, report, &paths)?;
+                let source = rq::value::protobuf::default_source(&descriptors, message)?;
+                run_source(args, report, source, &mut io::stdout())
+            }
+            ProtobufSubcmd::Fetch { address } => {
+                let paths = rq::config::Paths::new()?;
+                rq::proto_index::fetch_server(&paths, address)
+            }
+        },
+        Some(Subcmd::Run {
+            ref recipe,
+            ref file,
+        }) => run_recipe(recipe, file.as_deref(), report),
+        Some(Subcmd::Watch(ref options)) => watch(args, report, options),
+        Some(Subcmd::Schema { ref subcmd }) => match subcmd {
+            SchemaSubcmd::Ddl(options) => print_ddl(args, report, options),
         },
-        None => run(args),
+        None => run(args, report),
+    }
+}
+
+fn print_ddl(
+    args: &Options,
+    report: &rq::report::Report,
+    options: &DdlOptions,
+) -> rq::error::Result<()> {
+    let (name, fields) = match (&options.avro, &options.protobuf) {
+        (Some(path), _) => {
+            let schema = read_avro_schema_from_file(path, args.flag_avro_schema_dir.as_deref())?;
+            rq::ddl::fields_from_avro(&schema)?
+        }
+        (None, Some(message_name)) => {
+            let paths = rq::config::Paths::new()?;
+            let descriptors = load_descriptors(args, report, &paths)?;
+            rq::ddl::fields_from_protobuf(&descriptors, message_name)?
+        }
+        (None, None) => unreachable!("--protobuf is required unless --avro is given"),
+    };
+    let ddl_options = rq::ddl::Options {
+        dialect: options.dialect,
+        nested: options.nested,
+    };
+    let table = options.table.as_ref().unwrap_or(&name);
+    print!("{}", rq::ddl::create_table(table, &fields, ddl_options));
+    Ok(())
+}
+
+fn run_recipe(
+    name: &str,
+    file: Option<&path::Path>,
+    report: &rq::report::Report,
+) -> rq::error::Result<()> {
+    let args = recipe_options(name)?;
+    match file {
+        Some(file) => run_input(
+            &args,
+            report,
+            io::BufReader::new(fs::File::open(file)?),
+            &mut io::stdout(),
+        ),
+        None => run(&args, report),
+    }
+}
+
+fn recipe_options(name: &str) -> rq::error::Result<Options> {
+    use structopt::StructOpt;
+
+    let paths = rq::config::Paths::new()?;
+    let recipes = rq::recipe::load(&paths)?;
+    let recipe = recipes.get(name).ok_or_else(|| {
+        rq::error::Error::Message(format!(
+            "unknown recipe {:?}; recipes are defined in {:?}",
+            name,
+            paths.preferred_config("recipes.toml")
+        ))
+    })?;
+
+    let args = Options::from_iter_safe(iter::once("rq".to_owned()).chain(recipe.to_args()))
+        .map_err(|e| {
+            rq::error::Error::Message(format!("invalid recipe {:?}: {}", name, e.message))
+        })?;
+    if args.subcmd.is_some() {
+        return Err(rq::error::Error::Message(format!(
+            "invalid recipe {:?}: recipes can't run subcommands",
+            name
+        )));
+    }
+    Ok(args)
+}
+
+fn watch(
+    args: &Options,
+    report: &rq::report::Report,
+    watch: &WatchOptions,
+) -> rq::error::Result<()> {
+    let mut recipe_args = recipe_options(&watch.recipe)?;
+    // The output goes to files, so there is no terminal to infer colors from
+    recipe_args.flag_format.get_or_insert(Format::Indented);
+    let extension = watch
+        .extension
+        .clone()
+        .unwrap_or_else(|| output_extension(&recipe_args).to_owned());
+
+    fs::create_dir_all(&watch.out_dir)?;
+    let mut watcher = rq::watch::Watcher::new(&watch.dir);
+    info!(
+        "Watching {:?} for files to convert with recipe {:?}",
+        watch.dir, watch.recipe
+    );
+
+    loop {
+        for file in watcher.poll()? {
+            let name = file.file_stem().unwrap_or_default().to_string_lossy();
+            let target = watch.out_dir.join(format!("{}.{}", name, extension));
+            match convert_file(&recipe_args, report, &file, &target) {
+                Ok(()) => info!("Converted {:?} to {:?}", file, target),
+                Err(e) => {
+                    error!("Failed to convert {:?}", file);
+                    log_error(args, &e);
+                }
+            }
+        }
+        thread::sleep(time::Duration::from_millis(watch.interval));
+    }
+}
+
+fn convert_file(
+    args: &Options,
+    report: &rq::report::Report,
+    file: &path::Path,
+    target: &path::Path,
+) -> rq::error::Result<()> {
+    // Write to a hidden file first, so that nobody sees half-converted files
+    let tmp = target.with_file_name(format!(
+        ".{}.tmp",
+        target.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let result = (|| {
+        let input = io::BufReader::new(fs::File::open(file)?);
+        let mut output = io::BufWriter::new(fs::File::create(&tmp)?);
+        run_input(args, report, input, &mut output)?;
+        output.flush()?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => Ok(fs::rename(&tmp, target)?),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+fn output_extension(args: &Options) -> &'static str {
+    if args.flag_output_avro.is_some() || args.flag_avro_append.is_some() {
+        "avro"
+    } else if args.flag_output_bson {
+        "bson"
+    } else if args.flag_output_cbor {
+        "cbor"
+    } else if args.flag_output_csv {
+        "csv"
+    } else if args.flag_output_message_pack {
+        "msgpack"
+    } else if args.flag_output_parquet {
+        "parquet"
+    } else if args.flag_output_protobuf.is_some()
+        || args.flag_output_protobuf_struct
+        || args.flag_output_protobuf_route.is_some()
+    {
+        "pb"
+    } else if args.flag_output_protobuf_text.is_some() {
+        "txtpb"
+    } else if args.flag_output_raw || args.flag_output_table {
+        "txt"
+    } else if args.flag_output_toml {
+        "toml"
+    } else if args.flag_output_yaml {
+        "yaml"
+    } else {
+        "json"
     }
 }
 
-fn run(args: &Options) -> rq::error::Result<()> {
+fn run(args: &Options, report: &rq::report::Report) -> rq::error::Result<()> {
     let stdin = io::stdin();
-    let mut input = stdin.lock();
+    let mut pager = match start_pager(args)? {
+        Some(pager) => pager,
+        None => return run_input(args, report, stdin.lock(), &mut io::stdout()),
+    };
+
+    let result = {
+        let mut output = io::BufWriter::new(pager.stdin.take().unwrap());
+        run_input(args, report, stdin.lock(), &mut output).and_then(|()| Ok(output.flush()?))
+    };
+    pager.wait()?;
+    match result {
+        // The pager was closed before all output was shown
+        Err(rq::error::Error::Io(ref e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+fn start_pager(args: &Options) -> rq::error::Result<Option<process::Child>> {
+    if args.flag_no_pager || !atty::is(atty::Stream::Stdout) {
+        return Ok(None);
+    }
+
+    let configured = rq::config::Paths::new()
+        .ok()
+        .and_then(|paths| fs::read_to_string(paths.preferred_config("pager")).ok());
+    if !args.flag_pager && configured.is_none() {
+        return Ok(None);
+    }
+
+    let command = match pager_command(
+        env::var("RQ_PAGER").ok(),
+        configured,
+        env::var("PAGER").ok(),
+    ) {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+
+    debug!("Starting pager {:?}", command);
+    let mut pager = process::Command::new("sh");
+    pager.arg("-c").arg(&command).stdin(process::Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        // Like git: quit if the output fits on one screen, keep colors, and don't clear the screen
+        pager.env("LESS", "FRX");
+    }
+    Ok(Some(pager.spawn()?))
+}
+
+/// Picks the first pager command that is set, or none if that is `cat`.
+fn pager_command(
+    rq_pager: Option<String>,
+    configured: Option<String>,
+    pager: Option<String>,
+) -> Option<String> {
+    let command = rq_pager
+        .into_iter()
+        .chain(configured)
+        .chain(pager)
+        .map(|command| command.trim().to_owned())
+        .find(|command| !command.is_empty())
+        .unwrap_or_else(|| "less".to_owned());
+    if command == "cat" {
+        None
+    } else {
+        Some(command)
+    }
+}
 
-    if let Some(ref name) = args.flag_input_protobuf {
+fn run_input<R>(
+    args: &Options,
+    report: &rq::report::Report,
+    input: R,
+    output: &mut dyn io::Write,
+) -> rq::error::Result<()>
+where
+    R: io::Read,
+{
+    let mut input = report.count_input(input);
+    let output = &mut report.count_output(output);
+    if !args.flag_in.is_empty() {
+        let sources = args
+            .flag_in
+            .iter()
+            .map(|input| open_input_file(args, report, input));
+        run_source(args, report, rq::value::concat::source(sources), output)
+    } else if let Some(ref repo) = args.flag_input_git_log {
+        let repo = repo.as_deref().unwrap_or_else(|| path::Path::new("."));
+        run_source(args, report, rq::value::git::log_source(repo)?, output)
+    } else if let Some(ref name) = args.flag_input_protobuf {
         let paths = rq::config::Paths::new()?;
-        let proto_descriptors = load_descriptors(&paths)?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let pool = rq::value::protobuf::pool::DescriptorPool::from_file_set(&descriptors_proto)?;
+        let proto_descriptors = input_descriptors(args, &pool, name)?;
         let stream = protobuf::CodedInputStream::new(&mut input);
-        let source = rq::value::protobuf::source(&proto_descriptors, name, stream)?;
-        run_source(args, source)
+        let source = rq::value::protobuf::source(&proto_descriptors, name, stream)?
+            .decoding(protobuf_decoding(args));
+        let source = adapt_protobuf(args, &paths, &descriptors_proto, name, source)?;
+        run_source(args, report, source, output)
+    } else if let Some(ref name) = args.flag_input_protobuf_delimited {
+        let paths = rq::config::Paths::new()?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let pool = rq::value::protobuf::pool::DescriptorPool::from_file_set(&descriptors_proto)?;
+        let proto_descriptors = input_descriptors(args, &pool, name)?;
+        let stream = protobuf::CodedInputStream::new(&mut input);
+        let source = rq::value::protobuf::delimited_source(&proto_descriptors, name, stream)?
+            .decoding(protobuf_decoding(args));
+        let source = adapt_protobuf(args, &paths, &descriptors_proto, name, source)?;
+        run_source(args, report, source, output)
+    } else if let Some(ref name) = args.flag_input_protobuf_text {
+        let paths = rq::config::Paths::new()?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let pool = rq::value::protobuf::pool::DescriptorPool::from_file_set(&descriptors_proto)?;
+        let proto_descriptors = input_descriptors(args, &pool, name)?;
+        let source = rq::value::protobuf::text::source(&proto_descriptors, name, input)?
+            .decoding(protobuf_decoding(args));
+        let source = adapt_protobuf(args, &paths, &descriptors_proto, name, source)?;
+        run_source(args, report, source, output)
+    } else if let Some(ref method_name) = args.flag_input_grpc_frames {
+        let paths = rq::config::Paths::new()?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let pool = rq::value::protobuf::pool::DescriptorPool::from_file_set(&descriptors_proto)?;
+        let method = pool.method_by_name(method_name).ok_or_else(|| {
+            rq::error::Error::Message(format!("unknown gRPC method {:?}", method_name))
+        })?;
+        let message_name = if args.flag_grpc_responses {
+            method.output_type()
+        } else {
+            method.input_type()
+        };
+        let proto_descriptors = input_descriptors(args, &pool, message_name)?;
+        let source = rq::value::protobuf::grpc_source(&proto_descriptors, message_name, input)?
+            .decoding(protobuf_decoding(args));
+        let source = adapt_protobuf(args, &paths, &descriptors_proto, message_name, source)?;
+        run_source(args, report, source, output)
     } else if args.flag_input_avro {
-        let source = rq::value::avro::source(&mut input)?;
-        run_source(args, source)
+        if let Some(ref path) = args.flag_avro_reader_schema {
+            let (schema, logical_types) = read_avro_schema_with_logical_types(args, report, path)?;
+            let source = rq::value::avro::source_with_reader_schema(&mut input, &schema)?
+                .enum_style(args.flag_enum_style)
+                .logical_types(logical_types);
+            run_source(args, report, source, output)
+        } else {
+            let source = avro_source(args, report, &mut input)?;
+            run_source(args, report, source, output)
+        }
+    } else if let Some(ref path) = args.flag_input_avro_raw {
+        let (schema, logical_types) = read_avro_schema_with_logical_types(args, report, path)?;
+        let source =
+            rq::value::avro::raw_source(&mut input, &schema).enum_style(args.flag_enum_style);
+        if let Some(ref path) = args.flag_avro_reader_schema {
+            let (reader_schema, logical_types) =
+                read_avro_schema_with_logical_types(args, report, path)?;
+            let source = source
+                .reader_schema(&reader_schema)
+                .logical_types(logical_types);
+            run_source(args, report, source, output)
+        } else {
+            run_source(args, report, source.logical_types(logical_types), output)
+        }
+    } else if let Some(ref path) = args.flag_input_kafka_avro {
+        let (schema, logical_types) = read_avro_schema_with_logical_types(args, report, path)?;
+        let reader_schema = match args.flag_avro_reader_schema {
+            Some(ref path) => Some(read_avro_schema_with_logical_types(args, report, path)?),
+            None => None,
+        };
+        let source = rq::value::kafka::avro_source(&mut input, |payload| {
+            let datum = rq::value::avro::raw_source(payload, &schema)
+                .single_object(false)
+                .enum_style(args.flag_enum_style);
+            let mut datum = match reader_schema {
+                Some((ref reader_schema, ref logical_types)) => datum
+                    .reader_schema(reader_schema)
+                    .logical_types(logical_types.clone()),
+                None => datum.logical_types(logical_types.clone()),
+            };
+            rq::value::Source::read(&mut datum)?.ok_or_else(|| rq::error::Error::Format {
+                msg: "the Avro datum is empty".to_owned(),
+            })
+        });
+        run_source(args, report, source, output)
+    } else if let Some(ref name) = args.flag_input_kafka_protobuf {
+        let paths = rq::config::Paths::new()?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let pool = rq::value::protobuf::pool::DescriptorPool::from_file_set(&descriptors_proto)?;
+        let proto_descriptors = input_descriptors(args, &pool, name)?;
+        let message_indexes = pool.message_indexes(name).unwrap_or_default();
+        let source = rq::value::kafka::protobuf_source(&mut input, message_indexes, |payload| {
+            let stream = protobuf::CodedInputStream::from_bytes(payload);
+            let mut message = rq::value::protobuf::source(&proto_descriptors, name, stream)?
+                .decoding(protobuf_decoding(args));
+            rq::value::Source::read(&mut message)?.ok_or_else(|| rq::error::Error::Format {
+                msg: "the protobuf message is empty".to_owned(),
+            })
+        });
+        let source = adapt_protobuf(args, &paths, &descriptors_proto, name, source)?;
+        run_source(args, report, source, output)
+    } else if let Some(ref name) = args.flag_input_capnp {
+        let paths = rq::config::Paths::new()?;
+        let schema = rq::capnp_index::compile_schema(&paths)?;
+        let source = rq::value::capnproto::source(&schema, name, &mut input)?;
+        run_source(args, report, source, output)
+    } else if let Some(protocol) = args.flag_input_thrift {
+        let source =
+            rq::value::thrift::source(&mut input, protocol).enum_style(args.flag_enum_style);
+        match (&args.flag_thrift_idl, &args.flag_thrift_struct) {
+            (Some(path), Some(name)) => {
+                let idl = rq::value::thrift::Idl::parse(&fs::read_to_string(path)?)?;
+                run_source(args, report, source.idl(&idl, name)?, output)
+            }
+            (None, None) => run_source(args, report, source, output),
+            (Some(_), None) => Err(rq::error::Error::Message(
+                "--thrift-idl requires a --thrift-struct".to_owned(),
+            )),
+            (None, Some(_)) => Err(rq::error::Error::Message(
+                "--thrift-struct requires a --thrift-idl".to_owned(),
+            )),
+        }
+    } else if let Some(ref name) = args.flag_input_flatbuffers {
+        let paths = rq::config::Paths::new()?;
+        let schemas = rq::flatbuffers_index::load_schemas(&paths)?;
+        let source = rq::value::flatbuffers::source(&schemas, name, &mut input)?
+            .size_prefixed(args.flag_flatbuffers_size_prefixed)
+            .enum_style(args.flag_enum_style);
+        run_source(args, report, source, output)
+    } else if args.flag_input_bson {
+        let source = rq::value::bson::source(&mut input);
+        run_source(args, report, source, output)
     } else if args.flag_input_cbor {
         let source = rq::value::cbor::source(&mut input);
-        run_source(args, source)
+        run_source(args, report, source, output)
     } else if args.flag_input_message_pack {
         let source = rq::value::messagepack::source(&mut input);
-        run_source(args, source)
+        run_source(args, report, source, output)
+    } else if args.flag_input_jsonc {
+        if let Some(ref pointer) = args.flag_json_pointer {
+            let reader = rq::value::jsonc::reader(&mut input);
+            let source = rq::value::json_pointer::source(reader, pointer)?;
+            return run_source(args, report, spread_top_level(args, source), output);
+        }
+        let source = rq::value::jsonc::source(&mut input);
+        run_source(args, report, spread_top_level(args, source), output)
+    } else if args.flag_input_frontmatter {
+        let source = rq::value::frontmatter::source(&mut input)?;
+        run_source(args, report, source, output)
+    } else if args.flag_input_mail {
+        let source = rq::value::mail::source(&mut input);
+        run_source(args, report, source, output)
     } else if args.flag_input_toml {
         let source = rq::value::toml::source(&mut input)?;
-        run_source(args, source)
+        run_source(args, report, source, output)
     } else if args.flag_input_yaml {
         let source = rq::value::yaml::source(&mut input);
-        run_source(args, source)
+        run_source(args, report, spread_top_level(args, source), output)
+    } else if args.flag_input_parquet {
+        let source = rq::value::parquet::source(&mut input)?;
+        run_source(args, report, source, output)
+    } else if args.flag_input_pcap {
+        let source = rq::value::pcap::source(&mut input)?;
+        run_source(args, report, source, output)
     } else if args.flag_input_raw {
         let source = rq::value::raw::source(&mut input);
-        run_source(args, source)
+        run_source(args, report, source, output)
+    } else if args.flag_input_fixed_width {
+        let layout = args.flag_layout.as_ref().ok_or_else(|| {
+            rq::error::Error::Message("--input-fixed-width requires a --layout".to_owned())
+        })?;
+        let layout = rq::value::fixed_width::Layout::parse(&fs::read_to_string(layout)?)?;
+        let source = rq::value::fixed_width::source(&mut input, layout);
+        run_source(args, report, source, output)
     } else if args.flag_input_csv {
         if env::args().skip(1).any(|v| v == "-v") && !has_ran_cmd("help")? {
             warn!("You started rq -v, which puts it in CSV input mode.");
@@ -191,8 +1140,9 @@ fn run(args: &Options) -> rq::error::Result<()> {
                  warning."
             );
         }
-        let source = rq::value::csv::source(&mut input);
-        run_source(args, source)
+        let source =
+            rq::value::csv::source_with_number_format(&mut input, csv_number_format(args)?);
+        run_source(args, report, source, output)
     } else {
         if !args.flag_input_json && !has_ran_cmd("help")? {
             warn!("You started rq without any input flags, which puts it in JSON input mode.");
@@ -202,16 +1152,150 @@ fn run(args: &Options) -> rq::error::Result<()> {
                  warning."
             );
         }
+        if let Some(ref pointer) = args.flag_json_pointer {
+            let source = rq::value::json_pointer::source(&mut input, pointer)?;
+            return run_source(args, report, spread_top_level(args, source), output);
+        }
         let source = rq::value::json::source(&mut input);
-        run_source(args, source)
+        run_source(args, report, spread_top_level(args, source), output)
     }
 }
 
-fn run_source<I>(args: &Options, source: I) -> rq::error::Result<()>
+/// Removes the inactive members of oneofs from protobuf messages, and redacts the fields that are
+/// marked as sensitive or configured in redactions.toml, unless --no-redaction is given.
+fn adapt_protobuf<'a, I>(
+    args: &Options,
+    paths: &rq::config::Paths,
+    descriptors_proto: &protobuf::descriptor::FileDescriptorSet,
+    message_name: &str,
+    source: I,
+) -> rq::error::Result<Box<dyn rq::value::Source + 'a>>
 where
-    I: rq::value::Source,
+    I: rq::value::Source + 'a,
+{
+    let oneofs = rq::value::oneof::Oneofs::for_message(descriptors_proto, message_name)
+        .annotate(args.flag_annotate_oneofs);
+    let source: Box<dyn rq::value::Source + 'a> = if oneofs.is_empty() {
+        Box::new(source)
+    } else {
+        Box::new(rq::value::transform::source(source, oneofs))
+    };
+
+    if args.flag_no_redaction {
+        return Ok(source);
+    }
+
+    let rules = rq::redaction::load(paths)?;
+    let redact = rq::redaction::Redact::for_message(descriptors_proto, message_name, &rules);
+    if redact.is_empty() {
+        Ok(source)
+    } else {
+        debug!("Redacting {:?}", redact);
+        Ok(Box::new(rq::value::transform::source(source, redact)))
+    }
+}
+
+fn spread_top_level<'a, I>(args: &Options, source: I) -> Box<dyn rq::value::Source + 'a>
+where
+    I: rq::value::Source + 'a,
+{
+    if args.flag_spread_top_level {
+        Box::new(rq::value::spread::source(source))
+    } else {
+        Box::new(source)
+    }
+}
+
+/// How the protobuf sources decode messages.
+fn protobuf_decoding(args: &Options) -> rq::value::protobuf::Decoding {
+    rq::value::protobuf::Decoding::default()
+        .unknown_enums(args.flag_unknown_enums)
+        .enum_style(args.flag_enum_style)
+        .reject_unknown_fields(args.flag_strict)
+        .preserve_unknown_fields(args.flag_preserve_unknown_fields)
+        .int64_as_string(args.flag_int64_as_string)
+        .well_known_types(args.flag_unwrap_well_known_types)
+}
+
+/// Applies --fold-keys to a path from the command line.
+fn path(args: &Options, path: &rq::value::path::Path) -> rq::value::path::Path {
+    path.clone().key_match(key_match(args))
+}
+
+fn key_match(args: &Options) -> rq::value::path::KeyMatch {
+    if args.flag_fold_keys {
+        rq::value::path::KeyMatch::Folded
+    } else {
+        rq::value::path::KeyMatch::Exact
+    }
+}
+
+/// Applies the --nonfinite policy to sinks for text formats.
+fn nonfinite<'a, O>(args: &Options, sink: O) -> Box<dyn rq::value::Sink + 'a>
+where
+    O: rq::value::Sink + 'a,
+{
+    match args.flag_nonfinite {
+        Some(policy) => Box::new(rq::value::nonfinite::sink(sink, policy)),
+        None => Box::new(sink),
+    }
+}
+
+fn toml_sink<W>(args: &Options, w: W) -> rq::value::unrepresentable::Sink<rq::value::toml::Sink<W>>
+where
+    W: io::Write,
+{
+    rq::value::unrepresentable::sink(
+        rq::value::toml::sink(w),
+        rq::value::unrepresentable::TOML,
+        args.flag_on_unrepresentable,
+    )
+}
+
+fn csv_number_format(args: &Options) -> rq::error::Result<Option<rq::value::csv::NumberFormat>> {
+    match args.flag_csv_locale {
+        Some(ref locale) => Ok(Some(rq::value::csv::NumberFormat::from_locale(locale)?)),
+        None => Ok(None),
+    }
+}
+
+fn open_input_file(
+    args: &Options,
+    report: &rq::report::Report,
+    input: &InputFile,
+) -> rq::error::Result<Box<dyn rq::value::Source>> {
+    let file = io::BufReader::new(report.count_input(fs::File::open(&input.path)?));
+    Ok(match input.format {
+        InputFormat::Avro => Box::new(avro_source(args, report, file)?),
+        InputFormat::Bson => Box::new(rq::value::bson::source(file)),
+        InputFormat::Cbor => Box::new(rq::value::cbor::source(file)),
+        InputFormat::Csv => Box::new(rq::value::csv::source_with_number_format(
+            file,
+            csv_number_format(args)?,
+        )),
+        InputFormat::Frontmatter => Box::new(rq::value::frontmatter::source(file)?),
+        InputFormat::Json => spread_top_level(args, rq::value::json::source(file)),
+        InputFormat::Jsonc => spread_top_level(args, rq::value::jsonc::source(file)),
+        InputFormat::Mail => Box::new(rq::value::mail::source(file)),
+        InputFormat::MessagePack => Box::new(rq::value::messagepack::source(file)),
+        InputFormat::Parquet => Box::new(rq::value::parquet::source(file)?),
+        InputFormat::Pcap => Box::new(rq::value::pcap::source(file)?),
+        InputFormat::Raw => Box::new(rq::value::raw::source(file)),
+        InputFormat::Toml => Box::new(rq::value::toml::source(file)?),
+        InputFormat::Yaml => spread_top_level(args, rq::value::yaml::source(file)),
+    })
+}
+
+fn run_source<'a, I>(
+    args: &Options,
+    report: &rq::report::Report,
+    source: I,
+    mut output: &mut dyn io::Write,
+) -> rq::error::Result<()>
+where
+    I: rq::value::Source + 'a,
 {
-    let mut output = io::stdout();
+    let mut source = adapt_source(args, report, source)?;
 
     let format = args.flag_format.unwrap_or_else(infer_format);
 
@@ -220,134 +1304,765 @@ where
             match format {
                 Format::Compact => {
                     let sink = $compact(&mut output);
-                    run_source_sink(source, sink)
+                    run_source_sink(args, report, source, sink)
                 }
                 Format::Readable => {
                     let sink = $readable(&mut output);
-                    run_source_sink(source, sink)
+                    run_source_sink(args, report, source, sink)
                 }
                 Format::Indented => {
                     let sink = $indented(&mut output);
-                    run_source_sink(source, sink)
+                    run_source_sink(args, report, source, sink)
                 }
             }
         };
     }
 
-    if args.flag_output_protobuf.is_some() {
-        Err(rq::error::Error::unimplemented(
-            "protobuf serialization".to_owned(),
-        ))
-    } else if let Some(ref schema_filename) = args.flag_output_avro {
-        use std::str::FromStr;
-
-        let schema = read_avro_schema_from_file(path::Path::new(schema_filename))?;
-        let codec_string = if let Some(ref c) = args.flag_codec {
-            c.as_str()
-        } else {
-            "null"
-        };
-        let codec = if let Ok(v) = avro_rs::Codec::from_str(codec_string) {
-            v
-        } else {
-            return Err(rq::error::Error::Message(format!(
-                "illegal Avro codec: {}",
-                codec_string
-            )));
+    if let Some(ref message_name) = args.flag_output_protobuf {
+        let paths = rq::config::Paths::new()?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let descriptors = rq::value::protobuf::encoding_descriptors(&descriptors_proto);
+        let sink = rq::value::protobuf::sink(&descriptors, message_name, &mut output)?;
+        run_source_sink(args, report, source, sink)
+    } else if args.flag_output_protobuf_struct {
+        let sink = rq::value::protobuf::struct_sink(&mut output);
+        run_source_sink(args, report, source, sink)
+    } else if let Some(ref message_name) = args.flag_output_protobuf_text {
+        let paths = rq::config::Paths::new()?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let descriptors = rq::value::protobuf::encoding_descriptors(&descriptors_proto);
+        let sink = rq::value::protobuf::text::sink(&descriptors, message_name, &mut output)?;
+        run_source_sink(args, report, source, sink)
+    } else if let Some(ref message_name) = args.flag_output_protobuf_json {
+        let paths = rq::config::Paths::new()?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let descriptors = rq::value::protobuf::encoding_descriptors(&descriptors_proto);
+        match format {
+            Format::Compact => {
+                let sink = rq::value::protobuf::json_sink(
+                    &descriptors,
+                    message_name,
+                    rq::value::json::sink_compact(&mut output),
+                )?;
+                run_source_sink(args, report, source, sink)
+            }
+            Format::Readable => {
+                let sink = rq::value::protobuf::json_sink(
+                    &descriptors,
+                    message_name,
+                    rq::value::json::sink_readable(&mut output),
+                )?;
+                run_source_sink(args, report, source, sink)
+            }
+            Format::Indented => {
+                let sink = rq::value::protobuf::json_sink(
+                    &descriptors,
+                    message_name,
+                    rq::value::json::sink_indented(&mut output),
+                )?;
+                run_source_sink(args, report, source, sink)
+            }
+        }
+    } else if let Some(ref route) = args.flag_output_protobuf_route {
+        if args.flag_route_map.is_empty() {
+            return Err(rq::error::Error::Message(
+                "--output-protobuf-route requires a --route-map".to_owned(),
+            ));
+        }
+        let paths = rq::config::Paths::new()?;
+        let descriptors_proto = compile_descriptor_set(args, report, &paths)?;
+        let descriptors = rq::value::protobuf::encoding_descriptors(&descriptors_proto);
+        let routes = args
+            .flag_route_map
+            .iter()
+            .map(|r| (r.value.clone(), r.message.clone()))
+            .collect::<Vec<_>>();
+        let sink =
+            rq::value::protobuf::route_sink(&descriptors, path(args, route), &routes, &mut output)?;
+        run_source_sink(args, report, source, sink)
+    } else if let Some(ref path) = args.flag_avro_append {
+        let is_empty = fs::metadata(path).map_or(true, |m| m.len() == 0);
+        let options = avro_sink_options(args)?;
+        let open = || {
+            fs::OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(path)
         };
-        let sink = rq::value::avro::sink(&schema, &mut output, codec)?;
-        run_source_sink(source, sink)
+        match args.flag_output_avro {
+            None if is_empty => Err(rq::error::Error::Message(format!(
+                "--avro-append needs --output-avro with a schema to create {}",
+                path.display()
+            ))),
+            Some(ref schema_filename) if is_empty => {
+                let schema = read_avro_schema_from_file(
+                    path::Path::new(schema_filename),
+                    args.flag_avro_schema_dir.as_deref(),
+                )?;
+                report.add_schema(rq::report::Schema::avro(&schema));
+                let w = io::BufWriter::new(open()?);
+                let sink = rq::value::avro::sink_with_options(&schema, w, options)?;
+                run_source_sink(args, report, source, sink)
+            }
+            _ => {
+                let file = open()?;
+                let container = read_avro_container(args, &file)?;
+                report.add_schema(rq::report::Schema::avro(&container.schema));
+                let w = io::BufWriter::new(file);
+                let sink = rq::value::avro::append_sink(&container, w, options)?;
+                run_source_sink(args, report, source, sink)
+            }
+        }
+    } else if let Some(ref schema_filename) = args.flag_output_avro {
+        let schema = read_avro_schema_from_file(
+            path::Path::new(schema_filename),
+            args.flag_avro_schema_dir.as_deref(),
+        )?;
+        report.add_schema(rq::report::Schema::avro(&schema));
+        let options = avro_sink_options(args)?;
+        let sink = rq::value::avro::sink_with_options(&schema, &mut output, options)?;
+        run_source_sink(args, report, source, sink)
+    } else if args.flag_output_bson {
+        let sink = rq::value::bson::sink(&mut output);
+        run_source_sink(args, report, source, sink)
     } else if args.flag_output_cbor {
-        let sink = rq::value::cbor::sink(&mut output);
-        run_source_sink(source, sink)
+        if args.flag_canonical_cbor {
+            let sink = rq::value::cbor::sink_canonical(&mut output);
+            run_source_sink(args, report, source, sink)
+        } else {
+            let sink = rq::value::cbor::sink(&mut output);
+            run_source_sink(args, report, source, sink)
+        }
     } else if args.flag_output_message_pack {
-        let sink = rq::value::messagepack::sink(&mut output);
-        run_source_sink(source, sink)
+        let options = rq::value::messagepack::SinkOptions {
+            legacy_raw: args.flag_message_pack_legacy_raw,
+            stringify_keys: args.flag_message_pack_string_keys,
+        };
+        let sink = rq::value::messagepack::sink_with_options(&mut output, options);
+        run_source_sink(args, report, source, sink)
+    } else if args.flag_output_parquet {
+        let sink = rq::value::parquet::sink(&mut output, parquet_sink_options(args)?);
+        run_source_sink(args, report, source, sink)
     } else if args.flag_output_toml {
         // TODO: add TOML ugly printing eventually; now it's always "readable"
         dispatch_format!(
-            rq::value::toml::sink,
-            rq::value::toml::sink,
-            rq::value::toml::sink
+            |w| nonfinite(args, toml_sink(args, w)),
+            |w| nonfinite(args, toml_sink(args, w)),
+            |w| nonfinite(args, toml_sink(args, w))
         )
     } else if args.flag_output_yaml {
         // TODO: add YAML ugly printing eventually; now it's always "readable"
+        let anchors = args.flag_yaml_anchors;
         dispatch_format!(
-            rq::value::yaml::sink,
-            rq::value::yaml::sink,
-            rq::value::yaml::sink
+            |w| nonfinite(args, rq::value::yaml::sink(w).anchors(anchors)),
+            |w| nonfinite(args, rq::value::yaml::sink(w).anchors(anchors)),
+            |w| nonfinite(args, rq::value::yaml::sink(w).anchors(anchors))
         )
+    } else if args.flag_output_table {
+        let options = rq::value::table::Options {
+            columns: args.flag_columns.clone(),
+            max_width: args.flag_max_column_width,
+        };
+        let sink = rq::value::table::sink(&mut output, options);
+        run_source_sink(args, report, source, sink)
     } else if args.flag_output_raw {
         let sink = rq::value::raw::sink(&mut output);
-        run_source_sink(source, sink)
+        run_source_sink(args, report, source, sink)
     } else if args.flag_output_csv {
-        let sink = rq::value::csv::sink(&mut output);
-        run_source_sink(source, sink)
+        let sink = rq::value::unrepresentable::sink(
+            rq::value::csv::sink(&mut output),
+            rq::value::unrepresentable::CSV,
+            args.flag_on_unrepresentable,
+        );
+        let sink = nonfinite(args, sink);
+        run_source_sink(args, report, source, sink)
     } else {
+        if args.flag_control_records {
+            source = Box::new(rq::value::control::envelope(source));
+        }
+        let raw = args.flag_raw_output;
+        let int64 = args.flag_int64_as_string;
         dispatch_format!(
-            rq::value::json::sink_compact,
-            rq::value::json::sink_readable,
-            rq::value::json::sink_indented
+            |w| nonfinite(
+                args,
+                rq::value::json::sink_compact(w)
+                    .raw_strings(raw)
+                    .int64_as_string(int64)
+            ),
+            |w| nonfinite(
+                args,
+                rq::value::json::sink_readable(w)
+                    .raw_strings(raw)
+                    .int64_as_string(int64)
+            ),
+            |w| nonfinite(
+                args,
+                rq::value::json::sink_indented(w)
+                    .raw_strings(raw)
+                    .int64_as_string(int64)
+            )
         )
     }
 }
 
-fn read_avro_schema_from_file(path: &path::Path) -> rq::error::Result<avro_rs::Schema> {
-    let mut file = fs::File::open(path)?;
-    let mut buffer = String::new();
-    file.read_to_string(&mut buffer)?;
-    avro_rs::Schema::parse_str(&buffer)
-        .map_err(|e| rq::error::Error::Avro(rq::error::Avro::downcast(e)))
-}
-
-fn run_source_sink<I, O>(mut source: I, mut sink: O) -> rq::error::Result<()>
-where
-    I: rq::value::Source,
-    O: rq::value::Sink,
-{
-    while let Some(result) = rq::value::Source::read(&mut source)? {
-        sink.write(result)?;
+/// Reads the header of an Avro container file that is appended to with --avro-append, checking
+/// that its schema is the same as the one of --output-avro, if given.
+fn read_avro_container(
+    args: &Options,
+    file: &fs::File,
+) -> rq::error::Result<rq::value::avro::Container> {
+    let container = rq::value::avro::read_container(io::BufReader::new(file))?;
+    if let Some(ref schema_filename) = args.flag_output_avro {
+        let schema = read_avro_schema_from_file(
+            path::Path::new(schema_filename),
+            args.flag_avro_schema_dir.as_deref(),
+        )?;
+        if schema.canonical_form() != container.schema.canonical_form() {
+            return Err(rq::error::Error::Message(format!(
+                "the schema in {} is different from the schema of the Avro container",
+                schema_filename
+            )));
+        }
     }
-    Ok(())
+    Ok(container)
 }
 
-fn load_descriptors(
-    paths: &rq::config::Paths,
-) -> rq::error::Result<serde_protobuf::descriptor::Descriptors> {
-    let descriptors_proto = rq::proto_index::compile_descriptor_set(paths)?;
-    Ok(serde_protobuf::descriptor::Descriptors::from_proto(
-        &descriptors_proto,
-    ))
-}
+fn avro_sink_options(args: &Options) -> rq::error::Result<rq::value::avro::SinkOptions> {
+    use std::str::FromStr;
 
-fn infer_format() -> Format {
-    if atty::is(atty::Stream::Stdout) {
-        Format::Readable
+    let codec_string = if let Some(ref c) = args.flag_codec {
+        c.as_str()
     } else {
-        Format::Compact
+        "null"
+    };
+    let codec = if let Ok(v) = avro_rs::Codec::from_str(codec_string) {
+        v
+    } else {
+        return Err(rq::error::Error::Message(format!(
+            "illegal Avro codec: {}",
+            codec_string
+        )));
+    };
+    let mut options = rq::value::avro::SinkOptions {
+        codec,
+        block_records: args.flag_avro_block_records,
+        metadata: args
+            .flag_avro_metadata
+            .iter()
+            .map(|m| (m.key.clone(), m.value.clone().into_bytes()))
+            .collect(),
+        seed: args.flag_seed,
+        ..rq::value::avro::SinkOptions::default()
+    };
+    if let Some(block_size) = args.flag_avro_block_size {
+        options.block_size = block_size;
     }
+    Ok(options)
 }
 
-fn has_ran_cmd(cmd: &str) -> rq::error::Result<bool> {
-    let paths = match rq::config::Paths::new() {
-        Ok(paths) => paths,
-        Err(_) => return Ok(false),
+fn parquet_sink_options(args: &Options) -> rq::error::Result<rq::value::parquet::SinkOptions> {
+    let schema = match args.flag_parquet_schema {
+        Some(ref path) => Some(rq::value::parquet::Schema::parse(&fs::read_to_string(
+            path,
+        )?)?),
+        None => None,
     };
-    paths
-        .find_config(&format!("{}{}", "has-ran-", cmd))
-        .map(|v| !v.is_empty())
-        .map_err(From::from)
-}
-
-fn set_ran_cmd(cmd: &str) -> rq::error::Result<()> {
-    let paths = match rq::config::Paths::new() {
-        Ok(paths) => paths,
-        Err(_) => return Ok(()),
+    let codec = match args.flag_codec {
+        Some(ref codec) => codec.parse()?,
+        None => rq::value::parquet::Codec::Snappy,
     };
+    Ok(rq::value::parquet::SinkOptions {
+        schema,
+        infer_records: args.flag_parquet_infer_records,
+        codec,
+        ..rq::value::parquet::SinkOptions::default()
+    })
+}
 
-    let file = paths.preferred_config(format!("{}{}", "has-ran-", cmd));
+/// Creates a checker that writes each record in the output format and reads it back, for
+/// --check-roundtrip and --strict-roundtrip.
+fn roundtrip_checker<'a>(
+    args: &'a Options,
+    report: &rq::report::Report,
+) -> rq::error::Result<Option<rq::value::roundtrip::Checker<'a>>> {
+    if !args.flag_check_roundtrip && !args.flag_strict_roundtrip {
+        return Ok(None);
+    }
 
-    if let Some(parent) = file.parent() {
+    macro_rules! roundtrip {
+        (|$w:ident| $sink:expr, |$r:ident| $source:expr) => {
+            rq::value::roundtrip::Checker::new(move |v| {
+                let mut buffer = Vec::new();
+                {
+                    let $w = &mut buffer;
+                    let mut sink = $sink;
+                    rq::value::Sink::write(&mut sink, v)?;
+                    rq::value::Sink::finish(&mut sink)?;
+                }
+                let $r = buffer.as_slice();
+                let mut source = $source;
+                rq::value::Source::read(&mut source)
+            })
+        };
+    }
+
+    let checker = if args.flag_output_protobuf.is_some()
+        || args.flag_output_protobuf_struct
+        || args.flag_output_protobuf_route.is_some()
+        || args.flag_output_protobuf_json.is_some()
+        || args.flag_output_table
+    {
+        return Err(rq::error::Error::Message(
+            "round trips can't be checked for this output format, since it can't be read"
+                .to_owned(),
+        ));
+    } else if let Some(ref schema_filename) = args.flag_output_avro {
+        let schema = read_avro_schema_from_file(
+            path::Path::new(schema_filename),
+            args.flag_avro_schema_dir.as_deref(),
+        )?;
+        let options = avro_sink_options(args)?;
+        roundtrip!(
+            |w| rq::value::avro::sink_with_options(&schema, w, options.clone())?,
+            |r| rq::value::avro::source(r)?.logical_types(rq::value::avro::LogicalTypes::default())
+        )
+    } else if let Some(ref path) = args.flag_avro_append {
+        let schema = read_avro_container(args, &fs::File::open(path)?)?.schema;
+        let options = avro_sink_options(args)?;
+        roundtrip!(
+            |w| rq::value::avro::sink_with_options(&schema, w, options.clone())?,
+            |r| rq::value::avro::source(r)?.logical_types(rq::value::avro::LogicalTypes::default())
+        )
+    } else if let Some(ref message_name) = args.flag_output_protobuf_text {
+        let paths = rq::config::Paths::new()?;
+        let descriptors = rq::value::protobuf::encoding_descriptors(&compile_descriptor_set(
+            args, report, &paths,
+        )?);
+        let message_name = message_name.clone();
+        roundtrip!(
+            |w| rq::value::protobuf::text::sink(&descriptors, &message_name, w)?,
+            |r| rq::value::protobuf::text::source(&descriptors, &message_name, r)?
+        )
+    } else if args.flag_output_bson {
+        roundtrip!(|w| rq::value::bson::sink(w), |r| rq::value::bson::source(r))
+    } else if args.flag_output_cbor {
+        let canonical = args.flag_canonical_cbor;
+        roundtrip!(
+            |w| if canonical {
+                rq::value::cbor::sink_canonical(w)
+            } else {
+                rq::value::cbor::sink(w)
+            },
+            |r| rq::value::cbor::source(r)
+        )
+    } else if args.flag_output_message_pack {
+        let options = rq::value::messagepack::SinkOptions {
+            legacy_raw: args.flag_message_pack_legacy_raw,
+            stringify_keys: args.flag_message_pack_string_keys,
+        };
+        roundtrip!(
+            |w| rq::value::messagepack::sink_with_options(w, options),
+            |r| rq::value::messagepack::source(r)
+        )
+    } else if args.flag_output_parquet {
+        let options = parquet_sink_options(args)?;
+        roundtrip!(|w| rq::value::parquet::sink(w, options.clone()), |r| {
+            rq::value::parquet::source(r)?
+        })
+    } else if args.flag_output_toml {
+        roundtrip!(|w| nonfinite(args, toml_sink(args, w)), |r| {
+            rq::value::toml::source(r)?
+        })
+    } else if args.flag_output_yaml {
+        let anchors = args.flag_yaml_anchors;
+        roundtrip!(
+            |w| nonfinite(args, rq::value::yaml::sink(w).anchors(anchors)),
+            |r| rq::value::yaml::source(r)
+        )
+    } else if args.flag_output_raw {
+        roundtrip!(|w| rq::value::raw::sink(w), |r| rq::value::raw::source(r))
+    } else if args.flag_output_csv {
+        roundtrip!(
+            |w| nonfinite(
+                args,
+                rq::value::unrepresentable::sink(
+                    rq::value::csv::sink(w),
+                    rq::value::unrepresentable::CSV,
+                    args.flag_on_unrepresentable,
+                )
+            ),
+            |r| rq::value::csv::source(r)
+        )
+    } else {
+        let raw = args.flag_raw_output;
+        let int64 = args.flag_int64_as_string;
+        roundtrip!(
+            |w| nonfinite(
+                args,
+                rq::value::json::sink_compact(w)
+                    .raw_strings(raw)
+                    .int64_as_string(int64)
+            ),
+            |r| rq::value::json::source(r)
+        )
+    };
+    Ok(Some(checker))
+}
+
+/// Wraps the source in the adapters enabled by the command-line flags.
+fn adapt_source<'a, I>(
+    args: &Options,
+    report: &rq::report::Report,
+    source: I,
+) -> rq::error::Result<Box<dyn rq::value::Source + 'a>>
+where
+    I: rq::value::Source + 'a,
+{
+    let source: Box<dyn rq::value::Source + 'a> = if args.flag_control_records {
+        Box::new(rq::value::control::source(source))
+    } else {
+        Box::new(source)
+    };
+
+    let source: Box<dyn rq::value::Source + 'a> = match args.flag_input_schema {
+        Some(ref path) => {
+            let schema = rq::value::schema::Schema::parse_str(&fs::read_to_string(path)?)?;
+            Box::new(rq::value::schema::source(source, schema))
+        }
+        None => source,
+    };
+
+    let mut transforms: Vec<Box<dyn rq::value::Transform>> = Vec::new();
+
+    if let Some(policy) = args.flag_duplicate_keys {
+        transforms.push(Box::new(policy));
+    }
+
+    if let Some(ref query) = args.arg_query {
+        let key_match = if args.flag_fold_keys {
+            rq::value::path::KeyMatch::Folded
+        } else {
+            rq::value::path::KeyMatch::Exact
+        };
+        let mut query = rq::query::Query::parse(query)?.key_match(key_match);
+        if query.uses_proto_descriptors() {
+            query = query.proto_descriptors(&load_descriptors(
+                args,
+                report,
+                &rq::config::Paths::new()?,
+            )?)?;
+        }
+        transforms.push(Box::new(query));
+    }
+
+    if let Some(ref mask) = args.flag_field_mask {
+        transforms.push(Box::new(mask.clone()));
+    }
+
+    if let Some(ref pointers) = args.flag_anonymize {
+        let key = args
+            .flag_key
+            .as_ref()
+            .ok_or_else(|| rq::error::Error::Message("--anonymize requires --key".to_owned()))?;
+        transforms.push(Box::new(
+            rq::value::anonymize::Anonymize::new(
+                pointers.clone(),
+                rq::value::anonymize::read_key(key)?,
+            )
+            .key_match(key_match(args)),
+        ));
+    }
+
+    if let Some(target) = args.flag_normalize_timestamps {
+        let options = rq::value::timestamp::Options {
+            target,
+            assume_offset: rq::value::timestamp::parse_offset(&args.flag_assume_tz)?,
+            epoch_fields: args
+                .flag_epoch_field
+                .iter()
+                .map(|p| path(args, p))
+                .collect(),
+        };
+        transforms.push(Box::new(options));
+    } else if !args.flag_epoch_field.is_empty() {
+        return Err(rq::error::Error::Message(
+            "--epoch-field requires --normalize-timestamps".to_owned(),
+        ));
+    }
+
+    let source = rq::value::transform::source(source, transforms);
+
+    match args.flag_adjacent_count {
+        Some(ref key) => Ok(Box::new(rq::value::adjacent::source(
+            source,
+            key.as_ref().map(|key| path(args, key)),
+        ))),
+        None => Ok(Box::new(source)),
+    }
+}
+
+/// Opens an Avro container with the options from the command line.
+fn avro_source<'a, R>(
+    args: &Options,
+    report: &rq::report::Report,
+    r: R,
+) -> rq::error::Result<rq::value::avro::Source<'a, R>>
+where
+    R: io::Read,
+{
+    let source = rq::value::avro::source(r)?.enum_style(args.flag_enum_style);
+    report.add_schema(rq::report::Schema::avro(source.writer_schema()));
+    if args.flag_avro_raw_logical_types {
+        Ok(source.logical_types(rq::value::avro::LogicalTypes::default()))
+    } else {
+        Ok(source)
+    }
+}
+
+/// Reads a schema for decoding Avro input, with its logical types unless they should be
+/// output raw.
+fn read_avro_schema_with_logical_types(
+    args: &Options,
+    report: &rq::report::Report,
+    path: &path::Path,
+) -> rq::error::Result<(avro_rs::Schema, rq::value::avro::LogicalTypes)> {
+    let mut registry = rq::value::avro::SchemaRegistry::new();
+    if let Some(ref dir) = args.flag_avro_schema_dir {
+        registry.add_dir(dir)?;
+    }
+    let (schema, logical_types) =
+        registry.parse_str_with_logical_types(&fs::read_to_string(path)?)?;
+    report.add_schema(rq::report::Schema::avro(&schema));
+    if args.flag_avro_raw_logical_types {
+        Ok((schema, rq::value::avro::LogicalTypes::default()))
+    } else {
+        Ok((schema, logical_types))
+    }
+}
+
+fn read_avro_schema_from_file(
+    path: &path::Path,
+    schema_dir: Option<&path::Path>,
+) -> rq::error::Result<avro_rs::Schema> {
+    let mut file = fs::File::open(path)?;
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer)?;
+    let mut registry = rq::value::avro::SchemaRegistry::new();
+    if let Some(dir) = schema_dir {
+        registry.add_dir(dir)?;
+    }
+    registry.parse_str(&buffer)
+}
+
+fn run_source_sink<I, O>(
+    args: &Options,
+    report: &rq::report::Report,
+    mut source: I,
+    mut sink: O,
+) -> rq::error::Result<()>
+where
+    I: rq::value::Source,
+    O: rq::value::Sink,
+{
+    let mut roundtrip = roundtrip_checker(args, report)?;
+
+    if !args.flag_skip_bad_records && args.flag_errors_to.is_none() {
+        let source = rq::value::transform::source(
+            source,
+            rq::value::transform::from_fn(|v| {
+                report.record_read();
+                if let Some(ref mut checker) = roundtrip {
+                    checker.check(&v);
+                }
+                Ok(Some(v))
+            }),
+        );
+        let sink = ReportingSink(sink, report);
+        // Nothing cancels rq itself, only applications that embed it
+        let token = rq::value::cancel::CancellationToken::new();
+        rq::value::cancel::run_source_sink(source, sink, &token)?;
+        return report_roundtrip(args, roundtrip);
+    }
+
+    let mut errors_to = match args.flag_errors_to {
+        Some(ref path) => Some(io::BufWriter::new(fs::File::create(path)?)),
+        None => None,
+    };
+    let mut skipped = 0;
+
+    let mut skip = |e: &rq::error::Error,
+                    category: &'static str,
+                    record: &rq::value::Value|
+     -> rq::error::Result<()> {
+        skipped += 1;
+        report.record_skipped(category);
+        debug!("Skipping bad record: {}", e.chain());
+        if let Some(ref mut w) = errors_to {
+            let record = serde_json::to_value(record)
+                .unwrap_or_else(|_| serde_json::Value::String(record.to_string()));
+            let line = serde_json::json!({ "error": e.chain(), "record": record });
+            serde_json::to_writer(&mut *w, &line)?;
+            w.write_all(b"\n")?;
+        }
+        Ok(())
+    };
+
+    loop {
+        let result = match rq::value::Source::read(&mut source) {
+            Ok(Some(result)) => result,
+            Ok(None) => break,
+            // Records that don't match the input schema are quarantined like unwritable ones
+            Err(rq::error::Error::InvalidRecord { msg, record }) => {
+                report.record_read();
+                skip(&rq::error::Error::Message(msg), "invalid_record", &record)?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        report.record_read();
+        match sink.write(result.clone()) {
+            Ok(()) => {
+                report.record_written();
+                if let Some(ref mut checker) = roundtrip {
+                    checker.check(&result);
+                }
+            }
+            // Failing to write output is not the record's fault
+            Err(e @ rq::error::Error::Io(_)) => return Err(e),
+            Err(e) => skip(&e, e.category(), &result)?,
+        }
+    }
+
+    // Sinks that buffer records may only reject some of them when they are finished
+    loop {
+        match sink.finish() {
+            Ok(()) => break,
+            Err(rq::error::Error::InvalidRecord { msg, record }) => {
+                report.record_unwritten();
+                skip(&rq::error::Error::Message(msg), "invalid_record", &record)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(mut w) = errors_to {
+        w.flush()?;
+    }
+    if skipped > 0 && args.flag_strict {
+        return Err(rq::error::Error::Message(format!(
+            "skipped {} bad records",
+            skipped
+        )));
+    } else if skipped > 0 {
+        warn!("Skipped {} bad records", skipped);
+    }
+    report_roundtrip(args, roundtrip)
+}
+
+/// A sink that counts the records that it writes in the report.
+struct ReportingSink<'a, O>(O, &'a rq::report::Report);
+
+impl<'a, O> rq::value::Sink for ReportingSink<'a, O>
+where
+    O: rq::value::Sink,
+{
+    fn write(&mut self, v: rq::value::Value) -> rq::error::Result<()> {
+        self.0.write(v)?;
+        self.1.record_written();
+        Ok(())
+    }
+
+    fn finish(&mut self) -> rq::error::Result<()> {
+        self.0.finish()
+    }
+}
+
+fn report_roundtrip(
+    args: &Options,
+    checker: Option<rq::value::roundtrip::Checker>,
+) -> rq::error::Result<()> {
+    if let Some(checker) = checker {
+        let report = checker.report();
+        if report.is_lossless() {
+            info!("{}", report);
+        } else if args.flag_strict_roundtrip || args.flag_strict {
+            return Err(rq::error::Error::Message(report.to_string()));
+        } else {
+            warn!("{}", report);
+        }
+    }
+    Ok(())
+}
+
+/// Compiles the added proto files, checking that all types that they refer to are defined in
+/// --strict mode.
+fn compile_descriptor_set(
+    args: &Options,
+    report: &rq::report::Report,
+    paths: &rq::config::Paths,
+) -> rq::error::Result<protobuf::descriptor::FileDescriptorSet> {
+    let descriptors_proto = rq::proto_index::compile_descriptor_set(paths)?;
+    if args.flag_strict {
+        rq::value::protobuf::check_refs(&descriptors_proto)?;
+    }
+    report.add_schema(rq::report::Schema::protobuf(&descriptors_proto)?);
+    Ok(descriptors_proto)
+}
+
+/// The descriptors for decoding input messages of a type, which only describe the types that it
+/// uses, unless --unwrap-well-known-types needs all of them for the messages in `Any` fields.
+fn input_descriptors(
+    args: &Options,
+    pool: &rq::value::protobuf::pool::DescriptorPool,
+    message_name: &str,
+) -> rq::error::Result<serde_protobuf::descriptor::Descriptors> {
+    if args.flag_unwrap_well_known_types {
+        Ok(pool.all_descriptors())
+    } else {
+        pool.descriptors(message_name)
+    }
+}
+
+fn load_descriptors(
+    args: &Options,
+    report: &rq::report::Report,
+    paths: &rq::config::Paths,
+) -> rq::error::Result<serde_protobuf::descriptor::Descriptors> {
+    let descriptors_proto = compile_descriptor_set(args, report, paths)?;
+    Ok(rq::value::protobuf::decoding_descriptors(
+        &descriptors_proto,
+    ))
+}
+
+fn infer_format() -> Format {
+    if atty::is(atty::Stream::Stdout) {
+        Format::Readable
+    } else {
+        Format::Compact
+    }
+}
+
+fn has_ran_cmd(cmd: &str) -> rq::error::Result<bool> {
+    let paths = match rq::config::Paths::new() {
+        Ok(paths) => paths,
+        Err(_) => return Ok(false),
+    };
+    paths
+        .find_config(&format!("{}{}", "has-ran-", cmd))
+        .map(|v| !v.is_empty())
+        .map_err(From::from)
+}
+
+fn set_ran_cmd(cmd: &str) -> rq::error::Result<()> {
+    let paths = match rq::config::Paths::new() {
+        Ok(paths) => paths,
+        Err(_) => return Ok(()),
+    };
+
+    let file = paths.preferred_config(format!("{}{}", "has-ran-", cmd));
+
+    if let Some(parent) = file.parent() {
         fs::create_dir_all(parent)?;
     }
 
@@ -420,6 +2135,81 @@ impl str::FromStr for Format {
     }
 }
 
+impl str::FromStr for InputFile {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('=') {
+            Some(i) => Ok(Self {
+                format: s[..i].parse()?,
+                path: path::PathBuf::from(&s[i + 1..]),
+            }),
+            None => Err(failure::err_msg(format!(
+                "expected FORMAT=PATH, got: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl str::FromStr for AvroMetadata {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('=') {
+            Some(i) => Ok(Self {
+                key: s[..i].to_owned(),
+                value: s[i + 1..].to_owned(),
+            }),
+            None => Err(failure::err_msg(format!("expected KEY=VALUE, got: {}", s))),
+        }
+    }
+}
+
+impl str::FromStr for Route {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('=') {
+            Some(i) => Ok(Self {
+                value: s[..i].to_owned(),
+                message: s[i + 1..].to_owned(),
+            }),
+            None => Err(failure::err_msg(format!(
+                "expected VALUE=MESSAGE, got: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl str::FromStr for InputFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "avro" => Ok(Self::Avro),
+            "bson" => Ok(Self::Bson),
+            "cbor" => Ok(Self::Cbor),
+            "csv" => Ok(Self::Csv),
+            "frontmatter" => Ok(Self::Frontmatter),
+            "json" => Ok(Self::Json),
+            "jsonc" => Ok(Self::Jsonc),
+            "mail" => Ok(Self::Mail),
+            "message-pack" => Ok(Self::MessagePack),
+            "parquet" => Ok(Self::Parquet),
+            "pcap" => Ok(Self::Pcap),
+            "raw" => Ok(Self::Raw),
+            "toml" => Ok(Self::Toml),
+            "yaml" => Ok(Self::Yaml),
+            _ => Err(failure::err_msg(format!(
+                "unrecognized input format: {}",
+                s
+            ))),
+        }
+    }
+}
+
 fn format_log_record(
     formatter: &mut env_logger::fmt::Formatter,
     record: &log::Record,
@@ -482,6 +2272,13 @@ mod test {
         assert_eq!(a.arg_query, Some("select x".to_owned()));
     }
 
+    #[test]
+    fn test_docopt_path_query() {
+        let a = parse_args(&["rq", "-j", ".foo.bar[0]"]);
+        assert!(a.flag_input_json);
+        assert_eq!(a.arg_query, Some(".foo.bar[0]".to_owned()));
+    }
+
     #[test]
     fn test_docopt_no_args() {
         parse_args(&["rq"]);
@@ -521,12 +2318,109 @@ mod test {
         assert!(a.flag_output_json);
     }
 
+    #[test]
+    fn test_docopt_raw_output() {
+        let a = parse_args(&["rq", "-J", "--raw-output"]);
+        assert!(a.flag_output_json);
+        assert!(a.flag_raw_output);
+    }
+
+    #[test]
+    fn test_docopt_output_table() {
+        let a = parse_args(&[
+            "rq",
+            "--output-table",
+            "--columns",
+            "a,b",
+            "--max-column-width",
+            "20",
+        ]);
+        assert!(a.flag_output_table);
+        assert_eq!(a.flag_columns, Some(vec!["a".to_owned(), "b".to_owned()]));
+        assert_eq!(a.flag_max_column_width, Some(20));
+    }
+
     #[test]
     fn test_docopt_input_raw() {
         let a = parse_args(&["rq", "-r"]);
         assert!(a.flag_input_raw);
     }
 
+    #[test]
+    fn test_docopt_input_fixed_width() {
+        let a = parse_args(&["rq", "--input-fixed-width", "--layout", "spec.json"]);
+        assert!(a.flag_input_fixed_width);
+        assert_eq!(a.flag_layout, Some(path::PathBuf::from("spec.json")));
+    }
+
+    #[test]
+    fn test_docopt_input_parquet() {
+        let a = parse_args(&["rq", "--input-parquet"]);
+        assert!(a.flag_input_parquet);
+    }
+
+    #[test]
+    fn test_docopt_input_pcap() {
+        let a = parse_args(&["rq", "--input-pcap"]);
+        assert!(a.flag_input_pcap);
+    }
+
+    #[test]
+    fn test_docopt_json_pointer() {
+        let a = parse_args(&["rq", "--json-pointer", "/items"]);
+        assert_eq!(a.flag_json_pointer, Some("/items".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_seed() {
+        let a = parse_args(&["rq", "--seed", "42"]);
+        assert_eq!(a.flag_seed, Some(42));
+    }
+
+    #[test]
+    fn test_docopt_int64_as_string() {
+        let a = parse_args(&["rq", "--input-protobuf", ".foo.Bar", "--int64-as-string"]);
+        assert!(a.flag_int64_as_string);
+    }
+
+    #[test]
+    fn test_docopt_unwrap_well_known_types() {
+        let a = parse_args(&["rq", "--input-protobuf", ".foo.Bar"]);
+        assert!(!a.flag_unwrap_well_known_types);
+        let a = parse_args(&[
+            "rq",
+            "--input-protobuf",
+            ".foo.Bar",
+            "--unwrap-well-known-types",
+        ]);
+        assert!(a.flag_unwrap_well_known_types);
+    }
+
+    #[test]
+    fn test_docopt_preserve_unknown_fields() {
+        let a = parse_args(&["rq", "--input-protobuf", ".foo.Bar"]);
+        assert!(!a.flag_preserve_unknown_fields);
+        let a = parse_args(&[
+            "rq",
+            "--input-protobuf",
+            ".foo.Bar",
+            "--preserve-unknown-fields",
+        ]);
+        assert!(a.flag_preserve_unknown_fields);
+    }
+
+    #[test]
+    fn test_docopt_control_records() {
+        let a = parse_args(&["rq", "--control-records"]);
+        assert!(a.flag_control_records);
+    }
+
+    #[test]
+    fn test_docopt_annotate_oneofs() {
+        let a = parse_args(&["rq", "--annotate-oneofs"]);
+        assert!(a.flag_annotate_oneofs);
+    }
+
     #[test]
     fn test_docopt_input_raw_long() {
         let a = parse_args(&["rq", "--input-raw"]);
@@ -545,6 +2439,166 @@ mod test {
         assert!(a.flag_output_raw);
     }
 
+    #[test]
+    fn test_docopt_input_jsonc() {
+        let a = parse_args(&["rq", "--input-jsonc"]);
+        assert!(a.flag_input_jsonc);
+    }
+
+    #[test]
+    fn test_docopt_duplicate_keys() {
+        let a = parse_args(&["rq", "--duplicate-keys", "collect"]);
+        assert_eq!(
+            a.flag_duplicate_keys,
+            Some(rq::value::duplicate_keys::Policy::Collect)
+        );
+    }
+
+    #[test]
+    fn test_docopt_pager() {
+        assert!(parse_args(&["rq", "--pager"]).flag_pager);
+        assert!(parse_args(&["rq", "--no-pager"]).flag_no_pager);
+    }
+
+    #[test]
+    fn test_pager_command() {
+        let s = |v: &str| Some(v.to_owned());
+        assert_eq!(pager_command(None, None, None), s("less"));
+        assert_eq!(pager_command(None, s("\n"), s("more")), s("more"));
+        assert_eq!(
+            pager_command(s("most"), s("less -S\n"), s("more")),
+            s("most")
+        );
+        assert_eq!(pager_command(None, s("less -S\n"), s("more")), s("less -S"));
+        assert_eq!(pager_command(None, None, s("cat")), None);
+    }
+
+    #[test]
+    fn test_docopt_roundtrip() {
+        let a = parse_args(&["rq", "--check-roundtrip"]);
+        assert!(a.flag_check_roundtrip);
+        assert!(!a.flag_strict_roundtrip);
+        let a = parse_args(&["rq", "--strict-roundtrip"]);
+        assert!(a.flag_strict_roundtrip);
+    }
+
+    #[test]
+    fn test_roundtrip_checker_on_unrepresentable() {
+        let s = |v: &str| rq::value::Value::String(v.to_owned());
+        let record = rq::value::Value::Map(vec![
+            (s("a"), rq::value::Value::Unit),
+            (s("b"), rq::value::Value::I64(1)),
+        ]);
+        let a = parse_args(&[
+            "rq",
+            "-T",
+            "--check-roundtrip",
+            "--on-unrepresentable",
+            "skip-field",
+        ]);
+        let report = rq::report::Report::new();
+        let mut checker = roundtrip_checker(&a, &report).unwrap().unwrap();
+        checker.check(&record);
+        let summary = checker.report().to_string();
+        assert!(summary.contains("value removed"), "{}", summary);
+        assert!(!summary.contains("unreadable"), "{}", summary);
+    }
+
+    #[test]
+    fn test_roundtrip_checker_int64_as_string() {
+        // Larger than the integers that JavaScript numbers can represent exactly
+        let record = rq::value::Value::I64(1 << 60);
+        let report = rq::report::Report::new();
+        let a = parse_args(&["rq", "--check-roundtrip", "--int64-as-string"]);
+        let mut checker = roundtrip_checker(&a, &report).unwrap().unwrap();
+        checker.check(&record);
+        let summary = checker.report().to_string();
+        assert!(summary.contains("integer became string"), "{}", summary);
+    }
+
+    #[test]
+    fn test_docopt_report() {
+        let a = parse_args(&["rq", "--report", "json", "--report-to", "/dev/fd/3"]);
+        assert_eq!(a.flag_report, Some(rq::report::Format::Json));
+        assert_eq!(a.flag_report_to, Some(path::PathBuf::from("/dev/fd/3")));
+    }
+
+    #[test]
+    fn test_docopt_strict() {
+        let a = parse_args(&["rq", "--strict"]);
+        assert!(a.flag_strict);
+        assert!(!parse_args(&["rq"]).flag_strict);
+    }
+
+    #[test]
+    fn test_docopt_field_mask() {
+        use structopt::StructOpt;
+        let a = parse_args(&["rq", "--field-mask", "id,user.name"]);
+        assert_eq!(
+            a.flag_field_mask,
+            Some(rq::value::field_mask::FieldMask::parse("id,user.name").unwrap())
+        );
+        assert!(Options::from_iter_safe(&["rq", "--field-mask", "a..b"]).is_err());
+    }
+
+    #[test]
+    fn test_docopt_anonymize() {
+        use structopt::StructOpt;
+        let a = parse_args(&[
+            "rq",
+            "--anonymize",
+            "/user/email,/user/id",
+            "--key",
+            "env:SECRET",
+        ]);
+        assert_eq!(
+            a.flag_anonymize,
+            Some(rq::value::anonymize::Pointers::parse("/user/email,/user/id").unwrap())
+        );
+        assert_eq!(a.flag_key, Some("env:SECRET".to_owned()));
+        assert!(Options::from_iter_safe(&["rq", "--anonymize", ".user.email"]).is_err());
+    }
+
+    #[test]
+    fn test_docopt_errors_to() {
+        let a = parse_args(&["rq", "--skip-bad-records", "--errors-to", "rejected.jsonl"]);
+        assert!(a.flag_skip_bad_records);
+        assert_eq!(
+            a.flag_errors_to,
+            Some(path::PathBuf::from("rejected.jsonl"))
+        );
+    }
+
+    #[test]
+    fn test_docopt_nonfinite() {
+        let a = parse_args(&["rq", "--nonfinite", "string"]);
+        assert_eq!(a.flag_nonfinite, Some(rq::value::nonfinite::Policy::String));
+    }
+
+    #[test]
+    fn test_docopt_on_unrepresentable() {
+        use rq::value::unrepresentable::Policy;
+
+        let a = parse_args(&["rq", "-T", "--on-unrepresentable", "skip-field"]);
+        assert_eq!(a.flag_on_unrepresentable, Policy::SkipField);
+        let a = parse_args(&["rq", "-T"]);
+        assert_eq!(a.flag_on_unrepresentable, Policy::Error);
+    }
+
+    #[test]
+    fn test_docopt_yaml_anchors() {
+        let a = parse_args(&["rq", "-Y", "--yaml-anchors"]);
+        assert!(a.flag_output_yaml);
+        assert!(a.flag_yaml_anchors);
+    }
+
+    #[test]
+    fn test_docopt_spread_top_level() {
+        let a = parse_args(&["rq", "-y", "--spread-top-level"]);
+        assert!(a.flag_input_yaml);
+        assert!(a.flag_spread_top_level);
+    }
+
     #[test]
     fn test_docopt_input_csv() {
         let a = parse_args(&["rq", "-v"]);
@@ -569,6 +2623,37 @@ mod test {
         assert!(a.flag_output_csv);
     }
 
+    #[test]
+    fn test_docopt_avro_append() {
+        let a = parse_args(&["rq", "-A", "main.avsc", "--avro-append", "log.avro"]);
+        assert_eq!(a.flag_avro_append, Some(path::PathBuf::from("log.avro")));
+        assert_eq!(output_extension(&a), "avro");
+    }
+
+    #[test]
+    fn test_docopt_input_bson() {
+        let a = parse_args(&["rq", "-b"]);
+        assert!(a.flag_input_bson);
+    }
+
+    #[test]
+    fn test_docopt_input_bson_long() {
+        let a = parse_args(&["rq", "--input-bson"]);
+        assert!(a.flag_input_bson);
+    }
+
+    #[test]
+    fn test_docopt_output_bson() {
+        let a = parse_args(&["rq", "-B"]);
+        assert!(a.flag_output_bson);
+    }
+
+    #[test]
+    fn test_docopt_output_bson_long() {
+        let a = parse_args(&["rq", "--output-bson"]);
+        assert!(a.flag_output_bson);
+    }
+
     #[test]
     fn test_docopt_input_cbor() {
         let a = parse_args(&["rq", "-c"]);
@@ -593,6 +2678,326 @@ mod test {
         assert!(a.flag_output_cbor);
     }
 
+    #[test]
+    fn test_docopt_canonical_cbor() {
+        let a = parse_args(&["rq", "-C", "--canonical-cbor"]);
+        assert!(a.flag_output_cbor);
+        assert!(a.flag_canonical_cbor);
+    }
+
+    #[test]
+    fn test_docopt_message_pack_options() {
+        let a = parse_args(&[
+            "rq",
+            "-M",
+            "--message-pack-legacy-raw",
+            "--message-pack-string-keys",
+        ]);
+        assert!(a.flag_output_message_pack);
+        assert!(a.flag_message_pack_legacy_raw);
+        assert!(a.flag_message_pack_string_keys);
+    }
+
+    #[test]
+    fn test_docopt_avro_sink_options() {
+        let a = parse_args(&[
+            "rq",
+            "-A",
+            "main.avsc",
+            "--avro-block-records",
+            "100",
+            "--avro-metadata",
+            "origin=rq",
+            "--avro-metadata",
+            "query=a=b",
+        ]);
+        assert_eq!(a.flag_avro_block_records, Some(100));
+        assert_eq!(a.flag_avro_block_size, None);
+        assert_eq!(
+            a.flag_avro_metadata,
+            vec![
+                AvroMetadata {
+                    key: "origin".to_owned(),
+                    value: "rq".to_owned()
+                },
+                AvroMetadata {
+                    key: "query".to_owned(),
+                    value: "a=b".to_owned()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_docopt_output_parquet() {
+        let a = parse_args(&[
+            "rq",
+            "--output-parquet",
+            "--parquet-schema",
+            "schema.txt",
+            "--parquet-infer-records",
+            "10",
+        ]);
+        assert!(a.flag_output_parquet);
+        assert_eq!(
+            a.flag_parquet_schema,
+            Some(path::PathBuf::from("schema.txt"))
+        );
+        assert_eq!(a.flag_parquet_infer_records, 10);
+        assert_eq!(parse_args(&["rq"]).flag_parquet_infer_records, 1000);
+    }
+
+    #[test]
+    fn test_docopt_avro_schema_dir() {
+        let a = parse_args(&["rq", "-A", "main.avsc", "--avro-schema-dir", "schemas"]);
+        assert_eq!(a.flag_output_avro, Some("main.avsc".to_owned()));
+        assert_eq!(a.flag_avro_schema_dir, Some(path::PathBuf::from("schemas")));
+    }
+
+    #[test]
+    fn test_docopt_adjacent_count() {
+        let a = parse_args(&["rq", "--adjacent-count"]);
+        assert_eq!(a.flag_adjacent_count, Some(None));
+        let a = parse_args(&["rq", "--adjacent-count", ".user"]);
+        assert_eq!(
+            a.flag_adjacent_count,
+            Some(Some(rq::value::path::Path::parse(".user").unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_docopt_input_schema() {
+        let a = parse_args(&[
+            "rq",
+            "-c",
+            "--input-schema",
+            "schema.json",
+            "--skip-bad-records",
+        ]);
+        assert_eq!(
+            a.flag_input_schema,
+            Some(path::PathBuf::from("schema.json"))
+        );
+        assert!(a.flag_skip_bad_records);
+    }
+
+    #[test]
+    fn test_docopt_fold_keys() {
+        let a = parse_args(&["rq", "--fold-keys", ".user.id"]);
+        assert!(a.flag_fold_keys);
+        assert_eq!(a.arg_query, Some(".user.id".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_avro_raw_logical_types() {
+        let a = parse_args(&["rq", "-a", "--avro-raw-logical-types"]);
+        assert!(a.flag_avro_raw_logical_types);
+    }
+
+    #[test]
+    fn test_docopt_input_avro_raw() {
+        let a = parse_args(&["rq", "--input-avro-raw", "event.avsc"]);
+        assert_eq!(
+            a.flag_input_avro_raw,
+            Some(path::PathBuf::from("event.avsc"))
+        );
+    }
+
+    #[test]
+    fn test_docopt_input_kafka() {
+        let a = parse_args(&["rq", "--input-kafka-avro", "event.avsc"]);
+        assert_eq!(
+            a.flag_input_kafka_avro,
+            Some(path::PathBuf::from("event.avsc"))
+        );
+        let a = parse_args(&["rq", "--input-kafka-protobuf", ".foo.Bar"]);
+        assert_eq!(a.flag_input_kafka_protobuf, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_input_capnp() {
+        let a = parse_args(&["rq", "--input-capnp", "Person"]);
+        assert_eq!(a.flag_input_capnp, Some("Person".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_input_thrift() {
+        let a = parse_args(&["rq", "--input-thrift", "compact"]);
+        assert_eq!(
+            a.flag_input_thrift,
+            Some(rq::value::thrift::Protocol::Compact)
+        );
+        let a = parse_args(&[
+            "rq",
+            "--input-thrift",
+            "binary",
+            "--thrift-idl",
+            "person.thrift",
+            "--thrift-struct",
+            "Person",
+        ]);
+        assert_eq!(
+            a.flag_input_thrift,
+            Some(rq::value::thrift::Protocol::Binary)
+        );
+        assert_eq!(
+            a.flag_thrift_idl,
+            Some(path::PathBuf::from("person.thrift"))
+        );
+        assert_eq!(a.flag_thrift_struct, Some("Person".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_input_flatbuffers() {
+        let a = parse_args(&["rq", "--input-flatbuffers", "MyGame.Monster"]);
+        assert_eq!(a.flag_input_flatbuffers, Some("MyGame.Monster".to_owned()));
+        assert!(!a.flag_flatbuffers_size_prefixed);
+        let a = parse_args(&[
+            "rq",
+            "--input-flatbuffers",
+            "Monster",
+            "--flatbuffers-size-prefixed",
+        ]);
+        assert!(a.flag_flatbuffers_size_prefixed);
+    }
+
+    #[test]
+    fn test_docopt_flatbuffers_add_schema() {
+        let a = parse_args(&["rq", "flatbuffers", "add", "monster.bfbs"]);
+        match a.subcmd {
+            Some(Subcmd::Flatbuffers {
+                subcmd: FlatbuffersSubcmd::Add { schema, base },
+            }) => {
+                assert_eq!(schema, path::PathBuf::from("monster.bfbs"));
+                assert_eq!(base, None);
+            }
+            _ => panic!("expected the flatbuffers add subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_docopt_capnp_add_schema() {
+        let a = parse_args(&[
+            "rq",
+            "capnp",
+            "add",
+            "-b",
+            "schemas",
+            "schemas/person.capnp",
+        ]);
+        match a.subcmd {
+            Some(Subcmd::Capnp {
+                subcmd: CapnpSubcmd::Add { schema, base },
+            }) => {
+                assert_eq!(schema, path::PathBuf::from("schemas/person.capnp"));
+                assert_eq!(base, Some(path::PathBuf::from("schemas")));
+            }
+            _ => panic!("expected the capnp add subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_docopt_avro_reader_schema() {
+        let a = parse_args(&["rq", "-a", "--avro-reader-schema", "v2.avsc"]);
+        assert!(a.flag_input_avro);
+        assert_eq!(
+            a.flag_avro_reader_schema,
+            Some(path::PathBuf::from("v2.avsc"))
+        );
+    }
+
+    #[test]
+    fn test_docopt_in() {
+        let a = parse_args(&["rq", "--in", "json=a.json", "--in", "avro=b=c.avro"]);
+        assert_eq!(
+            a.flag_in,
+            vec![
+                InputFile {
+                    format: InputFormat::Json,
+                    path: path::PathBuf::from("a.json"),
+                },
+                InputFile {
+                    format: InputFormat::Avro,
+                    path: path::PathBuf::from("b=c.avro"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_docopt_input_frontmatter() {
+        let a = parse_args(&["rq", "--input-frontmatter"]);
+        assert!(a.flag_input_frontmatter);
+        let a = parse_args(&["rq", "--in", "frontmatter=post.md"]);
+        assert_eq!(a.flag_in[0].format, InputFormat::Frontmatter);
+    }
+
+    #[test]
+    fn test_docopt_input_git_log() {
+        let a = parse_args(&["rq", "--input-git-log"]);
+        assert_eq!(a.flag_input_git_log, Some(None));
+        let a = parse_args(&["rq", "--input-git-log", "repo"]);
+        assert_eq!(
+            a.flag_input_git_log,
+            Some(Some(path::PathBuf::from("repo")))
+        );
+    }
+
+    #[test]
+    fn test_docopt_input_mail() {
+        let a = parse_args(&["rq", "--input-mail"]);
+        assert!(a.flag_input_mail);
+    }
+
+    #[test]
+    fn test_docopt_in_invalid() {
+        use structopt::StructOpt;
+        assert!(Options::from_iter_safe(&["rq", "--in", "a.json"]).is_err());
+        assert!(Options::from_iter_safe(&["rq", "--in", "xml=a.xml"]).is_err());
+    }
+
+    #[test]
+    fn test_docopt_normalize_timestamps() {
+        let a = parse_args(&["rq", "--normalize-timestamps", "epoch-millis"]);
+        assert_eq!(
+            a.flag_normalize_timestamps,
+            Some(rq::value::timestamp::Representation::EpochMillis)
+        );
+        assert_eq!(a.flag_assume_tz, "UTC");
+        assert!(a.flag_epoch_field.is_empty());
+
+        let a = parse_args(&[
+            "rq",
+            "--normalize-timestamps",
+            "rfc3339",
+            "--assume-tz",
+            "+02:00",
+            "--epoch-field",
+            ".created",
+            "--epoch-field",
+            ".events[0].at",
+        ]);
+        assert_eq!(
+            a.flag_normalize_timestamps,
+            Some(rq::value::timestamp::Representation::Rfc3339)
+        );
+        assert_eq!(a.flag_assume_tz, "+02:00");
+        assert_eq!(
+            a.flag_epoch_field,
+            vec![
+                rq::value::path::Path::parse(".created").unwrap(),
+                rq::value::path::Path::parse(".events[0].at").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_docopt_csv_locale() {
+        let a = parse_args(&["rq", "-v", "--csv-locale", "de_DE"]);
+        assert!(a.flag_input_csv);
+        assert_eq!(a.flag_csv_locale, Some("de_DE".to_owned()));
+    }
+
     #[test]
     fn test_docopt_input_protobuf() {
         let a = parse_args(&["rq", "-p", ".foo.Bar"]);
@@ -605,6 +3010,73 @@ mod test {
         assert_eq!(a.flag_input_protobuf, Some(".foo.Bar".to_owned()));
     }
 
+    #[test]
+    fn test_docopt_input_grpc_frames() {
+        let a = parse_args(&[
+            "rq",
+            "--input-grpc-frames",
+            "/foo.Greeter/Hello",
+            "--grpc-responses",
+        ]);
+        assert_eq!(
+            a.flag_input_grpc_frames,
+            Some("/foo.Greeter/Hello".to_owned())
+        );
+        assert!(a.flag_grpc_responses);
+    }
+
+    #[test]
+    fn test_docopt_unknown_enums() {
+        let a = parse_args(&["rq", "--input-protobuf", ".foo.Bar"]);
+        assert_eq!(
+            a.flag_unknown_enums,
+            rq::value::protobuf::UnknownEnums::Number
+        );
+        let a = parse_args(&["rq", "--unknown-enums", "string"]);
+        assert_eq!(
+            a.flag_unknown_enums,
+            rq::value::protobuf::UnknownEnums::String
+        );
+    }
+
+    #[test]
+    fn test_docopt_no_redaction() {
+        let a = parse_args(&["rq", "--input-protobuf", ".foo.Bar", "--no-redaction"]);
+        assert!(a.flag_no_redaction);
+    }
+
+    #[test]
+    fn test_docopt_input_protobuf_delimited() {
+        let a = parse_args(&["rq", "--input-protobuf-delimited", ".foo.Bar"]);
+        assert_eq!(a.flag_input_protobuf_delimited, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_input_protobuf_text() {
+        let a = parse_args(&["rq", "--input-protobuf-text", ".foo.Bar"]);
+        assert_eq!(a.flag_input_protobuf_text, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_output_protobuf_text() {
+        let a = parse_args(&["rq", "--output-protobuf-text", ".foo.Bar"]);
+        assert_eq!(a.flag_output_protobuf_text, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_output_protobuf_json() {
+        let a = parse_args(&["rq", "--output-protobuf-json", ".foo.Bar"]);
+        assert_eq!(a.flag_output_protobuf_json, Some(".foo.Bar".to_owned()));
+    }
+
+    #[test]
+    fn test_docopt_enum_style() {
+        let a = parse_args(&["rq", "--input-avro"]);
+        assert_eq!(a.flag_enum_style, rq::value::enums::Style::Symbol);
+        let a = parse_args(&["rq", "--input-avro", "--enum-style", "qualified"]);
+        assert_eq!(a.flag_enum_style, rq::value::enums::Style::Qualified);
+    }
+
     #[test]
     fn test_docopt_output_protobuf() {
         let a = parse_args(&["rq", "-P", ".foo.Bar"]);
@@ -617,6 +3089,40 @@ mod test {
         assert_eq!(a.flag_output_protobuf, Some(".foo.Bar".to_owned()));
     }
 
+    #[test]
+    fn test_docopt_output_protobuf_route() {
+        let a = parse_args(&[
+            "rq",
+            "--output-protobuf-route",
+            ".type",
+            "--route-map",
+            "a=.pkg.A,b=.pkg.B",
+        ]);
+        assert_eq!(
+            a.flag_output_protobuf_route,
+            Some(rq::value::path::Path::parse(".type").unwrap())
+        );
+        assert_eq!(
+            a.flag_route_map,
+            vec![
+                Route {
+                    value: "a".to_owned(),
+                    message: ".pkg.A".to_owned(),
+                },
+                Route {
+                    value: "b".to_owned(),
+                    message: ".pkg.B".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_docopt_output_protobuf_struct() {
+        let a = parse_args(&["rq", "--output-protobuf-struct"]);
+        assert!(a.flag_output_protobuf_struct);
+    }
+
     #[test]
     fn test_docopt_protobuf_add_schema() {
         let a = parse_args(&["rq", "-l", "info", "protobuf", "add", "schema.proto"]);
@@ -624,14 +3130,97 @@ mod test {
         assert_eq!(
             Some(path::PathBuf::from("schema.proto")),
             match a.subcmd {
-                Some(Subcmd::Protobuf { subcmd }) => match subcmd {
-                    ProtobufSubcmd::Add { schema, .. } => Some(schema),
-                },
+                Some(Subcmd::Protobuf {
+                    subcmd: ProtobufSubcmd::Add { schema, .. },
+                }) => Some(schema),
                 _ => None,
             }
         );
     }
 
+    #[test]
+    fn test_docopt_protobuf_default() {
+        let a = parse_args(&["rq", "-J", "protobuf", "default", ".pkg.Type"]);
+        assert!(a.flag_output_json);
+        match a.subcmd {
+            Some(Subcmd::Protobuf {
+                subcmd: ProtobufSubcmd::Default { message },
+            }) => assert_eq!(message, ".pkg.Type"),
+            _ => panic!("expected the protobuf default subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_docopt_protobuf_fetch() {
+        let a = parse_args(&["rq", "protobuf", "fetch", "localhost:50051"]);
+        match a.subcmd {
+            Some(Subcmd::Protobuf {
+                subcmd: ProtobufSubcmd::Fetch { address },
+            }) => assert_eq!(address, "localhost:50051"),
+            _ => panic!("expected the protobuf fetch subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_docopt_run_recipe() {
+        let a = parse_args(&["rq", "run", "avro-payloads", "input.avro"]);
+        match a.subcmd {
+            Some(Subcmd::Run { recipe, file }) => {
+                assert_eq!(recipe, "avro-payloads");
+                assert_eq!(file, Some(path::PathBuf::from("input.avro")));
+            }
+            _ => panic!("expected the run subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_docopt_watch() {
+        let a = parse_args(&[
+            "rq",
+            "watch",
+            "inbox",
+            "--recipe",
+            "to-json",
+            "--out-dir",
+            "outbox",
+        ]);
+        match a.subcmd {
+            Some(Subcmd::Watch(options)) => {
+                assert_eq!(options.dir, path::PathBuf::from("inbox"));
+                assert_eq!(options.recipe, "to-json");
+                assert_eq!(options.out_dir, path::PathBuf::from("outbox"));
+                assert_eq!(options.extension, None);
+                assert_eq!(options.interval, 1000);
+            }
+            _ => panic!("expected the watch subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_docopt_schema_ddl() {
+        let a = parse_args(&[
+            "rq",
+            "schema",
+            "ddl",
+            "--protobuf",
+            ".pkg.Type",
+            "--dialect",
+            "mysql",
+        ]);
+        match a.subcmd {
+            Some(Subcmd::Schema {
+                subcmd: SchemaSubcmd::Ddl(options),
+            }) => {
+                assert_eq!(options.avro, None);
+                assert_eq!(options.protobuf, Some(".pkg.Type".to_owned()));
+                assert_eq!(options.dialect, rq::ddl::Dialect::Mysql);
+                assert_eq!(options.nested, rq::ddl::Nested::Flatten);
+                assert_eq!(options.table, None);
+            }
+            _ => panic!("expected the schema ddl subcommand"),
+        }
+    }
+
     #[test]
     fn test_docopt_format_compact() {
         let a = parse_args(&["rq", "--format", "compact"]);
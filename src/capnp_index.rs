@@ -0,0 +1,86 @@
+use crate::config;
+use crate::error;
+use crate::proto_index;
+use crate::value::capnproto;
+
+use std::fs;
+use std::path;
+use std::process;
+
+pub fn add_file(
+    paths: &config::Paths,
+    relative_to: &path::Path,
+    file: &path::Path,
+) -> error::Result<()> {
+    let rel_file = file
+        .strip_prefix(relative_to)
+        .unwrap_or_else(|_| file.file_name().map_or(file, path::Path::new));
+    let target = paths.preferred_data("capnp").join(rel_file);
+
+    if let Some(parent) = target.parent() {
+        trace!("Creating directory {:?}", parent);
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::copy(file, &target)?;
+    info!("Added Cap'n Proto schema file as {:?}", target);
+    Ok(())
+}
+
+/// Compiles all added schema files with `capnp compile`, or reads them from the cache if none of
+/// them changed since they were last compiled.
+pub fn compile_schema(paths: &config::Paths) -> error::Result<capnproto::Schema> {
+    let capnp_includes = paths.find_data("capnp")?;
+    let capnp_files = paths.find_data("capnp/**/*.capnp")?;
+    let cache = paths.preferred_cache("capnp-schema-cache.bin");
+
+    debug!("Cap'n Proto includes: {:?}", capnp_includes);
+    debug!("Cap'n Proto files: {:?}", capnp_files);
+    debug!("Cap'n Proto cache location: {:?}", cache);
+
+    if capnp_files.is_empty() {
+        return Err(error::Error::Message(
+            "no Cap'n Proto schema files were added, see `rq capnp add`".to_owned(),
+        ));
+    }
+
+    if proto_index::is_cache_stale(&cache, &capnp_files)? {
+        info!("Cap'n Proto schema cache is stale; recomputing");
+
+        if let Some(parent) = cache.parent() {
+            trace!("Creating directory {:?}", parent);
+            fs::create_dir_all(parent)?;
+        }
+
+        // The prefixes make the names of the types relative to the data directories
+        let include_args = capnp_includes
+            .into_iter()
+            .flat_map(|p| {
+                let p = p.to_string_lossy().into_owned();
+                vec![format!("-I{}", p), format!("--src-prefix={}", p)]
+            })
+            .collect::<Vec<_>>();
+
+        let output = process::Command::new("capnp")
+            .arg("compile")
+            .arg("-o-")
+            .args(&include_args)
+            .args(&capnp_files)
+            .output()?;
+        if !output.status.success() {
+            return Err(error::Error::Message(format!(
+                "capnp schema compilation failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        fs::write(&cache, &output.stdout)?;
+
+        trace!("Cap'n Proto schema cache regenerated");
+    }
+
+    let schema = capnproto::Schema::from_code_generator_request(&fs::read(&cache)?)?;
+
+    trace!("Successfully parsed Cap'n Proto schema from cache");
+
+    Ok(schema)
+}
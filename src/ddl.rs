@@ -0,0 +1,390 @@
+use crate::error;
+
+use avro_rs;
+use serde_protobuf::descriptor;
+use std::fmt::Write;
+use std::str;
+
+/// The SQL dialect to generate DDL for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dialect {
+    Postgres,
+    Mysql,
+    Sqlite,
+}
+
+/// How to store fields holding nested records.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Nested {
+    /// A column for each nested field, named like `parent_child`.
+    Flatten,
+    /// A single JSON column.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Options {
+    pub dialect: Dialect,
+    pub nested: Nested,
+}
+
+/// A field of a record schema, in a form that is independent of the schema language.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub field_type: FieldType,
+    pub nullable: bool,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FieldType {
+    Boolean,
+    Int32,
+    Int64,
+    UInt64,
+    Float32,
+    Float64,
+    String,
+    Bytes,
+    Record(Vec<Field>),
+    /// Values that have no column type of their own, like arrays, maps and unions, which are
+    /// stored as JSON.
+    Json,
+}
+
+/// Converts an Avro record schema into its name and fields.
+pub fn fields_from_avro(schema: &avro_rs::Schema) -> error::Result<(String, Vec<Field>)> {
+    match *schema {
+        avro_rs::Schema::Record {
+            ref name,
+            ref fields,
+            ..
+        } => Ok((name.name.clone(), avro_record_fields(fields))),
+        _ => Err(error::Error::Message(
+            "only Avro record schemas can be converted to tables".to_owned(),
+        )),
+    }
+}
+
+fn avro_record_fields(fields: &[avro_rs::schema::RecordField]) -> Vec<Field> {
+    fields
+        .iter()
+        .map(|field| {
+            let (field_type, nullable) = avro_type(&field.schema);
+            Field {
+                name: field.name.clone(),
+                field_type,
+                nullable,
+            }
+        })
+        .collect()
+}
+
+fn avro_type(schema: &avro_rs::Schema) -> (FieldType, bool) {
+    use avro_rs::Schema;
+
+    match *schema {
+        Schema::Null => (FieldType::Json, true),
+        Schema::Boolean => (FieldType::Boolean, false),
+        Schema::Int => (FieldType::Int32, false),
+        Schema::Long => (FieldType::Int64, false),
+        Schema::Float => (FieldType::Float32, false),
+        Schema::Double => (FieldType::Float64, false),
+        Schema::Bytes | Schema::Fixed { .. } => (FieldType::Bytes, false),
+        Schema::String | Schema::Enum { .. } => (FieldType::String, false),
+        Schema::Array(_) | Schema::Map(_) => (FieldType::Json, false),
+        Schema::Record { ref fields, .. } => (FieldType::Record(avro_record_fields(fields)), false),
+        Schema::Union(ref union) => {
+            let variants = union
+                .variants()
+                .iter()
+                .filter(|s| **s != Schema::Null)
+                .collect::<Vec<_>>();
+            let nullable = variants.len() < union.variants().len();
+            match variants.as_slice() {
+                [single] => (avro_type(single).0, nullable),
+                _ => (FieldType::Json, nullable),
+            }
+        }
+    }
+}
+
+/// Converts a protobuf message type into its name and fields.
+pub fn fields_from_protobuf(
+    descriptors: &descriptor::Descriptors,
+    message_name: &str,
+) -> error::Result<(String, Vec<Field>)> {
+    let message = descriptors.message_by_name(message_name).ok_or_else(|| {
+        error::Error::Message(format!("unknown protobuf message type {}", message_name))
+    })?;
+    let name = message
+        .name()
+        .rsplit('.')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    Ok((name, protobuf_fields(descriptors, message, &mut Vec::new())))
+}
+
+fn protobuf_fields<'a>(
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    stack: &mut Vec<&'a str>,
+) -> Vec<Field> {
+    use serde_protobuf::descriptor::FieldType as Pb;
+
+    stack.push(message.name());
+    let fields = message
+        .fields()
+        .iter()
+        .map(|field| {
+            let field_type = if field.is_repeated() {
+                FieldType::Json
+            } else {
+                match field.field_type(descriptors) {
+                    Pb::Bool => FieldType::Boolean,
+                    Pb::Int32 | Pb::SInt32 | Pb::SFixed32 => FieldType::Int32,
+                    Pb::Int64 | Pb::SInt64 | Pb::SFixed64 | Pb::UInt32 | Pb::Fixed32 => {
+                        FieldType::Int64
+                    }
+                    Pb::UInt64 | Pb::Fixed64 => FieldType::UInt64,
+                    Pb::Float => FieldType::Float32,
+                    Pb::Double => FieldType::Float64,
+                    Pb::String | Pb::Enum(_) | Pb::UnresolvedEnum(_) => FieldType::String,
+                    Pb::Bytes => FieldType::Bytes,
+                    // Recursive messages can't be flattened
+                    Pb::Message(m) if !stack.contains(&m.name()) => {
+                        FieldType::Record(protobuf_fields(descriptors, m, stack))
+                    }
+                    Pb::Message(_) | Pb::UnresolvedMessage(_) | Pb::Group => FieldType::Json,
+                }
+            };
+            Field {
+                name: field.name().to_owned(),
+                field_type,
+                nullable: field.field_label() != descriptor::FieldLabel::Required,
+            }
+        })
+        .collect();
+    stack.pop();
+    fields
+}
+
+/// Generates a `CREATE TABLE` statement with a column for each field.
+pub fn create_table(table: &str, fields: &[Field], options: Options) -> String {
+    let mut columns = Vec::new();
+    add_columns(&mut columns, None, fields, false, options);
+
+    let mut result = String::new();
+    writeln!(result, "CREATE TABLE {} (", quote(table, options.dialect)).unwrap();
+    for (i, (name, sql_type, nullable)) in columns.iter().enumerate() {
+        write!(result, "  {} {}", quote(name, options.dialect), sql_type).unwrap();
+        if !nullable {
+            result.push_str(" NOT NULL");
+        }
+        result.push_str(if i + 1 < columns.len() { ",\n" } else { "\n" });
+    }
+    result.push_str(");\n");
+    result
+}
+
+fn add_columns(
+    columns: &mut Vec<(String, &'static str, bool)>,
+    prefix: Option<&str>,
+    fields: &[Field],
+    nullable: bool,
+    options: Options,
+) {
+    for field in fields {
+        let name = match prefix {
+            Some(prefix) => format!("{}_{}", prefix, field.name),
+            None => field.name.clone(),
+        };
+        let nullable = nullable || field.nullable;
+        match field.field_type {
+            FieldType::Record(ref fields) if options.nested == Nested::Flatten => {
+                add_columns(columns, Some(&name), fields, nullable, options)
+            }
+            ref field_type => columns.push((name, sql_type(field_type, options.dialect), nullable)),
+        }
+    }
+}
+
+fn sql_type(field_type: &FieldType, dialect: Dialect) -> &'static str {
+    match (dialect, field_type) {
+        (Dialect::Postgres, FieldType::Boolean) => "BOOLEAN",
+        (Dialect::Postgres, FieldType::Int32) => "INTEGER",
+        (Dialect::Postgres, FieldType::Int64) => "BIGINT",
+        (Dialect::Postgres, FieldType::UInt64) => "NUMERIC(20)",
+        (Dialect::Postgres, FieldType::Float32) => "REAL",
+        (Dialect::Postgres, FieldType::Float64) => "DOUBLE PRECISION",
+        (Dialect::Postgres, FieldType::String) => "TEXT",
+        (Dialect::Postgres, FieldType::Bytes) => "BYTEA",
+        (Dialect::Postgres, FieldType::Record(_)) | (Dialect::Postgres, FieldType::Json) => "JSONB",
+
+        (Dialect::Mysql, FieldType::Boolean) => "BOOLEAN",
+        (Dialect::Mysql, FieldType::Int32) => "INT",
+        (Dialect::Mysql, FieldType::Int64) => "BIGINT",
+        (Dialect::Mysql, FieldType::UInt64) => "BIGINT UNSIGNED",
+        (Dialect::Mysql, FieldType::Float32) => "FLOAT",
+        (Dialect::Mysql, FieldType::Float64) => "DOUBLE",
+        (Dialect::Mysql, FieldType::String) => "TEXT",
+        (Dialect::Mysql, FieldType::Bytes) => "LONGBLOB",
+        (Dialect::Mysql, FieldType::Record(_)) | (Dialect::Mysql, FieldType::Json) => "JSON",
+
+        // SQLite only has storage classes; 64 bit unsigned integers don't fit in its integers
+        (Dialect::Sqlite, FieldType::Boolean)
+        | (Dialect::Sqlite, FieldType::Int32)
+        | (Dialect::Sqlite, FieldType::Int64) => "INTEGER",
+        (Dialect::Sqlite, FieldType::UInt64) => "NUMERIC",
+        (Dialect::Sqlite, FieldType::Float32) | (Dialect::Sqlite, FieldType::Float64) => "REAL",
+        (Dialect::Sqlite, FieldType::Bytes) => "BLOB",
+        (Dialect::Sqlite, FieldType::String)
+        | (Dialect::Sqlite, FieldType::Record(_))
+        | (Dialect::Sqlite, FieldType::Json) => "TEXT",
+    }
+}
+
+fn quote(identifier: &str, dialect: Dialect) -> String {
+    match dialect {
+        Dialect::Mysql => format!("`{}`", identifier.replace('`', "``")),
+        Dialect::Postgres | Dialect::Sqlite => format!("\"{}\"", identifier.replace('"', "\"\"")),
+    }
+}
+
+impl str::FromStr for Dialect {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postgres" | "postgresql" => Ok(Dialect::Postgres),
+            "mysql" => Ok(Dialect::Mysql),
+            "sqlite" => Ok(Dialect::Sqlite),
+            _ => Err(error::Error::Message(format!(
+                "unknown SQL dialect {:?}, expected 'postgres', 'mysql' or 'sqlite'",
+                s
+            ))),
+        }
+    }
+}
+
+impl str::FromStr for Nested {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flatten" => Ok(Nested::Flatten),
+            "json" => Ok(Nested::Json),
+            _ => Err(error::Error::Message(format!(
+                "unknown nested field strategy {:?}, expected 'flatten' or 'json'",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PERSON: &str = r#"{
+        "type": "record",
+        "name": "Person",
+        "fields": [
+            {"name": "name", "type": "string"},
+            {"name": "age", "type": ["null", "int"]},
+            {"name": "tags", "type": {"type": "array", "items": "string"}},
+            {"name": "address", "type": ["null", {
+                "type": "record",
+                "name": "Address",
+                "fields": [
+                    {"name": "street", "type": "string"},
+                    {"name": "zip", "type": "long"}
+                ]
+            }]}
+        ]
+    }"#;
+
+    #[test]
+    fn test_avro_flatten() {
+        let schema = avro_rs::Schema::parse_str(PERSON).unwrap();
+        let (name, fields) = fields_from_avro(&schema).unwrap();
+        let options = Options {
+            dialect: Dialect::Postgres,
+            nested: Nested::Flatten,
+        };
+        assert_eq!(
+            create_table(&name, &fields, options),
+            "CREATE TABLE \"Person\" (\n  \
+             \"name\" TEXT NOT NULL,\n  \
+             \"age\" INTEGER,\n  \
+             \"tags\" JSONB NOT NULL,\n  \
+             \"address_street\" TEXT,\n  \
+             \"address_zip\" BIGINT\n\
+             );\n"
+        );
+    }
+
+    #[test]
+    fn test_avro_json() {
+        let schema = avro_rs::Schema::parse_str(PERSON).unwrap();
+        let (_, fields) = fields_from_avro(&schema).unwrap();
+        let options = Options {
+            dialect: Dialect::Mysql,
+            nested: Nested::Json,
+        };
+        assert_eq!(
+            create_table("people", &fields, options),
+            "CREATE TABLE `people` (\n  \
+             `name` TEXT NOT NULL,\n  \
+             `age` INT,\n  \
+             `tags` JSON NOT NULL,\n  \
+             `address` JSON\n\
+             );\n"
+        );
+    }
+
+    #[test]
+    fn test_protobuf() {
+        let mut node = descriptor::MessageDescriptor::new(".foo.Node");
+        node.add_field(descriptor::FieldDescriptor::new(
+            "id",
+            1,
+            descriptor::FieldLabel::Required,
+            descriptor::InternalFieldType::UInt64,
+            None,
+        ));
+        node.add_field(descriptor::FieldDescriptor::new(
+            "parent",
+            2,
+            descriptor::FieldLabel::Optional,
+            descriptor::InternalFieldType::UnresolvedMessage(".foo.Node".to_owned()),
+            None,
+        ));
+        node.add_field(descriptor::FieldDescriptor::new(
+            "labels",
+            3,
+            descriptor::FieldLabel::Repeated,
+            descriptor::InternalFieldType::String,
+            None,
+        ));
+        let mut descriptors = descriptor::Descriptors::new();
+        descriptors.add_message(node);
+        descriptors.resolve_refs();
+
+        let (name, fields) = fields_from_protobuf(&descriptors, ".foo.Node").unwrap();
+        let options = Options {
+            dialect: Dialect::Sqlite,
+            nested: Nested::Flatten,
+        };
+        assert_eq!(
+            create_table(&name, &fields, options),
+            "CREATE TABLE \"Node\" (\n  \
+             \"id\" NUMERIC NOT NULL,\n  \
+             \"parent\" TEXT,\n  \
+             \"labels\" TEXT\n\
+             );\n"
+        );
+        assert!(fields_from_protobuf(&descriptors, ".foo.Missing").is_err());
+    }
+}
@@ -1,4 +1,6 @@
+use crate::value;
 use csv;
+use git2;
 use glob;
 use protobuf;
 use rmpv;
@@ -46,6 +48,8 @@ pub enum Error {
     TomlDeserialize(#[cause] toml::de::Error),
     #[fail(display = "TOML serialize error")]
     TomlSerialize(#[cause] toml::ser::Error),
+    #[fail(display = "git error")]
+    Git(#[cause] git2::Error),
     #[fail(display = "glob error")]
     Glob(#[cause] glob::GlobError),
     #[fail(display = "glob pattern error")]
@@ -60,6 +64,10 @@ pub enum Error {
     IllegalState { msg: String },
     #[fail(display = "format error: {}", msg)]
     Format { msg: String },
+    #[fail(display = "upstream error: {}", msg)]
+    Upstream { msg: String },
+    #[fail(display = "invalid record: {}", msg)]
+    InvalidRecord { msg: String, record: value::Value },
     #[fail(display = "internal error: {}", _0)]
     Internal(&'static str),
     #[fail(display = "{}", _0)]
@@ -88,6 +96,41 @@ impl Error {
     pub fn illegal_state(msg: String) -> Self {
         Self::IllegalState { msg }
     }
+
+    /// Formats the error and its causes on one line.
+    pub fn chain(&self) -> String {
+        let mut result = self.to_string();
+        for cause in <dyn failure::Fail>::iter_causes(self) {
+            result.push_str(": ");
+            result.push_str(&cause.to_string());
+        }
+        result
+    }
+
+    /// A short name for the kind of error, for tallying errors in reports.
+    pub fn category(&self) -> &'static str {
+        match *self {
+            Self::Protobuf(_) | Self::NativeProtobuf(_) => "protobuf",
+            Self::Io(_) => "io",
+            Self::Utf8(_) => "utf8",
+            Self::MessagePackEncode(_) | Self::MessagePackDecode(_) => "message_pack",
+            Self::Avro(_) => "avro",
+            Self::Cbor(_) => "cbor",
+            Self::Hjson(_) => "hjson",
+            Self::Json(_) => "json",
+            Self::Yaml(_) | Self::YamlScan(_) => "yaml",
+            Self::TomlDeserialize(_) | Self::TomlSerialize(_) => "toml",
+            Self::Git(_) => "git",
+            Self::Glob(_) | Self::GlobPattern(_) => "glob",
+            Self::Csv(_) => "csv",
+            Self::Unimplemented { .. } => "unimplemented",
+            Self::IllegalState { .. } | Self::Internal(_) => "internal",
+            Self::Format { .. } => "format",
+            Self::Upstream { .. } => "upstream",
+            Self::InvalidRecord { .. } => "invalid_record",
+            Self::Message(_) => "other",
+        }
+    }
 }
 
 impl Avro {
@@ -142,6 +185,7 @@ gen_from!(serde_yaml::Error, Yaml);
 gen_from!(yaml_rust::ScanError, YamlScan);
 gen_from!(toml::de::Error, TomlDeserialize);
 gen_from!(toml::ser::Error, TomlSerialize);
+gen_from!(git2::Error, Git);
 gen_from!(glob::GlobError, Glob);
 gen_from!(glob::PatternError, GlobPattern);
 gen_from!(csv::Error, Csv);
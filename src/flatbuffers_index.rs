@@ -0,0 +1,41 @@
+use crate::config;
+use crate::error;
+use crate::value::flatbuffers;
+
+use std::fs;
+use std::path;
+
+/// Adds a binary reflection schema, after checking that it can be read.
+pub fn add_file(
+    paths: &config::Paths,
+    relative_to: &path::Path,
+    file: &path::Path,
+) -> error::Result<()> {
+    flatbuffers::Schema::from_bfbs(&fs::read(file)?)?;
+
+    let rel_file = file
+        .strip_prefix(relative_to)
+        .unwrap_or_else(|_| file.file_name().map_or(file, path::Path::new));
+    let target = paths.preferred_data("flatbuffers").join(rel_file);
+
+    if let Some(parent) = target.parent() {
+        trace!("Creating directory {:?}", parent);
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::copy(file, &target)?;
+    info!("Added FlatBuffers schema file as {:?}", target);
+    Ok(())
+}
+
+/// Reads all added binary reflection schemas.
+pub fn load_schemas(paths: &config::Paths) -> error::Result<Vec<flatbuffers::Schema>> {
+    let schema_files = paths.find_data("flatbuffers/**/*.bfbs")?;
+
+    debug!("FlatBuffers schema files: {:?}", schema_files);
+
+    schema_files
+        .iter()
+        .map(|file| flatbuffers::Schema::from_bfbs(&fs::read(file)?))
+        .collect()
+}
@@ -21,10 +21,18 @@ extern crate log;
 #[macro_use]
 extern crate pest;
 
+pub mod capnp_index;
 pub mod config;
+pub mod ddl;
 pub mod error;
+pub mod flatbuffers_index;
 pub mod proto_index;
+pub mod query;
+pub mod recipe;
+pub mod redaction;
+pub mod report;
 pub mod value;
+pub mod watch;
 
 pub const VERSION: &str = env!("VERGEN_GIT_SEMVER");
 
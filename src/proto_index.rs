@@ -7,6 +7,75 @@ use std::fs;
 use std::path;
 use std::process;
 
+pub mod reflection;
+
+/// An index of the RPC methods of all services in a descriptor set.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ServiceIndex {
+    methods: Vec<Method>,
+}
+
+/// A single RPC method, with message types given as fully qualified names like `.foo.Bar`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Method {
+    pub service: String,
+    pub name: String,
+    pub input_type: String,
+    pub output_type: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
+}
+
+impl ServiceIndex {
+    pub fn from_proto(file_set_proto: &protobuf::descriptor::FileDescriptorSet) -> Self {
+        let mut methods = Vec::new();
+        for file in file_set_proto.get_file() {
+            for service in file.get_service() {
+                let service_name = if file.get_package().is_empty() {
+                    service.get_name().to_owned()
+                } else {
+                    format!("{}.{}", file.get_package(), service.get_name())
+                };
+                for method in service.get_method() {
+                    methods.push(Method {
+                        service: service_name.clone(),
+                        name: method.get_name().to_owned(),
+                        input_type: method.get_input_type().to_owned(),
+                        output_type: method.get_output_type().to_owned(),
+                        client_streaming: method.get_client_streaming(),
+                        server_streaming: method.get_server_streaming(),
+                    });
+                }
+            }
+        }
+        ServiceIndex { methods }
+    }
+
+    pub fn methods(&self) -> &[Method] {
+        &self.methods
+    }
+
+    /// Looks up a method by its gRPC path like `/foo.Greeter/Hello`; `foo.Greeter.Hello` is also
+    /// accepted.
+    pub fn method_by_name(&self, name: &str) -> Option<&Method> {
+        let name = name.trim_start_matches('/');
+        self.methods.iter().find(|m| {
+            name.len() == m.service.len() + 1 + m.name.len()
+                && name.starts_with(m.service.as_str())
+                && name.ends_with(m.name.as_str())
+                && (name.as_bytes()[m.service.len()] == b'/'
+                    || name.as_bytes()[m.service.len()] == b'.')
+        })
+    }
+}
+
+impl Method {
+    /// The gRPC path of this method, like `/foo.Greeter/Hello`.
+    pub fn path(&self) -> String {
+        format!("/{}/{}", self.service, self.name)
+    }
+}
+
 pub fn add_file(
     paths: &config::Paths,
     relative_to: &path::Path,
@@ -32,13 +101,79 @@ pub fn compile_descriptor_set(
 ) -> error::Result<protobuf::descriptor::FileDescriptorSet> {
     let proto_includes = paths.find_data("proto")?;
     let proto_files = paths.find_data("proto/**/*.proto")?;
+    let fetched_files = paths.find_data("descriptors/*.pb")?;
     let cache = paths.preferred_cache("descriptor-cache.pb");
 
     debug!("Proto includes: {:?}", proto_includes);
     debug!("Proto files: {:?}", proto_files);
     debug!("Proto cache location: {:?}", cache);
 
-    if is_cache_stale(&cache, &proto_files)? {
+    let mut descriptor_set = if proto_files.is_empty() {
+        protobuf::descriptor::FileDescriptorSet::new()
+    } else {
+        compile_proto_files(proto_includes, &proto_files, &cache)?
+    };
+
+    // Files that were fetched from servers, unless they were also added as proto files
+    for fetched_file in fetched_files {
+        let mut file = fs::File::open(&fetched_file)?;
+        let fetched: protobuf::descriptor::FileDescriptorSet =
+            protobuf::Message::parse_from_reader(&mut file)?;
+        for file in fetched.get_file() {
+            if !descriptor_set
+                .get_file()
+                .iter()
+                .any(|f| f.get_name() == file.get_name())
+            {
+                descriptor_set.mut_file().push(file.clone());
+            }
+        }
+        trace!("Added descriptors fetched as {:?}", fetched_file);
+    }
+
+    Ok(descriptor_set)
+}
+
+/// Fetches the descriptors of all services of the gRPC server at an address like
+/// `localhost:50051` with server reflection, and stores them so that their types can be used like
+/// those of added proto files.
+pub fn fetch_server(paths: &config::Paths, address: &str) -> error::Result<()> {
+    let file_set = reflection::fetch(address)?;
+    let file_name = address
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let target = paths
+        .preferred_data("descriptors")
+        .join(format!("{}.pb", file_name));
+
+    if let Some(parent) = target.parent() {
+        trace!("Creating directory {:?}", parent);
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(&target, protobuf::Message::write_to_bytes(&file_set)?)?;
+    info!(
+        "Fetched {} proto files from {} as {:?}",
+        file_set.get_file().len(),
+        address,
+        target
+    );
+    Ok(())
+}
+
+fn compile_proto_files(
+    proto_includes: Vec<path::PathBuf>,
+    proto_files: &[path::PathBuf],
+    cache: &path::Path,
+) -> error::Result<protobuf::descriptor::FileDescriptorSet> {
+    if is_cache_stale(cache, proto_files)? {
         info!("Proto descriptor cache is stale; recomputing");
 
         if let Some(parent) = cache.parent() {
@@ -53,9 +188,9 @@ pub fn compile_descriptor_set(
 
         let status = process::Command::new("protoc")
             .arg("-o")
-            .arg(&cache)
+            .arg(cache)
             .args(&include_args)
-            .args(&proto_files)
+            .args(proto_files)
             .status()?;
         if !status.success() {
             panic!("protoc descriptor compilation failed");
@@ -64,7 +199,7 @@ pub fn compile_descriptor_set(
         trace!("Proto descriptor cache regenerated");
     }
 
-    let mut cache_file = fs::File::open(&cache)?;
+    let mut cache_file = fs::File::open(cache)?;
     let descriptor_set = protobuf::Message::parse_from_reader(&mut cache_file)?;
 
     trace!("Successfully parsed descriptor set from cache");
@@ -72,17 +207,17 @@ pub fn compile_descriptor_set(
     Ok(descriptor_set)
 }
 
-fn is_cache_stale<P>(cache: &path::Path, proto_files: &[P]) -> error::Result<bool>
+pub(crate) fn is_cache_stale<P>(cache: &path::Path, proto_files: &[P]) -> error::Result<bool>
 where
     P: AsRef<path::Path>,
 {
     if cache.exists() {
-        let cache_metadata = fs::metadata(&cache)?;
+        let cache_metadata = fs::metadata(cache)?;
         let cache_mtime = cache_metadata.modified()?;
         let mut max_proto_mtime = std::time::SystemTime::UNIX_EPOCH;
 
         for proto_file in proto_files.iter() {
-            let proto_metadata = fs::metadata(&proto_file)?;
+            let proto_metadata = fs::metadata(proto_file)?;
             let proto_mtime = proto_metadata.modified()?;
             max_proto_mtime = cmp::max(max_proto_mtime, proto_mtime);
         }
@@ -92,3 +227,40 @@ where
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protobuf::descriptor;
+
+    #[test]
+    fn test_service_index() {
+        let mut method = descriptor::MethodDescriptorProto::new();
+        method.set_name("Hello".to_owned());
+        method.set_input_type(".foo.HelloRequest".to_owned());
+        method.set_output_type(".foo.HelloReply".to_owned());
+        method.set_server_streaming(true);
+        let mut service = descriptor::ServiceDescriptorProto::new();
+        service.set_name("Greeter".to_owned());
+        service.mut_method().push(method);
+        let mut file = descriptor::FileDescriptorProto::new();
+        file.set_package("foo".to_owned());
+        file.mut_service().push(service);
+        let mut file_set = descriptor::FileDescriptorSet::new();
+        file_set.mut_file().push(file);
+
+        let index = ServiceIndex::from_proto(&file_set);
+        assert_eq!(index.methods().len(), 1);
+
+        let method = index.method_by_name("/foo.Greeter/Hello").unwrap();
+        assert_eq!(method.path(), "/foo.Greeter/Hello");
+        assert_eq!(method.input_type, ".foo.HelloRequest");
+        assert_eq!(method.output_type, ".foo.HelloReply");
+        assert!(!method.client_streaming);
+        assert!(method.server_streaming);
+
+        assert_eq!(index.method_by_name("foo.Greeter.Hello"), Some(method));
+        assert_eq!(index.method_by_name("/foo.Greeter/Bye"), None);
+        assert_eq!(index.method_by_name("/foo.Greete/rHello"), None);
+    }
+}
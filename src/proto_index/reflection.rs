@@ -0,0 +1,759 @@
+//! A client for the gRPC server reflection protocol, which downloads the descriptors of the
+//! services of a server.
+//!
+//! It speaks just enough HTTP/2 over plaintext TCP (h2c with prior knowledge) to make unary calls:
+//! headers are sent as HPACK literals, and the headers that the server sends are only decoded for
+//! the status of calls.
+
+use crate::error;
+
+use protobuf;
+use protobuf::Message as _;
+use std::io;
+use std::net;
+use std::str;
+use std::time;
+
+mod hpack;
+
+/// The reflection services to try, newest first, since older servers only have v1alpha.
+const SERVICE_PATHS: [&str; 2] = [
+    "/grpc.reflection.v1.ServerReflection/ServerReflectionInfo",
+    "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+];
+
+const TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const MAX_FRAME_SIZE: usize = 16_384;
+const MAX_WINDOW_SIZE: u32 = 0x7fff_ffff;
+const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_PING: u8 = 0x6;
+const FRAME_GOAWAY: u8 = 0x7;
+const FRAME_WINDOW_UPDATE: u8 = 0x8;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+
+const STATUS_OK: &str = "0";
+const STATUS_UNIMPLEMENTED: &str = "12";
+
+// Field numbers from grpc/reflection/v1/reflection.proto
+const REQUEST_FILE_BY_FILENAME: u32 = 3;
+const REQUEST_FILE_CONTAINING_SYMBOL: u32 = 4;
+const REQUEST_LIST_SERVICES: u32 = 7;
+const RESPONSE_FILE_DESCRIPTOR: u32 = 4;
+const RESPONSE_LIST_SERVICES: u32 = 6;
+const RESPONSE_ERROR: u32 = 7;
+
+/// An HTTP/2 connection to a gRPC server.
+struct Client<S> {
+    stream: S,
+    authority: String,
+    next_stream_id: u32,
+    settings_received: bool,
+    headers: hpack::Decoder,
+}
+
+/// What a reflection service answered.
+#[derive(Debug, PartialEq)]
+enum Response {
+    Files(Vec<Vec<u8>>),
+    Services(Vec<String>),
+    Error { code: i32, message: String },
+}
+
+/// Downloads the descriptors of all services of the gRPC server at an address like
+/// `localhost:50051`, with the files that they import.
+pub fn fetch(address: &str) -> error::Result<protobuf::descriptor::FileDescriptorSet> {
+    let stream = net::TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut client = Client::new(stream, address)?;
+    fetch_files(&mut client, address)
+}
+
+fn fetch_files<S>(
+    client: &mut Client<S>,
+    address: &str,
+) -> error::Result<protobuf::descriptor::FileDescriptorSet>
+where
+    S: io::Read + io::Write,
+{
+    let mut service_path = None;
+    let mut services = Vec::new();
+    for path in &SERVICE_PATHS {
+        if let Some(response) = client.call(path, &request(REQUEST_LIST_SERVICES, "*")?)? {
+            service_path = Some(*path);
+            services = match parse_response(&response)? {
+                Response::Services(services) => services,
+                response => return Err(unexpected(address, "its services", response)),
+            };
+            break;
+        }
+    }
+    let service_path = service_path.ok_or_else(|| error::Error::Upstream {
+        msg: format!(
+            "the gRPC server at {} doesn't support server reflection",
+            address
+        ),
+    })?;
+    debug!("Services of {}: {:?}", address, services);
+
+    let mut file_set = protobuf::descriptor::FileDescriptorSet::new();
+    let mut pending = services
+        .into_iter()
+        .map(|s| (REQUEST_FILE_CONTAINING_SYMBOL, s))
+        .collect::<Vec<_>>();
+    pending.reverse();
+    while let Some((field, name)) = pending.pop() {
+        let files = match client.call(service_path, &request(field, &name)?)? {
+            Some(response) => parse_response(&response)?,
+            None => {
+                return Err(error::Error::Upstream {
+                    msg: format!("the gRPC server at {} didn't describe {}", address, name),
+                })
+            }
+        };
+        let files = match files {
+            Response::Files(files) => files,
+            response => return Err(unexpected(address, &name, response)),
+        };
+        for bytes in files {
+            let file = protobuf::descriptor::FileDescriptorProto::parse_from_bytes(&bytes)?;
+            if has_file(&file_set, file.get_name()) {
+                continue;
+            }
+            for dependency in file.get_dependency() {
+                if !has_file(&file_set, dependency) && !pending.iter().any(|(_, n)| n == dependency)
+                {
+                    pending.push((REQUEST_FILE_BY_FILENAME, dependency.clone()));
+                }
+            }
+            trace!("Fetched proto file {:?}", file.get_name());
+            file_set.mut_file().push(file);
+        }
+        // The files that a server sent along with the one that was asked for
+        pending.retain(|(f, n)| *f != REQUEST_FILE_BY_FILENAME || !has_file(&file_set, n));
+    }
+    Ok(file_set)
+}
+
+fn has_file(file_set: &protobuf::descriptor::FileDescriptorSet, name: &str) -> bool {
+    file_set.get_file().iter().any(|f| f.get_name() == name)
+}
+
+fn unexpected(address: &str, what: &str, response: Response) -> error::Error {
+    let msg = match response {
+        Response::Error { code, message } => format!(
+            "the gRPC server at {} failed describing {}: {} (code {})",
+            address, what, message, code
+        ),
+        response => format!(
+            "the gRPC server at {} sent an unexpected response describing {}: {:?}",
+            address, what, response
+        ),
+    };
+    error::Error::Upstream { msg }
+}
+
+/// Encodes a `ServerReflectionRequest` with a single string field.
+fn request(field: u32, value: &str) -> error::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    {
+        let mut output = protobuf::CodedOutputStream::vec(&mut result);
+        output.write_string(field, value)?;
+        output.flush()?;
+    }
+    Ok(result)
+}
+
+/// Decodes the fields of a `ServerReflectionResponse` that are answers to the requests.
+fn parse_response(bytes: &[u8]) -> error::Result<Response> {
+    let mut input = protobuf::CodedInputStream::from_bytes(bytes);
+    while !input.eof()? {
+        let (number, wire_type) = input.read_tag_unpack()?;
+        match number {
+            RESPONSE_FILE_DESCRIPTOR => {
+                let bytes = input.read_bytes()?;
+                return Ok(Response::Files(repeated_bytes(&bytes)?));
+            }
+            RESPONSE_LIST_SERVICES => {
+                let bytes = input.read_bytes()?;
+                let services = repeated_bytes(&bytes)?
+                    .into_iter()
+                    .map(|service| {
+                        let names = repeated_bytes(&service)?;
+                        let name = names.into_iter().next().unwrap_or_default();
+                        Ok(String::from_utf8(name)?)
+                    })
+                    .collect::<error::Result<Vec<_>>>()?;
+                return Ok(Response::Services(services));
+            }
+            RESPONSE_ERROR => {
+                let bytes = input.read_bytes()?;
+                let mut input = protobuf::CodedInputStream::from_bytes(&bytes);
+                let mut code = 0;
+                let mut message = String::new();
+                while !input.eof()? {
+                    match input.read_tag_unpack()? {
+                        (1, _) => code = input.read_int32()?,
+                        (2, _) => message = input.read_string()?,
+                        (_, wire_type) => input.skip_field(wire_type)?,
+                    }
+                }
+                return Ok(Response::Error { code, message });
+            }
+            _ => input.skip_field(wire_type)?,
+        }
+    }
+    Err(error::Error::Upstream {
+        msg: "the gRPC server sent an empty reflection response".to_owned(),
+    })
+}
+
+/// The values of field 1 of a message, which is a repeated bytes, string or message field in all
+/// reflection messages.
+fn repeated_bytes(bytes: &[u8]) -> error::Result<Vec<Vec<u8>>> {
+    let mut input = protobuf::CodedInputStream::from_bytes(bytes);
+    let mut result = Vec::new();
+    while !input.eof()? {
+        match input.read_tag_unpack()? {
+            (1, _) => result.push(input.read_bytes()?),
+            (_, wire_type) => input.skip_field(wire_type)?,
+        }
+    }
+    Ok(result)
+}
+
+impl<S> Client<S>
+where
+    S: io::Read + io::Write,
+{
+    fn new(stream: S, authority: &str) -> error::Result<Self> {
+        let mut client = Client {
+            stream,
+            authority: authority.to_owned(),
+            next_stream_id: 1,
+            settings_received: false,
+            headers: hpack::Decoder::default(),
+        };
+
+        // Large windows, so that responses don't need to be acknowledged while they arrive
+        let mut settings = Vec::new();
+        for &(id, value) in &[
+            (SETTINGS_ENABLE_PUSH, 0),
+            (SETTINGS_INITIAL_WINDOW_SIZE, MAX_WINDOW_SIZE),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+        client.stream.write_all(PREFACE)?;
+        client.write_frame(FRAME_SETTINGS, 0, 0, &settings)?;
+        client.write_frame(
+            FRAME_WINDOW_UPDATE,
+            0,
+            0,
+            &(MAX_WINDOW_SIZE - DEFAULT_WINDOW_SIZE).to_be_bytes(),
+        )?;
+        Ok(client)
+    }
+
+    /// Makes a unary call, and returns the response message, or `None` if the server doesn't
+    /// implement the method.
+    fn call(&mut self, path: &str, message: &[u8]) -> error::Result<Option<Vec<u8>>> {
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 2;
+
+        let mut headers = Vec::new();
+        for &(name, value) in &[
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", path),
+            (":authority", self.authority.as_str()),
+            ("content-type", "application/grpc"),
+            ("te", "trailers"),
+        ] {
+            // A literal header field without indexing, with a new name
+            headers.push(0);
+            hpack_string(&mut headers, name);
+            hpack_string(&mut headers, value);
+        }
+        let mut data = vec![0];
+        data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        data.extend_from_slice(message);
+        if headers.len() > MAX_FRAME_SIZE || data.len() > MAX_FRAME_SIZE {
+            return Err(error::Error::Message(format!(
+                "gRPC request to {} is too large",
+                path
+            )));
+        }
+        self.write_frame(FRAME_HEADERS, FLAG_END_HEADERS, stream_id, &headers)?;
+        self.write_frame(FRAME_DATA, FLAG_END_STREAM, stream_id, &data)?;
+        self.stream.flush()?;
+
+        let mut body = Vec::new();
+        let mut status = None;
+        let mut message = Vec::new();
+        loop {
+            let (frame_type, flags, id, mut payload) = self.read_frame()?;
+            match frame_type {
+                // Header blocks of all streams change the state of the HPACK decoder
+                FRAME_HEADERS => {
+                    let headers = self.header_block(flags, payload)?;
+                    if id != stream_id {
+                        continue;
+                    }
+                    for (name, value) in headers {
+                        match name.as_slice() {
+                            b"grpc-status" => status = Some(value),
+                            b"grpc-message" => message = value,
+                            _ => (),
+                        }
+                    }
+                    if flags & FLAG_END_STREAM != 0 {
+                        break;
+                    }
+                }
+                FRAME_DATA if id == stream_id => {
+                    body.extend_from_slice(unpad(flags, &mut payload)?);
+                    if flags & FLAG_END_STREAM != 0 {
+                        break;
+                    }
+                }
+                FRAME_RST_STREAM if id == stream_id => {
+                    return Err(error::Error::Upstream {
+                        msg: format!(
+                            "the gRPC server reset the call of {} with error code {}",
+                            path,
+                            be_u32(&payload)
+                        ),
+                    })
+                }
+                FRAME_GOAWAY => {
+                    return Err(error::Error::Upstream {
+                        msg: format!(
+                            "the gRPC server closed the connection with error code {}",
+                            be_u32(payload.get(4..).unwrap_or_default())
+                        ),
+                    })
+                }
+                FRAME_SETTINGS | FRAME_PING if flags & FLAG_ACK == 0 => {
+                    let ack = if frame_type == FRAME_PING {
+                        payload.as_slice()
+                    } else {
+                        &[]
+                    };
+                    self.write_frame(frame_type, FLAG_ACK, 0, ack)?;
+                    self.stream.flush()?;
+                }
+                _ => (),
+            }
+        }
+
+        match status.as_ref().map(|s| String::from_utf8_lossy(s)) {
+            None => (),
+            Some(ref status) if status == STATUS_OK => (),
+            Some(ref status) if status == STATUS_UNIMPLEMENTED => return Ok(None),
+            Some(status) => {
+                let mut msg = format!(
+                    "the gRPC server failed the call of {} with status {}",
+                    path, status
+                );
+                if !message.is_empty() {
+                    msg.push_str(": ");
+                    msg.push_str(&percent_decode(&message));
+                }
+                return Err(error::Error::Upstream { msg });
+            }
+        }
+        if body.is_empty() {
+            return Ok(None);
+        }
+        if body.len() < 5 || body[0] != 0 || be_u32(&body[1..5]) as usize != body.len() - 5 {
+            return Err(error::Error::Upstream {
+                msg: format!("the gRPC server sent a malformed response to {}", path),
+            });
+        }
+        Ok(Some(body.split_off(5)))
+    }
+
+    /// Reads the rest of a header block that starts in a HEADERS frame, and decodes it.
+    fn header_block(
+        &mut self,
+        flags: u8,
+        mut payload: Vec<u8>,
+    ) -> error::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut block = unpad(flags, &mut payload)?;
+        if flags & FLAG_PRIORITY != 0 {
+            // The stream dependency and weight
+            block = block.get(5..).unwrap_or_default();
+        }
+        let mut block = block.to_vec();
+        let mut end_headers = flags & FLAG_END_HEADERS != 0;
+        while !end_headers {
+            let (frame_type, flags, _, payload) = self.read_frame()?;
+            if frame_type != FRAME_CONTINUATION {
+                return Err(error::Error::Upstream {
+                    msg: "the gRPC server interrupted a header block".to_owned(),
+                });
+            }
+            block.extend_from_slice(&payload);
+            end_headers = flags & FLAG_END_HEADERS != 0;
+        }
+        self.headers.decode(&block)
+    }
+
+    fn write_frame(
+        &mut self,
+        frame_type: u8,
+        flags: u8,
+        stream_id: u32,
+        payload: &[u8],
+    ) -> error::Result<()> {
+        let mut header = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        header.push(frame_type);
+        header.push(flags);
+        header.extend_from_slice(&stream_id.to_be_bytes());
+        self.stream.write_all(&header)?;
+        self.stream.write_all(payload)?;
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> error::Result<(u8, u8, u32, Vec<u8>)> {
+        let mut header = [0; 9];
+        self.stream.read_exact(&mut header)?;
+        let len = be_u32(&[0, header[0], header[1], header[2]]) as usize;
+        if !self.settings_received && header[3] != FRAME_SETTINGS {
+            return Err(error::Error::Upstream {
+                msg: format!("{} didn't answer with HTTP/2", self.authority),
+            });
+        }
+        if len > MAX_FRAME_SIZE {
+            return Err(error::Error::Upstream {
+                msg: format!("the gRPC server sent a frame of {} bytes", len),
+            });
+        }
+        self.settings_received = true;
+
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload)?;
+        let stream_id = be_u32(&header[5..9]) & 0x7fff_ffff;
+        Ok((header[3], header[4], stream_id, payload))
+    }
+}
+
+/// The data of a frame without its padding.
+fn unpad(flags: u8, payload: &mut [u8]) -> error::Result<&[u8]> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+    let padding = payload.first().map_or(0, |&p| p as usize);
+    if padding >= payload.len() {
+        return Err(error::Error::Upstream {
+            msg: "the gRPC server sent a frame with too much padding".to_owned(),
+        });
+    }
+    let end = payload.len() - padding;
+    Ok(&payload[1..end])
+}
+
+/// Decodes a `grpc-message`, whose bytes outside of printable ASCII are percent-encoded.
+fn percent_decode(message: &[u8]) -> String {
+    let mut result = Vec::new();
+    let mut rest = message;
+    while let Some((&first, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if first == b'%' => {
+                result.push(byte);
+                rest = &tail[2..];
+            }
+            _ => {
+                result.push(first);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .take(4)
+        .fold(0, |n, &b| (n << 8) | u32::from(b))
+}
+
+/// Appends an HPACK string literal, which isn't Huffman encoded.
+fn hpack_string(out: &mut Vec<u8>, s: &str) {
+    // An integer with a 7 bit prefix
+    let mut n = s.len();
+    if n < 0x7f {
+        out.push(n as u8);
+    } else {
+        out.push(0x7f);
+        n -= 0x7f;
+        while n >= 0x80 {
+            out.push((n % 0x80) as u8 | 0x80);
+            n /= 0x80;
+        }
+        out.push(n as u8);
+    }
+    out.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A stream that reads what a server would send, and keeps what is written to it.
+    struct Scripted {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl io::Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl io::Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(frame_type: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut result = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        result.push(frame_type);
+        result.push(flags);
+        result.extend_from_slice(&stream_id.to_be_bytes());
+        result.extend_from_slice(payload);
+        result
+    }
+
+    /// A header block with literal header fields, which are added to the dynamic table if
+    /// `indexed`.
+    fn header_block(indexed: bool, headers: &[(&str, &str)]) -> Vec<u8> {
+        let mut result = Vec::new();
+        for &(name, value) in headers {
+            result.push(if indexed { 0x40 } else { 0 });
+            hpack_string(&mut result, name);
+            hpack_string(&mut result, value);
+        }
+        result
+    }
+
+    /// A response that succeeds with a message.
+    fn reply(stream_id: u32, message: &[u8]) -> Vec<u8> {
+        let mut data = vec![0];
+        data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        data.extend_from_slice(message);
+        let mut result = frame(FRAME_HEADERS, FLAG_END_HEADERS, stream_id, &[]);
+        result.extend(frame(FRAME_DATA, 0, stream_id, &data));
+        result.extend(frame(
+            FRAME_HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            stream_id,
+            &header_block(false, &[("grpc-status", "0")]),
+        ));
+        result
+    }
+
+    fn message(fields: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut result = Vec::new();
+        {
+            let mut output = protobuf::CodedOutputStream::vec(&mut result);
+            for &(number, bytes) in fields {
+                output.write_bytes(number, bytes).unwrap();
+            }
+            output.flush().unwrap();
+        }
+        result
+    }
+
+    fn file(name: &str, dependencies: &[&str]) -> Vec<u8> {
+        let mut file = protobuf::descriptor::FileDescriptorProto::new();
+        file.set_name(name.to_owned());
+        file.set_dependency(dependencies.iter().map(|d| (*d).to_owned()).collect());
+        file.write_to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_fetch_files() {
+        let services = message(&[(
+            RESPONSE_LIST_SERVICES,
+            &message(&[(1, &message(&[(1, b"foo.Greeter")]))]),
+        )]);
+        let greeter_files = message(&[(
+            RESPONSE_FILE_DESCRIPTOR,
+            &message(&[
+                (1, &file("foo.proto", &["bar.proto", "baz.proto"])),
+                (1, &file("bar.proto", &[])),
+            ]),
+        )]);
+        let baz_files = message(&[(
+            RESPONSE_FILE_DESCRIPTOR,
+            &message(&[(1, &file("baz.proto", &[]))]),
+        )]);
+
+        let mut input = frame(FRAME_SETTINGS, 0, 0, &[0, 4, 0, 1, 0, 0]);
+        // The v1 service is unimplemented, which only shows in the trailers, and they are split
+        // into a HEADERS and a CONTINUATION frame
+        let trailers = header_block(
+            true,
+            &[("grpc-status", "12"), ("grpc-message", "unknown service")],
+        );
+        input.extend(frame(FRAME_HEADERS, FLAG_END_STREAM, 1, &trailers[..10]));
+        input.extend(frame(
+            FRAME_CONTINUATION,
+            FLAG_END_HEADERS,
+            1,
+            &trailers[10..],
+        ));
+        input.extend(reply(3, &services));
+        input.extend(frame(FRAME_PING, 0, 0, b"pingpong"));
+        input.extend(reply(5, &greeter_files));
+        input.extend(reply(7, &baz_files));
+        let stream = Scripted {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+        };
+
+        let mut client = Client::new(stream, "localhost:50051").unwrap();
+        let file_set = fetch_files(&mut client, "localhost:50051").unwrap();
+        assert_eq!(
+            file_set
+                .get_file()
+                .iter()
+                .map(|f| f.get_name())
+                .collect::<Vec<_>>(),
+            vec!["foo.proto", "bar.proto", "baz.proto"]
+        );
+
+        let output = client.stream.output;
+        assert!(output.starts_with(PREFACE));
+        let contains = |needle: &[u8]| output.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(SERVICE_PATHS[0].as_bytes()));
+        assert!(contains(SERVICE_PATHS[1].as_bytes()));
+        assert!(contains(&frame(FRAME_SETTINGS, FLAG_ACK, 0, &[])));
+        assert!(contains(&frame(FRAME_PING, FLAG_ACK, 0, b"pingpong")));
+        assert!(contains(
+            &request(REQUEST_FILE_BY_FILENAME, "baz.proto").unwrap()
+        ));
+        assert!(!contains(
+            &request(REQUEST_FILE_BY_FILENAME, "bar.proto").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_fetch_files_errors() {
+        let fetch = |input: Vec<u8>| {
+            let stream = Scripted {
+                input: io::Cursor::new(input),
+                output: Vec::new(),
+            };
+            let mut client = Client::new(stream, "localhost:50051").unwrap();
+            match fetch_files(&mut client, "localhost:50051") {
+                Err(error::Error::Upstream { msg }) => msg,
+                r => panic!("expected an error, got {:?}", r),
+            }
+        };
+
+        assert_eq!(
+            fetch(b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec()),
+            "localhost:50051 didn't answer with HTTP/2"
+        );
+
+        let mut input = frame(FRAME_SETTINGS, 0, 0, &[]);
+        input.extend(frame(
+            FRAME_HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            1,
+            &header_block(true, &[("grpc-status", "12")]),
+        ));
+        // The same status, from the dynamic table
+        input.extend(frame(
+            FRAME_HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            3,
+            &[0xbe],
+        ));
+        assert_eq!(
+            fetch(input),
+            "the gRPC server at localhost:50051 doesn't support server reflection"
+        );
+
+        let mut input = frame(FRAME_SETTINGS, 0, 0, &[]);
+        input.extend(frame(
+            FRAME_HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            1,
+            &header_block(
+                false,
+                &[("grpc-status", "7"), ("grpc-message", "access%20denied")],
+            ),
+        ));
+        assert_eq!(
+            fetch(input),
+            "the gRPC server failed the call of \
+             /grpc.reflection.v1.ServerReflection/ServerReflectionInfo with status 7: access \
+             denied"
+        );
+
+        let error = message(&[(
+            RESPONSE_ERROR,
+            &[
+                0x08, 5, 0x12, 9, b'n', b'o', b't', b' ', b'f', b'o', b'u', b'n', b'd',
+            ],
+        )]);
+        let mut input = frame(FRAME_SETTINGS, 0, 0, &[]);
+        input.extend(reply(1, &error));
+        assert_eq!(
+            fetch(input),
+            "the gRPC server at localhost:50051 failed describing its services: not found (code \
+             5)"
+        );
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode(b"caf%C3%A9 100%"), "caf\u{e9} 100%");
+        assert_eq!(percent_decode(b"%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn test_hpack_string() {
+        let mut out = Vec::new();
+        hpack_string(&mut out, "te");
+        assert_eq!(out, b"\x02te");
+
+        let long = "x".repeat(300);
+        let mut out = Vec::new();
+        hpack_string(&mut out, &long);
+        // 300 = 127 + 173, and 173 = 45 + 128
+        assert_eq!(&out[..3], &[0x7f, 0xad, 0x01]);
+        assert_eq!(out.len(), 303);
+    }
+}
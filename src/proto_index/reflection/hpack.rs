@@ -0,0 +1,610 @@
+//! A decoder of HPACK header blocks (RFC 7541), for reading the status of gRPC calls from the
+//! headers that a server sends.
+
+use crate::error;
+
+use std::collections;
+
+/// The size of the dynamic table, which is never changed with `SETTINGS_HEADER_TABLE_SIZE`.
+const MAX_TABLE_SIZE: usize = 4096;
+
+/// The names and values of the static table, from index 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The Huffman codes of all bytes and of EOS, as codes and their lengths in bits.
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+/// The state that the header blocks of a connection share.
+#[derive(Debug)]
+pub struct Decoder {
+    /// The entries of the dynamic table, newest first.
+    table: collections::VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            table: collections::VecDeque::new(),
+            size: 0,
+            max_size: MAX_TABLE_SIZE,
+        }
+    }
+}
+
+impl Decoder {
+    /// Decodes a complete header block into the names and values of its headers.
+    pub fn decode(&mut self, mut block: &[u8]) -> error::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let input = &mut block;
+        let mut headers = Vec::new();
+        while let Some(&first) = input.first() {
+            if first & 0x80 != 0 {
+                // An indexed header field
+                let index = integer(input, 7)?;
+                headers.push(self.entry(index)?);
+            } else if first & 0xc0 == 0x40 {
+                // A literal header field with incremental indexing
+                let header = self.literal(input, 6)?;
+                self.insert(header.clone());
+                headers.push(header);
+            } else if first & 0xe0 == 0x20 {
+                let size = integer(input, 5)?;
+                if size > MAX_TABLE_SIZE {
+                    return Err(bad(format!("a dynamic table size of {}", size)));
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // A literal header field without indexing, or one that is never indexed
+                headers.push(self.literal(input, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    fn literal(&self, input: &mut &[u8], prefix: u8) -> error::Result<(Vec<u8>, Vec<u8>)> {
+        let name = match integer(input, prefix)? {
+            0 => string(input)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, string(input)?))
+    }
+
+    fn entry(&self, index: usize) -> error::Result<(Vec<u8>, Vec<u8>)> {
+        if let Some(&(name, value)) = index.checked_sub(1).and_then(|i| STATIC_TABLE.get(i)) {
+            return Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()));
+        }
+        index
+            .checked_sub(STATIC_TABLE.len() + 1)
+            .and_then(|i| self.table.get(i))
+            .cloned()
+            .ok_or_else(|| bad(format!("the header index {}", index)))
+    }
+
+    fn insert(&mut self, header: (Vec<u8>, Vec<u8>)) {
+        let size = entry_size(&header);
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(header);
+        }
+    }
+
+    /// Evicts the oldest entries until there is room for an entry of a size.
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            match self.table.pop_back() {
+                Some(header) => self.size -= entry_size(&header),
+                None => break,
+            }
+        }
+    }
+}
+
+fn entry_size((name, value): &(Vec<u8>, Vec<u8>)) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// Reads an integer with a prefix of some bits of the first byte.
+fn integer(input: &mut &[u8], prefix: u8) -> error::Result<usize> {
+    let max = (1 << prefix) - 1;
+    let mut result = usize::from(next(input)? & max);
+    if result < usize::from(max) {
+        return Ok(result);
+    }
+    for shift in (0..28).step_by(7) {
+        let byte = next(input)?;
+        result += usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    Err(bad("an integer that is too large".to_owned()))
+}
+
+fn string(input: &mut &[u8]) -> error::Result<Vec<u8>> {
+    let huffman = input.first().map_or(false, |&b| b & 0x80 != 0);
+    let len = integer(input, 7)?;
+    if len > input.len() {
+        return Err(bad("a truncated string".to_owned()));
+    }
+    let (data, rest) = input.split_at(len);
+    *input = rest;
+    if huffman {
+        decode_huffman(data)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+fn decode_huffman(data: &[u8]) -> error::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    let (mut code, mut len) = (0u32, 0u8);
+    for &byte in data {
+        for bit in (0..8).rev() {
+            code = code << 1 | u32::from((byte >> bit) & 1);
+            len += 1;
+            match HUFFMAN_CODES.iter().position(|&c| c == (code, len)) {
+                Some(256) => return Err(bad("a Huffman string with EOS".to_owned())),
+                Some(symbol) => {
+                    result.push(symbol as u8);
+                    code = 0;
+                    len = 0;
+                }
+                None if len >= 30 => return Err(bad("a bad Huffman code".to_owned())),
+                None => (),
+            }
+        }
+    }
+    // The padding is the most significant bits of EOS
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(bad("bad Huffman padding".to_owned()));
+    }
+    Ok(result)
+}
+
+fn next(input: &mut &[u8]) -> error::Result<u8> {
+    let (&first, rest) = input
+        .split_first()
+        .ok_or_else(|| bad("a truncated header block".to_owned()))?;
+    *input = rest;
+    Ok(first)
+}
+
+fn bad(what: String) -> error::Error {
+    error::Error::Upstream {
+        msg: format!("the gRPC server sent headers with {}", what),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        pairs
+            .iter()
+            .map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        // The requests of RFC 7541 C.3
+        let mut decoder = Decoder::default();
+        assert_eq!(
+            decoder
+                .decode(b"\x82\x86\x84\x41\x0fwww.example.com")
+                .unwrap(),
+            headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+        assert_eq!(
+            decoder.decode(b"\x82\x86\x84\xbe\x58\x08no-cache").unwrap(),
+            headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+        assert_eq!(decoder.size, 110);
+    }
+
+    #[test]
+    fn test_decode_huffman() {
+        // The requests of RFC 7541 C.4
+        let mut decoder = Decoder::default();
+        assert_eq!(
+            decoder
+                .decode(&[
+                    0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0,
+                    0xab, 0x90, 0xf4, 0xff,
+                ])
+                .unwrap(),
+            headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+        decoder
+            .decode(&[
+                0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf,
+            ])
+            .unwrap();
+        assert_eq!(
+            decoder
+                .decode(&[
+                    0x82, 0x87, 0x85, 0xbf, 0x40, 0x88, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xa9, 0x7d,
+                    0x7f, 0x89, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xb8, 0xe8, 0xb4, 0xbf,
+                ])
+                .unwrap(),
+            headers(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])
+        );
+        assert_eq!(decoder.size, 164);
+    }
+
+    #[test]
+    fn test_decode_eviction() {
+        let mut decoder = Decoder::default();
+        // A table size update to fit one entry, and two literals with incremental indexing
+        decoder
+            .decode(b"\x3f\x1b\x40\x01a\x01b\x40\x01c\x01d")
+            .unwrap();
+        assert_eq!(decoder.decode(b"\xbe").unwrap(), headers(&[("c", "d")]));
+        assert!(decoder.decode(b"\xbf").is_err());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut decoder = Decoder::default();
+        for block in &[
+            &b"\x80"[..],
+            b"\xbe",
+            b"\x00\x05abc",
+            b"\x0f",
+            b"\x3f\xe2\x1f",
+            b"\x00\x81\x00",
+            b"\x00\x84\xff\xff\xff\xff",
+        ] {
+            match decoder.decode(block) {
+                Err(error::Error::Upstream { .. }) => (),
+                r => panic!("expected an error decoding {:?}, got {:?}", block, r),
+            }
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Native evaluation of simple queries, without a JavaScript engine.
+
+use crate::error;
+use crate::value;
+use crate::value::path;
+use serde_protobuf::descriptor;
+use std::cmp;
+
+/// A query that is evaluated directly on `value::Value`s.
+///
+/// A query is a pipeline of steps separated by `|`, where each step is one of:
+///
+///   * `id`, which keeps each record as it is,
+///   * a path like `.foo.bar[0]`, which replaces each record with the value at that path,
+///   * `map (x) => x.foo`, which does the same with an arrow function that selects a field,
+///   * `select (x) => x.foo`, which keeps the records where the field is truthy, or
+///   * `select (x) => x.foo == 1`, which compares the field with a JSON literal or a
+///     single-quoted string using `==`, `!=`, `<`, `<=`, `>` or `>=` (`===` and `!==` are
+///     accepted too), or
+///   * `proto_descriptor('.foo.Bar')`, which replaces each record with the field metadata of a
+///     protobuf message type that has been added with `rq protobuf add`, see
+///     `value::protobuf::descriptor_value`.
+///
+/// Fields that don't exist are `null`, and values are truthy unless they are `null`, `false`,
+/// zero or empty strings, like in JavaScript.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Query(Vec<Step>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Step {
+    Map(path::Path),
+    Select(path::Path, Option<(Op, value::Value)>),
+    Descriptor(String, Option<value::Value>),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Query {
+    pub fn parse(s: &str) -> error::Result<Self> {
+        split_steps(s)
+            .into_iter()
+            .map(|step| {
+                parse_step(step.trim()).ok_or_else(|| {
+                    error::Error::Message(format!(
+                        "unsupported query {:?}: only paths like '.foo', 'map (x) => x.foo', \
+                         'select (x) => x.foo == 1' and 'proto_descriptor('.foo.Bar')' are supported, since there is no JavaScript \
+                         engine",
+                        step.trim()
+                    ))
+                })
+            })
+            .collect::<error::Result<_>>()
+            .map(Query)
+    }
+
+    /// Sets how the keys of the paths in the query are compared with the keys of maps.
+    pub fn key_match(self, key_match: path::KeyMatch) -> Self {
+        Query(
+            self.0
+                .into_iter()
+                .map(|step| match step {
+                    Step::Map(p) => Step::Map(p.key_match(key_match)),
+                    Step::Select(p, cmp) => Step::Select(p.key_match(key_match), cmp),
+                    step => step,
+                })
+                .collect(),
+        )
+    }
+
+    /// Whether the query uses `proto_descriptor(...)`, and so needs `proto_descriptors`.
+    pub fn uses_proto_descriptors(&self) -> bool {
+        self.0
+            .iter()
+            .any(|step| matches!(*step, Step::Descriptor(..)))
+    }
+
+    /// Looks up the message types of the `proto_descriptor(...)` calls in the query.
+    pub fn proto_descriptors(self, descriptors: &descriptor::Descriptors) -> error::Result<Self> {
+        self.0
+            .into_iter()
+            .map(|step| match step {
+                Step::Descriptor(name, _) => {
+                    let v = value::protobuf::descriptor_value(descriptors, &name)?;
+                    Ok(Step::Descriptor(name, Some(v)))
+                }
+                step => Ok(step),
+            })
+            .collect::<error::Result<_>>()
+            .map(Query)
+    }
+}
+
+/// Splits a query at the `|`s that aren't inside of string literals.
+fn split_steps(s: &str) -> Vec<&str> {
+    let mut steps = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut escape = false;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(_), '\\') if !escape => {
+                escape = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escape => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '|') => {
+                steps.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+        escape = false;
+    }
+    steps.push(&s[start..]);
+    steps
+}
+
+fn parse_step(s: &str) -> Option<Step> {
+    if s == "id" {
+        return Some(Step::Map(path::Path::default()));
+    } else if s.starts_with('.') {
+        return path::Path::parse(s).ok().map(Step::Map);
+    } else if let Some(arg) = s
+        .strip_prefix("proto_descriptor(")
+        .and_then(|s| s.strip_suffix(')'))
+    {
+        return match parse_literal(arg.trim())? {
+            value::Value::String(name) => Some(Step::Descriptor(name, None)),
+            _ => None,
+        };
+    }
+
+    let (process, lambda) = s.split_at(s.find(char::is_whitespace)?);
+    let (param, body) = parse_lambda(lambda.trim())?;
+    let (path, rest) = parse_field(param, body)?;
+    match process {
+        "map" if rest.is_empty() => Some(Step::Map(path)),
+        "select" if rest.is_empty() => Some(Step::Select(path, None)),
+        "select" => {
+            let (op, literal) = parse_comparison(rest)?;
+            Some(Step::Select(path, Some((op, literal))))
+        }
+        _ => None,
+    }
+}
+
+/// Parses `(x) => body` or `x => body` into the parameter name and the body.
+fn parse_lambda(s: &str) -> Option<(&str, &str)> {
+    let arrow = s.find("=>")?;
+    let param = s[..arrow].trim();
+    let param = param
+        .strip_prefix('(')
+        .and_then(|p| p.strip_suffix(')'))
+        .unwrap_or(param)
+        .trim();
+    if param.is_empty()
+        || !param
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+    {
+        return None;
+    }
+    Some((param, s[arrow + 2..].trim()))
+}
+
+/// Parses a field access like `x.foo["bar"][0]` on the parameter, returning it as a path and
+/// the rest of the body.
+fn parse_field<'a>(param: &str, body: &'a str) -> Option<(path::Path, &'a str)> {
+    let body = body.strip_prefix(param)?;
+    let end = body
+        .find(|c: char| c.is_whitespace() || "=!<>".contains(c))
+        .unwrap_or(body.len());
+    let (field, rest) = body.split_at(end);
+    let path = if field.is_empty() {
+        path::Path::default()
+    } else {
+        path::Path::parse(field).ok()?
+    };
+    Some((path, rest.trim()))
+}
+
+fn parse_comparison(s: &str) -> Option<(Op, value::Value)> {
+    let ops = [
+        ("===", Op::Eq),
+        ("!==", Op::Ne),
+        ("==", Op::Eq),
+        ("!=", Op::Ne),
+        ("<=", Op::Le),
+        (">=", Op::Ge),
+        ("<", Op::Lt),
+        (">", Op::Gt),
+    ];
+    let (op, rest) = ops
+        .iter()
+        .find_map(|&(token, op)| s.strip_prefix(token).map(|rest| (op, rest.trim())))?;
+
+    Some((op, parse_literal(rest)?))
+}
+
+/// Parses a JSON literal or a single-quoted string.
+fn parse_literal(s: &str) -> Option<value::Value> {
+    match s.strip_prefix('\'').and_then(|r| r.strip_suffix('\'')) {
+        Some(s) => Some(value::Value::String(s.to_owned())),
+        None => serde_json::from_str(s).ok(),
+    }
+}
+
+fn is_truthy(v: Option<&value::Value>) -> bool {
+    use crate::value::Value;
+
+    match v {
+        None | Some(Value::Unit) | Some(Value::Bool(false)) => false,
+        Some(Value::String(s)) => !s.is_empty(),
+        Some(v) => match v.cmp(&Value::I64(0)) {
+            cmp::Ordering::Equal => false,
+            // Only numbers are equal to zero
+            _ => true,
+        },
+    }
+}
+
+impl Op {
+    fn apply(self, a: Option<&value::Value>, b: &value::Value) -> bool {
+        let a = a.unwrap_or(&value::Value::Unit);
+        match self {
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+            // Values of different kinds, like strings and numbers, are never ordered
+            _ if !same_kind(a, b) => false,
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Gt => a > b,
+            Op::Ge => a >= b,
+        }
+    }
+}
+
+fn same_kind(a: &value::Value, b: &value::Value) -> bool {
+    use crate::value::Value;
+
+    let kind = |v: &Value| match *v {
+        Value::String(_) | Value::Char(_) => 1,
+        Value::Unit | Value::Bool(_) | Value::Bytes(_) | Value::Sequence(_) | Value::Map(_) => 0,
+        _ => 2,
+    };
+    kind(a) != 0 && kind(a) == kind(b)
+}
+
+impl value::Transform for Query {
+    fn transform(&mut self, mut v: value::Value) -> error::Result<Option<value::Value>> {
+        for step in &self.0 {
+            match *step {
+                Step::Map(ref path) => {
+                    v = path.select_mut(&mut v).map_or(value::Value::Unit, |v| {
+                        std::mem::replace(v, value::Value::Unit)
+                    });
+                }
+                Step::Select(ref path, None) => {
+                    if !is_truthy(path.select(&v)) {
+                        return Ok(None);
+                    }
+                }
+                Step::Select(ref path, Some((op, ref literal))) => {
+                    if !op.apply(path.select(&v), literal) {
+                        return Ok(None);
+                    }
+                }
+                Step::Descriptor(_, Some(ref descriptor)) => v = descriptor.clone(),
+                Step::Descriptor(ref name, None) => {
+                    return Err(error::Error::Message(format!(
+                        "the protobuf descriptor of {} has not been looked up",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(Some(v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Transform as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn record(name: &str, age: i64) -> Value {
+        Value::Map(vec![
+            (s("name"), s(name)),
+            (s("age"), Value::I64(age)),
+            (s("tags"), Value::Sequence(vec![s("a|b")])),
+        ])
+    }
+
+    fn run(query: &str, records: Vec<Value>) -> Vec<Value> {
+        let mut query = Query::parse(query).unwrap();
+        records
+            .into_iter()
+            .filter_map(|r| query.transform(r).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let path = |p: &str| path::Path::parse(p).unwrap();
+        assert_eq!(
+            Query::parse("id | .a | map x => x.b[0] | select (y) => y != 'x|y'").unwrap(),
+            Query(vec![
+                Step::Map(path::Path::default()),
+                Step::Map(path(".a")),
+                Step::Map(path(".b[0]")),
+                Step::Select(path::Path::default(), Some((Op::Ne, s("x|y")))),
+            ])
+        );
+        let query = Query::parse(r#"proto_descriptor(".foo.Bar") | .fields"#).unwrap();
+        assert!(query.uses_proto_descriptors());
+        assert_eq!(
+            query,
+            Query(vec![
+                Step::Descriptor(".foo.Bar".to_owned(), None),
+                Step::Map(path(".fields")),
+            ])
+        );
+        assert!(!Query::parse(".fields").unwrap().uses_proto_descriptors());
+        for query in &[
+            "select x",
+            "map (x) => y.foo",
+            "map (x) => x.foo + 1",
+            "select (x) => x.foo == bar",
+            "reduce (a, b) => a",
+            "proto_descriptor(1)",
+            ".foo |",
+        ] {
+            assert!(Query::parse(query).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let records = vec![record("ann", 31), record("bob", 0), record("", 25)];
+        assert_eq!(
+            run(
+                "select (x) => x.age >= 25 | map (x) => x.name",
+                records.clone()
+            ),
+            vec![s("ann"), s("")]
+        );
+        assert_eq!(
+            run("select (x) => x.age", records.clone()),
+            vec![record("ann", 31), record("", 25)]
+        );
+        assert_eq!(
+            run("select x => x.name | .age", records.clone()),
+            vec![Value::I64(31), Value::I64(0)]
+        );
+        assert_eq!(
+            run(
+                r#"select (r) => r.tags[0] === "a|b" | .name"#,
+                records.clone()
+            ),
+            vec![s("ann"), s("bob"), s("")]
+        );
+        assert_eq!(run("select (x) => x.age > 'a'", records.clone()), vec![]);
+        assert_eq!(run("select (x) => x.missing", records), vec![]);
+    }
+}
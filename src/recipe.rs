@@ -0,0 +1,147 @@
+use crate::config;
+use crate::error;
+
+use std::collections;
+use std::fs;
+use toml;
+
+/// A named pipeline from the `recipes.toml` config file, so that common conversions can be run
+/// with `rq run NAME` instead of a long command line.  Recipes are defined like this:
+///
+/// ```toml
+/// [avro-payloads]
+/// input = "avro"
+/// query = ".payload"
+/// output = "json"
+/// args = ["--format", "compact"]
+/// ```
+///
+/// All keys are optional; `input` and `output` name the formats of the `--input-*` and
+/// `--output-*` flags, and `args` can contain any other flags.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Recipe {
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub query: Option<String>,
+    pub args: Vec<String>,
+}
+
+pub type Recipes = collections::BTreeMap<String, Recipe>;
+
+/// Loads the recipes from the config directory, if there are any.
+pub fn load(paths: &config::Paths) -> error::Result<Recipes> {
+    let file = paths.preferred_config("recipes.toml");
+    if file.exists() {
+        trace!("Loading recipes from {:?}", file);
+        parse(&fs::read_to_string(&file)?)
+    } else {
+        Ok(Recipes::new())
+    }
+}
+
+pub fn parse(s: &str) -> error::Result<Recipes> {
+    let table = match toml::from_str(s)? {
+        toml::Value::Table(table) => table,
+        _ => unreachable!(),
+    };
+
+    let mut recipes = Recipes::new();
+    for (name, definition) in table {
+        let error = |msg: String| error::Error::Message(format!("recipe {:?}: {}", name, msg));
+        let definition = match definition {
+            toml::Value::Table(definition) => definition,
+            _ => return Err(error("expected a table".to_owned())),
+        };
+
+        let mut recipe = Recipe::default();
+        for (key, value) in definition {
+            match (key.as_str(), value) {
+                ("input", toml::Value::String(v)) => recipe.input = Some(v),
+                ("output", toml::Value::String(v)) => recipe.output = Some(v),
+                ("query", toml::Value::String(v)) => recipe.query = Some(v),
+                ("args", toml::Value::Array(vs)) => {
+                    for v in vs {
+                        match v {
+                            toml::Value::String(v) => recipe.args.push(v),
+                            v => {
+                                return Err(error(format!("expected a string argument, got {}", v)))
+                            }
+                        }
+                    }
+                }
+                ("input", _) | ("output", _) | ("query", _) => {
+                    return Err(error(format!("{} must be a string", key)))
+                }
+                ("args", _) => return Err(error("args must be an array".to_owned())),
+                (key, _) => return Err(error(format!("unknown key {}", key))),
+            }
+        }
+        recipes.insert(name, recipe);
+    }
+    Ok(recipes)
+}
+
+impl Recipe {
+    /// The command line arguments that this recipe stands for, excluding the program name.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        if let Some(ref input) = self.input {
+            result.push(format!("--input-{}", input));
+        }
+        if let Some(ref output) = self.output {
+            result.push(format!("--output-{}", output));
+        }
+        result.extend(self.args.iter().cloned());
+        if let Some(ref query) = self.query {
+            result.push(query.clone());
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let recipes = parse(
+            r#"
+            [avro-payloads]
+            input = "avro"
+            query = ".payload"
+            output = "json"
+            args = ["--format", "compact"]
+
+            [empty]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(recipes.len(), 2);
+        assert_eq!(recipes["empty"], Recipe::default());
+        assert_eq!(
+            recipes["avro-payloads"].to_args(),
+            vec![
+                "--input-avro",
+                "--output-json",
+                "--format",
+                "compact",
+                ".payload"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for s in &[
+            "recipe = 1",
+            "[recipe]\ninput = 1",
+            "[recipe]\nargs = \"-j\"",
+            "[recipe]\nargs = [1]",
+            "[recipe]\ninptu = \"json\"",
+        ] {
+            assert!(parse(s).is_err(), "{}", s);
+        }
+    }
+}
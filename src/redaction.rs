@@ -0,0 +1,383 @@
+use crate::config;
+use crate::error;
+use crate::value;
+
+use protobuf;
+use protobuf::descriptor;
+use std::collections;
+use std::fs;
+use toml;
+
+/// The fields of protobuf messages to redact, by fully qualified message type, from the
+/// `redactions.toml` config file.  Fields are given as dot-separated paths relative to the
+/// message, like this:
+///
+/// ```toml
+/// ".acme.User" = ["email", "address.street"]
+/// ```
+///
+/// Fields that are declared with a custom `sensitive` bool option set to true are always
+/// redacted, as long as the option extends `google.protobuf.FieldOptions`:
+///
+/// ```proto
+/// extend google.protobuf.FieldOptions {
+///   bool sensitive = 50000;
+/// }
+///
+/// message User {
+///   string email = 1 [(sensitive) = true];
+/// }
+/// ```
+pub type Rules = collections::BTreeMap<String, Vec<String>>;
+
+/// A transform that replaces the values of some fields of protobuf messages with `Unit`.  Fields
+/// inside of sequences are redacted in every element.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Redact {
+    message_name: String,
+    messages: collections::HashMap<String, MessageRedactions>,
+}
+
+/// The redactions for one message type.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct MessageRedactions {
+    /// Paths relative to the message to replace with `Unit`.
+    paths: Vec<Vec<String>>,
+    /// Fields that contain messages, with their types.
+    message_fields: Vec<(String, String)>,
+}
+
+/// Loads the redaction rules from the config directory, if there are any.
+pub fn load(paths: &config::Paths) -> error::Result<Rules> {
+    let file = paths.preferred_config("redactions.toml");
+    if file.exists() {
+        trace!("Loading redactions from {:?}", file);
+        parse(&fs::read_to_string(&file)?)
+    } else {
+        Ok(Rules::new())
+    }
+}
+
+pub fn parse(s: &str) -> error::Result<Rules> {
+    let table = match toml::from_str(s)? {
+        toml::Value::Table(table) => table,
+        _ => unreachable!(),
+    };
+
+    let mut rules = Rules::new();
+    for (message, fields) in table {
+        let error = || {
+            error::Error::Message(format!(
+                "redactions for {:?}: expected an array of field paths",
+                message
+            ))
+        };
+        let fields = match fields {
+            toml::Value::Array(fields) => fields,
+            _ => return Err(error()),
+        };
+        let fields = fields
+            .into_iter()
+            .map(|field| match field {
+                toml::Value::String(field) => Ok(field),
+                _ => Err(error()),
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        rules.insert(message, fields);
+    }
+    Ok(rules)
+}
+
+impl Redact {
+    /// Finds the fields to redact in messages of the given type, both from the rules and from
+    /// the `sensitive` field options in the descriptor set.
+    pub fn for_message(
+        file_set: &descriptor::FileDescriptorSet,
+        message_name: &str,
+        rules: &Rules,
+    ) -> Self {
+        let index = MessageIndex::new(file_set);
+        let mut redact = Redact {
+            message_name: message_name.to_owned(),
+            messages: collections::HashMap::new(),
+        };
+        index.collect(message_name, rules, &mut redact.messages);
+        redact
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.values().all(|m| m.paths.is_empty())
+    }
+
+    fn apply(&self, value: &mut value::Value, message_name: &str) {
+        let redactions = match self.messages.get(message_name) {
+            Some(redactions) => redactions,
+            None => return,
+        };
+        for path in &redactions.paths {
+            redact(value, path);
+        }
+        if let value::Value::Map(ref mut entries) = *value {
+            for (field, field_type) in &redactions.message_fields {
+                for (k, v) in entries.iter_mut() {
+                    if is_key(k, field) {
+                        match *v {
+                            value::Value::Sequence(ref mut seq) => {
+                                for v in seq {
+                                    self.apply(v, field_type);
+                                }
+                            }
+                            ref mut v => self.apply(v, field_type),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl value::Transform for Redact {
+    fn transform(&mut self, mut v: value::Value) -> error::Result<Option<value::Value>> {
+        self.apply(&mut v, &self.message_name);
+        Ok(Some(v))
+    }
+}
+
+fn redact(value: &mut value::Value, path: &[String]) {
+    match *value {
+        value::Value::Map(ref mut entries) => {
+            if let Some((first, rest)) = path.split_first() {
+                for (k, v) in entries.iter_mut() {
+                    if is_key(k, first) {
+                        if rest.is_empty() {
+                            *v = value::Value::Unit;
+                        } else {
+                            redact(v, rest);
+                        }
+                    }
+                }
+            }
+        }
+        value::Value::Sequence(ref mut seq) => {
+            for v in seq {
+                redact(v, path);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn is_key(key: &value::Value, name: &str) -> bool {
+    match *key {
+        value::Value::String(ref s) => s == name,
+        _ => false,
+    }
+}
+
+struct MessageIndex<'a> {
+    messages: collections::HashMap<String, &'a descriptor::DescriptorProto>,
+    sensitive_options: Vec<u32>,
+}
+
+impl<'a> MessageIndex<'a> {
+    fn new(file_set: &'a descriptor::FileDescriptorSet) -> Self {
+        let mut index = MessageIndex {
+            messages: collections::HashMap::new(),
+            sensitive_options: Vec::new(),
+        };
+        for file in file_set.get_file() {
+            let prefix = if file.get_package().is_empty() {
+                String::new()
+            } else {
+                format!(".{}", file.get_package())
+            };
+            index.add_extensions(file.get_extension());
+            for message in file.get_message_type() {
+                index.add_message(&prefix, message);
+            }
+        }
+        index
+    }
+
+    fn add_message(&mut self, prefix: &str, message: &'a descriptor::DescriptorProto) {
+        let name = format!("{}.{}", prefix, message.get_name());
+        self.add_extensions(message.get_extension());
+        for nested in message.get_nested_type() {
+            self.add_message(&name, nested);
+        }
+        self.messages.insert(name, message);
+    }
+
+    fn add_extensions(&mut self, extensions: &[descriptor::FieldDescriptorProto]) {
+        for extension in extensions {
+            if extension.get_extendee() == ".google.protobuf.FieldOptions"
+                && extension.get_name() == "sensitive"
+                && extension.get_field_type() == descriptor::FieldDescriptorProto_Type::TYPE_BOOL
+            {
+                self.sensitive_options.push(extension.get_number() as u32);
+            }
+        }
+    }
+
+    fn is_sensitive(&self, field: &descriptor::FieldDescriptorProto) -> bool {
+        let unknown = protobuf::Message::get_unknown_fields(field.get_options());
+        self.sensitive_options.iter().any(|&number| {
+            unknown
+                .get(number)
+                .map_or(false, |values| values.varint.iter().any(|&v| v != 0))
+        })
+    }
+
+    fn collect(
+        &self,
+        message_name: &str,
+        rules: &Rules,
+        messages: &mut collections::HashMap<String, MessageRedactions>,
+    ) {
+        if messages.contains_key(message_name) {
+            return;
+        }
+        let message = match self.messages.get(message_name) {
+            Some(message) => *message,
+            None => return,
+        };
+
+        let mut redactions = MessageRedactions::default();
+        if let Some(fields) = rules.get(message_name) {
+            for field in fields {
+                redactions
+                    .paths
+                    .push(field.split('.').map(str::to_owned).collect());
+            }
+        }
+        for field in message.get_field() {
+            if self.is_sensitive(field) {
+                redactions.paths.push(vec![field.get_name().to_owned()]);
+            } else if field.get_field_type() == descriptor::FieldDescriptorProto_Type::TYPE_MESSAGE
+            {
+                redactions.message_fields.push((
+                    field.get_name().to_owned(),
+                    field.get_type_name().to_owned(),
+                ));
+            }
+        }
+
+        // Insert before recursing, so that recursive messages terminate
+        let field_types = redactions
+            .message_fields
+            .iter()
+            .map(|(_, t)| t.clone())
+            .collect::<Vec<_>>();
+        messages.insert(message_name.to_owned(), redactions);
+        for field_type in field_types {
+            self.collect(&field_type, rules, messages);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Transform as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn field(
+        name: &str,
+        number: i32,
+        field_type: descriptor::FieldDescriptorProto_Type,
+    ) -> descriptor::FieldDescriptorProto {
+        let mut field = descriptor::FieldDescriptorProto::new();
+        field.set_name(name.to_owned());
+        field.set_number(number);
+        field.set_field_type(field_type);
+        field
+    }
+
+    fn file_set() -> descriptor::FileDescriptorSet {
+        use protobuf::descriptor::FieldDescriptorProto_Type as Type;
+
+        let mut sensitive = field("sensitive", 50000, Type::TYPE_BOOL);
+        sensitive.set_extendee(".google.protobuf.FieldOptions".to_owned());
+
+        let mut email = field("email", 1, Type::TYPE_STRING);
+        protobuf::Message::mut_unknown_fields(email.mut_options()).add_varint(50000, 1);
+        let mut address = field("address", 2, Type::TYPE_MESSAGE);
+        address.set_type_name(".acme.User.Address".to_owned());
+        let mut friends = field("friends", 3, Type::TYPE_MESSAGE);
+        friends.set_label(descriptor::FieldDescriptorProto_Label::LABEL_REPEATED);
+        friends.set_type_name(".acme.User".to_owned());
+
+        let mut address_type = descriptor::DescriptorProto::new();
+        address_type.set_name("Address".to_owned());
+        address_type
+            .mut_field()
+            .push(field("street", 1, Type::TYPE_STRING));
+        address_type
+            .mut_field()
+            .push(field("city", 2, Type::TYPE_STRING));
+
+        let mut user = descriptor::DescriptorProto::new();
+        user.set_name("User".to_owned());
+        user.mut_field().push(field("name", 4, Type::TYPE_STRING));
+        user.mut_field().push(email);
+        user.mut_field().push(address);
+        user.mut_field().push(friends);
+        user.mut_nested_type().push(address_type);
+
+        let mut file = descriptor::FileDescriptorProto::new();
+        file.set_package("acme".to_owned());
+        file.mut_extension().push(sensitive);
+        file.mut_message_type().push(user);
+
+        let mut file_set = descriptor::FileDescriptorSet::new();
+        file_set.mut_file().push(file);
+        file_set
+    }
+
+    #[test]
+    fn test_parse() {
+        let rules = parse("\".acme.User\" = [\"address.street\"]").unwrap();
+        assert_eq!(rules[".acme.User"], vec!["address.street".to_owned()]);
+        assert!(parse("\".acme.User\" = \"email\"").is_err());
+        assert!(parse("\".acme.User\" = [1]").is_err());
+    }
+
+    #[test]
+    fn test_redact() {
+        let rules = parse("\".acme.User.Address\" = [\"street\"]").unwrap();
+        let mut redact = Redact::for_message(&file_set(), ".acme.User", &rules);
+
+        let user = |friends| {
+            Value::Map(vec![
+                (s("name"), s("rq")),
+                (s("email"), s("rq@example.com")),
+                (
+                    s("address"),
+                    Value::Map(vec![(s("street"), s("Main St")), (s("city"), s("Town"))]),
+                ),
+                (s("friends"), Value::Sequence(friends)),
+            ])
+        };
+        let redacted = |friends| {
+            Value::Map(vec![
+                (s("name"), s("rq")),
+                (s("email"), Value::Unit),
+                (
+                    s("address"),
+                    Value::Map(vec![(s("street"), Value::Unit), (s("city"), s("Town"))]),
+                ),
+                (s("friends"), Value::Sequence(friends)),
+            ])
+        };
+
+        assert_eq!(
+            redact.transform(user(vec![user(vec![])])).unwrap().unwrap(),
+            redacted(vec![redacted(vec![])])
+        );
+        assert!(Redact::for_message(&file_set(), ".acme.Missing", &rules).is_empty());
+    }
+}
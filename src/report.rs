@@ -0,0 +1,324 @@
+use crate::error;
+
+use avro_rs;
+use protobuf;
+use protobuf::Message as _;
+use serde_json;
+use std::cell;
+use std::collections;
+use std::fmt;
+use std::io;
+use std::rc;
+use std::str;
+use std::time;
+
+/// What happened during a run, for writing a machine-readable report at the end of it with
+/// `--report`, so that the provenance of conversions can be archived without scraping logs.
+///
+/// The counters are updated through shared references, since they are collected from all over
+/// the pipeline.
+pub struct Report {
+    started: time::Instant,
+    records_read: cell::Cell<u64>,
+    records_written: cell::Cell<u64>,
+    records_skipped: cell::Cell<u64>,
+    errors: cell::RefCell<collections::BTreeMap<&'static str, u64>>,
+    input_bytes: rc::Rc<cell::Cell<u64>>,
+    output_bytes: rc::Rc<cell::Cell<u64>>,
+    schemas: cell::RefCell<Vec<Schema>>,
+}
+
+/// A schema that was used during a run, identified by its fingerprint.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Schema {
+    pub format: &'static str,
+    pub name: Option<String>,
+    pub fingerprint: u64,
+}
+
+/// The formats that reports can be written in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Json,
+}
+
+/// A reader or writer that adds the number of bytes that pass through it to a counter.
+pub struct Counting<T>(T, rc::Rc<cell::Cell<u64>>);
+
+/// The CRC-64-AVRO fingerprint of an empty input, which is also the polynomial.
+const EMPTY_FINGERPRINT: u64 = 0xc15d_213a_a4d7_a795;
+
+const FINGERPRINT_TABLE: [u64; 256] = fingerprint_table();
+
+impl Report {
+    pub fn new() -> Self {
+        Report {
+            started: time::Instant::now(),
+            records_read: cell::Cell::new(0),
+            records_written: cell::Cell::new(0),
+            records_skipped: cell::Cell::new(0),
+            errors: cell::RefCell::new(collections::BTreeMap::new()),
+            input_bytes: rc::Rc::new(cell::Cell::new(0)),
+            output_bytes: rc::Rc::new(cell::Cell::new(0)),
+            schemas: cell::RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn record_read(&self) {
+        self.records_read.set(self.records_read.get() + 1);
+    }
+
+    pub fn record_written(&self) {
+        self.records_written.set(self.records_written.get() + 1);
+    }
+
+    /// Takes back a record that was counted as written, because the sink buffered it and only
+    /// rejected it later.
+    pub fn record_unwritten(&self) {
+        self.records_written
+            .set(self.records_written.get().saturating_sub(1));
+    }
+
+    /// Counts a record that was skipped because of an error in the category, see
+    /// `error::Error::category`.
+    pub fn record_skipped(&self, category: &'static str) {
+        self.records_skipped.set(self.records_skipped.get() + 1);
+        *self.errors.borrow_mut().entry(category).or_insert(0) += 1;
+    }
+
+    /// Adds a schema, unless it has been added already.
+    pub fn add_schema(&self, schema: Schema) {
+        let mut schemas = self.schemas.borrow_mut();
+        if !schemas.contains(&schema) {
+            schemas.push(schema);
+        }
+    }
+
+    /// Wraps a reader of input so that the bytes read from it are counted.
+    pub fn count_input<R>(&self, r: R) -> Counting<R>
+    where
+        R: io::Read,
+    {
+        Counting(r, self.input_bytes.clone())
+    }
+
+    /// Wraps a writer of output so that the bytes written to it are counted.
+    pub fn count_output<W>(&self, w: W) -> Counting<W>
+    where
+        W: io::Write,
+    {
+        Counting(w, self.output_bytes.clone())
+    }
+
+    /// The report as JSON, with the outcome of the run, whose error is also tallied.
+    pub fn to_json(&self, result: &error::Result<()>) -> serde_json::Value {
+        let mut errors = self.errors.borrow().clone();
+        let error = match *result {
+            Ok(()) => serde_json::Value::Null,
+            Err(ref e) => {
+                *errors.entry(e.category()).or_insert(0) += 1;
+                serde_json::json!({ "category": e.category(), "message": e.chain() })
+            }
+        };
+        let schemas = self
+            .schemas
+            .borrow()
+            .iter()
+            .map(|s| {
+                serde_json::json!({
+                    "format": s.format,
+                    "name": s.name,
+                    "fingerprint": format!("{:016x}", s.fingerprint),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        serde_json::json!({
+            "version": crate::VERSION,
+            "status": if result.is_ok() { "ok" } else { "error" },
+            "error": error,
+            "records": {
+                "read": self.records_read.get(),
+                "written": self.records_written.get(),
+                "skipped": self.records_skipped.get(),
+            },
+            "errors": errors,
+            "bytes": {
+                "input": self.input_bytes.get(),
+                "output": self.output_bytes.get(),
+            },
+            "duration_seconds": self.started.elapsed().as_secs_f64(),
+            "schemas": schemas,
+        })
+    }
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Schema {
+    /// The Avro schema, with the fingerprint of its parsing canonical form as the Avro
+    /// specification defines it.
+    pub fn avro(schema: &avro_rs::Schema) -> Self {
+        let name = match *schema {
+            avro_rs::Schema::Record { ref name, .. }
+            | avro_rs::Schema::Enum { ref name, .. }
+            | avro_rs::Schema::Fixed { ref name, .. } => Some(name.fullname(None)),
+            _ => None,
+        };
+        Schema {
+            format: "avro",
+            name,
+            fingerprint: fingerprint(schema.canonical_form().as_bytes()),
+        }
+    }
+
+    /// The protobuf descriptors of all added proto files, with the fingerprint of their encoding.
+    pub fn protobuf(file_set: &protobuf::descriptor::FileDescriptorSet) -> error::Result<Self> {
+        Ok(Schema {
+            format: "protobuf",
+            name: None,
+            fingerprint: fingerprint(&file_set.write_to_bytes()?),
+        })
+    }
+}
+
+/// The CRC-64-AVRO fingerprint of the bytes, which the Avro specification uses for schemas.
+pub fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(EMPTY_FINGERPRINT, |fp, &b| {
+        (fp >> 8) ^ FINGERPRINT_TABLE[((fp ^ u64::from(b)) & 0xff) as usize]
+    })
+}
+
+const fn fingerprint_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut fp = i as u64;
+        let mut j = 0;
+        while j < 8 {
+            fp = (fp >> 1) ^ (EMPTY_FINGERPRINT & (fp & 1).wrapping_neg());
+            j += 1;
+        }
+        table[i] = fp;
+        i += 1;
+    }
+    table
+}
+
+impl<R> io::Read for Counting<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        self.1.set(self.1.get() + n as u64);
+        Ok(n)
+    }
+}
+
+impl<W> io::Write for Counting<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1.set(self.1.get() + n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl str::FromStr for Format {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            _ => Err(error::Error::Message(format!(
+                "unknown report format {:?}, expected 'json'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Report")
+            .field("records_read", &self.records_read.get())
+            .field("records_written", &self.records_written.get())
+            .field("records_skipped", &self.records_skipped.get())
+            .field("input_bytes", &self.input_bytes.get())
+            .field("output_bytes", &self.output_bytes.get())
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for Counting<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Counting")
+            .field("inner", &self.0)
+            .field("count", &self.1.get())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read as _;
+    use std::io::Write as _;
+
+    #[test]
+    fn test_fingerprint() {
+        // From the test cases of the Avro specification
+        assert_eq!(fingerprint(b"\"null\""), 7_195_948_357_588_979_594);
+        assert_eq!(fingerprint(b"\"int\""), 8_247_732_601_305_521_295);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let report = Report::new();
+        let mut data = Vec::new();
+        report
+            .count_input(&b"{} {}"[..])
+            .read_to_end(&mut data)
+            .unwrap();
+        report.count_output(Vec::new()).write_all(b"{}\n").unwrap();
+        report.record_read();
+        report.record_read();
+        report.record_written();
+        report.record_skipped("format");
+        report.add_schema(Schema::avro(&avro_rs::Schema::Long));
+        report.add_schema(Schema::avro(&avro_rs::Schema::Long));
+
+        let mut json = report.to_json(&Err(error::Error::Message("oops".to_owned())));
+        json.as_object_mut().unwrap().remove("duration_seconds");
+        json.as_object_mut().unwrap().remove("version");
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "status": "error",
+                "error": { "category": "other", "message": "oops" },
+                "records": { "read": 2, "written": 1, "skipped": 1 },
+                "errors": { "format": 1, "other": 1 },
+                "bytes": { "input": 5, "output": 3 },
+                "schemas": [{
+                    "format": "avro",
+                    "name": null,
+                    "fingerprint": format!("{:016x}", fingerprint(b"\"long\"")),
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+
+/// A source that collapses runs of consecutive equal records of another source into records
+/// like `{"value": ..., "count": 3}`, like `uniq -c`.
+///
+/// Only the current run is kept in memory, so equal records that aren't adjacent are counted
+/// separately; sort the input first to count all of them.  With a key path, records are equal if
+/// they have equal values at that path, and each run is represented by its first record.
+pub struct Source<S> {
+    inner: S,
+    key: Option<value::path::Path>,
+    run: Option<(value::Value, u64)>,
+}
+
+#[inline]
+pub fn source<S>(inner: S, key: Option<value::path::Path>) -> Source<S>
+where
+    S: value::Source,
+{
+    Source {
+        inner,
+        key,
+        run: None,
+    }
+}
+
+impl<S> Source<S> {
+    fn is_same(&self, a: &value::Value, b: &value::Value) -> bool {
+        match self.key {
+            Some(ref key) => key.select(a) == key.select(b),
+            None => a == b,
+        }
+    }
+}
+
+fn counted(value: value::Value, count: u64) -> value::Value {
+    value::Value::Map(vec![
+        (value::Value::String("value".to_owned()), value),
+        (
+            value::Value::String("count".to_owned()),
+            value::Value::U64(count),
+        ),
+    ])
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while let Some(v) = self.inner.read()? {
+            match self.run.take() {
+                Some((first, count)) if self.is_same(&first, &v) => {
+                    self.run = Some((first, count + 1));
+                }
+                Some((first, count)) => {
+                    self.run = Some((v, 1));
+                    return Ok(Some(counted(first, count)));
+                }
+                None => self.run = Some((v, 1)),
+            }
+        }
+        Ok(self.run.take().map(|(first, count)| counted(first, count)))
+    }
+}
+
+impl<S> fmt::Debug for Source<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AdjacentCountSource")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    struct Records(Vec<Value>);
+
+    impl value::Source for Records {
+        fn read(&mut self) -> error::Result<Option<Value>> {
+            if self.0.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(self.0.remove(0)))
+            }
+        }
+    }
+
+    fn read_all<S: value::Source>(mut source: S) -> Vec<Value> {
+        let mut result = Vec::new();
+        while let Some(v) = source.read().unwrap() {
+            result.push(v);
+        }
+        result
+    }
+
+    #[test]
+    fn test_count() {
+        let records = vec!["a", "a", "b", "a", "a", "a"]
+            .into_iter()
+            .map(|s| Value::String(s.to_owned()))
+            .collect();
+        let s = |v: &str| Value::String(v.to_owned());
+        assert_eq!(
+            read_all(source(Records(records), None)),
+            vec![counted(s("a"), 2), counted(s("b"), 1), counted(s("a"), 3)]
+        );
+        assert_eq!(read_all(source(Records(vec![]), None)), vec![]);
+    }
+
+    #[test]
+    fn test_count_by_key() {
+        let s = |v: &str| Value::String(v.to_owned());
+        let record =
+            |user: &str, n: i64| Value::Map(vec![(s("user"), s(user)), (s("n"), Value::I64(n))]);
+        let key = value::path::Path::parse(".user").unwrap();
+        assert_eq!(
+            read_all(source(
+                Records(vec![record("x", 1), record("x", 2), record("y", 3)]),
+                Some(key)
+            )),
+            vec![counted(record("x", 1), 2), counted(record("y", 3), 1)]
+        );
+    }
+}
@@ -0,0 +1,407 @@
+use crate::error;
+use crate::value;
+use crate::value::path;
+use serde_json;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::str;
+
+/// A transform that replaces the values at some JSON pointers with their keyed hash, so that
+/// records can be shared without revealing those values, while equal values still hash the same
+/// and can be joined on.
+///
+/// Values are replaced with the hex-encoded HMAC-SHA256 of their contents: the UTF-8 bytes of
+/// strings, the bytes of byte strings, and the compact JSON of everything else.  `Unit` values
+/// and pointers that don't refer to anything in a record are left alone.
+pub struct Anonymize {
+    pointers: Vec<Vec<String>>,
+    key_match: path::KeyMatch,
+    key: Vec<u8>,
+}
+
+/// JSON pointers as defined in RFC 6901, separated by commas like `/user/email,/user/id`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Pointers(Vec<Vec<String>>);
+
+impl Pointers {
+    pub fn parse(s: &str) -> error::Result<Self> {
+        s.split(',')
+            .map(str::trim)
+            .map(|pointer| {
+                if pointer.is_empty() {
+                    Err(error::Error::Message(format!(
+                        "invalid JSON pointers {:?}: empty pointer",
+                        s
+                    )))
+                } else {
+                    value::json_pointer::parse(pointer)
+                }
+            })
+            .collect::<error::Result<_>>()
+            .map(Pointers)
+    }
+}
+
+impl str::FromStr for Pointers {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Anonymize {
+    pub fn new(pointers: Pointers, key: Vec<u8>) -> Self {
+        Anonymize {
+            pointers: pointers.0,
+            key_match: path::KeyMatch::default(),
+            key,
+        }
+    }
+
+    /// Sets how the tokens of the pointers are compared with the keys of maps.
+    #[inline]
+    pub fn key_match(mut self, key_match: path::KeyMatch) -> Self {
+        self.key_match = key_match;
+        self
+    }
+
+    fn hash(&self, v: &value::Value) -> error::Result<value::Value> {
+        let data = match *v {
+            value::Value::String(ref s) => s.as_bytes().to_vec(),
+            value::Value::Bytes(ref b) => b.clone(),
+            ref v => serde_json::to_vec(v)?,
+        };
+        let digest = hmac_sha256(&self.key, &data);
+        let hex = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        Ok(value::Value::String(hex))
+    }
+}
+
+/// Returns the value that a JSON pointer refers to, where each token selects an entry of a map by
+/// its key, or an element of a sequence by its index.
+fn select_mut<'a>(
+    tokens: &[String],
+    value: &'a mut value::Value,
+    key_match: path::KeyMatch,
+) -> Option<&'a mut value::Value> {
+    tokens.iter().try_fold(value, |value, token| {
+        let segment = match *value {
+            // Indices can't have signs or leading zeros
+            value::Value::Sequence(_) => path::Segment::Index(
+                token
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|i| *i >= 0 && i.to_string() == *token)?,
+            ),
+            _ => path::Segment::Key(token.clone()),
+        };
+        segment.select_mut_with(value, key_match)
+    })
+}
+
+/// Reads a secret key as given on the command line, either `env:NAME` for the value of an
+/// environment variable, or `file:PATH` for the contents of a file.
+///
+/// Keys can't be given directly, since they would be visible to other users in the process list.
+pub fn read_key(spec: &str) -> error::Result<Vec<u8>> {
+    let key = if let Some(name) = spec.strip_prefix("env:") {
+        env::var(name)
+            .map_err(|e| error::Error::Message(format!("cannot read key from ${}: {}", name, e)))?
+            .into_bytes()
+    } else if let Some(file) = spec.strip_prefix("file:") {
+        fs::read(file)?
+    } else {
+        return Err(error::Error::Message(format!(
+            "invalid key {:?}, expected 'env:NAME' or 'file:PATH'",
+            spec
+        )));
+    };
+
+    if key.is_empty() {
+        Err(error::Error::Message(format!(
+            "the key from {} is empty",
+            spec
+        )))
+    } else {
+        Ok(key)
+    }
+}
+
+impl value::Transform for Anonymize {
+    fn transform(&mut self, mut v: value::Value) -> error::Result<Option<value::Value>> {
+        for pointer in &self.pointers {
+            if let Some(target) = select_mut(pointer, &mut v, self.key_match) {
+                if *target != value::Value::Unit {
+                    *target = self.hash(target)?;
+                }
+            }
+        }
+        Ok(Some(v))
+    }
+}
+
+impl fmt::Debug for Anonymize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The key is deliberately left out
+        f.debug_struct("Anonymize")
+            .field("pointers", &self.pointers)
+            .field("key_match", &self.key_match)
+            .finish()
+    }
+}
+
+// HMAC-SHA256 is implemented here because the `hmac` and `sha2` crates can't be added as
+// dependencies; it is checked against the test vectors of RFC 4231.
+const BLOCK_SIZE: usize = 64;
+
+/// Computes HMAC-SHA256 as specified in RFC 2104.
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = block.iter().map(|b| b ^ 0x36).collect::<Vec<_>>();
+    inner.extend_from_slice(data);
+    let mut outer = block.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>();
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Computes SHA-256 as specified in FIPS 180-4.
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (h, v) in h.iter_mut().zip(&[a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+
+    let mut result = [0u8; 32];
+    for (out, h) in result.chunks_mut(4).zip(&h) {
+        out.copy_from_slice(&h.to_be_bytes());
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Transform as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // Test cases 1 to 7 from RFC 4231
+        let long_data: &[u8] = b"This is a test using a larger than block-size key and a larger \
+            than block-size data. The key needs to be hashed before being used by the HMAC \
+            algorithm.";
+        let key_4 = (1..=25).collect::<Vec<u8>>();
+        let cases: [(&[u8], &[u8], &str); 7] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                &key_4,
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            // Truncated to 128 bits in the RFC
+            (
+                &[0x0c; 20],
+                b"Test With Truncation",
+                "a3b6167473100ee06e0c796c2955552b",
+            ),
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &[0xaa; 131],
+                long_data,
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (i, &(key, data, expected)) in cases.iter().enumerate() {
+            let actual = hex(&hmac_sha256(key, data));
+            assert!(actual.starts_with(expected), "test case {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_pointers() {
+        assert_eq!(
+            Pointers::parse("/user/email, /a~1b/0").unwrap(),
+            Pointers(vec![
+                vec!["user".to_owned(), "email".to_owned()],
+                vec!["a/b".to_owned(), "0".to_owned()],
+            ])
+        );
+        assert!(Pointers::parse("user/email").is_err());
+        assert!(Pointers::parse("/a,,/b").is_err());
+    }
+
+    #[test]
+    fn test_transform_sequences() {
+        let pointers = Pointers::parse("/ids/1,/ids/01,/ids/-1,/0").unwrap();
+        let mut anonymize =
+            Anonymize::new(pointers, b"Jefe".to_vec()).key_match(path::KeyMatch::Folded);
+        let record = Value::Map(vec![
+            (s("IDS"), Value::Sequence(vec![s("a"), s("b"), s("c")])),
+            (s("0"), s("d")),
+        ]);
+        assert_eq!(
+            anonymize.transform(record).unwrap().unwrap(),
+            Value::Map(vec![
+                (
+                    s("IDS"),
+                    Value::Sequence(vec![s("a"), s(&hex(&hmac_sha256(b"Jefe", b"b"))), s("c"),])
+                ),
+                (s("0"), s(&hex(&hmac_sha256(b"Jefe", b"d")))),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_transform() {
+        let pointers = Pointers::parse("/user/email,/user/id,/missing").unwrap();
+        let mut anonymize = Anonymize::new(pointers, b"Jefe".to_vec());
+
+        let record = |id| {
+            Value::Map(vec![(
+                s("user"),
+                Value::Map(vec![
+                    (s("email"), s("what do ya want for nothing?")),
+                    (s("id"), id),
+                    (s("name"), s("kept")),
+                ]),
+            )])
+        };
+
+        let anonymized = anonymize.transform(record(Value::I32(7))).unwrap().unwrap();
+        assert_eq!(
+            anonymized,
+            Value::Map(vec![(
+                s("user"),
+                Value::Map(vec![
+                    (
+                        s("email"),
+                        s("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+                    ),
+                    (s("id"), s(&hex(&hmac_sha256(b"Jefe", b"7")))),
+                    (s("name"), s("kept")),
+                ]),
+            )])
+        );
+
+        // Nulls stay nulls
+        let anonymized = anonymize.transform(record(Value::Unit)).unwrap().unwrap();
+        assert_eq!(
+            path::Path::parse(".user.id").unwrap().select(&anonymized),
+            Some(&Value::Unit)
+        );
+    }
+
+    #[test]
+    fn test_read_key() {
+        assert!(read_key("SECRET").is_err());
+        assert!(read_key("env:RQ_TEST_ANONYMIZE_KEY_THAT_IS_NOT_SET").is_err());
+    }
+}
@@ -1,26 +1,386 @@
 use crate::error;
 use crate::value;
 use avro_rs;
+use serde_json;
 use std;
+use std::collections;
 use std::fmt;
+use std::fs;
 use std::io;
+use std::path;
+use std::str;
 
-pub struct Source<'a, R>(avro_rs::Reader<'a, R>)
+pub struct Source<'a, R>(
+    avro_rs::Reader<'a, io::Chain<io::Cursor<Vec<u8>>, R>>,
+    value::enums::Style,
+    LogicalTypes,
+)
 where
     R: io::Read;
 
-pub struct Sink<'a, W>(avro_rs::Writer<'a, W>)
+/// The logical types of an Avro schema, like `timestamp-millis` on a `long`, which the schemas of
+/// avro_rs don't keep.
+///
+/// Sources use them to output dates, times and timestamps as ISO 8601 strings, decimals as
+/// decimal strings and durations as maps with `months`, `days` and `milliseconds`, instead of the
+/// underlying numbers and bytes.  UUIDs are already strings.  The default has no logical types,
+/// so that sources output the underlying values.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LogicalTypes(Logical);
+
+/// A logical type, or the logical types nested in a complex type, in the shape of the schema.
+#[derive(Clone, Debug, Default, PartialEq)]
+enum Logical {
+    #[default]
+    None,
+    Decimal(u32),
+    Date,
+    TimeMillis,
+    TimeMicros,
+    TimestampMillis,
+    TimestampMicros,
+    LocalTimestampMillis,
+    LocalTimestampMicros,
+    Duration,
+    Array(Box<Logical>),
+    Map(Box<Logical>),
+    Union(Vec<Logical>),
+    Record(collections::HashMap<String, Logical>),
+}
+
+/// A source that reads Avro datums without a container, like Kafka messages, that were all
+/// written with the same schema.
+///
+/// The datums may either be bare, or each be framed in the single-object encoding, which starts
+/// with the bytes `C3 01` and the fingerprint of the schema.  Which of the two is detected from
+/// the first datum.
+pub struct RawSource<'a, R>
+where
+    R: io::Read,
+{
+    input: io::BufReader<R>,
+    schema: &'a avro_rs::Schema,
+    reader_schema: Option<&'a avro_rs::Schema>,
+    fingerprint: u64,
+    single_object: Option<bool>,
+    enum_style: value::enums::Style,
+    logical_types: LogicalTypes,
+}
+
+/// A sink that writes records to an Avro object container file.
+///
+/// Records are collected into blocks, and each block is compressed and written out once it is
+/// large enough according to the `SinkOptions`.
+pub struct Sink<'a, W>
 where
-    W: io::Write;
+    W: io::Write,
+{
+    schema: &'a avro_rs::Schema,
+    w: W,
+    options: SinkOptions,
+    marker: [u8; 16],
+    has_header: bool,
+    block: Vec<u8>,
+    block_records: usize,
+}
+
+/// Settings for how an Avro container file is written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SinkOptions {
+    pub codec: avro_rs::Codec,
+    /// A block is finished once its uncompressed size reaches this many bytes.
+    pub block_size: usize,
+    /// A block is finished once it contains this many records, if set.
+    pub block_records: Option<usize>,
+    /// Additional metadata for the container header.  Keys starting with `avro.` are reserved.
+    pub metadata: Vec<(String, Vec<u8>)>,
+    /// Derive the sync marker from this seed instead of choosing it randomly, so that the same
+    /// records always produce the same file.
+    pub seed: Option<u64>,
+}
+
+/// The header of an existing Avro container file, which `append_sink` adds blocks to.
+pub struct Container {
+    pub schema: avro_rs::Schema,
+    codec: avro_rs::Codec,
+    marker: [u8; 16],
+}
 
+const MAGIC: &[u8] = b"Obj\x01";
+const SINGLE_OBJECT_MAGIC: &[u8] = b"\xc3\x01";
+
+/// Reads an Avro container, decoding logical types according to the schema in its header.
 #[inline]
-pub fn source<'a, R>(r: R) -> error::Result<Source<'a, R>>
+pub fn source<'a, R>(mut r: R) -> error::Result<Source<'a, R>>
 where
     R: io::Read,
 {
-    Ok(Source(avro_rs::Reader::new(r).map_err(|e| {
-        error::Error::Avro(error::Avro::downcast(e))
-    })?))
+    let (mut header, metadata) = read_header(&mut r)?;
+    let schema = metadata
+        .iter()
+        .find(|(k, _)| k == b"avro.schema")
+        .and_then(|(_, v)| str::from_utf8(v).ok());
+    let mut logical_types = LogicalTypes::default();
+    if let Some(schema) = schema {
+        // avro-rs can't resolve references to named types, so it gets the schema with their
+        // definitions inlined, and otherwise reports the error itself
+        if let Ok((schema, types)) = SchemaRegistry::new().parse_str_with_logical_types(schema) {
+            let schema = serde_json::to_string(&schema)?.into_bytes();
+            let metadata = metadata
+                .iter()
+                .map(|(k, v)| match k.as_slice() {
+                    b"avro.schema" => (k.as_slice(), schema.as_slice()),
+                    k => (k, v.as_slice()),
+                })
+                .collect::<Vec<_>>();
+            let mut marker = [0; 16];
+            marker.copy_from_slice(&header[header.len() - 16..]);
+            header = encode_header(&metadata, &marker);
+            logical_types = types;
+        }
+    }
+    Ok(Source(
+        avro_rs::Reader::new(io::Read::chain(io::Cursor::new(header), r))
+            .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?,
+        value::enums::Style::default(),
+        logical_types,
+    ))
+}
+
+/// Like `source`, but resolves the records from the schema that they were written with to the
+/// given reader schema, using the Avro schema resolution rules: fields are matched by name,
+/// fields that the writer didn't know get their defaults, and types are promoted, like `int` to
+/// `long`.  Logical types aren't decoded unless they are set with `logical_types`, since they
+/// would have to come from the reader schema.
+#[inline]
+pub fn source_with_reader_schema<R>(
+    r: R,
+    reader_schema: &avro_rs::Schema,
+) -> error::Result<Source<R>>
+where
+    R: io::Read,
+{
+    Ok(Source(
+        avro_rs::Reader::with_schema(
+            reader_schema,
+            io::Read::chain(io::Cursor::new(Vec::new()), r),
+        )
+        .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?,
+        value::enums::Style::default(),
+        LogicalTypes::default(),
+    ))
+}
+
+/// Reads the header of an existing Avro container file, so that records can be appended to it
+/// with `append_sink`.
+pub fn read_container<R>(mut r: R) -> error::Result<Container>
+where
+    R: io::Read,
+{
+    use std::str::FromStr;
+
+    let (header, metadata) = read_header(&mut r)?;
+    if !header.starts_with(MAGIC) {
+        return Err(avro_error("not an Avro container file".to_owned()));
+    }
+    let value = |key: &[u8]| {
+        metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| String::from_utf8_lossy(v))
+    };
+
+    let schema = value(b"avro.schema")
+        .ok_or_else(|| avro_error("the Avro container has no schema".to_owned()))?;
+    let schema = SchemaRegistry::new().parse_str(&schema)?;
+    let codec = match value(b"avro.codec") {
+        Some(codec) => avro_rs::Codec::from_str(&codec)
+            .map_err(|_| avro_error(format!("unsupported Avro codec: {}", codec)))?,
+        None => avro_rs::Codec::Null,
+    };
+    let mut marker = [0; 16];
+    marker.copy_from_slice(&header[header.len() - 16..]);
+
+    Ok(Container {
+        schema,
+        codec,
+        marker,
+    })
+}
+
+/// The key-value pairs of an Avro container header.
+type Metadata = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Reads the header of an Avro container, returning its bytes so that they can be read again,
+/// and its metadata.
+fn read_header<R>(r: &mut R) -> error::Result<(Vec<u8>, Metadata)>
+where
+    R: io::Read,
+{
+    let mut header = HeaderReader {
+        r,
+        bytes: Vec::new(),
+    };
+    let mut metadata = Vec::new();
+
+    if header.read_up_to(MAGIC.len() as u64)? != MAGIC {
+        // Let avro_rs report the error
+        return Ok((header.bytes, metadata));
+    }
+    loop {
+        let count = header.read_long()?;
+        if count == 0 {
+            break;
+        }
+        if count < 0 {
+            // The size of the block in bytes
+            header.read_long()?;
+        }
+        for _ in 0..count.unsigned_abs() {
+            let len = header.read_long()?;
+            let key = header.read(len.max(0) as u64)?;
+            let len = header.read_long()?;
+            let value = header.read(len.max(0) as u64)?;
+            metadata.push((key, value));
+        }
+    }
+    header.read(16)?;
+
+    Ok((header.bytes, metadata))
+}
+
+/// A reader that keeps the bytes that it read.
+struct HeaderReader<'r, R> {
+    r: &'r mut R,
+    bytes: Vec<u8>,
+}
+
+impl<'r, R> HeaderReader<'r, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, len: u64) -> error::Result<Vec<u8>> {
+        let bytes = self.read_up_to(len)?;
+        if (bytes.len() as u64) < len {
+            return Err(avro_error("truncated Avro header".to_owned()));
+        }
+        Ok(bytes)
+    }
+
+    fn read_up_to(&mut self, len: u64) -> error::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        io::Read::read_to_end(&mut io::Read::take(&mut *self.r, len), &mut bytes)?;
+        self.bytes.extend_from_slice(&bytes);
+        Ok(bytes)
+    }
+
+    /// Reads a zig-zag encoded variable-length integer.
+    fn read_long(&mut self) -> error::Result<i64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read(1)?[0];
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((n >> 1) as i64 ^ -((n & 1) as i64));
+            }
+        }
+        Err(avro_error("bad integer in Avro header".to_owned()))
+    }
+}
+
+impl<'a, R> Source<'a, R>
+where
+    R: io::Read,
+{
+    /// Outputs the values of enums in the given style, see `value::enums::Style`.
+    #[inline]
+    pub fn enum_style(mut self, enum_style: value::enums::Style) -> Self {
+        self.1 = enum_style;
+        self
+    }
+
+    /// Decodes these logical types instead of the ones from the header of the container.
+    #[inline]
+    pub fn logical_types(mut self, logical_types: LogicalTypes) -> Self {
+        self.2 = logical_types;
+        self
+    }
+
+    /// The schema in the header of the container, which the records were written with.
+    #[inline]
+    pub fn writer_schema(&self) -> &avro_rs::Schema {
+        self.0.writer_schema()
+    }
+}
+
+#[inline]
+pub fn raw_source<R>(r: R, schema: &avro_rs::Schema) -> RawSource<R>
+where
+    R: io::Read,
+{
+    RawSource {
+        input: io::BufReader::new(r),
+        schema,
+        reader_schema: None,
+        fingerprint: fingerprint(schema.canonical_form().as_bytes()),
+        single_object: None,
+        enum_style: value::enums::Style::default(),
+        logical_types: LogicalTypes::default(),
+    }
+}
+
+impl<'a, R> RawSource<'a, R>
+where
+    R: io::Read,
+{
+    /// Outputs the values of enums in the given style, see `value::enums::Style`.
+    #[inline]
+    pub fn enum_style(mut self, enum_style: value::enums::Style) -> Self {
+        self.enum_style = enum_style;
+        self
+    }
+
+    /// Decodes the given logical types, see `LogicalTypes`.
+    #[inline]
+    pub fn logical_types(mut self, logical_types: LogicalTypes) -> Self {
+        self.logical_types = logical_types;
+        self
+    }
+
+    /// Reads the datums in the single-object encoding, or bare if `false`, instead of detecting
+    /// which one it is from the first datum.
+    #[inline]
+    pub fn single_object(mut self, single_object: bool) -> Self {
+        self.single_object = Some(single_object);
+        self
+    }
+
+    /// Resolves each datum against this schema, like `source_with_reader_schema`, so that the
+    /// declared defaults of fields that the datums were written without are filled in.
+    #[inline]
+    pub fn reader_schema(mut self, reader_schema: &'a avro_rs::Schema) -> Self {
+        self.reader_schema = Some(reader_schema);
+        self
+    }
+}
+
+/// Computes the CRC-64-AVRO (Rabin) fingerprint of the given bytes, which the single-object
+/// encoding uses to identify schemas by their parsing canonical form.
+fn fingerprint(bytes: &[u8]) -> u64 {
+    const EMPTY: u64 = 0xc15d_213a_a4d7_a795;
+
+    let mut table = [0u64; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut fp = i as u64;
+        for _ in 0..8 {
+            fp = (fp >> 1) ^ (EMPTY & (fp & 1).wrapping_neg());
+        }
+        *entry = fp;
+    }
+
+    bytes.iter().fold(EMPTY, |fp, &b| {
+        (fp >> 8) ^ table[((fp ^ u64::from(b)) & 0xff) as usize]
+    })
 }
 
 #[inline]
@@ -28,7 +388,449 @@ pub fn sink<W>(schema: &avro_rs::Schema, w: W, codec: avro_rs::Codec) -> error::
 where
     W: io::Write,
 {
-    Ok(Sink(avro_rs::Writer::with_codec(schema, w, codec)))
+    sink_with_options(
+        schema,
+        w,
+        SinkOptions {
+            codec,
+            ..SinkOptions::default()
+        },
+    )
+}
+
+#[inline]
+pub fn sink_with_options<W>(
+    schema: &avro_rs::Schema,
+    w: W,
+    options: SinkOptions,
+) -> error::Result<Sink<W>>
+where
+    W: io::Write,
+{
+    if let Some((key, _)) = options
+        .metadata
+        .iter()
+        .find(|(k, _)| k.starts_with("avro."))
+    {
+        return Err(avro_error(format!(
+            "the Avro metadata key {} is reserved",
+            key
+        )));
+    }
+    if options.block_records == Some(0) {
+        return Err(avro_error(
+            "Avro blocks must contain at least one record".to_owned(),
+        ));
+    }
+
+    Ok(Sink {
+        schema,
+        w,
+        marker: sync_marker(options.seed),
+        options,
+        has_header: false,
+        block: Vec::new(),
+        block_records: 0,
+    })
+}
+
+/// Creates a sink that adds blocks to an existing Avro container file, whose header has been
+/// read with `read_container`, through a writer that writes to the end of that file.
+///
+/// The blocks use the codec and sync marker of the container, so the codec and seed of the
+/// options are ignored, and metadata can't be added since the header isn't written again.
+pub fn append_sink<W>(container: &Container, w: W, options: SinkOptions) -> error::Result<Sink<W>>
+where
+    W: io::Write,
+{
+    if !options.metadata.is_empty() {
+        return Err(avro_error(
+            "metadata can't be added when appending to an Avro container".to_owned(),
+        ));
+    }
+    let mut sink = sink_with_options(
+        &container.schema,
+        w,
+        SinkOptions {
+            codec: container.codec,
+            ..options
+        },
+    )?;
+    sink.marker = container.marker;
+    sink.has_header = true;
+    Ok(sink)
+}
+
+impl Default for SinkOptions {
+    fn default() -> Self {
+        SinkOptions {
+            codec: avro_rs::Codec::Null,
+            // The same as what avro-rs uses
+            block_size: 16000,
+            block_records: None,
+            metadata: Vec::new(),
+            seed: None,
+        }
+    }
+}
+
+/// A collection of named Avro types (records, enums and fixed types) that schemas may refer to by
+/// name, even when the types are defined in different schema files.
+#[derive(Debug, Default)]
+pub struct SchemaRegistry {
+    types: collections::HashMap<String, serde_json::Value>,
+}
+
+const PRIMITIVE_TYPES: &[&str] = &[
+    "null", "boolean", "int", "long", "float", "double", "bytes", "string",
+];
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers all named types defined in the given JSON schema.
+    pub fn add_json(&mut self, json: &str) -> error::Result<()> {
+        let schema = serde_json::from_str(json)?;
+        self.add_definitions(&schema, None)
+    }
+
+    /// Registers all named types defined in the `.avsc` files of the given directory.
+    pub fn add_dir(&mut self, dir: &path::Path) -> error::Result<()> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |e| e == "avsc") {
+                paths.push(path);
+            }
+        }
+        // Sort for deterministic error messages
+        paths.sort();
+
+        for path in paths {
+            trace!("Loading Avro schema from {:?}", path);
+            self.add_json(&fs::read_to_string(&path)?)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the given JSON schema, replacing references to named types with their definitions
+    /// from this registry or from the schema itself.
+    ///
+    /// The definitions are inlined because avro-rs can't resolve references; sinks still write
+    /// each named type's definition only once.
+    pub fn parse_str(&mut self, json: &str) -> error::Result<avro_rs::Schema> {
+        self.parse_str_with_logical_types(json)
+            .map(|(schema, _)| schema)
+    }
+
+    /// Like `parse_str`, but also returns the logical types of the schema.
+    pub fn parse_str_with_logical_types(
+        &mut self,
+        json: &str,
+    ) -> error::Result<(avro_rs::Schema, LogicalTypes)> {
+        let schema = serde_json::from_str(json)?;
+        self.add_definitions(&schema, None)?;
+        let schema = self.resolve(&schema, None, &mut Vec::new())?;
+        let logical_types = LogicalTypes::from_json(&schema);
+        let schema = avro_rs::Schema::parse(&schema)
+            .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?;
+        Ok((schema, logical_types))
+    }
+
+    fn add_definitions(
+        &mut self,
+        schema: &serde_json::Value,
+        namespace: Option<&str>,
+    ) -> error::Result<()> {
+        match *schema {
+            serde_json::Value::Array(ref variants) => {
+                for variant in variants {
+                    self.add_definitions(variant, namespace)?;
+                }
+                Ok(())
+            }
+            serde_json::Value::Object(ref fields) => match fields.get("type") {
+                Some(serde_json::Value::String(t))
+                    if t == "record" || t == "error" || t == "enum" || t == "fixed" =>
+                {
+                    let (name, namespace) = full_name(fields, namespace)?;
+                    let mut definition = fields.clone();
+                    if let Some(ref namespace) = namespace {
+                        definition.insert(
+                            "namespace".to_owned(),
+                            serde_json::Value::String(namespace.clone()),
+                        );
+                    }
+                    let definition = serde_json::Value::Object(definition);
+
+                    match self.types.get(&name) {
+                        Some(existing) if *existing != definition => {
+                            return Err(avro_error(format!(
+                                "conflicting definitions of Avro type {}",
+                                name
+                            )));
+                        }
+                        _ => {
+                            self.types.insert(name, definition);
+                        }
+                    }
+
+                    if let Some(serde_json::Value::Array(record_fields)) = fields.get("fields") {
+                        for field in record_fields {
+                            if let Some(t) = field.get("type") {
+                                self.add_definitions(t, namespace.as_deref())?;
+                            }
+                        }
+                    }
+                    Ok(())
+                }
+                Some(serde_json::Value::String(t)) if t == "array" => match fields.get("items") {
+                    Some(items) => self.add_definitions(items, namespace),
+                    None => Ok(()),
+                },
+                Some(serde_json::Value::String(t)) if t == "map" => match fields.get("values") {
+                    Some(values) => self.add_definitions(values, namespace),
+                    None => Ok(()),
+                },
+                Some(t) => self.add_definitions(t, namespace),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve(
+        &self,
+        schema: &serde_json::Value,
+        namespace: Option<&str>,
+        stack: &mut Vec<String>,
+    ) -> error::Result<serde_json::Value> {
+        match *schema {
+            serde_json::Value::String(ref name) => {
+                if PRIMITIVE_TYPES.contains(&name.as_str()) {
+                    Ok(schema.clone())
+                } else {
+                    self.resolve_reference(name, namespace, stack)
+                }
+            }
+            serde_json::Value::Array(ref variants) => Ok(serde_json::Value::Array(
+                variants
+                    .iter()
+                    .map(|v| self.resolve(v, namespace, stack))
+                    .collect::<error::Result<_>>()?,
+            )),
+            serde_json::Value::Object(ref fields) => {
+                let mut fields = fields.clone();
+                match fields.get("type").cloned() {
+                    Some(serde_json::Value::String(ref t)) if t == "record" || t == "error" => {
+                        let (name, namespace) = full_name(&fields, namespace)?;
+                        if let Some(serde_json::Value::Array(record_fields)) =
+                            fields.get_mut("fields")
+                        {
+                            stack.push(name);
+                            for field in record_fields.iter_mut() {
+                                if let Some(t) = field.get_mut("type") {
+                                    *t = self.resolve(t, namespace.as_deref(), stack)?;
+                                }
+                            }
+                            stack.pop();
+                        }
+                        Ok(serde_json::Value::Object(fields))
+                    }
+                    Some(serde_json::Value::String(ref t)) if t == "enum" || t == "fixed" => {
+                        Ok(serde_json::Value::Object(fields))
+                    }
+                    Some(serde_json::Value::String(ref t)) if t == "array" || t == "map" => {
+                        let key = if t == "array" { "items" } else { "values" };
+                        if let Some(inner) = fields.get_mut(key) {
+                            *inner = self.resolve(inner, namespace, stack)?;
+                        }
+                        Ok(serde_json::Value::Object(fields))
+                    }
+                    Some(serde_json::Value::String(ref t))
+                        if PRIMITIVE_TYPES.contains(&t.as_str()) =>
+                    {
+                        Ok(serde_json::Value::Object(fields))
+                    }
+                    // A reference such as `{"type": "com.example.Foo"}`, or a nested definition
+                    Some(t) => self.resolve(&t, namespace, stack),
+                    None => Ok(serde_json::Value::Object(fields)),
+                }
+            }
+            _ => Ok(schema.clone()),
+        }
+    }
+
+    fn resolve_reference(
+        &self,
+        name: &str,
+        namespace: Option<&str>,
+        stack: &mut Vec<String>,
+    ) -> error::Result<serde_json::Value> {
+        let qualified = match namespace {
+            Some(namespace) if !name.contains('.') => format!("{}.{}", namespace, name),
+            _ => name.to_owned(),
+        };
+        let (name, definition) = match self.types.get_key_value(&qualified) {
+            Some(entry) => entry,
+            None => self
+                .types
+                .get_key_value(name)
+                .ok_or_else(|| avro_error(format!("unknown Avro type: {}", qualified)))?,
+        };
+
+        if stack.contains(name) {
+            return Err(avro_error(format!(
+                "recursive Avro type {} is not supported",
+                name
+            )));
+        }
+
+        // Definitions are stored with their namespace made explicit
+        self.resolve(definition, None, stack)
+    }
+}
+
+/// Replaces all but the first definition of each named type with its full name, since a name may
+/// only be defined once, but avro-rs writes the definition wherever the type is used.
+fn define_once(
+    schema: serde_json::Value,
+    namespace: Option<&str>,
+    defined: &mut collections::HashSet<String>,
+) -> error::Result<serde_json::Value> {
+    match schema {
+        serde_json::Value::Array(variants) => Ok(serde_json::Value::Array(
+            variants
+                .into_iter()
+                .map(|v| define_once(v, namespace, defined))
+                .collect::<error::Result<_>>()?,
+        )),
+        serde_json::Value::Object(mut fields) => {
+            let t = fields
+                .get("type")
+                .and_then(|t| t.as_str())
+                .map(str::to_owned);
+            match t.as_deref() {
+                Some("record") | Some("error") | Some("enum") | Some("fixed") => {
+                    let (name, namespace) = full_name(&fields, namespace)?;
+                    if !defined.insert(name.clone()) {
+                        return Ok(serde_json::Value::String(name));
+                    }
+                    if let Some(serde_json::Value::Array(record_fields)) = fields.get_mut("fields")
+                    {
+                        for field in record_fields.iter_mut() {
+                            if let Some(t) = field.get_mut("type") {
+                                *t = define_once(t.take(), namespace.as_deref(), defined)?;
+                            }
+                        }
+                    }
+                }
+                Some("array") | Some("map") => {
+                    let key = if t.as_deref() == Some("array") {
+                        "items"
+                    } else {
+                        "values"
+                    };
+                    if let Some(inner) = fields.get_mut(key) {
+                        *inner = define_once(inner.take(), namespace, defined)?;
+                    }
+                }
+                _ => (),
+            }
+            Ok(serde_json::Value::Object(fields))
+        }
+        schema => Ok(schema),
+    }
+}
+
+fn full_name(
+    fields: &serde_json::Map<String, serde_json::Value>,
+    namespace: Option<&str>,
+) -> error::Result<(String, Option<String>)> {
+    let name = match fields.get("name") {
+        Some(serde_json::Value::String(name)) => name,
+        _ => return Err(avro_error("named Avro type without a name".to_owned())),
+    };
+
+    if let Some(index) = name.rfind('.') {
+        Ok((name.clone(), Some(name[..index].to_owned())))
+    } else {
+        let namespace = match fields.get("namespace") {
+            Some(serde_json::Value::String(namespace)) if !namespace.is_empty() => {
+                Some(namespace.as_str())
+            }
+            _ => namespace,
+        };
+        match namespace {
+            Some(namespace) => Ok((
+                format!("{}.{}", namespace, name),
+                Some(namespace.to_owned()),
+            )),
+            None => Ok((name.clone(), None)),
+        }
+    }
+}
+
+fn avro_error(message: String) -> error::Error {
+    error::Error::Avro(error::Avro::Custom { message })
+}
+
+impl<'a, R> value::Source for RawSource<'a, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        use std::io::BufRead;
+        use std::io::Read;
+
+        let single_object = match self.single_object {
+            Some(single_object) => single_object,
+            None => {
+                let buffer = self.input.fill_buf()?;
+                if buffer.is_empty() {
+                    return Ok(None);
+                }
+                let single_object = buffer.starts_with(SINGLE_OBJECT_MAGIC);
+                self.single_object = Some(single_object);
+                single_object
+            }
+        };
+        if self.input.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        if single_object {
+            let mut header = [0; 10];
+            self.input.read_exact(&mut header)?;
+            if !header.starts_with(SINGLE_OBJECT_MAGIC) {
+                return Err(avro_error(
+                    "expected a single-object encoded Avro datum".to_owned(),
+                ));
+            }
+            let mut fingerprint = [0; 8];
+            fingerprint.copy_from_slice(&header[2..]);
+            let fingerprint = u64::from_le_bytes(fingerprint);
+            if fingerprint != self.fingerprint {
+                return Err(avro_error(format!(
+                    "the Avro datum was written with a schema with fingerprint {:016x}, \
+                     but the given schema has fingerprint {:016x}",
+                    fingerprint, self.fingerprint
+                )));
+            }
+        }
+
+        let v = avro_rs::from_avro_datum(self.schema, &mut self.input, self.reader_schema)
+            .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?;
+        Ok(Some(value_from_avro(
+            v,
+            Some(self.reader_schema.unwrap_or(self.schema)),
+            None,
+            self.enum_style,
+            &self.logical_types.0,
+        )))
+    }
 }
 
 impl<'a, R> value::Source for Source<'a, R>
@@ -38,36 +840,332 @@ where
     #[inline]
     fn read(&mut self) -> error::Result<Option<value::Value>> {
         match self.0.next() {
-            Some(Ok(v)) => Ok(Some(value_from_avro(v))),
+            Some(Ok(v)) => Ok(Some(value_from_avro(
+                v,
+                Some(
+                    self.0
+                        .reader_schema()
+                        .unwrap_or_else(|| self.0.writer_schema()),
+                ),
+                None,
+                self.1,
+                &(self.2).0,
+            ))),
             Some(Err(e)) => Err(error::Error::Avro(error::Avro::downcast(e))),
             None => Ok(None),
         }
     }
 }
 
-fn value_from_avro(value: avro_rs::types::Value) -> value::Value {
+impl LogicalTypes {
+    /// Finds the logical types in a JSON Avro schema.
+    pub fn parse_str(json: &str) -> error::Result<Self> {
+        Ok(Self::from_json(&serde_json::from_str(json)?))
+    }
+
+    /// Finds the logical types in a JSON Avro schema, where named types may be referred to after
+    /// they are defined.
+    pub fn from_json(schema: &serde_json::Value) -> Self {
+        LogicalTypes(Logical::from_json(
+            schema,
+            None,
+            &mut collections::HashMap::new(),
+        ))
+    }
+}
+
+impl Logical {
+    fn from_json(
+        schema: &serde_json::Value,
+        namespace: Option<&str>,
+        names: &mut collections::HashMap<String, Logical>,
+    ) -> Self {
+        use serde_json::Value;
+
+        let fields = match *schema {
+            Value::String(ref name) => {
+                let qualified = match namespace {
+                    Some(namespace) if !name.contains('.') => format!("{}.{}", namespace, name),
+                    _ => name.clone(),
+                };
+                return names
+                    .get(&qualified)
+                    .or_else(|| names.get(name))
+                    .cloned()
+                    .unwrap_or_default();
+            }
+            Value::Array(ref variants) => {
+                return Logical::Union(
+                    variants
+                        .iter()
+                        .map(|v| Logical::from_json(v, namespace, names))
+                        .collect(),
+                );
+            }
+            Value::Object(ref fields) => fields,
+            _ => return Logical::None,
+        };
+
+        let logical = match fields.get("logicalType").and_then(Value::as_str) {
+            Some("decimal") => {
+                Logical::Decimal(fields.get("scale").and_then(Value::as_u64).unwrap_or(0) as u32)
+            }
+            Some("date") => Logical::Date,
+            Some("time-millis") => Logical::TimeMillis,
+            Some("time-micros") => Logical::TimeMicros,
+            Some("timestamp-millis") => Logical::TimestampMillis,
+            Some("timestamp-micros") => Logical::TimestampMicros,
+            Some("local-timestamp-millis") => Logical::LocalTimestampMillis,
+            Some("local-timestamp-micros") => Logical::LocalTimestampMicros,
+            Some("duration") => Logical::Duration,
+            _ => Logical::None,
+        };
+
+        match fields.get("type") {
+            Some(Value::String(t)) if t == "array" => {
+                let items = fields.get("items").unwrap_or(&Value::Null);
+                Logical::Array(Box::new(Logical::from_json(items, namespace, names)))
+            }
+            Some(Value::String(t)) if t == "map" => {
+                let values = fields.get("values").unwrap_or(&Value::Null);
+                Logical::Map(Box::new(Logical::from_json(values, namespace, names)))
+            }
+            Some(Value::String(t)) if t == "record" || t == "error" => {
+                let (name, namespace) = match full_name(fields, namespace) {
+                    Ok(name) => name,
+                    Err(_) => return Logical::None,
+                };
+                // Recursive references to the record don't get logical types
+                names.insert(name.clone(), Logical::None);
+                let fields = fields
+                    .get("fields")
+                    .and_then(Value::as_array)
+                    .map_or(&[][..], |f| &f[..])
+                    .iter()
+                    .filter_map(|field| {
+                        let field_name = field.get("name")?.as_str()?.to_owned();
+                        let schema = field.get("type")?;
+                        let logical = Logical::from_json(schema, namespace.as_deref(), names);
+                        Some((field_name, logical))
+                    })
+                    .collect();
+                let logical = Logical::Record(fields);
+                names.insert(name, logical.clone());
+                logical
+            }
+            Some(Value::String(t)) if t == "fixed" => {
+                if let Ok((name, _)) = full_name(fields, namespace) {
+                    names.insert(name, logical.clone());
+                }
+                logical
+            }
+            Some(schema @ Value::Object(_)) | Some(schema @ Value::Array(_))
+                if logical == Logical::None =>
+            {
+                Logical::from_json(schema, namespace, names)
+            }
+            _ => logical,
+        }
+    }
+
+    fn variant(&self, index: usize) -> &Logical {
+        match *self {
+            Logical::Union(ref variants) => variants.get(index).unwrap_or(&Logical::None),
+            _ => &Logical::None,
+        }
+    }
+
+    fn field(&self, name: &str) -> &Logical {
+        match *self {
+            Logical::Record(ref fields) => fields.get(name).unwrap_or(&Logical::None),
+            _ => &Logical::None,
+        }
+    }
+
+    fn inner(&self) -> &Logical {
+        match *self {
+            Logical::Array(ref inner) | Logical::Map(ref inner) => inner,
+            _ => &Logical::None,
+        }
+    }
+
+    fn decode_int(&self, v: i32) -> value::Value {
+        match *self {
+            Logical::Date => value::Value::String(value::timestamp::iso_date(v.into())),
+            Logical::TimeMillis => value::Value::String(time_of_day(i64::from(v) * 1000)),
+            _ => value::Value::I32(v),
+        }
+    }
+
+    fn decode_long(&self, v: i64) -> value::Value {
+        let timestamp = |micros: i64, local: bool| {
+            let mut s = value::timestamp::rfc3339_nanos(
+                micros.div_euclid(1_000_000),
+                micros.rem_euclid(1_000_000) as u32 * 1000,
+            );
+            if local {
+                s.pop();
+            }
+            value::Value::String(s)
+        };
+        match *self {
+            Logical::TimeMicros => value::Value::String(time_of_day(v)),
+            Logical::TimestampMillis => timestamp(v.saturating_mul(1000), false),
+            Logical::TimestampMicros => timestamp(v, false),
+            Logical::LocalTimestampMillis => timestamp(v.saturating_mul(1000), true),
+            Logical::LocalTimestampMicros => timestamp(v, true),
+            _ => value::Value::I64(v),
+        }
+    }
+
+    fn decode_bytes(&self, v: Vec<u8>) -> value::Value {
+        match *self {
+            Logical::Decimal(scale) if !v.is_empty() && v.len() <= 16 => {
+                // A big-endian two's complement integer, sign-extended to 128 bits
+                let fill = if v[0] & 0x80 != 0 { 0xff } else { 0 };
+                let mut bytes = [fill; 16];
+                bytes[16 - v.len()..].copy_from_slice(&v);
+                value::Value::String(decimal(i128::from_be_bytes(bytes), scale))
+            }
+            Logical::Duration if v.len() == 12 => {
+                let field = |i: usize| {
+                    let mut bytes = [0; 4];
+                    bytes.copy_from_slice(&v[i * 4..i * 4 + 4]);
+                    value::Value::U32(u32::from_le_bytes(bytes))
+                };
+                value::Value::Map(vec![
+                    (value::Value::String("months".to_owned()), field(0)),
+                    (value::Value::String("days".to_owned()), field(1)),
+                    (value::Value::String("milliseconds".to_owned()), field(2)),
+                ])
+            }
+            _ => value::Value::Bytes(v),
+        }
+    }
+}
+
+/// Formats microseconds after midnight like `15:04:05.999`.
+pub(crate) fn time_of_day(micros: i64) -> String {
+    let seconds = micros.div_euclid(1_000_000);
+    let micros = micros.rem_euclid(1_000_000);
+    let mut s = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if micros % 1000 == 0 && micros != 0 {
+        s.push_str(&format!(".{:03}", micros / 1000));
+    } else if micros != 0 {
+        s.push_str(&format!(".{:06}", micros));
+    }
+    s
+}
+
+/// Formats an unscaled decimal integer with the given number of fractional digits.
+pub(crate) fn decimal(unscaled: i128, scale: u32) -> String {
+    let digits = unscaled.unsigned_abs().to_string();
+    let sign = if unscaled < 0 { "-" } else { "" };
+    let scale = scale as usize;
+    if scale == 0 {
+        format!("{}{}", sign, digits)
+    } else if digits.len() > scale {
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        format!("{}{}.{}", sign, integer, fraction)
+    } else {
+        format!("{}0.{:0>width$}", sign, digits, width = scale)
+    }
+}
+
+fn value_from_avro(
+    value: avro_rs::types::Value,
+    schema: Option<&avro_rs::Schema>,
+    namespace: Option<&str>,
+    enum_style: value::enums::Style,
+    logical: &Logical,
+) -> value::Value {
     use avro_rs::types::Value;
+    use avro_rs::Schema;
+
     match value {
         Value::Null => value::Value::Unit,
         Value::Boolean(v) => value::Value::Bool(v),
-        Value::Int(v) => value::Value::I32(v),
-        Value::Long(v) => value::Value::I64(v),
+        Value::Int(v) => logical.decode_int(v),
+        Value::Long(v) => logical.decode_long(v),
         Value::Float(v) => value::Value::from_f32(v),
         Value::Double(v) => value::Value::from_f64(v),
-        Value::Bytes(v) | Value::Fixed(_, v) => value::Value::Bytes(v),
-        Value::String(v) | Value::Enum(_, v) => value::Value::String(v),
-        Value::Union(boxed) => value_from_avro(*boxed),
-        Value::Array(v) => value::Value::Sequence(v.into_iter().map(value_from_avro).collect()),
-        Value::Map(v) => value::Value::Map(
-            v.into_iter()
-                .map(|(k, v)| (value::Value::String(k), value_from_avro(v)))
-                .collect(),
-        ),
-        Value::Record(v) => value::Value::Map(
-            v.into_iter()
-                .map(|(k, v)| (value::Value::String(k), value_from_avro(v)))
-                .collect(),
-        ),
+        Value::Bytes(v) | Value::Fixed(_, v) => logical.decode_bytes(v),
+        Value::String(v) => value::Value::String(v),
+        Value::Enum(i, v) => match (enum_style, schema) {
+            (value::enums::Style::Number, _) => value::Value::I32(i),
+            (value::enums::Style::Qualified, Some(Schema::Enum { name, .. })) => {
+                value::Value::String(format!("{}.{}", name.fullname(namespace), v))
+            }
+            _ => value::Value::String(v),
+        },
+        Value::Union(boxed) => {
+            let (schema, logical) = match schema {
+                Some(Schema::Union(union)) => match union.find_schema(&boxed) {
+                    Some((i, s)) => (Some(s), logical.variant(i)),
+                    None => (None, &Logical::None),
+                },
+                _ => (None, &Logical::None),
+            };
+            value_from_avro(*boxed, schema, namespace, enum_style, logical)
+        }
+        Value::Array(v) => {
+            let items = match schema {
+                Some(Schema::Array(items)) => Some(&**items),
+                _ => None,
+            };
+            value::Value::Sequence(
+                v.into_iter()
+                    .map(|v| value_from_avro(v, items, namespace, enum_style, logical.inner()))
+                    .collect(),
+            )
+        }
+        Value::Map(v) => {
+            let values = match schema {
+                Some(Schema::Map(values)) => Some(&**values),
+                _ => None,
+            };
+            value::Value::Map(
+                v.into_iter()
+                    .map(|(k, v)| {
+                        let v = value_from_avro(v, values, namespace, enum_style, logical.inner());
+                        (value::Value::String(k), v)
+                    })
+                    .collect(),
+            )
+        }
+        Value::Record(v) => {
+            let (fullname, fields) = match schema {
+                Some(Schema::Record {
+                    name,
+                    fields,
+                    lookup,
+                    ..
+                }) => (Some(name.fullname(namespace)), Some((fields, lookup))),
+                _ => (None, None),
+            };
+            // Named types nested in a record default to the namespace of the record
+            let namespace = match fullname {
+                Some(ref fullname) => fullname.rfind('.').map(|i| &fullname[..i]),
+                None => namespace,
+            };
+            value::Value::Map(
+                v.into_iter()
+                    .map(|(k, v)| {
+                        let schema = fields.and_then(|(fields, lookup)| {
+                            lookup.get(&k).map(|&i| &fields[i].schema)
+                        });
+                        let v =
+                            value_from_avro(v, schema, namespace, enum_style, logical.field(&k));
+                        (value::Value::String(k), v)
+                    })
+                    .collect(),
+            )
+        }
     }
 }
 
@@ -77,13 +1175,134 @@ where
 {
     #[inline]
     fn write(&mut self, value: value::Value) -> error::Result<()> {
-        self.0
-            .append(value_to_avro(value)?)
+        let datum = avro_rs::to_avro_datum(self.schema, value_to_avro(value)?)
+            .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?;
+        self.block.extend(datum);
+        self.block_records += 1;
+
+        if self.block.len() >= self.options.block_size
+            || Some(self.block_records) == self.options.block_records
+        {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W> Sink<'a, W>
+where
+    W: io::Write,
+{
+    /// Writes the current block, if there is one.
+    pub fn flush(&mut self) -> error::Result<()> {
+        if !self.has_header {
+            self.write_header()?;
+        }
+        if self.block_records == 0 {
+            return Ok(());
+        }
+
+        let mut block = std::mem::take(&mut self.block);
+        self.options
+            .codec
+            .compress(&mut block)
             .map_err(|e| error::Error::Avro(error::Avro::downcast(e)))?;
+
+        let mut header = Vec::new();
+        encode_long(self.block_records as i64, &mut header);
+        encode_long(block.len() as i64, &mut header);
+        self.w.write_all(&header)?;
+        self.w.write_all(&block)?;
+        self.w.write_all(&self.marker)?;
+        self.w.flush()?;
+
+        self.block_records = 0;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> error::Result<()> {
+        let schema = define_once(
+            serde_json::to_value(self.schema)?,
+            None,
+            &mut collections::HashSet::new(),
+        )?;
+        let schema = serde_json::to_string(&schema)?.into_bytes();
+        let codec = match avro_rs::types::ToAvro::avro(self.options.codec) {
+            avro_rs::types::Value::Bytes(codec) => codec,
+            _ => unreachable!(),
+        };
+
+        let mut metadata = vec![
+            (&b"avro.schema"[..], schema.as_slice()),
+            (&b"avro.codec"[..], codec.as_slice()),
+        ];
+        metadata.extend(
+            self.options
+                .metadata
+                .iter()
+                .map(|(k, v)| (k.as_bytes(), v.as_slice())),
+        );
+
+        let header = encode_header(&metadata, &self.marker);
+        self.w.write_all(&header)?;
+        self.has_header = true;
         Ok(())
     }
 }
 
+fn encode_header(metadata: &[(&[u8], &[u8])], marker: &[u8; 16]) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    encode_long(metadata.len() as i64, &mut header);
+    for (k, v) in metadata {
+        encode_bytes(k, &mut header);
+        encode_bytes(v, &mut header);
+    }
+    encode_long(0, &mut header);
+    header.extend_from_slice(marker);
+    header
+}
+
+fn encode_long(n: i64, buf: &mut Vec<u8>) {
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    encode_long(bytes.len() as i64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+fn sync_marker(seed: Option<u64>) -> [u8; 16] {
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut marker = [0; 16];
+    match seed {
+        Some(mut state) => {
+            // SplitMix64, which mixes even small seeds into well-distributed markers
+            for chunk in marker.chunks_mut(8) {
+                state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                chunk.copy_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+            }
+        }
+        None => {
+            // RandomState is randomly seeded, which is plenty for a marker that only has to be
+            // unlikely to occur in the data
+            for chunk in marker.chunks_mut(8) {
+                let hasher = collections::hash_map::RandomState::new().build_hasher();
+                chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+            }
+        }
+    }
+    marker
+}
+
 fn value_to_avro(value: value::Value) -> error::Result<avro_rs::types::Value> {
     use avro_rs::types::Value;
     use std::convert::TryFrom;
@@ -150,7 +1369,30 @@ where
     R: io::Read,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("AvroSource").finish()
+        f.debug_struct("AvroSource")
+            .field("enum_style", &self.1)
+            .finish()
+    }
+}
+
+impl<'a, R> fmt::Debug for RawSource<'a, R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AvroRawSource")
+            .field("single_object", &self.single_object)
+            .field("enum_style", &self.enum_style)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Container {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AvroContainer")
+            .field("schema", &self.schema)
+            .field("codec", &self.codec)
+            .finish()
     }
 }
 
@@ -159,7 +1401,9 @@ where
     W: io::Write,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("AvroSink").finish()
+        f.debug_struct("AvroSink")
+            .field("options", &self.options)
+            .finish()
     }
 }
 
@@ -168,9 +1412,541 @@ where
     W: io::Write,
 {
     fn drop(&mut self) {
-        match self.0.flush() {
+        match self.flush() {
             Ok(_) => (),
             Err(error) => panic!("{}", error),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ADDRESS: &str = r#"{
+        "type": "record",
+        "name": "Address",
+        "namespace": "com.example",
+        "fields": [
+            {"name": "street", "type": "string"},
+            {"name": "kind", "type": {"type": "enum", "name": "Kind", "symbols": ["HOME", "WORK"]}}
+        ]
+    }"#;
+
+    #[test]
+    fn test_registry_cross_file_reference() {
+        let mut registry = SchemaRegistry::new();
+        registry.add_json(ADDRESS).unwrap();
+        let schema = registry
+            .parse_str(
+                r#"{
+                    "type": "record",
+                    "name": "Person",
+                    "namespace": "com.example",
+                    "fields": [
+                        {"name": "home", "type": "Address"},
+                        {"name": "work", "type": ["null", "com.example.Address"]},
+                        {"name": "kinds", "type": {"type": "array", "items": "Kind"}}
+                    ]
+                }"#,
+            )
+            .unwrap();
+
+        let expected = avro_rs::Schema::parse_str(&format!(
+            r#"{{
+                "type": "record",
+                "name": "Person",
+                "namespace": "com.example",
+                "fields": [
+                    {{"name": "home", "type": {address}}},
+                    {{"name": "work", "type": ["null", {address}]}},
+                    {{"name": "kinds", "type": {{
+                        "type": "array",
+                        "items": {{
+                            "type": "enum",
+                            "name": "Kind",
+                            "namespace": "com.example",
+                            "symbols": ["HOME", "WORK"]
+                        }}
+                    }}}}
+                ]
+            }}"#,
+            address = ADDRESS
+        ))
+        .unwrap();
+        assert_eq!(schema, expected);
+    }
+
+    /// Checks a schema like the Java implementation does, which rejects names that are defined
+    /// twice and names that aren't defined before they are used.
+    fn parse_strict(
+        schema: &serde_json::Value,
+        namespace: Option<&str>,
+        defined: &mut collections::HashSet<String>,
+    ) -> Result<(), String> {
+        match *schema {
+            serde_json::Value::String(ref name) if !PRIMITIVE_TYPES.contains(&name.as_str()) => {
+                let qualified = match namespace {
+                    Some(namespace) if !name.contains('.') => format!("{}.{}", namespace, name),
+                    _ => name.clone(),
+                };
+                if defined.contains(&qualified) {
+                    Ok(())
+                } else {
+                    Err(format!("Undefined name: {}", qualified))
+                }
+            }
+            serde_json::Value::Array(ref variants) => variants
+                .iter()
+                .try_for_each(|v| parse_strict(v, namespace, defined)),
+            serde_json::Value::Object(ref fields) => match fields["type"].as_str() {
+                Some("record") | Some("enum") | Some("fixed") => {
+                    let (name, namespace) = full_name(fields, namespace).unwrap();
+                    if !defined.insert(name.clone()) {
+                        return Err(format!("Can't redefine: {}", name));
+                    }
+                    fields
+                        .get("fields")
+                        .and_then(|f| f.as_array())
+                        .into_iter()
+                        .flatten()
+                        .try_for_each(|f| parse_strict(&f["type"], namespace.as_deref(), defined))
+                }
+                Some("array") => parse_strict(&fields["items"], namespace, defined),
+                Some("map") => parse_strict(&fields["values"], namespace, defined),
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    #[test]
+    fn test_sink_defines_names_once() {
+        use crate::value::Source as _;
+
+        let mut registry = SchemaRegistry::new();
+        registry.add_json(ADDRESS).unwrap();
+        let schema = registry
+            .parse_str(
+                r#"{
+                    "type": "record",
+                    "name": "Person",
+                    "namespace": "com.example",
+                    "fields": [
+                        {"name": "home", "type": "Address"},
+                        {"name": "work", "type": ["null", "com.example.Address"]},
+                        {"name": "kinds", "type": {"type": "array", "items": "Kind"}}
+                    ]
+                }"#,
+            )
+            .unwrap();
+
+        let mut out = Vec::new();
+        sink(&schema, &mut out, avro_rs::Codec::Null)
+            .unwrap()
+            .flush()
+            .unwrap();
+
+        let (_, metadata) = read_header(&mut out.as_slice()).unwrap();
+        let (_, json) = metadata
+            .into_iter()
+            .find(|(k, _)| k == b"avro.schema")
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        parse_strict(&json, None, &mut collections::HashSet::new()).unwrap();
+        assert_eq!(
+            parse_strict(
+                &serde_json::to_value(&schema).unwrap(),
+                None,
+                &mut collections::HashSet::new()
+            ),
+            Err("Can't redefine: com.example.Address".to_owned())
+        );
+        assert_eq!(
+            json["fields"][1]["type"],
+            serde_json::json!(["null", "com.example.Address"])
+        );
+        assert_eq!(json["fields"][2]["type"]["items"], "com.example.Kind");
+
+        // The file can still be read, and appended to
+        let mut source = source(out.as_slice()).unwrap();
+        assert_eq!(*source.writer_schema(), schema);
+        assert_eq!(source.read().unwrap(), None);
+        assert_eq!(read_container(out.as_slice()).unwrap().schema, schema);
+    }
+
+    #[test]
+    fn test_registry_unknown_reference() {
+        let mut registry = SchemaRegistry::new();
+        let result = registry.parse_str(r#"{"type": "array", "items": "com.example.Missing"}"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_registry_recursive_reference() {
+        let mut registry = SchemaRegistry::new();
+        let result = registry.parse_str(
+            r#"{
+                "type": "record",
+                "name": "List",
+                "fields": [{"name": "next", "type": ["null", "List"]}]
+            }"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_registry_conflicting_definitions() {
+        let mut registry = SchemaRegistry::new();
+        registry.add_json(ADDRESS).unwrap();
+        let result = registry.add_json(
+            r#"{"type": "fixed", "name": "Address", "namespace": "com.example", "size": 4}"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_sink_options() {
+        use crate::value::Sink as _;
+
+        let schema = avro_rs::Schema::parse_str(r#""long""#).unwrap();
+        let options = SinkOptions {
+            codec: avro_rs::Codec::Deflate,
+            block_records: Some(2),
+            metadata: vec![("origin".to_owned(), b"rq".to_vec())],
+            ..SinkOptions::default()
+        };
+
+        let mut out = Vec::new();
+        {
+            let mut sink = sink_with_options(&schema, &mut out, options).unwrap();
+            for i in 0..5 {
+                sink.write(value::Value::I64(i)).unwrap();
+            }
+        }
+
+        let marker = &out[out.len() - 16..];
+        let blocks = out.windows(16).filter(|w| *w == marker).count() - 1;
+        assert_eq!(blocks, 3);
+        assert!(out.windows(7).any(|w| w == b"\x0corigin"));
+
+        let values = avro_rs::Reader::new(out.as_slice())
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            (0..5).map(avro_rs::types::Value::Long).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_sink_seed() {
+        use crate::value::Sink as _;
+
+        let schema = avro_rs::Schema::parse_str(r#""long""#).unwrap();
+        let write = |seed| {
+            let options = SinkOptions {
+                seed,
+                ..SinkOptions::default()
+            };
+            let mut out = Vec::new();
+            {
+                let mut sink = sink_with_options(&schema, &mut out, options).unwrap();
+                sink.write(value::Value::I64(1)).unwrap();
+            }
+            out
+        };
+
+        assert_eq!(write(Some(1)), write(Some(1)));
+        assert_ne!(write(Some(1)), write(Some(2)));
+        assert_ne!(write(None), write(None));
+    }
+
+    #[test]
+    fn test_source_enum_style() {
+        use crate::value::Sink as _;
+        use crate::value::Source as _;
+
+        let schema = avro_rs::Schema::parse_str(ADDRESS).unwrap();
+        let mut out = Vec::new();
+        {
+            let mut sink = sink(&schema, &mut out, avro_rs::Codec::Null).unwrap();
+            sink.write(value::Value::Map(vec![
+                (
+                    value::Value::String("street".to_owned()),
+                    value::Value::String("Main".to_owned()),
+                ),
+                (
+                    value::Value::String("kind".to_owned()),
+                    value::Value::String("WORK".to_owned()),
+                ),
+            ]))
+            .unwrap();
+        }
+
+        let read = |enum_style| {
+            let mut source = source(out.as_slice()).unwrap().enum_style(enum_style);
+            match source.read().unwrap() {
+                Some(value::Value::Map(entries)) => entries[1].1.clone(),
+                v => panic!("unexpected record {:?}", v),
+            }
+        };
+        let s = |v: &str| value::Value::String(v.to_owned());
+        assert_eq!(read(value::enums::Style::Symbol), s("WORK"));
+        assert_eq!(
+            read(value::enums::Style::Qualified),
+            s("com.example.Kind.WORK")
+        );
+        assert_eq!(read(value::enums::Style::Number), value::Value::I32(1));
+    }
+
+    #[test]
+    fn test_source_with_reader_schema() {
+        use crate::value::Sink as _;
+        use crate::value::Source as _;
+
+        let writer_schema = avro_rs::Schema::parse_str(
+            r#"{"type": "record", "name": "Point", "fields": [
+                {"name": "y", "type": "int"},
+                {"name": "x", "type": "int"}
+            ]}"#,
+        )
+        .unwrap();
+        let reader_schema = avro_rs::Schema::parse_str(
+            r#"{"type": "record", "name": "Point", "fields": [
+                {"name": "x", "type": "long"},
+                {"name": "y", "type": "double"},
+                {"name": "label", "type": "string", "default": "none"}
+            ]}"#,
+        )
+        .unwrap();
+        let s = |v: &str| value::Value::String(v.to_owned());
+
+        let mut out = Vec::new();
+        {
+            let mut sink = sink(&writer_schema, &mut out, avro_rs::Codec::Null).unwrap();
+            sink.write(value::Value::Map(vec![
+                (s("y"), value::Value::I32(2)),
+                (s("x"), value::Value::I32(1)),
+            ]))
+            .unwrap();
+        }
+
+        let mut source = source_with_reader_schema(out.as_slice(), &reader_schema).unwrap();
+        assert_eq!(
+            source.read().unwrap(),
+            Some(value::Value::Map(vec![
+                (s("x"), value::Value::I64(1)),
+                (s("y"), value::Value::F64(2.0.into())),
+                (s("label"), s("none")),
+            ]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_source_logical_types() {
+        use crate::value::Source as _;
+
+        let json = r#"{"type": "record", "name": "Payment", "fields": [
+            {"name": "day", "type": {"type": "int", "logicalType": "date"}},
+            {"name": "at", "type": ["null", {"type": "long", "logicalType": "timestamp-millis"}]},
+            {"name": "time", "type": {"type": "long", "logicalType": "time-micros"}},
+            {"name": "amount", "type":
+                {"type": "bytes", "logicalType": "decimal", "precision": 9, "scale": 2}},
+            {"name": "ids", "type": {"type": "array", "items":
+                {"type": "string", "logicalType": "uuid"}}},
+            {"name": "count", "type": "long"}
+        ]}"#;
+        let schema = avro_rs::Schema::parse_str(json).unwrap();
+
+        let mut record = avro_rs::types::Record::new(&schema).unwrap();
+        record.put("day", 13_000);
+        record.put(
+            "at",
+            avro_rs::types::Value::Union(Box::new(avro_rs::types::Value::Long(1_234_567_890_123))),
+        );
+        record.put("time", 3_723_000_004i64);
+        record.put("amount", avro_rs::types::Value::Bytes(vec![0xfe, 0x0c]));
+        record.put(
+            "ids",
+            avro_rs::types::Value::Array(vec![avro_rs::types::Value::String("9f1c".to_owned())]),
+        );
+        record.put("count", 7i64);
+        let datum = avro_rs::to_avro_datum(&schema, record).unwrap();
+
+        // A container with the original JSON schema, since the sink doesn't keep logical types
+        let mut container = MAGIC.to_vec();
+        encode_long(1, &mut container);
+        encode_bytes(b"avro.schema", &mut container);
+        encode_bytes(json.as_bytes(), &mut container);
+        encode_long(0, &mut container);
+        container.extend_from_slice(&[7; 16]);
+        encode_long(1, &mut container);
+        encode_bytes(&datum, &mut container);
+        container.extend_from_slice(&[7; 16]);
+
+        let s = |v: &str| value::Value::String(v.to_owned());
+        let mut source = source(container.as_slice()).unwrap();
+        assert_eq!(
+            source.read().unwrap(),
+            Some(value::Value::Map(vec![
+                (s("day"), s("2005-08-05")),
+                (s("at"), s("2009-02-13T23:31:30.123Z")),
+                (s("time"), s("01:02:03.000004")),
+                (s("amount"), s("-5.00")),
+                (s("ids"), value::Value::Sequence(vec![s("9f1c")])),
+                (s("count"), value::Value::I64(7)),
+            ]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+
+        let mut source = super::source(container.as_slice())
+            .unwrap()
+            .logical_types(LogicalTypes::default());
+        match source.read().unwrap() {
+            Some(value::Value::Map(entries)) => {
+                assert_eq!(entries[0].1, value::Value::I32(13_000));
+            }
+            v => panic!("unexpected record {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(decimal(12345, 2), "123.45");
+        assert_eq!(decimal(-5, 3), "-0.005");
+        assert_eq!(decimal(42, 0), "42");
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        // From the test cases of the Avro specification's reference implementation
+        assert_eq!(fingerprint(br#""int""#), 0x7275_d51a_3f39_5c8f);
+    }
+
+    #[test]
+    fn test_raw_source() {
+        use crate::value::Source as _;
+
+        let schema = avro_rs::Schema::parse_str(r#""long""#).unwrap();
+        let datum = |n: i64| avro_rs::to_avro_datum(&schema, n).unwrap();
+
+        let bare = [datum(1), datum(-2)].concat();
+        let mut source = raw_source(bare.as_slice(), &schema);
+        assert_eq!(source.read().unwrap(), Some(value::Value::I64(1)));
+        assert_eq!(source.read().unwrap(), Some(value::Value::I64(-2)));
+        assert_eq!(source.read().unwrap(), None);
+
+        let header = [
+            SINGLE_OBJECT_MAGIC,
+            &fingerprint(br#""long""#).to_le_bytes(),
+        ]
+        .concat();
+        let framed = [header.clone(), datum(3), header, datum(4)].concat();
+        let mut source = raw_source(framed.as_slice(), &schema);
+        assert_eq!(source.read().unwrap(), Some(value::Value::I64(3)));
+        assert_eq!(source.read().unwrap(), Some(value::Value::I64(4)));
+        assert_eq!(source.read().unwrap(), None);
+
+        let other = avro_rs::Schema::parse_str(r#""int""#).unwrap();
+        let mut source = raw_source(framed.as_slice(), &other);
+        assert!(source.read().is_err());
+
+        // -98 is encoded as the single-object magic
+        let bare = [datum(-98), datum(0)].concat();
+        let mut source = raw_source(bare.as_slice(), &schema).single_object(false);
+        assert_eq!(source.read().unwrap(), Some(value::Value::I64(-98)));
+        assert_eq!(source.read().unwrap(), Some(value::Value::I64(0)));
+    }
+
+    #[test]
+    fn test_raw_source_defaults() {
+        use crate::value::Source as _;
+
+        let writer = avro_rs::Schema::parse_str(
+            r#"{"type": "record", "name": "r", "fields": [{"name": "a", "type": "int"}]}"#,
+        )
+        .unwrap();
+        let reader = avro_rs::Schema::parse_str(
+            r#"{"type": "record", "name": "r", "fields": [
+                {"name": "a", "type": "long"},
+                {"name": "b", "type": "string", "default": "x"},
+                {"name": "c", "type": ["null", "int"], "default": null}
+            ]}"#,
+        )
+        .unwrap();
+        let datum = avro_rs::to_avro_datum(
+            &writer,
+            avro_rs::types::Value::Record(vec![("a".to_owned(), avro_rs::types::Value::Int(1))]),
+        )
+        .unwrap();
+
+        let s = |v: &str| value::Value::String(v.to_owned());
+        let mut source = raw_source(datum.as_slice(), &writer).reader_schema(&reader);
+        assert_eq!(
+            source.read().unwrap(),
+            Some(value::Value::Map(vec![
+                (s("a"), value::Value::I64(1)),
+                (s("b"), s("x")),
+                (s("c"), value::Value::Unit),
+            ]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_append_sink() {
+        use crate::value::Sink as _;
+
+        let schema = avro_rs::Schema::parse_str(r#""long""#).unwrap();
+        let options = SinkOptions {
+            codec: avro_rs::Codec::Deflate,
+            ..SinkOptions::default()
+        };
+        let mut out = Vec::new();
+        {
+            let mut sink = sink_with_options(&schema, &mut out, options).unwrap();
+            sink.write(value::Value::I64(1)).unwrap();
+        }
+
+        let container = read_container(out.as_slice()).unwrap();
+        assert_eq!(container.schema.canonical_form(), schema.canonical_form());
+        assert_eq!(container.codec, avro_rs::Codec::Deflate);
+        for i in 2..4 {
+            let mut sink = append_sink(&container, &mut out, SinkOptions::default()).unwrap();
+            sink.write(value::Value::I64(i)).unwrap();
+        }
+        // Nothing is written without records
+        let len = out.len();
+        drop(append_sink(&container, &mut out, SinkOptions::default()).unwrap());
+        assert_eq!(out.len(), len);
+
+        let values = avro_rs::Reader::new(out.as_slice())
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            (1..4).map(avro_rs::types::Value::Long).collect::<Vec<_>>()
+        );
+
+        let options = SinkOptions {
+            metadata: vec![("origin".to_owned(), b"rq".to_vec())],
+            ..SinkOptions::default()
+        };
+        assert!(append_sink(&container, Vec::new(), options).is_err());
+        assert!(read_container(&b"{}"[..]).is_err());
+    }
+
+    #[test]
+    fn test_sink_reserved_metadata() {
+        let schema = avro_rs::Schema::parse_str(r#""long""#).unwrap();
+        let options = SinkOptions {
+            metadata: vec![("avro.codec".to_owned(), b"null".to_vec())],
+            ..SinkOptions::default()
+        };
+        assert!(sink_with_options(&schema, Vec::new(), options).is_err());
+    }
+}
@@ -0,0 +1,851 @@
+use crate::error;
+use crate::value;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+/// A source that reads a stream of BSON documents, like the `.bson` files written by
+/// `mongodump`, yielding each document as a map.
+///
+/// BSON types that have no counterpart among values are represented like in relaxed MongoDB
+/// Extended JSON, so object IDs become `{"$oid": "..."}`, dates `{"$date": "..."}`, decimals
+/// `{"$numberDecimal": "..."}` and so on.  Binary data of any subtype becomes bytes.
+pub struct Source<R>(R)
+where
+    R: io::Read;
+
+/// A sink that writes records, which must be maps with string keys, as a stream of BSON
+/// documents.
+///
+/// Maps in the Extended JSON representations that the source produces are written as the BSON
+/// types they represent, so that documents survive a round trip.  Unsigned integers that don't
+/// fit in an `int64` are written as `decimal128`.
+pub struct Sink<W>(W)
+where
+    W: io::Write;
+
+const DOUBLE: u8 = 0x01;
+const STRING: u8 = 0x02;
+const DOCUMENT: u8 = 0x03;
+const ARRAY: u8 = 0x04;
+const BINARY: u8 = 0x05;
+const UNDEFINED: u8 = 0x06;
+const OBJECT_ID: u8 = 0x07;
+const BOOLEAN: u8 = 0x08;
+const DATETIME: u8 = 0x09;
+const NULL: u8 = 0x0a;
+const REGEX: u8 = 0x0b;
+const DB_POINTER: u8 = 0x0c;
+const CODE: u8 = 0x0d;
+const SYMBOL: u8 = 0x0e;
+const CODE_WITH_SCOPE: u8 = 0x0f;
+const INT32: u8 = 0x10;
+const TIMESTAMP: u8 = 0x11;
+const INT64: u8 = 0x12;
+const DECIMAL128: u8 = 0x13;
+const MIN_KEY: u8 = 0xff;
+const MAX_KEY: u8 = 0x7f;
+
+/// How deeply documents may be nested, like in MongoDB itself.
+const MAX_DEPTH: usize = 100;
+
+const DECIMAL128_BIAS: i64 = 6176;
+const DECIMAL128_MAX_COEFFICIENT: u128 = 9_999_999_999_999_999_999_999_999_999_999;
+
+#[inline]
+pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    Source(r)
+}
+
+#[inline]
+pub fn sink<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(w)
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let mut header = [0; 4];
+        if !value::read_or_eof(&mut self.0, &mut header, truncated)? {
+            return Ok(None);
+        }
+        let length = i32::from_le_bytes(header);
+        if length < 5 {
+            return Err(invalid(format!("document length {}", length)));
+        }
+
+        let mut data = header.to_vec();
+        io::Read::read_to_end(
+            &mut io::Read::take(&mut self.0, length as u64 - 4),
+            &mut data,
+        )?;
+        if data.len() < length as usize {
+            return Err(truncated());
+        }
+
+        let entries = Reader {
+            data: &data,
+            pos: 0,
+        }
+        .document(0)?;
+        Ok(Some(value::Value::Map(entries)))
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        let entries = match v {
+            value::Value::Map(entries) => entries,
+            v => {
+                return Err(error::Error::Format {
+                    msg: format!("BSON can only output records that are maps, not {}", v),
+                })
+            }
+        };
+        let mut out = Vec::new();
+        write_document(&entries, &mut out)?;
+        self.0.write_all(&out)?;
+        Ok(())
+    }
+}
+
+fn truncated() -> error::Error {
+    error::Error::Format {
+        msg: "truncated BSON document".to_owned(),
+    }
+}
+
+fn invalid(what: String) -> error::Error {
+    error::Error::Format {
+        msg: format!("invalid BSON: {}", what),
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> error::Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).ok_or_else(truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or_else(truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> error::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i32(&mut self) -> error::Result<i32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> error::Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn length(&mut self) -> error::Result<usize> {
+        let length = self.i32()?;
+        usize::try_from(length).map_err(|_| invalid(format!("length {}", length)))
+    }
+
+    fn cstring(&mut self) -> error::Result<String> {
+        let rest = &self.data[self.pos..];
+        let end = rest.iter().position(|&b| b == 0).ok_or_else(truncated)?;
+        self.pos += end + 1;
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+
+    fn string(&mut self) -> error::Result<String> {
+        let length = self.length()?;
+        let bytes = self.bytes(length)?;
+        match bytes.split_last() {
+            Some((0, s)) => Ok(String::from_utf8_lossy(s).into_owned()),
+            _ => Err(invalid("string without a terminating null".to_owned())),
+        }
+    }
+
+    fn document(&mut self, depth: usize) -> error::Result<Vec<(value::Value, value::Value)>> {
+        if depth > MAX_DEPTH {
+            return Err(invalid(format!(
+                "documents nested more than {} levels deep",
+                MAX_DEPTH
+            )));
+        }
+        let start = self.pos;
+        let length = self.length()?;
+        let mut entries = Vec::new();
+        loop {
+            let kind = self.byte()?;
+            if kind == 0 {
+                break;
+            }
+            let key = self.cstring()?;
+            let value = self.element(kind, depth)?;
+            entries.push((value::Value::String(key), value));
+        }
+        if self.pos - start != length {
+            return Err(invalid(format!(
+                "document length {} but {} bytes of elements",
+                length,
+                self.pos - start
+            )));
+        }
+        Ok(entries)
+    }
+
+    fn element(&mut self, kind: u8, depth: usize) -> error::Result<value::Value> {
+        use crate::value::Value;
+
+        Ok(match kind {
+            DOUBLE => Value::from_f64(f64::from_bits(self.u64()?)),
+            STRING => Value::String(self.string()?),
+            DOCUMENT => Value::Map(self.document(depth + 1)?),
+            ARRAY => Value::Sequence(
+                self.document(depth + 1)?
+                    .into_iter()
+                    .map(|(_, v)| v)
+                    .collect(),
+            ),
+            BINARY => {
+                let length = self.length()?;
+                let subtype = self.byte()?;
+                let mut bytes = self.bytes(length)?;
+                // The old binary subtype repeats the length inside of the data
+                if subtype == 0x02 && bytes.len() >= 4 {
+                    bytes = &bytes[4..];
+                }
+                Value::Bytes(bytes.to_vec())
+            }
+            UNDEFINED | NULL => Value::Unit,
+            OBJECT_ID => extended("$oid", object_id(self.bytes(12)?)),
+            BOOLEAN => match self.byte()? {
+                0 => Value::Bool(false),
+                1 => Value::Bool(true),
+                b => return Err(invalid(format!("boolean {}", b))),
+            },
+            DATETIME => extended("$date", date(self.u64()? as i64)),
+            REGEX => {
+                let pattern = self.cstring()?;
+                let options = self.cstring()?;
+                extended(
+                    "$regularExpression",
+                    Value::Map(vec![
+                        (string("pattern"), Value::String(pattern)),
+                        (string("options"), Value::String(options)),
+                    ]),
+                )
+            }
+            DB_POINTER => {
+                let namespace = self.string()?;
+                let id = extended("$oid", object_id(self.bytes(12)?));
+                extended(
+                    "$dbPointer",
+                    Value::Map(vec![
+                        (string("$ref"), Value::String(namespace)),
+                        (string("$id"), id),
+                    ]),
+                )
+            }
+            CODE => extended("$code", Value::String(self.string()?)),
+            SYMBOL => extended("$symbol", Value::String(self.string()?)),
+            CODE_WITH_SCOPE => {
+                self.i32()?;
+                let code = self.string()?;
+                let scope = self.document(depth + 1)?;
+                Value::Map(vec![
+                    (string("$code"), Value::String(code)),
+                    (string("$scope"), Value::Map(scope)),
+                ])
+            }
+            INT32 => Value::I32(self.i32()?),
+            TIMESTAMP => {
+                let timestamp = self.u64()?;
+                extended(
+                    "$timestamp",
+                    Value::Map(vec![
+                        (string("t"), Value::U32((timestamp >> 32) as u32)),
+                        (string("i"), Value::U32(timestamp as u32)),
+                    ]),
+                )
+            }
+            INT64 => Value::I64(self.u64()? as i64),
+            DECIMAL128 => {
+                let low = self.u64()?;
+                let high = self.u64()?;
+                extended("$numberDecimal", Value::String(decimal128(low, high)))
+            }
+            MIN_KEY => extended("$minKey", Value::I32(1)),
+            MAX_KEY => extended("$maxKey", Value::I32(1)),
+            kind => return Err(invalid(format!("unknown element type 0x{:02x}", kind))),
+        })
+    }
+}
+
+fn string(s: &str) -> value::Value {
+    value::Value::String(s.to_owned())
+}
+
+fn extended(key: &str, v: value::Value) -> value::Value {
+    value::Value::Map(vec![(string(key), v)])
+}
+
+fn object_id(bytes: &[u8]) -> value::Value {
+    value::Value::String(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Represents milliseconds since the Unix epoch as an RFC 3339 string for years that have four
+/// digits, and as a `$numberLong` otherwise.
+fn date(millis: i64) -> value::Value {
+    const MIN: i64 = -62_167_219_200_000; // 0000-01-01T00:00:00Z
+    const MAX: i64 = 253_402_300_799_999; // 9999-12-31T23:59:59.999Z
+
+    if (MIN..=MAX).contains(&millis) {
+        value::Value::String(value::timestamp::rfc3339_nanos(
+            millis.div_euclid(1000),
+            millis.rem_euclid(1000) as u32 * 1_000_000,
+        ))
+    } else {
+        extended("$numberLong", value::Value::String(millis.to_string()))
+    }
+}
+
+/// Formats a `decimal128` with the to-scientific-string rules of the General Decimal
+/// Arithmetic specification, like MongoDB does.
+fn decimal128(low: u64, high: u64) -> String {
+    let sign = if high >> 63 == 1 { "-" } else { "" };
+    let (exponent, coefficient) = if (high >> 61) & 0b11 == 0b11 {
+        match (high >> 58) & 0b11111 {
+            0b11111 => return "NaN".to_owned(),
+            0b11110 => return format!("{}Infinity", sign),
+            // The implied coefficient is always too large, so it counts as zero
+            _ => ((high >> 47) & 0x3fff, 0),
+        }
+    } else {
+        (
+            (high >> 49) & 0x3fff,
+            u128::from(high & 0x1_ffff_ffff_ffff) << 64 | u128::from(low),
+        )
+    };
+    let exponent = exponent as i64 - DECIMAL128_BIAS;
+    let coefficient = if coefficient > DECIMAL128_MAX_COEFFICIENT {
+        0
+    } else {
+        coefficient
+    };
+
+    let digits = coefficient.to_string();
+    let adjusted = exponent + digits.len() as i64 - 1;
+    if exponent <= 0 && adjusted >= -6 {
+        let point = digits.len() as i64 + exponent;
+        if exponent == 0 {
+            format!("{}{}", sign, digits)
+        } else if point > 0 {
+            let (int, frac) = digits.split_at(point as usize);
+            format!("{}{}.{}", sign, int, frac)
+        } else {
+            format!("{}0.{}{}", sign, "0".repeat(-point as usize), digits)
+        }
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        format!(
+            "{}{}{}{}E{}{}",
+            sign,
+            first,
+            point,
+            rest,
+            if adjusted < 0 { "-" } else { "+" },
+            adjusted.abs()
+        )
+    }
+}
+
+/// Parses a decimal number like `-1.5E+3`, `NaN` or `Infinity` into the low and high halves of
+/// a `decimal128`, or returns `None` if it isn't one or can't be represented exactly.
+fn parse_decimal128(s: &str) -> Option<(u64, u64)> {
+    let (sign, s) = match s.strip_prefix('-') {
+        Some(s) => (1 << 63, s),
+        None => (0, s.strip_prefix('+').unwrap_or(s)),
+    };
+    match s {
+        "NaN" => return Some((0, 0x7c00 << 48)),
+        "Infinity" | "Inf" => return Some((0, sign | 0x7800 << 48)),
+        _ => (),
+    }
+
+    let (mantissa, exponent) = match s.find(|c| c == 'e' || c == 'E') {
+        Some(i) => (&s[..i], s[i + 1..].parse::<i64>().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    if (int.is_empty() && frac.is_empty())
+        || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let digits = format!("{}{}", int, frac);
+    let digits = digits.trim_start_matches('0');
+    let coefficient = if digits.is_empty() {
+        0
+    } else if digits.len() > 34 {
+        return None;
+    } else {
+        digits.parse::<u128>().ok()?
+    };
+    let exponent = exponent.checked_sub(frac.len() as i64)? + DECIMAL128_BIAS;
+    if !(0..=0x2fff).contains(&exponent) {
+        return None;
+    }
+    Some((
+        coefficient as u64,
+        sign | (exponent as u64) << 49 | (coefficient >> 64) as u64,
+    ))
+}
+
+fn write_document(
+    entries: &[(value::Value, value::Value)],
+    out: &mut Vec<u8>,
+) -> error::Result<()> {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    for (k, v) in entries {
+        let key = match *k {
+            value::Value::String(ref s) => s,
+            ref k => {
+                return Err(error::Error::Format {
+                    msg: format!("BSON document keys must be strings, not {}", k),
+                })
+            }
+        };
+        write_element(key, v, out)?;
+    }
+    out.push(0);
+    finish_length(start, out)
+}
+
+fn write_element(key: &str, v: &value::Value, out: &mut Vec<u8>) -> error::Result<()> {
+    let kind = out.len();
+    out.push(0);
+    write_cstring(key, out)?;
+    out[kind] = write_value(v, out)?;
+    Ok(())
+}
+
+/// Writes the value, returning its element type.
+fn write_value(v: &value::Value, out: &mut Vec<u8>) -> error::Result<u8> {
+    use crate::value::Value;
+
+    let int32 = |n: i32, out: &mut Vec<u8>| {
+        out.extend_from_slice(&n.to_le_bytes());
+        INT32
+    };
+    let int64 = |n: i64, out: &mut Vec<u8>| {
+        out.extend_from_slice(&n.to_le_bytes());
+        INT64
+    };
+
+    Ok(match *v {
+        Value::Unit => NULL,
+        Value::Bool(b) => {
+            out.push(b as u8);
+            BOOLEAN
+        }
+        Value::I8(n) => int32(i32::from(n), out),
+        Value::I16(n) => int32(i32::from(n), out),
+        Value::I32(n) => int32(n, out),
+        Value::I64(n) => int64(n, out),
+        Value::U8(n) => int32(i32::from(n), out),
+        Value::U16(n) => int32(i32::from(n), out),
+        Value::U32(n) => match i32::try_from(n) {
+            Ok(n) => int32(n, out),
+            Err(_) => int64(i64::from(n), out),
+        },
+        Value::U64(n) => match i64::try_from(n) {
+            Ok(n) => int64(n, out),
+            Err(_) => {
+                out.extend_from_slice(&n.to_le_bytes());
+                out.extend_from_slice(&((DECIMAL128_BIAS as u64) << 49).to_le_bytes());
+                DECIMAL128
+            }
+        },
+        Value::F32(n) => {
+            out.extend_from_slice(&f64::from(n.0).to_le_bytes());
+            DOUBLE
+        }
+        Value::F64(n) => {
+            out.extend_from_slice(&n.0.to_le_bytes());
+            DOUBLE
+        }
+        Value::Char(c) => {
+            write_string(c.encode_utf8(&mut [0; 4]), out)?;
+            STRING
+        }
+        Value::String(ref s) => {
+            write_string(s, out)?;
+            STRING
+        }
+        Value::Bytes(ref b) => {
+            out.extend_from_slice(&byte_length(b.len())?.to_le_bytes());
+            out.push(0x00);
+            out.extend_from_slice(b);
+            BINARY
+        }
+        Value::Sequence(ref items) => {
+            let start = out.len();
+            out.extend_from_slice(&[0; 4]);
+            for (i, v) in items.iter().enumerate() {
+                write_element(&i.to_string(), v, out)?;
+            }
+            out.push(0);
+            finish_length(start, out)?;
+            ARRAY
+        }
+        Value::Map(ref entries) => match write_extended(entries, out)? {
+            Some(kind) => kind,
+            None => {
+                write_document(entries, out)?;
+                DOCUMENT
+            }
+        },
+    })
+}
+
+/// Writes a map in one of the Extended JSON representations that the source produces as the
+/// BSON type it represents, returning `None` if it isn't one.
+fn write_extended(
+    entries: &[(value::Value, value::Value)],
+    out: &mut Vec<u8>,
+) -> error::Result<Option<u8>> {
+    use crate::value::Value;
+
+    let field = |name: &str| {
+        entries
+            .iter()
+            .find(|(k, _)| matches!(*k, Value::String(ref k) if k == name))
+            .map(|(_, v)| v)
+    };
+    if entries.len() == 2 {
+        return match (field("$code"), field("$scope")) {
+            (Some(Value::String(code)), Some(Value::Map(scope))) => {
+                let start = out.len();
+                out.extend_from_slice(&[0; 4]);
+                write_string(code, out)?;
+                write_document(scope, out)?;
+                finish_length(start, out)?;
+                Ok(Some(CODE_WITH_SCOPE))
+            }
+            _ => Ok(None),
+        };
+    } else if entries.len() != 1 {
+        return Ok(None);
+    }
+
+    let (key, v) = match entries[0] {
+        (Value::String(ref key), ref v) => (key.as_str(), v),
+        _ => return Ok(None),
+    };
+    Ok(match (key, v) {
+        ("$oid", Value::String(s)) if s.len() == 24 => {
+            let bytes = (0..12)
+                .map(|i| u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok())
+                .collect::<Option<Vec<u8>>>();
+            bytes.map(|bytes| {
+                out.extend_from_slice(&bytes);
+                OBJECT_ID
+            })
+        }
+        ("$date", v) => {
+            let millis = match *v {
+                Value::String(ref s) => value::timestamp::parse_rfc3339(s)
+                    .map(|(seconds, nanos)| seconds * 1000 + i64::from(nanos / 1_000_000)),
+                Value::Map(ref entries) => match entries.as_slice() {
+                    [(Value::String(ref k), Value::String(ref n))] if k == "$numberLong" => {
+                        n.parse().ok()
+                    }
+                    _ => None,
+                },
+                ref v => integer(v).and_then(|n| i64::try_from(n).ok()),
+            };
+            millis.map(|millis| {
+                out.extend_from_slice(&millis.to_le_bytes());
+                DATETIME
+            })
+        }
+        ("$numberDecimal", Value::String(s)) => parse_decimal128(s).map(|(low, high)| {
+            out.extend_from_slice(&low.to_le_bytes());
+            out.extend_from_slice(&high.to_le_bytes());
+            DECIMAL128
+        }),
+        ("$numberLong", Value::String(s)) => s.parse::<i64>().ok().map(|n| {
+            out.extend_from_slice(&n.to_le_bytes());
+            INT64
+        }),
+        ("$timestamp", Value::Map(entries)) if entries.len() == 2 => {
+            let field = |name: &str| {
+                entries
+                    .iter()
+                    .find(|(k, _)| matches!(*k, Value::String(ref k) if k == name))
+                    .map(|(_, v)| v)
+            };
+            let field = |name| {
+                field(name)
+                    .and_then(integer)
+                    .and_then(|n| u32::try_from(n).ok())
+            };
+            match (field("t"), field("i")) {
+                (Some(t), Some(i)) => {
+                    out.extend_from_slice(&(u64::from(t) << 32 | u64::from(i)).to_le_bytes());
+                    Some(TIMESTAMP)
+                }
+                _ => None,
+            }
+        }
+        ("$regularExpression", Value::Map(entries)) => match entries.as_slice() {
+            [(Value::String(ref pk), Value::String(ref pattern)), (Value::String(ref ok), Value::String(ref options))]
+                if pk == "pattern" && ok == "options" =>
+            {
+                write_cstring(pattern, out)?;
+                write_cstring(options, out)?;
+                Some(REGEX)
+            }
+            _ => None,
+        },
+        ("$code", Value::String(code)) => {
+            write_string(code, out)?;
+            Some(CODE)
+        }
+        ("$symbol", Value::String(symbol)) => {
+            write_string(symbol, out)?;
+            Some(SYMBOL)
+        }
+        ("$minKey", Value::I32(1)) | ("$minKey", Value::I64(1)) => Some(MIN_KEY),
+        ("$maxKey", Value::I32(1)) | ("$maxKey", Value::I64(1)) => Some(MAX_KEY),
+        _ => None,
+    })
+}
+
+fn integer(v: &value::Value) -> Option<i128> {
+    match *v {
+        value::Value::I8(n) => Some(n.into()),
+        value::Value::I16(n) => Some(n.into()),
+        value::Value::I32(n) => Some(n.into()),
+        value::Value::I64(n) => Some(n.into()),
+        value::Value::U8(n) => Some(n.into()),
+        value::Value::U16(n) => Some(n.into()),
+        value::Value::U32(n) => Some(n.into()),
+        value::Value::U64(n) => Some(n.into()),
+        _ => None,
+    }
+}
+
+fn write_cstring(s: &str, out: &mut Vec<u8>) -> error::Result<()> {
+    if s.contains('\0') {
+        return Err(error::Error::Format {
+            msg: format!(
+                "BSON keys and regular expressions can't contain null: {:?}",
+                s
+            ),
+        });
+    }
+    out.extend_from_slice(s.as_bytes());
+    out.push(0);
+    Ok(())
+}
+
+fn write_string(s: &str, out: &mut Vec<u8>) -> error::Result<()> {
+    out.extend_from_slice(&byte_length(s.len() + 1)?.to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+    out.push(0);
+    Ok(())
+}
+
+fn byte_length(n: usize) -> error::Result<i32> {
+    i32::try_from(n).map_err(|_| error::Error::Format {
+        msg: format!("{} bytes is too large for BSON", n),
+    })
+}
+
+/// Fills in the length prefix of a document that starts at `start` and ends at the end of `out`.
+fn finish_length(start: usize, out: &mut Vec<u8>) -> error::Result<()> {
+    let length = byte_length(out.len() - start)?;
+    out[start..start + 4].copy_from_slice(&length.to_le_bytes());
+    Ok(())
+}
+
+impl<R> fmt::Debug for Source<R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BsonSource").finish()
+    }
+}
+
+impl<W> fmt::Debug for Sink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BsonSink").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn read_all(data: &[u8]) -> error::Result<Vec<Value>> {
+        let mut source = source(data);
+        let mut result = Vec::new();
+        while let Some(v) = source.read()? {
+            result.push(v);
+        }
+        Ok(result)
+    }
+
+    fn write_all(records: Vec<Value>) -> error::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        {
+            let mut sink = sink(&mut out);
+            for record in records {
+                sink.write(record)?;
+            }
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn test_source() {
+        // The examples from bsonspec.org, concatenated like in a mongodump file
+        let data = b"\x16\x00\x00\x00\x02hello\x00\x06\x00\x00\x00world\x00\x00\
+                     \x31\x00\x00\x00\x04BSON\x00\x26\x00\x00\x00\x020\x00\x08\x00\x00\x00awesome\
+                     \x00\x011\x00\x33\x33\x33\x33\x33\x33\x14\x40\x102\x00\xc2\x07\x00\x00\x00\x00";
+        assert_eq!(
+            read_all(data).unwrap(),
+            vec![
+                Value::Map(vec![(s("hello"), s("world"))]),
+                Value::Map(vec![(
+                    s("BSON"),
+                    Value::Sequence(vec![s("awesome"), Value::from_f64(5.05), Value::I32(1986)])
+                )]),
+            ]
+        );
+        assert!(read_all(&data[..30]).is_err());
+        assert!(read_all(b"\x05\x00\x00\x00\x01").is_err());
+        assert_eq!(read_all(b"").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let extended = |k: &str, v: Value| Value::Map(vec![(s(k), v)]);
+        let record = Value::Map(vec![
+            (s("_id"), extended("$oid", s("5f1d7a3e9b1e8b3c4d5e6f70"))),
+            (s("at"), extended("$date", s("2020-07-26T12:34:56.789Z"))),
+            (s("price"), extended("$numberDecimal", s("-12.50"))),
+            (s("big"), extended("$numberDecimal", s("1.000E+6114"))),
+            (
+                s("ts"),
+                extended(
+                    "$timestamp",
+                    Value::Map(vec![(s("t"), Value::U32(1)), (s("i"), Value::U32(2))]),
+                ),
+            ),
+            (
+                s("re"),
+                extended(
+                    "$regularExpression",
+                    Value::Map(vec![(s("pattern"), s("^a")), (s("options"), s("i"))]),
+                ),
+            ),
+            (s("min"), extended("$minKey", Value::I32(1))),
+            (s("n"), Value::I64(-3)),
+            (
+                s("flags"),
+                Value::Sequence(vec![Value::Bool(true), Value::Unit]),
+            ),
+            (s("data"), Value::Bytes(vec![1, 2, 3])),
+            (
+                s("nested"),
+                Value::Map(vec![(s("x"), Value::from_f64(0.5))]),
+            ),
+        ]);
+        let data = write_all(vec![record.clone(), Value::Map(vec![])]).unwrap();
+        assert_eq!(read_all(&data).unwrap(), vec![record, Value::Map(vec![])]);
+    }
+
+    #[test]
+    fn test_sink_conversions() {
+        let data = write_all(vec![Value::Map(vec![
+            (s("small"), Value::U8(7)),
+            (s("large"), Value::U64(u64::max_value())),
+            (s("c"), Value::Char('x')),
+            (s("$numberLong"), s("12")),
+        ])])
+        .unwrap();
+        assert_eq!(
+            read_all(&data).unwrap(),
+            vec![Value::Map(vec![
+                (s("small"), Value::I32(7)),
+                (
+                    s("large"),
+                    Value::Map(vec![(s("$numberDecimal"), s("18446744073709551615"))])
+                ),
+                (s("c"), s("x")),
+                (s("$numberLong"), s("12")),
+            ])]
+        );
+
+        assert!(write_all(vec![Value::I64(1)]).is_err());
+        assert!(write_all(vec![Value::Map(vec![(Value::I64(1), Value::Unit)])]).is_err());
+        assert!(write_all(vec![Value::Map(vec![(s("a\0b"), Value::Unit)])]).is_err());
+    }
+
+    #[test]
+    fn test_decimal128() {
+        for &(s, low, high) in &[
+            ("1", 1, 0x3040_0000_0000_0000),
+            ("-1", 1, 0xb040_0000_0000_0000),
+            ("0.001234", 1234, 0x3034_0000_0000_0000),
+            ("1.234E-7", 1234, 0x302c_0000_0000_0000),
+            ("1.0E+3", 10, 0x3044_0000_0000_0000),
+            ("0", 0, 0x3040_0000_0000_0000),
+            ("NaN", 0, 0x7c00_0000_0000_0000),
+            ("-Infinity", 0, 0xf800_0000_0000_0000),
+        ] {
+            assert_eq!(decimal128(low, high), s);
+            assert_eq!(parse_decimal128(s), Some((low, high)), "{}", s);
+        }
+        assert_eq!(parse_decimal128("1e-6177"), None);
+        assert_eq!(parse_decimal128("1x"), None);
+        assert_eq!(parse_decimal128("."), None);
+    }
+}
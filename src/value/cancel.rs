@@ -0,0 +1,174 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+use std::sync;
+use std::sync::atomic;
+
+/// A flag that another thread can set to stop a running conversion, for applications that embed
+/// rq and let users abort long-running work.
+///
+/// Clones share the same flag.  Cancellation is checked between records, so a record that is
+/// being read or written is always completed.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(sync::Arc<atomic::AtomicBool>);
+
+/// What a conversion did before it finished or was cancelled.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Summary {
+    /// The number of records that were written to the sink.
+    pub records: usize,
+    /// Whether the conversion stopped because of the cancellation token, rather than because the
+    /// source had no more records.
+    pub cancelled: bool,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(atomic::Ordering::SeqCst)
+    }
+}
+
+/// Writes all records of the source to the sink, until the source ends or the token is cancelled.
+///
+/// The sink is finished and dropped before returning either way, so that sinks that write trailers
+/// or buffer their output (like the Parquet and table sinks) are finalized and the partial output
+/// is usable.
+pub fn run_source_sink<I, O>(
+    mut source: I,
+    mut sink: O,
+    token: &CancellationToken,
+) -> error::Result<Summary>
+where
+    I: value::Source,
+    O: value::Sink,
+{
+    let mut summary = Summary::default();
+    while !token.is_cancelled() {
+        match source.read()? {
+            Some(v) => {
+                sink.write(v)?;
+                summary.records += 1;
+            }
+            None => {
+                sink.finish()?;
+                return Ok(summary);
+            }
+        }
+    }
+    debug!("Cancelled after {} records", summary.records);
+    summary.cancelled = true;
+    // The records that were written still make up a complete output
+    sink.finish()?;
+    Ok(summary)
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = if self.records == 1 { "" } else { "s" };
+        if self.cancelled {
+            write!(f, "cancelled after {} record{}", self.records, s)
+        } else {
+            write!(f, "{} record{}", self.records, s)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    struct Counter(u64);
+
+    impl value::Source for Counter {
+        fn read(&mut self) -> error::Result<Option<Value>> {
+            self.0 += 1;
+            Ok(if self.0 <= 5 {
+                Some(Value::U64(self.0))
+            } else {
+                None
+            })
+        }
+    }
+
+    /// Cancels the token after the given number of records, and records whether it was finished
+    /// and dropped.
+    struct CancellingSink<'a> {
+        token: CancellationToken,
+        after: usize,
+        written: &'a mut Vec<Value>,
+    }
+
+    impl<'a> value::Sink for CancellingSink<'a> {
+        fn write(&mut self, v: Value) -> error::Result<()> {
+            self.written.push(v);
+            if self.written.len() == self.after {
+                self.token.cancel();
+            }
+            Ok(())
+        }
+
+        fn finish(&mut self) -> error::Result<()> {
+            self.written.push(Value::Bool(true));
+            Ok(())
+        }
+    }
+
+    impl<'a> Drop for CancellingSink<'a> {
+        fn drop(&mut self) {
+            self.written.push(Value::Unit);
+        }
+    }
+
+    fn run(after: usize) -> (Summary, Vec<Value>) {
+        let token = CancellationToken::new();
+        let mut written = Vec::new();
+        let sink = CancellingSink {
+            token: token.clone(),
+            after,
+            written: &mut written,
+        };
+        let summary = run_source_sink(Counter(0), sink, &token).unwrap();
+        (summary, written)
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (summary, written) = run(2);
+        assert_eq!(
+            summary,
+            Summary {
+                records: 2,
+                cancelled: true
+            }
+        );
+        assert_eq!(summary.to_string(), "cancelled after 2 records");
+        // The sink was finalized
+        assert_eq!(
+            written,
+            vec![Value::U64(1), Value::U64(2), Value::Bool(true), Value::Unit]
+        );
+    }
+
+    #[test]
+    fn test_complete() {
+        let (summary, written) = run(10);
+        assert_eq!(
+            summary,
+            Summary {
+                records: 5,
+                cancelled: false
+            }
+        );
+        assert_eq!(summary.to_string(), "5 records");
+        assert_eq!(written.len(), 7);
+    }
+}
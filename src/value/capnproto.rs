@@ -0,0 +1,1107 @@
+//! Cap'n Proto messages, decoded dynamically with a schema that `capnp compile -o-` compiled, in
+//! the format that it writes to code generator plugins.
+//!
+//! Unset pointer fields, like texts, lists and structs, are decoded as `Unit`, like unset
+//! protobuf messages, and the other fields as their values or defaults.  Of unions, only the field
+//! that is set is decoded.  Interfaces and `AnyPointer` fields can't be decoded without knowing
+//! their types, so they are decoded as `Unit`.
+
+use crate::error;
+use crate::value;
+use ordered_float;
+use std::collections;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+/// The most segments that a message may have, like the reference implementation allows.
+const MAX_SEGMENTS: usize = 512;
+/// How deeply structs and lists may be nested in a message.
+const MAX_DEPTH: usize = 64;
+
+/// The types of a compiled schema, by their IDs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    nodes: collections::HashMap<u64, Node>,
+}
+
+/// A source that reads a stream of messages of a struct type, each framed with its segment table
+/// like `capnp encode` and `writeMessage` write them, and decodes each as a record.
+pub struct Source<'a, R>
+where
+    R: io::Read,
+{
+    schema: &'a Schema,
+    type_id: u64,
+    input: R,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Node {
+    name: String,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Struct {
+        discriminant_count: u16,
+        discriminant_offset: u32,
+        fields: Vec<Field>,
+    },
+    Enum(Vec<String>),
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Field {
+    name: String,
+    /// The value of the discriminant of the union when this field is set, if it is in one.
+    discriminant: Option<u16>,
+    kind: FieldKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum FieldKind {
+    /// A field at an offset in units of the size of its type, with the bits of its default value
+    /// that it is stored XORed with.
+    Slot {
+        offset: u32,
+        field_type: Type,
+        default: u64,
+    },
+    /// A group, which is stored in the struct that it is in.
+    Group(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Type {
+    Void,
+    Bool,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float32,
+    Float64,
+    Text,
+    Data,
+    List(Box<Type>),
+    Enum(u64),
+    Struct(u64),
+    Interface,
+    AnyPointer,
+}
+
+/// The segments of a message.
+struct Message {
+    segments: Vec<Vec<u8>>,
+}
+
+/// Decodes the contents of a message, while keeping track of how much of it has been read, so
+/// that pointers that point to the same objects many times can't make it blow up.
+struct Decoder<'a> {
+    schema: &'a Schema,
+    message: &'a Message,
+    budget: u64,
+}
+
+/// A pointer that has been followed: the word that it points to and the word that describes what
+/// is there, which is the pointer itself unless it is a far pointer.
+struct Target {
+    segment: usize,
+    word: usize,
+    pointer: u64,
+}
+
+/// Where the sections of a struct are, with the data section in bytes, since the elements of
+/// lists of primitives are read as structs too.
+#[derive(Clone, Copy, Debug, Default)]
+struct StructRef {
+    segment: usize,
+    data: usize,
+    data_len: usize,
+    pointers: usize,
+    pointer_count: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ListRef {
+    segment: usize,
+    start: usize,
+    count: usize,
+    element: Element,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Element {
+    Bits(usize),
+    Pointer,
+    Composite { data_words: usize, pointers: usize },
+}
+
+impl Schema {
+    /// Reads the schema from a `CodeGeneratorRequest`, like `capnp compile -o-` writes it.
+    pub fn from_code_generator_request(bytes: &[u8]) -> error::Result<Self> {
+        let message = read_message(&mut &bytes[..])?
+            .ok_or_else(|| bad("the compiled schema is empty".to_owned()))?;
+        let empty = Schema::default();
+        let mut decoder = Decoder::new(&empty, &message);
+        let request = decoder.root()?;
+
+        let mut nodes = collections::HashMap::new();
+        if let Some(list) = decoder.list_at(&request, 0)? {
+            for i in 0..list.count {
+                let node = decoder.element(&list, i)?;
+                let kind = match decoder.bits(&node, 6 * 16, 16) {
+                    1 => {
+                        let mut fields = Vec::new();
+                        if let Some(list) = decoder.list_at(&node, 3)? {
+                            for i in 0..list.count {
+                                let field = decoder.element(&list, i)?;
+                                fields.push(decoder.field(&field)?);
+                            }
+                        }
+                        Kind::Struct {
+                            discriminant_count: decoder.bits(&node, 15 * 16, 16) as u16,
+                            discriminant_offset: decoder.bits(&node, 8 * 32, 32) as u32,
+                            fields,
+                        }
+                    }
+                    2 => {
+                        let mut enumerants = Vec::new();
+                        if let Some(list) = decoder.list_at(&node, 3)? {
+                            for i in 0..list.count {
+                                let enumerant = decoder.element(&list, i)?;
+                                enumerants.push(decoder.text_at(&enumerant, 0)?);
+                            }
+                        }
+                        Kind::Enum(enumerants)
+                    }
+                    _ => Kind::Other,
+                };
+                let name = decoder.text_at(&node, 0)?;
+                nodes.insert(decoder.bits(&node, 0, 64), Node { name, kind });
+            }
+        }
+        Ok(Schema { nodes })
+    }
+
+    /// Looks up a struct type by its name like `Person.Address`, or by its full name with the
+    /// schema file that it is in, like `person.capnp:Person.Address`.
+    pub fn struct_id(&self, name: &str) -> error::Result<u64> {
+        let mut ids = self.nodes.iter().filter_map(|(id, node)| {
+            let is_match = match node.kind {
+                Kind::Struct { .. } => {
+                    node.name == name || node.name.split_once(':').map_or(false, |(_, n)| n == name)
+                }
+                _ => false,
+            };
+            if is_match {
+                Some(*id)
+            } else {
+                None
+            }
+        });
+        match (ids.next(), ids.next()) {
+            (Some(id), None) => Ok(id),
+            (Some(_), Some(_)) => Err(error::Error::Message(format!(
+                "ambiguous Cap'n Proto struct type {:?}, use its full name like \
+                 \"file.capnp:{}\"",
+                name, name
+            ))),
+            (None, _) => Err(error::Error::Message(format!(
+                "unknown Cap'n Proto struct type {:?}",
+                name
+            ))),
+        }
+    }
+}
+
+#[inline]
+pub fn source<'a, R>(schema: &'a Schema, type_name: &str, input: R) -> error::Result<Source<'a, R>>
+where
+    R: io::Read,
+{
+    Ok(Source {
+        schema,
+        type_id: schema.struct_id(type_name)?,
+        input,
+    })
+}
+
+impl<'a, R> value::Source for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let message = match read_message(&mut self.input)? {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+        let mut decoder = Decoder::new(self.schema, &message);
+        let root = decoder.root()?;
+        Ok(Some(decoder.struct_value(self.type_id, &root, 0)?))
+    }
+}
+
+/// Reads a message framed with its segment table, or `None` at the end of the input.
+fn read_message<R>(input: &mut R) -> error::Result<Option<Message>>
+where
+    R: io::Read,
+{
+    use std::io::Read;
+
+    let mut first = [0; 4];
+    let mut read = 0;
+    while read < first.len() {
+        match input.read(&mut first[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(bad("the segment table is truncated".to_owned())),
+            n => read += n,
+        }
+    }
+    let count = u32::from_le_bytes(first) as usize + 1;
+    if count > MAX_SEGMENTS {
+        return Err(bad(format!("the message has {} segments", count)));
+    }
+
+    // The segment table is padded to a whole number of words
+    let mut table = vec![0; 4 * (count + count % 2 - 1)];
+    input
+        .read_exact(&mut table)
+        .map_err(|_| bad("the segment table is truncated".to_owned()))?;
+    let mut segments = Vec::with_capacity(count);
+    for size in table.chunks(4).take(count) {
+        let len = u64::from(u32::from_le_bytes([size[0], size[1], size[2], size[3]])) * 8;
+        let mut segment = Vec::new();
+        input.take(len).read_to_end(&mut segment)?;
+        if segment.len() as u64 != len {
+            return Err(bad("a segment is truncated".to_owned()));
+        }
+        segments.push(segment);
+    }
+    Ok(Some(Message { segments }))
+}
+
+impl<'a> Decoder<'a> {
+    fn new(schema: &'a Schema, message: &'a Message) -> Self {
+        let words = message
+            .segments
+            .iter()
+            .map(|s| s.len() as u64 / 8)
+            .sum::<u64>();
+        Decoder {
+            schema,
+            message,
+            budget: words * 64 + 1024,
+        }
+    }
+
+    fn charge(&mut self, n: usize) -> error::Result<()> {
+        self.budget = self
+            .budget
+            .checked_sub(n as u64)
+            .ok_or_else(|| bad("the message points to the same objects too often".to_owned()))?;
+        Ok(())
+    }
+
+    fn word(&self, segment: usize, index: usize) -> error::Result<u64> {
+        self.message
+            .segments
+            .get(segment)
+            .and_then(|s| s.get(index.checked_mul(8)?..index.checked_mul(8)? + 8))
+            .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .ok_or_else(|| bad("a pointer points out of its segment".to_owned()))
+    }
+
+    fn check(&self, segment: usize, start: usize, words: usize) -> error::Result<()> {
+        let end = start
+            .checked_add(words)
+            .and_then(|end| end.checked_mul(8))
+            .ok_or_else(|| bad("an object is too large".to_owned()))?;
+        match self.message.segments.get(segment) {
+            Some(s) if s.len() >= end => Ok(()),
+            _ => Err(bad("a pointer points out of its segment".to_owned())),
+        }
+    }
+
+    fn follow(&self, segment: usize, index: usize) -> error::Result<Option<Target>> {
+        fn offset(index: usize, pointer: u64) -> error::Result<usize> {
+            let offset = i64::from(pointer as u32 as i32 >> 2);
+            usize::try_from(index as i64 + 1 + offset)
+                .map_err(|_| bad("a pointer points out of its segment".to_owned()))
+        }
+
+        let pointer = self.word(segment, index)?;
+        if pointer == 0 {
+            return Ok(None);
+        }
+        if pointer & 3 != 2 {
+            let word = offset(index, pointer)?;
+            return Ok(Some(Target {
+                segment,
+                word,
+                pointer,
+            }));
+        }
+
+        // A far pointer, to a landing pad in another segment
+        let pad_segment = (pointer >> 32) as usize;
+        let pad = ((pointer >> 3) & 0x1fff_ffff) as usize;
+        let landing = self.word(pad_segment, pad)?;
+        if pointer & 4 == 0 {
+            if landing & 3 == 2 {
+                return Err(bad("a far pointer lands on another far pointer".to_owned()));
+            }
+            Ok(Some(Target {
+                segment: pad_segment,
+                word: offset(pad, landing)?,
+                pointer: landing,
+            }))
+        } else {
+            if landing & 7 != 2 {
+                return Err(bad(
+                    "a double-far pointer lands on no far pointer".to_owned()
+                ));
+            }
+            Ok(Some(Target {
+                segment: (landing >> 32) as usize,
+                word: ((landing >> 3) & 0x1fff_ffff) as usize,
+                pointer: self.word(pad_segment, pad + 1)?,
+            }))
+        }
+    }
+
+    fn struct_ref(&mut self, target: &Target) -> error::Result<StructRef> {
+        if target.pointer & 3 != 0 {
+            return Err(bad("expected a struct pointer".to_owned()));
+        }
+        let data_words = (target.pointer >> 32) as u16 as usize;
+        let pointer_count = (target.pointer >> 48) as usize;
+        self.check(target.segment, target.word, data_words + pointer_count)?;
+        self.charge(1)?;
+        Ok(StructRef {
+            segment: target.segment,
+            data: target.word * 8,
+            data_len: data_words * 8,
+            pointers: target.word + data_words,
+            pointer_count,
+        })
+    }
+
+    fn list_ref(&mut self, target: &Target) -> error::Result<ListRef> {
+        if target.pointer & 3 != 1 {
+            return Err(bad("expected a list pointer".to_owned()));
+        }
+        let count = (target.pointer >> 35) as usize;
+        let (start, count, element) = match (target.pointer >> 32) & 7 {
+            6 => {
+                self.check(target.segment, target.word, count)?;
+                (target.word, count, Element::Pointer)
+            }
+            7 => {
+                // `count` is the number of words, and a tag like a struct pointer comes first
+                self.check(target.segment, target.word, count + 1)?;
+                let tag = self.word(target.segment, target.word)?;
+                let elements = (tag as u32 >> 2) as usize;
+                let data_words = (tag >> 32) as u16 as usize;
+                let pointers = (tag >> 48) as usize;
+                if tag & 3 != 0 || elements * (data_words + pointers) > count {
+                    return Err(bad("a list of structs has a bad tag".to_owned()));
+                }
+                let element = Element::Composite {
+                    data_words,
+                    pointers,
+                };
+                (target.word + 1, elements, element)
+            }
+            size => {
+                let bits = [0, 1, 8, 16, 32, 64][size as usize];
+                self.check(target.segment, target.word, (count * bits + 63) / 64)?;
+                (target.word, count, Element::Bits(bits))
+            }
+        };
+        self.charge(count.max(1))?;
+        Ok(ListRef {
+            segment: target.segment,
+            start,
+            count,
+            element,
+        })
+    }
+
+    fn root(&mut self) -> error::Result<StructRef> {
+        match self.follow(0, 0)? {
+            Some(target) => self.struct_ref(&target),
+            None => Ok(StructRef::default()),
+        }
+    }
+
+    fn list_at(&mut self, s: &StructRef, index: usize) -> error::Result<Option<ListRef>> {
+        if index >= s.pointer_count {
+            return Ok(None);
+        }
+        match self.follow(s.segment, s.pointers + index)? {
+            Some(target) => Ok(Some(self.list_ref(&target)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn struct_at(&mut self, s: &StructRef, index: usize) -> error::Result<Option<StructRef>> {
+        if index >= s.pointer_count {
+            return Ok(None);
+        }
+        match self.follow(s.segment, s.pointers + index)? {
+            Some(target) => Ok(Some(self.struct_ref(&target)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn bytes(&self, list: &ListRef) -> error::Result<&'a [u8]> {
+        match list.element {
+            Element::Bits(8) => {
+                let start = list.start * 8;
+                Ok(&self.message.segments[list.segment][start..start + list.count])
+            }
+            _ => Err(bad("expected a list of bytes".to_owned())),
+        }
+    }
+
+    fn text_at(&mut self, s: &StructRef, index: usize) -> error::Result<String> {
+        match self.list_at(s, index)? {
+            Some(list) => text(self.bytes(&list)?),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Reads bits of the data section of a struct, which are zero beyond its end, since the struct
+    /// may have been written with an older version of the schema.
+    fn bits(&self, s: &StructRef, offset: usize, width: usize) -> u64 {
+        let data = &self.message.segments.get(s.segment).map_or(&[][..], |seg| {
+            seg.get(s.data..s.data + s.data_len).unwrap_or_default()
+        });
+        if width == 1 {
+            data.get(offset / 8)
+                .map_or(0, |b| u64::from(b >> (offset % 8) & 1))
+        } else {
+            let start = offset / 8;
+            data.get(start..start + width / 8).map_or(0, |bytes| {
+                bytes.iter().rev().fold(0, |n, &b| (n << 8) | u64::from(b))
+            })
+        }
+    }
+
+    /// The element of a list as a struct, also if the list has primitives or pointers, since
+    /// lists may be upgraded from those to structs, and the other way around.
+    fn element(&self, list: &ListRef, i: usize) -> error::Result<StructRef> {
+        match list.element {
+            Element::Composite {
+                data_words,
+                pointers,
+            } => {
+                let word = list.start + i * (data_words + pointers);
+                Ok(StructRef {
+                    segment: list.segment,
+                    data: word * 8,
+                    data_len: data_words * 8,
+                    pointers: word + data_words,
+                    pointer_count: pointers,
+                })
+            }
+            Element::Pointer => Ok(StructRef {
+                segment: list.segment,
+                pointers: list.start + i,
+                pointer_count: 1,
+                ..StructRef::default()
+            }),
+            Element::Bits(1) => Err(error::Error::Unimplemented {
+                msg: "lists of bits can only be decoded as lists of booleans".to_owned(),
+            }),
+            Element::Bits(bits) => Ok(StructRef {
+                segment: list.segment,
+                data: list.start * 8 + i * bits / 8,
+                data_len: bits / 8,
+                ..StructRef::default()
+            }),
+        }
+    }
+
+    fn field(&mut self, field: &StructRef) -> error::Result<Field> {
+        let name = self.text_at(field, 0)?;
+        let discriminant = match self.bits(field, 16, 16) as u16 ^ 0xffff {
+            0xffff => None,
+            d => Some(d),
+        };
+        let kind = match self.bits(field, 4 * 16, 16) {
+            0 => {
+                let field_type = match self.struct_at(field, 2)? {
+                    Some(t) => self.field_type(&t, 0)?,
+                    None => Type::Void,
+                };
+                let default = match self.struct_at(field, 3)? {
+                    Some(v) => self.default_bits(&v),
+                    None => 0,
+                };
+                FieldKind::Slot {
+                    offset: self.bits(field, 32, 32) as u32,
+                    field_type,
+                    default,
+                }
+            }
+            1 => FieldKind::Group(self.bits(field, 2 * 64, 64)),
+            n => return Err(bad(format!("field {:?} is of unknown kind {}", name, n))),
+        };
+        Ok(Field {
+            name,
+            discriminant,
+            kind,
+        })
+    }
+
+    fn field_type(&mut self, t: &StructRef, depth: usize) -> error::Result<Type> {
+        if depth > MAX_DEPTH {
+            return Err(bad("a list type is nested too deeply".to_owned()));
+        }
+        Ok(match self.bits(t, 0, 16) {
+            0 => Type::Void,
+            1 => Type::Bool,
+            2 => Type::Int8,
+            3 => Type::Int16,
+            4 => Type::Int32,
+            5 => Type::Int64,
+            6 => Type::UInt8,
+            7 => Type::UInt16,
+            8 => Type::UInt32,
+            9 => Type::UInt64,
+            10 => Type::Float32,
+            11 => Type::Float64,
+            12 => Type::Text,
+            13 => Type::Data,
+            14 => match self.struct_at(t, 0)? {
+                Some(element) => Type::List(Box::new(self.field_type(&element, depth + 1)?)),
+                None => Type::List(Box::new(Type::Void)),
+            },
+            15 => Type::Enum(self.bits(t, 64, 64)),
+            16 => Type::Struct(self.bits(t, 64, 64)),
+            17 => Type::Interface,
+            18 => Type::AnyPointer,
+            n => return Err(bad(format!("unknown type {}", n))),
+        })
+    }
+
+    /// The bits of a default value of a primitive type.
+    fn default_bits(&self, v: &StructRef) -> u64 {
+        match self.bits(v, 0, 16) {
+            1 => self.bits(v, 16, 1),
+            2 | 6 => self.bits(v, 16, 8),
+            3 | 7 | 15 => self.bits(v, 16, 16),
+            4 | 8 | 10 => self.bits(v, 32, 32),
+            5 | 9 | 11 => self.bits(v, 64, 64),
+            _ => 0,
+        }
+    }
+
+    fn struct_value(
+        &mut self,
+        type_id: u64,
+        s: &StructRef,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        if depth > MAX_DEPTH {
+            return Err(bad("structs are nested too deeply".to_owned()));
+        }
+        let schema = self.schema;
+        let (discriminant_count, discriminant_offset, fields) = match schema.nodes.get(&type_id) {
+            Some(Node {
+                kind:
+                    Kind::Struct {
+                        discriminant_count,
+                        discriminant_offset,
+                        ref fields,
+                    },
+                ..
+            }) => (*discriminant_count, *discriminant_offset, fields),
+            _ => return Err(bad(format!("unknown struct type {:016x}", type_id))),
+        };
+        let discriminant = if discriminant_count > 0 {
+            Some(self.bits(s, discriminant_offset as usize * 16, 16) as u16)
+        } else {
+            None
+        };
+
+        let mut entries = Vec::with_capacity(fields.len());
+        for field in fields {
+            if field.discriminant.is_some() && field.discriminant != discriminant {
+                continue;
+            }
+            let v = match field.kind {
+                FieldKind::Slot {
+                    offset,
+                    ref field_type,
+                    default,
+                } => self.slot_value(s, offset as usize, field_type, default, depth)?,
+                FieldKind::Group(id) => self.struct_value(id, s, depth + 1)?,
+            };
+            entries.push((value::Value::String(field.name.clone()), v));
+        }
+        Ok(value::Value::Map(entries))
+    }
+
+    fn slot_value(
+        &mut self,
+        s: &StructRef,
+        offset: usize,
+        field_type: &Type,
+        default: u64,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        use crate::value::Value;
+
+        let bits = |width: usize| self.bits(s, offset * width, width) ^ default;
+        Ok(match *field_type {
+            Type::Void => Value::Unit,
+            Type::Bool => Value::Bool(bits(1) != 0),
+            Type::Int8 => Value::I8(bits(8) as i8),
+            Type::Int16 => Value::I16(bits(16) as i16),
+            Type::Int32 => Value::I32(bits(32) as i32),
+            Type::Int64 => Value::I64(bits(64) as i64),
+            Type::UInt8 => Value::U8(bits(8) as u8),
+            Type::UInt16 => Value::U16(bits(16) as u16),
+            Type::UInt32 => Value::U32(bits(32) as u32),
+            Type::UInt64 => Value::U64(bits(64)),
+            Type::Float32 => {
+                Value::F32(ordered_float::OrderedFloat(f32::from_bits(bits(32) as u32)))
+            }
+            Type::Float64 => Value::F64(ordered_float::OrderedFloat(f64::from_bits(bits(64)))),
+            Type::Enum(id) => self.enum_value(id, bits(16) as u16),
+            _ => self.pointer_value(s, offset, field_type, depth)?,
+        })
+    }
+
+    fn enum_value(&self, type_id: u64, n: u16) -> value::Value {
+        match self.schema.nodes.get(&type_id) {
+            Some(Node {
+                kind: Kind::Enum(ref enumerants),
+                ..
+            }) if (n as usize) < enumerants.len() => {
+                value::Value::String(enumerants[n as usize].clone())
+            }
+            _ => value::Value::U16(n),
+        }
+    }
+
+    fn pointer_value(
+        &mut self,
+        s: &StructRef,
+        index: usize,
+        field_type: &Type,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        use crate::value::Value;
+
+        if index >= s.pointer_count {
+            return Ok(Value::Unit);
+        }
+        let target = match self.follow(s.segment, s.pointers + index)? {
+            Some(target) => target,
+            None => return Ok(Value::Unit),
+        };
+        Ok(match *field_type {
+            Type::Text => {
+                let list = self.list_ref(&target)?;
+                Value::String(text(self.bytes(&list)?)?)
+            }
+            Type::Data => {
+                let list = self.list_ref(&target)?;
+                Value::Bytes(self.bytes(&list)?.to_vec())
+            }
+            Type::List(ref element_type) => {
+                let list = self.list_ref(&target)?;
+                self.list_value(&list, element_type, depth + 1)?
+            }
+            Type::Struct(id) => {
+                let s = self.struct_ref(&target)?;
+                self.struct_value(id, &s, depth + 1)?
+            }
+            _ => Value::Unit,
+        })
+    }
+
+    fn list_value(
+        &mut self,
+        list: &ListRef,
+        element_type: &Type,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        if depth > MAX_DEPTH {
+            return Err(bad("lists are nested too deeply".to_owned()));
+        }
+        let mut values = Vec::with_capacity(list.count.min(1024));
+        for i in 0..list.count {
+            let v = match (list.element, element_type) {
+                (Element::Bits(1), Type::Bool) => {
+                    let byte = self.message.segments[list.segment][list.start * 8 + i / 8];
+                    value::Value::Bool(byte >> (i % 8) & 1 != 0)
+                }
+                (_, Type::Struct(id)) => {
+                    let s = self.element(list, i)?;
+                    self.struct_value(*id, &s, depth + 1)?
+                }
+                _ => {
+                    let s = self.element(list, i)?;
+                    self.slot_value(&s, 0, element_type, 0, depth)?
+                }
+            };
+            values.push(v);
+        }
+        Ok(value::Value::Sequence(values))
+    }
+}
+
+/// A text without its NUL terminator.
+fn text(bytes: &[u8]) -> error::Result<String> {
+    let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+fn bad(msg: String) -> error::Error {
+    error::Error::Format {
+        msg: format!("bad Cap'n Proto message: {}", msg),
+    }
+}
+
+impl<'a, R> fmt::Debug for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CapnProtoSource")
+            .field("type_id", &self.type_id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    /// Lays out a single-segment message word by word.
+    #[derive(Default)]
+    struct Builder(Vec<u64>);
+
+    impl Builder {
+        fn alloc(&mut self, words: usize) -> usize {
+            let at = self.0.len();
+            self.0.resize(at + words, 0);
+            at
+        }
+
+        fn new_struct(&mut self, at: usize, data_words: u16, pointers: u16) -> usize {
+            let target = self.alloc(usize::from(data_words + pointers));
+            let offset = (target as i64 - at as i64 - 1) << 2;
+            self.0[at] =
+                u64::from(offset as u32) | u64::from(data_words) << 32 | u64::from(pointers) << 48;
+            target
+        }
+
+        fn list_pointer(&mut self, at: usize, target: usize, size: u64, count: usize) {
+            let offset = (target as i64 - at as i64 - 1) << 2;
+            self.0[at] = u64::from(offset as u32) | 1 | size << 32 | (count as u64) << 35;
+        }
+
+        fn new_list(&mut self, at: usize, size: u64, count: usize, words: usize) -> usize {
+            let target = self.alloc(words);
+            self.list_pointer(at, target, size, count);
+            target
+        }
+
+        /// A list of structs, and the first words of its elements.
+        fn new_structs(
+            &mut self,
+            at: usize,
+            count: usize,
+            data_words: u16,
+            pointers: u16,
+        ) -> Vec<usize> {
+            let size = usize::from(data_words + pointers);
+            let tag = self.new_list(at, 7, count * size, count * size + 1);
+            self.0[tag] =
+                (count as u64) << 2 | u64::from(data_words) << 32 | u64::from(pointers) << 48;
+            (0..count).map(|i| tag + 1 + i * size).collect()
+        }
+
+        fn text(&mut self, at: usize, s: &str) {
+            let bytes = s.as_bytes();
+            let target = self.new_list(at, 2, bytes.len() + 1, bytes.len() / 8 + 1);
+            for (i, &b) in bytes.iter().enumerate() {
+                self.0[target + i / 8] |= u64::from(b) << (8 * (i % 8));
+            }
+        }
+
+        /// Sets bits of a data section, at an offset in units of their width.
+        fn set(&mut self, word: usize, offset: usize, width: usize, value: u64) {
+            let bit = offset * width;
+            let mask = if width == 64 { !0 } else { (1 << width) - 1 };
+            self.0[word + bit / 64] |= (value & mask) << (bit % 64);
+        }
+
+        fn message(&self) -> Vec<u8> {
+            let mut result = 0u32.to_le_bytes().to_vec();
+            result.extend_from_slice(&(self.0.len() as u32).to_le_bytes());
+            for word in &self.0 {
+                result.extend_from_slice(&word.to_le_bytes());
+            }
+            result
+        }
+    }
+
+    const PERSON: u64 = 0x10;
+    const COLOR: u64 = 0x11;
+    const CONTACT: u64 = 0x12;
+
+    /// The request for a schema like:
+    ///
+    /// ```capnp
+    /// struct Person {
+    ///   name @0 :Text;
+    ///   age @1 :UInt16 = 30;
+    ///   favorite @2 :Color;
+    ///   tags @3 :List(Text);
+    ///   friends @4 :List(Person);
+    ///   contact :group {
+    ///     union {
+    ///       email @5 :Text;
+    ///       phone @6 :UInt64;
+    ///     }
+    ///   }
+    ///   flags @7 :List(Bool);
+    /// }
+    /// enum Color { red @0; green @1; }
+    /// ```
+    fn request() -> Vec<u8> {
+        let mut b = Builder::default();
+        b.alloc(1);
+        let request = b.new_struct(0, 0, 4);
+        let nodes = b.new_structs(request, 3, 5, 6);
+
+        let slot = |b: &mut Builder, field: usize, name: &str, offset: u64, t: u64| {
+            b.text(field + 3, name);
+            b.set(field, 1, 32, offset);
+            let field_type = b.new_struct(field + 3 + 2, 3, 1);
+            b.set(field_type, 0, 16, t);
+            field_type
+        };
+
+        // Person, with the discriminant of the union of the group at 16-bit offset 2
+        let person = nodes[0];
+        b.set(person, 0, 64, PERSON);
+        b.text(person + 5, "person.capnp:Person");
+        b.set(person, 6, 16, 1);
+        let fields = b.new_structs(person + 5 + 3, 7, 3, 4);
+        slot(&mut b, fields[0], "name", 0, 12);
+        slot(&mut b, fields[1], "age", 0, 7);
+        let default = b.new_struct(fields[1] + 3 + 3, 2, 1);
+        b.set(default, 0, 16, 7);
+        b.set(default, 1, 16, 30);
+        let color = slot(&mut b, fields[2], "favorite", 1, 15);
+        b.set(color, 1, 64, COLOR);
+        let tags = slot(&mut b, fields[3], "tags", 1, 14);
+        let element = b.new_struct(tags + 3, 3, 1);
+        b.set(element, 0, 16, 12);
+        let friends = slot(&mut b, fields[4], "friends", 2, 14);
+        let element = b.new_struct(friends + 3, 3, 1);
+        b.set(element, 0, 16, 16);
+        b.set(element, 1, 64, PERSON);
+        b.text(fields[5] + 3, "contact");
+        b.set(fields[5], 4, 16, 1);
+        b.set(fields[5], 2, 64, CONTACT);
+        let flags = slot(&mut b, fields[6], "flags", 4, 14);
+        let element = b.new_struct(flags + 3, 3, 1);
+        b.set(element, 0, 16, 1);
+
+        let contact = nodes[1];
+        b.set(contact, 0, 64, CONTACT);
+        b.text(contact + 5, "person.capnp:Person.contact");
+        b.set(contact, 6, 16, 1);
+        b.set(contact, 15, 16, 2);
+        b.set(contact, 8, 32, 2);
+        let fields = b.new_structs(contact + 5 + 3, 2, 3, 4);
+        slot(&mut b, fields[0], "email", 3, 12);
+        slot(&mut b, fields[1], "phone", 1, 9);
+        // The discriminant values, which are stored XORed with 0xffff
+        b.set(fields[0], 1, 16, 0xffff);
+        b.set(fields[1], 1, 16, 0xfffe);
+
+        let color = nodes[2];
+        b.set(color, 0, 64, COLOR);
+        b.text(color + 5, "person.capnp:Color");
+        b.set(color, 6, 16, 2);
+        let enumerants = b.new_structs(color + 5 + 3, 2, 1, 2);
+        b.text(enumerants[0] + 1, "red");
+        b.text(enumerants[1] + 1, "green");
+
+        b.message()
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = Schema::from_code_generator_request(&request()).unwrap();
+        assert_eq!(schema.struct_id("Person").unwrap(), PERSON);
+        assert_eq!(schema.struct_id("person.capnp:Person").unwrap(), PERSON);
+        assert_eq!(schema.struct_id("Person.contact").unwrap(), CONTACT);
+        assert!(schema.struct_id("Color").is_err());
+        assert!(schema.struct_id("Nope").is_err());
+
+        match schema.nodes[&PERSON].kind {
+            Kind::Struct { ref fields, .. } => {
+                assert_eq!(
+                    fields[1].kind,
+                    FieldKind::Slot {
+                        offset: 0,
+                        field_type: Type::UInt16,
+                        default: 30
+                    }
+                );
+                assert_eq!(
+                    fields[4].kind,
+                    FieldKind::Slot {
+                        offset: 2,
+                        field_type: Type::List(Box::new(Type::Struct(PERSON))),
+                        default: 0
+                    }
+                );
+            }
+            ref kind => panic!("expected a struct, got {:?}", kind),
+        }
+        match schema.nodes[&CONTACT].kind {
+            Kind::Struct { ref fields, .. } => {
+                assert_eq!(fields[0].discriminant, Some(0));
+                assert_eq!(fields[1].discriminant, Some(1));
+            }
+            ref kind => panic!("expected a struct, got {:?}", kind),
+        }
+        assert_eq!(
+            schema.nodes[&COLOR].kind,
+            Kind::Enum(vec!["red".to_owned(), "green".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        let schema = Schema::from_code_generator_request(&request()).unwrap();
+
+        // Person has 2 data words and 5 pointers: name, tags, friends, email and flags
+        let mut b = Builder::default();
+        b.alloc(1);
+        let person = b.new_struct(0, 2, 5);
+        b.text(person + 2, "Ada");
+        b.set(person, 0, 16, 36 ^ 30);
+        b.set(person, 1, 16, 1);
+        b.set(person, 2, 16, 0);
+        let tags = b.new_list(person + 2 + 1, 6, 2, 2);
+        b.text(tags, "a");
+        b.text(tags + 1, "b");
+        let friends = b.new_structs(person + 2 + 2, 1, 2, 5);
+        b.set(friends[0], 1, 16, 7);
+        b.set(friends[0], 2, 16, 1);
+        b.set(friends[0], 1, 64, 555);
+        b.text(person + 2 + 3, "ada@example.com");
+        let flags = b.new_list(person + 2 + 4, 1, 3, 1);
+        b.0[flags] = 0b101;
+        let mut input = b.message();
+
+        // An empty message has all defaults
+        let mut b = Builder::default();
+        b.alloc(1);
+        b.new_struct(0, 0, 0);
+        input.extend(b.message());
+
+        let mut source = source(&schema, "Person", &input[..]).unwrap();
+        let s = |s: &str| Value::String(s.to_owned());
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![
+                (s("name"), s("Ada")),
+                (s("age"), Value::U16(36)),
+                (s("favorite"), s("green")),
+                (s("tags"), Value::Sequence(vec![s("a"), s("b")])),
+                (
+                    s("friends"),
+                    Value::Sequence(vec![Value::Map(vec![
+                        (s("name"), Value::Unit),
+                        (s("age"), Value::U16(30)),
+                        (s("favorite"), Value::U16(7)),
+                        (s("tags"), Value::Unit),
+                        (s("friends"), Value::Unit),
+                        (
+                            s("contact"),
+                            Value::Map(vec![(s("phone"), Value::U64(555))])
+                        ),
+                        (s("flags"), Value::Unit),
+                    ])])
+                ),
+                (
+                    s("contact"),
+                    Value::Map(vec![(s("email"), s("ada@example.com"))])
+                ),
+                (
+                    s("flags"),
+                    Value::Sequence(vec![
+                        Value::Bool(true),
+                        Value::Bool(false),
+                        Value::Bool(true)
+                    ])
+                ),
+            ]))
+        );
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![
+                (s("name"), Value::Unit),
+                (s("age"), Value::U16(30)),
+                (s("favorite"), s("red")),
+                (s("tags"), Value::Unit),
+                (s("friends"), Value::Unit),
+                (s("contact"), Value::Map(vec![(s("email"), Value::Unit)])),
+                (s("flags"), Value::Unit),
+            ]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_source_errors() {
+        let schema = Schema::from_code_generator_request(&request()).unwrap();
+        let read = |input: Vec<u8>| source(&schema, "Person", &input[..]).unwrap().read();
+
+        // A pointer out of the segment
+        let mut b = Builder::default();
+        b.alloc(1);
+        b.0[0] = 100 << 2;
+        assert!(read(b.message()).is_err());
+
+        // Truncated messages
+        assert!(read(vec![0, 0]).is_err());
+        assert!(read(vec![0, 0, 0, 0, 2, 0, 0, 0, 0]).is_err());
+
+        // A friend that is its own friend
+        let mut b = Builder::default();
+        b.alloc(1);
+        let person = b.new_struct(0, 2, 5);
+        let friends = b.new_structs(person + 2 + 2, 1, 2, 5);
+        b.list_pointer(friends[0] + 2 + 2, friends[0] - 1, 7, 7);
+        match read(b.message()) {
+            Err(error::Error::Format { msg }) => {
+                assert_eq!(msg, "bad Cap'n Proto message: lists are nested too deeply")
+            }
+            r => panic!("expected an error, got {:?}", r),
+        }
+    }
+}
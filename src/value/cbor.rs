@@ -10,7 +10,10 @@ pub struct Source<R>(serde_cbor::de::Deserializer<serde_cbor::de::IoRead<R>>)
 where
     R: io::Read;
 
-pub struct Sink<W>(serde_cbor::ser::Serializer<serde_cbor::ser::IoWrite<W>>)
+pub struct Sink<W>(
+    serde_cbor::ser::Serializer<serde_cbor::ser::IoWrite<W>>,
+    bool,
+)
 where
     W: io::Write;
 
@@ -29,9 +32,26 @@ pub fn sink<W>(w: W) -> Sink<W>
 where
     W: io::Write,
 {
-    Sink(serde_cbor::ser::Serializer::new(
-        serde_cbor::ser::IoWrite::new(w),
-    ))
+    Sink(
+        serde_cbor::ser::Serializer::new(serde_cbor::ser::IoWrite::new(w)),
+        false,
+    )
+}
+
+/// Creates a sink that uses the core deterministic encoding of RFC 8949, section 4.2.1.
+///
+/// In addition to the shortest-form integers, floats and definite lengths that are always used,
+/// map keys are sorted by the bytewise lexicographic order of their encodings, and maps with
+/// duplicate keys are rejected.
+#[inline]
+pub fn sink_canonical<W>(w: W) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink(
+        serde_cbor::ser::Serializer::new(serde_cbor::ser::IoWrite::new(w)),
+        true,
+    )
 }
 
 impl<R> value::Source for Source<R>
@@ -56,10 +76,43 @@ where
 {
     #[inline]
     fn write(&mut self, v: value::Value) -> error::Result<()> {
+        let v = if self.1 { canonicalize(v)? } else { v };
         serde::Serialize::serialize(&v, &mut self.0).map_err(From::from)
     }
 }
 
+fn canonicalize(value: value::Value) -> error::Result<value::Value> {
+    match value {
+        value::Value::Sequence(v) => Ok(value::Value::Sequence(
+            v.into_iter()
+                .map(canonicalize)
+                .collect::<error::Result<Vec<_>>>()?,
+        )),
+        value::Value::Map(v) => {
+            let mut entries = v
+                .into_iter()
+                .map(|(k, v)| {
+                    let k = canonicalize(k)?;
+                    let encoded = serde_cbor::to_vec(&k)?;
+                    Ok((encoded, k, canonicalize(v)?))
+                })
+                .collect::<error::Result<Vec<_>>>()?;
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+            if let Some(w) = entries.windows(2).find(|w| w[0].0 == w[1].0) {
+                return Err(error::Error::Format {
+                    msg: format!("canonical CBOR cannot output duplicate map key: {}", w[0].1),
+                });
+            }
+
+            Ok(value::Value::Map(
+                entries.into_iter().map(|(_, k, v)| (k, v)).collect(),
+            ))
+        }
+        v => Ok(v),
+    }
+}
+
 impl<R> fmt::Debug for Source<R>
 where
     R: io::Read,
@@ -77,3 +130,48 @@ where
         f.debug_struct("CborSink").finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+
+    #[test]
+    fn test_canonical_key_order() {
+        let mut out = Vec::new();
+        sink_canonical(&mut out)
+            .write(value::Value::Map(vec![
+                (value::Value::String("aa".to_owned()), value::Value::U8(1)),
+                (value::Value::String("b".to_owned()), value::Value::U8(2)),
+                (value::Value::I32(-1), value::Value::U8(3)),
+                (value::Value::U64(10), value::Value::U8(4)),
+            ]))
+            .unwrap();
+        assert_eq!(
+            out,
+            vec![0xa4, 0x0a, 0x04, 0x20, 0x03, 0x61, b'b', 0x02, 0x62, b'a', b'a', 0x01]
+        );
+    }
+
+    #[test]
+    fn test_canonical_duplicate_keys() {
+        let mut out = Vec::new();
+        let result = sink_canonical(&mut out).write(value::Value::Map(vec![
+            (value::Value::U8(1), value::Value::Unit),
+            (value::Value::I64(1), value::Value::Unit),
+        ]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_bytes_as_byte_string() {
+        let mut out = Vec::new();
+        sink(&mut out)
+            .write(value::Value::Sequence(vec![
+                value::Value::Bytes(vec![1, 2, 3]),
+                value::Value::Sequence(vec![value::Value::U8(1)]),
+            ]))
+            .unwrap();
+        assert_eq!(out, vec![0x82, 0x43, 0x01, 0x02, 0x03, 0x81, 0x01]);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+
+/// A source that reads all records from each of the given sources in turn.
+///
+/// The sources are only created when the previous ones have been read, so that for example files
+/// are opened one at a time.
+pub struct Source<'a> {
+    current: Option<Box<dyn value::Source + 'a>>,
+    remaining: Box<dyn Iterator<Item = error::Result<Box<dyn value::Source + 'a>>> + 'a>,
+}
+
+#[inline]
+pub fn source<'a, I>(sources: I) -> Source<'a>
+where
+    I: IntoIterator<Item = error::Result<Box<dyn value::Source + 'a>>>,
+    I::IntoIter: 'a,
+{
+    Source {
+        current: None,
+        remaining: Box::new(sources.into_iter()),
+    }
+}
+
+impl<'a> value::Source for Source<'a> {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            if let Some(ref mut source) = self.current {
+                if let Some(v) = source.read()? {
+                    return Ok(Some(v));
+                }
+            }
+            match self.remaining.next() {
+                Some(source) => self.current = Some(source?),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Source<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConcatSource")
+            .field("reading", &self.current.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use std::cell;
+
+    #[test]
+    fn test_concat() {
+        let sources: Vec<error::Result<Box<dyn value::Source>>> = vec![
+            Ok(Box::new(value::json::source(&b"1 2"[..]))),
+            Ok(Box::new(value::json::source(&b""[..]))),
+            Ok(Box::new(value::raw::source(&b"three\n"[..]))),
+        ];
+        let mut source = source(sources);
+
+        assert_eq!(source.read().unwrap(), Some(value::Value::U64(1)));
+        assert_eq!(source.read().unwrap(), Some(value::Value::U64(2)));
+        assert_eq!(
+            source.read().unwrap(),
+            Some(value::Value::String("three".to_owned()))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_concat_lazy() {
+        let opened = cell::Cell::new(0);
+        let open = |i| -> error::Result<Box<dyn value::Source>> {
+            opened.set(opened.get() + 1);
+            if i == 0 {
+                Ok(Box::new(value::json::source(&b"1"[..])))
+            } else {
+                Err(error::Error::Message("no such file".to_owned()))
+            }
+        };
+        let mut source = source((0..2).map(open));
+
+        assert_eq!(opened.get(), 0);
+        assert_eq!(source.read().unwrap(), Some(value::Value::U64(1)));
+        assert_eq!(opened.get(), 1);
+        assert!(source.read().is_err());
+        assert_eq!(opened.get(), 2);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+
+/// The key of the map entry that marks a record as a control record.
+pub const KEY: &str = "@rq";
+
+/// A control record, which carries information about a stream of records rather than data.
+///
+/// Control records are maps with a `"@rq"` entry that names their type, like
+/// `{"@rq": "error", "message": "..."}` or `{"@rq": "eof"}`, so that chained rq instances can
+/// propagate errors through shell pipelines, where exit codes of earlier stages are usually lost.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Control {
+    /// The upstream stage failed with the given message.
+    Error(String),
+    /// The upstream stage wrote all of its records.
+    Eof,
+    /// A control record of a type that this version doesn't know.
+    Unknown(String),
+}
+
+/// A source that interprets the control records in the records of another source.
+///
+/// Error records make the source fail, an end-of-file record ends it, and records of unknown
+/// types are skipped.  All other records are passed on.
+pub struct Source<S>(S, bool);
+
+/// A source that adds control records to the records of another source, for writing to the next
+/// stage of a pipeline.
+///
+/// When the inner source fails, an error record is produced before the error itself, and when it
+/// ends, an end-of-file record is produced.
+pub struct Envelope<S> {
+    inner: S,
+    pending: Option<error::Error>,
+    done: bool,
+}
+
+#[inline]
+pub fn source<S>(inner: S) -> Source<S>
+where
+    S: value::Source,
+{
+    Source(inner, false)
+}
+
+#[inline]
+pub fn envelope<S>(inner: S) -> Envelope<S>
+where
+    S: value::Source,
+{
+    Envelope {
+        inner,
+        pending: None,
+        done: false,
+    }
+}
+
+impl Control {
+    /// Returns the control record that a value represents, if it is one.
+    pub fn parse(value: &value::Value) -> Option<Self> {
+        let entries = match *value {
+            value::Value::Map(ref entries) => entries,
+            _ => return None,
+        };
+        let get = |key: &str| {
+            entries
+                .iter()
+                .find(|(k, _)| *k == value::Value::String(key.to_owned()))
+                .map(|(_, v)| v)
+        };
+        match get(KEY)? {
+            value::Value::String(ref t) if t == "error" => {
+                let message = match get("message") {
+                    Some(value::Value::String(ref message)) => message.clone(),
+                    Some(v) => v.to_string(),
+                    None => "unknown error".to_owned(),
+                };
+                Some(Control::Error(message))
+            }
+            value::Value::String(ref t) if t == "eof" => Some(Control::Eof),
+            t => Some(Control::Unknown(t.to_string())),
+        }
+    }
+
+    /// Converts the control record into a value, for writing it.
+    pub fn to_value(&self) -> value::Value {
+        let key = value::Value::String(KEY.to_owned());
+        let s = |v: &str| value::Value::String(v.to_owned());
+        match *self {
+            Control::Error(ref message) => {
+                value::Value::Map(vec![(key, s("error")), (s("message"), s(message))])
+            }
+            Control::Eof => value::Value::Map(vec![(key, s("eof"))]),
+            Control::Unknown(ref t) => value::Value::Map(vec![(key, s(t))]),
+        }
+    }
+}
+
+/// Formats an error and its causes on one line, without repeating the prefix of errors that
+/// came from further upstream.
+fn message(error: &error::Error) -> String {
+    match *error {
+        error::Error::Upstream { ref msg } => msg.clone(),
+        ref e => e.chain(),
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while !self.1 {
+            let v = match self.0.read()? {
+                Some(v) => v,
+                None => break,
+            };
+            match Control::parse(&v) {
+                None => return Ok(Some(v)),
+                Some(Control::Error(msg)) => {
+                    self.1 = true;
+                    return Err(error::Error::Upstream { msg });
+                }
+                Some(Control::Eof) => self.1 = true,
+                Some(Control::Unknown(t)) => debug!("Skipping control record of type {}", t),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<S> value::Source for Envelope<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if let Some(e) = self.pending.take() {
+            return Err(e);
+        }
+        if self.done {
+            return Ok(None);
+        }
+        match self.inner.read() {
+            Ok(Some(v)) => Ok(Some(v)),
+            Ok(None) => {
+                self.done = true;
+                Ok(Some(Control::Eof.to_value()))
+            }
+            Err(e) => {
+                self.done = true;
+                let record = Control::Error(message(&e)).to_value();
+                self.pending = Some(e);
+                Ok(Some(record))
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for Source<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ControlSource")
+            .field("done", &self.1)
+            .finish()
+    }
+}
+
+impl<S> fmt::Debug for Envelope<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ControlEnvelope")
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    struct Records(Vec<error::Result<Value>>);
+
+    impl value::Source for Records {
+        fn read(&mut self) -> error::Result<Option<Value>> {
+            if self.0.is_empty() {
+                Ok(None)
+            } else {
+                self.0.remove(0).map(Some)
+            }
+        }
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut envelope = envelope(Records(vec![
+            Ok(Value::U64(1)),
+            Err(error::Error::Message("bad input".to_owned())),
+        ]));
+        assert_eq!(envelope.read().unwrap(), Some(Value::U64(1)));
+        let record = envelope.read().unwrap().unwrap();
+        assert_eq!(
+            Control::parse(&record),
+            Some(Control::Error("bad input".to_owned()))
+        );
+        assert!(envelope.read().is_err());
+        assert_eq!(envelope.read().unwrap(), None);
+
+        let mut envelope = super::envelope(Records(vec![Err(error::Error::Upstream {
+            msg: "bad input".to_owned(),
+        })]));
+        let record = envelope.read().unwrap().unwrap();
+        assert_eq!(
+            Control::parse(&record),
+            Some(Control::Error("bad input".to_owned()))
+        );
+
+        let mut envelope = super::envelope(Records(vec![]));
+        assert_eq!(envelope.read().unwrap(), Some(Control::Eof.to_value()));
+        assert_eq!(envelope.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_source() {
+        let unknown = Control::Unknown("progress".to_owned()).to_value();
+        let mut source = source(Records(vec![
+            Ok(Value::U64(1)),
+            Ok(unknown),
+            Ok(Control::Error("bad input".to_owned()).to_value()),
+        ]));
+        assert_eq!(source.read().unwrap(), Some(Value::U64(1)));
+        let error = source.read().unwrap_err();
+        assert_eq!(error.to_string(), "upstream error: bad input");
+        assert_eq!(source.read().unwrap(), None);
+
+        let mut source = super::source(Records(vec![
+            Ok(Control::Eof.to_value()),
+            Ok(Value::U64(2)),
+        ]));
+        assert_eq!(source.read().unwrap(), None);
+    }
+}
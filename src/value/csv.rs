@@ -5,16 +5,37 @@ use ordered_float;
 use std::fmt;
 use std::io;
 
-pub struct Source<R>(csv::StringRecordsIntoIter<R>)
+pub struct Source<R>(csv::StringRecordsIntoIter<R>, Option<NumberFormat>)
 where
     R: io::Read;
 
-pub struct Sink<W>(csv::Writer<W>)
+/// How numbers are written in a particular locale.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NumberFormat {
+    pub decimal_separator: char,
+    pub thousands_separators: &'static [char],
+}
+
+const CURRENCY_SYMBOLS: &[&str] = &[
+    "R$", "$", "€", "£", "¥", "₹", "₽", "₩", "₺", "₴", "CHF", "Fr.", "kr", "zł", "Kč", "Ft",
+];
+
+pub struct Sink<W>(csv::Writer<W>, Option<usize>)
 where
     W: io::Write;
 
 #[inline]
 pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    source_with_number_format(r, None)
+}
+
+/// Creates a CSV source that converts cells holding numbers in the given format into numeric
+/// values.  Thousands separators and a leading or trailing currency symbol are allowed.
+#[inline]
+pub fn source_with_number_format<R>(r: R, number_format: Option<NumberFormat>) -> Source<R>
 where
     R: io::Read,
 {
@@ -23,6 +44,7 @@ where
             .has_headers(false)
             .from_reader(r)
             .into_records(),
+        number_format,
     )
 }
 
@@ -31,7 +53,7 @@ pub fn sink<W>(w: W) -> Sink<W>
 where
     W: io::Write,
 {
-    Sink(csv::Writer::from_writer(w))
+    Sink(csv::Writer::from_writer(w), None)
 }
 
 impl<R> value::Source for Source<R>
@@ -43,7 +65,11 @@ where
         match self.0.next() {
             Some(Ok(v)) => Ok(Some(value::Value::Sequence(
                 v.iter()
-                    .map(|s| value::Value::String(s.to_string()))
+                    .map(|s| {
+                        self.1
+                            .and_then(|f| f.parse(s))
+                            .unwrap_or_else(|| value::Value::String(s.to_string()))
+                    })
                     .collect(),
             ))),
             Some(Err(e)) => Err(error::Error::from(e)),
@@ -52,6 +78,98 @@ where
     }
 }
 
+impl NumberFormat {
+    /// Looks up the number format of a locale like `de_DE` or `fr_FR.UTF-8`.
+    pub fn from_locale(locale: &str) -> error::Result<Self> {
+        const SPACES: &[char] = &[' ', '\u{a0}', '\u{202f}'];
+        const APOSTROPHES: &[char] = &['\'', '\u{2019}'];
+
+        let name = locale.split(|c| c == '.' || c == '@').next().unwrap_or("");
+        let (decimal_separator, thousands_separators) = match name {
+            "C" | "POSIX" => ('.', &[','][..]),
+            "de_CH" | "fr_CH" | "it_CH" | "rm_CH" => ('.', APOSTROPHES),
+            "de_DE" | "de_AT" | "de_LU" | "nl_NL" | "nl_BE" | "es_ES" | "es_AR" | "it_IT"
+            | "pt_BR" | "da_DK" | "id_ID" | "tr_TR" | "el_GR" | "ro_RO" | "hr_HR" | "sl_SI" => {
+                (',', &['.'][..])
+            }
+            "fr_FR" | "fr_BE" | "fr_CA" | "sv_SE" | "sv_FI" | "nb_NO" | "nn_NO" | "fi_FI"
+            | "pl_PL" | "cs_CZ" | "sk_SK" | "hu_HU" | "ru_RU" | "uk_UA" | "pt_PT" | "bg_BG"
+            | "et_EE" | "lt_LT" | "lv_LV" => (',', SPACES),
+            _ if name.starts_with("en_") || name.starts_with("zh_") || name.starts_with("ja_") => {
+                ('.', &[','][..])
+            }
+            _ => {
+                return Err(error::Error::Message(format!(
+                    "unsupported CSV locale: {}",
+                    locale
+                )))
+            }
+        };
+
+        Ok(Self {
+            decimal_separator,
+            thousands_separators,
+        })
+    }
+
+    /// Parses a number like `-1.234,50 €`, returning `None` if the string isn't a number.
+    fn parse(self, s: &str) -> Option<value::Value> {
+        let mut s = s.trim();
+        for symbol in CURRENCY_SYMBOLS {
+            if s.starts_with(symbol) {
+                s = s[symbol.len()..].trim_start();
+                break;
+            } else if s.ends_with(symbol) {
+                s = s[..s.len() - symbol.len()].trim_end();
+                break;
+            }
+        }
+
+        let (negative, s) = if let Some(rest) = s.strip_prefix('-') {
+            (true, rest)
+        } else {
+            (false, s.strip_prefix('+').unwrap_or(s))
+        };
+
+        let (integer, fraction) = match s.find(self.decimal_separator) {
+            Some(i) => (&s[..i], Some(&s[i + self.decimal_separator.len_utf8()..])),
+            None => (s, None),
+        };
+
+        // Thousands separators must separate groups of exactly three digits, so that e.g. `1.5`
+        // is not mistaken for fifteen in locales that use `.` as thousands separator
+        let groups = integer
+            .split(|c| self.thousands_separators.contains(&c))
+            .collect::<Vec<_>>();
+        let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        let valid_groups = groups.iter().all(|g| all_digits(g))
+            && (groups.len() == 1
+                || (groups[0].len() <= 3 && groups[1..].iter().all(|g| g.len() == 3)));
+        if !valid_groups || !fraction.map_or(true, all_digits) {
+            return None;
+        }
+
+        let mut normalized = String::with_capacity(s.len() + 1);
+        if negative {
+            normalized.push('-');
+        }
+        groups.iter().for_each(|g| normalized.push_str(g));
+
+        match fraction {
+            Some(fraction) => {
+                normalized.push('.');
+                normalized.push_str(fraction);
+                normalized.parse().ok().map(value::Value::from_f64)
+            }
+            None => normalized
+                .parse()
+                .map(value::Value::I64)
+                .or_else(|_| normalized.parse().map(value::Value::U64))
+                .ok(),
+        }
+    }
+}
+
 impl<W> value::Sink for Sink<W>
 where
     W: io::Write,
@@ -64,6 +182,20 @@ where
                     .into_iter()
                     .map(value_to_csv)
                     .collect::<error::Result<Vec<_>>>()?;
+                // Checked here rather than by the writer, which would have written part of the
+                // record already
+                match self.1 {
+                    Some(len) if len != record.len() => {
+                        return Err(error::Error::Format {
+                            msg: format!(
+                                "csv records must all have {} fields, got {}",
+                                len,
+                                record.len()
+                            ),
+                        })
+                    }
+                    _ => self.1 = Some(record.len()),
+                }
                 self.0.write_record(record)?;
                 Ok(())
             }
@@ -126,3 +258,62 @@ where
         f.debug_struct("CsvSink").finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn s(v: &str) -> value::Value {
+        value::Value::String(v.to_owned())
+    }
+
+    #[test]
+    fn test_locale_numbers() {
+        let input = "\"1.234,5 €\",-12,1.5,abc,\"12,\",$3\n";
+        let format = NumberFormat::from_locale("de_DE.UTF-8").unwrap();
+        let mut source = source_with_number_format(input.as_bytes(), Some(format));
+        assert_eq!(
+            source.read().unwrap(),
+            Some(value::Value::Sequence(vec![
+                value::Value::from_f64(1234.5),
+                value::Value::I64(-12),
+                s("1.5"),
+                s("abc"),
+                s("12,"),
+                value::Value::I64(3),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_locale_thousands_separators() {
+        let format = NumberFormat::from_locale("fr_FR").unwrap();
+        assert_eq!(
+            format.parse("1\u{202f}234\u{202f}567,25"),
+            Some(value::Value::from_f64(1_234_567.25))
+        );
+        assert_eq!(format.parse("1 23"), None);
+
+        let format = NumberFormat::from_locale("en_US").unwrap();
+        assert_eq!(
+            format.parse("18,446,744,073,709,551,615"),
+            Some(value::Value::U64(u64::MAX))
+        );
+        assert_eq!(format.parse("£ 0.5"), Some(value::Value::from_f64(0.5)));
+    }
+
+    #[test]
+    fn test_no_locale() {
+        let mut source = source("1,2.5\n".as_bytes());
+        assert_eq!(
+            source.read().unwrap(),
+            Some(value::Value::Sequence(vec![s("1"), s("2.5")]))
+        );
+    }
+
+    #[test]
+    fn test_unknown_locale() {
+        assert!(NumberFormat::from_locale("xx_XX").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::value;
+use ansi_term;
+use serde_json;
+use std::fmt;
+
+/// The differences between two values, as a list of changes at paths within the values.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Diff {
+    changes: Vec<(Vec<PathSegment>, Change)>,
+}
+
+/// A step from a value to one of its children.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PathSegment {
+    Index(usize),
+    Key(value::Value),
+}
+
+/// A single difference between the old and the new value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+    Added(value::Value),
+    Removed(value::Value),
+    Changed(value::Value, value::Value),
+}
+
+/// Computes the differences needed to turn `a` into `b`.
+///
+/// Sequences are compared element by element and maps are compared key by key, so a change deep
+/// inside a value is reported at its own path.  Values are compared with `Value`'s equality, so
+/// numbers with different representations but the same numeric value are not reported.
+pub fn diff(a: &value::Value, b: &value::Value) -> Diff {
+    let mut result = Diff::default();
+    diff_at(&mut Vec::new(), a, b, &mut result.changes);
+    result
+}
+
+fn diff_at(
+    path: &mut Vec<PathSegment>,
+    a: &value::Value,
+    b: &value::Value,
+    changes: &mut Vec<(Vec<PathSegment>, Change)>,
+) {
+    use crate::value::Value;
+
+    if a == b {
+        return;
+    }
+
+    match (a, b) {
+        (Value::Sequence(a), Value::Sequence(b)) => {
+            for (i, (a, b)) in a.iter().zip(b).enumerate() {
+                path.push(PathSegment::Index(i));
+                diff_at(path, a, b, changes);
+                path.pop();
+            }
+            for (i, a) in a.iter().enumerate().skip(b.len()) {
+                changes.push((
+                    child(path, PathSegment::Index(i)),
+                    Change::Removed(a.clone()),
+                ));
+            }
+            for (i, b) in b.iter().enumerate().skip(a.len()) {
+                changes.push((child(path, PathSegment::Index(i)), Change::Added(b.clone())));
+            }
+        }
+        (Value::Map(a), Value::Map(b)) => {
+            for (k, a) in a {
+                match b.iter().find(|(bk, _)| bk == k) {
+                    Some((_, b)) => {
+                        path.push(PathSegment::Key(k.clone()));
+                        diff_at(path, a, b, changes);
+                        path.pop();
+                    }
+                    None => changes.push((
+                        child(path, PathSegment::Key(k.clone())),
+                        Change::Removed(a.clone()),
+                    )),
+                }
+            }
+            for (k, b) in b {
+                if !a.iter().any(|(ak, _)| ak == k) {
+                    changes.push((
+                        child(path, PathSegment::Key(k.clone())),
+                        Change::Added(b.clone()),
+                    ));
+                }
+            }
+        }
+        _ => changes.push((path.clone(), Change::Changed(a.clone(), b.clone()))),
+    }
+}
+
+fn child(path: &[PathSegment], segment: PathSegment) -> Vec<PathSegment> {
+    let mut result = path.to_vec();
+    result.push(segment);
+    result
+}
+
+impl Diff {
+    /// Whether the two values were equal.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn changes(&self) -> &[(Vec<PathSegment>, Change)] {
+        &self.changes
+    }
+
+    /// Renders the diff like its `Display` implementation, but with ANSI colors for terminals.
+    pub fn to_colored_string(&self) -> String {
+        let mut result = String::new();
+        self.render(&mut result, true).unwrap();
+        result
+    }
+
+    fn render<W>(&self, w: &mut W, colored: bool) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        use ansi_term::Colour;
+
+        let style = |colour: Colour| {
+            if colored {
+                colour.normal()
+            } else {
+                ansi_term::Style::default()
+            }
+        };
+
+        for (path, change) in &self.changes {
+            let path = format_path(path);
+            match *change {
+                Change::Added(ref v) => {
+                    let line = format!("+ {}: {}", path, format_value(v));
+                    writeln!(w, "{}", style(Colour::Green).paint(line))?;
+                }
+                Change::Removed(ref v) => {
+                    let line = format!("- {}: {}", path, format_value(v));
+                    writeln!(w, "{}", style(Colour::Red).paint(line))?;
+                }
+                Change::Changed(ref a, ref b) => {
+                    let old = format!("- {}: {}", path, format_value(a));
+                    let new = format!("+ {}: {}", path, format_value(b));
+                    writeln!(w, "{}", style(Colour::Red).paint(old))?;
+                    writeln!(w, "{}", style(Colour::Green).paint(new))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.render(f, false)
+    }
+}
+
+fn format_path(path: &[PathSegment]) -> String {
+    if path.is_empty() {
+        return ".".to_owned();
+    }
+
+    let mut result = String::new();
+    for segment in path {
+        match *segment {
+            PathSegment::Index(i) => result.push_str(&format!("[{}]", i)),
+            PathSegment::Key(value::Value::String(ref k)) if is_identifier(k) => {
+                result.push('.');
+                result.push_str(k);
+            }
+            PathSegment::Key(ref k) => result.push_str(&format!("[{}]", format_value(k))),
+        }
+    }
+    result
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn format_value(v: &value::Value) -> String {
+    // Not all values can be represented as JSON, e.g. maps with non-string keys
+    serde_json::to_string(v).unwrap_or_else(|_| v.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    #[test]
+    fn test_equal() {
+        let a = Value::Map(vec![(s("a"), Value::I32(1))]);
+        let b = Value::Map(vec![(s("a"), Value::U64(1))]);
+        assert!(diff(&a, &b).is_empty());
+        assert_eq!(diff(&a, &b).to_string(), "");
+    }
+
+    #[test]
+    fn test_nested() {
+        let a = Value::Map(vec![
+            (s("name"), s("rq")),
+            (s("tags"), Value::Sequence(vec![s("a"), s("b")])),
+            (s("old key"), Value::Bool(true)),
+        ]);
+        let b = Value::Map(vec![
+            (s("name"), s("rq")),
+            (s("tags"), Value::Sequence(vec![s("a"), s("c"), s("d")])),
+            (s("new"), Value::Unit),
+        ]);
+
+        let d = diff(&a, &b);
+        assert_eq!(
+            d.changes(),
+            &[
+                (
+                    vec![PathSegment::Key(s("tags")), PathSegment::Index(1)],
+                    Change::Changed(s("b"), s("c"))
+                ),
+                (
+                    vec![PathSegment::Key(s("tags")), PathSegment::Index(2)],
+                    Change::Added(s("d"))
+                ),
+                (
+                    vec![PathSegment::Key(s("old key"))],
+                    Change::Removed(Value::Bool(true))
+                ),
+                (vec![PathSegment::Key(s("new"))], Change::Added(Value::Unit)),
+            ]
+        );
+        assert_eq!(
+            d.to_string(),
+            "- .tags[1]: \"b\"\n\
+             + .tags[1]: \"c\"\n\
+             + .tags[2]: \"d\"\n\
+             - [\"old key\"]: true\n\
+             + .new: null\n"
+        );
+    }
+
+    #[test]
+    fn test_root_change() {
+        let d = diff(&Value::I32(1), &s("1"));
+        assert_eq!(d.to_string(), "- .: 1\n+ .: \"1\"\n");
+        assert_eq!(
+            d.to_colored_string(),
+            "\u{1b}[31m- .: 1\u{1b}[0m\n\u{1b}[32m+ .: \"1\"\u{1b}[0m\n"
+        );
+    }
+}
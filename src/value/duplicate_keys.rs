@@ -0,0 +1,191 @@
+use crate::error;
+use crate::value;
+use crate::value::path;
+use serde_json;
+use std::collections;
+use std::str;
+
+/// What to do with maps that contain the same key more than once.
+///
+/// Sources like JSON and YAML keep all entries of such maps, which most sinks then write out as
+/// is, or with the last entry winning.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    /// Fail with an error that says where the duplicate key is.
+    Error,
+    /// Keep the value of the first entry.
+    First,
+    /// Keep the value of the last entry, at the position of the first entry.
+    Last,
+    /// Keep a sequence of all values, at the position of the first entry.
+    Collect,
+}
+
+/// A source that applies a `Policy` to all maps in the records.
+pub type Source<S> = value::transform::Source<S, Policy>;
+
+#[inline]
+pub fn source<S>(inner: S, policy: Policy) -> Source<S>
+where
+    S: value::Source,
+{
+    value::transform::source(inner, policy)
+}
+
+impl value::Transform for Policy {
+    #[inline]
+    fn transform(&mut self, v: value::Value) -> error::Result<Option<value::Value>> {
+        apply(v, *self).map(Some)
+    }
+}
+
+/// Applies the policy to all maps in the value.
+pub fn apply(value: value::Value, policy: Policy) -> error::Result<value::Value> {
+    apply_at(value, policy, &mut Vec::new())
+}
+
+fn apply_at(
+    value: value::Value,
+    policy: Policy,
+    at: &mut Vec<path::Segment>,
+) -> error::Result<value::Value> {
+    use crate::value::Value;
+
+    match value {
+        Value::Sequence(seq) => {
+            let mut result = Vec::with_capacity(seq.len());
+            for (i, v) in seq.into_iter().enumerate() {
+                at.push(path::Segment::Index(i as i64));
+                result.push(apply_at(v, policy, at)?);
+                at.pop();
+            }
+            Ok(Value::Sequence(result))
+        }
+        Value::Map(entries) => {
+            let mut indices: collections::HashMap<Value, usize> = collections::HashMap::new();
+            let mut result: Vec<(Value, Vec<Value>)> = Vec::with_capacity(entries.len());
+
+            for (k, v) in entries {
+                at.push(path::Segment::Key(key_name(&k)));
+                let v = apply_at(v, policy, at)?;
+                at.pop();
+
+                match indices.get(&k) {
+                    Some(&i) => {
+                        if policy == Policy::Error {
+                            return Err(error::Error::Message(format!(
+                                "duplicate key {} at {}",
+                                format_key(&k),
+                                path::Path::new(at.clone())
+                            )));
+                        }
+                        result[i].1.push(v);
+                    }
+                    None => {
+                        indices.insert(k.clone(), result.len());
+                        result.push((k, vec![v]));
+                    }
+                }
+            }
+
+            Ok(Value::Map(
+                result
+                    .into_iter()
+                    .map(|(k, mut values)| {
+                        let v = match policy {
+                            _ if values.len() == 1 => values.pop().unwrap(),
+                            Policy::Error | Policy::First => values.swap_remove(0),
+                            Policy::Last => values.pop().unwrap(),
+                            Policy::Collect => Value::Sequence(values),
+                        };
+                        (k, v)
+                    })
+                    .collect(),
+            ))
+        }
+        v => Ok(v),
+    }
+}
+
+fn key_name(key: &value::Value) -> String {
+    match *key {
+        value::Value::String(ref s) => s.clone(),
+        ref k => k.to_string(),
+    }
+}
+
+fn format_key(key: &value::Value) -> String {
+    serde_json::to_string(key).unwrap_or_else(|_| key.to_string())
+}
+
+impl str::FromStr for Policy {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Policy::Error),
+            "first" => Ok(Policy::First),
+            "last" => Ok(Policy::Last),
+            "collect" => Ok(Policy::Collect),
+            _ => Err(error::Error::Message(format!(
+                "unknown duplicate key policy {:?}, expected 'error', 'first', 'last' or 'collect'",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn record() -> Value {
+        Value::Map(vec![
+            (s("a"), Value::I32(1)),
+            (s("b"), Value::I32(2)),
+            (s("a"), Value::I32(3)),
+            (s("a"), Value::I32(4)),
+        ])
+    }
+
+    #[test]
+    fn test_first_last_collect() {
+        assert_eq!(
+            apply(record(), Policy::First).unwrap(),
+            Value::Map(vec![(s("a"), Value::I32(1)), (s("b"), Value::I32(2))])
+        );
+        assert_eq!(
+            apply(record(), Policy::Last).unwrap(),
+            Value::Map(vec![(s("a"), Value::I32(4)), (s("b"), Value::I32(2))])
+        );
+        assert_eq!(
+            apply(record(), Policy::Collect).unwrap(),
+            Value::Map(vec![
+                (
+                    s("a"),
+                    Value::Sequence(vec![Value::I32(1), Value::I32(3), Value::I32(4)])
+                ),
+                (s("b"), Value::I32(2)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        let value = Value::Map(vec![(s("x"), Value::Sequence(vec![Value::Unit, record()]))]);
+        match apply(value, Policy::Error) {
+            Err(error::Error::Message(msg)) => assert_eq!(msg, "duplicate key \"a\" at .x[1]"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(apply(Value::Map(vec![(s("a"), record())]), Policy::Error).is_err());
+        assert_eq!(
+            apply(Value::Map(vec![(s("a"), Value::Unit)]), Policy::Error).unwrap(),
+            Value::Map(vec![(s("a"), Value::Unit)])
+        );
+    }
+}
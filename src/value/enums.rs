@@ -0,0 +1,31 @@
+use crate::error;
+use std::str;
+
+/// How to output the values of enum fields of formats with schemas, like protobuf and Avro.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Style {
+    /// The bare name of the value, like `"RED"`.
+    #[default]
+    Symbol,
+    /// The name of the value qualified with the full name of its enum type, like
+    /// `".pkg.Color.RED"` for protobuf or `"pkg.Color.RED"` for Avro.
+    Qualified,
+    /// The number of the value in protobuf, or its index among the symbols in Avro.
+    Number,
+}
+
+impl str::FromStr for Style {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "symbol" => Ok(Style::Symbol),
+            "qualified" => Ok(Style::Qualified),
+            "number" => Ok(Style::Number),
+            _ => Err(error::Error::Message(format!(
+                "unknown enum style {:?}, expected 'symbol', 'qualified' or 'number'",
+                s
+            ))),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::error;
+use crate::value;
+use std::collections;
+use std::str;
+
+/// A set of field paths to keep in records, like a `google.protobuf.FieldMask`.
+///
+/// Paths are written like `a.b,c`: field names separated by dots, and paths separated by commas.
+/// A path selects a field and everything below it, so `a` makes `a.b` redundant.  Fields inside
+/// sequences are selected in every element, and paths that continue below a value that isn't a
+/// map select nothing.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FieldMask(collections::BTreeMap<String, FieldMask>);
+
+/// A source that prunes every record down to the fields of a `FieldMask`.
+pub type Source<S> = value::transform::Source<S, FieldMask>;
+
+#[inline]
+pub fn source<S>(inner: S, mask: FieldMask) -> Source<S>
+where
+    S: value::Source,
+{
+    value::transform::source(inner, mask)
+}
+
+impl FieldMask {
+    pub fn parse(s: &str) -> error::Result<Self> {
+        let mut mask = FieldMask::default();
+        for path in s.split(',').map(str::trim) {
+            let fields = path.split('.').collect::<Vec<_>>();
+            if fields.iter().any(|f| f.is_empty()) {
+                return Err(error::Error::Message(format!(
+                    "invalid field mask {:?}: empty field name in path {:?}",
+                    s, path
+                )));
+            }
+            mask.add(&fields);
+        }
+        Ok(mask)
+    }
+
+    fn add(&mut self, fields: &[&str]) {
+        if let Some((first, rest)) = fields.split_first() {
+            // An empty mask below a field means the whole field is kept
+            let is_new = !self.0.contains_key(*first);
+            let child = self.0.entry((*first).to_owned()).or_default();
+            if is_new || !child.0.is_empty() {
+                if rest.is_empty() {
+                    child.0.clear();
+                } else {
+                    child.add(rest);
+                }
+            }
+        }
+    }
+
+    /// Removes all fields from the value that are not selected by this mask.
+    pub fn apply(&self, value: value::Value) -> value::Value {
+        self.select(value).unwrap_or(value::Value::Unit)
+    }
+
+    fn select(&self, value: value::Value) -> Option<value::Value> {
+        use crate::value::Value;
+
+        match value {
+            Value::Map(entries) => Some(Value::Map(
+                entries
+                    .into_iter()
+                    .filter_map(|(k, v)| {
+                        let child = match k {
+                            Value::String(ref s) => self.0.get(s),
+                            Value::Char(c) => self.0.get(&c.to_string()),
+                            _ => None,
+                        }?;
+                        let v = if child.0.is_empty() {
+                            v
+                        } else {
+                            child.select(v)?
+                        };
+                        Some((k, v))
+                    })
+                    .collect(),
+            )),
+            Value::Sequence(seq) => Some(Value::Sequence(
+                seq.into_iter().filter_map(|v| self.select(v)).collect(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl str::FromStr for FieldMask {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl value::Transform for FieldMask {
+    #[inline]
+    fn transform(&mut self, v: value::Value) -> error::Result<Option<value::Value>> {
+        Ok(Some(self.apply(v)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            FieldMask::parse("a.b, a").unwrap(),
+            FieldMask::parse("a").unwrap()
+        );
+        assert_eq!(
+            FieldMask::parse("a,a.b").unwrap(),
+            FieldMask::parse("a").unwrap()
+        );
+        for mask in &["", "a,", ".a", "a..b"] {
+            assert!(FieldMask::parse(mask).is_err(), "{}", mask);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let value = Value::Map(vec![
+            (s("id"), Value::I32(1)),
+            (
+                s("user"),
+                Value::Map(vec![
+                    (s("name"), s("rq")),
+                    (s("email"), s("rq@example.com")),
+                ]),
+            ),
+            (
+                s("items"),
+                Value::Sequence(vec![
+                    Value::Map(vec![(s("sku"), s("x")), (s("qty"), Value::I32(2))]),
+                    Value::Map(vec![(s("qty"), Value::I32(3))]),
+                ]),
+            ),
+            (s("note"), s("dropped")),
+        ]);
+
+        let mask = FieldMask::parse("id,user.name,items.sku,note.text,missing").unwrap();
+        assert_eq!(
+            mask.apply(value),
+            Value::Map(vec![
+                (s("id"), Value::I32(1)),
+                (s("user"), Value::Map(vec![(s("name"), s("rq"))])),
+                (
+                    s("items"),
+                    Value::Sequence(vec![
+                        Value::Map(vec![(s("sku"), s("x"))]),
+                        Value::Map(vec![]),
+                    ])
+                ),
+            ])
+        );
+    }
+}
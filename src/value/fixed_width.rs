@@ -0,0 +1,329 @@
+use crate::error;
+use crate::value;
+use serde_json;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str;
+
+/// A source that reads lines of fixed-width text, like the exports of mainframe systems, as map
+/// records with one entry per column of a layout.
+#[derive(Debug)]
+pub struct Source<R>
+where
+    R: io::Read,
+{
+    lines: io::Lines<io::BufReader<R>>,
+    layout: Layout,
+    line: usize,
+}
+
+/// The columns of fixed-width records.
+///
+/// A layout is read from a JSON array of columns like
+/// `[{"name": "id", "offset": 0, "width": 6, "type": "integer"}, ...]`, where offsets and widths
+/// count characters and the type defaults to `string`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Layout {
+    columns: Vec<Column>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub offset: usize,
+    pub width: usize,
+    pub column_type: ColumnType,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColumnType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+}
+
+#[inline]
+pub fn source<R>(r: R, layout: Layout) -> Source<R>
+where
+    R: io::Read,
+{
+    use std::io::BufRead;
+    Source {
+        lines: io::BufReader::new(r).lines(),
+        layout,
+        line: 0,
+    }
+}
+
+impl Layout {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self { columns }
+    }
+
+    pub fn parse(s: &str) -> error::Result<Self> {
+        let error = |msg: String| error::Error::Message(format!("invalid layout: {}", msg));
+
+        let columns = match serde_json::from_str(s)? {
+            serde_json::Value::Array(columns) => columns,
+            _ => return Err(error("expected an array of columns".to_owned())),
+        };
+
+        let columns = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let name = column
+                    .get("name")
+                    .and_then(serde_json::Value::as_str)
+                    .ok_or_else(|| error(format!("column {} has no name", i)))?;
+                let number = |key: &str| {
+                    column
+                        .get(key)
+                        .and_then(serde_json::Value::as_u64)
+                        .and_then(|n| usize::try_from(n).ok())
+                        .ok_or_else(|| error(format!("column {:?} has no {}", name, key)))
+                };
+                let column_type = match column.get("type") {
+                    None => ColumnType::String,
+                    Some(serde_json::Value::String(t)) => t.parse()?,
+                    Some(_) => {
+                        return Err(error(format!("column {:?} has a bad type", name)));
+                    }
+                };
+                let offset = number("offset")?;
+                let width = number("width")?;
+                if offset.checked_add(width).is_none() {
+                    return Err(error(format!(
+                        "column {:?} ends past the largest offset",
+                        name
+                    )));
+                }
+                Ok(Column {
+                    name: name.to_owned(),
+                    offset,
+                    width,
+                    column_type,
+                })
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+
+        Ok(Self { columns })
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Splits a line into a map record according to this layout.
+    ///
+    /// Padding around each field is trimmed, and columns past the end of the line are empty.
+    /// Empty fields of non-string columns become `Unit`.
+    pub fn record(&self, line: &str) -> error::Result<value::Value> {
+        let chars = line.chars().collect::<Vec<_>>();
+        let entries = self
+            .columns
+            .iter()
+            .map(|column| {
+                let start = column.offset.min(chars.len());
+                let end = column.offset.saturating_add(column.width).min(chars.len());
+                let text = chars[start..end].iter().collect::<String>();
+                let value = column.value(text.trim())?;
+                Ok((value::Value::String(column.name.clone()), value))
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        Ok(value::Value::Map(entries))
+    }
+}
+
+impl Column {
+    fn value(&self, text: &str) -> error::Result<value::Value> {
+        let error = || error::Error::Format {
+            msg: format!(
+                "column {:?}: expected {} but got {:?}",
+                self.name, self.column_type, text
+            ),
+        };
+
+        if text.is_empty() && self.column_type != ColumnType::String {
+            return Ok(value::Value::Unit);
+        }
+
+        match self.column_type {
+            ColumnType::String => Ok(value::Value::String(text.to_owned())),
+            ColumnType::Integer => {
+                // Signed fields often carry an explicit plus sign
+                let number = text.strip_prefix('+').unwrap_or(text);
+                if let Ok(n) = number.parse() {
+                    Ok(value::Value::U64(n))
+                } else {
+                    number.parse().map(value::Value::I64).map_err(|_| error())
+                }
+            }
+            ColumnType::Float => text
+                .parse()
+                .map(value::Value::from_f64)
+                .map_err(|_| error()),
+            ColumnType::Boolean => match text.to_lowercase().as_str() {
+                "true" | "t" | "yes" | "y" | "1" => Ok(value::Value::Bool(true)),
+                "false" | "f" | "no" | "n" | "0" => Ok(value::Value::Bool(false)),
+                _ => Err(error()),
+            },
+        }
+    }
+}
+
+impl str::FromStr for ColumnType {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" => Ok(ColumnType::String),
+            "integer" => Ok(ColumnType::Integer),
+            "float" => Ok(ColumnType::Float),
+            "boolean" => Ok(ColumnType::Boolean),
+            _ => Err(error::Error::Message(format!(
+                "unknown column type {:?}; expected string, integer, float or boolean",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ColumnType::String => "string",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Boolean => "boolean",
+        };
+        f.write_str(name)
+    }
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.line += 1;
+                    // Trailing blank lines are common at the end of exports
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let line = line.strip_suffix('\r').unwrap_or(&line);
+                    return match self.layout.record(line) {
+                        Ok(record) => Ok(Some(record)),
+                        Err(error::Error::Format { msg }) => Err(error::Error::Format {
+                            msg: format!("line {}: {}", self.line, msg),
+                        }),
+                        Err(e) => Err(e),
+                    };
+                }
+                Some(Err(e)) => return Err(error::Error::from(e)),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    const LAYOUT: &str = r#"[
+        {"name": "id", "offset": 0, "width": 6, "type": "integer"},
+        {"name": "name", "offset": 6, "width": 10},
+        {"name": "balance", "offset": 16, "width": 8, "type": "float"},
+        {"name": "active", "offset": 24, "width": 1, "type": "boolean"}
+    ]"#;
+
+    #[test]
+    fn test_parse_layout() {
+        let layout = Layout::parse(LAYOUT).unwrap();
+        assert_eq!(layout.columns().len(), 4);
+        assert_eq!(
+            layout.columns()[1],
+            Column {
+                name: "name".to_owned(),
+                offset: 6,
+                width: 10,
+                column_type: ColumnType::String,
+            }
+        );
+
+        for layout in &[
+            "{}",
+            r#"[{"offset": 0, "width": 1}]"#,
+            r#"[{"name": "a", "width": 1}]"#,
+            r#"[{"name": "a", "offset": 0, "width": 1, "type": "date"}]"#,
+            r#"[{"name": "a", "offset": 18446744073709551615, "width": 1}]"#,
+        ] {
+            assert!(Layout::parse(layout).is_err(), "{}", layout);
+        }
+    }
+
+    #[test]
+    fn test_source() {
+        let input = "000042Ada       001234.5Y\r\n\
+                     \n\
+                     -00007Grace Hopp\n";
+        let mut source = source(input.as_bytes(), Layout::parse(LAYOUT).unwrap());
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![
+                (s("id"), Value::U64(42)),
+                (s("name"), s("Ada")),
+                (s("balance"), Value::from_f64(1234.5)),
+                (s("active"), Value::Bool(true)),
+            ]))
+        );
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![
+                (s("id"), Value::I64(-7)),
+                (s("name"), s("Grace Hopp")),
+                (s("balance"), Value::Unit),
+                (s("active"), Value::Unit),
+            ]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_bad_field() {
+        let mut source = source(
+            "000001x\n00000Xy\n".as_bytes(),
+            Layout::parse(LAYOUT).unwrap(),
+        );
+        assert!(source.read().is_ok());
+        assert_eq!(
+            source.read().unwrap_err().to_string(),
+            "format error: line 2: column \"id\": expected integer but got \"00000X\""
+        );
+    }
+    #[test]
+    fn test_record_past_the_end() {
+        let layout = Layout::new(vec![Column {
+            name: "a".to_owned(),
+            offset: usize::MAX,
+            width: 2,
+            column_type: ColumnType::String,
+        }]);
+        assert_eq!(
+            layout.record("abc").unwrap(),
+            Value::Map(vec![(s("a"), s(""))])
+        );
+    }
+}
@@ -0,0 +1,1151 @@
+//! FlatBuffers, decoded dynamically with a binary reflection schema, like `flatc --binary
+//! --schema` writes them to `.bfbs` files.
+//!
+//! Tables and structs are decoded as maps with their fields in the order that they are declared
+//! in.  Unset strings, vectors, tables and optional scalars are decoded as `Unit`, and other unset
+//! scalars as their defaults.  Unions are decoded as their `_type` field, with the name of the
+//! type, and the table itself.
+
+use crate::error;
+use crate::value;
+use ordered_float;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+/// How deeply tables, structs and vectors may be nested in a buffer.
+const MAX_DEPTH: usize = 64;
+
+/// The base types of `reflection.fbs`.
+mod base_type {
+    pub const UTYPE: u8 = 1;
+    pub const BOOL: u8 = 2;
+    pub const BYTE: u8 = 3;
+    pub const UBYTE: u8 = 4;
+    pub const SHORT: u8 = 5;
+    pub const USHORT: u8 = 6;
+    pub const INT: u8 = 7;
+    pub const UINT: u8 = 8;
+    pub const LONG: u8 = 9;
+    pub const ULONG: u8 = 10;
+    pub const FLOAT: u8 = 11;
+    pub const DOUBLE: u8 = 12;
+    pub const STRING: u8 = 13;
+    pub const VECTOR: u8 = 14;
+    pub const OBJ: u8 = 15;
+    pub const UNION: u8 = 16;
+    pub const ARRAY: u8 = 17;
+}
+
+/// The tables, structs and enums of a binary reflection schema.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    objects: Vec<Object>,
+    enums: Vec<Enum>,
+    root_table: Option<String>,
+}
+
+/// A source that reads a FlatBuffer of a table type from the whole input, or a stream of
+/// FlatBuffers that are each prefixed with their size, like `FinishSizePrefixed` writes them.
+pub struct Source<'a, R>
+where
+    R: io::Read,
+{
+    schema: &'a Schema,
+    object: usize,
+    input: R,
+    size_prefixed: bool,
+    enum_style: value::enums::Style,
+    done: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Object {
+    name: String,
+    /// The fields, by their IDs, without deprecated fields.
+    fields: Vec<Field>,
+    is_struct: bool,
+    bytesize: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Field {
+    name: String,
+    field_type: Type,
+    id: u16,
+    offset: u16,
+    default_integer: i64,
+    default_real: f64,
+    optional: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Type {
+    base_type: u8,
+    element: u8,
+    /// The index of the object of tables and structs, or of the enum of scalars and unions.
+    index: i32,
+    fixed_length: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Enum {
+    name: String,
+    /// The values, with the indexes of the objects of union members.
+    values: Vec<(i64, String, i32)>,
+}
+
+/// Reads the parts of a FlatBuffer, checking that they are in it.
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Table {
+    pos: usize,
+    vtable: usize,
+    vtable_len: usize,
+}
+
+/// Decodes the contents of a buffer, while keeping track of how much of it has been decoded, so
+/// that offsets that point to the same objects many times can't make it blow up.
+struct Decoder<'a> {
+    schema: &'a Schema,
+    reader: Reader<'a>,
+    enum_style: value::enums::Style,
+    budget: u64,
+}
+
+impl Schema {
+    /// Reads a binary reflection schema, like the `.bfbs` files that `flatc` writes.
+    pub fn from_bfbs(bytes: &[u8]) -> error::Result<Self> {
+        let r = Reader { buf: bytes };
+        let root = r.table(r.uoffset(0)?)?;
+
+        let mut objects = Vec::new();
+        for object in r.tables(&root, 0)? {
+            let mut fields = Vec::new();
+            for field in r.tables(&object, 1)? {
+                if r.scalar(&field, 6, 1)?.unwrap_or(0) != 0 {
+                    continue;
+                }
+                let field_type = match r.field(&field, 1)? {
+                    Some(pos) => r.reflection_type(&r.table(r.uoffset(pos)?)?)?,
+                    None => return Err(bad_schema("a field has no type".to_owned())),
+                };
+                fields.push(Field {
+                    name: r.string_field(&field, 0)?,
+                    field_type,
+                    id: r.scalar(&field, 2, 2)?.unwrap_or(0) as u16,
+                    offset: r.scalar(&field, 3, 2)?.unwrap_or(0) as u16,
+                    default_integer: r.scalar(&field, 4, 8)?.unwrap_or(0) as i64,
+                    default_real: f64::from_bits(r.scalar(&field, 5, 8)?.unwrap_or(0)),
+                    optional: r.scalar(&field, 11, 1)?.unwrap_or(0) != 0,
+                });
+            }
+            fields.sort_by_key(|f| f.id);
+            objects.push(Object {
+                name: r.string_field(&object, 0)?,
+                fields,
+                is_struct: r.scalar(&object, 2, 1)?.unwrap_or(0) != 0,
+                bytesize: r.scalar(&object, 4, 4)?.unwrap_or(0) as u32 as usize,
+            });
+        }
+
+        let mut enums = Vec::new();
+        for e in r.tables(&root, 1)? {
+            let mut values = Vec::new();
+            for v in r.tables(&e, 1)? {
+                let union_index = match r.field(&v, 3)? {
+                    Some(pos) => r.reflection_type(&r.table(r.uoffset(pos)?)?)?.index,
+                    None => -1,
+                };
+                values.push((
+                    r.scalar(&v, 1, 8)?.unwrap_or(0) as i64,
+                    r.string_field(&v, 0)?,
+                    union_index,
+                ));
+            }
+            enums.push(Enum {
+                name: r.string_field(&e, 0)?,
+                values,
+            });
+        }
+
+        let root_table = match r.field(&root, 4)? {
+            Some(pos) => Some(r.string_field(&r.table(r.uoffset(pos)?)?, 0)?),
+            None => None,
+        };
+        Ok(Schema {
+            objects,
+            enums,
+            root_table,
+        })
+    }
+
+    /// Looks up a table type by its full name like `MyGame.Monster`, or by its name like
+    /// `Monster` if no other type has that name.
+    fn table_index(&self, name: &str) -> Option<usize> {
+        let tables = || {
+            self.objects
+                .iter()
+                .enumerate()
+                .filter(|(_, o)| !o.is_struct)
+        };
+        tables()
+            .find(|(_, o)| o.name == name)
+            .map(|(i, _)| i)
+            .or_else(|| {
+                let mut matches = tables().filter(|(_, o)| o.name.rsplit('.').next() == Some(name));
+                match (matches.next(), matches.next()) {
+                    (Some((i, _)), None) => Some(i),
+                    _ => None,
+                }
+            })
+    }
+
+    /// The full name of the root table type of the schema, if it declares one.
+    pub fn root_table(&self) -> Option<&str> {
+        self.root_table.as_deref()
+    }
+}
+
+/// Creates a source for FlatBuffers of the table type with a name, from the first schema that has
+/// it.
+pub fn source<'a, R>(
+    schemas: &'a [Schema],
+    type_name: &str,
+    input: R,
+) -> error::Result<Source<'a, R>>
+where
+    R: io::Read,
+{
+    let (schema, object) = schemas
+        .iter()
+        .find_map(|schema| schema.table_index(type_name).map(|object| (schema, object)))
+        .ok_or_else(|| {
+            error::Error::Message(format!("unknown FlatBuffers table type {:?}", type_name))
+        })?;
+    Ok(Source {
+        schema,
+        object,
+        input,
+        size_prefixed: false,
+        enum_style: value::enums::Style::default(),
+        done: false,
+    })
+}
+
+impl<'a, R> Source<'a, R>
+where
+    R: io::Read,
+{
+    /// Whether the input is a stream of buffers that are each prefixed with their size as a
+    /// 4-byte little-endian number, instead of a single buffer.
+    pub fn size_prefixed(mut self, size_prefixed: bool) -> Self {
+        self.size_prefixed = size_prefixed;
+        self
+    }
+
+    /// How to output the values of enums.
+    pub fn enum_style(mut self, enum_style: value::enums::Style) -> Self {
+        self.enum_style = enum_style;
+        self
+    }
+}
+
+impl<'a, R> value::Source for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        use std::io::Read;
+
+        if self.done {
+            return Ok(None);
+        }
+        let mut buf = Vec::new();
+        if self.size_prefixed {
+            let mut len = [0; 4];
+            let mut read = 0;
+            while read < len.len() {
+                match self.input.read(&mut len[read..])? {
+                    0 if read == 0 => return Ok(None),
+                    0 => return Err(bad("its size is truncated".to_owned())),
+                    n => read += n,
+                }
+            }
+            let len = u64::from(u32::from_le_bytes(len));
+            (&mut self.input).take(len).read_to_end(&mut buf)?;
+            if buf.len() as u64 != len {
+                return Err(bad(format!("expected {} bytes", len)));
+            }
+        } else {
+            self.done = true;
+            self.input.read_to_end(&mut buf)?;
+            if buf.is_empty() {
+                return Ok(None);
+            }
+        }
+
+        let reader = Reader { buf: &buf };
+        let mut decoder = Decoder {
+            schema: self.schema,
+            reader,
+            enum_style: self.enum_style,
+            budget: buf.len() as u64 * 64 + 1024,
+        };
+        let root = reader.uoffset(0)?;
+        Ok(Some(decoder.table_value(self.object, root, 0)?))
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, pos: usize, len: usize) -> error::Result<&'a [u8]> {
+        pos.checked_add(len)
+            .and_then(|end| self.buf.get(pos..end))
+            .ok_or_else(|| bad("an offset points out of the buffer".to_owned()))
+    }
+
+    /// Reads a little-endian number of 1, 2, 4 or 8 bytes.
+    fn uint(&self, pos: usize, len: usize) -> error::Result<u64> {
+        Ok(self
+            .bytes(pos, len)?
+            .iter()
+            .rev()
+            .fold(0, |n, &b| (n << 8) | u64::from(b)))
+    }
+
+    /// Follows an offset, which is relative to where it is.
+    fn uoffset(&self, pos: usize) -> error::Result<usize> {
+        let offset = self.uint(pos, 4)? as usize;
+        pos.checked_add(offset)
+            .filter(|&target| target < self.buf.len())
+            .ok_or_else(|| bad("an offset points out of the buffer".to_owned()))
+    }
+
+    fn table(&self, pos: usize) -> error::Result<Table> {
+        let soffset = i64::from(self.uint(pos, 4)? as u32 as i32);
+        let vtable = usize::try_from(pos as i64 - soffset)
+            .map_err(|_| bad("a vtable is out of the buffer".to_owned()))?;
+        let vtable_len = self.uint(vtable, 2)? as usize;
+        if vtable_len < 4 || vtable_len % 2 != 0 {
+            return Err(bad(format!("a vtable has size {}", vtable_len)));
+        }
+        self.bytes(vtable, vtable_len)?;
+        Ok(Table {
+            pos,
+            vtable,
+            vtable_len,
+        })
+    }
+
+    /// Where a field of a table is, if it is set.
+    fn field(&self, table: &Table, slot: u16) -> error::Result<Option<usize>> {
+        let entry = 4 + 2 * usize::from(slot);
+        if entry + 2 > table.vtable_len {
+            return Ok(None);
+        }
+        match self.uint(table.vtable + entry, 2)? as usize {
+            0 => Ok(None),
+            offset => Ok(Some(table.pos + offset)),
+        }
+    }
+
+    fn scalar(&self, table: &Table, slot: u16, len: usize) -> error::Result<Option<u64>> {
+        match self.field(table, slot)? {
+            Some(pos) => Ok(Some(self.uint(pos, len)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn string(&self, pos: usize) -> error::Result<String> {
+        let len = self.uint(pos, 4)? as usize;
+        Ok(String::from_utf8(self.bytes(pos + 4, len)?.to_vec())?)
+    }
+
+    fn string_field(&self, table: &Table, slot: u16) -> error::Result<String> {
+        match self.field(table, slot)? {
+            Some(pos) => self.string(self.uoffset(pos)?),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// The start and length of a vector.
+    fn vector(&self, pos: usize, element_size: usize) -> error::Result<(usize, usize)> {
+        let len = self.uint(pos, 4)? as usize;
+        self.bytes(pos + 4, len.saturating_mul(element_size))?;
+        Ok((pos + 4, len))
+    }
+
+    /// The tables of a vector field of a table.
+    fn tables(&self, table: &Table, slot: u16) -> error::Result<Vec<Table>> {
+        let pos = match self.field(table, slot)? {
+            Some(pos) => self.uoffset(pos)?,
+            None => return Ok(Vec::new()),
+        };
+        let (start, len) = self.vector(pos, 4)?;
+        (0..len)
+            .map(|i| self.table(self.uoffset(start + 4 * i)?))
+            .collect()
+    }
+
+    fn reflection_type(&self, t: &Table) -> error::Result<Type> {
+        Ok(Type {
+            base_type: self.scalar(t, 0, 1)?.unwrap_or(0) as u8,
+            element: self.scalar(t, 1, 1)?.unwrap_or(0) as u8,
+            index: self.scalar(t, 2, 4)?.map_or(-1, |i| i as u32 as i32),
+            fixed_length: self.scalar(t, 3, 2)?.unwrap_or(0) as u16,
+        })
+    }
+}
+
+/// The size of a scalar of a base type.
+fn scalar_size(base_type: u8) -> Option<usize> {
+    match base_type {
+        base_type::UTYPE | base_type::BOOL | base_type::BYTE | base_type::UBYTE => Some(1),
+        base_type::SHORT | base_type::USHORT => Some(2),
+        base_type::INT | base_type::UINT | base_type::FLOAT => Some(4),
+        base_type::LONG | base_type::ULONG | base_type::DOUBLE => Some(8),
+        _ => None,
+    }
+}
+
+impl<'a> Decoder<'a> {
+    fn charge(&mut self, n: usize) -> error::Result<()> {
+        self.budget = self
+            .budget
+            .checked_sub(n as u64)
+            .ok_or_else(|| bad("it points to the same objects too often".to_owned()))?;
+        Ok(())
+    }
+
+    fn object(&self, index: i32) -> error::Result<&'a Object> {
+        let schema = self.schema;
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| schema.objects.get(i))
+            .ok_or_else(|| bad_schema(format!("unknown object {}", index)))
+    }
+
+    fn table_value(
+        &mut self,
+        object: usize,
+        pos: usize,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        if depth > MAX_DEPTH {
+            return Err(bad("tables are nested too deeply".to_owned()));
+        }
+        let object = self.object(object as i32)?;
+        let table = self.reader.table(pos)?;
+        self.charge(1)?;
+
+        let mut entries = Vec::with_capacity(object.fields.len());
+        for field in &object.fields {
+            let t = field.field_type;
+            let v = match t.base_type {
+                base_type::UNION => match self.union_type(&table, field)? {
+                    Some(member) => match self.reader.field(&table, field.id)? {
+                        Some(pos) => {
+                            let pos = self.reader.uoffset(pos)?;
+                            self.table_value(member, pos, depth + 1)?
+                        }
+                        None => value::Value::Unit,
+                    },
+                    None => value::Value::Unit,
+                },
+                base_type::VECTOR if t.element == base_type::UNION => {
+                    self.union_vector(&table, field, depth)?
+                }
+                base_type::STRING | base_type::VECTOR => {
+                    match self.reader.field(&table, field.id)? {
+                        Some(pos) => {
+                            let pos = self.reader.uoffset(pos)?;
+                            self.value(t, pos, depth)?
+                        }
+                        None => value::Value::Unit,
+                    }
+                }
+                base_type::OBJ => match self.reader.field(&table, field.id)? {
+                    Some(pos) if self.object(t.index)?.is_struct => self.value(t, pos, depth)?,
+                    Some(pos) => {
+                        let pos = self.reader.uoffset(pos)?;
+                        self.value(t, pos, depth)?
+                    }
+                    None => value::Value::Unit,
+                },
+                _ => match self.reader.field(&table, field.id)? {
+                    Some(pos) => self.value(t, pos, depth)?,
+                    None if field.optional => value::Value::Unit,
+                    None => self.default_value(field),
+                },
+            };
+            entries.push((value::Value::String(field.name.clone()), v));
+        }
+        Ok(value::Value::Map(entries))
+    }
+
+    /// The object of the member of a union that is set, from the `_type` field before it.
+    fn union_type(&self, table: &Table, field: &Field) -> error::Result<Option<usize>> {
+        let n = match field.id.checked_sub(1) {
+            Some(id) => self.reader.scalar(table, id, 1)?.unwrap_or(0),
+            None => 0,
+        };
+        self.union_member(field.field_type.index, n)
+    }
+
+    fn union_member(&self, enum_index: i32, n: u64) -> error::Result<Option<usize>> {
+        if n == 0 {
+            return Ok(None);
+        }
+        let e = usize::try_from(enum_index)
+            .ok()
+            .and_then(|i| self.schema.enums.get(i))
+            .ok_or_else(|| bad_schema(format!("unknown enum {}", enum_index)))?;
+        match e.values.iter().find(|(v, _, _)| *v as u64 == n) {
+            Some(&(_, _, index)) => Ok(Some(self.object(index).map(|_| index as usize)?)),
+            None => Err(bad(format!("{} has no member {}", e.name, n))),
+        }
+    }
+
+    fn union_vector(
+        &mut self,
+        table: &Table,
+        field: &Field,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        let (types_pos, pos) = match (
+            field
+                .id
+                .checked_sub(1)
+                .map(|id| self.reader.field(table, id))
+                .transpose()?
+                .flatten(),
+            self.reader.field(table, field.id)?,
+        ) {
+            (Some(types_pos), Some(pos)) => {
+                (self.reader.uoffset(types_pos)?, self.reader.uoffset(pos)?)
+            }
+            _ => return Ok(value::Value::Unit),
+        };
+        let (types, types_len) = self.reader.vector(types_pos, 1)?;
+        let (start, len) = self.reader.vector(pos, 4)?;
+        if types_len != len {
+            return Err(bad(
+                "a vector of unions has a different number of types".to_owned()
+            ));
+        }
+        self.charge(len)?;
+        let mut values = Vec::with_capacity(len.min(1024));
+        for i in 0..len {
+            let n = self.reader.uint(types + i, 1)?;
+            let v = match self.union_member(field.field_type.index, n)? {
+                Some(member) => {
+                    let pos = self.reader.uoffset(start + 4 * i)?;
+                    self.table_value(member, pos, depth + 1)?
+                }
+                None => value::Value::Unit,
+            };
+            values.push(v);
+        }
+        Ok(value::Value::Sequence(values))
+    }
+
+    /// Decodes a value at a position, which is where a scalar or a struct is, or where the
+    /// offset to a string, vector or table points to.
+    fn value(&mut self, t: Type, pos: usize, depth: usize) -> error::Result<value::Value> {
+        if depth > MAX_DEPTH {
+            return Err(bad("it is nested too deeply".to_owned()));
+        }
+        match t.base_type {
+            base_type::STRING => Ok(value::Value::String(self.reader.string(pos)?)),
+            base_type::OBJ => {
+                let object = self.object(t.index)?;
+                if object.is_struct {
+                    self.struct_value(object, pos, depth + 1)
+                } else {
+                    self.table_value(t.index as usize, pos, depth + 1)
+                }
+            }
+            base_type::VECTOR => {
+                let element = Type {
+                    base_type: t.element,
+                    element: 0,
+                    index: t.index,
+                    fixed_length: 0,
+                };
+                let inline = match t.element {
+                    base_type::OBJ if self.object(t.index)?.is_struct => {
+                        Some(self.object(t.index)?.bytesize)
+                    }
+                    base_type => scalar_size(base_type),
+                };
+                let (start, len) = self.reader.vector(pos, inline.unwrap_or(4))?;
+                self.charge(len)?;
+                let mut values = Vec::with_capacity(len.min(1024));
+                for i in 0..len {
+                    let v = match inline {
+                        Some(size) => self.value(element, start + i * size, depth + 1)?,
+                        None => {
+                            let pos = self.reader.uoffset(start + 4 * i)?;
+                            self.value(element, pos, depth + 1)?
+                        }
+                    };
+                    values.push(v);
+                }
+                Ok(value::Value::Sequence(values))
+            }
+            base_type => match scalar_size(base_type) {
+                Some(size) => {
+                    let bits = self.reader.uint(pos, size)?;
+                    Ok(self.scalar_value(t, bits))
+                }
+                None => Err(error::Error::Unimplemented {
+                    msg: format!("decoding FlatBuffers values of base type {}", base_type),
+                }),
+            },
+        }
+    }
+
+    fn struct_value(
+        &mut self,
+        object: &Object,
+        pos: usize,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        self.reader.bytes(pos, object.bytesize)?;
+        self.charge(1)?;
+        let mut entries = Vec::with_capacity(object.fields.len());
+        for field in &object.fields {
+            let t = field.field_type;
+            let pos = pos + usize::from(field.offset);
+            let v = if t.base_type == base_type::ARRAY {
+                let element = Type {
+                    base_type: t.element,
+                    element: 0,
+                    index: t.index,
+                    fixed_length: 0,
+                };
+                let size = match t.element {
+                    base_type::OBJ => self.object(t.index)?.bytesize,
+                    base_type => scalar_size(base_type).ok_or_else(|| {
+                        bad_schema(format!("an array has base type {}", base_type))
+                    })?,
+                };
+                let mut values = Vec::with_capacity(usize::from(t.fixed_length));
+                for i in 0..usize::from(t.fixed_length) {
+                    values.push(self.value(element, pos + i * size, depth + 1)?);
+                }
+                value::Value::Sequence(values)
+            } else {
+                self.value(t, pos, depth)?
+            };
+            entries.push((value::Value::String(field.name.clone()), v));
+        }
+        Ok(value::Value::Map(entries))
+    }
+
+    fn default_value(&self, field: &Field) -> value::Value {
+        let t = field.field_type;
+        let bits = match t.base_type {
+            base_type::FLOAT => u64::from((field.default_real as f32).to_bits()),
+            base_type::DOUBLE => field.default_real.to_bits(),
+            _ => field.default_integer as u64,
+        };
+        self.scalar_value(t, bits)
+    }
+
+    fn scalar_value(&self, t: Type, bits: u64) -> value::Value {
+        use crate::value::Value;
+
+        let v = match t.base_type {
+            base_type::BOOL => return Value::Bool(bits != 0),
+            base_type::FLOAT => {
+                return Value::F32(ordered_float::OrderedFloat(f32::from_bits(bits as u32)))
+            }
+            base_type::DOUBLE => {
+                return Value::F64(ordered_float::OrderedFloat(f64::from_bits(bits)))
+            }
+            base_type::BYTE => Value::I8(bits as i8),
+            base_type::UBYTE | base_type::UTYPE => Value::U8(bits as u8),
+            base_type::SHORT => Value::I16(bits as i16),
+            base_type::USHORT => Value::U16(bits as u16),
+            base_type::INT => Value::I32(bits as i32),
+            base_type::UINT => Value::U32(bits as u32),
+            base_type::LONG => Value::I64(bits as i64),
+            _ => Value::U64(bits),
+        };
+
+        // Integers of enum types, and the types of unions
+        let e = match usize::try_from(t.index)
+            .ok()
+            .and_then(|i| self.schema.enums.get(i))
+        {
+            Some(e) => e,
+            None => return v,
+        };
+        let n = match t.base_type {
+            base_type::BYTE => i64::from(bits as i8),
+            base_type::SHORT => i64::from(bits as i16),
+            base_type::INT => i64::from(bits as i32),
+            base_type::UBYTE | base_type::UTYPE => i64::from(bits as u8),
+            base_type::USHORT => i64::from(bits as u16),
+            base_type::UINT => i64::from(bits as u32),
+            _ => bits as i64,
+        };
+        match (
+            self.enum_style,
+            e.values.iter().find(|(value, _, _)| *value == n),
+        ) {
+            (value::enums::Style::Symbol, Some((_, symbol, _))) => Value::String(symbol.clone()),
+            (value::enums::Style::Qualified, Some((_, symbol, _))) => {
+                Value::String(format!("{}.{}", e.name, symbol))
+            }
+            _ => v,
+        }
+    }
+}
+
+fn bad(msg: String) -> error::Error {
+    error::Error::Format {
+        msg: format!("bad FlatBuffer: {}", msg),
+    }
+}
+
+fn bad_schema(msg: String) -> error::Error {
+    error::Error::Format {
+        msg: format!("bad FlatBuffers reflection schema: {}", msg),
+    }
+}
+
+impl<'a, R> fmt::Debug for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FlatBuffersSource")
+            .field("object", &self.schema.objects[self.object].name)
+            .field("size_prefixed", &self.size_prefixed)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    /// Lays out a FlatBuffer front to back, with each vtable just before its table, so that
+    /// offsets always point forward like they must.
+    struct Builder(Vec<u8>);
+
+    impl Builder {
+        fn new() -> Self {
+            Builder(vec![0; 4])
+        }
+
+        /// Appends a table with fields of the given sizes, where 0 leaves a field unset, points an
+        /// offset to it, and returns where it and its fields are.
+        fn table(&mut self, at: usize, sizes: &[usize]) -> (usize, Vec<usize>) {
+            let vtable = self.0.len();
+            let vtable_len = 4 + 2 * sizes.len();
+            let table_len = 4 + sizes.iter().sum::<usize>();
+            self.0.extend(&(vtable_len as u16).to_le_bytes());
+            self.0.extend(&(table_len as u16).to_le_bytes());
+            let mut offset = 4;
+            let mut positions = Vec::new();
+            for &size in sizes {
+                let entry = if size == 0 { 0 } else { offset };
+                self.0.extend(&(entry as u16).to_le_bytes());
+                positions.push(vtable + vtable_len + entry);
+                offset += size;
+            }
+            let table = self.0.len();
+            self.0.extend(&((table - vtable) as i32).to_le_bytes());
+            self.0.resize(table + table_len, 0);
+            self.point_to(at, table);
+            (table, positions)
+        }
+
+        fn set(&mut self, pos: usize, bytes: &[u8]) {
+            self.0[pos..pos + bytes.len()].copy_from_slice(bytes);
+        }
+
+        fn point_to(&mut self, at: usize, target: usize) {
+            self.set(at, &((target - at) as u32).to_le_bytes());
+        }
+
+        /// Points an offset to the next string or vector that is appended.
+        fn point(&mut self, at: usize) {
+            self.point_to(at, self.0.len());
+        }
+
+        fn string(&mut self, at: usize, s: &str) {
+            self.point(at);
+            self.0.extend(&(s.len() as u32).to_le_bytes());
+            self.0.extend(s.as_bytes());
+            self.0.push(0);
+        }
+
+        /// Appends a vector with elements of a size, and returns where they are.
+        fn vector(&mut self, at: usize, len: usize, size: usize) -> Vec<usize> {
+            self.point(at);
+            self.0.extend(&(len as u32).to_le_bytes());
+            let start = self.0.len();
+            self.0.resize(start + len * size, 0);
+            (0..len).map(|i| start + i * size).collect()
+        }
+
+        /// Appends a reflection `Type` table.
+        fn reflection_type(&mut self, at: usize, base_type: u8, element: u8, index: i32) {
+            let (_, t) = self.table(at, &[1, 1, 4, 2]);
+            self.set(t[0], &[base_type]);
+            self.set(t[1], &[element]);
+            self.set(t[2], &index.to_le_bytes());
+            self.set(t[3], &2u16.to_le_bytes());
+        }
+    }
+
+    const MONSTER: i32 = 0;
+    const VEC2: i32 = 1;
+    const WEAPON: i32 = 2;
+    const COLOR: i32 = 0;
+    const EQUIPMENT: i32 = 1;
+
+    /// The reflection schema of:
+    ///
+    /// ```fbs
+    /// namespace Game;
+    /// enum Color : byte { Red, Green, Blue }
+    /// struct Vec2 { x: float; y: float; }
+    /// table Weapon { name: string; damage: short; }
+    /// union Equipment { Weapon }
+    /// table Monster {
+    ///   pos: Vec2;
+    ///   hp: short = 100;
+    ///   name: string;
+    ///   inventory: [ubyte];
+    ///   color: Color = Blue;
+    ///   weapons: [Weapon];
+    ///   equipped: Equipment;
+    ///   mana: float (deprecated);
+    ///   speed: int = null;
+    ///   path: [Vec2];
+    /// }
+    /// root_type Monster;
+    /// ```
+    ///
+    /// Like `flatc` does, the fields are sorted by name.
+    fn bfbs() -> Vec<u8> {
+        let mut b = Builder::new();
+        let (_, schema) = b.table(0, &[4, 4, 0, 0, 4]);
+
+        // (name, base type, element, index, id, offset, default, deprecated, optional)
+        type FieldSpec = (&'static str, u8, u8, i32, u16, u16, i64, bool, bool);
+        let objects: Vec<(&str, bool, i32, Vec<FieldSpec>)> = vec![
+            (
+                "Game.Monster",
+                false,
+                0,
+                vec![
+                    ("color", base_type::BYTE, 0, COLOR, 4, 0, 2, false, false),
+                    (
+                        "equipped",
+                        base_type::UNION,
+                        0,
+                        EQUIPMENT,
+                        7,
+                        0,
+                        0,
+                        false,
+                        false,
+                    ),
+                    (
+                        "equipped_type",
+                        base_type::UTYPE,
+                        0,
+                        EQUIPMENT,
+                        6,
+                        0,
+                        0,
+                        false,
+                        false,
+                    ),
+                    ("hp", base_type::SHORT, 0, -1, 1, 0, 100, false, false),
+                    (
+                        "inventory",
+                        base_type::VECTOR,
+                        base_type::UBYTE,
+                        -1,
+                        3,
+                        0,
+                        0,
+                        false,
+                        false,
+                    ),
+                    ("mana", base_type::FLOAT, 0, -1, 8, 0, 0, true, false),
+                    ("name", base_type::STRING, 0, -1, 2, 0, 0, false, false),
+                    (
+                        "path",
+                        base_type::VECTOR,
+                        base_type::OBJ,
+                        VEC2,
+                        10,
+                        0,
+                        0,
+                        false,
+                        false,
+                    ),
+                    ("pos", base_type::OBJ, 0, VEC2, 0, 0, 0, false, false),
+                    ("speed", base_type::INT, 0, -1, 9, 0, 0, false, true),
+                    (
+                        "weapons",
+                        base_type::VECTOR,
+                        base_type::OBJ,
+                        WEAPON,
+                        5,
+                        0,
+                        0,
+                        false,
+                        false,
+                    ),
+                ],
+            ),
+            (
+                "Game.Vec2",
+                true,
+                8,
+                vec![
+                    ("x", base_type::FLOAT, 0, -1, 0, 0, 0, false, false),
+                    ("y", base_type::FLOAT, 0, -1, 1, 4, 0, false, false),
+                ],
+            ),
+            (
+                "Game.Weapon",
+                false,
+                0,
+                vec![
+                    ("damage", base_type::SHORT, 0, -1, 1, 0, 0, false, false),
+                    ("name", base_type::STRING, 0, -1, 0, 0, 0, false, false),
+                ],
+            ),
+        ];
+        let object_positions = b.vector(schema[0], objects.len(), 4);
+        let mut monster = 0;
+        for (at, (name, is_struct, bytesize, fields)) in object_positions.into_iter().zip(objects) {
+            let (table, object) = b.table(at, &[4, 4, 1, 0, 4]);
+            if monster == 0 {
+                monster = table;
+            }
+            b.set(object[2], &[is_struct as u8]);
+            b.set(object[4], &bytesize.to_le_bytes());
+            b.string(object[0], name);
+            let field_positions = b.vector(object[1], fields.len(), 4);
+            for (at, spec) in field_positions.into_iter().zip(fields) {
+                let (name, base, element, index, id, offset, default, deprecated, optional) = spec;
+                let (_, field) = b.table(at, &[4, 4, 2, 2, 8, 0, 1, 0, 0, 0, 0, 1]);
+                b.set(field[2], &id.to_le_bytes());
+                b.set(field[3], &offset.to_le_bytes());
+                b.set(field[4], &default.to_le_bytes());
+                b.set(field[6], &[deprecated as u8]);
+                b.set(field[11], &[optional as u8]);
+                b.string(field[0], name);
+                b.reflection_type(field[1], base, element, index);
+            }
+        }
+        b.point_to(schema[4], monster);
+
+        // (name, values as (name, value, union member))
+        type EnumSpec = (&'static str, Vec<(&'static str, i64, i32)>);
+        let enums: Vec<EnumSpec> = vec![
+            (
+                "Game.Color",
+                vec![("Red", 0, -1), ("Green", 1, -1), ("Blue", 2, -1)],
+            ),
+            (
+                "Game.Equipment",
+                vec![("NONE", 0, -1), ("Weapon", 1, WEAPON)],
+            ),
+        ];
+        let enum_positions = b.vector(schema[1], enums.len(), 4);
+        for (at, (name, values)) in enum_positions.into_iter().zip(enums) {
+            let (_, e) = b.table(at, &[4, 4]);
+            b.string(e[0], name);
+            let value_positions = b.vector(e[1], values.len(), 4);
+            for (at, (name, value, union_index)) in value_positions.into_iter().zip(values) {
+                let (_, v) = b.table(at, &[4, 8, 0, 4]);
+                b.set(v[1], &value.to_le_bytes());
+                b.string(v[0], name);
+                b.reflection_type(v[3], base_type::OBJ, 0, union_index);
+            }
+        }
+        b.0
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = Schema::from_bfbs(&bfbs()).unwrap();
+        assert_eq!(schema.root_table(), Some("Game.Monster"));
+        assert_eq!(schema.table_index("Game.Weapon"), Some(WEAPON as usize));
+        assert_eq!(schema.table_index("Monster"), Some(MONSTER as usize));
+        assert_eq!(schema.table_index("Vec2"), None);
+
+        let monster = &schema.objects[MONSTER as usize];
+        assert_eq!(
+            monster
+                .fields
+                .iter()
+                .map(|f| f.name.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "pos",
+                "hp",
+                "name",
+                "inventory",
+                "color",
+                "weapons",
+                "equipped_type",
+                "equipped",
+                "speed",
+                "path"
+            ]
+        );
+        assert_eq!(
+            schema.enums[EQUIPMENT as usize].values[1],
+            (1, "Weapon".to_owned(), WEAPON)
+        );
+    }
+
+    /// A Monster as the root table, with the slots of its fields in the order of their IDs.
+    fn monster(b: &mut Builder) {
+        let (_, m) = b.table(0, &[8, 2, 4, 4, 0, 4, 1, 4, 0, 0, 4]);
+        b.set(m[0], &1.5f32.to_le_bytes());
+        b.set(m[0] + 4, &(-2f32).to_le_bytes());
+        b.set(m[1], &300i16.to_le_bytes());
+        b.string(m[2], "Orc");
+        let inventory = b.vector(m[3], 3, 1);
+        b.set(inventory[0], &[1, 2, 3]);
+        let weapons = b.vector(m[5], 2, 4);
+        for (at, (name, damage)) in weapons.into_iter().zip(vec![("Axe", 5i16), ("Bow", 3)]) {
+            let (_, w) = b.table(at, &[4, 2]);
+            b.set(w[1], &damage.to_le_bytes());
+            b.string(w[0], name);
+        }
+        b.set(m[6], &[1]);
+        let (_, w) = b.table(m[7], &[4]);
+        b.string(w[0], "Sword");
+        let path = b.vector(m[10], 2, 8);
+        b.set(path[1], &1f32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_source() {
+        let schema = Schema::from_bfbs(&bfbs()).unwrap();
+        let schemas = vec![schema];
+        let mut b = Builder::new();
+        monster(&mut b);
+
+        let s = |s: &str| Value::String(s.to_owned());
+        let f32 = |f: f32| Value::F32(ordered_float::OrderedFloat(f));
+        let vec2 = |x: f32, y: f32| Value::Map(vec![(s("x"), f32(x)), (s("y"), f32(y))]);
+        let expected = Value::Map(vec![
+            (s("pos"), vec2(1.5, -2.0)),
+            (s("hp"), Value::I16(300)),
+            (s("name"), s("Orc")),
+            (
+                s("inventory"),
+                Value::Sequence(vec![Value::U8(1), Value::U8(2), Value::U8(3)]),
+            ),
+            (s("color"), s("Blue")),
+            (
+                s("weapons"),
+                Value::Sequence(vec![
+                    Value::Map(vec![(s("name"), s("Axe")), (s("damage"), Value::I16(5))]),
+                    Value::Map(vec![(s("name"), s("Bow")), (s("damage"), Value::I16(3))]),
+                ]),
+            ),
+            (s("equipped_type"), s("Weapon")),
+            (
+                s("equipped"),
+                Value::Map(vec![(s("name"), s("Sword")), (s("damage"), Value::I16(0))]),
+            ),
+            (s("speed"), Value::Unit),
+            (
+                s("path"),
+                Value::Sequence(vec![vec2(0.0, 0.0), vec2(1.0, 0.0)]),
+            ),
+        ]);
+
+        let mut source = source(&schemas, "Monster", &b.0[..]).unwrap();
+        assert_eq!(source.read().unwrap(), Some(expected));
+        assert_eq!(source.read().unwrap(), None);
+
+        // Two size-prefixed buffers, the second an empty Monster
+        let mut input = (b.0.len() as u32).to_le_bytes().to_vec();
+        input.extend(&b.0);
+        let mut b = Builder::new();
+        b.table(0, &[]);
+        input.extend(&(b.0.len() as u32).to_le_bytes());
+        input.extend(&b.0);
+        let mut source = super::source(&schemas, "Game.Monster", &input[..])
+            .unwrap()
+            .size_prefixed(true)
+            .enum_style(value::enums::Style::Qualified);
+        match source.read().unwrap() {
+            Some(Value::Map(entries)) => assert_eq!(entries[4].1, s("Game.Color.Blue")),
+            v => panic!("expected a map, got {:?}", v),
+        }
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![
+                (s("pos"), Value::Unit),
+                (s("hp"), Value::I16(100)),
+                (s("name"), Value::Unit),
+                (s("inventory"), Value::Unit),
+                (s("color"), s("Game.Color.Blue")),
+                (s("weapons"), Value::Unit),
+                (s("equipped_type"), s("Game.Equipment.NONE")),
+                (s("equipped"), Value::Unit),
+                (s("speed"), Value::Unit),
+                (s("path"), Value::Unit),
+            ]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+
+        assert!(super::source(&schemas, "Vec2", &input[..]).is_err());
+    }
+
+    #[test]
+    fn test_source_errors() {
+        let schemas = vec![Schema::from_bfbs(&bfbs()).unwrap()];
+        let error = |input: &[u8]| match source(&schemas, "Monster", input).unwrap().read() {
+            Err(error::Error::Format { msg }) => msg,
+            r => panic!("expected an error, got {:?}", r),
+        };
+
+        assert_eq!(
+            error(&[100, 0, 0, 0]),
+            "bad FlatBuffer: an offset points out of the buffer"
+        );
+        let mut b = Builder::new();
+        let (_, m) = b.table(0, &[0, 0, 4]);
+        b.set(m[2], &1000u32.to_le_bytes());
+        assert_eq!(
+            error(&b.0),
+            "bad FlatBuffer: an offset points out of the buffer"
+        );
+
+        // A weapon as a union member of type 2, which doesn't exist
+        let mut b = Builder::new();
+        let (_, m) = b.table(0, &[0, 0, 0, 0, 0, 0, 1, 4]);
+        b.set(m[6], &[2]);
+        b.table(m[7], &[]);
+        assert_eq!(
+            error(&b.0),
+            "bad FlatBuffer: Game.Equipment has no member 2"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use crate::error;
+use crate::value;
+use serde;
+use serde_yaml;
+use std::io;
+use toml;
+
+/// A source that reads a document with front matter, like a Markdown post of a static site
+/// generator, as a single record with the parsed front matter under `meta` and the rest of the
+/// document under `body`.
+///
+/// Front matter is YAML between lines of `---` (or ending with `...`), or TOML between lines of
+/// `+++`, at the very start of the document.  Documents without front matter have a `Unit`
+/// `meta`.
+#[derive(Debug)]
+pub struct Source(Option<String>);
+
+#[inline]
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut string = String::new();
+    r.read_to_string(&mut string)?;
+    Ok(Source(Some(string)))
+}
+
+/// Splits a document into its parsed front matter and its body.
+pub fn split(document: &str) -> error::Result<(value::Value, &str)> {
+    let document = document.strip_prefix('\u{feff}').unwrap_or(document);
+
+    let mut lines = document.split_inclusive('\n');
+    let (is_toml, mut offset) = match lines.next() {
+        Some(line) if line.trim_end() == "---" => (false, line.len()),
+        Some(line) if line.trim_end() == "+++" => (true, line.len()),
+        _ => return Ok((value::Value::Unit, document)),
+    };
+    let start = offset;
+
+    for line in lines {
+        let end = offset;
+        offset += line.len();
+        let closes = match line.trim_end() {
+            "+++" => is_toml,
+            "---" | "..." => !is_toml,
+            _ => false,
+        };
+        if closes {
+            let meta = parse_meta(&document[start..end], is_toml)?;
+            return Ok((meta, &document[offset..]));
+        }
+    }
+
+    Err(error::Error::Format {
+        msg: "the front matter is not terminated".to_owned(),
+    })
+}
+
+fn parse_meta(meta: &str, is_toml: bool) -> error::Result<value::Value> {
+    if meta.trim().is_empty() {
+        Ok(value::Value::Map(Vec::new()))
+    } else if is_toml {
+        let de = toml::de::Deserializer::new(meta);
+        Ok(serde::Deserialize::deserialize(de)?)
+    } else {
+        Ok(serde_yaml::from_str(meta)?)
+    }
+}
+
+impl value::Source for Source {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.0.take() {
+            Some(document) => {
+                let (meta, body) = split(&document)?;
+                Ok(Some(value::Value::Map(vec![
+                    (value::Value::String("meta".to_owned()), meta),
+                    (
+                        value::Value::String("body".to_owned()),
+                        value::Value::String(body.to_owned()),
+                    ),
+                ])))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    #[test]
+    fn test_yaml() {
+        let document = "---\ntitle: Hello\ntags: [a]\n---\n# Hello\n\nText\n";
+        let mut source = source(document.as_bytes()).unwrap();
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![
+                (
+                    s("meta"),
+                    Value::Map(vec![
+                        (s("title"), s("Hello")),
+                        (s("tags"), Value::Sequence(vec![s("a")])),
+                    ])
+                ),
+                (s("body"), s("# Hello\n\nText\n")),
+            ]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_toml() {
+        let (meta, body) = split("+++\r\ndraft = true\r\n+++\r\nText").unwrap();
+        assert_eq!(meta, Value::Map(vec![(s("draft"), Value::Bool(true))]));
+        assert_eq!(body, "Text");
+    }
+
+    #[test]
+    fn test_edge_cases() {
+        assert_eq!(split("Text\n---\n").unwrap(), (Value::Unit, "Text\n---\n"));
+        assert_eq!(split("").unwrap(), (Value::Unit, ""));
+        assert_eq!(
+            split("---\n...\nText").unwrap(),
+            (Value::Map(vec![]), "Text")
+        );
+        // A TOML block doesn't end at a YAML delimiter
+        assert!(split("+++\na = 1\n---\n").is_err());
+        assert!(split("---\na: [\n---\n").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::error;
+use crate::value;
+use git2;
+use std::fmt;
+use std::path;
+use std::vec;
+
+/// A source that reads the history of a git repository as one record per commit, newest first,
+/// starting at `HEAD`.
+///
+/// Each record has the `hash` of the commit, its `author` with `name` and `email`, the author
+/// `date` as an RFC 3339 string in UTC, the `message`, and the paths of the `files` that the
+/// commit changed compared to its first parent.
+pub struct LogSource {
+    repository: git2::Repository,
+    commits: vec::IntoIter<git2::Oid>,
+}
+
+/// Opens the repository that contains the given path, and lists the commits reachable from
+/// `HEAD`.
+pub fn log_source<P>(path: P) -> error::Result<LogSource>
+where
+    P: AsRef<path::Path>,
+{
+    let repository = git2::Repository::discover(path)?;
+    let commits = {
+        let mut revwalk = repository.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TIME)?;
+        revwalk.push_head()?;
+        revwalk.collect::<Result<Vec<_>, _>>()?
+    };
+    Ok(LogSource {
+        repository,
+        commits: commits.into_iter(),
+    })
+}
+
+impl LogSource {
+    fn commit_value(&self, oid: git2::Oid) -> error::Result<value::Value> {
+        let commit = self.repository.find_commit(oid)?;
+        let author = commit.author();
+        let parent_tree = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let diff =
+            self.repository
+                .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+        let files = diff
+            .deltas()
+            .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+            .map(|path| value::Value::String(path.to_string_lossy().into_owned()))
+            .collect();
+
+        Ok(value::Value::Map(vec![
+            (s("hash"), value::Value::String(oid.to_string())),
+            (
+                s("author"),
+                value::Value::Map(vec![
+                    (s("name"), lossy(author.name_bytes())),
+                    (s("email"), lossy(author.email_bytes())),
+                ]),
+            ),
+            (
+                s("date"),
+                value::Value::String(value::timestamp::rfc3339(author.when().seconds())),
+            ),
+            (s("message"), lossy(commit.message_bytes())),
+            (s("files"), value::Value::Sequence(files)),
+        ]))
+    }
+}
+
+fn s(v: &str) -> value::Value {
+    value::Value::String(v.to_owned())
+}
+
+fn lossy(bytes: &[u8]) -> value::Value {
+    value::Value::String(String::from_utf8_lossy(bytes).into_owned())
+}
+
+impl value::Source for LogSource {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.commits.next() {
+            Some(oid) => self.commit_value(oid).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl fmt::Debug for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GitLogSource")
+            .field("path", &self.repository.path())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn commit(repository: &git2::Repository, file: &str, message: &str, time: i64) {
+        fs::write(repository.workdir().unwrap().join(file), message).unwrap();
+        let mut index = repository.index().unwrap();
+        index.add_path(path::Path::new(file)).unwrap();
+        index.write().unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature =
+            git2::Signature::new("Ada", "ada@example.com", &git2::Time::new(time, 60)).unwrap();
+        let parent = repository.head().ok().map(|h| h.peel_to_commit().unwrap());
+        let parents = parent.iter().collect::<Vec<_>>();
+        repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_log() {
+        let dir = env::temp_dir().join(format!("rq-git-test-{}", process::id()));
+        let repository = git2::Repository::init(&dir).unwrap();
+        commit(&repository, "a.txt", "First\n", 1_000_000_000);
+        commit(&repository, "b.txt", "Second\n", 1_000_000_060);
+
+        let mut source = log_source(dir.join(".git")).unwrap();
+        let mut records = Vec::new();
+        while let Some(v) = source.read().unwrap() {
+            records.push(v);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let fields = |v: &Value| match *v {
+            Value::Map(ref entries) => entries.iter().skip(1).cloned().collect::<Vec<_>>(),
+            ref v => panic!("not a map: {:?}", v),
+        };
+        let author = Value::Map(vec![
+            (s("name"), s("Ada")),
+            (s("email"), s("ada@example.com")),
+        ]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            fields(&records[0]),
+            vec![
+                (s("author"), author.clone()),
+                (s("date"), s("2001-09-09T01:47:40Z")),
+                (s("message"), s("Second\n")),
+                (s("files"), Value::Sequence(vec![s("b.txt")])),
+            ]
+        );
+        assert_eq!(
+            fields(&records[1]),
+            vec![
+                (s("author"), author),
+                (s("date"), s("2001-09-09T01:46:40Z")),
+                (s("message"), s("First\n")),
+                (s("files"), Value::Sequence(vec![s("a.txt")])),
+            ]
+        );
+    }
+}
@@ -8,7 +8,7 @@ use serde;
 use serde_json;
 use std::fmt;
 use std::io;
-use std::str;
+use std::rc;
 
 pub struct Source<'de, R>(
     serde_json::StreamDeserializer<'de, serde_json::de::IoRead<R>, value::Value>,
@@ -16,37 +16,59 @@ pub struct Source<'de, R>(
 where
     R: io::Read;
 
-pub struct Sink<W, F>(W, F)
+pub struct Sink<W, F>
 where
     W: io::Write,
-    F: Clone + serde_json::ser::Formatter;
+    F: Clone + serde_json::ser::Formatter,
+{
+    writer: W,
+    formatter: F,
+    raw_strings: bool,
+    int64_as_string: bool,
+    /// The serialized record, which is reused between records.
+    buffer: Vec<u8>,
+}
 
+/// A formatter for colored, indented JSON.
+///
+/// Instead of wrapping every token in the escape codes of its style, the formatter remembers
+/// which style is active and only writes the (precomputed) codes that switch from that style to
+/// the next one, so that runs of tokens with the same style share one span.
 #[derive(Clone, Debug)]
 pub struct ReadableFormatter {
     current_indent: usize,
     is_in_object_key: bool,
     has_value: bool,
+    active: Token,
+    palette: rc::Rc<Palette>,
+}
 
-    null_style: ansi_term::Style,
-
-    true_style: ansi_term::Style,
-    false_style: ansi_term::Style,
-
-    number_style: ansi_term::Style,
-
-    string_quote_style: ansi_term::Style,
-    string_char_style: ansi_term::Style,
-    string_escape_style: ansi_term::Style,
+/// The kinds of tokens that have their own style.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Token {
+    Plain,
+    Null,
+    True,
+    False,
+    Number,
+    StringQuote,
+    StringChar,
+    StringEscape,
+    ArrayBracket,
+    ArrayComma,
+    ObjectBrace,
+    ObjectColon,
+    ObjectComma,
+    ObjectKeyQuote,
+    ObjectKeyChar,
+    ObjectKeyEscape,
+}
 
-    array_bracket_style: ansi_term::Style,
-    array_comma_style: ansi_term::Style,
+const TOKENS: usize = Token::ObjectKeyEscape as usize + 1;
 
-    object_brace_style: ansi_term::Style,
-    object_colon_style: ansi_term::Style,
-    object_comma_style: ansi_term::Style,
-    object_key_quote_style: ansi_term::Style,
-    object_key_char_style: ansi_term::Style,
-    object_key_escape_style: ansi_term::Style,
+/// The escape codes that switch from the style of one token to that of another.
+struct Palette {
+    transitions: Vec<Vec<u8>>,
 }
 
 #[inline]
@@ -62,7 +84,7 @@ pub fn sink_compact<W>(w: W) -> Sink<W, serde_json::ser::CompactFormatter>
 where
     W: io::Write,
 {
-    Sink(w, serde_json::ser::CompactFormatter)
+    Sink::new(w, serde_json::ser::CompactFormatter)
 }
 
 #[inline]
@@ -70,7 +92,7 @@ pub fn sink_readable<W>(w: W) -> Sink<W, ReadableFormatter>
 where
     W: io::Write,
 {
-    Sink(w, ReadableFormatter::new())
+    Sink::new(w, ReadableFormatter::new())
 }
 
 #[inline]
@@ -78,7 +100,63 @@ pub fn sink_indented<'a, W>(w: W) -> Sink<W, serde_json::ser::PrettyFormatter<'a
 where
     W: io::Write,
 {
-    Sink(w, serde_json::ser::PrettyFormatter::new())
+    Sink::new(w, serde_json::ser::PrettyFormatter::new())
+}
+
+impl<W, F> Sink<W, F>
+where
+    W: io::Write,
+    F: Clone + serde_json::ser::Formatter,
+{
+    fn new(writer: W, formatter: F) -> Self {
+        Sink {
+            writer,
+            formatter,
+            raw_strings: false,
+            int64_as_string: false,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Makes the sink write records that are strings verbatim, without JSON quotes or escapes,
+    /// while other records are still written as JSON.
+    #[inline]
+    pub fn raw_strings(mut self, raw_strings: bool) -> Self {
+        self.raw_strings = raw_strings;
+        self
+    }
+
+    /// Makes the sink write integers that JavaScript numbers can't represent exactly as strings,
+    /// so that the output is safe to parse with `JSON.parse`.
+    #[inline]
+    pub fn int64_as_string(mut self, int64_as_string: bool) -> Self {
+        self.int64_as_string = int64_as_string;
+        self
+    }
+}
+
+/// The largest integer that JavaScript numbers can represent exactly, `2^53 - 1`.
+pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Replaces the integers in the value that are outside of the range that JavaScript numbers can
+/// represent exactly with their decimal strings.
+pub fn unsafe_integers_to_strings(v: value::Value) -> value::Value {
+    use crate::value::Value;
+
+    match v {
+        Value::I64(n) if n.unsigned_abs() > MAX_SAFE_INTEGER => Value::String(n.to_string()),
+        Value::U64(n) if n > MAX_SAFE_INTEGER => Value::String(n.to_string()),
+        Value::Sequence(seq) => {
+            Value::Sequence(seq.into_iter().map(unsafe_integers_to_strings).collect())
+        }
+        Value::Map(entries) => Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k, unsafe_integers_to_strings(v)))
+                .collect(),
+        ),
+        v => v,
+    }
 }
 
 impl<'de, R> value::Source for Source<'de, R>
@@ -102,45 +180,169 @@ where
 {
     #[inline]
     fn write(&mut self, v: value::Value) -> error::Result<()> {
+        if self.raw_strings {
+            match v {
+                value::Value::String(ref s) => {
+                    self.writer.write_all(s.as_bytes())?;
+                    self.writer.write_all(b"\n")?;
+                    return Ok(());
+                }
+                value::Value::Char(c) => {
+                    writeln!(self.writer, "{}", c)?;
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+
+        let v = if self.int64_as_string {
+            unsafe_integers_to_strings(v)
+        } else {
+            v
+        };
+
+        // Serialize into a buffer that is reused between records, so that the many small writes
+        // of the formatter don't each go to the (possibly unbuffered) output.
+        self.buffer.clear();
         {
-            let mut serializer =
-                serde_json::ser::Serializer::with_formatter(&mut self.0, self.1.clone());
+            let mut serializer = serde_json::ser::Serializer::with_formatter(
+                &mut self.buffer,
+                self.formatter.clone(),
+            );
             serde::Serialize::serialize(&v, &mut serializer)?;
         }
-        self.0.write_all(b"\n")?;
+        self.buffer.push(b'\n');
+        self.writer.write_all(&self.buffer)?;
         Ok(())
     }
 }
 
-impl ReadableFormatter {
+impl Palette {
     fn new() -> Self {
         use ansi_term::{Colour, Style};
 
+        let style = |token| match token {
+            Token::Plain => Style::default(),
+
+            Token::Null => Colour::Black.dimmed().bold().italic(),
+
+            Token::True => Colour::Green.bold().italic(),
+            Token::False => Colour::Red.bold().italic(),
+
+            Token::Number => Colour::Blue.normal(),
+
+            Token::StringQuote => Colour::Green.dimmed(),
+            Token::StringChar => Colour::Green.normal(),
+            Token::StringEscape => Colour::Green.dimmed(),
+
+            Token::ArrayBracket => Style::default().bold(),
+            Token::ArrayComma => Style::default().bold(),
+
+            Token::ObjectBrace => Style::default().bold(),
+            Token::ObjectColon => Style::default().bold(),
+            Token::ObjectComma => Style::default().bold(),
+            Token::ObjectKeyQuote => Colour::Blue.dimmed(),
+            Token::ObjectKeyChar => Colour::Blue.normal(),
+            Token::ObjectKeyEscape => Colour::Blue.dimmed(),
+        };
+
+        let tokens = [
+            Token::Plain,
+            Token::Null,
+            Token::True,
+            Token::False,
+            Token::Number,
+            Token::StringQuote,
+            Token::StringChar,
+            Token::StringEscape,
+            Token::ArrayBracket,
+            Token::ArrayComma,
+            Token::ObjectBrace,
+            Token::ObjectColon,
+            Token::ObjectComma,
+            Token::ObjectKeyQuote,
+            Token::ObjectKeyChar,
+            Token::ObjectKeyEscape,
+        ];
+        let mut transitions = Vec::with_capacity(TOKENS * TOKENS);
+        for &from in &tokens {
+            for &to in &tokens {
+                let infix = style(from).infix(style(to)).to_string();
+                transitions.push(infix.into_bytes());
+            }
+        }
+
+        Palette { transitions }
+    }
+}
+
+impl ReadableFormatter {
+    fn new() -> Self {
         Self {
             current_indent: 0,
             is_in_object_key: false,
             has_value: false,
+            active: Token::Plain,
+            palette: rc::Rc::new(Palette::new()),
+        }
+    }
 
-            null_style: Colour::Black.dimmed().bold().italic(),
-
-            true_style: Colour::Green.bold().italic(),
-            false_style: Colour::Red.bold().italic(),
+    /// Switches to the style of the given token.
+    #[inline]
+    fn switch<W>(&mut self, writer: &mut W, token: Token) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        if self.active != token {
+            let transition =
+                &self.palette.transitions[self.active as usize * TOKENS + token as usize];
+            writer.write_all(transition)?;
+            self.active = token;
+        }
+        Ok(())
+    }
 
-            number_style: Colour::Blue.normal(),
+    /// Writes a token in its style.
+    #[inline]
+    fn paint<W>(&mut self, writer: &mut W, token: Token, s: &str) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.switch(writer, token)?;
+        writer.write_all(s.as_bytes())
+    }
 
-            string_quote_style: Colour::Green.dimmed(),
-            string_char_style: Colour::Green.normal(),
-            string_escape_style: Colour::Green.dimmed(),
+    /// Resets the style if a top-level value was just completed, so that every record ends in
+    /// the default style.
+    #[inline]
+    fn end_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        if self.current_indent == 0 {
+            self.switch(writer, Token::Plain)?;
+        }
+        Ok(())
+    }
 
-            array_bracket_style: Style::default().bold(),
-            array_comma_style: Style::default().bold(),
+    /// Starts a new line at the current indentation.  Styles are reset at the end of each line,
+    /// for pagers that handle lines separately.
+    #[inline]
+    fn newline<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.switch(writer, Token::Plain)?;
+        writer.write_all(b"\n")?;
+        indent(writer, self.current_indent)
+    }
 
-            object_brace_style: Style::default().bold(),
-            object_colon_style: Style::default().bold(),
-            object_comma_style: Style::default().bold(),
-            object_key_quote_style: Colour::Blue.dimmed(),
-            object_key_char_style: Colour::Blue.normal(),
-            object_key_escape_style: Colour::Blue.dimmed(),
+    #[inline]
+    fn string_token(&self, key: Token, value: Token) -> Token {
+        if self.is_in_object_key {
+            key
+        } else {
+            value
         }
     }
 
@@ -151,10 +353,9 @@ impl ReadableFormatter {
         W: io::Write + ?Sized,
         I: itoa::Integer,
     {
-        write!(writer, "{}", self.number_style.prefix())?;
+        self.switch(writer, Token::Number)?;
         itoa::write(&mut writer, value)?;
-        write!(writer, "{}", self.number_style.suffix())?;
-        Ok(())
+        self.end_value(writer)
     }
 
     /// Writes a floating point value like `-31.26e+12` to the
@@ -165,10 +366,15 @@ impl ReadableFormatter {
         W: io::Write + ?Sized,
         F: dtoa::Floating,
     {
-        write!(writer, "{}", self.number_style.prefix())?;
+        self.switch(writer, Token::Number)?;
         dtoa::write(&mut writer, value)?;
-        write!(writer, "{}", self.number_style.suffix())?;
-        Ok(())
+        self.end_value(writer)
+    }
+}
+
+impl fmt::Debug for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Palette").finish()
     }
 }
 
@@ -179,7 +385,8 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     where
         W: io::Write + ?Sized,
     {
-        write!(writer, "{}", self.null_style.paint("null")).map_err(From::from)
+        self.paint(writer, Token::Null, "null")?;
+        self.end_value(writer)
     }
 
     /// Writes a `true` or `false` value to the specified writer.
@@ -188,12 +395,12 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     where
         W: io::Write + ?Sized,
     {
-        let s = if value {
-            self.true_style.paint("true")
+        if value {
+            self.paint(writer, Token::True, "true")?;
         } else {
-            self.false_style.paint("false")
-        };
-        write!(writer, "{}", s).map_err(From::from)
+            self.paint(writer, Token::False, "false")?;
+        }
+        self.end_value(writer)
     }
 
     #[inline]
@@ -283,13 +490,8 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     where
         W: io::Write + ?Sized,
     {
-        let style = if self.is_in_object_key {
-            self.object_key_quote_style
-        } else {
-            self.string_quote_style
-        };
-
-        write!(writer, "{}", style.paint("\"")).map_err(From::from)
+        let token = self.string_token(Token::ObjectKeyQuote, Token::StringQuote);
+        self.paint(writer, token, "\"")
     }
 
     /// Called after each series of `write_string_fragment` and
@@ -299,13 +501,9 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     where
         W: io::Write + ?Sized,
     {
-        let style = if self.is_in_object_key {
-            self.object_key_quote_style
-        } else {
-            self.string_quote_style
-        };
-
-        write!(writer, "{}", style.paint("\"")).map_err(From::from)
+        let token = self.string_token(Token::ObjectKeyQuote, Token::StringQuote);
+        self.paint(writer, token, "\"")?;
+        self.end_value(writer)
     }
 
     /// Writes a string fragment that doesn't need any escaping to the
@@ -315,13 +513,8 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     where
         W: io::Write + ?Sized,
     {
-        let style = if self.is_in_object_key {
-            self.object_key_char_style
-        } else {
-            self.string_char_style
-        };
-
-        write!(writer, "{}", style.paint(fragment)).map_err(From::from)
+        let token = self.string_token(Token::ObjectKeyChar, Token::StringChar);
+        self.paint(writer, token, fragment)
     }
 
     /// Writes a character escape code to the specified writer.
@@ -336,11 +529,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     {
         use serde_json::ser::CharEscape::*;
 
-        let style = if self.is_in_object_key {
-            self.object_key_escape_style
-        } else {
-            self.string_escape_style
-        };
+        let token = self.string_token(Token::ObjectKeyEscape, Token::StringEscape);
 
         let s = match char_escape {
             Quote => "\\\"",
@@ -361,14 +550,12 @@ impl serde_json::ser::Formatter for ReadableFormatter {
                     HEX_DIGITS[(byte >> 4) as usize],
                     HEX_DIGITS[(byte & 0xF) as usize],
                 ];
-                let s = unsafe { str::from_utf8_unchecked(bytes) };
-
-                // Need to return early because of allocated String
-                return write!(writer, "{}", style.paint(s)).map_err(From::from);
+                self.switch(writer, token)?;
+                return writer.write_all(bytes);
             }
         };
 
-        write!(writer, "{}", style.paint(s)).map_err(From::from)
+        self.paint(writer, token, s)
     }
 
     /// Called before every array.  Writes a `[` to the specified
@@ -381,7 +568,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         self.current_indent += 1;
         self.has_value = false;
 
-        write!(writer, "{}", self.array_bracket_style.paint("[")).map_err(From::from)
+        self.paint(writer, Token::ArrayBracket, "[")
     }
 
     /// Called after every array.  Writes a `]` to the specified
@@ -394,11 +581,11 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         self.current_indent -= 1;
 
         if self.has_value {
-            writeln!(writer)?;
-            indent(writer, self.current_indent)?;
+            self.newline(writer)?;
         }
 
-        write!(writer, "{}", self.array_bracket_style.paint("]")).map_err(From::from)
+        self.paint(writer, Token::ArrayBracket, "]")?;
+        self.end_value(writer)
     }
 
     /// Called before every array value.  Writes a `,` if needed to
@@ -409,12 +596,10 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         W: io::Write + ?Sized,
     {
         if !first {
-            write!(writer, "{}", self.array_comma_style.paint(","))?;
+            self.paint(writer, Token::ArrayComma, ",")?;
         }
 
-        writeln!(writer)?;
-        indent(writer, self.current_indent)?;
-        Ok(())
+        self.newline(writer)
     }
 
     /// Called after every array value.
@@ -437,7 +622,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         self.current_indent += 1;
         self.has_value = false;
 
-        write!(writer, "{}", self.object_brace_style.paint("{")).map_err(From::from)
+        self.paint(writer, Token::ObjectBrace, "{")
     }
 
     /// Called after every object.  Writes a `}` to the specified
@@ -450,11 +635,11 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         self.current_indent -= 1;
 
         if self.has_value {
-            writeln!(writer)?;
-            indent(writer, self.current_indent)?;
+            self.newline(writer)?;
         }
 
-        write!(writer, "{}", self.object_brace_style.paint("}")).map_err(From::from)
+        self.paint(writer, Token::ObjectBrace, "}")?;
+        self.end_value(writer)
     }
 
     /// Called before every object key.
@@ -466,12 +651,10 @@ impl serde_json::ser::Formatter for ReadableFormatter {
         self.is_in_object_key = true;
 
         if !first {
-            write!(writer, "{}", self.object_comma_style.paint(","))?;
+            self.paint(writer, Token::ObjectComma, ",")?;
         }
 
-        writeln!(writer)?;
-        indent(writer, self.current_indent)?;
-        Ok(())
+        self.newline(writer)
     }
 
     /// Called after every object key.  A `:` should be written to the
@@ -494,7 +677,7 @@ impl serde_json::ser::Formatter for ReadableFormatter {
     where
         W: io::Write + ?Sized,
     {
-        write!(writer, "{}", self.object_colon_style.paint(": ")).map_err(From::from)
+        self.paint(writer, Token::ObjectColon, ": ")
     }
 
     /// Called after every object value.
@@ -537,3 +720,64 @@ where
         f.debug_struct("JsonSink").finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+    use crate::value::Value;
+
+    #[test]
+    fn test_int64_as_string() {
+        let mut output = Vec::new();
+        {
+            let mut sink = sink_compact(&mut output).int64_as_string(true);
+            sink.write(Value::Sequence(vec![
+                Value::U64(MAX_SAFE_INTEGER),
+                Value::U64(MAX_SAFE_INTEGER + 1),
+                Value::I64(-(MAX_SAFE_INTEGER as i64) - 1),
+                Value::Map(vec![(Value::String("id".to_owned()), Value::U64(u64::MAX))]),
+            ]))
+            .unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[9007199254740991,\"9007199254740992\",\"-9007199254740992\",\
+             {\"id\":\"18446744073709551615\"}]\n"
+        );
+    }
+
+    #[test]
+    fn test_readable() {
+        let mut output = Vec::new();
+        {
+            let mut sink = sink_readable(&mut output);
+            sink.write(Value::Map(vec![(
+                Value::String("a".to_owned()),
+                Value::Sequence(vec![Value::I64(1), Value::String("x\ty".to_owned())]),
+            )]))
+            .unwrap();
+            sink.write(Value::Bool(true)).unwrap();
+        }
+        let output = String::from_utf8(output).unwrap();
+
+        // The colon and bracket share a bold span, and every line ends in the default style
+        assert!(output.contains("\u{1b}[1m: [\u{1b}[0m\n"));
+        assert!(output.lines().all(|l| l.ends_with("\u{1b}[0m")));
+
+        let mut plain = String::new();
+        let mut in_escape = false;
+        for c in output.chars() {
+            match c {
+                '\u{1b}' => in_escape = true,
+                'm' if in_escape => in_escape = false,
+                c if !in_escape => plain.push(c),
+                _ => (),
+            }
+        }
+        assert_eq!(
+            plain,
+            "{\n  \"a\": [\n    1,\n    \"x\\ty\"\n  ]\n}\ntrue\n"
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use crate::error;
+use crate::value;
+use serde_json;
+use std::fmt;
+use std::io;
+
+/// A source that streams the elements of the array (or the values of the object) at a JSON
+/// pointer like `/items` in each JSON document, without reading the whole document into memory.
+///
+/// Only one element is held in memory at a time, so this works for documents that are much
+/// larger than memory, as long as the elements themselves are not.  If the pointer refers to a
+/// value that is not a container, that value is the only record of its document, and documents
+/// that don't contain the pointer produce no records.
+pub struct Source<R>
+where
+    R: io::Read,
+{
+    input: io::Bytes<io::BufReader<R>>,
+    peeked: Option<u8>,
+    pointer: Vec<String>,
+    /// The closing bytes of the containers around the current position.
+    enclosing: Vec<u8>,
+    state: State,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Document,
+    Elements { close: u8, first: bool },
+    Done,
+}
+
+#[inline]
+pub fn source<R>(r: R, pointer: &str) -> error::Result<Source<R>>
+where
+    R: io::Read,
+{
+    Ok(Source {
+        input: io::Read::bytes(io::BufReader::new(r)),
+        peeked: None,
+        pointer: parse(pointer)?,
+        enclosing: Vec::new(),
+        state: State::Document,
+    })
+}
+
+/// Parses a JSON pointer as defined in RFC 6901 into its reference tokens.
+pub fn parse(pointer: &str) -> error::Result<Vec<String>> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    match pointer.strip_prefix('/') {
+        Some(rest) => Ok(rest
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect()),
+        None => Err(error::Error::Message(format!(
+            "invalid JSON pointer {:?}: must be empty or start with '/'",
+            pointer
+        ))),
+    }
+}
+
+fn unexpected(what: &str) -> error::Error {
+    error::Error::Format {
+        msg: format!("unexpected {} in JSON input", what),
+    }
+}
+
+impl<R> Source<R>
+where
+    R: io::Read,
+{
+    fn peek(&mut self) -> error::Result<Option<u8>> {
+        if self.peeked.is_none() {
+            self.peeked = self.input.next().transpose()?;
+        }
+        Ok(self.peeked)
+    }
+
+    fn next(&mut self) -> error::Result<Option<u8>> {
+        self.peek()?;
+        Ok(self.peeked.take())
+    }
+
+    fn expect_next(&mut self) -> error::Result<u8> {
+        self.next()?.ok_or_else(|| unexpected("end"))
+    }
+
+    fn skip_whitespace(&mut self) -> error::Result<()> {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek()? {
+            self.peeked = None;
+        }
+        Ok(())
+    }
+
+    /// Reads a whole value, appending its bytes to `out` if there is one.
+    fn scan_value(&mut self, mut out: Option<&mut Vec<u8>>) -> error::Result<()> {
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escape = false;
+        let mut empty = true;
+
+        self.skip_whitespace()?;
+        loop {
+            let b = match self.peek()? {
+                Some(b) => b,
+                // A number at the very end of the input
+                None if depth == 0 && !in_string && !empty => return Ok(()),
+                None => return Err(unexpected("end")),
+            };
+            let scalar_ended = depth == 0
+                && !in_string
+                && matches!(b, b',' | b']' | b'}' | b' ' | b'\t' | b'\n' | b'\r');
+            if scalar_ended && empty {
+                // Not consuming anything here would make callers loop forever
+                return Err(unexpected(if b == b',' { "comma" } else { "bracket" }));
+            }
+            if scalar_ended {
+                return Ok(());
+            }
+            self.peeked = None;
+            empty = false;
+            if let Some(ref mut out) = out {
+                out.push(b);
+            }
+
+            if in_string {
+                match b {
+                    b'\\' if !escape => escape = true,
+                    b'"' if !escape => {
+                        in_string = false;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                    _ => escape = false,
+                }
+            } else {
+                match b {
+                    b'"' => in_string = true,
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' if depth == 0 => return Err(unexpected("bracket")),
+                    b']' | b'}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn read_value(&mut self) -> error::Result<value::Value> {
+        let mut bytes = Vec::new();
+        self.scan_value(Some(&mut bytes))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Reads an object key and the colon after it.
+    fn read_key(&mut self) -> error::Result<String> {
+        self.skip_whitespace()?;
+        if self.peek()? != Some(b'"') {
+            return Err(unexpected("object key"));
+        }
+        let mut bytes = Vec::new();
+        self.scan_value(Some(&mut bytes))?;
+        let key = serde_json::from_slice(&bytes)?;
+        self.skip_whitespace()?;
+        match self.expect_next()? {
+            b':' => Ok(key),
+            _ => Err(unexpected("character after object key")),
+        }
+    }
+
+    /// Moves past the separator before the next member of a container, returning `false` if the
+    /// container ends instead.
+    fn next_member(&mut self, close: u8, first: bool) -> error::Result<bool> {
+        self.skip_whitespace()?;
+        match self.peek()? {
+            Some(b) if b == close => {
+                self.peeked = None;
+                Ok(false)
+            }
+            Some(b',') if !first => {
+                self.peeked = None;
+                Ok(true)
+            }
+            Some(_) if first => Ok(true),
+            Some(_) => Err(unexpected("character between values")),
+            None => Err(unexpected("end")),
+        }
+    }
+
+    /// Finds the value at the pointer in the current document, leaving the input right before
+    /// it.  Returns `false` if there is no such value, in which case the document is skipped.
+    fn find_pointer(&mut self) -> error::Result<bool> {
+        self.enclosing.clear();
+        for i in 0..self.pointer.len() {
+            self.skip_whitespace()?;
+            let found = match self.peek()? {
+                Some(b'{') => {
+                    self.peeked = None;
+                    self.enclosing.push(b'}');
+                    let mut first = true;
+                    loop {
+                        if !self.next_member(b'}', first)? {
+                            self.enclosing.pop();
+                            break false;
+                        }
+                        first = false;
+                        if self.read_key()? == self.pointer[i] {
+                            break true;
+                        }
+                        self.scan_value(None)?;
+                    }
+                }
+                Some(b'[') => {
+                    self.peeked = None;
+                    self.enclosing.push(b']');
+                    let index = self.pointer[i].parse::<usize>().ok();
+                    let mut current = 0;
+                    loop {
+                        if !self.next_member(b']', current == 0)? {
+                            self.enclosing.pop();
+                            break false;
+                        }
+                        if Some(current) == index {
+                            break true;
+                        }
+                        self.scan_value(None)?;
+                        current += 1;
+                    }
+                }
+                _ => {
+                    self.scan_value(None)?;
+                    false
+                }
+            };
+
+            if !found {
+                self.leave_enclosing()?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Skips the rest of the containers around the current position.
+    fn leave_enclosing(&mut self) -> error::Result<()> {
+        while let Some(close) = self.enclosing.pop() {
+            while self.next_member(close, false)? {
+                if close == b'}' {
+                    self.read_key()?;
+                }
+                self.scan_value(None)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Document => {
+                    self.skip_whitespace()?;
+                    if self.peek()?.is_none() {
+                        self.state = State::Done;
+                        continue;
+                    }
+                    if !self.find_pointer()? {
+                        continue;
+                    }
+
+                    self.skip_whitespace()?;
+                    match self.peek()? {
+                        Some(b'[') => {
+                            self.peeked = None;
+                            self.state = State::Elements {
+                                close: b']',
+                                first: true,
+                            };
+                        }
+                        Some(b'{') => {
+                            self.peeked = None;
+                            self.state = State::Elements {
+                                close: b'}',
+                                first: true,
+                            };
+                        }
+                        _ => {
+                            let value = self.read_value()?;
+                            self.leave_enclosing()?;
+                            return Ok(Some(value));
+                        }
+                    }
+                }
+                State::Elements { close, first } => {
+                    if !self.next_member(close, first)? {
+                        self.leave_enclosing()?;
+                        self.state = State::Document;
+                        continue;
+                    }
+                    self.state = State::Elements {
+                        close,
+                        first: false,
+                    };
+                    if close == b'}' {
+                        self.read_key()?;
+                    }
+                    return self.read_value().map(Some);
+                }
+            }
+        }
+    }
+}
+
+impl<R> fmt::Debug for Source<R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JsonPointerSource")
+            .field("pointer", &self.pointer)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn read_all(input: &str, pointer: &str) -> error::Result<Vec<Value>> {
+        let mut source = source(input.as_bytes(), pointer)?;
+        let mut result = Vec::new();
+        while let Some(v) = source.read()? {
+            result.push(v);
+        }
+        Ok(result)
+    }
+
+    fn json(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("").unwrap(), Vec::<String>::new());
+        assert_eq!(parse("/a~1b/~01/").unwrap(), vec!["a/b", "~1", ""]);
+        assert!(parse("a").is_err());
+    }
+
+    #[test]
+    fn test_array() {
+        let input = r#"{"meta": {"items": "not these"}, "items": [1, {"a": "]}\""}, [2]], "x": 3}"#;
+        assert_eq!(
+            read_all(input, "/items").unwrap(),
+            vec![json("1"), json(r#"{"a": "]}\""}"#), json("[2]")]
+        );
+    }
+
+    #[test]
+    fn test_nested_and_objects() {
+        let input = r#"{"data": [{"skip": true}, {"rows": {"a": 1, "b": [true]}}]} {"data": []}"#;
+        assert_eq!(
+            read_all(input, "/data/1/rows").unwrap(),
+            vec![json("1"), json("[true]")]
+        );
+        assert_eq!(read_all(input, "/data/0/skip").unwrap(), vec![json("true")]);
+        assert_eq!(read_all(input, "/data/2").unwrap(), vec![]);
+        assert_eq!(
+            read_all("[1, 2] 3", "").unwrap(),
+            vec![json("1"), json("2"), json("3")]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(read_all(r#"{"items": [1, 2"#, "/items").is_err());
+        assert!(read_all(r#"{"items": [1 2]}"#, "/items").is_err());
+        assert!(read_all(r#"{"items" [1]}"#, "/items").is_err());
+        assert!(read_all(r#"{"items": [tru]}"#, "/items").is_err());
+        assert!(read_all(r#"{"items": [1,]}"#, "/items").is_err());
+        // Separators and brackets outside of any value
+        assert!(read_all("]", "/a").is_err());
+        assert!(read_all("}", "/a").is_err());
+        assert!(read_all("1,2", "/a").is_err());
+        assert!(read_all("]", "").is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use crate::value;
+use std::collections;
+use std::fmt;
+use std::io;
+
+/// A reader that rewrites lenient JSON into strict JSON as it is being read.
+///
+/// Supported extensions are `//` and `/* */` comments, trailing commas in arrays and objects,
+/// unquoted object keys and single-quoted strings, as found in e.g. `tsconfig.json` and editor
+/// settings files.  Anything else is passed through for the JSON parser to report.
+pub struct Reader<R>
+where
+    R: io::Read,
+{
+    input: io::Bytes<io::BufReader<R>>,
+    state: State,
+    held: Held,
+    whitespace: Vec<u8>,
+    output: collections::VecDeque<u8>,
+    done: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Normal,
+    Slash,
+    LineComment,
+    BlockComment,
+    BlockCommentStar,
+    String { quote: u8, escape: bool },
+    Word,
+}
+
+/// A token whose output depends on the next significant byte.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Held {
+    Nothing,
+    /// Dropped if followed by `]` or `}`.
+    Comma,
+    /// Quoted if followed by `:`.
+    Word(Vec<u8>),
+}
+
+#[inline]
+pub fn source<'de, R>(r: R) -> value::json::Source<'de, Reader<R>>
+where
+    R: io::Read,
+{
+    value::json::source(reader(r))
+}
+
+#[inline]
+pub fn reader<R>(r: R) -> Reader<R>
+where
+    R: io::Read,
+{
+    Reader {
+        input: io::Read::bytes(io::BufReader::new(r)),
+        state: State::Normal,
+        held: Held::Nothing,
+        whitespace: Vec::new(),
+        output: collections::VecDeque::new(),
+        done: false,
+    }
+}
+
+impl<R> io::Read for Reader<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output.is_empty() && !self.done {
+            match self.input.next() {
+                Some(b) => self.push(b?),
+                None => self.finish()?,
+            }
+        }
+
+        let n = buf.len().min(self.output.len());
+        for (dst, src) in buf.iter_mut().zip(self.output.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl<R> Reader<R>
+where
+    R: io::Read,
+{
+    fn push(&mut self, b: u8) {
+        match self.state {
+            State::Normal => match b {
+                b' ' | b'\t' | b'\n' | b'\r' => self.whitespace.push(b),
+                b'/' => self.state = State::Slash,
+                _ => self.significant(b),
+            },
+            State::Slash => match b {
+                b'/' => self.state = State::LineComment,
+                b'*' => self.state = State::BlockComment,
+                _ => {
+                    // Not a comment; let the JSON parser complain about the slash
+                    self.state = State::Normal;
+                    self.significant(b'/');
+                    self.push(b);
+                }
+            },
+            State::LineComment => {
+                if b == b'\n' {
+                    self.state = State::Normal;
+                    self.whitespace.push(b);
+                }
+            }
+            State::BlockComment => {
+                if b == b'*' {
+                    self.state = State::BlockCommentStar;
+                }
+            }
+            State::BlockCommentStar => match b {
+                b'/' => {
+                    self.state = State::Normal;
+                    self.whitespace.push(b' ');
+                }
+                b'*' => (),
+                _ => self.state = State::BlockComment,
+            },
+            State::String { quote, escape } => {
+                self.state = State::String {
+                    quote,
+                    escape: !escape && b == b'\\',
+                };
+                match (quote, escape, b) {
+                    (_, false, b'\\') if quote == b'\'' => (),
+                    (b'\'', true, b'\'') => self.output.push_back(b'\''),
+                    (b'\'', true, _) => self.output.extend(&[b'\\', b]),
+                    (b'\'', false, b'"') => self.output.extend(b"\\\""),
+                    (_, false, _) if b == quote => {
+                        self.output.push_back(b'"');
+                        self.state = State::Normal;
+                    }
+                    _ => self.output.push_back(b),
+                }
+            }
+            State::Word => {
+                if is_word_byte(b) {
+                    if let Held::Word(ref mut word) = self.held {
+                        word.push(b);
+                    }
+                } else {
+                    self.state = State::Normal;
+                    self.push(b);
+                }
+            }
+        }
+    }
+
+    /// Handles a byte that is not whitespace and not part of a comment or string.
+    fn significant(&mut self, b: u8) {
+        match std::mem::replace(&mut self.held, Held::Nothing) {
+            Held::Nothing => (),
+            Held::Comma => {
+                if b != b']' && b != b'}' {
+                    self.output.push_back(b',');
+                }
+            }
+            Held::Word(word) => {
+                if b == b':' {
+                    self.output.push_back(b'"');
+                    self.output.extend(word);
+                    self.output.push_back(b'"');
+                } else {
+                    self.output.extend(word);
+                }
+            }
+        }
+        self.output.extend(self.whitespace.drain(..));
+
+        match b {
+            b',' => self.held = Held::Comma,
+            b'"' | b'\'' => {
+                self.output.push_back(b'"');
+                self.state = State::String {
+                    quote: b,
+                    escape: false,
+                };
+            }
+            _ if is_word_byte(b) => {
+                self.held = Held::Word(vec![b]);
+                self.state = State::Word;
+            }
+            _ => self.output.push_back(b),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::BlockComment | State::BlockCommentStar => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unterminated comment",
+                ));
+            }
+            // Let the JSON parser complain about a slash at the very end
+            State::Slash => {
+                self.state = State::Normal;
+                self.significant(b'/');
+            }
+            _ => (),
+        }
+        match std::mem::replace(&mut self.held, Held::Nothing) {
+            Held::Nothing => (),
+            Held::Comma => self.output.push_back(b','),
+            Held::Word(word) => self.output.extend(word),
+        }
+        self.output.extend(self.whitespace.drain(..));
+        self.done = true;
+        Ok(())
+    }
+}
+
+fn is_word_byte(b: u8) -> bool {
+    !b" \t\n\r{}[]:,\"'/".contains(&b)
+}
+
+impl<R> fmt::Debug for Reader<R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JsoncReader").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    fn rewrite(input: &str) -> String {
+        let mut result = String::new();
+        reader(input.as_bytes())
+            .read_to_string(&mut result)
+            .unwrap();
+        result
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            rewrite("// leading\n{\"a\": /* inline */ 1} /* trailing */"),
+            "\n{\"a\":   1}  "
+        );
+    }
+
+    #[test]
+    fn test_trailing_commas() {
+        assert_eq!(
+            rewrite("{\"a\": [1, 2,\n], \"b\": 3, // c\n}"),
+            "{\"a\": [1, 2\n], \"b\": 3 \n}"
+        );
+    }
+
+    #[test]
+    fn test_unquoted_keys() {
+        assert_eq!(
+            rewrite("{a: true, $b_1 : null, c:-1e5}"),
+            "{\"a\": true, \"$b_1\" : null, \"c\":-1e5}"
+        );
+    }
+
+    #[test]
+    fn test_single_quotes() {
+        assert_eq!(
+            rewrite(r#"{'it\'s': 'say "hi" \n // not a comment'}"#),
+            r#"{"it's": "say \"hi\" \n // not a comment"}"#
+        );
+    }
+
+    #[test]
+    fn test_strict_json_unchanged() {
+        let input = "{\"a\\\"\": [\"/*\", 1.5e-3, null]}\n[]\n";
+        assert_eq!(rewrite(input), input);
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        use crate::value::Source;
+
+        for input in &["{\"a\": 1} /* trailing", "[1, /* inline *"] {
+            let mut result = String::new();
+            let error = reader(input.as_bytes())
+                .read_to_string(&mut result)
+                .unwrap_err();
+            assert_eq!(error.to_string(), "unterminated comment");
+        }
+        // A slash that doesn't start a comment is left for the JSON parser
+        assert_eq!(rewrite("[1] /"), "[1] /");
+
+        let mut source = source(&b"{\"a\": 1 /* unterminated"[..]);
+        let error = source.read().unwrap_err().chain();
+        assert!(error.contains("unterminated comment"), "{}", error);
+    }
+
+    #[test]
+    fn test_source() {
+        use crate::value::Source;
+
+        let mut source = source(&b"{a: 1,} // first\n['b',]"[..]);
+        assert_eq!(
+            source.read().unwrap(),
+            Some(value::Value::Map(vec![(
+                value::Value::String("a".to_owned()),
+                value::Value::U64(1)
+            )]))
+        );
+        assert_eq!(
+            source.read().unwrap(),
+            Some(value::Value::Sequence(vec![value::Value::String(
+                "b".to_owned()
+            )]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+}
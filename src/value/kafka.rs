@@ -0,0 +1,282 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+use std::io;
+
+/// The first byte of a record in the Confluent wire format.
+const MAGIC: u8 = 0;
+
+/// A source that reads Kafka records in the Confluent wire format, each prefixed with its length
+/// as a 4-byte big-endian number, like `kcat -C -f '%R%s'` writes them.
+///
+/// The framing of each record, a magic byte and the ID of its schema in the schema registry,
+/// followed by the indexes of its message type for protobuf, is stripped, and the payload is
+/// decoded by a function, usually with an Avro or protobuf source.  Since the schema is given
+/// instead of fetched from a registry, all records must have the same schema ID.
+pub struct Source<R, F> {
+    input: R,
+    message_indexes: Option<Vec<usize>>,
+    schema_id: Option<u32>,
+    offset: u64,
+    decode: F,
+}
+
+/// Creates a source for records with Avro payloads, which are bare datums.
+#[inline]
+pub fn avro_source<R, F>(input: R, decode: F) -> Source<R, F>
+where
+    R: io::Read,
+    F: FnMut(&[u8]) -> error::Result<value::Value>,
+{
+    Source {
+        input,
+        message_indexes: None,
+        schema_id: None,
+        offset: 0,
+        decode,
+    }
+}
+
+/// Creates a source for records with protobuf payloads, which must be messages of the type with
+/// the given indexes in its file, see `protobuf::pool::DescriptorPool::message_indexes`.
+#[inline]
+pub fn protobuf_source<R, F>(input: R, message_indexes: &[usize], decode: F) -> Source<R, F>
+where
+    R: io::Read,
+    F: FnMut(&[u8]) -> error::Result<value::Value>,
+{
+    Source {
+        input,
+        message_indexes: Some(message_indexes.to_vec()),
+        schema_id: None,
+        offset: 0,
+        decode,
+    }
+}
+
+impl<R, F> Source<R, F>
+where
+    R: io::Read,
+    F: FnMut(&[u8]) -> error::Result<value::Value>,
+{
+    fn error(&self, msg: String) -> error::Error {
+        error::Error::Format {
+            msg: format!("bad Kafka record at byte {}: {}", self.offset, msg),
+        }
+    }
+
+    /// Strips the indexes of the message type from a protobuf payload, and checks them.
+    fn strip_message_indexes<'b>(&self, payload: &'b [u8]) -> error::Result<&'b [u8]> {
+        let expected = match self.message_indexes {
+            Some(ref expected) => expected,
+            None => return Ok(payload),
+        };
+        let truncated = || self.error("the indexes of its message type are truncated".to_owned());
+
+        let (count, mut rest) = read_zigzag(payload).ok_or_else(truncated)?;
+        let mut indexes = Vec::new();
+        for _ in 0..count {
+            let (index, r) = read_zigzag(rest).ok_or_else(truncated)?;
+            indexes.push(index);
+            rest = r;
+        }
+        // An empty list is short for the first message type in the file
+        if indexes.is_empty() {
+            indexes.push(0);
+        }
+        if indexes
+            .iter()
+            .map(|&i| i as usize)
+            .ne(expected.iter().copied())
+        {
+            return Err(self.error(format!(
+                "its message type has indexes {:?} in the schema, but the given message type \
+                 has indexes {:?}",
+                indexes, expected
+            )));
+        }
+        Ok(rest)
+    }
+}
+
+impl<R, F> value::Source for Source<R, F>
+where
+    R: io::Read,
+    F: FnMut(&[u8]) -> error::Result<value::Value>,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        let mut len = [0; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match self.input.read(&mut len[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(self.error("its length is truncated".to_owned())),
+                n => read += n,
+            }
+        }
+        let len = u32::from_be_bytes(len) as usize;
+
+        let mut record = vec![0; len];
+        self.input
+            .read_exact(&mut record)
+            .map_err(|_| self.error(format!("expected {} bytes", len)))?;
+        if len < 5 || record[0] != MAGIC {
+            return Err(self.error("it isn't in the Confluent wire format".to_owned()));
+        }
+
+        let schema_id = u32::from_be_bytes([record[1], record[2], record[3], record[4]]);
+        match self.schema_id {
+            Some(id) if id != schema_id => {
+                return Err(self.error(format!(
+                    "it has schema ID {}, but the records before it have schema ID {}",
+                    schema_id, id
+                )))
+            }
+            _ => self.schema_id = Some(schema_id),
+        }
+
+        let payload = self.strip_message_indexes(&record[5..])?;
+        let v = (self.decode)(payload)
+            .map_err(|e| self.error(format!("its payload can't be decoded: {}", e)))?;
+        self.offset += 4 + len as u64;
+        Ok(Some(v))
+    }
+}
+
+/// Reads a zig-zag encoded varint, and returns it with the rest of the bytes.
+fn read_zigzag(bytes: &[u8]) -> Option<(i64, &[u8])> {
+    let mut n = 0u64;
+    for (i, &b) in bytes.iter().enumerate().take(10) {
+        n |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some(((n >> 1) as i64 ^ -((n & 1) as i64), &bytes[i + 1..]));
+        }
+    }
+    None
+}
+
+impl<R, F> fmt::Debug for Source<R, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KafkaSource")
+            .field("message_indexes", &self.message_indexes)
+            .field("schema_id", &self.schema_id)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    fn record(schema_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut result = ((payload.len() + 5) as u32).to_be_bytes().to_vec();
+        result.push(MAGIC);
+        result.extend_from_slice(&schema_id.to_be_bytes());
+        result.extend_from_slice(payload);
+        result
+    }
+
+    fn read_all<R, F>(mut source: Source<R, F>) -> error::Result<Vec<value::Value>>
+    where
+        R: io::Read,
+        F: FnMut(&[u8]) -> error::Result<value::Value>,
+    {
+        let mut result = Vec::new();
+        while let Some(v) = source.read()? {
+            result.push(v);
+        }
+        Ok(result)
+    }
+
+    fn bytes(payload: &[u8]) -> error::Result<value::Value> {
+        Ok(value::Value::Bytes(payload.to_vec()))
+    }
+
+    #[test]
+    fn test_avro_source() {
+        let mut input = record(7, b"a");
+        input.extend(record(7, b""));
+        assert_eq!(
+            read_all(avro_source(&input[..], bytes)).unwrap(),
+            vec![
+                value::Value::Bytes(b"a".to_vec()),
+                value::Value::Bytes(vec![])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_protobuf_source() {
+        // The first message type, as an empty list of indexes and as the list [0]
+        let mut input = record(7, &[0, b'a']);
+        input.extend(record(7, &[2, 0, b'b']));
+        assert_eq!(
+            read_all(protobuf_source(&input[..], &[0], bytes)).unwrap(),
+            vec![
+                value::Value::Bytes(b"a".to_vec()),
+                value::Value::Bytes(b"b".to_vec())
+            ]
+        );
+
+        // The second message type nested in the first
+        let input = record(7, &[4, 2, 0, b'c']);
+        assert_eq!(
+            read_all(protobuf_source(&input[..], &[1, 0], bytes)).unwrap(),
+            vec![value::Value::Bytes(b"c".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: Vec<u8>, message_indexes: Option<&[usize]>| {
+            let result = match message_indexes {
+                Some(indexes) => read_all(protobuf_source(&input[..], indexes, bytes)),
+                None => read_all(avro_source(&input[..], bytes)),
+            };
+            match result {
+                Err(error::Error::Format { msg }) => msg,
+                r => panic!("expected an error, got {:?}", r),
+            }
+        };
+
+        let mut input = record(7, b"a");
+        input.extend(record(8, b"b"));
+        assert_eq!(
+            error(input, None),
+            "bad Kafka record at byte 10: it has schema ID 8, but the records before it have \
+             schema ID 7"
+        );
+        assert_eq!(
+            error(vec![0, 0, 0, 2, 0, 0], None),
+            "bad Kafka record at byte 0: it isn't in the Confluent wire format"
+        );
+        assert_eq!(
+            error(vec![0, 0, 0, 9, 0], None),
+            "bad Kafka record at byte 0: expected 9 bytes"
+        );
+        assert_eq!(
+            error(vec![0, 0], None),
+            "bad Kafka record at byte 0: its length is truncated"
+        );
+        assert_eq!(
+            error(record(7, &[2, 2]), Some(&[0])),
+            "bad Kafka record at byte 0: its message type has indexes [1] in the schema, but the \
+             given message type has indexes [0]"
+        );
+        assert_eq!(
+            error(record(7, &[2]), Some(&[0])),
+            "bad Kafka record at byte 0: the indexes of its message type are truncated"
+        );
+
+        let failing = |_: &[u8]| Err(error::Error::Message("oops".to_owned()));
+        match read_all(avro_source(&record(7, b"a")[..], failing)) {
+            Err(error::Error::Format { msg }) => assert_eq!(
+                msg,
+                "bad Kafka record at byte 0: its payload can't be decoded: oops"
+            ),
+            r => panic!("expected an error, got {:?}", r),
+        }
+    }
+}
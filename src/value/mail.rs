@@ -0,0 +1,530 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+
+/// A source that reads email messages, either a whole mbox file or a single EML message, as one
+/// record per message.
+///
+/// Each record has the `headers` of the message, with encoded words decoded and headers that
+/// occur several times collected into sequences, the `parts` of the message with their content
+/// type, charset, file name, decoded size and decoded text (for `text/*` parts), and the `body`,
+/// which is the text of the first `text/plain` part.
+///
+/// Input that starts with a `From ` line is read as mbox, otherwise it is a single message.
+pub struct Source<R>
+where
+    R: io::Read,
+{
+    input: io::BufReader<R>,
+    /// Whether the separator line of another message in an mbox has been read.
+    next_from: bool,
+    is_mbox: Option<bool>,
+}
+
+/// How deeply multipart messages may be nested, so that malicious input can't overflow the
+/// stack.
+const MAX_DEPTH: usize = 32;
+
+#[inline]
+pub fn source<R>(r: R) -> Source<R>
+where
+    R: io::Read,
+{
+    Source {
+        input: io::BufReader::new(r),
+        next_from: false,
+        is_mbox: None,
+    }
+}
+
+impl<R> Source<R>
+where
+    R: io::Read,
+{
+    /// Reads the raw bytes of the next message.
+    fn read_message(&mut self) -> error::Result<Option<Vec<u8>>> {
+        let mut message = Vec::new();
+        if self.is_mbox.is_none() {
+            let mut first = Vec::new();
+            if self.input.read_until(b'\n', &mut first)? == 0 {
+                return Ok(None);
+            }
+            let is_mbox = first.starts_with(b"From ");
+            self.is_mbox = Some(is_mbox);
+            if !is_mbox {
+                message.extend(first);
+            }
+        } else if !std::mem::replace(&mut self.next_from, false) {
+            return Ok(None);
+        }
+
+        if self.is_mbox == Some(false) {
+            io::Read::read_to_end(&mut self.input, &mut message)?;
+            return Ok(Some(message));
+        }
+
+        loop {
+            let mut line = Vec::new();
+            if self.input.read_until(b'\n', &mut line)? == 0 {
+                return Ok(Some(message));
+            }
+            if line.starts_with(b"From ") {
+                self.next_from = true;
+                return Ok(Some(message));
+            }
+            // mboxrd escapes lines that start with any number of '>' followed by "From "
+            let quoted = line.iter().take_while(|&&b| b == b'>').count();
+            if quoted > 0 && line[quoted..].starts_with(b"From ") {
+                message.extend_from_slice(&line[1..]);
+            } else {
+                message.extend(line);
+            }
+        }
+    }
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.read_message()?.map(|message| message_value(&message)))
+    }
+}
+
+/// Converts a raw message into a record.
+fn message_value(message: &[u8]) -> value::Value {
+    let (headers, body) = split_headers(message);
+    let mut parts = Vec::new();
+    collect_parts(&headers, body, 0, &mut parts);
+
+    let text = parts
+        .iter()
+        .find(|p| p.content_type == "text/plain")
+        .and_then(|p| p.text.clone());
+
+    let mut merged: Vec<(value::Value, value::Value)> = Vec::new();
+    for (name, v) in headers {
+        let v = value::Value::String(decode_words(&v));
+        match merged.iter_mut().find(|(k, _)| match *k {
+            value::Value::String(ref k) => k.eq_ignore_ascii_case(&name),
+            _ => false,
+        }) {
+            Some((_, value::Value::Sequence(ref mut seq))) => seq.push(v),
+            Some((_, existing)) => {
+                let first = std::mem::replace(existing, value::Value::Unit);
+                *existing = value::Value::Sequence(vec![first, v]);
+            }
+            None => merged.push((value::Value::String(name), v)),
+        }
+    }
+
+    value::Value::Map(vec![
+        (s("headers"), value::Value::Map(merged)),
+        (
+            s("parts"),
+            value::Value::Sequence(parts.into_iter().map(Part::into_value).collect()),
+        ),
+        (
+            s("body"),
+            text.map_or(value::Value::Unit, value::Value::String),
+        ),
+    ])
+}
+
+struct Part {
+    content_type: String,
+    charset: Option<String>,
+    filename: Option<String>,
+    size: usize,
+    text: Option<String>,
+}
+
+impl Part {
+    fn into_value(self) -> value::Value {
+        let opt = |v: Option<String>| v.map_or(value::Value::Unit, value::Value::String);
+        value::Value::Map(vec![
+            (s("content_type"), value::Value::String(self.content_type)),
+            (s("charset"), opt(self.charset)),
+            (s("filename"), opt(self.filename)),
+            (s("size"), value::Value::U64(self.size as u64)),
+            (s("text"), opt(self.text)),
+        ])
+    }
+}
+
+fn s(v: &str) -> value::Value {
+    value::Value::String(v.to_owned())
+}
+
+/// Splits a message or part into its unfolded headers and its body.
+fn split_headers(raw: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut offset = 0;
+    for line in raw.split_inclusive(|&b| b == b'\n') {
+        offset += line.len();
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+        if line.is_empty() {
+            return (headers, &raw[offset..]);
+        }
+        if line.starts_with(|c| c == ' ' || c == '\t') {
+            if let Some((_, v)) = headers.last_mut() {
+                v.push(' ');
+                v.push_str(line.trim());
+            }
+        } else if let Some(i) = line.find(':') {
+            headers.push((line[..i].trim().to_owned(), line[i + 1..].trim().to_owned()));
+        }
+    }
+    (headers, &raw[raw.len()..])
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Parses a header like `Content-Type` into its lowercased value and its parameters.
+fn parse_parameters(header: &str) -> (String, Vec<(String, String)>) {
+    let mut pieces = header.split(';');
+    let main = pieces.next().unwrap_or("").trim().to_ascii_lowercase();
+    let params = pieces
+        .filter_map(|p| {
+            let i = p.find('=')?;
+            let v = p[i + 1..].trim().trim_matches('"');
+            Some((p[..i].trim().to_ascii_lowercase(), v.to_owned()))
+        })
+        .collect();
+    (main, params)
+}
+
+fn parameter<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.as_str())
+}
+
+fn collect_parts(headers: &[(String, String)], body: &[u8], depth: usize, parts: &mut Vec<Part>) {
+    let (content_type, params) = parse_parameters(header(headers, "Content-Type").unwrap_or(""));
+    let content_type = if content_type.is_empty() {
+        "text/plain".to_owned()
+    } else {
+        content_type
+    };
+
+    if content_type.starts_with("multipart/") && depth < MAX_DEPTH {
+        if let Some(boundary) = parameter(&params, "boundary") {
+            for part in split_multipart(body, boundary) {
+                let (headers, body) = split_headers(part);
+                collect_parts(&headers, body, depth + 1, parts);
+            }
+            return;
+        }
+    }
+
+    let encoding = header(headers, "Content-Transfer-Encoding")
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    let decoded = match encoding.as_str() {
+        "base64" => decode_base64(body),
+        "quoted-printable" => decode_quoted_printable(body),
+        _ => body.to_vec(),
+    };
+
+    let (_, disposition) = parse_parameters(header(headers, "Content-Disposition").unwrap_or(""));
+    let filename = parameter(&disposition, "filename")
+        .or_else(|| parameter(&params, "name"))
+        .map(decode_words);
+    let charset = parameter(&params, "charset").map(str::to_ascii_lowercase);
+    let text = if content_type.starts_with("text/") {
+        Some(decode_charset(charset.as_deref(), &decoded))
+    } else {
+        None
+    };
+
+    parts.push(Part {
+        content_type,
+        charset,
+        filename,
+        size: decoded.len(),
+        text,
+    });
+}
+
+/// Splits the body of a multipart part into the raw parts between the boundary lines.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut offset = 0;
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = trim_line_end(line);
+        if !trimmed.starts_with(delimiter.as_bytes()) {
+            continue;
+        }
+        let rest = &trimmed[delimiter.len()..];
+        if let Some(start) = start {
+            // The line break before the boundary belongs to the boundary
+            let end = if body[..line_start].ends_with(b"\r\n") {
+                line_start - 2
+            } else if body[..line_start].ends_with(b"\n") {
+                line_start - 1
+            } else {
+                line_start
+            };
+            parts.push(&body[start..end.max(start)]);
+        }
+        if rest.starts_with(b"--") {
+            return parts;
+        }
+        start = Some(offset);
+    }
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+fn trim_line_end(line: &[u8]) -> &[u8] {
+    let mut end = line.len();
+    while end > 0 && (line[end - 1] == b'\n' || line[end - 1] == b'\r') {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+fn decode_base64(input: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &b in input {
+        let v = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => continue,
+        };
+        buffer = (buffer << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    result
+}
+
+fn hex(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+fn decode_quoted_printable(input: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] != b'=' {
+            result.push(input[i]);
+            i += 1;
+            continue;
+        }
+        match (input.get(i + 1), input.get(i + 2)) {
+            // Soft line breaks
+            (Some(b'\r'), Some(b'\n')) => i += 3,
+            (Some(b'\n'), _) => i += 2,
+            (Some(&h), Some(&l)) if hex(h).is_some() && hex(l).is_some() => {
+                result.push(hex(h).unwrap() << 4 | hex(l).unwrap());
+                i += 3;
+            }
+            _ => {
+                result.push(b'=');
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Decodes text in the given charset, falling back to UTF-8 with replacement characters for
+/// charsets that aren't known.
+fn decode_charset(charset: Option<&str>, bytes: &[u8]) -> String {
+    match charset {
+        Some("iso-8859-1") | Some("latin1") | Some("windows-1252") => {
+            bytes.iter().map(|&b| b as char).collect()
+        }
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Decodes the RFC 2047 encoded words, like `=?UTF-8?B?SGk=?=`, in a header value.
+fn decode_words(header: &str) -> String {
+    let mut result = String::new();
+    let mut rest = header;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let decoded = rest[start + 2..].find("?=").and_then(|end| {
+            let word = &rest[start + 2..start + 2 + end];
+            let mut fields = word.splitn(3, '?');
+            let charset = fields.next()?.to_ascii_lowercase();
+            let encoding = fields.next()?;
+            let text = fields.next()?.as_bytes();
+            let bytes = if encoding.eq_ignore_ascii_case("b") {
+                decode_base64(text)
+            } else if encoding.eq_ignore_ascii_case("q") {
+                let text = text
+                    .iter()
+                    .map(|&b| if b == b'_' { b' ' } else { b })
+                    .collect::<Vec<_>>();
+                decode_quoted_printable(&text)
+            } else {
+                return None;
+            };
+            Some((decode_charset(Some(&charset), &bytes), start + 2 + end + 2))
+        });
+        match decoded {
+            Some((text, end)) => {
+                // White space between adjacent encoded words is not part of the text
+                let between = &rest[..start];
+                if !(after_word && between.trim().is_empty()) {
+                    result.push_str(between);
+                }
+                result.push_str(&text);
+                rest = &rest[end..];
+                after_word = true;
+            }
+            None => {
+                result.push_str(&rest[..start + 2]);
+                rest = &rest[start + 2..];
+                after_word = false;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+impl<R> fmt::Debug for Source<R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MailSource")
+            .field("is_mbox", &self.is_mbox)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn read_all(input: &str) -> Vec<Value> {
+        let mut source = source(input.as_bytes());
+        let mut result = Vec::new();
+        while let Some(v) = source.read().unwrap() {
+            result.push(v);
+        }
+        result
+    }
+
+    fn get<'a>(v: &'a Value, key: &str) -> &'a Value {
+        match *v {
+            Value::Map(ref entries) => entries
+                .iter()
+                .find(|(k, _)| *k == s(key))
+                .map(|(_, v)| v)
+                .unwrap_or_else(|| panic!("no key {:?} in {:?}", key, v)),
+            _ => panic!("not a map: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_eml() {
+        let messages = read_all(
+            "From: =?UTF-8?Q?J=C3=B6rg?= <j@example.com>\r\n\
+             Subject: =?utf-8?B?SGVsbG8=?= =?utf-8?B?IHdvcmxk?=\r\n\
+             Received: a\r\n\
+             Received: b\r\n\
+             \tc\r\n\
+             Content-Type: multipart/mixed; boundary=\"xyz\"\r\n\
+             \r\n\
+             preamble\r\n\
+             --xyz\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\
+             \r\n\
+             Gr=C3=BC=C3=9Fe, =\r\n\
+             J=C3=B6rg\r\n\
+             --xyz\r\n\
+             Content-Type: application/octet-stream; name=\"a.bin\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             \r\n\
+             AAEC\r\n\
+             --xyz--\r\n",
+        );
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+
+        let headers = get(message, "headers");
+        assert_eq!(get(headers, "From"), &s("Jörg <j@example.com>"));
+        assert_eq!(get(headers, "Subject"), &s("Hello world"));
+        assert_eq!(
+            get(headers, "Received"),
+            &Value::Sequence(vec![s("a"), s("b c")])
+        );
+        assert_eq!(get(message, "body"), &s("Grüße, Jörg"));
+
+        let parts = match *get(message, "parts") {
+            Value::Sequence(ref parts) => parts,
+            ref v => panic!("not a sequence: {:?}", v),
+        };
+        assert_eq!(parts.len(), 2);
+        assert_eq!(get(&parts[0], "charset"), &s("utf-8"));
+        assert_eq!(
+            get(&parts[1], "content_type"),
+            &s("application/octet-stream")
+        );
+        assert_eq!(get(&parts[1], "filename"), &s("a.bin"));
+        assert_eq!(get(&parts[1], "size"), &Value::U64(3));
+        assert_eq!(get(&parts[1], "text"), &Value::Unit);
+    }
+
+    #[test]
+    fn test_mbox() {
+        let messages = read_all(
+            "From a@example.com Mon Jan  1 00:00:00 2024\n\
+             Subject: one\n\
+             \n\
+             >From the start\n\
+             \n\
+             From b@example.com Mon Jan  1 00:00:01 2024\n\
+             Subject: two\n\
+             Content-Type: text/plain; charset=iso-8859-1\n\
+             Content-Transfer-Encoding: base64\n\
+             \n\
+             /w==\n",
+        );
+        assert_eq!(messages.len(), 2);
+        assert_eq!(get(get(&messages[0], "headers"), "Subject"), &s("one"));
+        assert_eq!(get(&messages[0], "body"), &s("From the start\n\n"));
+        assert_eq!(get(&messages[1], "body"), &s("ÿ"));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(read_all(""), vec![]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::io;
 
 use ordered_float;
+use rmp;
 use rmpv;
 
 use crate::error;
@@ -12,10 +13,21 @@ where
     R: io::Read;
 
 #[derive(Debug)]
-pub struct MessagePackSink<W>(W)
+pub struct MessagePackSink<W>(W, SinkOptions)
 where
     W: io::Write;
 
+/// Options that make MessagePack output digestible for more limited consumers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SinkOptions {
+    /// Encode strings and bytes using only the "raw" family of the original MessagePack spec, i.e.
+    /// without the `str8` and `bin` types that older decoders don't understand.
+    pub legacy_raw: bool,
+    /// Convert map keys that are booleans or numbers into strings, and fail on other keys that
+    /// aren't strings.
+    pub stringify_keys: bool,
+}
+
 #[inline]
 pub fn source<R>(r: R) -> MessagePackSource<R>
 where
@@ -29,7 +41,15 @@ pub fn sink<W>(w: W) -> MessagePackSink<W>
 where
     W: io::Write,
 {
-    MessagePackSink(w)
+    MessagePackSink(w, SinkOptions::default())
+}
+
+#[inline]
+pub fn sink_with_options<W>(w: W, options: SinkOptions) -> MessagePackSink<W>
+where
+    W: io::Write,
+{
+    MessagePackSink(w, options)
 }
 
 impl<R> value::Source for MessagePackSource<R>
@@ -56,7 +76,12 @@ where
 {
     #[inline]
     fn write(&mut self, v: value::Value) -> error::Result<()> {
-        rmpv::encode::write_value(&mut self.0, &value_to_message_pack(v)).map_err(From::from)
+        let v = value_to_message_pack(v, self.1.stringify_keys)?;
+        if self.1.legacy_raw {
+            write_legacy_value(&mut self.0, &v)
+        } else {
+            rmpv::encode::write_value(&mut self.0, &v).map_err(From::from)
+        }
     }
 }
 
@@ -93,9 +118,9 @@ fn value_from_message_pack(value: rmpv::Value) -> error::Result<value::Value> {
     }
 }
 
-fn value_to_message_pack(value: value::Value) -> rmpv::Value {
+fn value_to_message_pack(value: value::Value, stringify_keys: bool) -> error::Result<rmpv::Value> {
     use rmpv::Value;
-    match value {
+    Ok(match value {
         value::Value::Unit => Value::Nil,
         value::Value::Bool(v) => Value::Boolean(v),
 
@@ -116,13 +141,217 @@ fn value_to_message_pack(value: value::Value) -> rmpv::Value {
         value::Value::String(v) => Value::String(v.into()),
         value::Value::Bytes(v) => Value::Binary(v),
 
-        value::Value::Sequence(v) => {
-            Value::Array(v.into_iter().map(value_to_message_pack).collect())
-        }
+        value::Value::Sequence(v) => Value::Array(
+            v.into_iter()
+                .map(|v| value_to_message_pack(v, stringify_keys))
+                .collect::<error::Result<_>>()?,
+        ),
         value::Value::Map(v) => Value::Map(
             v.into_iter()
-                .map(|(k, v)| (value_to_message_pack(k), value_to_message_pack(v)))
-                .collect(),
+                .map(|(k, v)| {
+                    let k = if stringify_keys { stringify_key(k)? } else { k };
+                    Ok((
+                        value_to_message_pack(k, stringify_keys)?,
+                        value_to_message_pack(v, stringify_keys)?,
+                    ))
+                })
+                .collect::<error::Result<_>>()?,
         ),
+    })
+}
+
+/// Converts a map key into a string: booleans become `true` or `false` and numbers their decimal
+/// form (or `NaN`, `inf` and `-inf`), while Unit, bytes, sequences and maps are an error.
+fn stringify_key(key: value::Value) -> error::Result<value::Value> {
+    use crate::value::Value;
+    let s = match key {
+        Value::String(_) | Value::Char(_) => return Ok(key),
+        Value::Bool(v) => v.to_string(),
+
+        Value::I8(v) => v.to_string(),
+        Value::I16(v) => v.to_string(),
+        Value::I32(v) => v.to_string(),
+        Value::I64(v) => v.to_string(),
+
+        Value::U8(v) => v.to_string(),
+        Value::U16(v) => v.to_string(),
+        Value::U32(v) => v.to_string(),
+        Value::U64(v) => v.to_string(),
+
+        Value::F32(ordered_float::OrderedFloat(v)) => v.to_string(),
+        Value::F64(ordered_float::OrderedFloat(v)) => v.to_string(),
+
+        Value::Unit => return Err(key_error("Unit")),
+        Value::Bytes(_) => return Err(key_error("bytes")),
+        Value::Sequence(_) => return Err(key_error("sequences")),
+        Value::Map(_) => return Err(key_error("maps")),
+    };
+    Ok(Value::String(s))
+}
+
+fn key_error(kind: &str) -> error::Error {
+    error::Error::Format {
+        msg: format!(
+            "message-pack cannot convert map keys that are {} into strings",
+            kind
+        ),
+    }
+}
+
+fn write_legacy_value<W>(w: &mut W, value: &rmpv::Value) -> error::Result<()>
+where
+    W: io::Write,
+{
+    use rmpv::Value;
+    match *value {
+        Value::String(ref v) => write_legacy_raw(w, v.as_bytes()),
+        Value::Binary(ref v) => write_legacy_raw(w, v),
+        Value::Array(ref v) => {
+            rmp::encode::write_array_len(w, v.len() as u32)?;
+            for e in v {
+                write_legacy_value(w, e)?;
+            }
+            Ok(())
+        }
+        Value::Map(ref v) => {
+            rmp::encode::write_map_len(w, v.len() as u32)?;
+            for (k, v) in v {
+                write_legacy_value(w, k)?;
+                write_legacy_value(w, v)?;
+            }
+            Ok(())
+        }
+        ref v => rmpv::encode::write_value(w, v).map_err(From::from),
+    }
+}
+
+/// Writes a fixraw, raw16 or raw32 value (which have the same markers as fixstr, str16 and str32).
+fn write_legacy_raw<W>(w: &mut W, data: &[u8]) -> error::Result<()>
+where
+    W: io::Write,
+{
+    let len = data.len();
+    if len < 32 {
+        w.write_all(&[0xa0 | len as u8])?;
+    } else if len < 0x1_0000 {
+        w.write_all(&[0xda])?;
+        w.write_all(&(len as u16).to_be_bytes())?;
+    } else {
+        w.write_all(&[0xdb])?;
+        w.write_all(&(len as u32).to_be_bytes())?;
+    }
+    w.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+    use crate::value::Value;
+
+    fn write(options: SinkOptions, value: Value) -> error::Result<Vec<u8>> {
+        let mut result = Vec::new();
+        sink_with_options(&mut result, options).write(value)?;
+        Ok(result)
+    }
+
+    fn legacy(value: Value) -> Vec<u8> {
+        let options = SinkOptions {
+            legacy_raw: true,
+            ..SinkOptions::default()
+        };
+        write(options, value).unwrap()
+    }
+
+    fn stringified(key: Value) -> error::Result<Vec<u8>> {
+        let options = SinkOptions {
+            stringify_keys: true,
+            ..SinkOptions::default()
+        };
+        write(options, Value::Map(vec![(key, Value::Unit)]))
+    }
+
+    #[test]
+    fn test_legacy_raw() {
+        assert_eq!(legacy(Value::String("abc".to_owned())), b"\xa3abc");
+        assert_eq!(legacy(Value::Bytes(vec![1, 2])), b"\xa2\x01\x02");
+
+        let fixraw = legacy(Value::Bytes(vec![7; 31]));
+        assert_eq!(fixraw[0], 0xbf);
+        assert_eq!(fixraw.len(), 32);
+
+        // This would be a str8 without the legacy encoding
+        let raw16 = legacy(Value::String("x".repeat(32)));
+        assert_eq!(&raw16[..3], &[0xda, 0x00, 0x20]);
+        assert_eq!(raw16.len(), 35);
+        let raw16 = legacy(Value::Bytes(vec![7; 0xffff]));
+        assert_eq!(&raw16[..3], &[0xda, 0xff, 0xff]);
+
+        let raw32 = legacy(Value::Bytes(vec![7; 0x1_0000]));
+        assert_eq!(&raw32[..5], &[0xdb, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(raw32.len(), 0x1_0005);
+
+        assert_eq!(
+            legacy(Value::Map(vec![(
+                Value::String("k".to_owned()),
+                Value::Sequence(vec![Value::Bytes(vec![1]), Value::U8(2)]),
+            )])),
+            b"\x81\xa1k\x92\xa1\x01\x02"
+        );
+        assert_eq!(
+            write(SinkOptions::default(), Value::String("x".repeat(32))).unwrap()[..2],
+            [0xd9, 0x20]
+        );
+    }
+
+    #[test]
+    fn test_stringify_keys() {
+        assert_eq!(stringified(Value::Bool(true)).unwrap(), b"\x81\xa4true\xc0");
+        assert_eq!(stringified(Value::I8(-12)).unwrap(), b"\x81\xa3-12\xc0");
+        assert_eq!(stringified(Value::U64(7)).unwrap(), b"\x81\xa17\xc0");
+        assert_eq!(
+            stringified(Value::from_f64(1.5)).unwrap(),
+            b"\x81\xa31.5\xc0"
+        );
+        assert_eq!(stringified(Value::Char('c')).unwrap(), b"\x81\xa1c\xc0");
+        assert_eq!(
+            stringified(Value::String("a".to_owned())).unwrap(),
+            b"\x81\xa1a\xc0"
+        );
+        // Maps inside of other values have their keys converted too
+        assert_eq!(
+            write(
+                SinkOptions {
+                    stringify_keys: true,
+                    ..SinkOptions::default()
+                },
+                Value::Sequence(vec![Value::Map(vec![(Value::U8(1), Value::U8(2))])]),
+            )
+            .unwrap(),
+            b"\x91\x81\xa11\x02"
+        );
+
+        for key in vec![
+            Value::Unit,
+            Value::Bytes(vec![1]),
+            Value::Sequence(vec![]),
+            Value::Map(vec![]),
+        ] {
+            match stringified(key) {
+                Err(error::Error::Format { msg }) => assert!(msg.contains("map keys"), "{}", msg),
+                r => panic!("expected an error, got {:?}", r),
+            }
+        }
+
+        // Keys are left alone without the option
+        assert_eq!(
+            write(
+                SinkOptions::default(),
+                Value::Map(vec![(Value::U8(1), Value::Unit)])
+            )
+            .unwrap(),
+            b"\x81\x01\xc0"
+        );
     }
 }
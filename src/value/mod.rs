@@ -3,20 +3,72 @@ use crate::error;
 use ordered_float;
 use serde;
 use serde_json;
+use std::cmp;
 use std::fmt;
+use std::hash;
 use std::io;
 
+pub mod adjacent;
+pub mod anonymize;
 pub mod avro;
+pub mod bson;
+pub mod cancel;
+pub mod capnproto;
 pub mod cbor;
+pub mod concat;
+pub mod control;
 pub mod csv;
+pub mod diff;
+pub mod duplicate_keys;
+pub mod enums;
+pub mod field_mask;
+pub mod fixed_width;
+pub mod flatbuffers;
+pub mod frontmatter;
+pub mod git;
 pub mod json;
+pub mod json_pointer;
+pub mod jsonc;
+pub mod kafka;
+pub mod mail;
 pub mod messagepack;
+pub mod nonfinite;
+pub mod oneof;
+pub mod parquet;
+pub mod path;
+pub mod pcap;
 pub mod protobuf;
 pub mod raw;
+pub mod roundtrip;
+pub mod schema;
+pub mod spread;
+pub mod table;
+pub mod thrift;
+pub mod timestamp;
 pub mod toml;
+pub mod transform;
+pub mod unrepresentable;
 pub mod yaml;
 
-#[derive(Debug)]
+pub use self::diff::diff;
+
+/// A dynamically typed record value.
+///
+/// Values are compared structurally, but numbers are compared by their numeric value regardless of
+/// representation, so `I32(1) == U64(1) == F64(1.0)`.  The resulting total order is:
+///
+///   1. `Unit`
+///   2. `Bool`, with `false < true`
+///   3. numbers (all integer and float variants), by numeric value; `NaN` is equal to itself and
+///      greater than every other number
+///   4. text (`Char` and `String`), by code points; a `Char` is equal to the one-char `String`
+///   5. `Bytes`, lexicographically
+///   6. `Sequence`, lexicographically
+///   7. `Map`, by comparing the entries sorted by key (and then by value) lexicographically, which
+///      means that entry order does not affect equality
+///
+/// `Hash` is consistent with this equality.
+#[derive(Clone, Debug)]
 pub enum Value {
     Unit,
     Bool(bool),
@@ -49,8 +101,76 @@ pub trait Source {
     fn read(&mut self) -> error::Result<Option<Value>>;
 }
 
+impl<S> Source for Box<S>
+where
+    S: Source + ?Sized,
+{
+    fn read(&mut self) -> error::Result<Option<Value>> {
+        (**self).read()
+    }
+}
+
 pub trait Sink {
     fn write(&mut self, v: Value) -> error::Result<()>;
+
+    /// Writes what the sink has buffered once all records have been written.
+    ///
+    /// Sinks that buffer records may reject some of them only now, with an
+    /// `error::Error::InvalidRecord`; calling this again then finishes the remaining records.
+    fn finish(&mut self) -> error::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S> Sink for Box<S>
+where
+    S: Sink + ?Sized,
+{
+    fn write(&mut self, v: Value) -> error::Result<()> {
+        (**self).write(v)
+    }
+
+    fn finish(&mut self) -> error::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// A step between a `Source` and a `Sink` that rewrites each record, or drops it by returning
+/// `None`.
+///
+/// Transforms can be applied to a source with `transform::source`, and composed with
+/// `transform::chain` or by collecting them into a `Vec`.
+pub trait Transform {
+    fn transform(&mut self, v: Value) -> error::Result<Option<Value>>;
+}
+
+impl<T> Transform for Box<T>
+where
+    T: Transform + ?Sized,
+{
+    fn transform(&mut self, v: Value) -> error::Result<Option<Value>> {
+        (**self).transform(v)
+    }
+}
+
+/// Reads exactly `buf.len()` bytes for a source of a binary format, or returns `false` if the
+/// input ends before the first byte.  If it ends later, fails with the error from `truncated`.
+pub(crate) fn read_or_eof<R, F>(r: &mut R, buf: &mut [u8], truncated: F) -> error::Result<bool>
+where
+    R: io::Read,
+    F: FnOnce() -> error::Error,
+{
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(truncated()),
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(error::Error::from(e)),
+        }
+    }
+    Ok(true)
 }
 
 struct ValueVisitor;
@@ -132,6 +252,174 @@ impl fmt::Display for Value {
     }
 }
 
+/// A number normalized for comparison purposes.
+#[derive(Clone, Copy, Debug)]
+enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl Value {
+    /// The position of this value's kind in the total order of values.
+    fn rank(&self) -> u8 {
+        match *self {
+            Self::Unit => 0,
+            Self::Bool(_) => 1,
+            Self::I8(_)
+            | Self::I16(_)
+            | Self::I32(_)
+            | Self::I64(_)
+            | Self::U8(_)
+            | Self::U16(_)
+            | Self::U32(_)
+            | Self::U64(_)
+            | Self::F32(_)
+            | Self::F64(_) => 2,
+            Self::Char(_) | Self::String(_) => 3,
+            Self::Bytes(_) => 4,
+            Self::Sequence(_) => 5,
+            Self::Map(_) => 6,
+        }
+    }
+
+    fn as_number(&self) -> Option<Number> {
+        match *self {
+            Self::I8(v) => Some(Number::Int(i128::from(v))),
+            Self::I16(v) => Some(Number::Int(i128::from(v))),
+            Self::I32(v) => Some(Number::Int(i128::from(v))),
+            Self::I64(v) => Some(Number::Int(i128::from(v))),
+            Self::U8(v) => Some(Number::Int(i128::from(v))),
+            Self::U16(v) => Some(Number::Int(i128::from(v))),
+            Self::U32(v) => Some(Number::Int(i128::from(v))),
+            Self::U64(v) => Some(Number::Int(i128::from(v))),
+            Self::F32(ordered_float::OrderedFloat(v)) => Some(Number::Float(f64::from(v))),
+            Self::F64(ordered_float::OrderedFloat(v)) => Some(Number::Float(v)),
+            _ => None,
+        }
+    }
+}
+
+impl Number {
+    fn cmp(self, other: Self) -> cmp::Ordering {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a.cmp(&b),
+            (Self::Float(a), Self::Float(b)) => {
+                ordered_float::OrderedFloat(a).cmp(&ordered_float::OrderedFloat(b))
+            }
+            (Self::Int(a), Self::Float(b)) => cmp_int_float(a, b),
+            (Self::Float(a), Self::Int(b)) => cmp_int_float(b, a).reverse(),
+        }
+    }
+
+    /// Converts floats with an integral value to integers, so that hashing agrees with `cmp`.
+    fn normalize(self) -> Self {
+        match self {
+            Self::Float(f) if f.fract() == 0.0 && (INT_START_F64..INT_END_F64).contains(&f) => {
+                Self::Int(f as i128)
+            }
+            n => n,
+        }
+    }
+}
+
+// The half-open range of integers that a `Value` can hold, as exactly representable floats
+const INT_START_F64: f64 = -9_223_372_036_854_775_808.0;
+const INT_END_F64: f64 = 18_446_744_073_709_551_616.0;
+
+/// Compares an integer with a float exactly, without rounding the integer to a float.
+fn cmp_int_float(i: i128, f: f64) -> cmp::Ordering {
+    if f.is_nan() {
+        // NaN is the greatest number
+        return cmp::Ordering::Less;
+    }
+
+    let floor = f.floor();
+    if floor >= INT_END_F64 {
+        cmp::Ordering::Less
+    } else if floor < INT_START_F64 {
+        cmp::Ordering::Greater
+    } else {
+        match i.cmp(&(floor as i128)) {
+            cmp::Ordering::Equal if f > floor => cmp::Ordering::Less,
+            o => o,
+        }
+    }
+}
+
+fn cmp_text(a: &str, b: &str) -> cmp::Ordering {
+    // Byte-wise comparison of UTF-8 agrees with code point order
+    a.cmp(b)
+}
+
+fn sorted_entries(map: &[(Value, Value)]) -> Vec<&(Value, Value)> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort();
+    entries
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        let mut a_buf = [0; 4];
+        let mut b_buf = [0; 4];
+
+        match (self, other) {
+            (Self::Unit, Self::Unit) => cmp::Ordering::Equal,
+            (Self::Bool(a), Self::Bool(b)) => a.cmp(b),
+            (Self::Char(a), Self::Char(b)) => a.cmp(b),
+            (Self::Char(a), Self::String(b)) => cmp_text(a.encode_utf8(&mut a_buf), b),
+            (Self::String(a), Self::Char(b)) => cmp_text(a, b.encode_utf8(&mut b_buf)),
+            (Self::String(a), Self::String(b)) => cmp_text(a, b),
+            (Self::Bytes(a), Self::Bytes(b)) => a.cmp(b),
+            (Self::Sequence(a), Self::Sequence(b)) => a.cmp(b),
+            (Self::Map(a), Self::Map(b)) => sorted_entries(a).cmp(&sorted_entries(b)),
+            (a, b) => match (a.as_number(), b.as_number()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                _ => a.rank().cmp(&b.rank()),
+            },
+        }
+    }
+}
+
+impl hash::Hash for Value {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: hash::Hasher,
+    {
+        self.rank().hash(state);
+        match *self {
+            Self::Unit => (),
+            Self::Bool(v) => v.hash(state),
+            Self::Char(v) => {
+                let mut buf = [0; 4];
+                v.encode_utf8(&mut buf).hash(state)
+            }
+            Self::String(ref v) => v.as_str().hash(state),
+            Self::Bytes(ref v) => v.hash(state),
+            Self::Sequence(ref v) => v.hash(state),
+            Self::Map(ref v) => sorted_entries(v).hash(state),
+            ref v => match v.as_number().map(Number::normalize) {
+                Some(Number::Int(i)) => i.hash(state),
+                Some(Number::Float(f)) => ordered_float::OrderedFloat(f).hash(state),
+                None => unreachable!(),
+            },
+        }
+    }
+}
+
 impl serde::ser::Serialize for Value {
     #[inline]
     fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
@@ -157,7 +445,7 @@ impl serde::ser::Serialize for Value {
 
             Self::Char(v) => v.serialize(s),
             Self::String(ref v) => v.serialize(s),
-            Self::Bytes(ref v) => v.serialize(s),
+            Self::Bytes(ref v) => s.serialize_bytes(v),
 
             Self::Sequence(ref v) => v.serialize(s),
             Self::Map(ref v) => {
@@ -370,3 +658,85 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
         Ok(Value::Map(values))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections;
+
+    #[test]
+    fn test_numeric_equality_across_types() {
+        assert_eq!(Value::I32(1), Value::U32(1));
+        assert_eq!(Value::U64(1), Value::from_f64(1.0));
+        assert_eq!(Value::I8(-3), Value::from_f32(-3.0));
+        assert_ne!(Value::I64(1), Value::from_f64(1.5));
+        assert_ne!(Value::U64(u64::MAX), Value::from_f64(u64::MAX as f64));
+    }
+
+    #[test]
+    fn test_numeric_ordering_across_types() {
+        assert!(Value::I64(-1) < Value::U8(0));
+        assert!(Value::U64(1) < Value::from_f64(1.5));
+        assert!(Value::from_f64(1.5) < Value::I16(2));
+        assert!(Value::U64(u64::MAX) < Value::from_f64(f64::INFINITY));
+        assert!(Value::from_f64(f64::NEG_INFINITY) < Value::I64(i64::MIN));
+        assert!(Value::U64(u64::MAX) < Value::from_f64(f64::NAN));
+        assert_eq!(Value::from_f64(f64::NAN), Value::from_f32(f32::NAN));
+    }
+
+    #[test]
+    fn test_kind_ordering() {
+        let mut values = vec![
+            Value::Map(vec![]),
+            Value::Sequence(vec![]),
+            Value::Bytes(vec![]),
+            Value::String("".to_owned()),
+            Value::I32(0),
+            Value::Bool(true),
+            Value::Unit,
+        ];
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                Value::Unit,
+                Value::Bool(true),
+                Value::I32(0),
+                Value::String("".to_owned()),
+                Value::Bytes(vec![]),
+                Value::Sequence(vec![]),
+                Value::Map(vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_char_equals_string() {
+        assert_eq!(Value::Char('a'), Value::String("a".to_owned()));
+        assert!(Value::Char('a') < Value::String("ab".to_owned()));
+    }
+
+    #[test]
+    fn test_map_equality_ignores_order() {
+        let a = Value::Map(vec![
+            (Value::String("a".to_owned()), Value::I32(1)),
+            (Value::String("b".to_owned()), Value::I32(2)),
+        ]);
+        let b = Value::Map(vec![
+            (Value::String("b".to_owned()), Value::U8(2)),
+            (Value::String("a".to_owned()), Value::from_f64(1.0)),
+        ]);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_hash_agrees_with_equality() {
+        let mut set = collections::HashSet::new();
+        set.insert(Value::I32(1));
+        set.insert(Value::U64(1));
+        set.insert(Value::from_f64(1.0));
+        set.insert(Value::Char('x'));
+        set.insert(Value::String("x".to_owned()));
+        assert_eq!(set.len(), 2);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+use std::str;
+
+/// What to do with NaN and infinite floats, which many text formats can't represent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    /// Fail with an error.
+    Error,
+    /// Replace them with `Unit`.
+    Null,
+    /// Replace them with the strings `"NaN"`, `"Infinity"` and `"-Infinity"`, like JavaScript and
+    /// many JSON libraries do.
+    String,
+}
+
+/// A sink that applies a `Policy` to all NaN and infinite floats before passing records on.
+pub struct Sink<S>(S, Policy);
+
+#[inline]
+pub fn sink<S>(inner: S, policy: Policy) -> Sink<S>
+where
+    S: value::Sink,
+{
+    Sink(inner, policy)
+}
+
+impl<S> value::Sink for Sink<S>
+where
+    S: value::Sink,
+{
+    #[inline]
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        let value = apply(value, self.1)?;
+        self.0.write(value)
+    }
+
+    #[inline]
+    fn finish(&mut self) -> error::Result<()> {
+        self.0.finish()
+    }
+}
+
+/// Applies the policy to all NaN and infinite floats in the value, including map keys.
+pub fn apply(value: value::Value, policy: Policy) -> error::Result<value::Value> {
+    use crate::value::Value;
+
+    match value {
+        Value::F32(v) if !v.0.is_finite() => replace(f64::from(v.0), policy),
+        Value::F64(v) if !v.0.is_finite() => replace(v.0, policy),
+        Value::Sequence(seq) => Ok(Value::Sequence(
+            seq.into_iter()
+                .map(|v| apply(v, policy))
+                .collect::<error::Result<_>>()?,
+        )),
+        Value::Map(entries) => Ok(Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| Ok((apply(k, policy)?, apply(v, policy)?)))
+                .collect::<error::Result<_>>()?,
+        )),
+        v => Ok(v),
+    }
+}
+
+fn replace(v: f64, policy: Policy) -> error::Result<value::Value> {
+    let name = if v.is_nan() {
+        "NaN"
+    } else if v > 0.0 {
+        "Infinity"
+    } else {
+        "-Infinity"
+    };
+
+    match policy {
+        Policy::Error => Err(error::Error::Format {
+            msg: format!("the output format can't represent {}", name),
+        }),
+        Policy::Null => Ok(value::Value::Unit),
+        Policy::String => Ok(value::Value::String(name.to_owned())),
+    }
+}
+
+impl str::FromStr for Policy {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Policy::Error),
+            "null" => Ok(Policy::Null),
+            "string" => Ok(Policy::String),
+            _ => Err(error::Error::Message(format!(
+                "unknown non-finite float policy {:?}, expected 'error', 'null' or 'string'",
+                s
+            ))),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Sink<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NonfiniteSink")
+            .field("inner", &self.0)
+            .field("policy", &self.1)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    fn record() -> Value {
+        Value::Map(vec![
+            (Value::String("nan".to_owned()), Value::from_f64(f64::NAN)),
+            (
+                Value::String("list".to_owned()),
+                Value::Sequence(vec![
+                    Value::from_f32(f32::INFINITY),
+                    Value::from_f64(f64::NEG_INFINITY),
+                    Value::from_f64(1.5),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_null() {
+        assert_eq!(
+            apply(record(), Policy::Null).unwrap(),
+            Value::Map(vec![
+                (Value::String("nan".to_owned()), Value::Unit),
+                (
+                    Value::String("list".to_owned()),
+                    Value::Sequence(vec![Value::Unit, Value::Unit, Value::from_f64(1.5)]),
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        let s = |v: &str| Value::String(v.to_owned());
+        assert_eq!(
+            apply(record(), Policy::String).unwrap(),
+            Value::Map(vec![
+                (s("nan"), s("NaN")),
+                (
+                    s("list"),
+                    Value::Sequence(vec![s("Infinity"), s("-Infinity"), Value::from_f64(1.5)]),
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        assert!(apply(record(), Policy::Error).is_err());
+        assert_eq!(
+            apply(Value::from_f64(0.5), Policy::Error).unwrap(),
+            Value::from_f64(0.5)
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use crate::error;
+use crate::value;
+
+use protobuf::descriptor;
+use std::collections;
+
+/// A transform that removes the members of protobuf `oneof`s that are not set from decoded
+/// messages, so that only the active branch of each `oneof` remains.
+///
+/// Optionally, the name of the active member (or `Unit` if none is set) is added to the message
+/// under the name of the `oneof`, which can't clash with a field name.  Synthetic `oneof`s for
+/// proto3 `optional` fields are left alone.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Oneofs {
+    message_name: String,
+    messages: collections::HashMap<String, MessageOneofs>,
+    annotate: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct MessageOneofs {
+    /// The `oneof`s of the message, with the names of their members.
+    oneofs: Vec<(String, Vec<String>)>,
+    /// Fields that contain messages, with their types.
+    message_fields: Vec<(String, String)>,
+}
+
+impl Oneofs {
+    /// Finds the `oneof`s of messages of the given type and of all messages nested in them.
+    pub fn for_message(file_set: &descriptor::FileDescriptorSet, message_name: &str) -> Self {
+        let mut messages = collections::HashMap::new();
+        for file in file_set.get_file() {
+            let prefix = if file.get_package().is_empty() {
+                String::new()
+            } else {
+                format!(".{}", file.get_package())
+            };
+            for message in file.get_message_type() {
+                add_message(&prefix, message, &mut messages);
+            }
+        }
+        Oneofs {
+            message_name: message_name.to_owned(),
+            messages,
+            annotate: false,
+        }
+    }
+
+    #[inline]
+    pub fn annotate(self, annotate: bool) -> Self {
+        Oneofs { annotate, ..self }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.values().all(|m| m.oneofs.is_empty())
+    }
+
+    fn apply(&self, value: &mut value::Value, message_name: &str) {
+        let message = match self.messages.get(message_name) {
+            Some(message) => message,
+            None => return,
+        };
+        let entries = match *value {
+            value::Value::Map(ref mut entries) => entries,
+            _ => return,
+        };
+
+        for (oneof, members) in &message.oneofs {
+            let mut active = value::Value::Unit;
+            entries.retain(|(k, v)| match *k {
+                value::Value::String(ref k) if members.contains(k) => {
+                    if *v == value::Value::Unit {
+                        false
+                    } else {
+                        active = value::Value::String(k.clone());
+                        true
+                    }
+                }
+                _ => true,
+            });
+            if self.annotate {
+                entries.push((value::Value::String(oneof.clone()), active));
+            }
+        }
+
+        for (field, field_type) in &message.message_fields {
+            for (k, v) in entries.iter_mut() {
+                if *k == value::Value::String(field.clone()) {
+                    match *v {
+                        value::Value::Sequence(ref mut seq) => {
+                            for v in seq {
+                                self.apply(v, field_type);
+                            }
+                        }
+                        ref mut v => self.apply(v, field_type),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn add_message(
+    prefix: &str,
+    message: &descriptor::DescriptorProto,
+    messages: &mut collections::HashMap<String, MessageOneofs>,
+) {
+    let name = format!("{}.{}", prefix, message.get_name());
+    for nested in message.get_nested_type() {
+        add_message(&name, nested, messages);
+    }
+
+    let mut oneofs = message
+        .get_oneof_decl()
+        .iter()
+        .map(|oneof| (oneof.get_name().to_owned(), Vec::new()))
+        .collect::<Vec<_>>();
+    let mut synthetic = vec![false; oneofs.len()];
+    let mut message_fields = Vec::new();
+    for field in message.get_field() {
+        if field.has_oneof_index() {
+            let index = field.get_oneof_index() as usize;
+            if let Some((_, members)) = oneofs.get_mut(index) {
+                members.push(field.get_name().to_owned());
+                synthetic[index] |= field.get_proto3_optional();
+            }
+        }
+        if field.get_field_type() == descriptor::FieldDescriptorProto_Type::TYPE_MESSAGE {
+            message_fields.push((
+                field.get_name().to_owned(),
+                field.get_type_name().to_owned(),
+            ));
+        }
+    }
+    let mut synthetic = synthetic.into_iter();
+    oneofs.retain(|_| !synthetic.next().unwrap_or(false));
+
+    messages.insert(
+        name,
+        MessageOneofs {
+            oneofs,
+            message_fields,
+        },
+    );
+}
+
+impl value::Transform for Oneofs {
+    fn transform(&mut self, mut v: value::Value) -> error::Result<Option<value::Value>> {
+        self.apply(&mut v, &self.message_name);
+        Ok(Some(v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Transform as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn field(
+        name: &str,
+        number: i32,
+        field_type: descriptor::FieldDescriptorProto_Type,
+        oneof_index: Option<i32>,
+    ) -> descriptor::FieldDescriptorProto {
+        let mut field = descriptor::FieldDescriptorProto::new();
+        field.set_name(name.to_owned());
+        field.set_number(number);
+        field.set_field_type(field_type);
+        if let Some(index) = oneof_index {
+            field.set_oneof_index(index);
+        }
+        field
+    }
+
+    fn file_set() -> descriptor::FileDescriptorSet {
+        use protobuf::descriptor::FieldDescriptorProto_Type as Type;
+
+        let mut event = descriptor::DescriptorProto::new();
+        event.set_name("Event".to_owned());
+        for name in &["payload", "_note"] {
+            let mut oneof = descriptor::OneofDescriptorProto::new();
+            oneof.set_name((*name).to_owned());
+            event.mut_oneof_decl().push(oneof);
+        }
+        event
+            .mut_field()
+            .push(field("click", 1, Type::TYPE_STRING, Some(0)));
+        event
+            .mut_field()
+            .push(field("view", 2, Type::TYPE_INT32, Some(0)));
+        let mut note = field("note", 3, Type::TYPE_STRING, Some(1));
+        note.set_proto3_optional(true);
+        event.mut_field().push(note);
+        let mut children = field("children", 4, Type::TYPE_MESSAGE, None);
+        children.set_label(descriptor::FieldDescriptorProto_Label::LABEL_REPEATED);
+        children.set_type_name(".acme.Event".to_owned());
+        event.mut_field().push(children);
+
+        let mut file = descriptor::FileDescriptorProto::new();
+        file.set_package("acme".to_owned());
+        file.mut_message_type().push(event);
+        let mut file_set = descriptor::FileDescriptorSet::new();
+        file_set.mut_file().push(file);
+        file_set
+    }
+
+    fn event(click: Value, view: Value, children: Vec<Value>) -> Value {
+        Value::Map(vec![
+            (s("click"), click),
+            (s("view"), view),
+            (s("note"), Value::Unit),
+            (s("children"), Value::Sequence(children)),
+        ])
+    }
+
+    #[test]
+    fn test_active_branch() {
+        let mut oneofs = Oneofs::for_message(&file_set(), ".acme.Event");
+        assert!(!oneofs.is_empty());
+
+        let child = event(Value::Unit, Value::I32(0), vec![]);
+        let record = event(s("button"), Value::Unit, vec![child]);
+        assert_eq!(
+            oneofs.transform(record).unwrap(),
+            Some(Value::Map(vec![
+                (s("click"), s("button")),
+                (s("note"), Value::Unit),
+                (
+                    s("children"),
+                    Value::Sequence(vec![Value::Map(vec![
+                        (s("view"), Value::I32(0)),
+                        (s("note"), Value::Unit),
+                        (s("children"), Value::Sequence(vec![])),
+                    ])])
+                ),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_annotate() {
+        let mut oneofs = Oneofs::for_message(&file_set(), ".acme.Event").annotate(true);
+        let record = event(Value::Unit, Value::Unit, vec![]);
+        assert_eq!(
+            oneofs.transform(record).unwrap(),
+            Some(Value::Map(vec![
+                (s("note"), Value::Unit),
+                (s("children"), Value::Sequence(vec![])),
+                (s("payload"), Value::Unit),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_unknown_message() {
+        let mut oneofs = Oneofs::for_message(&file_set(), ".acme.Missing");
+        let record = event(Value::Unit, Value::Unit, vec![]);
+        assert_eq!(oneofs.transform(record.clone()).unwrap(), Some(record));
+    }
+}
@@ -0,0 +1,2991 @@
+use crate::error;
+use crate::value;
+use crate::value::thrift::compact;
+use crate::value::thrift::compact::Read as _;
+use libflate;
+use serde_json;
+use snap;
+use std::collections;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::iter;
+use std::str;
+use std::vec;
+
+/// A source that reads a Parquet file, yielding one map per row.
+///
+/// Parquet files keep their metadata at the end, so the whole input is read into memory before
+/// the first row is decoded; rows are then decoded one row group at a time.  Lists and maps are
+/// output as sequences and maps, and strings, dates, times, timestamps and decimals are decoded
+/// like Avro logical types.  The `PLAIN` and dictionary encodings and the `SNAPPY` and `GZIP`
+/// codecs are supported.
+pub struct Source {
+    data: Vec<u8>,
+    schema: Vec<Node>,
+    columns: Vec<Column>,
+    row_groups: Vec<RowGroup>,
+    next_row_group: usize,
+    rows: vec::IntoIter<value::Value>,
+}
+
+/// A sink that writes records, which must be maps, as the rows of a Parquet file.
+///
+/// Unless `SinkOptions::schema` is set, the schema is inferred from the first
+/// `SinkOptions::infer_records` records, which are kept in memory until then: integers become
+/// `INT64` columns and other numbers `DOUBLE` columns, sequences become lists, maps with string
+/// keys become groups, and fields whose values have different types become strings, with JSON
+/// for the values that aren't strings.  All inferred fields are optional.  Each row group has a
+/// single `PLAIN` data page per column, and the footer is written when the sink is finished.
+pub struct Sink<W>
+where
+    W: io::Write,
+{
+    w: W,
+    options: SinkOptions,
+    schema: Option<Schema>,
+    columns: Vec<Column>,
+    data: Vec<ColumnData>,
+    pending: Vec<value::Value>,
+    /// Records that were kept in memory, but didn't fit the schema inferred from them.
+    rejected: collections::VecDeque<(String, value::Value)>,
+    rows: usize,
+    offset: usize,
+    row_groups: Vec<(usize, Vec<ChunkMetadata>)>,
+    finished: bool,
+}
+
+/// Settings for how a Parquet file is written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SinkOptions {
+    /// The schema of the file, which is inferred from the first records if it isn't set.
+    pub schema: Option<Schema>,
+    /// The number of records that the schema is inferred from.
+    pub infer_records: usize,
+    /// A row group is finished once it contains this many rows.
+    pub row_group_rows: usize,
+    pub codec: Codec,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Codec {
+    Uncompressed,
+    Snappy,
+    Gzip,
+}
+
+/// The schema of a Parquet file: the name of the message and its fields.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schema {
+    name: String,
+    fields: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Node {
+    name: String,
+    repetition: Repetition,
+    logical: Logical,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Leaf(Physical),
+    Group(Vec<Node>),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Repetition {
+    Required,
+    Optional,
+    Repeated,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Physical {
+    Boolean,
+    Int32,
+    Int64,
+    Int96,
+    Float,
+    Double,
+    ByteArray,
+    FixedLenByteArray(usize),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Logical {
+    None,
+    String,
+    List,
+    Map,
+    Decimal(u32),
+    Date,
+    TimeMillis,
+    TimeMicros,
+    Timestamp(TimeUnit, bool),
+    Unsigned,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum TimeUnit {
+    Millis,
+    Micros,
+    Nanos,
+}
+
+/// A leaf column, with the path of fields leading to it from the root of the schema.
+#[derive(Debug)]
+struct Column {
+    levels: Vec<Level>,
+    physical: Physical,
+    logical: Logical,
+}
+
+/// A field on the path to a column, with the maximum definition and repetition levels that
+/// values have when the field is present.
+#[derive(Clone, Debug)]
+struct Level {
+    name: String,
+    repetition: Repetition,
+    def: u16,
+    rep: u16,
+}
+
+#[derive(Debug)]
+struct RowGroup {
+    num_rows: usize,
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    codec: i64,
+    num_values: usize,
+    start: usize,
+}
+
+/// A decoded value of a column, with its repetition and definition levels; the value is only
+/// present if the definition level is the maximum one.
+struct Entry {
+    rep: u16,
+    def: u16,
+    value: Option<value::Value>,
+}
+
+/// A value of the Thrift compact protocol, which Parquet metadata is encoded with.  Structs are
+/// kept as lists of field IDs and values, since only a few fields of each are interesting.
+#[derive(Debug)]
+enum Thrift {
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    List(Vec<Thrift>),
+    Map(Vec<(Thrift, Thrift)>),
+    Struct(Vec<(i16, Thrift)>),
+}
+
+struct ThriftReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// The levels and `PLAIN`-encoded values of a column of the row group that is being written.
+#[derive(Debug, Default)]
+struct ColumnData {
+    reps: Vec<u16>,
+    defs: Vec<u16>,
+    values: Vec<u8>,
+    bits: usize,
+}
+
+/// The location and size of a column chunk that has been written.
+#[derive(Debug)]
+struct ChunkMetadata {
+    start: usize,
+    num_values: usize,
+    uncompressed_size: usize,
+    compressed_size: usize,
+}
+
+/// Where a value is written in the columns of its record: the repetition level of its first
+/// column value, the definition level that it has when present, and the number of repeated
+/// fields that it is inside of.
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    rep: u16,
+    def: u16,
+    depth: u16,
+}
+
+/// The types that the values of a field have, for inferring a schema.
+#[derive(Clone, Debug, PartialEq)]
+enum Shape {
+    Null,
+    Bool,
+    Int,
+    Unsigned,
+    Float,
+    String,
+    Bytes,
+    List(Box<Shape>),
+    Struct(Vec<(String, Shape)>),
+}
+
+struct SchemaParser<'a> {
+    tokens: iter::Peekable<vec::IntoIter<&'a str>>,
+}
+
+const MAGIC: &[u8] = b"PAR1";
+const MAX_DEPTH: usize = 64;
+
+pub fn source<R>(mut r: R) -> error::Result<Source>
+where
+    R: io::Read,
+{
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    if data.len() < 12 || !data.starts_with(MAGIC) || !data.ends_with(MAGIC) {
+        return Err(format_error("not a Parquet file".to_owned()));
+    }
+
+    let end = data.len() - 8;
+    let length = u32_le(&data[end..end + 4]) as usize;
+    let start = end
+        .checked_sub(length)
+        .filter(|&start| start >= MAGIC.len())
+        .ok_or_else(|| format_error("bad Parquet footer length".to_owned()))?;
+    let metadata = ThriftReader::new(&data[start..end]).structure(0)?;
+
+    let elements = metadata.list(2);
+    let (root, elements) = elements
+        .split_first()
+        .ok_or_else(|| format_error("the Parquet schema is empty".to_owned()))?;
+    let mut elements = elements.iter();
+    let schema = (0..root.int(5).unwrap_or(0))
+        .map(|_| node(&mut elements, 0))
+        .collect::<error::Result<Vec<_>>>()?;
+
+    let mut columns = Vec::new();
+    for node in &schema {
+        add_columns(node, &mut Vec::new(), 0, 0, &mut columns);
+    }
+
+    let row_groups = metadata
+        .list(4)
+        .iter()
+        .map(|group| row_group(group, columns.len()))
+        .collect::<error::Result<Vec<_>>>()?;
+
+    Ok(Source {
+        data,
+        schema,
+        columns,
+        row_groups,
+        next_row_group: 0,
+        rows: Vec::new().into_iter(),
+    })
+}
+
+/// Writes records as the rows of a Parquet file.
+pub fn sink<W>(w: W, mut options: SinkOptions) -> Sink<W>
+where
+    W: io::Write,
+{
+    let schema = options.schema.take();
+    let mut sink = Sink {
+        w,
+        options,
+        schema: None,
+        columns: Vec::new(),
+        data: Vec::new(),
+        pending: Vec::new(),
+        rejected: collections::VecDeque::new(),
+        rows: 0,
+        offset: 0,
+        row_groups: Vec::new(),
+        finished: false,
+    };
+    if let Some(schema) = schema {
+        sink.set_schema(schema);
+    }
+    sink
+}
+
+fn node<'a, I>(elements: &mut I, depth: usize) -> error::Result<Node>
+where
+    I: Iterator<Item = &'a Thrift>,
+{
+    let element = elements
+        .next()
+        .ok_or_else(|| format_error("truncated Parquet schema".to_owned()))?;
+    if depth > MAX_DEPTH {
+        return Err(format_error(
+            "the Parquet schema is nested too deeply".to_owned(),
+        ));
+    }
+
+    let name = element.string(4).unwrap_or_default();
+    let repetition = match element.int(3) {
+        Some(1) => Repetition::Optional,
+        Some(2) => Repetition::Repeated,
+        _ => Repetition::Required,
+    };
+    let kind = match element.int(5) {
+        Some(children) => Kind::Group(
+            (0..children)
+                .map(|_| node(elements, depth + 1))
+                .collect::<error::Result<_>>()?,
+        ),
+        None => Kind::Leaf(match element.int(1) {
+            Some(0) => Physical::Boolean,
+            Some(1) => Physical::Int32,
+            Some(2) => Physical::Int64,
+            Some(3) => Physical::Int96,
+            Some(4) => Physical::Float,
+            Some(5) => Physical::Double,
+            Some(6) => Physical::ByteArray,
+            Some(7) => Physical::FixedLenByteArray(element.int(2).unwrap_or(0) as usize),
+            t => {
+                return Err(format_error(format!(
+                    "unknown Parquet type {:?} of field {}",
+                    t, name
+                )))
+            }
+        }),
+    };
+    let logical = element
+        .field(10)
+        .and_then(logical_type)
+        .unwrap_or_else(|| converted_type(element));
+
+    Ok(Node {
+        name,
+        repetition,
+        logical,
+        kind,
+    })
+}
+
+/// Interprets a `LogicalType` union.
+fn logical_type(t: &Thrift) -> Option<Logical> {
+    if t.field(1).is_some() || t.field(4).is_some() || t.field(12).is_some() {
+        Some(Logical::String)
+    } else if t.field(2).is_some() {
+        Some(Logical::Map)
+    } else if t.field(3).is_some() {
+        Some(Logical::List)
+    } else if let Some(decimal) = t.field(5) {
+        Some(Logical::Decimal(decimal.int(1).unwrap_or(0) as u32))
+    } else if t.field(6).is_some() {
+        Some(Logical::Date)
+    } else if let Some(time) = t.field(7) {
+        match time_unit(time.field(2)?)? {
+            TimeUnit::Millis => Some(Logical::TimeMillis),
+            TimeUnit::Micros => Some(Logical::TimeMicros),
+            TimeUnit::Nanos => Some(Logical::None),
+        }
+    } else if let Some(timestamp) = t.field(8) {
+        let utc = timestamp.bool(1).unwrap_or(true);
+        Some(Logical::Timestamp(time_unit(timestamp.field(2)?)?, utc))
+    } else if let Some(integer) = t.field(10) {
+        if integer.bool(2) == Some(false) {
+            Some(Logical::Unsigned)
+        } else {
+            Some(Logical::None)
+        }
+    } else {
+        None
+    }
+}
+
+fn time_unit(t: &Thrift) -> Option<TimeUnit> {
+    if t.field(1).is_some() {
+        Some(TimeUnit::Millis)
+    } else if t.field(2).is_some() {
+        Some(TimeUnit::Micros)
+    } else if t.field(3).is_some() {
+        Some(TimeUnit::Nanos)
+    } else {
+        None
+    }
+}
+
+/// Interprets the legacy `ConvertedType` of a schema element.
+fn converted_type(element: &Thrift) -> Logical {
+    match element.int(6) {
+        Some(0) | Some(4) | Some(19) => Logical::String,
+        Some(1) | Some(2) => Logical::Map,
+        Some(3) => Logical::List,
+        Some(5) => Logical::Decimal(element.int(7).unwrap_or(0) as u32),
+        Some(6) => Logical::Date,
+        Some(7) => Logical::TimeMillis,
+        Some(8) => Logical::TimeMicros,
+        Some(9) => Logical::Timestamp(TimeUnit::Millis, true),
+        Some(10) => Logical::Timestamp(TimeUnit::Micros, true),
+        Some(11..=14) => Logical::Unsigned,
+        _ => Logical::None,
+    }
+}
+
+fn add_columns(node: &Node, path: &mut Vec<Level>, def: u16, rep: u16, columns: &mut Vec<Column>) {
+    let (def, rep) = match node.repetition {
+        Repetition::Required => (def, rep),
+        Repetition::Optional => (def + 1, rep),
+        Repetition::Repeated => (def + 1, rep + 1),
+    };
+    path.push(Level {
+        name: node.name.clone(),
+        repetition: node.repetition,
+        def,
+        rep,
+    });
+    match node.kind {
+        Kind::Leaf(physical) => columns.push(Column {
+            levels: path.clone(),
+            physical,
+            logical: node.logical,
+        }),
+        Kind::Group(ref children) => {
+            for child in children {
+                add_columns(child, path, def, rep, columns);
+            }
+        }
+    }
+    path.pop();
+}
+
+fn row_group(group: &Thrift, num_columns: usize) -> error::Result<RowGroup> {
+    let chunks = group
+        .list(1)
+        .iter()
+        .map(|chunk| {
+            if chunk.field(1).is_some() {
+                return Err(error::Error::Unimplemented {
+                    msg: "Parquet columns in external files".to_owned(),
+                });
+            }
+            let metadata = chunk
+                .field(3)
+                .ok_or_else(|| format_error("missing Parquet column metadata".to_owned()))?;
+            let data_page_offset = metadata.int(9).unwrap_or(0);
+            let start = match metadata.int(11) {
+                Some(offset) if offset > 0 && offset < data_page_offset => offset,
+                _ => data_page_offset,
+            };
+            Ok(Chunk {
+                codec: metadata.int(4).unwrap_or(0),
+                num_values: metadata.int(5).unwrap_or(0).max(0) as usize,
+                start: start.max(0) as usize,
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+    if chunks.len() != num_columns {
+        return Err(format_error(format!(
+            "a Parquet row group has {} columns, but the schema has {}",
+            chunks.len(),
+            num_columns
+        )));
+    }
+    Ok(RowGroup {
+        num_rows: group.int(3).unwrap_or(0).max(0) as usize,
+        chunks,
+    })
+}
+
+impl Source {
+    fn read_row_group(&self, group: &RowGroup) -> error::Result<Vec<value::Value>> {
+        let mut entries = self
+            .columns
+            .iter()
+            .zip(&group.chunks)
+            .map(|(column, chunk)| {
+                Ok(read_chunk(&self.data, column, chunk)?
+                    .into_iter()
+                    .peekable())
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+
+        let mut rows = Vec::with_capacity(group.num_rows);
+        for _ in 0..group.num_rows {
+            let mut fields = Vec::new();
+            for (column, entries) in self.columns.iter().zip(&mut entries) {
+                let max_rep = column.levels.last().map_or(0, |l| l.rep) as usize;
+                let mut counters = vec![0; max_rep];
+                let mut first = true;
+                while let Some(entry) = entries.next_if(|e| first || e.rep > 0) {
+                    if !first {
+                        // A repetition level says which repeated field gets a new element
+                        let rep = entry.rep as usize;
+                        counters[rep - 1] += 1;
+                        for counter in &mut counters[rep..] {
+                            *counter = 0;
+                        }
+                    }
+                    first = false;
+                    insert(
+                        &mut fields,
+                        &column.levels,
+                        &counters,
+                        entry.def,
+                        entry.value,
+                    );
+                }
+                if first {
+                    return Err(format_error(
+                        "a Parquet column has fewer values than its row group has rows".to_owned(),
+                    ));
+                }
+            }
+            rows.push(value::Value::Map(simplify(fields, &self.schema)));
+        }
+        Ok(rows)
+    }
+}
+
+/// Inserts a value of a column into a row, creating the maps and sequences on its path.
+fn insert(
+    fields: &mut Vec<(value::Value, value::Value)>,
+    levels: &[Level],
+    counters: &[usize],
+    def: u16,
+    v: Option<value::Value>,
+) {
+    let (level, rest) = match levels.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let key = value::Value::String(level.name.clone());
+    let i = match fields.iter().position(|(k, _)| *k == key) {
+        Some(i) => i,
+        None => {
+            let empty = if level.repetition == Repetition::Repeated {
+                value::Value::Sequence(Vec::new())
+            } else if def < level.def || rest.is_empty() {
+                value::Value::Unit
+            } else {
+                value::Value::Map(Vec::new())
+            };
+            fields.push((key, empty));
+            fields.len() - 1
+        }
+    };
+    if def < level.def {
+        // This field is null or empty, which every column below it agrees on
+        return;
+    }
+
+    let slot = &mut fields[i].1;
+    let slot = if level.repetition == Repetition::Repeated {
+        let index = counters[level.rep as usize - 1];
+        match *slot {
+            value::Value::Sequence(ref mut items) => {
+                while items.len() <= index {
+                    items.push(if rest.is_empty() {
+                        value::Value::Unit
+                    } else {
+                        value::Value::Map(Vec::new())
+                    });
+                }
+                &mut items[index]
+            }
+            _ => return,
+        }
+    } else {
+        slot
+    };
+
+    if rest.is_empty() {
+        *slot = v.unwrap_or(value::Value::Unit);
+    } else if let value::Value::Map(ref mut fields) = *slot {
+        insert(fields, rest, counters, def, v);
+    }
+}
+
+/// Turns the groups of lists and maps into sequences and maps.
+fn simplify(
+    fields: Vec<(value::Value, value::Value)>,
+    nodes: &[Node],
+) -> Vec<(value::Value, value::Value)> {
+    fields
+        .into_iter()
+        .map(|(k, v)| {
+            let node = match k {
+                value::Value::String(ref name) => nodes.iter().find(|n| n.name == *name),
+                _ => None,
+            };
+            match node {
+                Some(node) if node.repetition == Repetition::Repeated => match v {
+                    value::Value::Sequence(items) => {
+                        let items = items
+                            .into_iter()
+                            .map(|item| simplify_group(item, node))
+                            .collect();
+                        (k, value::Value::Sequence(items))
+                    }
+                    v => (k, v),
+                },
+                Some(node) => (k, simplify_group(v, node)),
+                None => (k, v),
+            }
+        })
+        .collect()
+}
+
+fn simplify_group(v: value::Value, node: &Node) -> value::Value {
+    let children = match node.kind {
+        Kind::Group(ref children) => children,
+        Kind::Leaf(_) => return v,
+    };
+    let fields = match v {
+        value::Value::Map(fields) => simplify(fields, children),
+        v => return v,
+    };
+
+    // Lists and maps are a group with a single repeated field of elements or key-value pairs
+    let repeated = match children.as_slice() {
+        [child] if child.repetition == Repetition::Repeated => child,
+        _ => return value::Value::Map(fields),
+    };
+    let items = match (node.logical, fields.as_slice()) {
+        (Logical::List, [(_, value::Value::Sequence(_))])
+        | (Logical::Map, [(_, value::Value::Sequence(_))]) => match fields.into_iter().next() {
+            Some((_, value::Value::Sequence(items))) => items,
+            _ => return value::Value::Unit,
+        },
+        _ => return value::Value::Map(fields),
+    };
+
+    if node.logical == Logical::Map {
+        let entries = items
+            .into_iter()
+            .map(|item| match item {
+                value::Value::Map(entry) => {
+                    let mut entry = entry.into_iter().map(|(_, v)| v);
+                    let k = entry.next().unwrap_or(value::Value::Unit);
+                    (k, entry.next().unwrap_or(value::Value::Unit))
+                }
+                item => (item, value::Value::Unit),
+            })
+            .collect();
+        return value::Value::Map(entries);
+    }
+
+    let unwrap = list_element(repeated).is_some();
+    value::Value::Sequence(
+        items
+            .into_iter()
+            .map(|item| match item {
+                value::Value::Map(mut element) if unwrap && element.len() == 1 => {
+                    element.pop().map_or(value::Value::Unit, |(_, v)| v)
+                }
+                item => item,
+            })
+            .collect(),
+    )
+}
+
+/// The element of a list whose repeated field is a group of one field, which is the element
+/// itself unless the group is the element, like in some legacy lists.
+fn list_element(repeated: &Node) -> Option<&Node> {
+    match repeated.kind {
+        Kind::Group(ref fields)
+            if fields.len() == 1
+                && repeated.name != "array"
+                && !repeated.name.ends_with("_tuple") =>
+        {
+            fields.first()
+        }
+        _ => None,
+    }
+}
+
+fn read_chunk(data: &[u8], column: &Column, chunk: &Chunk) -> error::Result<Vec<Entry>> {
+    let max_def = column.levels.last().map_or(0, |l| l.def);
+    let max_rep = column.levels.last().map_or(0, |l| l.rep);
+
+    let mut entries = Vec::with_capacity(chunk.num_values.min(1 << 16));
+    let mut dictionary = None;
+    let mut pos = chunk.start;
+    while entries.len() < chunk.num_values {
+        let mut reader = ThriftReader::new(data.get(pos..).unwrap_or_default());
+        let header = reader.structure(0)?;
+        pos += reader.pos;
+
+        let compressed_size = header.int(3).unwrap_or(0).max(0) as usize;
+        let uncompressed_size = header.int(2).unwrap_or(0).max(0) as usize;
+        let body = slice(data, pos, compressed_size)?;
+        pos += compressed_size;
+
+        match header.int(1) {
+            Some(2) => {
+                let num_values = header.field(7).and_then(|h| h.int(1)).unwrap_or(0);
+                let page = decompress(chunk.codec, body, uncompressed_size)?;
+                let mut plain = Plain::new(&page, column);
+                dictionary = Some(
+                    (0..num_values)
+                        .map(|_| plain.next())
+                        .collect::<error::Result<Vec<_>>>()?,
+                );
+            }
+            Some(0) => {
+                let page_header = header
+                    .field(5)
+                    .ok_or_else(|| format_error("missing Parquet data page header".to_owned()))?;
+                let num_values = page_values(page_header, chunk, entries.len())?;
+                let page = decompress(chunk.codec, body, uncompressed_size)?;
+                let mut input = page.as_slice();
+                let reps = levels_v1(&mut input, max_rep, num_values)?;
+                let defs = levels_v1(&mut input, max_def, num_values)?;
+                let encoding = page_header.int(2).unwrap_or(0);
+                let values = decode_values(input, column, encoding, &defs, dictionary.as_deref())?;
+                add_entries(&mut entries, &reps, &defs, values, max_rep, max_def)?;
+            }
+            Some(3) => {
+                let page_header = header
+                    .field(8)
+                    .ok_or_else(|| format_error("missing Parquet data page header".to_owned()))?;
+                let num_values = page_values(page_header, chunk, entries.len())?;
+                let def_length = page_header.int(5).unwrap_or(0).max(0) as usize;
+                let rep_length = page_header.int(6).unwrap_or(0).max(0) as usize;
+                let reps = levels(slice(body, 0, rep_length)?, max_rep, num_values)?;
+                let defs = levels(slice(body, rep_length, def_length)?, max_def, num_values)?;
+                let input = &body[rep_length + def_length..];
+                let page = if page_header.bool(7).unwrap_or(true) {
+                    let size = uncompressed_size.saturating_sub(rep_length + def_length);
+                    decompress(chunk.codec, input, size)?
+                } else {
+                    input.to_vec()
+                };
+                let encoding = page_header.int(4).unwrap_or(0);
+                let values = decode_values(&page, column, encoding, &defs, dictionary.as_deref())?;
+                add_entries(&mut entries, &reps, &defs, values, max_rep, max_def)?;
+            }
+            // Index pages and unknown page types can be skipped
+            _ => (),
+        }
+    }
+    Ok(entries)
+}
+
+/// The number of values in a data page, which can't be more than the rest of the column chunk.
+fn page_values(page_header: &Thrift, chunk: &Chunk, read: usize) -> error::Result<usize> {
+    let num_values = page_header.int(1).unwrap_or(0).max(0) as usize;
+    if num_values > chunk.num_values - read {
+        return Err(format_error(
+            "a Parquet page has more values than its column chunk".to_owned(),
+        ));
+    }
+    Ok(num_values)
+}
+
+fn add_entries(
+    entries: &mut Vec<Entry>,
+    reps: &[u16],
+    defs: &[u16],
+    values: Vec<value::Value>,
+    max_rep: u16,
+    max_def: u16,
+) -> error::Result<()> {
+    let mut values = values.into_iter();
+    for (&rep, &def) in reps.iter().zip(defs) {
+        if rep > max_rep || def > max_def || (entries.is_empty() && rep > 0) {
+            return Err(format_error(
+                "bad Parquet repetition or definition level".to_owned(),
+            ));
+        }
+        let value = if def == max_def {
+            Some(values.next().ok_or_else(truncated)?)
+        } else {
+            None
+        };
+        entries.push(Entry { rep, def, value });
+    }
+    Ok(())
+}
+
+/// Reads levels of a version 1 data page, which are prefixed by their length.
+fn levels_v1(input: &mut &[u8], max: u16, n: usize) -> error::Result<Vec<u16>> {
+    if max == 0 {
+        return Ok(vec![0; n]);
+    }
+    let length = u32_le(slice(input, 0, 4)?) as usize;
+    let data = slice(input, 4, length)?;
+    *input = &input[4 + length..];
+    levels(data, max, n)
+}
+
+fn levels(data: &[u8], max: u16, n: usize) -> error::Result<Vec<u16>> {
+    if max == 0 {
+        return Ok(vec![0; n]);
+    }
+    let bit_width = 16 - max.leading_zeros();
+    Ok(rle_hybrid(data, bit_width, n)?
+        .into_iter()
+        .map(|level| level as u16)
+        .collect())
+}
+
+/// Decodes `n` values of the given bit width that are encoded with the RLE/bit-packing hybrid
+/// encoding.
+fn rle_hybrid(data: &[u8], bit_width: u32, n: usize) -> error::Result<Vec<u32>> {
+    if bit_width > 32 {
+        return Err(format_error(format!("bad Parquet bit width {}", bit_width)));
+    }
+    let mask = ((1u64 << bit_width) - 1) as u32;
+
+    let mut values = Vec::with_capacity(n.min(1 << 16));
+    let mut reader = ThriftReader::new(data);
+    while values.len() < n {
+        let header = reader.varint()?;
+        let count = (header >> 1) as usize;
+        if header & 1 == 0 {
+            let bytes = reader.bytes((bit_width as usize + 7) / 8)?;
+            let v = bytes
+                .iter()
+                .rev()
+                .fold(0u32, |v, &b| v.wrapping_shl(8) | u32::from(b));
+            values.extend(std::iter::repeat(v & mask).take(count.min(n - values.len())));
+        } else {
+            let length = count.saturating_mul(bit_width as usize);
+            let bytes = reader.bytes(length.min(data.len() - reader.pos))?;
+            for i in 0..count.saturating_mul(8).min(n - values.len()) {
+                let bit = i * bit_width as usize;
+                let mut window = [0; 8];
+                for (j, w) in window.iter_mut().enumerate() {
+                    *w = bytes.get(bit / 8 + j).copied().unwrap_or(0);
+                }
+                let v = (u64::from_le_bytes(window) >> (bit % 8)) as u32 & mask;
+                values.push(v);
+            }
+        }
+    }
+    Ok(values)
+}
+
+fn decode_values(
+    data: &[u8],
+    column: &Column,
+    encoding: i64,
+    defs: &[u16],
+    dictionary: Option<&[value::Value]>,
+) -> error::Result<Vec<value::Value>> {
+    let max_def = column.levels.last().map_or(0, |l| l.def);
+    let present = defs.iter().filter(|&&def| def == max_def).count();
+    match encoding {
+        0 => {
+            let mut plain = Plain::new(data, column);
+            (0..present).map(|_| plain.next()).collect()
+        }
+        2 | 8 => {
+            let dictionary = dictionary.ok_or_else(|| {
+                format_error("a Parquet column has no dictionary page".to_owned())
+            })?;
+            let (&bit_width, data) = data.split_first().ok_or_else(truncated)?;
+            rle_hybrid(data, u32::from(bit_width), present)?
+                .into_iter()
+                .map(|i| {
+                    dictionary
+                        .get(i as usize)
+                        .cloned()
+                        .ok_or_else(|| format_error(format!("bad Parquet dictionary index {}", i)))
+                })
+                .collect()
+        }
+        _ => Err(error::Error::Unimplemented {
+            msg: format!("Parquet encoding {}", encoding),
+        }),
+    }
+}
+
+/// A decoder of the `PLAIN` encoding.
+struct Plain<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: usize,
+    column: &'a Column,
+}
+
+impl<'a> Plain<'a> {
+    fn new(data: &'a [u8], column: &'a Column) -> Self {
+        Plain {
+            data,
+            pos: 0,
+            bit: 0,
+            column,
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> error::Result<&'a [u8]> {
+        let bytes = slice(self.data, self.pos, n)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn next(&mut self) -> error::Result<value::Value> {
+        let logical = self.column.logical;
+        Ok(match self.column.physical {
+            Physical::Boolean => {
+                let byte = self.data.get(self.bit / 8).ok_or_else(truncated)?;
+                let v = byte >> (self.bit % 8) & 1 == 1;
+                self.bit += 1;
+                value::Value::Bool(v)
+            }
+            Physical::Int32 => int32(u32_le(self.bytes(4)?) as i32, logical),
+            Physical::Int64 => int64(u64_le(self.bytes(8)?) as i64, logical),
+            Physical::Int96 => {
+                let bytes = self.bytes(12)?;
+                int96(u64_le(&bytes[..8]), u32_le(&bytes[8..]))
+            }
+            Physical::Float => value::Value::from_f32(f32::from_bits(u32_le(self.bytes(4)?))),
+            Physical::Double => value::Value::from_f64(f64::from_bits(u64_le(self.bytes(8)?))),
+            Physical::ByteArray => {
+                let length = u32_le(self.bytes(4)?) as usize;
+                byte_array(self.bytes(length)?, logical)
+            }
+            Physical::FixedLenByteArray(length) => byte_array(self.bytes(length)?, logical),
+        })
+    }
+}
+
+fn int32(v: i32, logical: Logical) -> value::Value {
+    match logical {
+        Logical::Unsigned => value::Value::U32(v as u32),
+        Logical::Decimal(scale) => value::Value::String(value::avro::decimal(v.into(), scale)),
+        Logical::Date => value::Value::String(value::timestamp::iso_date(v.into())),
+        Logical::TimeMillis => value::Value::String(value::avro::time_of_day(i64::from(v) * 1000)),
+        _ => value::Value::I32(v),
+    }
+}
+
+fn int64(v: i64, logical: Logical) -> value::Value {
+    let (per_second, nanos_per_unit) = match logical {
+        Logical::Unsigned => return value::Value::U64(v as u64),
+        Logical::Decimal(scale) => {
+            return value::Value::String(value::avro::decimal(v.into(), scale))
+        }
+        Logical::TimeMicros => return value::Value::String(value::avro::time_of_day(v)),
+        Logical::Timestamp(TimeUnit::Millis, _) => (1_000, 1_000_000),
+        Logical::Timestamp(TimeUnit::Micros, _) => (1_000_000, 1_000),
+        Logical::Timestamp(TimeUnit::Nanos, _) => (1_000_000_000, 1),
+        _ => return value::Value::I64(v),
+    };
+    let mut s = value::timestamp::rfc3339_nanos(
+        v.div_euclid(per_second),
+        (v.rem_euclid(per_second) * nanos_per_unit) as u32,
+    );
+    if logical == Logical::Timestamp(TimeUnit::Millis, false)
+        || logical == Logical::Timestamp(TimeUnit::Micros, false)
+        || logical == Logical::Timestamp(TimeUnit::Nanos, false)
+    {
+        // Timestamps that aren't adjusted to UTC are local
+        s.pop();
+    }
+    value::Value::String(s)
+}
+
+/// Decodes a legacy `INT96` timestamp, which is the nanoseconds since midnight of a Julian day.
+fn int96(nanos: u64, julian_day: u32) -> value::Value {
+    const UNIX_EPOCH_JULIAN_DAY: i64 = 2_440_588;
+
+    let days = i64::from(julian_day) - UNIX_EPOCH_JULIAN_DAY;
+    value::Value::String(value::timestamp::rfc3339_nanos(
+        days * 86_400 + (nanos / 1_000_000_000) as i64,
+        (nanos % 1_000_000_000) as u32,
+    ))
+}
+
+fn byte_array(bytes: &[u8], logical: Logical) -> value::Value {
+    match logical {
+        Logical::String => match String::from_utf8(bytes.to_vec()) {
+            Ok(s) => value::Value::String(s),
+            Err(e) => value::Value::Bytes(e.into_bytes()),
+        },
+        Logical::Decimal(scale) if bytes.len() <= 16 => {
+            // Unscaled decimals are big-endian two's complement integers
+            let sign = if bytes.first().map_or(false, |&b| b >= 0x80) {
+                -1
+            } else {
+                0
+            };
+            let unscaled = bytes
+                .iter()
+                .fold(sign, |v: i128, &b| v << 8 | i128::from(b));
+            value::Value::String(value::avro::decimal(unscaled, scale))
+        }
+        _ => value::Value::Bytes(bytes.to_vec()),
+    }
+}
+
+fn decompress(codec: i64, data: &[u8], size: usize) -> error::Result<Vec<u8>> {
+    use std::io::Read;
+
+    match codec {
+        0 => Ok(data.to_vec()),
+        1 => snap::Decoder::new()
+            .decompress_vec(data)
+            .map_err(|e| format_error(format!("bad Snappy data in a Parquet page: {}", e))),
+        2 => {
+            let mut page = Vec::with_capacity(size.min(1 << 24));
+            libflate::gzip::Decoder::new(data)?.read_to_end(&mut page)?;
+            Ok(page)
+        }
+        _ => {
+            let name = match codec {
+                3 => "LZO",
+                4 => "BROTLI",
+                5 | 7 => "LZ4",
+                6 => "ZSTD",
+                _ => "unknown",
+            };
+            Err(error::Error::Unimplemented {
+                msg: format!("the {} Parquet compression codec", name),
+            })
+        }
+    }
+}
+
+impl Thrift {
+    fn field(&self, id: i16) -> Option<&Thrift> {
+        match *self {
+            Thrift::Struct(ref fields) => fields.iter().find(|f| f.0 == id).map(|f| &f.1),
+            _ => None,
+        }
+    }
+
+    fn int(&self, id: i16) -> Option<i64> {
+        match self.field(id) {
+            Some(&Thrift::Int(n)) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn bool(&self, id: i16) -> Option<bool> {
+        match self.field(id) {
+            Some(&Thrift::Bool(b)) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn string(&self, id: i16) -> Option<String> {
+        match self.field(id) {
+            Some(Thrift::Binary(ref b)) => Some(String::from_utf8_lossy(b).into_owned()),
+            _ => None,
+        }
+    }
+
+    fn list(&self, id: i16) -> &[Thrift] {
+        match self.field(id) {
+            Some(Thrift::List(ref items)) => items,
+            _ => &[],
+        }
+    }
+}
+
+impl<'a> ThriftReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ThriftReader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> error::Result<&'a [u8]> {
+        let bytes = slice(self.data, self.pos, n)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn structure(&mut self, depth: usize) -> error::Result<Thrift> {
+        if depth > MAX_DEPTH {
+            return Err(format_error(
+                "Parquet metadata is nested too deeply".to_owned(),
+            ));
+        }
+        let mut fields = Vec::new();
+        let mut id = 0;
+        while let Some((field_id, kind)) = self.field_header(id)? {
+            id = field_id;
+            let v = match kind {
+                compact::BOOL_TRUE => Thrift::Bool(true),
+                compact::BOOL_FALSE => Thrift::Bool(false),
+                kind => self.value(kind, depth)?,
+            };
+            fields.push((id, v));
+        }
+        Ok(Thrift::Struct(fields))
+    }
+
+    fn value(&mut self, kind: u8, depth: usize) -> error::Result<Thrift> {
+        Ok(match kind {
+            // Booleans outside of struct fields are encoded as a byte
+            compact::BOOL_TRUE | compact::BOOL_FALSE => Thrift::Bool(self.byte()? == 1),
+            compact::BYTE => Thrift::Int(i64::from(self.byte()? as i8)),
+            compact::I16 | compact::I32 | compact::I64 => Thrift::Int(self.zigzag()?),
+            compact::DOUBLE => Thrift::Double(f64::from_bits(u64_le(self.bytes(8)?))),
+            compact::BINARY => {
+                let length = self.size()?;
+                Thrift::Binary(self.bytes(length)?.to_vec())
+            }
+            compact::LIST | compact::SET => {
+                let (item_kind, size) = self.list_header()?;
+                let mut items = Vec::with_capacity(size.min(1 << 16));
+                for _ in 0..size {
+                    items.push(self.value(item_kind, depth + 1)?);
+                }
+                Thrift::List(items)
+            }
+            compact::MAP => {
+                let (key_kind, value_kind, size) = self.map_header()?;
+                let mut entries = Vec::with_capacity(size.min(1 << 16));
+                for _ in 0..size {
+                    let k = self.value(key_kind, depth + 1)?;
+                    let v = self.value(value_kind, depth + 1)?;
+                    entries.push((k, v));
+                }
+                Thrift::Map(entries)
+            }
+            compact::STRUCT => self.structure(depth + 1)?,
+            _ => return Err(self.error(format!("unknown Thrift type {}", kind))),
+        })
+    }
+}
+
+impl<'a> compact::Read for ThriftReader<'a> {
+    fn byte(&mut self) -> error::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn error(&self, msg: String) -> error::Error {
+        format_error(format!("bad Parquet metadata: {}", msg))
+    }
+}
+
+impl Schema {
+    /// Parses a schema in the text format of `parquet-mr`, like
+    /// `message m { required int64 id; optional binary name (STRING); }`.
+    ///
+    /// Groups can be annotated with `LIST` and `MAP`, `binary` fields with `STRING`, `UTF8`,
+    /// `ENUM` or `JSON`, `int32` fields with `DATE`, and `int64` fields with
+    /// `TIMESTAMP_MILLIS`, `TIMESTAMP_MICROS` or `TIMESTAMP(unit, isAdjustedToUTC)`; annotations
+    /// like `UINT_32` or `INTEGER(64, false)` make integers unsigned.  Dates and timestamps are
+    /// written from RFC 3339 strings, or from numbers of days or time units since the epoch.
+    pub fn parse(s: &str) -> error::Result<Self> {
+        let mut parser = SchemaParser {
+            tokens: schema_tokens(s).into_iter().peekable(),
+        };
+        parser.expect("message")?;
+        let name = parser.next()?.to_owned();
+        let fields = parser.group(0)?;
+        if let Some(token) = parser.tokens.next() {
+            return Err(schema_error(format!(
+                "unexpected {:?} after the message",
+                token
+            )));
+        }
+        Ok(Schema { name, fields })
+    }
+}
+
+/// Splits a schema into words and punctuation.
+fn schema_tokens(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in s.char_indices() {
+        if c.is_alphanumeric() || c == '_' {
+            start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = start.take() {
+            tokens.push(&s[start..i]);
+        }
+        if !c.is_whitespace() {
+            tokens.push(&s[i..i + c.len_utf8()]);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&s[start..]);
+    }
+    tokens
+}
+
+impl<'a> SchemaParser<'a> {
+    fn next(&mut self) -> error::Result<&'a str> {
+        self.tokens
+            .next()
+            .ok_or_else(|| schema_error("unexpected end".to_owned()))
+    }
+
+    fn expect(&mut self, token: &str) -> error::Result<()> {
+        match self.next()? {
+            t if t == token => Ok(()),
+            t => Err(schema_error(format!("expected {:?}, found {:?}", token, t))),
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.tokens.next_if(|&t| t == token).is_some()
+    }
+
+    /// Parses the fields of a group, from `{` to `}`.
+    fn group(&mut self, depth: usize) -> error::Result<Vec<Node>> {
+        if depth > MAX_DEPTH {
+            return Err(schema_error("the groups are nested too deeply".to_owned()));
+        }
+        self.expect("{")?;
+        let mut fields = Vec::new();
+        while !self.eat("}") {
+            fields.push(self.field(depth)?);
+        }
+        Ok(fields)
+    }
+
+    fn field(&mut self, depth: usize) -> error::Result<Node> {
+        let repetition = match self.next()? {
+            "required" => Repetition::Required,
+            "optional" => Repetition::Optional,
+            "repeated" => Repetition::Repeated,
+            t => {
+                return Err(schema_error(format!(
+                    "expected 'required', 'optional' or 'repeated', found {:?}",
+                    t
+                )))
+            }
+        };
+        let physical = match self.next()? {
+            "group" => None,
+            "boolean" => Some(Physical::Boolean),
+            "int32" => Some(Physical::Int32),
+            "int64" => Some(Physical::Int64),
+            "float" => Some(Physical::Float),
+            "double" => Some(Physical::Double),
+            "binary" => Some(Physical::ByteArray),
+            "fixed_len_byte_array" => {
+                self.expect("(")?;
+                let length = self.next()?;
+                self.expect(")")?;
+                Some(Physical::FixedLenByteArray(length.parse().map_err(
+                    |_| schema_error(format!("bad fixed_len_byte_array length {:?}", length)),
+                )?))
+            }
+            "int96" => {
+                return Err(error::Error::Unimplemented {
+                    msg: "writing Parquet int96 columns".to_owned(),
+                })
+            }
+            t => return Err(schema_error(format!("unknown type {:?}", t))),
+        };
+        let name = self.next()?.to_owned();
+        let logical = if self.eat("(") {
+            let logical = self.annotation()?;
+            self.expect(")")?;
+            logical
+        } else {
+            Logical::None
+        };
+        if self.eat("=") {
+            // Field IDs aren't used
+            self.next()?;
+        }
+
+        let fits = matches!(
+            (logical, physical),
+            (Logical::None, _)
+                | (Logical::List, None)
+                | (Logical::Map, None)
+                | (Logical::String, Some(Physical::ByteArray))
+                | (Logical::Date, Some(Physical::Int32))
+                | (Logical::Timestamp(..), Some(Physical::Int64))
+                | (Logical::Unsigned, Some(Physical::Int32))
+                | (Logical::Unsigned, Some(Physical::Int64))
+        );
+        if !fits {
+            return Err(schema_error(format!(
+                "the annotation of field {} doesn't fit its type",
+                name
+            )));
+        }
+
+        let kind = match physical {
+            Some(physical) => {
+                self.expect(";")?;
+                Kind::Leaf(physical)
+            }
+            None => Kind::Group(self.group(depth + 1)?),
+        };
+        Ok(Node {
+            name,
+            repetition,
+            logical,
+            kind,
+        })
+    }
+
+    fn annotation(&mut self) -> error::Result<Logical> {
+        let name = self.next()?;
+        let mut args = Vec::new();
+        if self.eat("(") {
+            loop {
+                args.push(self.next()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let flag = |arg: &str| match arg {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(schema_error(format!("expected a boolean, found {:?}", arg))),
+        };
+
+        Ok(match (name, args.as_slice()) {
+            ("STRING", []) | ("UTF8", []) | ("ENUM", []) | ("JSON", []) => Logical::String,
+            ("LIST", []) => Logical::List,
+            ("MAP", []) | ("MAP_KEY_VALUE", []) => Logical::Map,
+            ("DATE", []) => Logical::Date,
+            ("TIMESTAMP_MILLIS", []) => Logical::Timestamp(TimeUnit::Millis, true),
+            ("TIMESTAMP_MICROS", []) => Logical::Timestamp(TimeUnit::Micros, true),
+            ("TIMESTAMP", [unit, utc]) => {
+                let unit = match *unit {
+                    "MILLIS" => TimeUnit::Millis,
+                    "MICROS" => TimeUnit::Micros,
+                    "NANOS" => TimeUnit::Nanos,
+                    _ => return Err(schema_error(format!("unknown time unit {:?}", unit))),
+                };
+                Logical::Timestamp(unit, flag(utc)?)
+            }
+            ("INT_8", []) | ("INT_16", []) | ("INT_32", []) | ("INT_64", []) => Logical::None,
+            ("UINT_8", []) | ("UINT_16", []) | ("UINT_32", []) | ("UINT_64", []) => {
+                Logical::Unsigned
+            }
+            ("INTEGER", [_, signed]) if flag(signed)? => Logical::None,
+            ("INTEGER", [_, _]) => Logical::Unsigned,
+            ("DECIMAL", _) | ("TIME", _) | ("TIME_MILLIS", []) | ("TIME_MICROS", []) => {
+                return Err(error::Error::Unimplemented {
+                    msg: format!("writing Parquet {} columns", name),
+                })
+            }
+            _ => return Err(schema_error(format!("unknown annotation {:?}", name))),
+        })
+    }
+}
+
+fn schema_error(msg: String) -> error::Error {
+    error::Error::Message(format!("bad Parquet schema: {}", msg))
+}
+
+impl<W> Sink<W>
+where
+    W: io::Write,
+{
+    /// Writes the remaining rows and the footer, which makes the file complete.
+    ///
+    /// Records that were kept in memory for inferring the schema but don't fit it are rejected
+    /// here, one `error::Error::InvalidRecord` per call.
+    fn finish_file(&mut self) -> error::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        if self.schema.is_none() {
+            self.infer_schema()?;
+        }
+        if let Some((msg, record)) = self.rejected.pop_front() {
+            return Err(error::Error::InvalidRecord { msg, record });
+        }
+        self.finished = true;
+
+        if self.rows > 0 {
+            self.write_row_group()?;
+        }
+        self.start()?;
+        let footer = self.footer();
+        self.w.write_all(&footer)?;
+        self.w.write_all(&(footer.len() as u32).to_le_bytes())?;
+        self.w.write_all(MAGIC)?;
+        self.w.flush()?;
+        Ok(())
+    }
+
+    fn set_schema(&mut self, schema: Schema) {
+        self.columns.clear();
+        for node in &schema.fields {
+            add_columns(node, &mut Vec::new(), 0, 0, &mut self.columns);
+        }
+        self.data = self.columns.iter().map(|_| ColumnData::default()).collect();
+        self.schema = Some(schema);
+    }
+
+    fn infer_schema(&mut self) -> error::Result<()> {
+        let fields = match self.pending.iter().map(shape).fold(Shape::Null, merge) {
+            Shape::Struct(fields) => fields
+                .into_iter()
+                .map(|(name, shape)| shape_node(name, shape))
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.set_schema(Schema {
+            name: "schema".to_owned(),
+            fields,
+        });
+        for v in std::mem::take(&mut self.pending) {
+            // These records were already accepted, so they can only be rejected when finishing
+            match self.shred_row(v.clone()) {
+                Ok(()) => self.end_row()?,
+                Err(e) => self.rejected.push_back((e.to_string(), v)),
+            }
+        }
+        Ok(())
+    }
+
+    fn add_row(&mut self, v: value::Value) -> error::Result<()> {
+        self.shred_row(v)?;
+        self.end_row()
+    }
+
+    fn shred_row(&mut self, v: value::Value) -> error::Result<()> {
+        let schema = match self.schema {
+            Some(ref schema) => schema,
+            None => return Ok(()),
+        };
+        // A record that fails halfway must not leave values in some of the columns
+        let marks = self.data.iter().map(ColumnData::mark).collect::<Vec<_>>();
+        let at = Position {
+            rep: 0,
+            def: 0,
+            depth: 0,
+        };
+        if let Err(e) = shred_fields(&schema.fields, v, at, &mut self.data, &mut Vec::new()) {
+            for (data, mark) in self.data.iter_mut().zip(marks) {
+                data.reset(mark);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn end_row(&mut self) -> error::Result<()> {
+        self.rows += 1;
+        if self.rows >= self.options.row_group_rows {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> error::Result<()> {
+        if self.offset == 0 {
+            self.w.write_all(MAGIC)?;
+            self.offset = MAGIC.len();
+        }
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> error::Result<()> {
+        self.start()?;
+        let mut chunks = Vec::with_capacity(self.columns.len());
+        for (column, data) in self.columns.iter().zip(&mut self.data) {
+            let data = std::mem::take(data);
+            let max_def = column.levels.last().map_or(0, |l| l.def);
+            let max_rep = column.levels.last().map_or(0, |l| l.rep);
+
+            let mut page = Vec::new();
+            if max_rep > 0 {
+                write_levels(&data.reps, max_rep, &mut page);
+            }
+            if max_def > 0 {
+                write_levels(&data.defs, max_def, &mut page);
+            }
+            page.extend_from_slice(&data.values);
+            let body = compress(self.options.codec, &page)?;
+
+            let (uncompressed_size, compressed_size) = (page_size(&page)?, page_size(&body)?);
+            let mut header = compact::Writer::default();
+            header.fields(|t| {
+                t.i32(1, 0);
+                t.i32(2, uncompressed_size);
+                t.i32(3, compressed_size);
+                t.structure(5, |t| {
+                    t.i32(1, data.defs.len() as i32);
+                    // The PLAIN and RLE encodings
+                    t.i32(2, 0);
+                    t.i32(3, 3);
+                    t.i32(4, 3);
+                });
+            });
+            self.w.write_all(header.bytes())?;
+            self.w.write_all(&body)?;
+
+            chunks.push(ChunkMetadata {
+                start: self.offset,
+                num_values: data.defs.len(),
+                uncompressed_size: header.bytes().len() + page.len(),
+                compressed_size: header.bytes().len() + body.len(),
+            });
+            self.offset += header.bytes().len() + body.len();
+        }
+        self.row_groups.push((self.rows, chunks));
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn footer(&self) -> Vec<u8> {
+        let fields = self.schema.as_ref().map_or(&[][..], |s| &s.fields);
+        let name = self.schema.as_ref().map_or("schema", |s| &s.name);
+        let num_rows = self.row_groups.iter().map(|g| g.0).sum::<usize>();
+
+        let mut t = compact::Writer::default();
+        t.fields(|t| {
+            t.i32(1, 1);
+            t.list(
+                2,
+                compact::STRUCT,
+                1 + fields.iter().map(count_nodes).sum::<usize>(),
+            );
+            t.fields(|t| {
+                t.binary(4, name.as_bytes());
+                t.i32(5, fields.len() as i32);
+            });
+            for node in fields {
+                write_schema_element(t, node);
+            }
+            t.i64(3, num_rows as i64);
+            t.list(4, compact::STRUCT, self.row_groups.len());
+            for (rows, chunks) in &self.row_groups {
+                t.fields(|t| self.write_row_group_metadata(t, *rows, chunks));
+            }
+            t.binary(
+                6,
+                format!("rq version {}", env!("CARGO_PKG_VERSION")).as_bytes(),
+            );
+        });
+        t.into_bytes()
+    }
+
+    fn write_row_group_metadata(
+        &self,
+        t: &mut compact::Writer,
+        rows: usize,
+        chunks: &[ChunkMetadata],
+    ) {
+        t.list(1, compact::STRUCT, chunks.len());
+        for (column, chunk) in self.columns.iter().zip(chunks) {
+            t.fields(|t| {
+                t.i64(2, chunk.start as i64);
+                t.structure(3, |t| {
+                    t.i32(1, physical_type(column.physical));
+                    t.list(2, compact::I32, 2);
+                    t.zigzag(0);
+                    t.zigzag(3);
+                    t.list(3, compact::BINARY, column.levels.len());
+                    for level in &column.levels {
+                        t.string(level.name.as_bytes());
+                    }
+                    t.i32(4, self.options.codec.id());
+                    t.i64(5, chunk.num_values as i64);
+                    t.i64(6, chunk.uncompressed_size as i64);
+                    t.i64(7, chunk.compressed_size as i64);
+                    t.i64(9, chunk.start as i64);
+                });
+            });
+        }
+        t.i64(
+            2,
+            chunks.iter().map(|c| c.uncompressed_size).sum::<usize>() as i64,
+        );
+        t.i64(3, rows as i64);
+    }
+}
+
+fn page_size(page: &[u8]) -> error::Result<i32> {
+    i32::try_from(page.len())
+        .map_err(|_| error::Error::Message("a Parquet page is too large".to_owned()))
+}
+
+fn count_nodes(node: &Node) -> usize {
+    match node.kind {
+        Kind::Leaf(_) => 1,
+        Kind::Group(ref children) => 1 + children.iter().map(count_nodes).sum::<usize>(),
+    }
+}
+
+fn leaves(node: &Node) -> usize {
+    match node.kind {
+        Kind::Leaf(_) => 1,
+        Kind::Group(ref children) => children.iter().map(leaves).sum(),
+    }
+}
+
+fn write_schema_element(t: &mut compact::Writer, node: &Node) {
+    t.fields(|t| {
+        if let Kind::Leaf(physical) = node.kind {
+            t.i32(1, physical_type(physical));
+            if let Physical::FixedLenByteArray(length) = physical {
+                t.i32(2, length as i32);
+            }
+        }
+        t.i32(
+            3,
+            match node.repetition {
+                Repetition::Required => 0,
+                Repetition::Optional => 1,
+                Repetition::Repeated => 2,
+            },
+        );
+        t.binary(4, node.name.as_bytes());
+        if let Kind::Group(ref children) = node.kind {
+            t.i32(5, children.len() as i32);
+        }
+        if let Some(converted) = converted_type_id(node) {
+            t.i32(6, converted);
+        }
+        if node.logical != Logical::None {
+            t.structure(10, |t| write_logical_type(t, node));
+        }
+    });
+    if let Kind::Group(ref children) = node.kind {
+        for child in children {
+            write_schema_element(t, child);
+        }
+    }
+}
+
+fn physical_type(physical: Physical) -> i32 {
+    match physical {
+        Physical::Boolean => 0,
+        Physical::Int32 => 1,
+        Physical::Int64 => 2,
+        Physical::Int96 => 3,
+        Physical::Float => 4,
+        Physical::Double => 5,
+        Physical::ByteArray => 6,
+        Physical::FixedLenByteArray(_) => 7,
+    }
+}
+
+/// The legacy `ConvertedType` of a field, for readers that don't know `LogicalType`.
+fn converted_type_id(node: &Node) -> Option<i32> {
+    match (node.logical, &node.kind) {
+        (Logical::String, _) => Some(0),
+        (Logical::Map, _) => Some(1),
+        (Logical::List, _) => Some(3),
+        (Logical::Date, _) => Some(6),
+        (Logical::Timestamp(TimeUnit::Millis, true), _) => Some(9),
+        (Logical::Timestamp(TimeUnit::Micros, true), _) => Some(10),
+        (Logical::Unsigned, Kind::Leaf(Physical::Int32)) => Some(13),
+        (Logical::Unsigned, Kind::Leaf(Physical::Int64)) => Some(14),
+        _ => None,
+    }
+}
+
+/// Writes the fields of a `LogicalType` union.
+fn write_logical_type(t: &mut compact::Writer, node: &Node) {
+    let empty = |_: &mut compact::Writer| ();
+    match node.logical {
+        Logical::String => t.structure(1, empty),
+        Logical::Map => t.structure(2, empty),
+        Logical::List => t.structure(3, empty),
+        Logical::Date => t.structure(6, empty),
+        Logical::Timestamp(unit, utc) => t.structure(8, |t| {
+            t.bool(1, utc);
+            t.structure(2, |t| {
+                let id = match unit {
+                    TimeUnit::Millis => 1,
+                    TimeUnit::Micros => 2,
+                    TimeUnit::Nanos => 3,
+                };
+                t.structure(id, empty)
+            })
+        }),
+        Logical::Unsigned => t.structure(10, |t| {
+            let bits = match node.kind {
+                Kind::Leaf(Physical::Int32) => 32,
+                _ => 64,
+            };
+            t.byte(1, bits);
+            t.bool(2, false);
+        }),
+        _ => (),
+    }
+}
+
+/// Encodes levels with the RLE/bit-packing hybrid encoding, using only runs, prefixed by their
+/// length like in version 1 data pages.
+fn write_levels(levels: &[u16], max: u16, out: &mut Vec<u8>) {
+    let width = (16 - max.leading_zeros() as usize + 7) / 8;
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while let Some(&level) = levels.get(i) {
+        let n = levels[i..].iter().take_while(|&&l| l == level).count();
+        compact::write_varint((n as u64) << 1, &mut runs);
+        runs.extend_from_slice(&level.to_le_bytes()[..width]);
+        i += n;
+    }
+    out.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    out.extend_from_slice(&runs);
+}
+
+fn compress(codec: Codec, page: &[u8]) -> error::Result<Vec<u8>> {
+    use std::io::Write;
+
+    match codec {
+        Codec::Uncompressed => Ok(page.to_vec()),
+        Codec::Snappy => snap::Encoder::new()
+            .compress_vec(page)
+            .map_err(|e| error::Error::Message(format!("can't compress a Parquet page: {}", e))),
+        Codec::Gzip => {
+            let mut encoder = libflate::gzip::Encoder::new(Vec::new())?;
+            encoder.write_all(page)?;
+            Ok(encoder.finish().into_result()?)
+        }
+    }
+}
+
+impl Codec {
+    fn id(self) -> i32 {
+        match self {
+            Codec::Uncompressed => 0,
+            Codec::Snappy => 1,
+            Codec::Gzip => 2,
+        }
+    }
+}
+
+/// Writes the fields of a group, which must be a map with a key for each field of the record,
+/// to its columns.
+fn shred_fields(
+    nodes: &[Node],
+    v: value::Value,
+    at: Position,
+    mut columns: &mut [ColumnData],
+    path: &mut Vec<value::path::Segment>,
+) -> error::Result<()> {
+    let mut entries = match v {
+        value::Value::Map(entries) => entries,
+        v => return Err(mismatch(&v, "a map", path)),
+    };
+    for (k, _) in &entries {
+        let known = match *k {
+            value::Value::String(ref name) => nodes.iter().any(|n| n.name == *name),
+            _ => false,
+        };
+        if !known {
+            let mut path = path.clone();
+            path.push(value::path::Segment::Key(match *k {
+                value::Value::String(ref name) => name.clone(),
+                ref k => json(k),
+            }));
+            return Err(format_error(format!(
+                "the field {} isn't in the Parquet schema",
+                value::path::Path::new(path)
+            )));
+        }
+    }
+
+    for node in nodes {
+        let v = entries
+            .iter()
+            .position(|(k, _)| matches!(*k, value::Value::String(ref name) if *name == node.name))
+            .map_or(value::Value::Unit, |i| entries.remove(i).1);
+        let (node_columns, rest) = std::mem::take(&mut columns).split_at_mut(leaves(node));
+        columns = rest;
+        path.push(value::path::Segment::Key(node.name.clone()));
+        shred_node(node, v, at, node_columns, path)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+fn shred_node(
+    node: &Node,
+    v: value::Value,
+    at: Position,
+    columns: &mut [ColumnData],
+    path: &mut Vec<value::path::Segment>,
+) -> error::Result<()> {
+    match (node.repetition, v) {
+        (Repetition::Required, value::Value::Unit) => Err(format_error(format!(
+            "the required Parquet field {} is missing or null",
+            value::path::Path::new(path.clone())
+        ))),
+        (Repetition::Required, v) => shred_value(node, v, at, columns, path),
+        (Repetition::Optional, value::Value::Unit) => {
+            add_nulls(columns, at);
+            Ok(())
+        }
+        (Repetition::Optional, v) => {
+            let at = Position {
+                def: at.def + 1,
+                ..at
+            };
+            shred_value(node, v, at, columns, path)
+        }
+        (Repetition::Repeated, value::Value::Unit) => {
+            add_nulls(columns, at);
+            Ok(())
+        }
+        (Repetition::Repeated, value::Value::Sequence(items)) => {
+            shred_items(node, None, items, at, columns, path)
+        }
+        (Repetition::Repeated, v) => Err(mismatch(&v, "a sequence", path)),
+    }
+}
+
+/// Writes the items of a repeated field, either as the field itself or as its only child if
+/// `element` is set.
+fn shred_items(
+    node: &Node,
+    element: Option<&Node>,
+    items: Vec<value::Value>,
+    at: Position,
+    columns: &mut [ColumnData],
+    path: &mut Vec<value::path::Segment>,
+) -> error::Result<()> {
+    if items.is_empty() {
+        add_nulls(columns, at);
+        return Ok(());
+    }
+    for (i, item) in items.into_iter().enumerate() {
+        let item_at = Position {
+            rep: if i == 0 { at.rep } else { at.depth + 1 },
+            def: at.def + 1,
+            depth: at.depth + 1,
+        };
+        path.push(value::path::Segment::Index(i as i64));
+        match element {
+            Some(element) => shred_node(element, item, item_at, columns, path)?,
+            None => shred_value(node, item, item_at, columns, path)?,
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Writes a value that is present to the columns of its field.
+fn shred_value(
+    node: &Node,
+    v: value::Value,
+    at: Position,
+    columns: &mut [ColumnData],
+    path: &mut Vec<value::path::Segment>,
+) -> error::Result<()> {
+    let children = match node.kind {
+        Kind::Leaf(physical) => {
+            let column = &mut columns[0];
+            column.reps.push(at.rep);
+            column.defs.push(at.def);
+            return column.write(physical, node.logical, v, path);
+        }
+        Kind::Group(ref children) => children,
+    };
+
+    match (node.logical, children.as_slice()) {
+        (Logical::List, [repeated]) if repeated.repetition == Repetition::Repeated => match v {
+            value::Value::Sequence(items) => {
+                shred_items(repeated, list_element(repeated), items, at, columns, path)
+            }
+            v => Err(mismatch(&v, "a sequence", path)),
+        },
+        (Logical::Map, [repeated]) if repeated.repetition == Repetition::Repeated => {
+            let (key, value) = match repeated.kind {
+                Kind::Group(ref fields) if fields.len() == 2 => (&fields[0].name, &fields[1].name),
+                _ => return shred_fields(children, v, at, columns, path),
+            };
+            let items = match v {
+                value::Value::Map(entries) => entries
+                    .into_iter()
+                    .map(|(k, v)| {
+                        value::Value::Map(vec![
+                            (value::Value::String(key.clone()), k),
+                            (value::Value::String(value.clone()), v),
+                        ])
+                    })
+                    .collect(),
+                v => return Err(mismatch(&v, "a map", path)),
+            };
+            shred_items(repeated, None, items, at, columns, path)
+        }
+        _ => shred_fields(children, v, at, columns, path),
+    }
+}
+
+fn add_nulls(columns: &mut [ColumnData], at: Position) {
+    for column in columns {
+        column.reps.push(at.rep);
+        column.defs.push(at.def);
+    }
+}
+
+fn mismatch(v: &value::Value, expected: &str, path: &[value::path::Segment]) -> error::Error {
+    format_error(format!(
+        "the Parquet schema expects {} at {}, not {}",
+        expected,
+        value::path::Path::new(path.to_vec()),
+        json(v)
+    ))
+}
+
+fn json(v: &value::Value) -> String {
+    serde_json::to_string(v).unwrap_or_else(|_| format!("{:?}", v))
+}
+
+impl ColumnData {
+    fn mark(&self) -> (usize, usize, usize) {
+        (self.defs.len(), self.values.len(), self.bits)
+    }
+
+    fn reset(&mut self, (levels, values, bits): (usize, usize, usize)) {
+        self.reps.truncate(levels);
+        self.defs.truncate(levels);
+        self.values.truncate(values);
+        self.bits = bits;
+    }
+
+    /// Appends a value in the `PLAIN` encoding.
+    fn write(
+        &mut self,
+        physical: Physical,
+        logical: Logical,
+        v: value::Value,
+        path: &[value::path::Segment],
+    ) -> error::Result<()> {
+        let expected = match (physical, logical) {
+            (_, Logical::Date) => "a date",
+            (_, Logical::Timestamp(..)) => "a timestamp",
+            (Physical::Boolean, _) => "a boolean",
+            (Physical::Int32, Logical::Unsigned) => "an unsigned 32-bit integer",
+            (Physical::Int32, _) => "a 32-bit integer",
+            (Physical::Int64, Logical::Unsigned) => "an unsigned 64-bit integer",
+            (Physical::Int64, _) | (Physical::Int96, _) => "a 64-bit integer",
+            (Physical::Float, _) | (Physical::Double, _) => "a number",
+            (Physical::ByteArray, _) => "a string or bytes",
+            (Physical::FixedLenByteArray(_), _) => "bytes of a fixed length",
+        };
+        let bad = |v: &value::Value| mismatch(v, expected, path);
+
+        match physical {
+            Physical::Boolean => match v {
+                value::Value::Bool(b) => self.push_bool(b),
+                v => return Err(bad(&v)),
+            },
+            Physical::Int32 => {
+                let n = match logical {
+                    Logical::Date => date(&v).and_then(|n| i32::try_from(n).ok()),
+                    Logical::Unsigned => integer(&v)
+                        .and_then(|n| u32::try_from(n).ok())
+                        .map(|n| n as i32),
+                    _ => integer(&v).and_then(|n| i32::try_from(n).ok()),
+                };
+                let n = n.ok_or_else(|| bad(&v))?;
+                self.values.extend_from_slice(&n.to_le_bytes());
+            }
+            Physical::Int64 => {
+                let n = match logical {
+                    Logical::Timestamp(unit, _) => timestamp(&v, unit),
+                    Logical::Unsigned => integer(&v)
+                        .and_then(|n| u64::try_from(n).ok())
+                        .map(|n| n as i64),
+                    _ => integer(&v).and_then(|n| i64::try_from(n).ok()),
+                };
+                let n = n.ok_or_else(|| bad(&v))?;
+                self.values.extend_from_slice(&n.to_le_bytes());
+            }
+            Physical::Float => {
+                let x = number(&v).ok_or_else(|| bad(&v))?;
+                self.values.extend_from_slice(&(x as f32).to_le_bytes());
+            }
+            Physical::Double => {
+                let x = number(&v).ok_or_else(|| bad(&v))?;
+                self.values.extend_from_slice(&x.to_le_bytes());
+            }
+            Physical::ByteArray => {
+                let bytes = match v {
+                    value::Value::String(s) => s.into_bytes(),
+                    value::Value::Char(c) => c.to_string().into_bytes(),
+                    value::Value::Bytes(b) => b,
+                    v if logical == Logical::String => json(&v).into_bytes(),
+                    v => return Err(bad(&v)),
+                };
+                let length = u32::try_from(bytes.len()).map_err(|_| bad(&value::Value::Unit))?;
+                self.values.extend_from_slice(&length.to_le_bytes());
+                self.values.extend_from_slice(&bytes);
+            }
+            Physical::FixedLenByteArray(length) => match v {
+                value::Value::Bytes(ref b) if b.len() == length => self.values.extend_from_slice(b),
+                v => return Err(bad(&v)),
+            },
+            Physical::Int96 => {
+                return Err(error::Error::Unimplemented {
+                    msg: "writing Parquet int96 columns".to_owned(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn push_bool(&mut self, b: bool) {
+        let byte = self.bits / 8;
+        if byte == self.values.len() {
+            self.values.push(0);
+        }
+        let mask = 1 << (self.bits % 8);
+        if b {
+            self.values[byte] |= mask;
+        } else {
+            self.values[byte] &= !mask;
+        }
+        self.bits += 1;
+    }
+}
+
+fn integer(v: &value::Value) -> Option<i128> {
+    match *v {
+        value::Value::I8(n) => Some(n.into()),
+        value::Value::I16(n) => Some(n.into()),
+        value::Value::I32(n) => Some(n.into()),
+        value::Value::I64(n) => Some(n.into()),
+        value::Value::U8(n) => Some(n.into()),
+        value::Value::U16(n) => Some(n.into()),
+        value::Value::U32(n) => Some(n.into()),
+        value::Value::U64(n) => Some(n.into()),
+        _ => None,
+    }
+}
+
+fn number(v: &value::Value) -> Option<f64> {
+    match *v {
+        value::Value::F32(x) => Some(x.0.into()),
+        value::Value::F64(x) => Some(x.0),
+        ref v => integer(v).map(|n| n as f64),
+    }
+}
+
+/// Days since the epoch of a date string, or of a number of days.
+fn date(v: &value::Value) -> Option<i64> {
+    match *v {
+        value::Value::String(ref s) => {
+            value::timestamp::parse_rfc3339(s).map(|(seconds, _)| seconds.div_euclid(86_400))
+        }
+        ref v => integer(v).and_then(|n| i64::try_from(n).ok()),
+    }
+}
+
+/// Time units since the epoch of a timestamp string, or of a number of time units.
+fn timestamp(v: &value::Value, unit: TimeUnit) -> Option<i64> {
+    let (per_second, nanos_per_unit) = match unit {
+        TimeUnit::Millis => (1_000, 1_000_000),
+        TimeUnit::Micros => (1_000_000, 1_000),
+        TimeUnit::Nanos => (1_000_000_000, 1),
+    };
+    match *v {
+        value::Value::String(ref s) => {
+            let (seconds, nanos) = value::timestamp::parse_rfc3339(s)?;
+            seconds
+                .checked_mul(per_second)?
+                .checked_add(i64::from(nanos) / nanos_per_unit)
+        }
+        ref v => integer(v).and_then(|n| i64::try_from(n).ok()),
+    }
+}
+
+fn shape(v: &value::Value) -> Shape {
+    match *v {
+        value::Value::Unit => Shape::Null,
+        value::Value::Bool(_) => Shape::Bool,
+        value::Value::U64(n) if n > i64::max_value() as u64 => Shape::Unsigned,
+        value::Value::F32(_) | value::Value::F64(_) => Shape::Float,
+        value::Value::Char(_) | value::Value::String(_) => Shape::String,
+        value::Value::Bytes(_) => Shape::Bytes,
+        value::Value::Sequence(ref items) => {
+            Shape::List(Box::new(items.iter().map(shape).fold(Shape::Null, merge)))
+        }
+        value::Value::Map(ref entries) => {
+            let mut fields: Vec<(String, Shape)> = Vec::new();
+            for (k, v) in entries {
+                let name = match *k {
+                    value::Value::String(ref name) => name,
+                    // Maps with other keys are written as JSON
+                    _ => return Shape::String,
+                };
+                match fields.iter_mut().find(|(n, _)| n == name) {
+                    Some(field) => {
+                        field.1 = merge(std::mem::replace(&mut field.1, Shape::Null), shape(v))
+                    }
+                    None => fields.push((name.clone(), shape(v))),
+                }
+            }
+            Shape::Struct(fields)
+        }
+        _ => Shape::Int,
+    }
+}
+
+fn merge(a: Shape, b: Shape) -> Shape {
+    match (a, b) {
+        (Shape::Null, s) | (s, Shape::Null) => s,
+        (Shape::Int, Shape::Float) | (Shape::Float, Shape::Int) => Shape::Float,
+        (Shape::List(a), Shape::List(b)) => Shape::List(Box::new(merge(*a, *b))),
+        (Shape::Struct(mut a), Shape::Struct(b)) => {
+            for (name, s) in b {
+                match a.iter_mut().find(|(n, _)| *n == name) {
+                    Some(field) => field.1 = merge(std::mem::replace(&mut field.1, Shape::Null), s),
+                    None => a.push((name, s)),
+                }
+            }
+            Shape::Struct(a)
+        }
+        (a, b) if a == b => a,
+        // Strings can hold any value as JSON
+        _ => Shape::String,
+    }
+}
+
+fn shape_node(name: String, shape: Shape) -> Node {
+    let (logical, kind) = match shape {
+        Shape::Bool => (Logical::None, Kind::Leaf(Physical::Boolean)),
+        Shape::Int => (Logical::None, Kind::Leaf(Physical::Int64)),
+        Shape::Unsigned => (Logical::Unsigned, Kind::Leaf(Physical::Int64)),
+        Shape::Float => (Logical::None, Kind::Leaf(Physical::Double)),
+        Shape::Bytes => (Logical::None, Kind::Leaf(Physical::ByteArray)),
+        Shape::List(element) => {
+            let repeated = Node {
+                name: "list".to_owned(),
+                repetition: Repetition::Repeated,
+                logical: Logical::None,
+                kind: Kind::Group(vec![shape_node("element".to_owned(), *element)]),
+            };
+            (Logical::List, Kind::Group(vec![repeated]))
+        }
+        Shape::Struct(fields) if !fields.is_empty() => {
+            let children = fields
+                .into_iter()
+                .map(|(name, shape)| shape_node(name, shape))
+                .collect();
+            (Logical::None, Kind::Group(children))
+        }
+        // Groups must have fields, so empty maps are written as JSON too
+        Shape::Null | Shape::String | Shape::Struct(_) => {
+            (Logical::String, Kind::Leaf(Physical::ByteArray))
+        }
+    };
+    Node {
+        name,
+        repetition: Repetition::Optional,
+        logical,
+        kind,
+    }
+}
+
+fn check_record(v: &value::Value) -> error::Result<()> {
+    match *v {
+        value::Value::Map(ref entries) => match entries
+            .iter()
+            .find(|(k, _)| !matches!(*k, value::Value::String(_)))
+        {
+            Some((k, _)) => Err(format_error(format!(
+                "Parquet can't represent map keys that aren't strings, like {}",
+                json(k)
+            ))),
+            None => Ok(()),
+        },
+        _ => Err(format_error(
+            "Parquet can only output records that are maps".to_owned(),
+        )),
+    }
+}
+
+fn slice(data: &[u8], start: usize, n: usize) -> error::Result<&[u8]> {
+    start
+        .checked_add(n)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(truncated)
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(buf)
+}
+
+fn u64_le(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+fn format_error(msg: String) -> error::Error {
+    error::Error::Format { msg }
+}
+
+fn truncated() -> error::Error {
+    format_error("truncated Parquet file".to_owned())
+}
+
+impl value::Source for Source {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Ok(Some(row));
+            }
+            match self.row_groups.get(self.next_row_group) {
+                Some(group) => {
+                    self.rows = self.read_row_group(group)?.into_iter();
+                    self.next_row_group += 1;
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParquetSource")
+            .field("columns", &self.columns.len())
+            .field("row_groups", &self.row_groups.len())
+            .field("next_row_group", &self.next_row_group)
+            .finish()
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, v: value::Value) -> error::Result<()> {
+        check_record(&v)?;
+        if self.schema.is_some() {
+            return self.add_row(v);
+        }
+        self.pending.push(v);
+        if self.pending.len() >= self.options.infer_records {
+            self.infer_schema()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> error::Result<()> {
+        self.finish_file()
+    }
+}
+
+impl<W> fmt::Debug for Sink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParquetSink")
+            .field("options", &self.options)
+            .field("schema", &self.schema)
+            .field("rows", &self.rows)
+            .field("row_groups", &self.row_groups.len())
+            .finish()
+    }
+}
+
+impl<W> Drop for Sink<W>
+where
+    W: io::Write,
+{
+    /// Finishes the file if `value::Sink::finish` wasn't called, which is the only way to get
+    /// errors.
+    fn drop(&mut self) {
+        loop {
+            match self.finish_file() {
+                Ok(()) => break,
+                Err(error::Error::InvalidRecord { msg, .. }) => {
+                    warn!("Dropped Parquet record: {}", msg)
+                }
+                Err(error) => {
+                    error!("Could not finish Parquet file: {}", error);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Default for SinkOptions {
+    fn default() -> Self {
+        SinkOptions {
+            schema: None,
+            infer_records: 1000,
+            row_group_rows: 100_000,
+            codec: Codec::Snappy,
+        }
+    }
+}
+
+impl str::FromStr for Codec {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "null" | "uncompressed" => Ok(Codec::Uncompressed),
+            "snappy" => Ok(Codec::Snappy),
+            "gzip" => Ok(Codec::Gzip),
+            _ => Err(error::Error::Message(format!(
+                "illegal Parquet codec: {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn encode(t: &Thrift, out: &mut Vec<u8>) {
+        fn kind(t: &Thrift) -> u8 {
+            match *t {
+                Thrift::Bool(true) => compact::BOOL_TRUE,
+                Thrift::Bool(false) => compact::BOOL_FALSE,
+                Thrift::Int(_) => compact::I64,
+                Thrift::Double(_) => compact::DOUBLE,
+                Thrift::Binary(_) => compact::BINARY,
+                Thrift::List(_) => compact::LIST,
+                Thrift::Map(_) => compact::MAP,
+                Thrift::Struct(_) => compact::STRUCT,
+            }
+        }
+
+        match *t {
+            Thrift::Bool(b) => out.push(b as u8),
+            Thrift::Int(n) => compact::write_varint(((n << 1) ^ (n >> 63)) as u64, out),
+            Thrift::Double(x) => out.extend_from_slice(&x.to_le_bytes()),
+            Thrift::Binary(ref b) => {
+                compact::write_varint(b.len() as u64, out);
+                out.extend_from_slice(b);
+            }
+            Thrift::List(ref items) => {
+                let item_kind = items.first().map_or(compact::STRUCT, kind);
+                out.push((items.len() as u8) << 4 | item_kind);
+                for item in items {
+                    encode(item, out);
+                }
+            }
+            Thrift::Map(ref entries) => {
+                compact::write_varint(entries.len() as u64, out);
+                if let Some((k, v)) = entries.first() {
+                    out.push(kind(k) << 4 | kind(v));
+                }
+                for (k, v) in entries {
+                    encode(k, out);
+                    encode(v, out);
+                }
+            }
+            Thrift::Struct(ref fields) => {
+                let mut last = 0;
+                for &(id, ref v) in fields {
+                    out.push(((id - last) as u8) << 4 | kind(v));
+                    last = id;
+                    if let Thrift::Bool(_) = *v {
+                    } else {
+                        encode(v, out);
+                    }
+                }
+                out.push(0);
+            }
+        }
+    }
+
+    fn st(fields: Vec<(i16, Thrift)>) -> Thrift {
+        Thrift::Struct(fields)
+    }
+
+    fn bin(s: &str) -> Thrift {
+        Thrift::Binary(s.as_bytes().to_vec())
+    }
+
+    fn int(n: i64) -> Thrift {
+        Thrift::Int(n)
+    }
+
+    fn plain_strings(strings: &[&str]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for s in strings {
+            out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            out.extend_from_slice(s.as_bytes());
+        }
+        out
+    }
+
+    /// Encodes levels as RLE runs of one value each.
+    fn rle(levels: &[u8]) -> Vec<u8> {
+        levels.iter().flat_map(|&level| vec![2, level]).collect()
+    }
+
+    fn with_length(data: Vec<u8>) -> Vec<u8> {
+        [(data.len() as u32).to_le_bytes().to_vec(), data].concat()
+    }
+
+    fn page(file: &mut Vec<u8>, header: Vec<(i16, Thrift)>, body: &[u8], uncompressed: usize) {
+        let mut fields = vec![(2, int(uncompressed as i64)), (3, int(body.len() as i64))];
+        fields.extend(header);
+        fields.sort_by_key(|f| f.0);
+        encode(&st(fields), file);
+        file.extend_from_slice(body);
+    }
+
+    fn column_chunk(
+        physical: i64,
+        name: &str,
+        codec: i64,
+        num_values: i64,
+        offsets: (i64, i64),
+    ) -> Thrift {
+        let mut metadata = vec![
+            (1, int(physical)),
+            (2, Thrift::List(vec![int(0)])),
+            (3, Thrift::List(vec![bin(name)])),
+            (4, int(codec)),
+            (5, int(num_values)),
+            (6, int(0)),
+            (7, int(0)),
+            (9, int(offsets.0)),
+        ];
+        if offsets.1 > 0 {
+            metadata.push((11, int(offsets.1)));
+        }
+        st(vec![(2, int(0)), (3, st(metadata))])
+    }
+
+    /// A file with the rows `{"id": 1, "name": "y", "tags": ["a", "b"]}`,
+    /// `{"id": 2, "name": null, "tags": null}` and `{"id": 3, "name": "x", "tags": []}`.
+    fn example() -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+
+        // id: a required int64 column
+        let id_offset = file.len() as i64;
+        let ids = [1i64, 2, 3]
+            .iter()
+            .flat_map(|n| n.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        page(
+            &mut file,
+            vec![
+                (1, int(0)),
+                (
+                    5,
+                    st(vec![(1, int(3)), (2, int(0)), (3, int(3)), (4, int(3))]),
+                ),
+            ],
+            &ids,
+            ids.len(),
+        );
+
+        // name: an optional string column with a dictionary, compressed with Snappy
+        let compress = |data: &[u8]| snap::Encoder::new().compress_vec(data).unwrap();
+        let dictionary_offset = file.len() as i64;
+        let dictionary = plain_strings(&["x", "y"]);
+        page(
+            &mut file,
+            vec![(1, int(2)), (7, st(vec![(1, int(2)), (2, int(0))]))],
+            &compress(&dictionary),
+            dictionary.len(),
+        );
+        let name_offset = file.len() as i64;
+        // The indices [1, 0] are bit-packed with a bit width of 1
+        let names = [with_length(rle(&[1, 0, 1])), vec![1, 3, 0b01]].concat();
+        page(
+            &mut file,
+            vec![
+                (1, int(0)),
+                (
+                    5,
+                    st(vec![(1, int(3)), (2, int(8)), (3, int(3)), (4, int(3))]),
+                ),
+            ],
+            &compress(&names),
+            names.len(),
+        );
+
+        // tags: an optional list of optional strings, in a version 2 data page
+        let tags_offset = file.len() as i64;
+        let reps = rle(&[0, 1, 0, 0]);
+        let defs = rle(&[3, 3, 0, 1]);
+        let tags = [reps.clone(), defs.clone(), plain_strings(&["a", "b"])].concat();
+        page(
+            &mut file,
+            vec![
+                (1, int(3)),
+                (
+                    8,
+                    st(vec![
+                        (1, int(4)),
+                        (2, int(2)),
+                        (3, int(3)),
+                        (4, int(0)),
+                        (5, int(defs.len() as i64)),
+                        (6, int(reps.len() as i64)),
+                        (7, Thrift::Bool(false)),
+                    ]),
+                ),
+            ],
+            &tags,
+            tags.len(),
+        );
+
+        let schema = vec![
+            st(vec![(4, bin("schema")), (5, int(3))]),
+            st(vec![(1, int(2)), (3, int(0)), (4, bin("id"))]),
+            st(vec![
+                (1, int(6)),
+                (3, int(1)),
+                (4, bin("name")),
+                (6, int(0)),
+            ]),
+            st(vec![
+                (3, int(1)),
+                (4, bin("tags")),
+                (5, int(1)),
+                (6, int(3)),
+            ]),
+            st(vec![(3, int(2)), (4, bin("list")), (5, int(1))]),
+            st(vec![
+                (1, int(6)),
+                (3, int(1)),
+                (4, bin("element")),
+                (6, int(0)),
+            ]),
+        ];
+        let row_group = st(vec![
+            (
+                1,
+                Thrift::List(vec![
+                    column_chunk(2, "id", 0, 3, (id_offset, 0)),
+                    column_chunk(6, "name", 1, 3, (name_offset, dictionary_offset)),
+                    column_chunk(6, "element", 0, 4, (tags_offset, 0)),
+                ]),
+            ),
+            (2, int(0)),
+            (3, int(3)),
+        ]);
+        let metadata = st(vec![
+            (1, int(1)),
+            (2, Thrift::List(schema)),
+            (3, int(3)),
+            (4, Thrift::List(vec![row_group])),
+        ]);
+
+        let mut footer = Vec::new();
+        encode(&metadata, &mut footer);
+        file.extend_from_slice(&footer);
+        file.extend_from_slice(&(footer.len() as u32).to_le_bytes());
+        file.extend_from_slice(MAGIC);
+        file
+    }
+
+    #[test]
+    fn test_source() {
+        let s = |v: &str| Value::String(v.to_owned());
+        let row = |id: i64, name: Value, tags: Value| {
+            Value::Map(vec![
+                (s("id"), Value::I64(id)),
+                (s("name"), name),
+                (s("tags"), tags),
+            ])
+        };
+
+        let file = example();
+        let mut source = source(file.as_slice()).unwrap();
+        assert_eq!(
+            source.read().unwrap(),
+            Some(row(1, s("y"), Value::Sequence(vec![s("a"), s("b")])))
+        );
+        assert_eq!(
+            source.read().unwrap(),
+            Some(row(2, Value::Unit, Value::Unit))
+        );
+        assert_eq!(
+            source.read().unwrap(),
+            Some(row(3, s("x"), Value::Sequence(vec![])))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_source_corrupt() {
+        assert!(source(&b"PAR1"[..]).is_err());
+
+        // Truncating the data must give errors, not panics
+        let file = example();
+        let footer = file.len() - 8;
+        for n in 4..footer {
+            let mut corrupt = file.clone();
+            corrupt.drain(n..footer);
+            if let Ok(mut source) = source(corrupt.as_slice()) {
+                while let Ok(Some(_)) = source.read() {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_logical_types() {
+        let s = |v: &str| Value::String(v.to_owned());
+        assert_eq!(int32(-1, Logical::Unsigned), Value::U32(u32::max_value()));
+        assert_eq!(int32(12345, Logical::Decimal(2)), s("123.45"));
+        assert_eq!(int32(1, Logical::Date), s("1970-01-02"));
+        assert_eq!(
+            int64(1_500, Logical::Timestamp(TimeUnit::Millis, true)),
+            s("1970-01-01T00:00:01.500Z")
+        );
+        assert_eq!(
+            int64(-1, Logical::Timestamp(TimeUnit::Nanos, false)),
+            s("1969-12-31T23:59:59.999999999")
+        );
+        assert_eq!(int96(1_000_000_000, 2_440_589), s("1970-01-02T00:00:01Z"));
+        assert_eq!(byte_array(&[0xff, 0x38], Logical::Decimal(1)), s("-20.0"));
+        assert_eq!(
+            byte_array(&[0xff], Logical::String),
+            Value::Bytes(vec![0xff])
+        );
+    }
+
+    #[test]
+    fn test_thrift_map() {
+        let mut data = Vec::new();
+        let map = Thrift::Map(vec![(bin("a"), int(1)), (bin("b"), int(-2))]);
+        encode(
+            &st(vec![(1, map), (2, Thrift::Map(vec![])), (3, int(3))]),
+            &mut data,
+        );
+
+        let decoded = ThriftReader::new(&data).structure(0).unwrap();
+        match decoded.field(1) {
+            Some(Thrift::Map(entries)) => {
+                let entries = entries
+                    .iter()
+                    .map(|(k, v)| match (k, v) {
+                        (Thrift::Binary(k), &Thrift::Int(v)) => (k.as_slice(), v),
+                        other => panic!("unexpected entry {:?}", other),
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(vec![(&b"a"[..], 1), (&b"b"[..], -2)], entries);
+            }
+            other => panic!("unexpected field {:?}", other),
+        }
+        match decoded.field(2) {
+            Some(Thrift::Map(entries)) => assert!(entries.is_empty()),
+            other => panic!("unexpected field {:?}", other),
+        }
+        assert_eq!(Some(3), decoded.int(3));
+    }
+
+    #[test]
+    fn test_rle_hybrid() {
+        // A run of three 5s, then a bit-packed group of 0..8 with a bit width of 3
+        let data = [6, 5, 3, 0b1000_1000, 0b1100_0110, 0b1111_1010];
+        assert_eq!(
+            rle_hybrid(&data, 3, 11).unwrap(),
+            vec![5, 5, 5, 0, 1, 2, 3, 4, 5, 6, 7]
+        );
+        assert!(rle_hybrid(&data, 3, 12).is_err());
+    }
+
+    fn write_all(records: Vec<Value>, options: SinkOptions) -> Vec<u8> {
+        let mut file = Vec::new();
+        {
+            let mut sink = sink(&mut file, options);
+            for record in records {
+                value::Sink::write(&mut sink, record).unwrap();
+            }
+            value::Sink::finish(&mut sink).unwrap();
+        }
+        file
+    }
+
+    fn read_all(file: &[u8]) -> Vec<Value> {
+        let mut source = source(file).unwrap();
+        let mut rows = Vec::new();
+        while let Some(row) = source.read().unwrap() {
+            rows.push(row);
+        }
+        rows
+    }
+
+    #[test]
+    fn test_sink_inferred() {
+        let s = |v: &str| Value::String(v.to_owned());
+        let records = vec![
+            Value::Map(vec![
+                (s("id"), Value::I64(1)),
+                (s("score"), Value::I64(3)),
+                (s("name"), s("a")),
+                (s("tags"), Value::Sequence(vec![s("x"), s("y")])),
+                (s("nested"), Value::Map(vec![(s("ok"), Value::Bool(true))])),
+                (s("raw"), Value::Bytes(vec![1, 2])),
+            ]),
+            Value::Map(vec![
+                (s("id"), Value::I64(2)),
+                (s("score"), Value::from_f64(2.5)),
+                (s("name"), Value::Unit),
+                (s("tags"), Value::Sequence(vec![])),
+                (s("big"), Value::U64(u64::max_value())),
+            ]),
+            Value::Map(vec![
+                (s("id"), Value::I64(3)),
+                (s("name"), Value::I64(7)),
+                (s("extra"), Value::Unit),
+            ]),
+        ];
+        let row = |values: Vec<Value>| {
+            let names = &[
+                "id", "score", "name", "tags", "nested", "raw", "big", "extra",
+            ];
+            Value::Map(names.iter().map(|n| s(n)).zip(values).collect())
+        };
+        let expected = vec![
+            row(vec![
+                Value::I64(1),
+                Value::from_f64(3.0),
+                s("a"),
+                Value::Sequence(vec![s("x"), s("y")]),
+                Value::Map(vec![(s("ok"), Value::Bool(true))]),
+                Value::Bytes(vec![1, 2]),
+                Value::Unit,
+                Value::Unit,
+            ]),
+            row(vec![
+                Value::I64(2),
+                Value::from_f64(2.5),
+                Value::Unit,
+                Value::Sequence(vec![]),
+                Value::Unit,
+                Value::Unit,
+                Value::U64(u64::max_value()),
+                Value::Unit,
+            ]),
+            row(vec![
+                Value::I64(3),
+                Value::Unit,
+                s("7"),
+                Value::Unit,
+                Value::Unit,
+                Value::Unit,
+                Value::Unit,
+                Value::Unit,
+            ]),
+        ];
+
+        for &codec in &[Codec::Uncompressed, Codec::Snappy, Codec::Gzip] {
+            let options = SinkOptions {
+                codec,
+                row_group_rows: 2,
+                ..SinkOptions::default()
+            };
+            let file = write_all(records.clone(), options);
+            assert_eq!(read_all(&file), expected);
+        }
+        assert_eq!(read_all(&write_all(vec![], SinkOptions::default())), vec![]);
+    }
+
+    #[test]
+    fn test_sink_schema() {
+        let s = |v: &str| Value::String(v.to_owned());
+        let schema = Schema::parse(
+            "message test {
+              required int32 id = 1;
+              optional binary name (STRING);
+              optional int32 day (DATE);
+              optional int64 at (TIMESTAMP(MICROS, true));
+              optional int64 count (UINT_64);
+              optional group attributes (MAP) {
+                repeated group key_value {
+                  required binary key (UTF8);
+                  optional double value;
+                }
+              }
+              repeated boolean flags;
+            }",
+        )
+        .unwrap();
+        let options = SinkOptions {
+            schema: Some(schema),
+            ..SinkOptions::default()
+        };
+        let records = vec![
+            Value::Map(vec![
+                (s("id"), Value::I64(1)),
+                (s("name"), s("n")),
+                (s("day"), s("2020-01-02")),
+                (s("at"), s("2020-01-02T03:04:05.5+01:00")),
+                (s("count"), Value::U64(5)),
+                (s("attributes"), Value::Map(vec![(s("a"), Value::I64(1))])),
+                (
+                    s("flags"),
+                    Value::Sequence(vec![Value::Bool(true), Value::Bool(false)]),
+                ),
+            ]),
+            Value::Map(vec![(s("id"), Value::I64(2))]),
+        ];
+        assert_eq!(
+            read_all(&write_all(records, options)),
+            vec![
+                Value::Map(vec![
+                    (s("id"), Value::I32(1)),
+                    (s("name"), s("n")),
+                    (s("day"), s("2020-01-02")),
+                    (s("at"), s("2020-01-02T02:04:05.500Z")),
+                    (s("count"), Value::U64(5)),
+                    (
+                        s("attributes"),
+                        Value::Map(vec![(s("a"), Value::from_f64(1.0))])
+                    ),
+                    (
+                        s("flags"),
+                        Value::Sequence(vec![Value::Bool(true), Value::Bool(false)])
+                    ),
+                ]),
+                Value::Map(vec![
+                    (s("id"), Value::I32(2)),
+                    (s("name"), Value::Unit),
+                    (s("day"), Value::Unit),
+                    (s("at"), Value::Unit),
+                    (s("count"), Value::Unit),
+                    (s("attributes"), Value::Unit),
+                    (s("flags"), Value::Sequence(vec![])),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sink_errors() {
+        let s = |v: &str| Value::String(v.to_owned());
+        let options = SinkOptions {
+            schema: Some(
+                Schema::parse("message m { required int32 id; optional int64 n; }").unwrap(),
+            ),
+            ..SinkOptions::default()
+        };
+        let mut file = Vec::new();
+        {
+            let mut sink = sink(&mut file, options);
+            let mut message = |v| match value::Sink::write(&mut sink, v) {
+                Err(error::Error::Format { msg }) => msg,
+                r => panic!("expected an error, got {:?}", r),
+            };
+            assert_eq!(
+                message(Value::Map(vec![(s("n"), Value::I64(1))])),
+                "the required Parquet field .id is missing or null"
+            );
+            assert_eq!(
+                message(Value::Map(vec![(s("id"), Value::I64(1 << 40))])),
+                "the Parquet schema expects a 32-bit integer at .id, not 1099511627776"
+            );
+            assert_eq!(
+                message(Value::Map(vec![(s("id"), Value::I64(1)), (s("n"), s("x"))])),
+                "the Parquet schema expects a 64-bit integer at .n, not \"x\""
+            );
+            assert_eq!(
+                message(Value::Map(vec![(s("id"), Value::I64(1)), (s("m"), s("x"))])),
+                "the field .m isn't in the Parquet schema"
+            );
+            assert_eq!(
+                message(Value::I64(1)),
+                "Parquet can only output records that are maps"
+            );
+            value::Sink::write(&mut sink, Value::Map(vec![(s("id"), Value::I64(2))])).unwrap();
+        }
+        assert_eq!(
+            read_all(&file),
+            vec![Value::Map(vec![
+                (s("id"), Value::I32(2)),
+                (s("n"), Value::Unit)
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_sink_finish_errors() {
+        struct Broken;
+
+        impl io::Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let record = Value::Map(vec![(Value::String("a".to_owned()), Value::I64(1))]);
+        let mut sink = sink(Broken, SinkOptions::default());
+        value::Sink::write(&mut sink, record.clone()).unwrap();
+        match value::Sink::finish(&mut sink) {
+            Err(error::Error::Io(ref e)) if e.kind() == io::ErrorKind::BrokenPipe => (),
+            r => panic!("expected a broken pipe, got {:?}", r),
+        }
+
+        // Dropping an unfinished sink doesn't panic
+        let mut sink = super::sink(Broken, SinkOptions::default());
+        value::Sink::write(&mut sink, record).unwrap();
+        drop(sink);
+    }
+
+    #[test]
+    fn test_sink_finish_rejects() {
+        let record = Value::Map(vec![(Value::String("a".to_owned()), Value::I64(1))]);
+        let mut file = Vec::new();
+        {
+            let mut sink = sink(&mut file, SinkOptions::default());
+            value::Sink::write(&mut sink, record.clone()).unwrap();
+            // Records are checked when they are written, so this makes for a schema that fits none
+            sink.pending.push(Value::I64(2));
+
+            let mut rejected = Vec::new();
+            loop {
+                match value::Sink::finish(&mut sink) {
+                    Ok(()) => break,
+                    Err(error::Error::InvalidRecord { record, .. }) => rejected.push(record),
+                    Err(e) => panic!("expected a rejected record, got {}", e),
+                }
+            }
+            assert_eq!(rejected, vec![record, Value::I64(2)]);
+        }
+        assert_eq!(read_all(&file), vec![]);
+    }
+
+    #[test]
+    fn test_schema_parse() {
+        assert_eq!(
+            Schema::parse("message m { optional group l (LIST) { repeated int64 element; } }")
+                .unwrap(),
+            Schema {
+                name: "m".to_owned(),
+                fields: vec![Node {
+                    name: "l".to_owned(),
+                    repetition: Repetition::Optional,
+                    logical: Logical::List,
+                    kind: Kind::Group(vec![Node {
+                        name: "element".to_owned(),
+                        repetition: Repetition::Repeated,
+                        logical: Logical::None,
+                        kind: Kind::Leaf(Physical::Int64),
+                    }]),
+                }],
+            }
+        );
+        for schema in &[
+            "message m { required int32 x }",
+            "message m { optional int32 x (STRING); }",
+            "message m { required int33 x; }",
+            "message m { required int64 t (TIMESTAMP(SECONDS, true)); }",
+            "message m { } }",
+        ] {
+            assert!(
+                matches!(Schema::parse(schema), Err(error::Error::Message(_))),
+                "{}",
+                schema
+            );
+        }
+        assert!(matches!(
+            Schema::parse("message m { required int96 t; }"),
+            Err(error::Error::Unimplemented { .. })
+        ));
+    }
+}
@@ -0,0 +1,381 @@
+use crate::error;
+use crate::value;
+use serde_json;
+use std::fmt;
+use std::str;
+use unicode_normalization::UnicodeNormalization;
+
+/// A path to a value nested inside of a record, like `.foo.bar[0]`.
+///
+/// Each segment is either a `.key` (or `."quoted key"`, or `["quoted key"]`) selecting an entry
+/// of a map, or an `[index]` selecting an element of a sequence, where negative indices count from
+/// the end.  The path `.` selects the whole record.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Path(Vec<Segment>, KeyMatch);
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Segment {
+    Key(String),
+    Index(i64),
+}
+
+/// How the keys of a path are compared with the keys of maps.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum KeyMatch {
+    /// Keys must be equal.
+    #[default]
+    Exact,
+    /// Keys are compared case-insensitively after Unicode NFC normalization, so that `id`
+    /// matches `ID` and `Id`, and composed and decomposed accents match each other.
+    Folded,
+}
+
+/// A source that replaces every record with the value at a path, or with `Unit` if there is no
+/// such value.
+pub type Source<S> = value::transform::Source<S, Path>;
+
+#[inline]
+pub fn source<S>(inner: S, path: Path) -> Source<S>
+where
+    S: value::Source,
+{
+    value::transform::source(inner, path)
+}
+
+impl Path {
+    pub fn new(segments: Vec<Segment>) -> Self {
+        Self(segments, KeyMatch::default())
+    }
+
+    /// Sets how keys are compared when selecting values.
+    #[inline]
+    pub fn key_match(mut self, key_match: KeyMatch) -> Self {
+        self.1 = key_match;
+        self
+    }
+
+    pub fn parse(s: &str) -> error::Result<Self> {
+        let error = |msg: &str| error::Error::Message(format!("invalid path {:?}: {}", s, msg));
+
+        if s == "." {
+            return Ok(Self::default());
+        }
+
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                if after_dot.starts_with('"') {
+                    let (key, after) = parse_string(after_dot).ok_or_else(|| error("bad key"))?;
+                    segments.push(Segment::Key(key));
+                    rest = after;
+                } else if after_dot.starts_with('[') {
+                    // jq-style `.[0]`
+                    rest = after_dot;
+                } else {
+                    let end = after_dot
+                        .find(|c| c == '.' || c == '[')
+                        .unwrap_or(after_dot.len());
+                    if end == 0 {
+                        return Err(error("empty key"));
+                    }
+                    segments.push(Segment::Key(after_dot[..end].to_owned()));
+                    rest = &after_dot[end..];
+                }
+            } else if let Some(inside) = rest.strip_prefix('[') {
+                let (segment, after) = if inside.starts_with('"') {
+                    let (key, after) = parse_string(inside).ok_or_else(|| error("bad key"))?;
+                    (Segment::Key(key), after)
+                } else {
+                    let end = inside.find(']').ok_or_else(|| error("missing ']'"))?;
+                    let index = inside[..end]
+                        .trim()
+                        .parse()
+                        .map_err(|_| error("bad index"))?;
+                    (Segment::Index(index), &inside[end..])
+                };
+                segments.push(segment);
+                rest = after
+                    .strip_prefix(']')
+                    .ok_or_else(|| error("missing ']'"))?;
+            } else {
+                return Err(error("expected '.' or '['"));
+            }
+        }
+
+        if segments.is_empty() {
+            Err(error("empty path"))
+        } else {
+            Ok(Self::new(segments))
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.0
+    }
+
+    /// Returns the value at this path, if there is one.
+    pub fn select<'a>(&self, value: &'a value::Value) -> Option<&'a value::Value> {
+        self.0
+            .iter()
+            .try_fold(value, |value, segment| segment.select_with(value, self.1))
+    }
+
+    /// Returns the value at this path mutably, if there is one.
+    pub fn select_mut<'a>(&self, value: &'a mut value::Value) -> Option<&'a mut value::Value> {
+        self.0.iter().try_fold(value, |value, segment| {
+            segment.select_mut_with(value, self.1)
+        })
+    }
+}
+
+impl Segment {
+    pub fn select<'a>(&self, value: &'a value::Value) -> Option<&'a value::Value> {
+        self.select_with(value, KeyMatch::Exact)
+    }
+
+    pub fn select_mut<'a>(&self, value: &'a mut value::Value) -> Option<&'a mut value::Value> {
+        self.select_mut_with(value, KeyMatch::Exact)
+    }
+
+    fn select_with<'a>(
+        &self,
+        value: &'a value::Value,
+        key_match: KeyMatch,
+    ) -> Option<&'a value::Value> {
+        match (self, value) {
+            (Segment::Key(k), value::Value::Map(entries)) => {
+                find_key(entries.iter().map(|(k, v)| (k, v)), k, key_match)
+            }
+            (Segment::Index(i), value::Value::Sequence(seq)) => {
+                resolve_index(*i, seq.len()).map(|i| &seq[i])
+            }
+            _ => None,
+        }
+    }
+
+    /// Like `select_mut`, but compares keys with the keys of maps as given.
+    pub fn select_mut_with<'a>(
+        &self,
+        value: &'a mut value::Value,
+        key_match: KeyMatch,
+    ) -> Option<&'a mut value::Value> {
+        match (self, value) {
+            (Segment::Key(k), value::Value::Map(entries)) => {
+                find_key(entries.iter_mut().map(|(k, v)| (&*k, v)), k, key_match)
+            }
+            (Segment::Index(i), value::Value::Sequence(seq)) => {
+                let len = seq.len();
+                resolve_index(*i, len).map(move |i| &mut seq[i])
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Finds the value of the entry with the given key.  With folded matching, an exact match is
+/// preferred over other entries that only match after folding.
+fn find_key<'a, I, V>(entries: I, name: &str, key_match: KeyMatch) -> Option<V>
+where
+    I: Iterator<Item = (&'a value::Value, V)>,
+{
+    match key_match {
+        KeyMatch::Exact => entries
+            .filter(|(k, _)| is_key(k, name))
+            .map(|(_, v)| v)
+            .next(),
+        KeyMatch::Folded => {
+            let mut folded = None;
+            for (k, v) in entries {
+                if is_key(k, name) {
+                    return Some(v);
+                } else if folded.is_none() && is_folded_key(k, name) {
+                    folded = Some(v);
+                }
+            }
+            folded
+        }
+    }
+}
+
+fn is_key(key: &value::Value, name: &str) -> bool {
+    match *key {
+        value::Value::String(ref s) => s == name,
+        value::Value::Char(c) => name.chars().eq(Some(c)),
+        _ => false,
+    }
+}
+
+fn is_folded_key(key: &value::Value, name: &str) -> bool {
+    let fold = |s: &str| {
+        s.nfc()
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+            .nfc()
+            .collect::<String>()
+    };
+    match *key {
+        value::Value::String(ref s) => fold(s) == fold(name),
+        value::Value::Char(c) => fold(c.encode_utf8(&mut [0; 4])) == fold(name),
+        _ => false,
+    }
+}
+
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if 0 <= index && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+/// Parses a JSON string literal at the start of `s`, returning it and the remaining input.
+fn parse_string(s: &str) -> Option<(String, &str)> {
+    let mut escape = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            '\\' if !escape => escape = true,
+            '"' if !escape => {
+                let key = serde_json::from_str(&s[..=i]).ok()?;
+                return Some((key, &s[i + 1..]));
+            }
+            _ => escape = false,
+        }
+    }
+    None
+}
+
+impl str::FromStr for Path {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, ".");
+        }
+
+        for segment in &self.0 {
+            match *segment {
+                Segment::Key(ref k)
+                    if !k.is_empty() && !k.contains(|c| c == '.' || c == '[' || c == '"') =>
+                {
+                    write!(f, ".{}", k)?
+                }
+                Segment::Key(ref k) => write!(f, "[{}]", serde_json::Value::from(k.as_str()))?,
+                Segment::Index(i) => write!(f, "[{}]", i)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl value::Transform for Path {
+    #[inline]
+    fn transform(&mut self, mut v: value::Value) -> error::Result<Option<value::Value>> {
+        Ok(Some(
+            self.select_mut(&mut v).map_or(value::Value::Unit, |v| {
+                std::mem::replace(v, value::Value::Unit)
+            }),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Path::parse(".").unwrap(), Path::default());
+        assert_eq!(
+            Path::parse(".foo.bar[0][-1]").unwrap(),
+            Path::new(vec![
+                Segment::Key("foo".to_owned()),
+                Segment::Key("bar".to_owned()),
+                Segment::Index(0),
+                Segment::Index(-1),
+            ])
+        );
+        assert_eq!(
+            Path::parse(r#"."a.b"["c]"].[2]"#).unwrap(),
+            Path::new(vec![
+                Segment::Key("a.b".to_owned()),
+                Segment::Key("c]".to_owned()),
+                Segment::Index(2),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for path in &[
+            "", "foo", ".foo.", "..foo", ".foo[", ".foo[x]", ".foo[0", r#"."foo"#,
+        ] {
+            assert!(Path::parse(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        for path in &[".", ".foo.bar[0][-1]", r#"["a.b"][""]"#] {
+            assert_eq!(Path::parse(path).unwrap().to_string(), *path);
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let value = Value::Map(vec![(
+            s("foo"),
+            Value::Sequence(vec![
+                Value::Map(vec![(Value::Char('x'), s("found"))]),
+                Value::Unit,
+            ]),
+        )]);
+
+        let select = |p: &str| Path::parse(p).unwrap().select(&value).cloned();
+        assert_eq!(select(".foo[0].x"), Some(s("found")));
+        assert_eq!(select(".foo[-2].x"), Some(s("found")));
+        assert_eq!(select(".foo[-1]"), Some(Value::Unit));
+        assert_eq!(select(".foo[2]"), None);
+        assert_eq!(select(".foo[-3]"), None);
+        assert_eq!(select(".foo.x"), None);
+        assert_eq!(select(".bar"), None);
+        assert_eq!(select("."), Some(value.clone()));
+    }
+
+    #[test]
+    fn test_select_folded() {
+        let value = Value::Map(vec![
+            (s("ID"), s("upper")),
+            (s("id"), s("lower")),
+            (s("Cafe\u{301}"), s("decomposed")),
+        ]);
+
+        let select = |p: &str, key_match| {
+            Path::parse(p)
+                .unwrap()
+                .key_match(key_match)
+                .select(&value)
+                .cloned()
+        };
+        assert_eq!(select(".Id", KeyMatch::Exact), None);
+        assert_eq!(select(".Id", KeyMatch::Folded), Some(s("upper")));
+        assert_eq!(select(".id", KeyMatch::Folded), Some(s("lower")));
+        assert_eq!(
+            select(".caf\u{e9}", KeyMatch::Folded),
+            Some(s("decomposed"))
+        );
+        assert_eq!(select(".cafe", KeyMatch::Folded), None);
+    }
+}
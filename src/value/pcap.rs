@@ -0,0 +1,711 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+use std::io;
+use std::net;
+
+/// A source that reads packet captures in the pcap or pcapng format, yielding one record per
+/// packet.
+///
+/// Each record has the capture `timestamp` (in seconds since the epoch), the `interface` it was
+/// captured on, its `length` and `captured_length`, and the decoded `link`, `network` and
+/// `transport` headers, which are `Unit` if the packet doesn't have them or they aren't supported.
+/// The `payload` is the part of the packet after the innermost decoded header.
+pub struct Source<R>
+where
+    R: io::Read,
+{
+    input: R,
+    format: Format,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        link_type: u32,
+    },
+    Pcapng {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Interface {
+    link_type: u32,
+    /// The number of timestamp units per second.
+    resolution: u64,
+}
+
+struct Packet<'a> {
+    timestamp: Option<f64>,
+    interface: u32,
+    length: u32,
+    data: &'a [u8],
+    link_type: u32,
+}
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+
+pub fn source<R>(mut r: R) -> error::Result<Source<R>>
+where
+    R: io::Read,
+{
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+
+    let format = match magic {
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => pcap_header(&mut r, true, magic)?,
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => pcap_header(&mut r, false, magic)?,
+        [0x0a, 0x0d, 0x0d, 0x0a] => Format::Pcapng {
+            big_endian: section_header(&mut r)?,
+            interfaces: Vec::new(),
+        },
+        _ => {
+            return Err(error::Error::Format {
+                msg: "not a pcap or pcapng file".to_owned(),
+            })
+        }
+    };
+
+    Ok(Source { input: r, format })
+}
+
+fn pcap_header<R>(r: &mut R, big_endian: bool, magic: [u8; 4]) -> error::Result<Format>
+where
+    R: io::Read,
+{
+    // Version, time zone, timestamp accuracy and snapshot length aren't interesting
+    let mut header = [0; 20];
+    r.read_exact(&mut header)?;
+    Ok(Format::Pcap {
+        big_endian,
+        nanos: magic[2] == 0x3c || magic[1] == 0x3c,
+        link_type: u32_at(&header, 16, big_endian).unwrap_or(0) & 0x0fff_ffff,
+    })
+}
+
+/// Reads the rest of a pcapng section header block, returning whether the section is big-endian.
+fn section_header<R>(r: &mut R) -> error::Result<bool>
+where
+    R: io::Read,
+{
+    let mut start = [0; 8];
+    r.read_exact(&mut start)?;
+    let big_endian = match start[4..8] {
+        [0x1a, 0x2b, 0x3c, 0x4d] => true,
+        [0x4d, 0x3c, 0x2b, 0x1a] => false,
+        _ => {
+            return Err(error::Error::Format {
+                msg: "bad pcapng byte-order magic".to_owned(),
+            })
+        }
+    };
+    let length = u32_at(&start, 0, big_endian).unwrap_or(0) as usize;
+    if length < 12 {
+        return Err(error::Error::Format {
+            msg: "bad pcapng section header length".to_owned(),
+        });
+    }
+    skip(r, length - 12)?;
+    Ok(big_endian)
+}
+
+fn skip<R>(r: &mut R, n: usize) -> error::Result<()>
+where
+    R: io::Read,
+{
+    let skipped = io::copy(&mut io::Read::take(r, n as u64), &mut io::sink())?;
+    if skipped < n as u64 {
+        Err(truncated())
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads `n` bytes as they arrive instead of allocating them up front, so that a corrupt length
+/// fails as a truncated capture.
+fn read_bytes<R>(r: &mut R, n: usize) -> error::Result<Vec<u8>>
+where
+    R: io::Read,
+{
+    let mut data = Vec::new();
+    io::Read::read_to_end(&mut io::Read::take(r, n as u64), &mut data)?;
+    if data.len() < n {
+        Err(truncated())
+    } else {
+        Ok(data)
+    }
+}
+
+fn truncated() -> error::Error {
+    error::Error::Format {
+        msg: "truncated packet capture".to_owned(),
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = [*data.get(offset)?, *data.get(offset + 1)?];
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn u32_at(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = [
+        *data.get(offset)?,
+        *data.get(offset + 1)?,
+        *data.get(offset + 2)?,
+        *data.get(offset + 3)?,
+    ];
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+impl<R> value::Source for Source<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.format {
+            Format::Pcap {
+                big_endian,
+                nanos,
+                link_type,
+            } => {
+                let mut header = [0; 16];
+                if !value::read_or_eof(&mut self.input, &mut header, truncated)? {
+                    return Ok(None);
+                }
+                let field = |i: usize| u32_at(&header, i * 4, big_endian).unwrap_or(0);
+                let data = read_bytes(&mut self.input, field(2) as usize)?;
+
+                let fraction = if nanos { 1e9 } else { 1e6 };
+                Ok(Some(
+                    Packet {
+                        timestamp: Some(f64::from(field(0)) + f64::from(field(1)) / fraction),
+                        interface: 0,
+                        length: field(3),
+                        data: &data,
+                        link_type,
+                    }
+                    .into_value(),
+                ))
+            }
+            Format::Pcapng { .. } => self.read_pcapng(),
+        }
+    }
+}
+
+impl<R> Source<R>
+where
+    R: io::Read,
+{
+    fn read_pcapng(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            let mut header = [0; 8];
+            if !value::read_or_eof(&mut self.input, &mut header, truncated)? {
+                return Ok(None);
+            }
+
+            if header[0..4] == [0x0a, 0x0d, 0x0d, 0x0a] {
+                // A new section, which may have a different byte order and its own interfaces
+                let mut rest = &header[4..8];
+                let mut chained = io::Read::chain(&mut rest, &mut self.input);
+                let big_endian = section_header(&mut chained)?;
+                self.format = Format::Pcapng {
+                    big_endian,
+                    interfaces: Vec::new(),
+                };
+                continue;
+            }
+
+            let (big_endian, interfaces) = match self.format {
+                Format::Pcapng {
+                    big_endian,
+                    ref mut interfaces,
+                } => (big_endian, interfaces),
+                Format::Pcap { .. } => unreachable!(),
+            };
+
+            let block_type = u32_at(&header, 0, big_endian).unwrap_or(0);
+            let length = u32_at(&header, 4, big_endian).unwrap_or(0) as usize;
+            if length < 12 || length % 4 != 0 {
+                return Err(error::Error::Format {
+                    msg: format!("bad pcapng block length {}", length),
+                });
+            }
+            let body = read_bytes(&mut self.input, length - 8)?;
+            // The body ends with a copy of the block length
+            let body = &body[..length - 12];
+
+            match block_type {
+                PCAPNG_SECTION_HEADER => unreachable!(),
+                PCAPNG_INTERFACE_DESCRIPTION => {
+                    interfaces.push(interface_description(body, big_endian));
+                }
+                PCAPNG_ENHANCED_PACKET => {
+                    let field = |i: usize| u32_at(body, i * 4, big_endian).ok_or_else(truncated);
+                    let interface_id = field(0)?;
+                    let interface = *interfaces.get(interface_id as usize).ok_or_else(|| {
+                        error::Error::Format {
+                            msg: format!("packet on undeclared interface {}", interface_id),
+                        }
+                    })?;
+                    let units = (u64::from(field(1)?) << 32) | u64::from(field(2)?);
+                    let captured = field(3)? as usize;
+                    let data = body.get(20..20 + captured).ok_or_else(truncated)?;
+
+                    return Ok(Some(
+                        Packet {
+                            timestamp: Some(
+                                (units / interface.resolution) as f64
+                                    + (units % interface.resolution) as f64
+                                        / interface.resolution as f64,
+                            ),
+                            interface: interface_id,
+                            length: field(4)?,
+                            data,
+                            link_type: interface.link_type,
+                        }
+                        .into_value(),
+                    ));
+                }
+                PCAPNG_SIMPLE_PACKET => {
+                    let interface = *interfaces.first().ok_or_else(|| error::Error::Format {
+                        msg: "packet on undeclared interface 0".to_owned(),
+                    })?;
+                    let length = u32_at(body, 0, big_endian).ok_or_else(truncated)?;
+                    let end = body.len().min(4 + length as usize);
+
+                    return Ok(Some(
+                        Packet {
+                            // Simple packet blocks have no timestamp
+                            timestamp: None,
+                            interface: 0,
+                            length,
+                            data: &body[4..end],
+                            link_type: interface.link_type,
+                        }
+                        .into_value(),
+                    ));
+                }
+                // Statistics, name resolution and other blocks are skipped
+                _ => (),
+            }
+        }
+    }
+}
+
+fn interface_description(body: &[u8], big_endian: bool) -> Interface {
+    let mut interface = Interface {
+        link_type: u32::from(u16_at(body, 0, big_endian).unwrap_or(0)),
+        resolution: 1_000_000,
+    };
+
+    let mut offset = 8;
+    while let (Some(code), Some(length)) = (
+        u16_at(body, offset, big_endian),
+        u16_at(body, offset + 2, big_endian),
+    ) {
+        let length = length as usize;
+        match code {
+            0 => break,
+            // if_tsresol: a negative power of ten, or of two if the high bit is set
+            9 if length >= 1 => {
+                let exponent = u32::from(body[offset + 4] & 0x7f);
+                let base: u64 = if body[offset + 4] & 0x80 == 0 { 10 } else { 2 };
+                if let Some(resolution) = base.checked_pow(exponent) {
+                    interface.resolution = resolution.max(1);
+                }
+            }
+            _ => (),
+        }
+        offset += 4 + (length + 3) / 4 * 4;
+    }
+    interface
+}
+
+impl<'a> Packet<'a> {
+    fn into_value(self) -> value::Value {
+        let (link, ethertype, rest) = decode_link(self.link_type, self.data);
+        let (network, protocol, rest) = match ethertype {
+            Some(0x0800) => decode_ipv4(rest),
+            Some(0x86dd) => decode_ipv6(rest),
+            _ => (value::Value::Unit, None, rest),
+        };
+        let (transport, rest) = match protocol {
+            Some(protocol) => decode_transport(protocol, rest),
+            None => (value::Value::Unit, rest),
+        };
+
+        map(vec![
+            (
+                "timestamp",
+                self.timestamp
+                    .map_or(value::Value::Unit, |t| value::Value::F64(t.into())),
+            ),
+            ("interface", value::Value::U32(self.interface)),
+            ("length", value::Value::U32(self.length)),
+            ("captured_length", value::Value::U64(self.data.len() as u64)),
+            ("link", link),
+            ("network", network),
+            ("transport", transport),
+            ("payload", value::Value::Bytes(rest.to_vec())),
+        ])
+    }
+}
+
+fn map(entries: Vec<(&str, value::Value)>) -> value::Value {
+    value::Value::Map(
+        entries
+            .into_iter()
+            .map(|(k, v)| (value::Value::String(k.to_owned()), v))
+            .collect(),
+    )
+}
+
+fn string(s: &str) -> value::Value {
+    value::Value::String(s.to_owned())
+}
+
+fn mac_address(bytes: &[u8]) -> value::Value {
+    value::Value::String(
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
+
+/// Decodes the link layer header, returning it, the ethertype of its contents and the contents.
+fn decode_link(link_type: u32, data: &[u8]) -> (value::Value, Option<u16>, &[u8]) {
+    match link_type {
+        LINKTYPE_ETHERNET if data.len() >= 14 => {
+            let mut ethertype = u16_at(data, 12, true).unwrap_or(0);
+            let mut offset = 14;
+            let mut vlans = Vec::new();
+            // 802.1Q and 802.1ad tags
+            while (ethertype == 0x8100 || ethertype == 0x88a8) && data.len() >= offset + 4 {
+                vlans.push(value::Value::U16(
+                    u16_at(data, offset, true).unwrap_or(0) & 0x0fff,
+                ));
+                ethertype = u16_at(data, offset + 2, true).unwrap_or(0);
+                offset += 4;
+            }
+            let link = map(vec![
+                ("type", string("ethernet")),
+                ("source", mac_address(&data[6..12])),
+                ("destination", mac_address(&data[0..6])),
+                ("ethertype", value::Value::U16(ethertype)),
+                ("vlans", value::Value::Sequence(vlans)),
+            ]);
+            (link, Some(ethertype), &data[offset..])
+        }
+        LINKTYPE_LINUX_SLL if data.len() >= 16 => {
+            let address_length = (u16_at(data, 4, true).unwrap_or(0) as usize).min(8);
+            let ethertype = u16_at(data, 14, true).unwrap_or(0);
+            let link = map(vec![
+                ("type", string("linux_sll")),
+                ("source", mac_address(&data[6..6 + address_length])),
+                ("ethertype", value::Value::U16(ethertype)),
+            ]);
+            (link, Some(ethertype), &data[16..])
+        }
+        LINKTYPE_NULL if data.len() >= 4 => {
+            // The address family is in the byte order of the capturing host
+            let family = u32_at(data, 0, false)
+                .filter(|f| *f < 0x100)
+                .or_else(|| u32_at(data, 0, true))
+                .unwrap_or(0);
+            let ethertype = match family {
+                2 => Some(0x0800),
+                24 | 28 | 30 => Some(0x86dd),
+                _ => None,
+            };
+            let link = map(vec![
+                ("type", string("null")),
+                ("family", value::Value::U32(family)),
+            ]);
+            (link, ethertype, &data[4..])
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => {
+            let ethertype = match data.first().map(|b| b >> 4) {
+                Some(4) => Some(0x0800),
+                Some(6) => Some(0x86dd),
+                _ => None,
+            };
+            (value::Value::Unit, ethertype, data)
+        }
+        _ => (value::Value::Unit, None, data),
+    }
+}
+
+/// Decodes an IPv4 header, returning it, the protocol of its contents and the contents.
+fn decode_ipv4(data: &[u8]) -> (value::Value, Option<u8>, &[u8]) {
+    let header_length = data.first().map_or(0, |b| (b & 0x0f) as usize * 4);
+    if header_length < 20 || data.len() < header_length {
+        return (value::Value::Unit, None, data);
+    }
+
+    let total_length = u16_at(data, 2, true).unwrap_or(0) as usize;
+    let fragment = u16_at(data, 6, true).unwrap_or(0);
+    let protocol = data[9];
+    let source = net::Ipv4Addr::new(data[12], data[13], data[14], data[15]);
+    let destination = net::Ipv4Addr::new(data[16], data[17], data[18], data[19]);
+    // Ethernet frames may be padded beyond the end of the IP packet
+    let end = data.len().min(total_length.max(header_length));
+
+    let network = map(vec![
+        ("protocol", string("ipv4")),
+        ("source", value::Value::String(source.to_string())),
+        ("destination", value::Value::String(destination.to_string())),
+        ("ttl", value::Value::U8(data[8])),
+        ("transport_protocol", value::Value::U8(protocol)),
+    ]);
+    // Only the first fragment contains the transport header
+    let protocol = if fragment & 0x1fff == 0 {
+        Some(protocol)
+    } else {
+        None
+    };
+    (network, protocol, &data[header_length..end])
+}
+
+/// Decodes an IPv6 header, returning it, the next header of its contents and the contents.
+fn decode_ipv6(data: &[u8]) -> (value::Value, Option<u8>, &[u8]) {
+    if data.len() < 40 {
+        return (value::Value::Unit, None, data);
+    }
+
+    let payload_length = u16_at(data, 4, true).unwrap_or(0) as usize;
+    let address = |offset: usize| {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&data[offset..offset + 16]);
+        value::Value::String(net::Ipv6Addr::from(bytes).to_string())
+    };
+    let network = map(vec![
+        ("protocol", string("ipv6")),
+        ("source", address(8)),
+        ("destination", address(24)),
+        ("ttl", value::Value::U8(data[7])),
+        ("transport_protocol", value::Value::U8(data[6])),
+    ]);
+    let end = data.len().min(40 + payload_length);
+    (network, Some(data[6]), &data[40..end])
+}
+
+/// Decodes a TCP, UDP or ICMP header, returning it and the payload.
+fn decode_transport(protocol: u8, data: &[u8]) -> (value::Value, &[u8]) {
+    let port = |offset| value::Value::U16(u16_at(data, offset, true).unwrap_or(0));
+
+    match protocol {
+        6 => {
+            let header_length = data.get(12).map_or(0, |b| (b >> 4) as usize * 4);
+            if header_length < 20 || data.len() < header_length {
+                return (value::Value::Unit, data);
+            }
+            let flags = [
+                (0x01, "FIN"),
+                (0x02, "SYN"),
+                (0x04, "RST"),
+                (0x08, "PSH"),
+                (0x10, "ACK"),
+                (0x20, "URG"),
+                (0x40, "ECE"),
+                (0x80, "CWR"),
+            ]
+            .iter()
+            .filter(|(bit, _)| data[13] & bit != 0)
+            .map(|(_, name)| string(name))
+            .collect();
+            let transport = map(vec![
+                ("protocol", string("tcp")),
+                ("source_port", port(0)),
+                ("destination_port", port(2)),
+                (
+                    "sequence",
+                    value::Value::U32(u32_at(data, 4, true).unwrap_or(0)),
+                ),
+                (
+                    "acknowledgement",
+                    value::Value::U32(u32_at(data, 8, true).unwrap_or(0)),
+                ),
+                ("flags", value::Value::Sequence(flags)),
+                ("window", port(14)),
+            ]);
+            (transport, &data[header_length..])
+        }
+        17 if data.len() >= 8 => {
+            let transport = map(vec![
+                ("protocol", string("udp")),
+                ("source_port", port(0)),
+                ("destination_port", port(2)),
+            ]);
+            (transport, &data[8..])
+        }
+        1 | 58 if data.len() >= 4 => {
+            let transport = map(vec![
+                (
+                    "protocol",
+                    string(if protocol == 1 { "icmp" } else { "icmpv6" }),
+                ),
+                ("type", value::Value::U8(data[0])),
+                ("code", value::Value::U8(data[1])),
+            ]);
+            (transport, &data[4..])
+        }
+        _ => (value::Value::Unit, data),
+    }
+}
+
+impl<R> fmt::Debug for Source<R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PcapSource")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    /// An Ethernet frame with an IPv4 UDP datagram from 10.0.0.1:5353 to 10.0.0.2:53.
+    fn udp_frame() -> Vec<u8> {
+        let mut frame = vec![
+            0x02, 0, 0, 0, 0, 0x02, // destination
+            0x02, 0, 0, 0, 0, 0x01, // source
+            0x08, 0x00, // IPv4
+            0x45, 0, 0, 31, 0, 0, 0, 0, 64, 17, 0, 0, // header up to the addresses
+            10, 0, 0, 1, 10, 0, 0, 2, // addresses
+            0x14, 0xe9, 0, 53, 0, 11, 0, 0, // UDP header
+        ];
+        frame.extend(b"abc");
+        frame
+    }
+
+    fn get<'a>(record: &'a Value, path: &str) -> &'a Value {
+        value::path::Path::parse(path)
+            .unwrap()
+            .select(record)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pcap() {
+        let frame = udp_frame();
+        let mut input = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        input.extend(&[0; 8]);
+        input.extend(&[0xff, 0xff, 0, 0, 1, 0, 0, 0]);
+        input.extend(&[10, 0, 0, 0, 0xa0, 0x86, 0x01, 0]);
+        input.extend(&(frame.len() as u32).to_le_bytes());
+        input.extend(&(frame.len() as u32).to_le_bytes());
+        input.extend(&frame);
+
+        let mut source = source(&input[..]).unwrap();
+        let record = source.read().unwrap().unwrap();
+        assert_eq!(*get(&record, ".timestamp"), Value::F64(10.1.into()));
+        assert_eq!(*get(&record, ".length"), Value::U32(45));
+        assert_eq!(*get(&record, ".link.source"), s("02:00:00:00:00:01"));
+        assert_eq!(*get(&record, ".network.source"), s("10.0.0.1"));
+        assert_eq!(*get(&record, ".network.destination"), s("10.0.0.2"));
+        assert_eq!(*get(&record, ".transport.protocol"), s("udp"));
+        assert_eq!(*get(&record, ".transport.source_port"), Value::U16(5353));
+        assert_eq!(*get(&record, ".transport.destination_port"), Value::U16(53));
+        assert_eq!(*get(&record, ".payload"), Value::Bytes(b"abc".to_vec()));
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let block = |block_type: u32, body: &[u8]| {
+            let length = (12 + body.len() as u32 + 3) / 4 * 4;
+            let mut block = block_type.to_be_bytes().to_vec();
+            block.extend(&length.to_be_bytes());
+            block.extend(body);
+            block.resize(length as usize - 4, 0);
+            block.extend(&length.to_be_bytes());
+            block
+        };
+
+        let mut input = block(
+            PCAPNG_SECTION_HEADER,
+            &[
+                0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ],
+        );
+        // Raw IP with millisecond timestamps
+        input.extend(block(
+            PCAPNG_INTERFACE_DESCRIPTION,
+            &[0, 101, 0, 0, 0, 0, 0, 0, 0, 9, 0, 1, 3, 0, 0, 0, 0, 0, 0, 0],
+        ));
+        let packet = &udp_frame()[14..];
+        let mut body = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x07, 0xd1];
+        body.extend(&(packet.len() as u32).to_be_bytes());
+        body.extend(&(packet.len() as u32).to_be_bytes());
+        body.extend(packet);
+        input.extend(block(PCAPNG_ENHANCED_PACKET, &body));
+        input.extend(block(5, &[0; 8]));
+
+        let mut source = source(&input[..]).unwrap();
+        let record = source.read().unwrap().unwrap();
+        assert_eq!(*get(&record, ".timestamp"), Value::F64(2.001.into()));
+        assert_eq!(*get(&record, ".link"), Value::Unit);
+        assert_eq!(*get(&record, ".network.protocol"), s("ipv4"));
+        assert_eq!(*get(&record, ".transport.protocol"), s("udp"));
+        assert_eq!(*get(&record, ".payload"), Value::Bytes(b"abc".to_vec()));
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated() {
+        // A packet that claims to have captured 4 GiB
+        let mut input = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        input.extend(&[0; 8]);
+        input.extend(&[0xff, 0xff, 0, 0, 1, 0, 0, 0]);
+        input.extend(&[0; 8]);
+        input.extend(&[0xff; 8]);
+        input.extend(&udp_frame());
+
+        let mut source = source(&input[..]).unwrap();
+        assert_eq!(
+            source.read().unwrap_err().to_string(),
+            "format error: truncated packet capture"
+        );
+    }
+
+    #[test]
+    fn test_not_a_capture() {
+        assert!(source(&b"{\"a\": 1}"[..]).is_err());
+        let source = source(&[0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0][..]);
+        assert!(source.is_err());
+    }
+}
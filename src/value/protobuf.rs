@@ -1,14 +1,141 @@
+use std::convert::TryFrom;
 use std::fmt;
+use std::io;
+use std::iter;
+use std::mem;
+use std::str;
 
 use crate::error;
 use protobuf;
-use serde;
 
 use crate::value;
+use protobuf::wire_format::WireType;
 use serde_protobuf;
 use serde_protobuf::descriptor;
 
-pub struct Source<'a>(serde_protobuf::de::Deserializer<'a>, bool);
+pub mod pool;
+pub mod text;
+
+/// The key under which the fields of a message that aren't in its type are kept, see
+/// `Decoding::preserve_unknown_fields`.
+pub const UNKNOWN_FIELDS_KEY: &str = "@unknown";
+
+pub struct Source<'a> {
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    input: protobuf::CodedInputStream<'a>,
+    decoding: Decoding,
+    done: bool,
+}
+
+/// What to do with enum numbers that are not declared in the enum type.
+///
+/// Proto3 enums are open, so decoders must accept any number, e.g. values that were added in a
+/// newer version of the schema.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum UnknownEnums {
+    /// Use the number itself.
+    #[default]
+    Number,
+    /// Use a string like `"UNKNOWN(5)"`.
+    String,
+    /// Fail with an error.
+    Error,
+}
+
+/// How the sources of this module decode messages.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Decoding {
+    enum_style: value::enums::Style,
+    unknown_enums: UnknownEnums,
+    reject_unknown_fields: bool,
+    preserve_unknown_fields: bool,
+    well_known_types: bool,
+    int64_as_string: bool,
+}
+
+/// A source that reads gRPC length-prefixed frames, as captured from the wire, and decodes the
+/// payload of each frame as a protobuf message.
+pub struct GrpcSource<'a, R>
+where
+    R: io::Read,
+{
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    input: R,
+    decoding: Decoding,
+}
+
+/// A source that reads a stream of messages that are each prefixed with their length as a varint,
+/// like Java's `writeDelimitedTo` writes them, and decodes each as a record.
+pub struct DelimitedSource<'a> {
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    input: protobuf::CodedInputStream<'a>,
+    decoding: Decoding,
+}
+
+/// A source that produces a single record: the default instance of a message type.
+///
+/// Scalar fields get their declared default or the zero value of their type, enums their value
+/// numbered zero, repeated fields an empty sequence, and optional message fields an empty map.
+/// Required message fields are populated recursively.
+#[derive(Debug)]
+pub struct DefaultSource(Option<value::Value>);
+
+/// A sink that writes each record as a length-delimited message of a type, as encoded by
+/// `encode`.
+///
+/// Each record is checked with `validate` first, so that a record that doesn't fit the message
+/// type is reported with all of its problems, and nothing of it is written.
+pub struct Sink<'a, W>
+where
+    W: io::Write,
+{
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    w: W,
+}
+
+/// A sink that writes each record as a length-delimited `google.protobuf.Struct` message.
+///
+/// This makes it possible to produce protobuf for arbitrary records without a schema.  Records
+/// must be maps.  All numbers become doubles, map keys are converted to strings, and bytes become
+/// lists of numbers, like in JSON.
+pub struct StructSink<W>(W)
+where
+    W: io::Write;
+
+/// A sink that writes each record as a length-delimited message of a type that depends on the
+/// record, for streams of different kinds of events.
+///
+/// The value at the route path of each record, e.g. `.type`, is looked up in a list of routes to
+/// find the message type.  If the route path is a single key and the message type has no field
+/// with that name, the key is left out of the message.
+pub struct RouteSink<'a, W>
+where
+    W: io::Write,
+{
+    descriptors: &'a descriptor::Descriptors,
+    route: value::path::Path,
+    messages: Vec<(String, &'a descriptor::MessageDescriptor)>,
+    w: W,
+}
+
+/// A sink that converts each record to a message of a type in the proto3 JSON mapping, and
+/// passes it on to a JSON sink.
+///
+/// Fields get their lowerCamelCase JSON names, 64-bit integers become strings, bytes become
+/// base64, enums their names, and the well-known types like `google.protobuf.Timestamp` and
+/// `google.protobuf.Struct` their special forms.  Fields that aren't set are left out.
+pub struct JsonSink<'a, S>
+where
+    S: value::Sink,
+{
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    inner: S,
+}
 
 #[inline]
 pub fn source<'a>(
@@ -16,30 +143,3342 @@ pub fn source<'a>(
     message_name: &str,
     input: protobuf::CodedInputStream<'a>,
 ) -> error::Result<Source<'a>> {
-    let de = serde_protobuf::de::Deserializer::for_named_message(descriptors, message_name, input)?;
-    Ok(Source(de, true))
+    Ok(Source {
+        descriptors,
+        message: message_by_name(descriptors, message_name)?,
+        input,
+        decoding: Decoding::default(),
+        done: false,
+    })
+}
+
+#[inline]
+pub fn delimited_source<'a>(
+    descriptors: &'a descriptor::Descriptors,
+    message_name: &str,
+    input: protobuf::CodedInputStream<'a>,
+) -> error::Result<DelimitedSource<'a>> {
+    Ok(DelimitedSource {
+        descriptors,
+        message: message_by_name(descriptors, message_name)?,
+        input,
+        decoding: Decoding::default(),
+    })
+}
+
+#[inline]
+pub fn grpc_source<'a, R>(
+    descriptors: &'a descriptor::Descriptors,
+    message_name: &str,
+    input: R,
+) -> error::Result<GrpcSource<'a, R>>
+where
+    R: io::Read,
+{
+    Ok(GrpcSource {
+        descriptors,
+        message: message_by_name(descriptors, message_name)?,
+        input,
+        decoding: Decoding::default(),
+    })
+}
+
+fn message_by_name<'a>(
+    descriptors: &'a descriptor::Descriptors,
+    message_name: &str,
+) -> error::Result<&'a descriptor::MessageDescriptor> {
+    descriptors.message_by_name(message_name).ok_or_else(|| {
+        error::Error::from(serde_protobuf::error::Error::UnknownMessage {
+            name: message_name.to_owned(),
+        })
+    })
+}
+
+/// Creates descriptors for decoding messages from a descriptor set, where the fields of legacy
+/// proto2 groups are described as fields of the nested message type of the group.
+///
+/// Encoding with these descriptors would write groups as length-delimited messages, so they
+/// should only be used for decoding.
+pub fn decoding_descriptors(
+    file_set: &protobuf::descriptor::FileDescriptorSet,
+) -> descriptor::Descriptors {
+    use protobuf::descriptor::FieldDescriptorProto_Type as Type;
+
+    fn ungroup_message(message: &mut protobuf::descriptor::DescriptorProto) {
+        for field in message.mut_field().iter_mut() {
+            if field.get_field_type() == Type::TYPE_GROUP {
+                field.set_field_type(Type::TYPE_MESSAGE);
+            }
+        }
+        for nested in message.mut_nested_type().iter_mut() {
+            ungroup_message(nested);
+        }
+    }
+
+    let mut file_set = file_set.clone();
+    for file in file_set.mut_file().iter_mut() {
+        for message in file.mut_message_type().iter_mut() {
+            ungroup_message(message);
+        }
+    }
+    let mut descriptors = descriptor::Descriptors::from_proto(&file_set);
+    mark_map_entries(&mut descriptors, &file_set);
+    descriptors
+}
+
+/// Creates descriptors for encoding and decoding messages from a descriptor set.
+pub fn encoding_descriptors(
+    file_set: &protobuf::descriptor::FileDescriptorSet,
+) -> descriptor::Descriptors {
+    let mut descriptors = descriptor::Descriptors::from_proto(file_set);
+    mark_map_entries(&mut descriptors, file_set);
+    descriptors
+}
+
+/// The suffix of the names of the empty messages that mark map entry types in descriptors.  Type
+/// names can't contain a colon, so these never clash with real types.
+const MAP_ENTRY_MARKER: &str = ":map_entry";
+
+/// Marks the message type with the given full name as the entry type of map fields.
+pub fn mark_map_entry(descriptors: &mut descriptor::Descriptors, name: &str) {
+    descriptors.add_message(descriptor::MessageDescriptor::new(format!(
+        "{}{}",
+        name, MAP_ENTRY_MARKER
+    )));
+}
+
+/// Marks the message types in a descriptor set that have the `map_entry` option.
+fn mark_map_entries(
+    descriptors: &mut descriptor::Descriptors,
+    file_set: &protobuf::descriptor::FileDescriptorSet,
+) {
+    fn mark(
+        descriptors: &mut descriptor::Descriptors,
+        prefix: &str,
+        message: &protobuf::descriptor::DescriptorProto,
+    ) {
+        let name = format!("{}.{}", prefix, message.get_name());
+        if message.get_options().get_map_entry() {
+            mark_map_entry(descriptors, &name);
+        }
+        for nested in message.get_nested_type() {
+            mark(descriptors, &name, nested);
+        }
+    }
+
+    for file in file_set.get_file() {
+        let prefix = if file.get_package().is_empty() {
+            String::new()
+        } else {
+            format!(".{}", file.get_package())
+        };
+        for message in file.get_message_type() {
+            mark(descriptors, &prefix, message);
+        }
+    }
+}
+
+/// Checks that the message and enum types that the fields in a descriptor set refer to are all
+/// defined in it.
+///
+/// `serde_protobuf` only logs a warning for fields with types that it can't resolve, which
+/// happens when a file imports one that isn't in the set, and fails once such a field is used.
+pub fn check_refs(file_set: &protobuf::descriptor::FileDescriptorSet) -> error::Result<()> {
+    use protobuf::descriptor::DescriptorProto;
+    use std::collections::HashSet;
+
+    fn collect(
+        prefix: &str,
+        message: &DescriptorProto,
+        types: &mut HashSet<String>,
+        refs: &mut Vec<(String, String)>,
+    ) {
+        let name = format!("{}.{}", prefix, message.get_name());
+        for field in message.get_field() {
+            if !field.get_type_name().is_empty() {
+                let field_name = format!("{}.{}", name, field.get_name());
+                refs.push((field_name, field.get_type_name().to_owned()));
+            }
+        }
+        for e in message.get_enum_type() {
+            types.insert(format!("{}.{}", name, e.get_name()));
+        }
+        for nested in message.get_nested_type() {
+            collect(&name, nested, types, refs);
+        }
+        types.insert(name);
+    }
+
+    let mut types = HashSet::new();
+    let mut refs = Vec::new();
+    for file in file_set.get_file() {
+        let prefix = if file.get_package().is_empty() {
+            String::new()
+        } else {
+            format!(".{}", file.get_package())
+        };
+        for e in file.get_enum_type() {
+            types.insert(format!("{}.{}", prefix, e.get_name()));
+        }
+        for message in file.get_message_type() {
+            collect(&prefix, message, &mut types, &mut refs);
+        }
+    }
+
+    let unresolved = refs
+        .into_iter()
+        .filter(|(_, type_name)| !types.contains(type_name))
+        .map(|(field, type_name)| format!("{} has unknown type {}", field, type_name))
+        .collect::<Vec<_>>();
+    if unresolved.is_empty() {
+        Ok(())
+    } else {
+        Err(error::Error::Message(format!(
+            "inconsistent protobuf schema: {}",
+            unresolved.join(", ")
+        )))
+    }
+}
+
+impl Decoding {
+    #[inline]
+    pub fn unknown_enums(self, unknown_enums: UnknownEnums) -> Self {
+        Decoding {
+            unknown_enums,
+            ..self
+        }
+    }
+
+    /// Outputs the values of enum fields in the given style, see `value::enums::Style`.
+    #[inline]
+    pub fn enum_style(self, enum_style: value::enums::Style) -> Self {
+        Decoding { enum_style, ..self }
+    }
+
+    /// Fails on fields that aren't in the message type, like ones that were added in a newer
+    /// version of the schema, instead of leaving them out.
+    #[inline]
+    pub fn reject_unknown_fields(self, reject_unknown_fields: bool) -> Self {
+        Decoding {
+            reject_unknown_fields,
+            ..self
+        }
+    }
+
+    /// Keeps fields that aren't in the message type in a map under the `"@unknown"` key, from
+    /// their numbers to their encoded bytes, tags included, so that schema drift can be detected
+    /// and messages can be encoded again without losing them.
+    #[inline]
+    pub fn preserve_unknown_fields(self, preserve_unknown_fields: bool) -> Self {
+        Decoding {
+            preserve_unknown_fields,
+            ..self
+        }
+    }
+
+    /// Outputs the well-known types as their natural values instead of as messages: timestamps
+    /// as RFC 3339 strings, durations as strings like `"1.5s"`, `Struct`, `Value` and `ListValue`
+    /// as the maps, values and sequences they hold, wrappers like `Int64Value` as the wrapped
+    /// value, field masks as sequences of paths, and `Any` as the message it holds, decoded with
+    /// the type of its type URL, with an `@type` key.
+    #[inline]
+    pub fn well_known_types(self, well_known_types: bool) -> Self {
+        Decoding {
+            well_known_types,
+            ..self
+        }
+    }
+
+    /// Outputs the values of 64-bit integer fields as decimal strings, like the canonical JSON
+    /// mapping of protobuf does, so that they don't lose precision in JavaScript.
+    #[inline]
+    pub fn int64_as_string(self, int64_as_string: bool) -> Self {
+        Decoding {
+            int64_as_string,
+            ..self
+        }
+    }
+}
+
+impl<'a> Source<'a> {
+    #[inline]
+    pub fn decoding(self, decoding: Decoding) -> Self {
+        Source { decoding, ..self }
+    }
+}
+
+impl<'a> DelimitedSource<'a> {
+    #[inline]
+    pub fn decoding(self, decoding: Decoding) -> Self {
+        DelimitedSource { decoding, ..self }
+    }
+}
+
+impl<'a, R> GrpcSource<'a, R>
+where
+    R: io::Read,
+{
+    #[inline]
+    pub fn decoding(self, decoding: Decoding) -> Self {
+        GrpcSource { decoding, ..self }
+    }
+}
+
+pub fn default_source(
+    descriptors: &descriptor::Descriptors,
+    message_name: &str,
+) -> error::Result<DefaultSource> {
+    let message = message_by_name(descriptors, message_name)?;
+    Ok(DefaultSource(Some(default_instance(
+        descriptors,
+        message,
+        &mut Vec::new(),
+    ))))
+}
+
+/// Describes a message type as a value like `{"name": ".foo.Bar", "fields": [...]}`, where each
+/// field has a `name`, `number`, `label` and `type`, the `type_name` of message and enum types,
+/// and the declared `default` value if any.
+pub fn descriptor_value(
+    descriptors: &descriptor::Descriptors,
+    message_name: &str,
+) -> error::Result<value::Value> {
+    use serde_protobuf::descriptor::FieldType;
+
+    let s = |s: &str| value::Value::String(s.to_owned());
+    let message = message_by_name(descriptors, message_name)?;
+    let fields = message
+        .fields()
+        .iter()
+        .map(|field| {
+            let label = match field.field_label() {
+                descriptor::FieldLabel::Optional => "optional",
+                descriptor::FieldLabel::Required => "required",
+                descriptor::FieldLabel::Repeated => "repeated",
+            };
+            let (field_type, type_name) = match field.field_type(descriptors) {
+                FieldType::Double => ("double", None),
+                FieldType::Float => ("float", None),
+                FieldType::Int64 => ("int64", None),
+                FieldType::UInt64 => ("uint64", None),
+                FieldType::Int32 => ("int32", None),
+                FieldType::Fixed64 => ("fixed64", None),
+                FieldType::Fixed32 => ("fixed32", None),
+                FieldType::Bool => ("bool", None),
+                FieldType::String => ("string", None),
+                FieldType::Group => ("group", None),
+                FieldType::Bytes => ("bytes", None),
+                FieldType::UInt32 => ("uint32", None),
+                FieldType::SFixed32 => ("sfixed32", None),
+                FieldType::SFixed64 => ("sfixed64", None),
+                FieldType::SInt32 => ("sint32", None),
+                FieldType::SInt64 => ("sint64", None),
+                FieldType::Message(m) => ("message", Some(m.name())),
+                FieldType::UnresolvedMessage(name) => ("message", Some(name)),
+                FieldType::Enum(e) => ("enum", Some(e.name())),
+                FieldType::UnresolvedEnum(name) => ("enum", Some(name)),
+            };
+            let mut entries = vec![
+                (s("name"), s(field.name())),
+                (s("number"), value::Value::I32(field.number())),
+                (s("label"), s(label)),
+                (s("type"), s(field_type)),
+            ];
+            if let Some(type_name) = type_name {
+                entries.push((s("type_name"), s(type_name)));
+            }
+            if field.default_value().is_some() {
+                let default = default_field_value(descriptors, field, &mut Vec::new());
+                entries.push((s("default"), default));
+            }
+            value::Value::Map(entries)
+        })
+        .collect();
+    Ok(value::Value::Map(vec![
+        (s("name"), s(message.name())),
+        (s("fields"), value::Value::Sequence(fields)),
+    ]))
+}
+
+#[inline]
+pub fn sink<'a, W>(
+    descriptors: &'a descriptor::Descriptors,
+    message_name: &str,
+    w: W,
+) -> error::Result<Sink<'a, W>>
+where
+    W: io::Write,
+{
+    Ok(Sink {
+        descriptors,
+        message: message_by_name(descriptors, message_name)?,
+        w,
+    })
+}
+
+#[inline]
+pub fn struct_sink<W>(w: W) -> StructSink<W>
+where
+    W: io::Write,
+{
+    StructSink(w)
+}
+
+/// Creates a sink that routes records to message types, where `routes` are pairs of a route
+/// value and a message name.
+pub fn route_sink<'a, W>(
+    descriptors: &'a descriptor::Descriptors,
+    route: value::path::Path,
+    routes: &[(String, String)],
+    w: W,
+) -> error::Result<RouteSink<'a, W>>
+where
+    W: io::Write,
+{
+    let messages = routes
+        .iter()
+        .map(|(value, message_name)| {
+            Ok((value.clone(), message_by_name(descriptors, message_name)?))
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+    Ok(RouteSink {
+        descriptors,
+        route,
+        messages,
+        w,
+    })
+}
+
+#[inline]
+pub fn json_sink<'a, S>(
+    descriptors: &'a descriptor::Descriptors,
+    message_name: &str,
+    inner: S,
+) -> error::Result<JsonSink<'a, S>>
+where
+    S: value::Sink,
+{
+    Ok(JsonSink {
+        descriptors,
+        message: message_by_name(descriptors, message_name)?,
+        inner,
+    })
 }
 
 impl<'a> value::Source for Source<'a> {
     #[inline]
     fn read(&mut self) -> error::Result<Option<value::Value>> {
-        if self.1 {
-            self.1 = false;
-            match serde::Deserialize::deserialize(&mut self.0)
-                .map_err(serde_protobuf::error::CompatError::into_error)
-            {
-                Ok(v) => Ok(Some(v)),
-                Err(serde_protobuf::error::Error::EndOfStream) => Ok(None),
-                Err(e) => Err(error::Error::from(e)),
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+
+        let mut bytes = Vec::new();
+        while !self.input.eof()? {
+            bytes.push(self.input.read_raw_byte()?);
+        }
+
+        match decode_bytes(self.descriptors, self.message, &bytes, self.decoding) {
+            Ok(v) if self.decoding.int64_as_string => Ok(Some(int64_to_string(v))),
+            Ok(v) => Ok(Some(v)),
+            Err(error::Error::Protobuf(serde_protobuf::error::Error::EndOfStream)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Replaces the values of 64-bit integer fields in a decoded message with their decimal strings.
+///
+/// Only 64-bit fields are decoded as `I64` and `U64`, so the field types don't need to be looked
+/// up again.  Map keys are left alone.
+fn int64_to_string(v: value::Value) -> value::Value {
+    match v {
+        value::Value::I64(n) => value::Value::String(n.to_string()),
+        value::Value::U64(n) => value::Value::String(n.to_string()),
+        value::Value::Sequence(seq) => {
+            value::Value::Sequence(seq.into_iter().map(int64_to_string).collect())
+        }
+        value::Value::Map(entries) => value::Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k, int64_to_string(v)))
+                .collect(),
+        ),
+        v => v,
+    }
+}
+
+/// Decodes a message from the input, instead of going through `serde_protobuf::de`, which panics
+/// on unknown enum numbers.
+fn decode(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    input: &mut protobuf::CodedInputStream,
+    decoding: Decoding,
+) -> error::Result<value::Value> {
+    let mut decoded = serde_protobuf::value::Message::new(message);
+    decoded.merge_from(descriptors, message, input)?;
+    message_value(descriptors, message, decoded, decoding)
+}
+
+/// Decodes an encoded message, also if it contains proto2 groups or packed repeated enums.
+///
+/// Groups are decoded as the nested messages that `decoding_descriptors` describes them as, by
+/// rewriting them as length-delimited fields when the message can't be decoded as it is.  Packed
+/// enums are rewritten as one varint field per value, since `serde_protobuf` only accepts those
+/// for enums.
+fn decode_bytes(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    bytes: &[u8],
+    decoding: Decoding,
+) -> error::Result<value::Value> {
+    let mut input = protobuf::CodedInputStream::from_bytes(bytes);
+    match decode(descriptors, message, &mut input, decoding) {
+        Err(error::Error::Protobuf(serde_protobuf::error::Error::BadWireType {
+            wire_type: WireType::WireTypeStartGroup,
+        }))
+        | Err(error::Error::Protobuf(serde_protobuf::error::Error::BadWireType {
+            wire_type: WireType::WireTypeLengthDelimited,
+        })) => {
+            let reencoded = reencode_bytes(descriptors, Some(message), bytes)?;
+            let mut input = protobuf::CodedInputStream::from_bytes(&reencoded);
+            decode(descriptors, message, &mut input, decoding)
+        }
+        result => result,
+    }
+}
+
+/// Re-encodes a message with all groups in it, also in nested messages, as length-delimited
+/// fields, and all packed enums as varint fields.
+fn reencode_bytes(
+    descriptors: &descriptor::Descriptors,
+    message: Option<&descriptor::MessageDescriptor>,
+    bytes: &[u8],
+) -> error::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    {
+        let mut input = protobuf::CodedInputStream::from_bytes(bytes);
+        let mut output = protobuf::CodedOutputStream::vec(&mut result);
+        reencode(descriptors, message, &mut input, None, &mut output)?;
+        output.flush()?;
+    }
+    Ok(result)
+}
+
+/// Copies fields from the input to the output, rewriting groups as length-delimited fields and
+/// packed enums as varint fields, until the end of the input or the end of the group with the
+/// given field number.
+///
+/// Fields that are not known to contain messages or enums are copied verbatim.
+fn reencode(
+    descriptors: &descriptor::Descriptors,
+    message: Option<&descriptor::MessageDescriptor>,
+    input: &mut protobuf::CodedInputStream,
+    group: Option<u32>,
+    output: &mut protobuf::CodedOutputStream,
+) -> error::Result<()> {
+    loop {
+        if input.eof()? {
+            return match group {
+                None => Ok(()),
+                Some(number) => Err(error::Error::Format {
+                    msg: format!("group of field {} is not terminated", number),
+                }),
+            };
+        }
+
+        let (number, wire_type) = input.read_tag_unpack()?;
+        let field_type = message
+            .and_then(|m| m.field_by_number(number as i32))
+            .map(|f| f.field_type(descriptors));
+        let field_message = match field_type {
+            Some(descriptor::FieldType::Message(m)) => Some(m),
+            _ => None,
+        };
+        let is_enum = matches!(field_type, Some(descriptor::FieldType::Enum(_)));
+        match wire_type {
+            WireType::WireTypeVarint => {
+                output.write_tag(number, wire_type)?;
+                output.write_raw_varint64(input.read_raw_varint64()?)?;
+            }
+            WireType::WireTypeFixed64 => {
+                output.write_tag(number, wire_type)?;
+                output.write_raw_little_endian64(input.read_raw_little_endian64()?)?;
+            }
+            WireType::WireTypeFixed32 => {
+                output.write_tag(number, wire_type)?;
+                output.write_raw_little_endian32(input.read_raw_little_endian32()?)?;
+            }
+            WireType::WireTypeLengthDelimited if is_enum => {
+                let bytes = input.read_bytes()?;
+                let mut packed = protobuf::CodedInputStream::from_bytes(&bytes);
+                while !packed.eof()? {
+                    output.write_tag(number, WireType::WireTypeVarint)?;
+                    output.write_raw_varint64(packed.read_raw_varint64()?)?;
+                }
+            }
+            WireType::WireTypeLengthDelimited => {
+                let bytes = input.read_bytes()?;
+                let bytes = match field_message {
+                    Some(m) => reencode_bytes(descriptors, Some(m), &bytes)?,
+                    None => bytes,
+                };
+                output.write_bytes(number, &bytes)?;
+            }
+            WireType::WireTypeStartGroup => {
+                let mut bytes = Vec::new();
+                {
+                    let mut group_output = protobuf::CodedOutputStream::vec(&mut bytes);
+                    reencode(
+                        descriptors,
+                        field_message,
+                        input,
+                        Some(number),
+                        &mut group_output,
+                    )?;
+                    group_output.flush()?;
+                }
+                output.write_bytes(number, &bytes)?;
+            }
+            WireType::WireTypeEndGroup if group == Some(number) => return Ok(()),
+            WireType::WireTypeEndGroup => {
+                return Err(error::Error::Format {
+                    msg: format!("unexpected end of group of field {}", number),
+                })
             }
-        } else {
-            Ok(None)
         }
     }
 }
 
-impl<'a> fmt::Debug for Source<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("ProtobufSource").finish()
+fn message_value(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    decoded: serde_protobuf::value::Message,
+    decoding: Decoding,
+) -> error::Result<value::Value> {
+    use serde_protobuf::value::Field;
+
+    if decoding.reject_unknown_fields {
+        if let Some(number) = decoded.unknown.iter().map(|(number, _)| number).min() {
+            return Err(error::Error::Format {
+                msg: format!("unknown field {} in message {}", number, message.name()),
+            });
+        }
+    }
+
+    let mut entries = Vec::with_capacity(decoded.fields.len());
+    for (number, field) in decoded.fields {
+        let descriptor = match message.field_by_number(number) {
+            Some(descriptor) => descriptor,
+            None => continue,
+        };
+        let v = match field {
+            Field::Singular(None) => value::Value::Unit,
+            Field::Singular(Some(v)) => field_value(descriptors, descriptor, v, decoding)?,
+            Field::Repeated(vs) => {
+                let vs = vs
+                    .into_iter()
+                    .map(|v| field_value(descriptors, descriptor, v, decoding))
+                    .collect::<error::Result<Vec<_>>>()?;
+                match map_entry(descriptors, descriptor) {
+                    Some(entry) => value::Value::Map(
+                        vs.into_iter()
+                            .map(|v| map_entry_pair(descriptors, entry, v))
+                            .collect(),
+                    ),
+                    None => value::Value::Sequence(vs),
+                }
+            }
+        };
+        entries.push((value::Value::String(descriptor.name().to_owned()), v));
+    }
+    if decoding.preserve_unknown_fields {
+        if let Some(unknown) = unknown_fields_value(&decoded.unknown)? {
+            entries.push((value::Value::String(UNKNOWN_FIELDS_KEY.to_owned()), unknown));
+        }
+    }
+    if decoding.well_known_types && is_well_known(message.name()) {
+        well_known_value(descriptors, message, entries, decoding)
+    } else {
+        Ok(value::Value::Map(entries))
+    }
+}
+
+/// Replaces a decoded message of a well-known type, whose fields have been replaced already,
+/// with its natural value, see `Decoding::well_known_types`.
+fn well_known_value(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    mut entries: Vec<(value::Value, value::Value)>,
+    decoding: Decoding,
+) -> error::Result<value::Value> {
+    use crate::value::Value;
+
+    let mut take = |name: &str| {
+        entries
+            .iter_mut()
+            .find(|(k, _)| matches!(*k, Value::String(ref k) if k == name))
+            .map_or(Value::Unit, |(_, v)| mem::replace(v, Value::Unit))
+    };
+    let bad = |v: Value| error::Error::Format {
+        msg: format!("invalid {}: {}", message.name(), v),
+    };
+
+    match message.name() {
+        ".google.protobuf.Timestamp" | ".google.protobuf.Duration" => {
+            let seconds = integer(&take("seconds")).unwrap_or(0);
+            let nanos = integer(&take("nanos")).unwrap_or(0);
+            let string = if message.name() == ".google.protobuf.Timestamp" {
+                timestamp_string(seconds, nanos)
+            } else {
+                duration_string(seconds, nanos)
+            };
+            string.map(Value::String).ok_or_else(|| {
+                bad(Value::Map(vec![
+                    (
+                        Value::String("seconds".to_owned()),
+                        Value::I64(seconds as i64),
+                    ),
+                    (Value::String("nanos".to_owned()), Value::I64(nanos as i64)),
+                ]))
+            })
+        }
+        ".google.protobuf.Struct" => match take("fields") {
+            Value::Unit => Ok(Value::Map(Vec::new())),
+            fields => Ok(fields),
+        },
+        ".google.protobuf.ListValue" => match take("values") {
+            Value::Unit => Ok(Value::Sequence(Vec::new())),
+            values => Ok(values),
+        },
+        ".google.protobuf.FieldMask" => match take("paths") {
+            Value::Unit => Ok(Value::Sequence(Vec::new())),
+            paths => Ok(paths),
+        },
+        ".google.protobuf.Value" => Ok(entries
+            .into_iter()
+            .find(|(_, v)| *v != Value::Unit)
+            .filter(|(k, _)| *k != Value::String("null_value".to_owned()))
+            .map_or(Value::Unit, |(_, v)| v)),
+        ".google.protobuf.Any" => {
+            let (type_url, bytes) = match (take("type_url"), take("value")) {
+                (Value::String(type_url), Value::Bytes(bytes)) => (type_url, bytes),
+                (Value::String(type_url), Value::Unit) => (type_url, Vec::new()),
+                (Value::Unit, Value::Unit) => return Ok(Value::Map(Vec::new())),
+                (type_url, bytes) => {
+                    return Err(bad(Value::Map(vec![
+                        (Value::String("type_url".to_owned()), type_url),
+                        (Value::String("value".to_owned()), bytes),
+                    ])))
+                }
+            };
+            let type_name = format!(".{}", type_url.rsplit('/').next().unwrap_or_default());
+            let any_message = message_by_name(descriptors, &type_name)?;
+            let type_key = Value::String("@type".to_owned());
+            match decode_bytes(descriptors, any_message, &bytes, decoding)? {
+                Value::Map(entries) if !is_well_known(&type_name) => Ok(Value::Map(
+                    iter::once((type_key, Value::String(type_url)))
+                        .chain(entries)
+                        .collect(),
+                )),
+                v => Ok(Value::Map(vec![
+                    (type_key, Value::String(type_url)),
+                    (Value::String("value".to_owned()), v),
+                ])),
+            }
+        }
+        // The wrappers of scalars
+        _ => match (take("value"), message.field_by_name("value")) {
+            (Value::Unit, Some(field)) => {
+                Ok(default_field_value(descriptors, field, &mut Vec::new()))
+            }
+            (v, _) => Ok(v),
+        },
+    }
+}
+
+/// The unknown fields of a message as a map from their numbers to their encoded bytes, or `None`
+/// if there are none, see `Decoding::preserve_unknown_fields`.
+fn unknown_fields_value(unknown: &protobuf::UnknownFields) -> error::Result<Option<value::Value>> {
+    let mut entries = Vec::new();
+    for (number, values) in unknown {
+        let mut bytes = Vec::new();
+        {
+            let mut output = protobuf::CodedOutputStream::vec(&mut bytes);
+            for v in values {
+                output.write_unknown(number, v)?;
+            }
+            output.flush()?;
+        }
+        entries.push((value::Value::U32(number), value::Value::Bytes(bytes)));
+    }
+    if entries.is_empty() {
+        Ok(None)
+    } else {
+        entries.sort();
+        Ok(Some(value::Value::Map(entries)))
+    }
+}
+
+/// Returns the entry type of a map field.
+///
+/// Descriptors don't keep message options, so the map entry types of a descriptor set are marked
+/// by `mark_map_entry` when the descriptors are created.
+fn map_entry<'a>(
+    descriptors: &'a descriptor::Descriptors,
+    field: &'a descriptor::FieldDescriptor,
+) -> Option<&'a descriptor::MessageDescriptor> {
+    match field.field_type(descriptors) {
+        descriptor::FieldType::Message(entry)
+            if field.is_repeated()
+                && descriptors
+                    .message_by_name(&format!("{}{}", entry.name(), MAP_ENTRY_MARKER))
+                    .is_some() =>
+        {
+            Some(entry)
+        }
+        _ => None,
+    }
+}
+
+/// Turns a decoded map entry message into a key and a value, filling in the defaults of fields
+/// that were left out, as proto3 encoders do for zero values.
+fn map_entry_pair(
+    descriptors: &descriptor::Descriptors,
+    entry: &descriptor::MessageDescriptor,
+    decoded: value::Value,
+) -> (value::Value, value::Value) {
+    let mut pair = [value::Value::Unit, value::Value::Unit];
+    if let value::Value::Map(fields) = decoded {
+        for (k, v) in fields {
+            match k {
+                value::Value::String(ref k) if k == "key" => pair[0] = v,
+                value::Value::String(ref k) if k == "value" => pair[1] = v,
+                _ => (),
+            }
+        }
+    }
+    for (i, v) in pair.iter_mut().enumerate() {
+        if *v == value::Value::Unit {
+            if let Some(field) = entry.field_by_number(i as i32 + 1) {
+                *v = default_field_value(descriptors, field, &mut Vec::new());
+            }
+        }
+    }
+    let [k, v] = pair;
+    (k, v)
+}
+
+fn field_value(
+    descriptors: &descriptor::Descriptors,
+    field: &descriptor::FieldDescriptor,
+    v: serde_protobuf::value::Value,
+    decoding: Decoding,
+) -> error::Result<value::Value> {
+    use serde_protobuf::value::Value as Pb;
+
+    Ok(match v {
+        Pb::Bool(v) => value::Value::Bool(v),
+        Pb::I32(v) => value::Value::I32(v),
+        Pb::I64(v) => value::Value::I64(v),
+        Pb::U32(v) => value::Value::U32(v),
+        Pb::U64(v) => value::Value::U64(v),
+        Pb::F32(v) => value::Value::from_f32(v),
+        Pb::F64(v) => value::Value::from_f64(v),
+        Pb::Bytes(v) => value::Value::Bytes(v),
+        Pb::String(v) => value::Value::String(v),
+        Pb::Message(m) => match field.field_type(descriptors) {
+            descriptor::FieldType::Message(d) => message_value(descriptors, d, m, decoding)?,
+            _ => unreachable!("a field with a message value has a message type"),
+        },
+        Pb::Enum(n) => match field.field_type(descriptors) {
+            descriptor::FieldType::Enum(d) => {
+                match (d.value_by_number(n), decoding.unknown_enums) {
+                    (Some(v), _) => match decoding.enum_style {
+                        value::enums::Style::Symbol => value::Value::String(v.name().to_owned()),
+                        value::enums::Style::Qualified => {
+                            value::Value::String(format!("{}.{}", d.name(), v.name()))
+                        }
+                        value::enums::Style::Number => value::Value::I32(n),
+                    },
+                    (None, UnknownEnums::Number) => value::Value::I32(n),
+                    (None, UnknownEnums::String) => value::Value::String(format!("UNKNOWN({})", n)),
+                    (None, UnknownEnums::Error) => {
+                        return Err(error::Error::Format {
+                            msg: format!(
+                                "unknown value {} of enum {} in field {}",
+                                n,
+                                d.name(),
+                                field.name()
+                            ),
+                        })
+                    }
+                }
+            }
+            _ => unreachable!("a field with an enum value has an enum type"),
+        },
+    })
+}
+
+/// Encodes a record as a message of the given type, in the binary wire format.
+///
+/// The record must be a map from field names to values.  `Unit` values are left out, repeated
+/// fields take sequences and are written unpacked (which all parsers accept), map fields take
+/// maps as well as sequences of entries, enums take either
+/// names or numbers, and bytes fields also accept strings and sequences of numbers, since those
+/// are what bytes look like in text formats.
+pub fn encode(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    record: &value::Value,
+) -> error::Result<Vec<u8>> {
+    let entries = match *record {
+        value::Value::Map(ref entries) => entries,
+        ref x => {
+            return Err(error::Error::Format {
+                msg: format!("message {} must be a map, got: {}", message.name(), x),
+            })
+        }
+    };
+
+    let mut result = Vec::new();
+    let mut present = Vec::with_capacity(entries.len());
+    {
+        let mut output = protobuf::CodedOutputStream::vec(&mut result);
+        for (k, v) in entries {
+            let name = match *k {
+                value::Value::String(ref s) => s.clone(),
+                value::Value::Char(c) => c.to_string(),
+                ref k => k.to_string(),
+            };
+            if name == UNKNOWN_FIELDS_KEY {
+                output.write_raw_bytes(&unknown_fields_bytes(message, v)?)?;
+                continue;
+            }
+            let field = message
+                .field_by_name(&name)
+                .ok_or_else(|| error::Error::Format {
+                    msg: format!("message {} has no field {:?}", message.name(), name),
+                })?;
+            if *v != value::Value::Unit {
+                present.push(field.number());
+            }
+
+            match (v, field.is_repeated()) {
+                (value::Value::Unit, _) => (),
+                (value::Value::Sequence(vs), true) => {
+                    for v in vs {
+                        encode_field(descriptors, message, field, v, &mut output)?;
+                    }
+                }
+                (value::Value::Map(pairs), true) if map_entry(descriptors, field).is_some() => {
+                    for (k, v) in pairs {
+                        let entry = value::Value::Map(vec![
+                            (value::Value::String("key".to_owned()), k.clone()),
+                            (value::Value::String("value".to_owned()), v.clone()),
+                        ]);
+                        encode_field(descriptors, message, field, &entry, &mut output)?;
+                    }
+                }
+                (v, false) => encode_field(descriptors, message, field, v, &mut output)?,
+                (v, true) => {
+                    return Err(error::Error::Format {
+                        msg: format!(
+                            "repeated field {} of {} must be a sequence, got: {}",
+                            field.name(),
+                            message.name(),
+                            v
+                        ),
+                    })
+                }
+            }
+        }
+        output.flush()?;
+    }
+
+    for field in message.fields() {
+        if field.field_label() == descriptor::FieldLabel::Required
+            && !present.contains(&field.number())
+        {
+            return Err(error::Error::Format {
+                msg: format!(
+                    "required field {} of {} is missing",
+                    field.name(),
+                    message.name()
+                ),
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+/// The encoded fields of a map like `unknown_fields_value` returns, which are checked to be whole
+/// fields with the numbers they are under.
+fn unknown_fields_bytes(
+    message: &descriptor::MessageDescriptor,
+    v: &value::Value,
+) -> error::Result<Vec<u8>> {
+    let invalid = || error::Error::Format {
+        msg: format!(
+            "{:?} of {} must map field numbers to their encoded bytes, got: {}",
+            UNKNOWN_FIELDS_KEY,
+            message.name(),
+            v
+        ),
+    };
+    let entries = match *v {
+        value::Value::Map(ref entries) => entries,
+        value::Value::Unit => return Ok(Vec::new()),
+        _ => return Err(invalid()),
+    };
+
+    let mut result = Vec::new();
+    for (k, v) in entries {
+        let number = integer(k)
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(invalid)?;
+        let bytes = match *v {
+            value::Value::Bytes(ref bytes) => bytes,
+            _ => return Err(invalid()),
+        };
+        let mut input = protobuf::CodedInputStream::from_bytes(bytes);
+        while !input.eof()? {
+            let (n, wire_type) = input.read_tag_unpack().map_err(|_| invalid())?;
+            if n != number {
+                return Err(invalid());
+            }
+            input.skip_field(wire_type).map_err(|_| invalid())?;
+        }
+        result.extend_from_slice(bytes);
+    }
+    Ok(result)
+}
+
+fn encode_field(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    field: &descriptor::FieldDescriptor,
+    v: &value::Value,
+    output: &mut protobuf::CodedOutputStream,
+) -> error::Result<()> {
+    use crate::value::Value;
+    use serde_protobuf::descriptor::FieldType;
+
+    let number = field.number() as u32;
+    let field_type = field.field_type(descriptors);
+    let mismatch = || error::Error::Format {
+        msg: format!(
+            "field {} of {} can't hold {}",
+            field.name(),
+            message.name(),
+            v
+        ),
+    };
+    let int = |v: &Value| integer(v).ok_or_else(mismatch);
+    let int32 = |v: &Value| int(v).and_then(|n| i32::try_from(n).map_err(|_| mismatch()));
+    let int64 = |v: &Value| int(v).and_then(|n| i64::try_from(n).map_err(|_| mismatch()));
+    let uint32 = |v: &Value| int(v).and_then(|n| u32::try_from(n).map_err(|_| mismatch()));
+    let uint64 = |v: &Value| int(v).and_then(|n| u64::try_from(n).map_err(|_| mismatch()));
+    let float = |v: &Value| float(v).ok_or_else(mismatch);
+
+    match field_type {
+        FieldType::Bool => match *v {
+            Value::Bool(b) => output.write_bool(number, b)?,
+            _ => return Err(mismatch()),
+        },
+        FieldType::Int32 => output.write_int32(number, int32(v)?)?,
+        FieldType::SInt32 => output.write_sint32(number, int32(v)?)?,
+        FieldType::SFixed32 => output.write_sfixed32(number, int32(v)?)?,
+        FieldType::Int64 => output.write_int64(number, int64(v)?)?,
+        FieldType::SInt64 => output.write_sint64(number, int64(v)?)?,
+        FieldType::SFixed64 => output.write_sfixed64(number, int64(v)?)?,
+        FieldType::UInt32 => output.write_uint32(number, uint32(v)?)?,
+        FieldType::Fixed32 => output.write_fixed32(number, uint32(v)?)?,
+        FieldType::UInt64 => output.write_uint64(number, uint64(v)?)?,
+        FieldType::Fixed64 => output.write_fixed64(number, uint64(v)?)?,
+        FieldType::Float => output.write_float(number, float(v)? as f32)?,
+        FieldType::Double => output.write_double(number, float(v)?)?,
+        FieldType::String => match *v {
+            Value::String(ref s) => output.write_string(number, s)?,
+            Value::Char(c) => output.write_string(number, &c.to_string())?,
+            _ => return Err(mismatch()),
+        },
+        FieldType::Bytes => match *v {
+            Value::Bytes(ref b) => output.write_bytes(number, b)?,
+            Value::String(ref s) => output.write_bytes(number, s.as_bytes())?,
+            Value::Sequence(ref seq) => {
+                let bytes = seq
+                    .iter()
+                    .map(|b| integer(b).and_then(|b| u8::try_from(b).ok()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(mismatch)?;
+                output.write_bytes(number, &bytes)?
+            }
+            _ => return Err(mismatch()),
+        },
+        FieldType::Enum(e) => {
+            let n = match *v {
+                Value::String(ref s) => e
+                    .value_by_name(s)
+                    .map(descriptor::EnumValueDescriptor::number)
+                    .ok_or_else(|| error::Error::Format {
+                        msg: format!("enum {} has no value {:?}", e.name(), s),
+                    })?,
+                ref v => int32(v)?,
+            };
+            output.write_enum(number, n)?
+        }
+        FieldType::Message(m) => output.write_bytes(number, &encode(descriptors, m, v)?)?,
+        FieldType::Group | FieldType::UnresolvedEnum(_) | FieldType::UnresolvedMessage(_) => {
+            return Err(error::Error::Format {
+                msg: format!(
+                    "field {} of {} has an unsupported type",
+                    field.name(),
+                    message.name()
+                ),
+            })
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a record can be encoded as a message of the given type, like `encode` does, but
+/// reports all unknown fields, values of the wrong type and missing required fields at once,
+/// each with the path where it is.
+pub fn validate(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    record: &value::Value,
+) -> error::Result<()> {
+    const MAX_PROBLEMS: usize = 10;
+
+    let mut problems = Vec::new();
+    validate_message(descriptors, message, record, &mut Vec::new(), &mut problems);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let mut msg = format!("record doesn't match message {}:", message.name());
+    for (path, problem) in problems.iter().take(MAX_PROBLEMS) {
+        msg.push_str(&format!("\n  {}: {}", path, problem));
+    }
+    if problems.len() > MAX_PROBLEMS {
+        msg.push_str(&format!(
+            "\n  and {} more problems",
+            problems.len() - MAX_PROBLEMS
+        ));
+    }
+    Err(error::Error::Format { msg })
+}
+
+fn validate_message(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    record: &value::Value,
+    path: &mut Vec<value::path::Segment>,
+    problems: &mut Vec<(value::path::Path, String)>,
+) {
+    let entries = match *record {
+        value::Value::Map(ref entries) => entries,
+        ref x => {
+            problems.push((
+                value::path::Path::new(path.clone()),
+                format!("message {} must be a map, got: {}", message.name(), x),
+            ));
+            return;
+        }
+    };
+
+    let mut present = Vec::with_capacity(entries.len());
+    for (k, v) in entries {
+        let name = match *k {
+            value::Value::String(ref s) => s.clone(),
+            value::Value::Char(c) => c.to_string(),
+            ref k => k.to_string(),
+        };
+        let field = message.field_by_name(&name);
+        path.push(value::path::Segment::Key(name.clone()));
+        if name == UNKNOWN_FIELDS_KEY {
+            if let Err(e) = unknown_fields_bytes(message, v) {
+                problems.push((value::path::Path::new(path.clone()), e.to_string()));
+            }
+            path.pop();
+            continue;
+        }
+        match field {
+            Some(field) => {
+                if *v != value::Value::Unit {
+                    present.push(field.number());
+                }
+                validate_values(descriptors, message, field, v, path, problems);
+            }
+            None => problems.push((
+                value::path::Path::new(path.clone()),
+                format!("message {} has no field {:?}", message.name(), name),
+            )),
+        }
+        path.pop();
+    }
+
+    for field in message.fields() {
+        if field.field_label() == descriptor::FieldLabel::Required
+            && !present.contains(&field.number())
+        {
+            path.push(value::path::Segment::Key(field.name().to_owned()));
+            problems.push((
+                value::path::Path::new(path.clone()),
+                format!(
+                    "required field {} of {} is missing",
+                    field.name(),
+                    message.name()
+                ),
+            ));
+            path.pop();
+        }
+    }
+}
+
+/// Checks the value of a field, which holds a sequence or a map if the field is repeated.
+fn validate_values(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    field: &descriptor::FieldDescriptor,
+    v: &value::Value,
+    path: &mut Vec<value::path::Segment>,
+    problems: &mut Vec<(value::path::Path, String)>,
+) {
+    match (v, field.is_repeated()) {
+        (value::Value::Unit, _) => (),
+        (value::Value::Sequence(vs), true) => {
+            for (i, v) in vs.iter().enumerate() {
+                path.push(value::path::Segment::Index(i as i64));
+                validate_value(descriptors, message, field, v, path, problems);
+                path.pop();
+            }
+        }
+        (value::Value::Map(pairs), true) if map_entry(descriptors, field).is_some() => {
+            for (k, v) in pairs {
+                let entry = value::Value::Map(vec![
+                    (value::Value::String("key".to_owned()), k.clone()),
+                    (value::Value::String("value".to_owned()), v.clone()),
+                ]);
+                path.push(value::path::Segment::Key(match *k {
+                    value::Value::String(ref s) => s.clone(),
+                    ref k => k.to_string(),
+                }));
+                validate_value(descriptors, message, field, &entry, path, problems);
+                path.pop();
+            }
+        }
+        (v, false) => validate_value(descriptors, message, field, v, path, problems),
+        (v, true) => problems.push((
+            value::path::Path::new(path.clone()),
+            format!(
+                "repeated field {} of {} must be a sequence, got: {}",
+                field.name(),
+                message.name(),
+                v
+            ),
+        )),
+    }
+}
+
+/// Checks a single value of a field, by encoding it on its own unless it is a message.
+fn validate_value(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    field: &descriptor::FieldDescriptor,
+    v: &value::Value,
+    path: &mut Vec<value::path::Segment>,
+    problems: &mut Vec<(value::path::Path, String)>,
+) {
+    if let descriptor::FieldType::Message(m) = field.field_type(descriptors) {
+        return validate_message(descriptors, m, v, path, problems);
+    }
+
+    let mut scratch = Vec::new();
+    let mut output = protobuf::CodedOutputStream::vec(&mut scratch);
+    if let Err(e) = encode_field(descriptors, message, field, v, &mut output) {
+        let msg = match e {
+            error::Error::Format { msg } => msg,
+            e => e.to_string(),
+        };
+        problems.push((value::path::Path::new(path.clone()), msg));
+    }
+}
+
+/// The value of a number without a fractional part, widened so that any integer type fits.
+fn integer(v: &value::Value) -> Option<i128> {
+    use crate::value::Value;
+
+    match *v {
+        Value::I8(n) => Some(i128::from(n)),
+        Value::I16(n) => Some(i128::from(n)),
+        Value::I32(n) => Some(i128::from(n)),
+        Value::I64(n) => Some(i128::from(n)),
+        Value::U8(n) => Some(i128::from(n)),
+        Value::U16(n) => Some(i128::from(n)),
+        Value::U32(n) => Some(i128::from(n)),
+        Value::U64(n) => Some(i128::from(n)),
+        Value::F32(n) if n.0.fract() == 0.0 => Some(n.0 as i128),
+        Value::F64(n) if n.0.fract() == 0.0 => Some(n.0 as i128),
+        _ => None,
+    }
+}
+
+fn float(v: &value::Value) -> Option<f64> {
+    match *v {
+        value::Value::F32(n) => Some(f64::from(n.0)),
+        value::Value::F64(n) => Some(n.0),
+        ref v => integer(v).map(|n| n as f64),
+    }
+}
+
+impl str::FromStr for UnknownEnums {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "number" => Ok(UnknownEnums::Number),
+            "string" => Ok(UnknownEnums::String),
+            "error" => Ok(UnknownEnums::Error),
+            _ => Err(error::Error::Message(format!(
+                "unknown enum policy {:?}, expected 'number', 'string' or 'error'",
+                s
+            ))),
+        }
+    }
+}
+
+impl value::Source for DefaultSource {
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        Ok(self.0.take())
+    }
+}
+
+fn default_instance<'a>(
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    stack: &mut Vec<&'a str>,
+) -> value::Value {
+    stack.push(message.name());
+    let entries = message
+        .fields()
+        .iter()
+        .map(|field| {
+            let v = if map_entry(descriptors, field).is_some() {
+                value::Value::Map(Vec::new())
+            } else if field.is_repeated() {
+                value::Value::Sequence(Vec::new())
+            } else {
+                default_field_value(descriptors, field, stack)
+            };
+            (value::Value::String(field.name().to_owned()), v)
+        })
+        .collect();
+    stack.pop();
+    value::Value::Map(entries)
+}
+
+fn default_field_value<'a>(
+    descriptors: &'a descriptor::Descriptors,
+    field: &'a descriptor::FieldDescriptor,
+    stack: &mut Vec<&'a str>,
+) -> value::Value {
+    use serde_protobuf::descriptor::FieldType;
+    use serde_protobuf::value::Value as Pb;
+
+    if let Some(v) = field.default_value() {
+        match *v {
+            Pb::Bool(v) => return value::Value::Bool(v),
+            Pb::I32(v) => return value::Value::I32(v),
+            Pb::I64(v) => return value::Value::I64(v),
+            Pb::U32(v) => return value::Value::U32(v),
+            Pb::U64(v) => return value::Value::U64(v),
+            Pb::F32(v) => return value::Value::from_f32(v),
+            Pb::F64(v) => return value::Value::from_f64(v),
+            Pb::Bytes(ref v) => return value::Value::Bytes(v.clone()),
+            Pb::String(ref v) => return value::Value::String(v.clone()),
+            // Enum defaults are not parsed by serde-protobuf, and messages have none
+            Pb::Enum(_) | Pb::Message(_) => (),
+        }
+    }
+
+    match field.field_type(descriptors) {
+        FieldType::Bool => value::Value::Bool(false),
+        FieldType::Int32 | FieldType::SInt32 | FieldType::SFixed32 => value::Value::I32(0),
+        FieldType::Int64 | FieldType::SInt64 | FieldType::SFixed64 => value::Value::I64(0),
+        FieldType::UInt32 | FieldType::Fixed32 => value::Value::U32(0),
+        FieldType::UInt64 | FieldType::Fixed64 => value::Value::U64(0),
+        FieldType::Float => value::Value::from_f32(0.0),
+        FieldType::Double => value::Value::from_f64(0.0),
+        FieldType::String => value::Value::String(String::new()),
+        FieldType::Bytes => value::Value::Bytes(Vec::new()),
+        FieldType::Enum(e) => e.value_by_number(0).map_or(value::Value::Unit, |v| {
+            value::Value::String(v.name().to_owned())
+        }),
+        FieldType::Message(m)
+            if field.field_label() == descriptor::FieldLabel::Required
+                && !stack.contains(&m.name()) =>
+        {
+            default_instance(descriptors, m, stack)
+        }
+        FieldType::Message(_) => value::Value::Map(Vec::new()),
+        FieldType::UnresolvedEnum(_) | FieldType::UnresolvedMessage(_) | FieldType::Group => {
+            value::Value::Unit
+        }
+    }
+}
+
+impl<'a> value::Source for DelimitedSource<'a> {
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.input.eof()? {
+            return Ok(None);
+        }
+
+        let len = self.input.read_raw_varint32()?;
+        let payload = match self.input.read_raw_bytes(len) {
+            Ok(payload) => payload,
+            Err(protobuf::ProtobufError::WireError(protobuf::error::WireError::UnexpectedEof)) => {
+                return Err(error::Error::Format {
+                    msg: format!("truncated delimited message: expected {} bytes", len),
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let value = decode_bytes(self.descriptors, self.message, &payload, self.decoding)?;
+        if self.decoding.int64_as_string {
+            Ok(Some(int64_to_string(value)))
+        } else {
+            Ok(Some(value))
+        }
+    }
+}
+
+impl<'a, R> value::Source for GrpcSource<'a, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        // Each frame is a compressed flag byte followed by a big-endian 32 bit payload length
+        let mut header = [0; 5];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.input.read(&mut header[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => {
+                    return Err(error::Error::Format {
+                        msg: "truncated gRPC frame header".to_owned(),
+                    })
+                }
+                n => filled += n,
+            }
+        }
+
+        if header[0] != 0 {
+            return Err(error::Error::Format {
+                msg: "compressed gRPC frames are not supported".to_owned(),
+            });
+        }
+
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        let mut payload = Vec::new();
+        io::Read::read_to_end(
+            &mut io::Read::take(&mut self.input, u64::from(len)),
+            &mut payload,
+        )?;
+        if payload.len() != len as usize {
+            return Err(error::Error::Format {
+                msg: format!(
+                    "truncated gRPC frame: expected {} bytes, got {}",
+                    len,
+                    payload.len()
+                ),
+            });
+        }
+
+        let value = decode_bytes(self.descriptors, self.message, &payload, self.decoding)?;
+        if self.decoding.int64_as_string {
+            Ok(Some(int64_to_string(value)))
+        } else {
+            Ok(Some(value))
+        }
+    }
+}
+
+impl<W> value::Sink for StructSink<W>
+where
+    W: io::Write,
+{
+    #[inline]
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        let message = match value {
+            value::Value::Map(entries) => encode_struct(&entries)?,
+            x => {
+                return Err(error::Error::Format {
+                    msg: format!("protobuf Struct output requires map records, got: {:?}", x),
+                })
+            }
+        };
+
+        write_delimited(&mut self.0, &message)
+    }
+}
+
+impl<'a, W> value::Sink for Sink<'a, W>
+where
+    W: io::Write,
+{
+    #[inline]
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        validate(self.descriptors, self.message, &value)?;
+        let encoded = encode(self.descriptors, self.message, &value)?;
+        write_delimited(&mut self.w, &encoded)
+    }
+}
+
+impl<'a, W> value::Sink for RouteSink<'a, W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, mut value: value::Value) -> error::Result<()> {
+        let route = match self.route.select(&value) {
+            Some(value::Value::String(s)) => s.clone(),
+            Some(value::Value::Unit) | None => {
+                return Err(error::Error::Format {
+                    msg: format!("record has no value at route {}", self.route),
+                })
+            }
+            Some(v) => v.to_string(),
+        };
+        let message = self
+            .messages
+            .iter()
+            .find(|(r, _)| *r == route)
+            .map(|(_, m)| *m)
+            .ok_or_else(|| error::Error::Format {
+                msg: format!("no message type for route {:?}", route),
+            })?;
+
+        if let [value::path::Segment::Key(ref key)] = *self.route.segments() {
+            if message.field_by_name(key).is_none() {
+                if let value::Value::Map(ref mut entries) = value {
+                    entries.retain(|(k, _)| *k != value::Value::String(key.clone()));
+                }
+            }
+        }
+
+        validate(self.descriptors, message, &value)?;
+        let encoded = encode(self.descriptors, message, &value)?;
+        write_delimited(&mut self.w, &encoded)
+    }
+}
+
+impl<'a, S> value::Sink for JsonSink<'a, S>
+where
+    S: value::Sink,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        // Round trip through the binary format, so that values have the types of their fields
+        validate(self.descriptors, self.message, &value)?;
+        let encoded = encode(self.descriptors, self.message, &value)?;
+        let decoded = decode_bytes(
+            self.descriptors,
+            self.message,
+            &encoded,
+            Decoding::default(),
+        )?;
+        let json = json_message(self.descriptors, self.message, &decoded)?;
+        self.inner.write(json)
+    }
+
+    fn finish(&mut self) -> error::Result<()> {
+        self.inner.finish()
+    }
+}
+
+fn write_delimited<W>(w: &mut W, message: &[u8]) -> error::Result<()>
+where
+    W: io::Write,
+{
+    let mut output = protobuf::CodedOutputStream::new(w);
+    output.write_raw_varint32(message.len() as u32)?;
+    output.write_raw_bytes(message)?;
+    output.flush()?;
+    Ok(())
+}
+
+// Field numbers from google/protobuf/struct.proto
+const STRUCT_FIELDS: u32 = 1;
+const STRUCT_FIELDS_KEY: u32 = 1;
+const STRUCT_FIELDS_VALUE: u32 = 2;
+const VALUE_NULL: u32 = 1;
+const VALUE_NUMBER: u32 = 2;
+const VALUE_STRING: u32 = 3;
+const VALUE_BOOL: u32 = 4;
+const VALUE_STRUCT: u32 = 5;
+const VALUE_LIST: u32 = 6;
+const LIST_VALUES: u32 = 1;
+
+fn encode_struct(entries: &[(value::Value, value::Value)]) -> error::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    {
+        let mut output = protobuf::CodedOutputStream::vec(&mut result);
+        for (k, v) in entries {
+            let mut entry = Vec::new();
+            {
+                let mut entry_output = protobuf::CodedOutputStream::vec(&mut entry);
+                let key = match *k {
+                    value::Value::String(ref s) => s.clone(),
+                    ref k => k.to_string(),
+                };
+                entry_output.write_string(STRUCT_FIELDS_KEY, &key)?;
+                entry_output.write_bytes(STRUCT_FIELDS_VALUE, &encode_struct_value(v)?)?;
+                entry_output.flush()?;
+            }
+            output.write_bytes(STRUCT_FIELDS, &entry)?;
+        }
+        output.flush()?;
+    }
+    Ok(result)
+}
+
+fn encode_struct_value(v: &value::Value) -> error::Result<Vec<u8>> {
+    use crate::value::Value;
+
+    let mut result = Vec::new();
+    {
+        let mut output = protobuf::CodedOutputStream::vec(&mut result);
+        match *v {
+            Value::Unit => output.write_enum(VALUE_NULL, 0)?,
+            Value::Bool(b) => output.write_bool(VALUE_BOOL, b)?,
+            Value::I8(n) => output.write_double(VALUE_NUMBER, f64::from(n))?,
+            Value::I16(n) => output.write_double(VALUE_NUMBER, f64::from(n))?,
+            Value::I32(n) => output.write_double(VALUE_NUMBER, f64::from(n))?,
+            Value::I64(n) => output.write_double(VALUE_NUMBER, n as f64)?,
+            Value::U8(n) => output.write_double(VALUE_NUMBER, f64::from(n))?,
+            Value::U16(n) => output.write_double(VALUE_NUMBER, f64::from(n))?,
+            Value::U32(n) => output.write_double(VALUE_NUMBER, f64::from(n))?,
+            Value::U64(n) => output.write_double(VALUE_NUMBER, n as f64)?,
+            Value::F32(n) => output.write_double(VALUE_NUMBER, f64::from(n.0))?,
+            Value::F64(n) => output.write_double(VALUE_NUMBER, n.0)?,
+            Value::Char(c) => output.write_string(VALUE_STRING, &c.to_string())?,
+            Value::String(ref s) => output.write_string(VALUE_STRING, s)?,
+            Value::Bytes(ref b) => {
+                let numbers = b.iter().map(|&b| Value::U8(b)).collect::<Vec<_>>();
+                output.write_bytes(VALUE_LIST, &encode_list(&numbers)?)?
+            }
+            Value::Sequence(ref seq) => output.write_bytes(VALUE_LIST, &encode_list(seq)?)?,
+            Value::Map(ref entries) => {
+                output.write_bytes(VALUE_STRUCT, &encode_struct(entries)?)?
+            }
+        }
+        output.flush()?;
+    }
+    Ok(result)
+}
+
+fn encode_list(values: &[value::Value]) -> error::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    {
+        let mut output = protobuf::CodedOutputStream::vec(&mut result);
+        for v in values {
+            output.write_bytes(LIST_VALUES, &encode_struct_value(v)?)?;
+        }
+        output.flush()?;
+    }
+    Ok(result)
+}
+
+// Valid ranges from google/protobuf/timestamp.proto and duration.proto
+const TIMESTAMP_MIN_SECONDS: i64 = -62_135_596_800;
+const TIMESTAMP_MAX_SECONDS: i64 = 253_402_300_799;
+const DURATION_MAX_SECONDS: i64 = 315_576_000_000;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Converts a decoded message to its representation in the proto3 JSON mapping.
+fn json_message(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    record: &value::Value,
+) -> error::Result<value::Value> {
+    use crate::value::Value;
+
+    let entries = match *record {
+        Value::Map(ref entries) => entries,
+        ref v => return Err(json_mismatch(message.name(), v)),
+    };
+    let get = |name: &str| {
+        entries
+            .iter()
+            .find(|(k, _)| matches!(*k, Value::String(ref k) if k == name))
+            .map(|(_, v)| v)
+            .filter(|v| **v != Value::Unit)
+    };
+    let int = |name: &str| get(name).and_then(integer).unwrap_or(0);
+    let field = |name: &str| {
+        message
+            .field_by_name(name)
+            .ok_or_else(|| error::Error::Format {
+                msg: format!("well-known type {} has no field {}", message.name(), name),
+            })
+    };
+    // The value of a field, or of its zero value if it isn't set
+    let field_or_zero = |name: &str| {
+        let field = field(name)?;
+        let zero = match field.field_type(descriptors) {
+            descriptor::FieldType::Bool => Value::Bool(false),
+            descriptor::FieldType::String => Value::String(String::new()),
+            descriptor::FieldType::Bytes => Value::Bytes(Vec::new()),
+            descriptor::FieldType::Message(_) => Value::Map(Vec::new()),
+            _ => Value::I32(0),
+        };
+        json_value(descriptors, field, get(name).unwrap_or(&zero))
+    };
+
+    match message.name() {
+        ".google.protobuf.Timestamp" => timestamp_string(int("seconds"), int("nanos"))
+            .map(Value::String)
+            .ok_or_else(|| json_mismatch(message.name(), record)),
+        ".google.protobuf.Duration" => duration_string(int("seconds"), int("nanos"))
+            .map(Value::String)
+            .ok_or_else(|| json_mismatch(message.name(), record)),
+        ".google.protobuf.Struct" => field_or_zero("fields"),
+        ".google.protobuf.ListValue" => field_or_zero("values"),
+        ".google.protobuf.Value" => {
+            match message.fields().iter().find(|f| get(f.name()).is_some()) {
+                Some(f) => json_value(descriptors, f, get(f.name()).unwrap_or(&Value::Unit)),
+                None => Ok(Value::Unit),
+            }
+        }
+        ".google.protobuf.DoubleValue"
+        | ".google.protobuf.FloatValue"
+        | ".google.protobuf.Int64Value"
+        | ".google.protobuf.UInt64Value"
+        | ".google.protobuf.Int32Value"
+        | ".google.protobuf.UInt32Value"
+        | ".google.protobuf.BoolValue"
+        | ".google.protobuf.StringValue"
+        | ".google.protobuf.BytesValue" => field_or_zero("value"),
+        ".google.protobuf.FieldMask" => {
+            let paths = match get("paths") {
+                Some(Value::Sequence(paths)) => paths
+                    .iter()
+                    .map(|p| match *p {
+                        Value::String(ref p) => Ok(json_name(p)),
+                        ref p => Err(json_mismatch(message.name(), p)),
+                    })
+                    .collect::<error::Result<Vec<_>>>()?,
+                _ => Vec::new(),
+            };
+            Ok(Value::String(paths.join(",")))
+        }
+        ".google.protobuf.Any" => {
+            let type_url = match get("type_url") {
+                Some(Value::String(type_url)) => type_url,
+                Some(v) => return Err(json_mismatch(message.name(), v)),
+                None => return Ok(Value::Map(Vec::new())),
+            };
+            let type_name = format!(".{}", type_url.rsplit('/').next().unwrap_or_default());
+            let any_message = message_by_name(descriptors, &type_name)?;
+            let bytes = match get("value") {
+                Some(Value::Bytes(bytes)) => bytes.as_slice(),
+                _ => &[],
+            };
+            let decoded = decode_bytes(descriptors, any_message, bytes, Decoding::default())?;
+            let type_key = Value::String("@type".to_owned());
+            let type_url = Value::String(type_url.clone());
+            match json_message(descriptors, any_message, &decoded)? {
+                Value::Map(entries) if !is_well_known(&type_name) => Ok(Value::Map(
+                    iter::once((type_key, type_url)).chain(entries).collect(),
+                )),
+                v => Ok(Value::Map(vec![
+                    (type_key, type_url),
+                    (Value::String("value".to_owned()), v),
+                ])),
+            }
+        }
+        _ => {
+            let mut fields = message.fields().iter().collect::<Vec<_>>();
+            fields.sort_by_key(|f| f.number());
+            let mut result = Vec::new();
+            for field in fields {
+                let v = match (get(field.name()), map_entry(descriptors, field)) {
+                    (None, _) => continue,
+                    (Some(Value::Map(pairs)), Some(entry)) => {
+                        let value_field = entry
+                            .field_by_name("value")
+                            .ok_or_else(|| json_mismatch(entry.name(), record))?;
+                        Value::Map(
+                            pairs
+                                .iter()
+                                .map(|(k, v)| {
+                                    Ok((json_key(k)?, json_value(descriptors, value_field, v)?))
+                                })
+                                .collect::<error::Result<_>>()?,
+                        )
+                    }
+                    (Some(Value::Sequence(items)), _) if field.is_repeated() => Value::Sequence(
+                        items
+                            .iter()
+                            .map(|v| json_value(descriptors, field, v))
+                            .collect::<error::Result<_>>()?,
+                    ),
+                    (Some(v), _) => json_value(descriptors, field, v)?,
+                };
+                result.push((Value::String(json_name(field.name())), v));
+            }
+            Ok(Value::Map(result))
+        }
+    }
+}
+
+/// Converts a decoded value of a field to its representation in the proto3 JSON mapping.
+fn json_value(
+    descriptors: &descriptor::Descriptors,
+    field: &descriptor::FieldDescriptor,
+    v: &value::Value,
+) -> error::Result<value::Value> {
+    use crate::value::Value;
+    use serde_protobuf::descriptor::FieldType;
+
+    let mismatch = || json_mismatch(field.name(), v);
+    let int = || integer(v).ok_or_else(mismatch);
+    let float = |single: bool| {
+        let x = float(v).ok_or_else(mismatch)?;
+        Ok(if x.is_nan() {
+            Value::String("NaN".to_owned())
+        } else if x.is_infinite() {
+            Value::String(if x > 0.0 { "Infinity" } else { "-Infinity" }.to_owned())
+        } else if single {
+            Value::from_f32(x as f32)
+        } else {
+            Value::from_f64(x)
+        })
+    };
+
+    match field.field_type(descriptors) {
+        FieldType::Message(m) => json_message(descriptors, m, v),
+        FieldType::Enum(e) if e.name() == ".google.protobuf.NullValue" => Ok(Value::Unit),
+        FieldType::Enum(e) => match *v {
+            Value::String(ref s) => Ok(Value::String(s.clone())),
+            ref v => {
+                let n = i32::try_from(integer(v).ok_or_else(mismatch)?).map_err(|_| mismatch())?;
+                Ok(e.value_by_number(n)
+                    .map_or(Value::I32(n), |e| Value::String(e.name().to_owned())))
+            }
+        },
+        FieldType::Bool => match *v {
+            Value::Bool(b) => Ok(Value::Bool(b)),
+            _ => Err(mismatch()),
+        },
+        FieldType::String => match *v {
+            Value::String(ref s) => Ok(Value::String(s.clone())),
+            Value::Char(c) => Ok(Value::String(c.to_string())),
+            _ => Err(mismatch()),
+        },
+        FieldType::Bytes => match *v {
+            Value::Bytes(ref b) => Ok(Value::String(base64(b))),
+            Value::String(ref s) => Ok(Value::String(base64(s.as_bytes()))),
+            _ => Err(mismatch()),
+        },
+        FieldType::Float => float(true),
+        FieldType::Double => float(false),
+        FieldType::Int64
+        | FieldType::SInt64
+        | FieldType::SFixed64
+        | FieldType::UInt64
+        | FieldType::Fixed64 => Ok(Value::String(int()?.to_string())),
+        FieldType::Int32
+        | FieldType::SInt32
+        | FieldType::SFixed32
+        | FieldType::UInt32
+        | FieldType::Fixed32 => Ok(Value::I64(i64::try_from(int()?).map_err(|_| mismatch())?)),
+        FieldType::Group | FieldType::UnresolvedEnum(_) | FieldType::UnresolvedMessage(_) => {
+            Err(error::Error::Format {
+                msg: format!("field {} has an unsupported type", field.name()),
+            })
+        }
+    }
+}
+
+/// Converts a map key to a string, since JSON object keys are strings.
+fn json_key(k: &value::Value) -> error::Result<value::Value> {
+    match *k {
+        value::Value::String(_) => Ok(k.clone()),
+        value::Value::Bool(b) => Ok(value::Value::String(b.to_string())),
+        ref k => integer(k)
+            .map(|n| value::Value::String(n.to_string()))
+            .ok_or_else(|| json_mismatch("map key", k)),
+    }
+}
+
+/// The JSON name of a field, which protoc derives by dropping underscores and capitalizing the
+/// letters after them, like `fooBar` for `foo_bar`.
+fn json_name(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut capitalize = false;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            result.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Formats a `google.protobuf.Timestamp` as an RFC 3339 string in UTC, like
+/// `2006-01-02T15:04:05.500Z`, unless it is out of the range of valid timestamps.
+fn timestamp_string(seconds: i128, nanos: i128) -> Option<String> {
+    if seconds < i128::from(TIMESTAMP_MIN_SECONDS)
+        || seconds > i128::from(TIMESTAMP_MAX_SECONDS)
+        || !(0..1_000_000_000).contains(&nanos)
+    {
+        return None;
+    }
+    let time = value::timestamp::rfc3339(seconds as i64);
+    Some(format!(
+        "{}{}Z",
+        time.trim_end_matches('Z'),
+        json_fraction(nanos as u32)
+    ))
+}
+
+/// Formats a `google.protobuf.Duration` as seconds with an `s` suffix, like `-1.5s`, unless it
+/// is out of the range of valid durations.
+fn duration_string(seconds: i128, nanos: i128) -> Option<String> {
+    if seconds.abs() > i128::from(DURATION_MAX_SECONDS)
+        || nanos.abs() >= 1_000_000_000
+        || (seconds < 0 && nanos > 0)
+        || (seconds > 0 && nanos < 0)
+    {
+        return None;
+    }
+    let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+    Some(format!(
+        "{}{}{}s",
+        sign,
+        seconds.abs(),
+        json_fraction(nanos.unsigned_abs() as u32)
+    ))
+}
+
+/// Formats nanoseconds as a fraction of a second with 0, 3, 6 or 9 digits.
+fn json_fraction(nanos: u32) -> String {
+    if nanos == 0 {
+        String::new()
+    } else if nanos % 1_000_000 == 0 {
+        format!(".{:03}", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        format!(".{:06}", nanos / 1_000)
+    } else {
+        format!(".{:09}", nanos)
+    }
+}
+
+/// Whether the message has a special form in the proto3 JSON mapping that isn't an object with
+/// its fields.
+fn is_well_known(message_name: &str) -> bool {
+    match message_name.strip_prefix(".google.protobuf.") {
+        Some(name) => matches!(
+            name,
+            "Any"
+                | "Duration"
+                | "FieldMask"
+                | "ListValue"
+                | "Struct"
+                | "Timestamp"
+                | "Value"
+                | "DoubleValue"
+                | "FloatValue"
+                | "Int64Value"
+                | "UInt64Value"
+                | "Int32Value"
+                | "UInt32Value"
+                | "BoolValue"
+                | "StringValue"
+                | "BytesValue"
+        ),
+        None => false,
+    }
+}
+
+/// Encodes bytes as standard base64 with padding.
+fn base64(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+fn json_mismatch(name: &str, v: &value::Value) -> error::Error {
+    error::Error::Format {
+        msg: format!("{} can't hold {} in the protobuf JSON mapping", name, v),
+    }
+}
+
+impl<W> fmt::Debug for StructSink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtobufStructSink").finish()
+    }
+}
+
+impl<'a, W> fmt::Debug for Sink<'a, W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtobufSink")
+            .field("message_name", &self.message.name())
+            .finish()
+    }
+}
+
+impl<'a, S> fmt::Debug for JsonSink<'a, S>
+where
+    S: value::Sink + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtobufJsonSink")
+            .field("message_name", &self.message.name())
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<'a, W> fmt::Debug for RouteSink<'a, W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let routes = self
+            .messages
+            .iter()
+            .map(|(r, m)| (r, m.name()))
+            .collect::<Vec<_>>();
+        f.debug_struct("ProtobufRouteSink")
+            .field("route", &self.route)
+            .field("routes", &routes)
+            .finish()
+    }
+}
+
+impl<'a, R> fmt::Debug for GrpcSource<'a, R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GrpcSource")
+            .field("message_name", &self.message.name())
+            .field("decoding", &self.decoding)
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for DelimitedSource<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtobufDelimitedSource")
+            .field("message_name", &self.message.name())
+            .field("decoding", &self.decoding)
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for Source<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtobufSource")
+            .field("message_name", &self.message.name())
+            .field("decoding", &self.decoding)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+    use crate::value::Value;
+    use protobuf::well_known_types;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    #[test]
+    fn test_struct_sink() {
+        let mut out = Vec::new();
+        {
+            let mut sink = struct_sink(&mut out);
+            sink.write(Value::Map(vec![
+                (s("null"), Value::Unit),
+                (s("number"), Value::I32(-3)),
+                (s("string"), s("héllo")),
+                (s("bool"), Value::Bool(false)),
+                (
+                    s("list"),
+                    Value::Sequence(vec![Value::Map(vec![]), Value::Bytes(vec![7])]),
+                ),
+            ]))
+            .unwrap();
+            sink.write(Value::Map(vec![(Value::I32(1), Value::from_f64(0.5))]))
+                .unwrap();
+        }
+
+        let mut input = protobuf::CodedInputStream::from_bytes(&out);
+        let first: well_known_types::Struct = input.read_message().unwrap();
+        let second: well_known_types::Struct = input.read_message().unwrap();
+        assert!(input.eof().unwrap());
+
+        let fields = first.get_fields();
+        assert!(fields["null"].has_null_value());
+        assert_eq!(fields["number"].get_number_value(), -3.0);
+        assert_eq!(fields["string"].get_string_value(), "héllo");
+        assert!(fields["bool"].has_bool_value());
+        assert!(!fields["bool"].get_bool_value());
+        let list = fields["list"].get_list_value().get_values();
+        assert!(list[0].get_struct_value().get_fields().is_empty());
+        assert_eq!(
+            list[1].get_list_value().get_values()[0].get_number_value(),
+            7.0
+        );
+
+        assert_eq!(second.get_fields()["1"].get_number_value(), 0.5);
+    }
+
+    fn greeting_descriptors() -> descriptor::Descriptors {
+        let mut message = descriptor::MessageDescriptor::new(".foo.Greeting");
+        message.add_field(descriptor::FieldDescriptor::new(
+            "text",
+            1,
+            descriptor::FieldLabel::Optional,
+            descriptor::InternalFieldType::String,
+            None,
+        ));
+        let mut descriptors = descriptor::Descriptors::new();
+        descriptors.add_message(message);
+        descriptors.resolve_refs();
+        descriptors
+    }
+
+    #[test]
+    fn test_grpc_source() {
+        use crate::value::Source as _;
+
+        let descriptors = greeting_descriptors();
+        // Two frames: {text: "hi"} and the empty message
+        let input: &[u8] = &[0, 0, 0, 0, 4, 0x0a, 2, b'h', b'i', 0, 0, 0, 0, 0];
+        let mut source = grpc_source(&descriptors, ".foo.Greeting", input).unwrap();
+
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![(s("text"), s("hi"))]))
+        );
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![(s("text"), Value::Unit)]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_grpc_source_errors() {
+        use crate::value::Source as _;
+
+        let descriptors = greeting_descriptors();
+        assert!(grpc_source(&descriptors, ".foo.Missing", &b""[..]).is_err());
+
+        for input in &[
+            &[0, 0, 0][..],
+            &[0, 0, 0, 0, 4, 0x0a][..],
+            &[1, 0, 0, 0, 0][..],
+        ] {
+            let mut source = grpc_source(&descriptors, ".foo.Greeting", *input).unwrap();
+            assert!(source.read().is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_default_source() {
+        use crate::value::Source as _;
+
+        let mut color = descriptor::EnumDescriptor::new(".foo.Color");
+        color.add_value(descriptor::EnumValueDescriptor::new("RED", 0));
+        let mut node = descriptor::MessageDescriptor::new(".foo.Node");
+        for (name, number, label, field_type, default) in vec![
+            (
+                "id",
+                1,
+                descriptor::FieldLabel::Optional,
+                descriptor::InternalFieldType::UInt64,
+                Some(serde_protobuf::value::Value::U64(7)),
+            ),
+            (
+                "color",
+                2,
+                descriptor::FieldLabel::Optional,
+                descriptor::InternalFieldType::UnresolvedEnum(".foo.Color".to_owned()),
+                None,
+            ),
+            (
+                "tags",
+                3,
+                descriptor::FieldLabel::Repeated,
+                descriptor::InternalFieldType::String,
+                None,
+            ),
+            (
+                "greeting",
+                4,
+                descriptor::FieldLabel::Required,
+                descriptor::InternalFieldType::UnresolvedMessage(".foo.Greeting".to_owned()),
+                None,
+            ),
+            (
+                "parent",
+                5,
+                descriptor::FieldLabel::Optional,
+                descriptor::InternalFieldType::UnresolvedMessage(".foo.Node".to_owned()),
+                None,
+            ),
+        ] {
+            node.add_field(descriptor::FieldDescriptor::new(
+                name, number, label, field_type, default,
+            ));
+        }
+        let mut descriptors = greeting_descriptors();
+        descriptors.add_enum(color);
+        descriptors.add_message(node);
+        descriptors.resolve_refs();
+
+        let mut source = default_source(&descriptors, ".foo.Node").unwrap();
+        assert_eq!(
+            source.read().unwrap(),
+            Some(Value::Map(vec![
+                (s("id"), Value::U64(7)),
+                (s("color"), s("RED")),
+                (s("tags"), Value::Sequence(vec![])),
+                (s("greeting"), Value::Map(vec![(s("text"), s(""))])),
+                (s("parent"), Value::Map(vec![])),
+            ]))
+        );
+        assert_eq!(source.read().unwrap(), None);
+        assert!(default_source(&descriptors, ".foo.Missing").is_err());
+    }
+
+    #[test]
+    fn test_descriptor_value() {
+        let mut node = descriptor::MessageDescriptor::new(".foo.Node");
+        node.add_field(descriptor::FieldDescriptor::new(
+            "id",
+            1,
+            descriptor::FieldLabel::Optional,
+            descriptor::InternalFieldType::UInt64,
+            Some(serde_protobuf::value::Value::U64(7)),
+        ));
+        node.add_field(descriptor::FieldDescriptor::new(
+            "greetings",
+            2,
+            descriptor::FieldLabel::Repeated,
+            descriptor::InternalFieldType::UnresolvedMessage(".foo.Greeting".to_owned()),
+            None,
+        ));
+        let mut descriptors = greeting_descriptors();
+        descriptors.add_message(node);
+        descriptors.resolve_refs();
+
+        assert_eq!(
+            descriptor_value(&descriptors, ".foo.Node").unwrap(),
+            Value::Map(vec![
+                (s("name"), s(".foo.Node")),
+                (
+                    s("fields"),
+                    Value::Sequence(vec![
+                        Value::Map(vec![
+                            (s("name"), s("id")),
+                            (s("number"), Value::I32(1)),
+                            (s("label"), s("optional")),
+                            (s("type"), s("uint64")),
+                            (s("default"), Value::U64(7)),
+                        ]),
+                        Value::Map(vec![
+                            (s("name"), s("greetings")),
+                            (s("number"), Value::I32(2)),
+                            (s("label"), s("repeated")),
+                            (s("type"), s("message")),
+                            (s("type_name"), s(".foo.Greeting")),
+                        ]),
+                    ])
+                ),
+            ])
+        );
+        assert!(descriptor_value(&descriptors, ".foo.Missing").is_err());
+    }
+
+    #[test]
+    fn test_unknown_enums() {
+        use crate::value::Source as _;
+
+        let mut color = descriptor::EnumDescriptor::new(".foo.Color");
+        color.add_value(descriptor::EnumValueDescriptor::new("RED", 0));
+        let mut paint = descriptor::MessageDescriptor::new(".foo.Paint");
+        paint.add_field(descriptor::FieldDescriptor::new(
+            "colors",
+            1,
+            descriptor::FieldLabel::Repeated,
+            descriptor::InternalFieldType::UnresolvedEnum(".foo.Color".to_owned()),
+            None,
+        ));
+        let mut descriptors = descriptor::Descriptors::new();
+        descriptors.add_enum(color);
+        descriptors.add_message(paint);
+        descriptors.resolve_refs();
+
+        // colors: [0, 5]
+        let input: &[u8] = &[0x08, 0, 0x08, 5];
+        let read = |unknown_enums| {
+            let stream = protobuf::CodedInputStream::from_bytes(input);
+            source(&descriptors, ".foo.Paint", stream)
+                .unwrap()
+                .decoding(Decoding::default().unknown_enums(unknown_enums))
+                .read()
+        };
+
+        let colors = |second| {
+            Some(Value::Map(vec![(
+                s("colors"),
+                Value::Sequence(vec![s("RED"), second]),
+            )]))
+        };
+        assert_eq!(read(UnknownEnums::Number).unwrap(), colors(Value::I32(5)));
+        assert_eq!(read(UnknownEnums::String).unwrap(), colors(s("UNKNOWN(5)")));
+        assert!(read(UnknownEnums::Error).is_err());
+
+        // The same colors packed, which proto3 does by default, also mixed with unpacked ones
+        for input in &[&[0x0a, 2, 0, 5][..], &[0x0a, 1, 0, 0x08, 5]] {
+            let stream = protobuf::CodedInputStream::from_bytes(input);
+            let packed = source(&descriptors, ".foo.Paint", stream)
+                .unwrap()
+                .decoding(Decoding::default().unknown_enums(UnknownEnums::Number))
+                .read();
+            assert_eq!(packed.unwrap(), colors(Value::I32(5)));
+        }
+        // A truncated packed field
+        let stream = protobuf::CodedInputStream::from_bytes(&[0x0a, 2, 0x80, 0x80]);
+        assert!(source(&descriptors, ".foo.Paint", stream)
+            .unwrap()
+            .read()
+            .is_err());
+
+        let read_styled = |enum_style| {
+            let stream = protobuf::CodedInputStream::from_bytes(input);
+            source(&descriptors, ".foo.Paint", stream)
+                .unwrap()
+                .decoding(
+                    Decoding::default()
+                        .enum_style(enum_style)
+                        .unknown_enums(UnknownEnums::String),
+                )
+                .read()
+        };
+        let styled = |first| {
+            Some(Value::Map(vec![(
+                s("colors"),
+                Value::Sequence(vec![first, s("UNKNOWN(5)")]),
+            )]))
+        };
+        assert_eq!(
+            read_styled(value::enums::Style::Symbol).unwrap(),
+            styled(s("RED"))
+        );
+        assert_eq!(
+            read_styled(value::enums::Style::Qualified).unwrap(),
+            styled(s(".foo.Color.RED"))
+        );
+        assert_eq!(
+            read_styled(value::enums::Style::Number).unwrap(),
+            styled(Value::I32(0))
+        );
+    }
+
+    pub(super) fn record_descriptors() -> descriptor::Descriptors {
+        let mut color = descriptor::EnumDescriptor::new(".foo.Color");
+        color.add_value(descriptor::EnumValueDescriptor::new("RED", 0));
+        color.add_value(descriptor::EnumValueDescriptor::new("BLUE", 1));
+        let mut record = descriptor::MessageDescriptor::new(".foo.Record");
+        for (name, number, label, field_type) in vec![
+            (
+                "id",
+                1,
+                descriptor::FieldLabel::Required,
+                descriptor::InternalFieldType::SInt64,
+            ),
+            (
+                "score",
+                2,
+                descriptor::FieldLabel::Optional,
+                descriptor::InternalFieldType::Float,
+            ),
+            (
+                "colors",
+                3,
+                descriptor::FieldLabel::Repeated,
+                descriptor::InternalFieldType::UnresolvedEnum(".foo.Color".to_owned()),
+            ),
+            (
+                "greeting",
+                4,
+                descriptor::FieldLabel::Optional,
+                descriptor::InternalFieldType::UnresolvedMessage(".foo.Greeting".to_owned()),
+            ),
+            (
+                "data",
+                5,
+                descriptor::FieldLabel::Optional,
+                descriptor::InternalFieldType::Bytes,
+            ),
+            (
+                "count",
+                6,
+                descriptor::FieldLabel::Optional,
+                descriptor::InternalFieldType::UInt32,
+            ),
+        ] {
+            record.add_field(descriptor::FieldDescriptor::new(
+                name, number, label, field_type, None,
+            ));
+        }
+        let mut descriptors = greeting_descriptors();
+        descriptors.add_enum(color);
+        descriptors.add_message(record);
+        descriptors.resolve_refs();
+        descriptors
+    }
+
+    #[test]
+    fn test_encode() {
+        let descriptors = record_descriptors();
+        let message = descriptors.message_by_name(".foo.Record").unwrap();
+
+        let record = Value::Map(vec![
+            (s("id"), Value::I64(-2)),
+            (s("score"), Value::U64(3)),
+            (s("colors"), Value::Sequence(vec![s("BLUE"), Value::I32(0)])),
+            (s("greeting"), Value::Map(vec![(s("text"), s("hi"))])),
+            (
+                s("data"),
+                Value::Sequence(vec![Value::U8(1), Value::U64(2)]),
+            ),
+            (s("count"), Value::Unit),
+        ]);
+        let encoded = encode(&descriptors, message, &record).unwrap();
+
+        let mut input = protobuf::CodedInputStream::from_bytes(&encoded);
+        assert_eq!(
+            decode(&descriptors, message, &mut input, Decoding::default()).unwrap(),
+            Value::Map(vec![
+                (s("id"), Value::I64(-2)),
+                (s("score"), Value::from_f32(3.0)),
+                (s("colors"), Value::Sequence(vec![s("BLUE"), s("RED")])),
+                (s("greeting"), Value::Map(vec![(s("text"), s("hi"))])),
+                (s("data"), Value::Bytes(vec![1, 2])),
+                (s("count"), Value::Unit),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_int64_as_string() {
+        use crate::value::Source as _;
+
+        let descriptors = record_descriptors();
+        let message = descriptors.message_by_name(".foo.Record").unwrap();
+        let record = Value::Map(vec![(s("id"), Value::I64(-2)), (s("count"), Value::U32(3))]);
+        let encoded = encode(&descriptors, message, &record).unwrap();
+
+        let stream = protobuf::CodedInputStream::from_bytes(&encoded);
+        let mut source = source(&descriptors, ".foo.Record", stream)
+            .unwrap()
+            .decoding(Decoding::default().int64_as_string(true));
+        let read = source.read().unwrap().unwrap();
+        let fields = match read {
+            Value::Map(ref entries) => entries,
+            ref v => panic!("not a map: {:?}", v),
+        };
+        assert!(fields.contains(&(s("id"), s("-2"))));
+        assert!(fields.contains(&(s("count"), Value::U32(3))));
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let descriptors = record_descriptors();
+        let message = descriptors.message_by_name(".foo.Record").unwrap();
+        let encode = |entries: Vec<(Value, Value)>| {
+            let mut record = vec![(s("id"), Value::I32(1))];
+            record.extend(entries);
+            encode(&descriptors, message, &Value::Map(record))
+        };
+
+        assert!(encode(vec![]).is_ok());
+        assert!(encode(vec![(s("missing"), Value::I32(1))]).is_err());
+        assert!(encode(vec![(s("count"), Value::I32(-1))]).is_err());
+        assert!(encode(vec![(s("count"), Value::from_f64(1.5))]).is_err());
+        assert!(encode(vec![(s("colors"), s("RED"))]).is_err());
+        assert!(encode(vec![(s("colors"), Value::Sequence(vec![s("GREEN")]))]).is_err());
+        assert!(encode(vec![(s("greeting"), s("hi"))]).is_err());
+        assert!(super::encode(&descriptors, message, &Value::Map(vec![])).is_err());
+        assert!(super::encode(&descriptors, message, &Value::I32(1)).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let descriptors = record_descriptors();
+        let message = descriptors.message_by_name(".foo.Record").unwrap();
+        let record = Value::Map(vec![
+            (s("count"), Value::I32(-1)),
+            (
+                s("colors"),
+                Value::Sequence(vec![s("GREEN"), Value::I32(0)]),
+            ),
+            (
+                s("greeting"),
+                Value::Map(vec![(s("text"), Value::I32(1)), (s("other"), Value::Unit)]),
+            ),
+            (s("bogus"), Value::Unit),
+        ]);
+        match validate(&descriptors, message, &record) {
+            Err(error::Error::Format { msg }) => assert_eq!(
+                msg,
+                "record doesn't match message .foo.Record:
+  .count: field count of .foo.Record can't hold -1
+  .colors[0]: enum .foo.Color has no value \"GREEN\"
+  .greeting.text: field text of .foo.Greeting can't hold 1
+  .greeting.other: message .foo.Greeting has no field \"other\"
+  .bogus: message .foo.Record has no field \"bogus\"
+  .id: required field id of .foo.Record is missing"
+            ),
+            r => panic!("expected an error, got {:?}", r),
+        }
+
+        let record = Value::Map(vec![
+            (s("id"), Value::I32(1)),
+            (s("colors"), Value::Sequence(vec![Value::Unit; 12])),
+        ]);
+        match validate(&descriptors, message, &record) {
+            Err(error::Error::Format { msg }) => {
+                assert_eq!(msg.lines().count(), 12);
+                assert!(msg.ends_with("\n  and 2 more problems"));
+            }
+            r => panic!("expected an error, got {:?}", r),
+        }
+
+        let record = Value::Map(vec![
+            (s("id"), Value::I32(1)),
+            (s("greeting"), Value::Map(vec![(s("text"), s("hi"))])),
+        ]);
+        assert!(validate(&descriptors, message, &record).is_ok());
+    }
+
+    #[test]
+    fn test_sink() {
+        use crate::value::Source as _;
+
+        let descriptors = greeting_descriptors();
+        let mut out = Vec::new();
+        {
+            let mut sink = sink(&descriptors, ".foo.Greeting", &mut out).unwrap();
+            sink.write(Value::Map(vec![(s("text"), s("hi"))])).unwrap();
+            sink.write(Value::Map(vec![])).unwrap();
+            assert!(sink
+                .write(Value::Map(vec![(s("text"), Value::I32(1))]))
+                .is_err());
+        }
+        assert_eq!(out, &[4, 0x0a, 2, b'h', b'i', 0]);
+
+        let mut input = protobuf::CodedInputStream::from_bytes(&out);
+        let len = input.read_raw_varint32().unwrap();
+        let message = input.read_raw_bytes(len).unwrap();
+        let stream = protobuf::CodedInputStream::from_bytes(&message);
+        assert_eq!(
+            source(&descriptors, ".foo.Greeting", stream)
+                .unwrap()
+                .read()
+                .unwrap(),
+            Some(Value::Map(vec![(s("text"), s("hi"))]))
+        );
+
+        assert!(sink(&descriptors, ".foo.Missing", Vec::new()).is_err());
+    }
+
+    fn json(
+        descriptors: &descriptor::Descriptors,
+        message_name: &str,
+        records: Vec<Value>,
+    ) -> String {
+        let mut out = Vec::new();
+        {
+            let mut sink = json_sink(
+                descriptors,
+                message_name,
+                value::json::sink_compact(&mut out),
+            )
+            .unwrap();
+            for record in records {
+                sink.write(record).unwrap();
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_json_sink() {
+        let descriptors = record_descriptors();
+        let record = Value::Map(vec![
+            (s("count"), Value::U32(7)),
+            (s("data"), Value::Bytes(vec![0xfb, 0xff, b'a', b'b'])),
+            (s("greeting"), Value::Map(vec![(s("text"), s("hi"))])),
+            (s("colors"), Value::Sequence(vec![s("BLUE"), Value::I32(5)])),
+            (s("score"), Value::from_f64(f64::NAN)),
+            (s("id"), Value::I64(-2)),
+        ]);
+        assert_eq!(
+            json(&descriptors, ".foo.Record", vec![record]),
+            r#"{"id":"-2","score":"NaN","colors":["BLUE",5],"greeting":{"text":"hi"},"data":"+/9hYg==","count":7}
+"#
+        );
+    }
+
+    /// Descriptors of a `.foo.Event` message with fields of the well-known types.
+    fn event_descriptors() -> descriptor::Descriptors {
+        use serde_protobuf::descriptor::FieldLabel::{Optional, Repeated};
+        use serde_protobuf::descriptor::InternalFieldType as T;
+
+        let message = |name: &str| T::UnresolvedMessage(name.to_owned());
+        let mut descriptors = greeting_descriptors();
+        let mut null_value = descriptor::EnumDescriptor::new(".google.protobuf.NullValue");
+        null_value.add_value(descriptor::EnumValueDescriptor::new("NULL_VALUE", 0));
+        descriptors.add_enum(null_value);
+        for (name, fields) in vec![
+            (
+                ".google.protobuf.Timestamp",
+                vec![
+                    ("seconds", Optional, T::Int64),
+                    ("nanos", Optional, T::Int32),
+                ],
+            ),
+            (
+                ".google.protobuf.Duration",
+                vec![
+                    ("seconds", Optional, T::Int64),
+                    ("nanos", Optional, T::Int32),
+                ],
+            ),
+            (
+                ".google.protobuf.Int64Value",
+                vec![("value", Optional, T::Int64)],
+            ),
+            (
+                ".google.protobuf.Any",
+                vec![
+                    ("type_url", Optional, T::String),
+                    ("value", Optional, T::Bytes),
+                ],
+            ),
+            (
+                ".google.protobuf.FieldMask",
+                vec![("paths", Repeated, T::String)],
+            ),
+            (
+                ".google.protobuf.Struct",
+                vec![(
+                    "fields",
+                    Repeated,
+                    message(".google.protobuf.Struct.FieldsEntry"),
+                )],
+            ),
+            (
+                ".google.protobuf.Struct.FieldsEntry",
+                vec![
+                    ("key", Optional, T::String),
+                    ("value", Optional, message(".google.protobuf.Value")),
+                ],
+            ),
+            (
+                ".google.protobuf.Value",
+                vec![
+                    (
+                        "null_value",
+                        Optional,
+                        T::UnresolvedEnum(".google.protobuf.NullValue".to_owned()),
+                    ),
+                    ("number_value", Optional, T::Double),
+                    ("string_value", Optional, T::String),
+                    ("bool_value", Optional, T::Bool),
+                    ("struct_value", Optional, message(".google.protobuf.Struct")),
+                    (
+                        "list_value",
+                        Optional,
+                        message(".google.protobuf.ListValue"),
+                    ),
+                ],
+            ),
+            (
+                ".google.protobuf.ListValue",
+                vec![("values", Repeated, message(".google.protobuf.Value"))],
+            ),
+            (
+                ".foo.Event",
+                vec![
+                    ("at", Optional, message(".google.protobuf.Timestamp")),
+                    ("took", Optional, message(".google.protobuf.Duration")),
+                    (
+                        "retry_count",
+                        Optional,
+                        message(".google.protobuf.Int64Value"),
+                    ),
+                    (
+                        "update_mask",
+                        Optional,
+                        message(".google.protobuf.FieldMask"),
+                    ),
+                    ("detail", Optional, message(".google.protobuf.Any")),
+                    ("attributes", Optional, message(".google.protobuf.Struct")),
+                ],
+            ),
+        ] {
+            let mut message = descriptor::MessageDescriptor::new(name);
+            for (number, (field, label, field_type)) in fields.into_iter().enumerate() {
+                message.add_field(descriptor::FieldDescriptor::new(
+                    field,
+                    number as i32 + 1,
+                    label,
+                    field_type,
+                    None,
+                ));
+            }
+            descriptors.add_message(message);
+        }
+        mark_map_entry(&mut descriptors, ".google.protobuf.Struct.FieldsEntry");
+        descriptors.resolve_refs();
+        descriptors
+    }
+
+    #[test]
+    fn test_json_sink_well_known_types() {
+        let descriptors = event_descriptors();
+
+        let timestamp = |seconds, nanos| {
+            Value::Map(vec![
+                (s("seconds"), Value::I64(seconds)),
+                (s("nanos"), Value::I32(nanos)),
+            ])
+        };
+        let any = |type_url: &str, value: Vec<u8>| {
+            Value::Map(vec![
+                (s("type_url"), s(type_url)),
+                (s("value"), Value::Bytes(value)),
+            ])
+        };
+        let records = vec![
+            Value::Map(vec![
+                (s("at"), timestamp(1_136_214_245, 500_000_000)),
+                (s("took"), timestamp(-1, -5_000)),
+                (s("retry_count"), Value::Map(vec![])),
+                (
+                    s("update_mask"),
+                    Value::Map(vec![(
+                        s("paths"),
+                        Value::Sequence(vec![s("foo_bar"), s("a.b_c")]),
+                    )]),
+                ),
+                (
+                    s("detail"),
+                    any("type.googleapis.com/google.protobuf.Timestamp", vec![]),
+                ),
+            ]),
+            Value::Map(vec![(
+                s("detail"),
+                any(
+                    "type.googleapis.com/foo.Greeting",
+                    vec![0x0a, 2, b'h', b'i'],
+                ),
+            )]),
+        ];
+        assert_eq!(
+            json(&descriptors, ".foo.Event", records),
+            "{\"at\":\"2006-01-02T15:04:05.500Z\",\"took\":\"-1.000005s\",\"retryCount\":\"0\",\
+             \"updateMask\":\"fooBar,a.bC\",\"detail\":{\"@type\":\
+             \"type.googleapis.com/google.protobuf.Timestamp\",\"value\":\"1970-01-01T00:00:00Z\"}}\n\
+             {\"detail\":{\"@type\":\"type.googleapis.com/foo.Greeting\",\"text\":\"hi\"}}\n"
+        );
+    }
+
+    #[test]
+    fn test_well_known_types() {
+        let descriptors = event_descriptors();
+        let message = message_by_name(&descriptors, ".foo.Event").unwrap();
+        let decode = |record: Value, well_known_types| {
+            let bytes = encode(&descriptors, message, &record).unwrap();
+            let decoding = Decoding {
+                well_known_types,
+                ..Decoding::default()
+            };
+            decode_bytes(&descriptors, message, &bytes, decoding).unwrap()
+        };
+        let field = |v: Value, name: &str| -> Value {
+            match v {
+                Value::Map(entries) => entries
+                    .into_iter()
+                    .find(|(k, _)| *k == s(name))
+                    .map(|(_, v)| v)
+                    .unwrap(),
+                v => panic!("not a message: {}", v),
+            }
+        };
+
+        let record = Value::Map(vec![
+            (
+                s("at"),
+                Value::Map(vec![
+                    (s("seconds"), Value::I64(1_136_214_245)),
+                    (s("nanos"), Value::I32(500_000_000)),
+                ]),
+            ),
+            (s("took"), Value::Map(vec![(s("seconds"), Value::I64(90))])),
+            (
+                s("retry_count"),
+                Value::Map(vec![(s("value"), Value::I64(3))]),
+            ),
+            (
+                s("update_mask"),
+                Value::Map(vec![(s("paths"), Value::Sequence(vec![s("a.b")]))]),
+            ),
+            (
+                s("detail"),
+                Value::Map(vec![
+                    (s("type_url"), s("type.googleapis.com/foo.Greeting")),
+                    (s("value"), Value::Bytes(vec![0x0a, 2, b'h', b'i'])),
+                ]),
+            ),
+            (
+                s("attributes"),
+                Value::Map(vec![(
+                    s("fields"),
+                    Value::Map(vec![
+                        (s("name"), Value::Map(vec![(s("string_value"), s("x"))])),
+                        (
+                            s("tags"),
+                            Value::Map(vec![(
+                                s("list_value"),
+                                Value::Map(vec![(
+                                    s("values"),
+                                    Value::Sequence(vec![
+                                        Value::Map(vec![(
+                                            s("number_value"),
+                                            Value::F64(ordered_float::OrderedFloat(1.5)),
+                                        )]),
+                                        Value::Map(vec![(s("null_value"), s("NULL_VALUE"))]),
+                                    ]),
+                                )]),
+                            )]),
+                        ),
+                    ]),
+                )]),
+            ),
+        ]);
+
+        assert_eq!(
+            decode(record.clone(), true),
+            Value::Map(vec![
+                (s("at"), s("2006-01-02T15:04:05.500Z")),
+                (s("took"), s("90s")),
+                (s("retry_count"), Value::I64(3)),
+                (s("update_mask"), Value::Sequence(vec![s("a.b")])),
+                (
+                    s("detail"),
+                    Value::Map(vec![
+                        (s("@type"), s("type.googleapis.com/foo.Greeting")),
+                        (s("text"), s("hi")),
+                    ]),
+                ),
+                (
+                    s("attributes"),
+                    Value::Map(vec![
+                        (s("name"), s("x")),
+                        (
+                            s("tags"),
+                            Value::Sequence(vec![
+                                Value::F64(ordered_float::OrderedFloat(1.5)),
+                                Value::Unit
+                            ]),
+                        ),
+                    ]),
+                ),
+            ])
+        );
+        // Without the option, the messages are decoded as they are
+        assert_eq!(
+            field(decode(record, false), "at"),
+            Value::Map(vec![
+                (s("seconds"), Value::I64(1_136_214_245)),
+                (s("nanos"), Value::I32(500_000_000)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_delimited_source() {
+        use crate::value::Source as _;
+
+        let descriptors = greeting_descriptors();
+        let read = |input: &[u8]| -> error::Result<Vec<Value>> {
+            let stream = protobuf::CodedInputStream::from_bytes(input);
+            let mut source = delimited_source(&descriptors, ".foo.Greeting", stream)?;
+            let mut result = Vec::new();
+            while let Some(v) = source.read()? {
+                result.push(v);
+            }
+            Ok(result)
+        };
+
+        assert_eq!(
+            read(&[4, 0x0a, 2, b'h', b'i', 0]).unwrap(),
+            vec![
+                Value::Map(vec![(s("text"), s("hi"))]),
+                Value::Map(vec![(s("text"), Value::Unit)]),
+            ]
+        );
+        assert_eq!(read(&[]).unwrap(), vec![]);
+        assert!(read(&[4, 0x0a, 2]).is_err());
+        assert!(delimited_source(
+            &descriptors,
+            ".foo.Missing",
+            protobuf::CodedInputStream::from_bytes(&[])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_route_sink() {
+        let descriptors = record_descriptors();
+        let routes = vec![
+            ("greeting".to_owned(), ".foo.Greeting".to_owned()),
+            ("record".to_owned(), ".foo.Record".to_owned()),
+        ];
+        let mut out = Vec::new();
+        {
+            let route = value::path::Path::parse(".type").unwrap();
+            let mut sink = route_sink(&descriptors, route, &routes, &mut out).unwrap();
+            sink.write(Value::Map(vec![
+                (s("type"), s("greeting")),
+                (s("text"), s("hi")),
+            ]))
+            .unwrap();
+            sink.write(Value::Map(vec![
+                (s("type"), s("record")),
+                (s("id"), Value::I32(1)),
+            ]))
+            .unwrap();
+            assert!(sink
+                .write(Value::Map(vec![(s("type"), s("other"))]))
+                .is_err());
+            assert!(sink.write(Value::Map(vec![])).is_err());
+        }
+        assert_eq!(out, &[4, 0x0a, 2, b'h', b'i', 2, 0x08, 2]);
+
+        let route = value::path::Path::parse(".type").unwrap();
+        let routes = vec![("x".to_owned(), ".foo.Missing".to_owned())];
+        assert!(route_sink(&descriptors, route, &routes, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_groups() {
+        use crate::value::Source as _;
+        use protobuf::descriptor::FieldDescriptorProto;
+        use protobuf::descriptor::FieldDescriptorProto_Label as Label;
+        use protobuf::descriptor::FieldDescriptorProto_Type as Type;
+
+        fn field(name: &str, number: i32, label: Label, field_type: Type) -> FieldDescriptorProto {
+            let mut field = FieldDescriptorProto::new();
+            field.set_name(name.to_owned());
+            field.set_number(number);
+            field.set_label(label);
+            field.set_field_type(field_type);
+            field
+        }
+
+        // message Search { repeated group Result = 1 { optional string url = 2; } optional int32
+        // count = 3; }
+        let mut result = protobuf::descriptor::DescriptorProto::new();
+        result.set_name("Result".to_owned());
+        result
+            .mut_field()
+            .push(field("url", 2, Label::LABEL_OPTIONAL, Type::TYPE_STRING));
+        let mut search = protobuf::descriptor::DescriptorProto::new();
+        search.set_name("Search".to_owned());
+        search.mut_nested_type().push(result);
+        let mut group = field("result", 1, Label::LABEL_REPEATED, Type::TYPE_GROUP);
+        group.set_type_name(".foo.Search.Result".to_owned());
+        search.mut_field().push(group);
+        search
+            .mut_field()
+            .push(field("count", 3, Label::LABEL_OPTIONAL, Type::TYPE_INT32));
+        let mut file = protobuf::descriptor::FileDescriptorProto::new();
+        file.set_package("foo".to_owned());
+        file.mut_message_type().push(search);
+        let mut file_set = protobuf::descriptor::FileDescriptorSet::new();
+        file_set.mut_file().push(file);
+        let descriptors = decoding_descriptors(&file_set);
+
+        let read = |input: &[u8]| {
+            let stream = protobuf::CodedInputStream::from_bytes(input);
+            source(&descriptors, ".foo.Search", stream).unwrap().read()
+        };
+        let results = |urls: &[&str]| {
+            Value::Sequence(
+                urls.iter()
+                    .map(|url| Value::Map(vec![(s("url"), s(url))]))
+                    .collect(),
+            )
+        };
+
+        // result { url: "a" } result { url: "b" } count: 5
+        let input = [
+            0x0b, 0x12, 1, b'a', 0x0c, 0x0b, 0x12, 1, b'b', 0x0c, 0x18, 5,
+        ];
+        assert_eq!(
+            read(&input).unwrap(),
+            Some(Value::Map(vec![
+                (s("result"), results(&["a", "b"])),
+                (s("count"), Value::I32(5)),
+            ]))
+        );
+        // The same message with length-delimited results still decodes
+        let input = [0x0a, 3, 0x12, 1, b'a', 0x18, 5];
+        assert_eq!(
+            read(&input).unwrap(),
+            Some(Value::Map(vec![
+                (s("result"), results(&["a"])),
+                (s("count"), Value::I32(5)),
+            ]))
+        );
+        assert!(read(&[0x0b, 0x12, 1, b'a']).is_err());
+        assert!(read(&[0x0b, 0x12, 1, b'a', 0x14]).is_err());
+    }
+
+    #[test]
+    fn test_map_entry_option() {
+        use crate::value::Source as _;
+        use protobuf::descriptor::FieldDescriptorProto;
+        use protobuf::descriptor::FieldDescriptorProto_Label as Label;
+        use protobuf::descriptor::FieldDescriptorProto_Type as Type;
+
+        fn entry(name: &str, map_entry: bool) -> protobuf::descriptor::DescriptorProto {
+            let mut entry = protobuf::descriptor::DescriptorProto::new();
+            entry.set_name(name.to_owned());
+            for &(name, number, field_type) in &[
+                ("key", 1, Type::TYPE_STRING),
+                ("value", 2, Type::TYPE_INT32),
+            ] {
+                let mut field = FieldDescriptorProto::new();
+                field.set_name(name.to_owned());
+                field.set_number(number);
+                field.set_label(Label::LABEL_OPTIONAL);
+                field.set_field_type(field_type);
+                entry.mut_field().push(field);
+            }
+            entry.mut_options().set_map_entry(map_entry);
+            entry
+        }
+
+        // message Bag { map<string, int32> items = 1; repeated ListEntry list = 2; } where
+        // ListEntry is declared by hand with the same fields as a map entry
+        let mut bag = protobuf::descriptor::DescriptorProto::new();
+        bag.set_name("Bag".to_owned());
+        bag.mut_nested_type().push(entry("ItemsEntry", true));
+        bag.mut_nested_type().push(entry("ListEntry", false));
+        for &(name, number, type_name) in &[
+            ("items", 1, ".foo.Bag.ItemsEntry"),
+            ("list", 2, ".foo.Bag.ListEntry"),
+        ] {
+            let mut field = FieldDescriptorProto::new();
+            field.set_name(name.to_owned());
+            field.set_number(number);
+            field.set_label(Label::LABEL_REPEATED);
+            field.set_field_type(Type::TYPE_MESSAGE);
+            field.set_type_name(type_name.to_owned());
+            bag.mut_field().push(field);
+        }
+        let mut file = protobuf::descriptor::FileDescriptorProto::new();
+        file.set_package("foo".to_owned());
+        file.mut_message_type().push(bag);
+        let mut file_set = protobuf::descriptor::FileDescriptorSet::new();
+        file_set.mut_file().push(file);
+
+        // items { key: "a" value: 3 } list { key: "b" value: 4 }
+        let input = [
+            0x0a, 5, 0x0a, 1, b'a', 0x10, 3, 0x12, 5, 0x0a, 1, b'b', 0x10, 4,
+        ];
+        for descriptors in vec![
+            decoding_descriptors(&file_set),
+            encoding_descriptors(&file_set),
+        ] {
+            let stream = protobuf::CodedInputStream::from_bytes(&input);
+            assert_eq!(
+                source(&descriptors, ".foo.Bag", stream)
+                    .unwrap()
+                    .read()
+                    .unwrap(),
+                Some(Value::Map(vec![
+                    (s("items"), Value::Map(vec![(s("a"), Value::I32(3))])),
+                    (
+                        s("list"),
+                        Value::Sequence(vec![Value::Map(vec![
+                            (s("key"), s("b")),
+                            (s("value"), Value::I32(4)),
+                        ])])
+                    ),
+                ]))
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_refs() {
+        use protobuf::descriptor::FieldDescriptorProto;
+
+        let mut field = FieldDescriptorProto::new();
+        field.set_name("at".to_owned());
+        field.set_number(1);
+        field.set_type_name(".google.protobuf.Timestamp".to_owned());
+        let mut nested = protobuf::descriptor::DescriptorProto::new();
+        nested.set_name("Inner".to_owned());
+        let mut inner = FieldDescriptorProto::new();
+        inner.set_name("inner".to_owned());
+        inner.set_number(2);
+        inner.set_type_name(".foo.Event.Inner".to_owned());
+        let mut event = protobuf::descriptor::DescriptorProto::new();
+        event.set_name("Event".to_owned());
+        event.mut_nested_type().push(nested);
+        event.mut_field().push(inner);
+        let mut file = protobuf::descriptor::FileDescriptorProto::new();
+        file.set_package("foo".to_owned());
+        file.mut_message_type().push(event);
+        let mut file_set = protobuf::descriptor::FileDescriptorSet::new();
+        file_set.mut_file().push(file);
+        check_refs(&file_set).unwrap();
+
+        file_set.mut_file()[0].mut_message_type()[0]
+            .mut_field()
+            .push(field);
+        match check_refs(&file_set) {
+            Err(error::Error::Message(msg)) => assert_eq!(
+                msg,
+                "inconsistent protobuf schema: .foo.Event.at has unknown type \
+                 .google.protobuf.Timestamp"
+            ),
+            r => panic!("expected an error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_reject_unknown_fields() {
+        use crate::value::Source as _;
+
+        let mut point = descriptor::MessageDescriptor::new(".foo.Point");
+        point.add_field(descriptor::FieldDescriptor::new(
+            "x",
+            1,
+            descriptor::FieldLabel::Optional,
+            descriptor::InternalFieldType::Int32,
+            None,
+        ));
+        let mut descriptors = descriptor::Descriptors::new();
+        descriptors.add_message(point);
+        descriptors.resolve_refs();
+
+        // x: 1, and field 7: 2
+        let input: &[u8] = &[0x08, 1, 0x38, 2];
+        let read = |reject| {
+            let stream = protobuf::CodedInputStream::from_bytes(input);
+            source(&descriptors, ".foo.Point", stream)
+                .unwrap()
+                .decoding(Decoding::default().reject_unknown_fields(reject))
+                .read()
+        };
+        assert_eq!(
+            read(false).unwrap(),
+            Some(Value::Map(vec![(s("x"), Value::I32(1))]))
+        );
+        match read(true) {
+            Err(error::Error::Format { msg }) => {
+                assert_eq!(msg, "unknown field 7 in message .foo.Point")
+            }
+            r => panic!("expected an error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_preserve_unknown_fields() {
+        use crate::value::Source as _;
+
+        let mut point = descriptor::MessageDescriptor::new(".foo.Point");
+        point.add_field(descriptor::FieldDescriptor::new(
+            "x",
+            1,
+            descriptor::FieldLabel::Optional,
+            descriptor::InternalFieldType::Int32,
+            None,
+        ));
+        let mut descriptors = descriptor::Descriptors::new();
+        descriptors.add_message(point);
+        descriptors.resolve_refs();
+        let message = message_by_name(&descriptors, ".foo.Point").unwrap();
+
+        // x: 1, field 7: 2, field 3: "hi" and field 7: 3
+        let input: &[u8] = &[0x08, 1, 0x38, 2, 0x1a, 2, b'h', b'i', 0x38, 3];
+        let stream = protobuf::CodedInputStream::from_bytes(input);
+        let record = source(&descriptors, ".foo.Point", stream)
+            .unwrap()
+            .decoding(Decoding::default().preserve_unknown_fields(true))
+            .read()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            record,
+            Value::Map(vec![
+                (s("x"), Value::I32(1)),
+                (
+                    s("@unknown"),
+                    Value::Map(vec![
+                        (Value::U32(3), Value::Bytes(vec![0x1a, 2, b'h', b'i'])),
+                        (Value::U32(7), Value::Bytes(vec![0x38, 2, 0x38, 3])),
+                    ]),
+                ),
+            ])
+        );
+
+        // The unknown fields are encoded again, after the known ones
+        assert_eq!(
+            encode(&descriptors, message, &record).unwrap(),
+            vec![0x08, 1, 0x1a, 2, b'h', b'i', 0x38, 2, 0x38, 3]
+        );
+
+        // Messages without unknown fields don't get the key
+        let stream = protobuf::CodedInputStream::from_bytes(&[0x08, 1]);
+        assert_eq!(
+            source(&descriptors, ".foo.Point", stream)
+                .unwrap()
+                .decoding(Decoding::default().preserve_unknown_fields(true))
+                .read()
+                .unwrap(),
+            Some(Value::Map(vec![(s("x"), Value::I32(1))]))
+        );
+
+        // The bytes must be whole fields with the number they are under
+        for unknown in vec![
+            Value::Map(vec![(Value::U32(7), Value::Bytes(vec![0x08, 1]))]),
+            Value::Map(vec![(Value::U32(7), Value::Bytes(vec![0x38]))]),
+            Value::Map(vec![(Value::U32(7), s("x"))]),
+            s("x"),
+        ] {
+            let record = Value::Map(vec![(s("@unknown"), unknown)]);
+            assert!(encode(&descriptors, message, &record).is_err());
+            assert!(validate(&descriptors, message, &record).is_err());
+        }
+    }
+
+    #[test]
+    fn test_map_fields() {
+        use crate::value::Source as _;
+
+        let mut entry = descriptor::MessageDescriptor::new(".foo.Counts.WordCountsEntry");
+        entry.add_field(descriptor::FieldDescriptor::new(
+            "key",
+            1,
+            descriptor::FieldLabel::Optional,
+            descriptor::InternalFieldType::String,
+            None,
+        ));
+        entry.add_field(descriptor::FieldDescriptor::new(
+            "value",
+            2,
+            descriptor::FieldLabel::Optional,
+            descriptor::InternalFieldType::Int32,
+            None,
+        ));
+        let mut counts = descriptor::MessageDescriptor::new(".foo.Counts");
+        counts.add_field(descriptor::FieldDescriptor::new(
+            "word_counts",
+            1,
+            descriptor::FieldLabel::Repeated,
+            descriptor::InternalFieldType::UnresolvedMessage(
+                ".foo.Counts.WordCountsEntry".to_owned(),
+            ),
+            None,
+        ));
+        let mut descriptors = descriptor::Descriptors::new();
+        descriptors.add_message(entry);
+        descriptors.add_message(counts);
+        mark_map_entry(&mut descriptors, ".foo.Counts.WordCountsEntry");
+        descriptors.resolve_refs();
+        let message = descriptors.message_by_name(".foo.Counts").unwrap();
+
+        let mut encoded = encode(
+            &descriptors,
+            message,
+            &Value::Map(vec![(
+                s("word_counts"),
+                Value::Map(vec![(s("a"), Value::I32(3))]),
+            )]),
+        )
+        .unwrap();
+        // An entry for "b" without a value, which means zero
+        encoded.extend(&[0x0a, 3, 0x0a, 1, b'b']);
+
+        let mut input = protobuf::CodedInputStream::from_bytes(&encoded);
+        assert_eq!(
+            decode(&descriptors, message, &mut input, Decoding::default()).unwrap(),
+            Value::Map(vec![(
+                s("word_counts"),
+                Value::Map(vec![(s("a"), Value::I32(3)), (s("b"), Value::I32(0))]),
+            )])
+        );
+
+        assert_eq!(
+            default_source(&descriptors, ".foo.Counts")
+                .unwrap()
+                .read()
+                .unwrap(),
+            Some(Value::Map(vec![(s("word_counts"), Value::Map(vec![]))]))
+        );
+    }
+
+    #[test]
+    fn test_struct_sink_requires_maps() {
+        let mut out = Vec::new();
+        assert!(struct_sink(&mut out).write(Value::I32(1)).is_err());
     }
 }
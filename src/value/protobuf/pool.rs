@@ -0,0 +1,694 @@
+//! A pool of protobuf descriptors that descriptor sets can be added to one at a time, and that
+//! only builds the descriptors of the types that a message uses when they are asked for, since
+//! resolving all types of schema sets with thousands of messages up front is slow.
+//!
+//! The pool also keeps the services of the descriptor sets, which `Descriptors` leaves out, so
+//! that the message types of RPC methods can be looked up.
+
+use std::collections;
+use std::fmt;
+
+use crate::error;
+use protobuf::descriptor::{DescriptorProto, EnumDescriptorProto, FileDescriptorProto};
+use protobuf::descriptor::{FieldDescriptorProto, FileDescriptorSet, ServiceDescriptorProto};
+use serde_protobuf::descriptor;
+
+/// Descriptor sets that have been added, with an index of where each type is defined in them and
+/// the extensions of each message type.
+#[derive(Clone, Default)]
+pub struct DescriptorPool {
+    files: Vec<FileDescriptorProto>,
+    types: collections::HashMap<String, Location>,
+    extensions: collections::HashMap<String, Vec<Extension>>,
+    services: Vec<ServiceDescriptor>,
+}
+
+/// A service, with the full name it is referred to by, like `.foo.Greeter`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceDescriptor {
+    name: String,
+    methods: Vec<MethodDescriptor>,
+}
+
+/// An RPC method of a service, with the message types that it takes and returns as full names
+/// like `.foo.HelloRequest`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MethodDescriptor {
+    service: String,
+    name: String,
+    input_type: String,
+    output_type: String,
+    client_streaming: bool,
+    server_streaming: bool,
+}
+
+/// An extension field of a message type, with the full name that it is referred to by, like
+/// `.foo.note` for `extend Bar { string note = 100; }` in package `foo`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extension {
+    pub name: String,
+    pub field: FieldDescriptorProto,
+}
+
+/// Where a type is defined: the index of its file, the indices of the messages it is nested in,
+/// and for enums, its index in the innermost of those.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Location {
+    Message {
+        file: usize,
+        path: Vec<usize>,
+    },
+    Enum {
+        file: usize,
+        path: Vec<usize>,
+        index: usize,
+    },
+}
+
+/// The types and extensions of the files of a descriptor set, before they are added to a pool.
+#[derive(Default)]
+struct Index {
+    types: Vec<(String, Location)>,
+    extensions: Vec<(String, Extension)>,
+    services: Vec<ServiceDescriptor>,
+}
+
+impl DescriptorPool {
+    pub fn new() -> Self {
+        DescriptorPool::default()
+    }
+
+    /// Creates a pool with the files of a single descriptor set.
+    pub fn from_file_set(file_set: &FileDescriptorSet) -> error::Result<Self> {
+        let mut pool = DescriptorPool::new();
+        pool.add_file_set(file_set)?;
+        Ok(pool)
+    }
+
+    /// Adds the files of a descriptor set, which may also contain files that have been added
+    /// already, like common imports.
+    ///
+    /// Fails without adding anything if a file differs from the one with the same name that was
+    /// added already, if a type or service is defined again, or if an extension has a number that
+    /// another extension of the same message has.
+    pub fn add_file_set(&mut self, file_set: &FileDescriptorSet) -> error::Result<()> {
+        let mut files = Vec::new();
+        let mut index = Index::default();
+        for file in file_set.get_file() {
+            let added = self
+                .files
+                .iter()
+                .chain(files.iter())
+                .find(|f| f.get_name() == file.get_name());
+            match added {
+                Some(added) if added == file => continue,
+                Some(_) => {
+                    return Err(error::Error::Message(format!(
+                        "proto file {:?} was added twice with different contents",
+                        file.get_name()
+                    )))
+                }
+                None => (),
+            }
+            index_file(self.files.len() + files.len(), file, &mut index);
+            files.push(file.clone());
+        }
+
+        let mut defined_by = collections::HashMap::new();
+        for (name, location) in &index.types {
+            let file = match *location {
+                Location::Message { file, .. } | Location::Enum { file, .. } => file,
+            };
+            let previous = self
+                .types
+                .get(name)
+                .map(|l| self.file_name(l))
+                .or_else(|| defined_by.get(name.as_str()).copied());
+            if let Some(previous) = previous {
+                return Err(error::Error::Message(format!(
+                    "type {} is defined in both {:?} and {:?}",
+                    name,
+                    previous,
+                    files[file - self.files.len()].get_name()
+                )));
+            }
+            defined_by.insert(name.as_str(), files[file - self.files.len()].get_name());
+        }
+        for (i, service) in index.services.iter().enumerate() {
+            if self.service_by_name(&service.name).is_some()
+                || index.services[..i].iter().any(|s| s.name == service.name)
+            {
+                return Err(error::Error::Message(format!(
+                    "service {} is defined twice",
+                    service.name
+                )));
+            }
+        }
+        for (i, (extendee, extension)) in index.extensions.iter().enumerate() {
+            let number = extension.field.get_number();
+            let previous = self
+                .extension(extendee, number)
+                .or_else(|| {
+                    index.extensions[..i]
+                        .iter()
+                        .find(|(e, x)| e == extendee && x.field.get_number() == number)
+                        .map(|(_, x)| x)
+                })
+                .map(|x| x.name.as_str());
+            if let Some(previous) = previous {
+                return Err(error::Error::Message(format!(
+                    "extensions {} and {} of {} both have number {}",
+                    previous, extension.name, extendee, number
+                )));
+            }
+        }
+
+        self.files.extend(files);
+        self.types.extend(index.types);
+        for (extendee, extension) in index.extensions {
+            self.extensions
+                .entry(extendee)
+                .or_insert_with(Vec::new)
+                .push(extension);
+        }
+        self.services.extend(index.services);
+        Ok(())
+    }
+
+    /// The index of a message type in its file, followed by its index in each message that it
+    /// is nested in, which is how the Confluent wire format for Kafka identifies message types.
+    pub fn message_indexes(&self, message_name: &str) -> Option<&[usize]> {
+        match self.types.get(message_name) {
+            Some(Location::Message { ref path, .. }) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// All services that have been added.
+    pub fn services(&self) -> &[ServiceDescriptor] {
+        &self.services
+    }
+
+    /// Looks up a service by its full name like `.foo.Greeter`; `foo.Greeter` is also accepted.
+    pub fn service_by_name(&self, name: &str) -> Option<&ServiceDescriptor> {
+        let name = name.trim_start_matches('.');
+        self.services.iter().find(|s| &s.name[1..] == name)
+    }
+
+    /// Looks up a method by its gRPC path like `/foo.Greeter/Hello`; `foo.Greeter.Hello` is also
+    /// accepted.
+    pub fn method_by_name(&self, name: &str) -> Option<&MethodDescriptor> {
+        let name = name.trim_start_matches(|c| c == '/' || c == '.');
+        let (service, method) = name.rsplit_once(|c| c == '/' || c == '.')?;
+        self.service_by_name(service)?.method_by_name(method)
+    }
+
+    /// Builds the descriptors for decoding the requests of a method, see `descriptors`.
+    pub fn input_descriptors(
+        &self,
+        method: &MethodDescriptor,
+    ) -> error::Result<descriptor::Descriptors> {
+        self.descriptors(method.input_type())
+    }
+
+    /// Builds the descriptors for decoding the responses of a method, see `descriptors`.
+    pub fn output_descriptors(
+        &self,
+        method: &MethodDescriptor,
+    ) -> error::Result<descriptor::Descriptors> {
+        self.descriptors(method.output_type())
+    }
+
+    /// The extension of a message type with a field number, if one has been added.
+    pub fn extension(&self, extendee: &str, number: i32) -> Option<&Extension> {
+        self.extensions(extendee)
+            .iter()
+            .find(|x| x.field.get_number() == number)
+    }
+
+    /// All extensions of a message type that have been added.
+    pub fn extensions(&self, extendee: &str) -> &[Extension] {
+        self.extensions.get(extendee).map_or(&[], Vec::as_slice)
+    }
+
+    /// Builds the descriptors for decoding messages of a type, which describe it and all types
+    /// that it refers to, also through other types, but nothing else.
+    ///
+    /// Like with `protobuf::decoding_descriptors`, groups are described as nested messages, and
+    /// extensions are described as fields with names like `[foo.note]`.  Types that aren't in the
+    /// pool are left unresolved, like `Descriptors` does, so that messages only fail to decode if
+    /// they use them.
+    pub fn descriptors(&self, message_name: &str) -> error::Result<descriptor::Descriptors> {
+        match self.types.get_key_value(message_name) {
+            Some((name, Location::Message { .. })) => Ok(self.build(vec![name])),
+            _ => Err(error::Error::from(
+                serde_protobuf::error::Error::UnknownMessage {
+                    name: message_name.to_owned(),
+                },
+            )),
+        }
+    }
+
+    /// Builds the descriptors for decoding messages of all types in the pool, like `descriptors`,
+    /// for when the types of messages are only known while decoding, like in `Any` fields.
+    pub fn all_descriptors(&self) -> descriptor::Descriptors {
+        self.build(self.types.keys().map(String::as_str).collect())
+    }
+
+    fn build<'a>(&'a self, mut pending: Vec<&'a str>) -> descriptor::Descriptors {
+        use protobuf::descriptor::FieldDescriptorProto_Type as Type;
+
+        let mut descriptors = descriptor::Descriptors::new();
+        let mut seen = pending.iter().copied().collect::<collections::HashSet<_>>();
+        while let Some(name) = pending.pop() {
+            let parent = &name[..name.rfind('.').unwrap_or(0)];
+            match self.types[name] {
+                Location::Message { file, ref path } => {
+                    let proto = self.message(file, path);
+                    if proto.get_options().get_map_entry() {
+                        super::mark_map_entry(&mut descriptors, name);
+                    }
+                    let fields = proto.get_field().iter().cloned();
+                    let extensions = self.extensions(name).iter().map(|x| {
+                        let mut field = x.field.clone();
+                        field.set_name(format!("[{}]", &x.name[1..]));
+                        field
+                    });
+
+                    let mut message = descriptor::MessageDescriptor::new(name);
+                    for mut field in fields.chain(extensions) {
+                        if field.get_field_type() == Type::TYPE_GROUP {
+                            field.set_field_type(Type::TYPE_MESSAGE);
+                        }
+                        if let Some((type_name, _)) =
+                            self.types.get_key_value(field.get_type_name())
+                        {
+                            if seen.insert(type_name) {
+                                pending.push(type_name);
+                            }
+                        }
+                        message.add_field(descriptor::FieldDescriptor::from_proto(&field));
+                    }
+                    descriptors.add_message(message);
+                }
+                Location::Enum {
+                    file,
+                    ref path,
+                    index,
+                } => {
+                    let proto = self.enum_proto(file, path, index);
+                    descriptors.add_enum(descriptor::EnumDescriptor::from_proto(parent, proto));
+                }
+            }
+        }
+        descriptors.resolve_refs();
+        descriptors
+    }
+
+    fn message(&self, file: usize, path: &[usize]) -> &DescriptorProto {
+        let mut message = &self.files[file].get_message_type()[path[0]];
+        for &i in &path[1..] {
+            message = &message.get_nested_type()[i];
+        }
+        message
+    }
+
+    fn enum_proto(&self, file: usize, path: &[usize], index: usize) -> &EnumDescriptorProto {
+        if path.is_empty() {
+            &self.files[file].get_enum_type()[index]
+        } else {
+            &self.message(file, path).get_enum_type()[index]
+        }
+    }
+
+    fn file_name(&self, location: &Location) -> &str {
+        match *location {
+            Location::Message { file, .. } | Location::Enum { file, .. } => {
+                self.files[file].get_name()
+            }
+        }
+    }
+}
+
+fn index_file(file_index: usize, file: &FileDescriptorProto, index: &mut Index) {
+    let prefix = if file.get_package().is_empty() {
+        String::new()
+    } else {
+        format!(".{}", file.get_package())
+    };
+    for (i, e) in file.get_enum_type().iter().enumerate() {
+        index.types.push((
+            format!("{}.{}", prefix, e.get_name()),
+            Location::Enum {
+                file: file_index,
+                path: Vec::new(),
+                index: i,
+            },
+        ));
+    }
+    for (i, message) in file.get_message_type().iter().enumerate() {
+        index_message(file_index, &prefix, vec![i], message, index);
+    }
+    index_extensions(&prefix, file.get_extension(), index);
+    for service in file.get_service() {
+        index
+            .services
+            .push(ServiceDescriptor::from_proto(&prefix, service));
+    }
+}
+
+fn index_message(
+    file_index: usize,
+    prefix: &str,
+    path: Vec<usize>,
+    message: &DescriptorProto,
+    index: &mut Index,
+) {
+    let name = format!("{}.{}", prefix, message.get_name());
+    for (i, e) in message.get_enum_type().iter().enumerate() {
+        index.types.push((
+            format!("{}.{}", name, e.get_name()),
+            Location::Enum {
+                file: file_index,
+                path: path.clone(),
+                index: i,
+            },
+        ));
+    }
+    for (i, nested) in message.get_nested_type().iter().enumerate() {
+        let mut nested_path = path.clone();
+        nested_path.push(i);
+        index_message(file_index, &name, nested_path, nested, index);
+    }
+    index_extensions(&name, message.get_extension(), index);
+    index.types.push((
+        name,
+        Location::Message {
+            file: file_index,
+            path,
+        },
+    ));
+}
+
+fn index_extensions(scope: &str, fields: &[FieldDescriptorProto], index: &mut Index) {
+    for field in fields {
+        index.extensions.push((
+            field.get_extendee().to_owned(),
+            Extension {
+                name: format!("{}.{}", scope, field.get_name()),
+                field: field.clone(),
+            },
+        ));
+    }
+}
+
+impl ServiceDescriptor {
+    fn from_proto(prefix: &str, proto: &ServiceDescriptorProto) -> Self {
+        let name = format!("{}.{}", prefix, proto.get_name());
+        let methods = proto
+            .get_method()
+            .iter()
+            .map(|m| MethodDescriptor {
+                service: name.clone(),
+                name: m.get_name().to_owned(),
+                input_type: m.get_input_type().to_owned(),
+                output_type: m.get_output_type().to_owned(),
+                client_streaming: m.get_client_streaming(),
+                server_streaming: m.get_server_streaming(),
+            })
+            .collect();
+        ServiceDescriptor { name, methods }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn methods(&self) -> &[MethodDescriptor] {
+        &self.methods
+    }
+
+    pub fn method_by_name(&self, name: &str) -> Option<&MethodDescriptor> {
+        self.methods.iter().find(|m| m.name == name)
+    }
+}
+
+impl MethodDescriptor {
+    /// The full name of the service of this method, like `.foo.Greeter`.
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn input_type(&self) -> &str {
+        &self.input_type
+    }
+
+    pub fn output_type(&self) -> &str {
+        &self.output_type
+    }
+
+    pub fn client_streaming(&self) -> bool {
+        self.client_streaming
+    }
+
+    pub fn server_streaming(&self) -> bool {
+        self.server_streaming
+    }
+
+    /// The gRPC path of this method, like `/foo.Greeter/Hello`.
+    pub fn path(&self) -> String {
+        format!("/{}/{}", &self.service[1..], self.name)
+    }
+}
+
+impl fmt::Debug for DescriptorPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DescriptorPool")
+            .field(
+                "files",
+                &self.files.iter().map(|f| f.get_name()).collect::<Vec<_>>(),
+            )
+            .field("types", &self.types.len())
+            .field("extensions", &self.extensions.len())
+            .field("services", &self.services.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protobuf::descriptor::FieldDescriptorProto_Label as Label;
+    use protobuf::descriptor::FieldDescriptorProto_Type as Type;
+    use serde_protobuf::descriptor::FieldType;
+
+    fn field(name: &str, number: i32, field_type: Type, type_name: &str) -> FieldDescriptorProto {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_owned());
+        field.set_number(number);
+        field.set_label(Label::LABEL_OPTIONAL);
+        field.set_field_type(field_type);
+        if !type_name.is_empty() {
+            field.set_type_name(type_name.to_owned());
+        }
+        field
+    }
+
+    fn message(name: &str, fields: Vec<FieldDescriptorProto>) -> DescriptorProto {
+        let mut message = DescriptorProto::new();
+        message.set_name(name.to_owned());
+        message.set_field(fields.into());
+        message
+    }
+
+    fn file(name: &str, package: &str, messages: Vec<DescriptorProto>) -> FileDescriptorProto {
+        let mut file = FileDescriptorProto::new();
+        file.set_name(name.to_owned());
+        file.set_package(package.to_owned());
+        file.set_message_type(messages.into());
+        file
+    }
+
+    fn file_set(files: Vec<FileDescriptorProto>) -> FileDescriptorSet {
+        let mut file_set = FileDescriptorSet::new();
+        file_set.set_file(files.into());
+        file_set
+    }
+
+    /// `foo.proto` with `.foo.Outer`, which refers to a nested message and enum and has an
+    /// extension range, and `.foo.Unrelated`, which refers to a type that isn't defined.
+    fn foo() -> FileDescriptorProto {
+        let mut kind = EnumDescriptorProto::new();
+        kind.set_name("Kind".to_owned());
+        let mut outer = message(
+            "Outer",
+            vec![
+                field("inner", 1, Type::TYPE_MESSAGE, ".foo.Outer.Inner"),
+                field("kind", 2, Type::TYPE_ENUM, ".foo.Outer.Kind"),
+                field("old", 3, Type::TYPE_GROUP, ".foo.Outer.Old"),
+            ],
+        );
+        outer.set_nested_type(
+            vec![
+                message("Inner", vec![field("x", 1, Type::TYPE_INT32, "")]),
+                message("Old", vec![field("y", 4, Type::TYPE_INT32, "")]),
+            ]
+            .into(),
+        );
+        outer.set_enum_type(vec![kind].into());
+        let unrelated = message(
+            "Unrelated",
+            vec![field("missing", 1, Type::TYPE_MESSAGE, ".bar.Missing")],
+        );
+        file("foo.proto", "foo", vec![outer, unrelated])
+    }
+
+    /// `note.proto`, which extends `.foo.Outer` with a `.note.note` string.
+    fn note(number: i32) -> FileDescriptorProto {
+        let mut note = field("note", number, Type::TYPE_STRING, "");
+        note.set_extendee(".foo.Outer".to_owned());
+        let mut file = file("note.proto", "note", vec![]);
+        file.set_extension(vec![note].into());
+        file
+    }
+
+    #[test]
+    fn test_descriptors() {
+        let mut pool = DescriptorPool::from_file_set(&file_set(vec![foo()])).unwrap();
+        pool.add_file_set(&file_set(vec![foo(), note(100)]))
+            .unwrap();
+
+        let descriptors = pool.descriptors(".foo.Outer").unwrap();
+        assert!(descriptors.message_by_name(".foo.Outer.Inner").is_some());
+        assert!(descriptors.enum_by_name(".foo.Outer.Kind").is_some());
+        assert!(descriptors.message_by_name(".foo.Unrelated").is_none());
+
+        let outer = descriptors.message_by_name(".foo.Outer").unwrap();
+        match outer.field_by_name("old").unwrap().field_type(&descriptors) {
+            FieldType::Message(m) => assert_eq!(m.name(), ".foo.Outer.Old"),
+            t => panic!("expected a message, got {:?}", t),
+        }
+        let note = outer.field_by_number(100).unwrap();
+        assert_eq!(note.name(), "[note.note]");
+
+        assert_eq!(
+            pool.extension(".foo.Outer", 100).unwrap().name,
+            ".note.note"
+        );
+        assert!(pool.extension(".foo.Outer", 101).is_none());
+        assert_eq!(pool.extensions(".foo.Outer.Inner"), &[]);
+
+        assert_eq!(pool.message_indexes(".foo.Outer"), Some(&[0][..]));
+        assert_eq!(pool.message_indexes(".foo.Outer.Old"), Some(&[0, 1][..]));
+        assert_eq!(pool.message_indexes(".foo.Unrelated"), Some(&[1][..]));
+        assert_eq!(pool.message_indexes(".foo.Outer.Kind"), None);
+
+        // Missing types are left unresolved
+        let descriptors = pool.descriptors(".foo.Unrelated").unwrap();
+        let unrelated = descriptors.message_by_name(".foo.Unrelated").unwrap();
+        match unrelated
+            .field_by_name("missing")
+            .unwrap()
+            .field_type(&descriptors)
+        {
+            FieldType::UnresolvedMessage(name) => assert_eq!(name, ".bar.Missing"),
+            t => panic!("expected an unresolved message, got {:?}", t),
+        }
+
+        assert!(pool.descriptors(".foo.Outer.Kind").is_err());
+        assert!(pool.descriptors(".foo.Nope").is_err());
+        assert!(pool
+            .all_descriptors()
+            .message_by_name(".foo.Unrelated")
+            .is_some());
+    }
+
+    #[test]
+    fn test_services() {
+        let mut method = protobuf::descriptor::MethodDescriptorProto::new();
+        method.set_name("Get".to_owned());
+        method.set_input_type(".foo.Outer.Inner".to_owned());
+        method.set_output_type(".foo.Outer".to_owned());
+        method.set_server_streaming(true);
+        let mut service = ServiceDescriptorProto::new();
+        service.set_name("Store".to_owned());
+        service.set_method(vec![method].into());
+        let mut store = file("store.proto", "foo", vec![]);
+        store.set_service(vec![service].into());
+
+        let mut pool = DescriptorPool::from_file_set(&file_set(vec![foo(), store])).unwrap();
+        assert_eq!(pool.services().len(), 1);
+        let service = pool.service_by_name("foo.Store").unwrap();
+        assert_eq!(service.name(), ".foo.Store");
+        assert_eq!(pool.service_by_name(".foo.Store"), Some(service));
+
+        let method = pool.method_by_name("/foo.Store/Get").unwrap();
+        assert_eq!(service.method_by_name("Get"), Some(method));
+        assert_eq!(pool.method_by_name("foo.Store.Get"), Some(method));
+        assert_eq!(method.service(), ".foo.Store");
+        assert_eq!(method.path(), "/foo.Store/Get");
+        assert_eq!(method.input_type(), ".foo.Outer.Inner");
+        assert_eq!(method.output_type(), ".foo.Outer");
+        assert!(!method.client_streaming());
+        assert!(method.server_streaming());
+        assert_eq!(pool.method_by_name("/foo.Store/Put"), None);
+        assert_eq!(pool.method_by_name("/foo.Stor/eGet"), None);
+
+        let input = pool.input_descriptors(method).unwrap();
+        assert!(input.message_by_name(".foo.Outer.Inner").is_some());
+        assert!(input.message_by_name(".foo.Outer").is_none());
+        let output = pool.output_descriptors(method).unwrap();
+        assert!(output.message_by_name(".foo.Outer").is_some());
+
+        let mut again = file("again.proto", "foo", vec![]);
+        again.set_service(pool.files[1].get_service().to_vec().into());
+        match pool.add_file_set(&file_set(vec![again])) {
+            Err(error::Error::Message(msg)) => {
+                assert_eq!(msg, "service .foo.Store is defined twice")
+            }
+            r => panic!("expected an error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_add_file_set_conflicts() {
+        let mut pool = DescriptorPool::from_file_set(&file_set(vec![foo(), note(100)])).unwrap();
+
+        let mut changed = foo();
+        changed.set_package("foo2".to_owned());
+        let mut copy = foo();
+        copy.set_name("copy.proto".to_owned());
+        let mut other_note = note(100);
+        other_note.set_name("other_note.proto".to_owned());
+        other_note.set_package("other".to_owned());
+
+        for (files, error) in vec![
+            (
+                vec![changed],
+                "proto file \"foo.proto\" was added twice with different contents",
+            ),
+            (
+                vec![copy],
+                "type .foo.Outer.Kind is defined in both \"foo.proto\" and \"copy.proto\"",
+            ),
+            (
+                vec![other_note],
+                "extensions .note.note and .other.note of .foo.Outer both have number 100",
+            ),
+        ] {
+            match pool.add_file_set(&file_set(files)) {
+                Err(error::Error::Message(msg)) => assert_eq!(msg, error),
+                r => panic!("expected an error, got {:?}", r),
+            }
+        }
+
+        // Nothing was added by the failed calls
+        assert_eq!(pool.files.len(), 2);
+        assert!(pool.extension(".foo.Outer", 100).is_some());
+        pool.add_file_set(&file_set(vec![note(100)])).unwrap();
+    }
+}
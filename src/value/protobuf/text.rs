@@ -0,0 +1,786 @@
+//! The protobuf text format, as printed by `TextFormat` and `DebugString` and read by `protoc
+//! --encode`, which is how protobuf messages are usually edited by hand.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+use crate::error;
+use crate::value;
+use serde_protobuf::descriptor;
+
+use super::Decoding;
+
+/// A source that reads a single message of a type in the text format, like `protoc --decode`
+/// prints it, and yields it as a record like `protobuf::Source` would.
+///
+/// Several records written by `Sink` are read back as a single message with their fields merged,
+/// like with the binary format.
+pub struct Source<'a, R>
+where
+    R: io::Read,
+{
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    input: R,
+    decoding: Decoding,
+    done: bool,
+}
+
+/// A sink that writes each record as a message of a type in the text format, with the fields in
+/// the order of their numbers and a blank line between messages.
+///
+/// Each record is checked with `protobuf::validate` first, so it accepts the same records as
+/// `protobuf::Sink`.
+pub struct Sink<'a, W>
+where
+    W: io::Write,
+{
+    descriptors: &'a descriptor::Descriptors,
+    message: &'a descriptor::MessageDescriptor,
+    w: W,
+    first: bool,
+}
+
+#[inline]
+pub fn source<'a, R>(
+    descriptors: &'a descriptor::Descriptors,
+    message_name: &str,
+    input: R,
+) -> error::Result<Source<'a, R>>
+where
+    R: io::Read,
+{
+    Ok(Source {
+        descriptors,
+        message: super::message_by_name(descriptors, message_name)?,
+        input,
+        decoding: Decoding::default(),
+        done: false,
+    })
+}
+
+#[inline]
+pub fn sink<'a, W>(
+    descriptors: &'a descriptor::Descriptors,
+    message_name: &str,
+    w: W,
+) -> error::Result<Sink<'a, W>>
+where
+    W: io::Write,
+{
+    Ok(Sink {
+        descriptors,
+        message: super::message_by_name(descriptors, message_name)?,
+        w,
+        first: true,
+    })
+}
+
+impl<'a, R> Source<'a, R>
+where
+    R: io::Read,
+{
+    /// Sets how the message is decoded, like for `protobuf::Source`.  Since the message is
+    /// checked against its type first, it never has unknown fields.
+    #[inline]
+    pub fn decoding(self, decoding: Decoding) -> Self {
+        Source { decoding, ..self }
+    }
+}
+
+impl<'a, R> value::Source for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+
+        let mut text = Vec::new();
+        self.input.read_to_end(&mut text)?;
+        let mut parser = Parser {
+            descriptors: self.descriptors,
+            input: &text,
+            pos: 0,
+            line: 1,
+        };
+        let record = value::Value::Map(parser.message(self.message, None)?);
+
+        super::validate(self.descriptors, self.message, &record)?;
+        let bytes = super::encode(self.descriptors, self.message, &record)?;
+        let v = super::decode_bytes(self.descriptors, self.message, &bytes, self.decoding)?;
+        if self.decoding.int64_as_string {
+            Ok(Some(super::int64_to_string(v)))
+        } else {
+            Ok(Some(v))
+        }
+    }
+}
+
+impl<'a, W> value::Sink for Sink<'a, W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        super::validate(self.descriptors, self.message, &value)?;
+        let mut out = String::new();
+        if !self.first {
+            out.push('\n');
+        }
+        self.first = false;
+        print_message(self.descriptors, self.message, &value, 0, &mut out)?;
+        self.w.write_all(out.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    String(Vec<u8>),
+    Punct(u8),
+}
+
+/// Parses the text format using the descriptors, into records that `protobuf::encode` accepts.
+struct Parser<'a, 'b> {
+    descriptors: &'a descriptor::Descriptors,
+    input: &'b [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+    fn error(&self, what: String) -> error::Error {
+        error::Error::Format {
+            msg: format!("bad protobuf text format at line {}: {}", self.line, what),
+        }
+    }
+
+    /// Skips white space and comments.
+    fn skip(&mut self) {
+        while let Some(&b) = self.input.get(self.pos) {
+            match b {
+                b'\n' => self.line += 1,
+                b'#' => {
+                    while self.input.get(self.pos).map_or(false, |&b| b != b'\n') {
+                        self.pos += 1;
+                    }
+                    continue;
+                }
+                b if b.is_ascii_whitespace() => (),
+                _ => return,
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> error::Result<Option<Token>> {
+        let (pos, line) = (self.pos, self.line);
+        let token = self.next();
+        self.pos = pos;
+        self.line = line;
+        token
+    }
+
+    fn next(&mut self) -> error::Result<Option<Token>> {
+        self.skip();
+        let start = self.pos;
+        let b = match self.input.get(self.pos) {
+            Some(&b) => b,
+            None => return Ok(None),
+        };
+        let word = |parser: &mut Self| {
+            while parser.input.get(parser.pos).map_or(false, |&b| {
+                b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || b == b'+' || b == b'-'
+            }) {
+                // Signs only belong to exponents, like in 1e-5
+                let b = parser.input[parser.pos];
+                if (b == b'+' || b == b'-') && !matches!(parser.input[parser.pos - 1], b'e' | b'E')
+                {
+                    break;
+                }
+                parser.pos += 1;
+            }
+            String::from_utf8_lossy(&parser.input[start..parser.pos]).into_owned()
+        };
+
+        Ok(Some(match b {
+            b'"' | b'\'' => Token::String(self.string(b)?),
+            b'0'..=b'9' | b'.' => {
+                self.pos += 1;
+                Token::Number(word(self))
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                while self
+                    .input
+                    .get(self.pos)
+                    .map_or(false, |&b| b.is_ascii_alphanumeric() || b == b'_')
+                {
+                    self.pos += 1;
+                }
+                Token::Ident(String::from_utf8_lossy(&self.input[start..self.pos]).into_owned())
+            }
+            b => {
+                self.pos += 1;
+                Token::Punct(b)
+            }
+        }))
+    }
+
+    /// Reads a quoted string with C-style escapes, which may contain any bytes.
+    fn string(&mut self, quote: u8) -> error::Result<Vec<u8>> {
+        let mut result = Vec::new();
+        self.pos += 1;
+        loop {
+            let b = match self.input.get(self.pos) {
+                Some(b'\n') | None => return Err(self.error("unterminated string".to_owned())),
+                Some(&b) => b,
+            };
+            self.pos += 1;
+            if b == quote {
+                return Ok(result);
+            } else if b != b'\\' {
+                result.push(b);
+                continue;
+            }
+
+            let escape = *self
+                .input
+                .get(self.pos)
+                .ok_or_else(|| self.error("unterminated string".to_owned()))?;
+            self.pos += 1;
+            let digits = |parser: &mut Self, max: usize, radix: u32| {
+                let start = parser.pos;
+                while parser.pos - start < max
+                    && parser
+                        .input
+                        .get(parser.pos)
+                        .map_or(false, |&b| (b as char).is_digit(radix))
+                {
+                    parser.pos += 1;
+                }
+                let digits = std::str::from_utf8(&parser.input[start..parser.pos]).unwrap_or("");
+                u32::from_str_radix(digits, radix)
+                    .map_err(|_| parser.error(format!("bad escape \\{}", escape as char)))
+            };
+            match escape {
+                b'n' => result.push(b'\n'),
+                b'r' => result.push(b'\r'),
+                b't' => result.push(b'\t'),
+                b'a' => result.push(0x07),
+                b'b' => result.push(0x08),
+                b'f' => result.push(0x0c),
+                b'v' => result.push(0x0b),
+                b'\\' | b'\'' | b'"' | b'?' => result.push(escape),
+                b'0'..=b'7' => {
+                    self.pos -= 1;
+                    let n = digits(self, 3, 8)?;
+                    result.push(
+                        u8::try_from(n).map_err(|_| self.error(format!("bad escape \\{:o}", n)))?,
+                    );
+                }
+                b'x' => result.push(digits(self, 2, 16)? as u8),
+                b'u' | b'U' => {
+                    let n = digits(self, if escape == b'u' { 4 } else { 8 }, 16)?;
+                    let c = std::char::from_u32(n)
+                        .ok_or_else(|| self.error(format!("bad code point {:x}", n)))?;
+                    result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                b => return Err(self.error(format!("bad escape \\{}", b as char))),
+            }
+        }
+    }
+
+    fn unexpected(&self, token: Option<Token>, expected: &str) -> error::Error {
+        let found = match token {
+            None => "the end of the input".to_owned(),
+            Some(Token::Ident(s)) | Some(Token::Number(s)) => s,
+            Some(Token::String(s)) => format!("{:?}", String::from_utf8_lossy(&s)),
+            Some(Token::Punct(b)) => format!("'{}'", b as char),
+        };
+        self.error(format!("expected {}, found {}", expected, found))
+    }
+
+    /// Parses the fields of a message up to the closing bracket, or the end of the input.
+    fn message(
+        &mut self,
+        message: &descriptor::MessageDescriptor,
+        end: Option<u8>,
+    ) -> error::Result<Vec<(value::Value, value::Value)>> {
+        let mut entries: Vec<(value::Value, value::Value)> = Vec::new();
+        loop {
+            let name = match (self.next()?, end) {
+                (None, None) => return Ok(entries),
+                (Some(Token::Punct(b)), Some(end)) if b == end => return Ok(entries),
+                (Some(Token::Ident(name)), _) => name,
+                (Some(Token::Punct(b'[')), _) => {
+                    return Err(error::Error::Unimplemented {
+                        msg: "extensions and expanded Any messages in the protobuf text format \
+                              aren't supported"
+                            .to_owned(),
+                    })
+                }
+                (t, _) => return Err(self.unexpected(t, "a field name")),
+            };
+            let field = message.field_by_name(&name).ok_or_else(|| {
+                self.error(format!(
+                    "message {} has no field {:?}",
+                    message.name(),
+                    name
+                ))
+            })?;
+
+            let is_message = matches!(
+                field.field_type(self.descriptors),
+                descriptor::FieldType::Message(_)
+            );
+            let colon = self.peek()? == Some(Token::Punct(b':'));
+            if colon {
+                self.next()?;
+            } else if !is_message {
+                let token = self.peek()?;
+                return Err(self.unexpected(token, "':'"));
+            }
+            let values = if self.peek()? == Some(Token::Punct(b'[')) && field.is_repeated() {
+                self.next()?;
+                let mut values = Vec::new();
+                if self.peek()? == Some(Token::Punct(b']')) {
+                    self.next()?;
+                } else {
+                    loop {
+                        values.push(self.value(field)?);
+                        match self.next()? {
+                            Some(Token::Punct(b',')) => (),
+                            Some(Token::Punct(b']')) => break,
+                            t => return Err(self.unexpected(t, "',' or ']'")),
+                        }
+                    }
+                }
+                values
+            } else {
+                vec![self.value(field)?]
+            };
+            if let Some(Token::Punct(b';')) | Some(Token::Punct(b',')) = self.peek()? {
+                self.next()?;
+            }
+
+            let key = value::Value::String(field.name().to_owned());
+            let existing = entries.iter_mut().find(|(k, _)| *k == key);
+            match (existing, field.is_repeated()) {
+                (Some((_, value::Value::Sequence(ref mut items))), true) => items.extend(values),
+                (None, true) => entries.push((key, value::Value::Sequence(values))),
+                (Some(_), false) => {
+                    return Err(self.error(format!(
+                        "non-repeated field {} is given more than once",
+                        field.name()
+                    )))
+                }
+                (_, false) => entries.push((key, values.into_iter().next().unwrap())),
+                (Some(_), true) => unreachable!("repeated fields are sequences"),
+            }
+        }
+    }
+
+    /// Parses a single value of a field, which is a message in brackets or a scalar.
+    fn value(&mut self, field: &descriptor::FieldDescriptor) -> error::Result<value::Value> {
+        use crate::value::Value;
+        use serde_protobuf::descriptor::FieldType;
+
+        let field_type = field.field_type(self.descriptors);
+        if let FieldType::Message(m) = field_type {
+            let end = match self.next()? {
+                Some(Token::Punct(b'{')) => b'}',
+                Some(Token::Punct(b'<')) => b'>',
+                t => return Err(self.unexpected(t, "'{' or '<'")),
+            };
+            return Ok(Value::Map(self.message(m, Some(end))?));
+        }
+
+        let negative = self.peek()? == Some(Token::Punct(b'-'));
+        if negative {
+            self.next()?;
+        }
+        let token = self.next()?;
+        let float = |s: &str| match s.to_ascii_lowercase().as_str() {
+            "inf" | "infinity" => Some(f64::INFINITY),
+            "nan" => Some(f64::NAN),
+            _ => None,
+        };
+        let sign = if negative { -1.0 } else { 1.0 };
+        let is_string = matches!(field_type, FieldType::String);
+
+        Ok(match (token, field_type) {
+            (Some(Token::String(mut s)), FieldType::String)
+            | (Some(Token::String(mut s)), FieldType::Bytes)
+                if !negative =>
+            {
+                // Adjacent strings are concatenated
+                while let Some(Token::String(more)) = self.peek()? {
+                    self.next()?;
+                    s.extend(more);
+                }
+                if is_string {
+                    Value::String(
+                        String::from_utf8(s)
+                            .map_err(|_| self.error("invalid UTF-8 in a string".to_owned()))?,
+                    )
+                } else {
+                    Value::Bytes(s)
+                }
+            }
+            (Some(Token::Ident(s)), FieldType::Bool) if !negative => match s.as_str() {
+                "true" | "True" | "t" => Value::Bool(true),
+                "false" | "False" | "f" => Value::Bool(false),
+                _ => return Err(self.error(format!("bad boolean {}", s))),
+            },
+            (Some(Token::Number(s)), FieldType::Bool) if !negative => match s.as_str() {
+                "1" => Value::Bool(true),
+                "0" => Value::Bool(false),
+                _ => return Err(self.error(format!("bad boolean {}", s))),
+            },
+            (Some(Token::Ident(s)), FieldType::Float)
+            | (Some(Token::Ident(s)), FieldType::Double)
+                if float(&s).is_some() =>
+            {
+                Value::from_f64(sign * float(&s).unwrap_or_default())
+            }
+            (Some(Token::Ident(s)), FieldType::Enum(_)) if !negative => Value::String(s),
+            (Some(Token::Number(s)), ref t)
+                if !matches!(t, FieldType::String | FieldType::Bytes) =>
+            {
+                self.number(&s, negative)?
+            }
+            (t, _) => {
+                return Err(self.unexpected(t, &format!("a value for field {}", field.name())))
+            }
+        })
+    }
+
+    fn number(&self, s: &str, negative: bool) -> error::Result<value::Value> {
+        let bad = || self.error(format!("bad number {}", s));
+        let (digits, radix) =
+            if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                (hex, 16)
+            } else if s.len() > 1 && s.starts_with('0') && s.bytes().all(|b| b.is_ascii_digit()) {
+                (&s[1..], 8)
+            } else {
+                (s, 10)
+            };
+
+        if let Ok(n) = u64::from_str_radix(digits, radix) {
+            return Ok(match (negative, i64::try_from(n)) {
+                (false, Ok(n)) => value::Value::I64(n),
+                (false, Err(_)) => value::Value::U64(n),
+                (true, _) => value::Value::I64(i64::try_from(-i128::from(n)).map_err(|_| bad())?),
+            });
+        }
+        if radix != 10 {
+            return Err(bad());
+        }
+        let float = s.trim_end_matches(|c| c == 'f' || c == 'F');
+        let x = float.parse::<f64>().map_err(|_| bad())?;
+        Ok(value::Value::from_f64(if negative { -x } else { x }))
+    }
+}
+
+fn print_message(
+    descriptors: &descriptor::Descriptors,
+    message: &descriptor::MessageDescriptor,
+    record: &value::Value,
+    indent: usize,
+    out: &mut String,
+) -> error::Result<()> {
+    let entries = match *record {
+        value::Value::Map(ref entries) => entries,
+        ref v => return Err(mismatch(message.name(), v)),
+    };
+    let mut fields = message.fields().iter().collect::<Vec<_>>();
+    fields.sort_by_key(|f| f.number());
+
+    for field in fields {
+        let v = entries
+            .iter()
+            .find(|(k, _)| matches!(*k, value::Value::String(ref k) if k == field.name()))
+            .map(|(_, v)| v);
+        match (v, super::map_entry(descriptors, field)) {
+            (None, _) | (Some(value::Value::Unit), _) => (),
+            (Some(value::Value::Map(pairs)), Some(_)) => {
+                for (k, v) in pairs {
+                    let pair = value::Value::Map(vec![
+                        (value::Value::String("key".to_owned()), k.clone()),
+                        (value::Value::String("value".to_owned()), v.clone()),
+                    ]);
+                    print_field(descriptors, field, &pair, indent, out)?;
+                }
+            }
+            (Some(value::Value::Sequence(items)), _) if field.is_repeated() => {
+                for item in items {
+                    print_field(descriptors, field, item, indent, out)?;
+                }
+            }
+            (Some(v), _) => print_field(descriptors, field, v, indent, out)?,
+        }
+    }
+    Ok(())
+}
+
+fn print_field(
+    descriptors: &descriptor::Descriptors,
+    field: &descriptor::FieldDescriptor,
+    v: &value::Value,
+    indent: usize,
+    out: &mut String,
+) -> error::Result<()> {
+    use crate::value::Value;
+    use serde_protobuf::descriptor::FieldType;
+
+    let pad = " ".repeat(indent);
+    let field_type = field.field_type(descriptors);
+    if let FieldType::Message(m) = field_type {
+        out.push_str(&format!("{}{} {{\n", pad, field.name()));
+        print_message(descriptors, m, v, indent + 2, out)?;
+        out.push_str(&format!("{}}}\n", pad));
+        return Ok(());
+    }
+
+    let bad = || mismatch(field.name(), v);
+    let is_float = matches!(field_type, FieldType::Float);
+    let text = match (field_type, v) {
+        (FieldType::Bool, Value::Bool(b)) => b.to_string(),
+        (FieldType::String, Value::String(s)) => quote(s.as_bytes(), false),
+        (FieldType::String, Value::Char(c)) => quote(c.to_string().as_bytes(), false),
+        (FieldType::Bytes, Value::Bytes(b)) => quote(b, true),
+        (FieldType::Bytes, Value::String(s)) => quote(s.as_bytes(), true),
+        (FieldType::Bytes, Value::Sequence(items)) => {
+            let bytes = items
+                .iter()
+                .map(|b| super::integer(b).and_then(|b| u8::try_from(b).ok()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(bad)?;
+            quote(&bytes, true)
+        }
+        (FieldType::Enum(e), Value::String(s)) if e.value_by_name(s).is_some() => s.clone(),
+        (FieldType::Enum(e), v) => {
+            let n = super::integer(v)
+                .and_then(|n| i32::try_from(n).ok())
+                .ok_or_else(bad)?;
+            e.value_by_number(n)
+                .map_or_else(|| n.to_string(), |v| v.name().to_owned())
+        }
+        (FieldType::Float, v) | (FieldType::Double, v) => {
+            let x = super::float(v).ok_or_else(bad)?;
+            if x.is_nan() {
+                "nan".to_owned()
+            } else if x.is_infinite() {
+                if x > 0.0 { "inf" } else { "-inf" }.to_owned()
+            } else if is_float {
+                (x as f32).to_string()
+            } else {
+                x.to_string()
+            }
+        }
+        (FieldType::Bool, _)
+        | (FieldType::String, _)
+        | (FieldType::Bytes, _)
+        | (FieldType::Group, _)
+        | (FieldType::UnresolvedEnum(_), _)
+        | (FieldType::UnresolvedMessage(_), _) => return Err(bad()),
+        (_, v) => super::integer(v).ok_or_else(bad)?.to_string(),
+    };
+    out.push_str(&format!("{}{}: {}\n", pad, field.name(), text));
+    Ok(())
+}
+
+/// Quotes a string with C-style escapes, escaping all bytes that aren't printable ASCII in bytes,
+/// and leaving UTF-8 as it is in strings.
+fn quote(bytes: &[u8], escape_non_ascii: bool) -> String {
+    let mut result = String::with_capacity(bytes.len() + 2);
+    result.push('"');
+    let mut rest = bytes;
+    while let Some((&b, tail)) = rest.split_first() {
+        match b {
+            b'\n' => result.push_str("\\n"),
+            b'\r' => result.push_str("\\r"),
+            b'\t' => result.push_str("\\t"),
+            b'"' => result.push_str("\\\""),
+            b'\'' => result.push_str("\\'"),
+            b'\\' => result.push_str("\\\\"),
+            0x20..=0x7e => result.push(b as char),
+            0x80..=0xff if !escape_non_ascii => {
+                // Strings are valid UTF-8, so this is the start of a character
+                let len = match b {
+                    0xf0..=0xff => 4,
+                    0xe0..=0xef => 3,
+                    _ => 2,
+                };
+                let len = len.min(rest.len());
+                result.push_str(&String::from_utf8_lossy(&rest[..len]));
+                rest = &rest[len..];
+                continue;
+            }
+            b => result.push_str(&format!("\\{:03o}", b)),
+        }
+        rest = tail;
+    }
+    result.push('"');
+    result
+}
+
+fn mismatch(name: &str, v: &value::Value) -> error::Error {
+    error::Error::Format {
+        msg: format!("{} can't hold {} in the protobuf text format", name, v),
+    }
+}
+
+impl<'a, R> fmt::Debug for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtobufTextSource")
+            .field("message_name", &self.message.name())
+            .field("decoding", &self.decoding)
+            .finish()
+    }
+}
+
+impl<'a, W> fmt::Debug for Sink<'a, W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtobufTextSink")
+            .field("message_name", &self.message.name())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn read(text: &str) -> error::Result<Option<Value>> {
+        let descriptors = super::super::test::record_descriptors();
+        let mut source = source(&descriptors, ".foo.Record", text.as_bytes())?;
+        source.read()
+    }
+
+    #[test]
+    fn test_source() {
+        let text = r#"
+            # A comment
+            id: -0x10
+            score: 1.5f
+            colors: BLUE colors: [RED, 1];
+            greeting < text: "h\x69 " 'th\145re' >
+            data: "\001\n\"\u00e9"
+        "#;
+        assert_eq!(
+            read(text).unwrap(),
+            Some(Value::Map(vec![
+                (s("id"), Value::I64(-16)),
+                (s("score"), Value::from_f32(1.5)),
+                (
+                    s("colors"),
+                    Value::Sequence(vec![s("BLUE"), s("RED"), s("BLUE")])
+                ),
+                (s("greeting"), Value::Map(vec![(s("text"), s("hi there"))])),
+                (s("data"), Value::Bytes(vec![1, b'\n', b'"', 0xc3, 0xa9])),
+                (s("count"), Value::Unit),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_source_errors() {
+        let message = |text| match read(text) {
+            Err(error::Error::Format { msg }) => msg,
+            r => panic!("expected an error, got {:?}", r),
+        };
+        assert_eq!(
+            message("id: 1\nname: \"x\""),
+            "bad protobuf text format at line 2: message .foo.Record has no field \"name\""
+        );
+        assert_eq!(
+            message("id: 1 id: 2"),
+            "bad protobuf text format at line 1: non-repeated field id is given more than once"
+        );
+        assert_eq!(
+            message("id: 1\ngreeting { text: 2 }"),
+            "bad protobuf text format at line 2: expected a value for field text, found 2"
+        );
+        assert_eq!(
+            message("id: 1 data: \"abc"),
+            "bad protobuf text format at line 1: unterminated string"
+        );
+        assert_eq!(
+            message("id 1"),
+            "bad protobuf text format at line 1: expected ':', found 1"
+        );
+    }
+
+    #[test]
+    fn test_sink() {
+        let descriptors = super::super::test::record_descriptors();
+        let mut out = Vec::new();
+        {
+            let mut sink = sink(&descriptors, ".foo.Record", &mut out).unwrap();
+            sink.write(Value::Map(vec![
+                (s("data"), Value::Bytes(vec![0, b'a', 0xff])),
+                (s("greeting"), Value::Map(vec![(s("text"), s("\"é\"\n"))])),
+                (s("colors"), Value::Sequence(vec![Value::I32(1), s("RED")])),
+                (s("score"), Value::from_f64(f64::NEG_INFINITY)),
+                (s("id"), Value::I64(-3)),
+                (s("count"), Value::Unit),
+            ]))
+            .unwrap();
+            sink.write(Value::Map(vec![(s("id"), Value::I64(4))]))
+                .unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id: -3\n\
+             score: -inf\n\
+             colors: BLUE\n\
+             colors: RED\n\
+             greeting {\n  \
+               text: \"\\\"é\\\"\\n\"\n\
+             }\n\
+             data: \"\\000a\\377\"\n\
+             \n\
+             id: 4\n"
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let descriptors = super::super::test::record_descriptors();
+        let record = Value::Map(vec![
+            (s("id"), Value::I64(i64::MIN)),
+            (s("score"), Value::from_f32(0.1)),
+            (s("colors"), Value::Sequence(vec![s("RED")])),
+            (s("greeting"), Value::Map(vec![(s("text"), s("\t'ü'"))])),
+            (s("data"), Value::Bytes((0..=255).collect())),
+            (s("count"), Value::U32(u32::MAX)),
+        ]);
+        let mut out = Vec::new();
+        sink(&descriptors, ".foo.Record", &mut out)
+            .unwrap()
+            .write(record.clone())
+            .unwrap();
+        let mut source = source(&descriptors, ".foo.Record", out.as_slice()).unwrap();
+        assert_eq!(source.read().unwrap(), Some(record));
+        assert_eq!(source.read().unwrap(), None);
+    }
+}
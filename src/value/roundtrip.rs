@@ -0,0 +1,274 @@
+use crate::error;
+use crate::value;
+use crate::value::diff;
+use crate::value::path;
+use std::collections;
+use std::fmt;
+
+/// Checks that records survive a round trip through an output format unchanged, by encoding and
+/// decoding each record again and comparing the result with the original.
+///
+/// Numbers are compared by value, so an `I32` that comes back as a `U64` is not a loss, but a
+/// float that is rounded is.
+pub struct Checker<'a> {
+    roundtrip: Box<dyn FnMut(value::Value) -> error::Result<Option<value::Value>> + 'a>,
+    report: Report,
+}
+
+/// A summary of the changes that the round trips of records caused.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report {
+    records: usize,
+    lossy_records: usize,
+    /// Changes by kind, with the number of occurrences and the first path they occurred at.
+    losses: collections::BTreeMap<String, (usize, String)>,
+}
+
+impl<'a> Checker<'a> {
+    /// Creates a checker from a function that encodes a record and decodes it again.
+    pub fn new<F>(roundtrip: F) -> Self
+    where
+        F: FnMut(value::Value) -> error::Result<Option<value::Value>> + 'a,
+    {
+        Checker {
+            roundtrip: Box::new(roundtrip),
+            report: Report::default(),
+        }
+    }
+
+    pub fn check(&mut self, record: &value::Value) {
+        let result = (self.roundtrip)(record.clone());
+        self.report.add(record, result);
+    }
+
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+}
+
+impl Report {
+    pub fn is_lossless(&self) -> bool {
+        self.lossy_records == 0
+    }
+
+    fn add(&mut self, record: &value::Value, result: error::Result<Option<value::Value>>) {
+        let mut losses = Vec::new();
+        match result {
+            Ok(Some(ref decoded)) => {
+                for (at, change) in diff(record, decoded).changes() {
+                    losses.push((describe(change), to_path(at)));
+                }
+                reordered_keys(record, decoded, &mut Vec::new(), &mut losses);
+            }
+            Ok(None) => losses.push(("record dropped".to_owned(), path::Path::default())),
+            Err(e) => losses.push((format!("record unreadable: {}", e), path::Path::default())),
+        }
+
+        self.records += 1;
+        if !losses.is_empty() {
+            self.lossy_records += 1;
+        }
+        for (kind, at) in losses {
+            self.losses
+                .entry(kind)
+                .or_insert_with(|| (0, at.to_string()))
+                .0 += 1;
+        }
+    }
+}
+
+fn describe(change: &diff::Change) -> String {
+    match *change {
+        diff::Change::Added(_) => "value added".to_owned(),
+        diff::Change::Removed(_) => "value removed".to_owned(),
+        diff::Change::Changed(ref a, ref b) => {
+            let (a, b) = (type_name(a), type_name(b));
+            if a == b {
+                format!("{} changed", a)
+            } else {
+                format!("{} became {}", a, b)
+            }
+        }
+    }
+}
+
+fn type_name(v: &value::Value) -> &'static str {
+    use crate::value::Value;
+
+    match *v {
+        Value::Unit => "null",
+        Value::Bool(_) => "bool",
+        Value::I8(_)
+        | Value::I16(_)
+        | Value::I32(_)
+        | Value::I64(_)
+        | Value::U8(_)
+        | Value::U16(_)
+        | Value::U32(_)
+        | Value::U64(_) => "integer",
+        Value::F32(_) | Value::F64(_) => "float",
+        Value::Char(_) | Value::String(_) => "string",
+        Value::Bytes(_) => "bytes",
+        Value::Sequence(_) => "sequence",
+        Value::Map(_) => "map",
+    }
+}
+
+fn to_path(at: &[diff::PathSegment]) -> path::Path {
+    path::Path::new(
+        at.iter()
+            .map(|segment| match *segment {
+                diff::PathSegment::Index(i) => path::Segment::Index(i as i64),
+                diff::PathSegment::Key(value::Value::String(ref k)) => {
+                    path::Segment::Key(k.clone())
+                }
+                diff::PathSegment::Key(ref k) => path::Segment::Key(k.to_string()),
+            })
+            .collect(),
+    )
+}
+
+/// Finds maps whose keys came back in a different order, which `diff` doesn't report.
+fn reordered_keys(
+    a: &value::Value,
+    b: &value::Value,
+    at: &mut Vec<diff::PathSegment>,
+    losses: &mut Vec<(String, path::Path)>,
+) {
+    use crate::value::Value;
+
+    match (a, b) {
+        (Value::Map(a), Value::Map(b)) => {
+            let order = |entries: &[(Value, Value)], other: &[(Value, Value)]| {
+                entries
+                    .iter()
+                    .map(|(k, _)| k)
+                    .filter(|k| other.iter().any(|(ok, _)| ok == *k))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            if order(a, b) != order(b, a) {
+                losses.push(("map keys reordered".to_owned(), to_path(at)));
+            }
+            for (k, a) in a {
+                if let Some((_, b)) = b.iter().find(|(bk, _)| bk == k) {
+                    at.push(diff::PathSegment::Key(k.clone()));
+                    reordered_keys(a, b, at, losses);
+                    at.pop();
+                }
+            }
+        }
+        (Value::Sequence(a), Value::Sequence(b)) => {
+            for (i, (a, b)) in a.iter().zip(b).enumerate() {
+                at.push(diff::PathSegment::Index(i));
+                reordered_keys(a, b, at, losses);
+                at.pop();
+            }
+        }
+        _ => (),
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_lossless() {
+            return write!(f, "all {} records round-trip unchanged", self.records);
+        }
+
+        write!(
+            f,
+            "{} of {} records changed in a round trip:",
+            self.lossy_records, self.records
+        )?;
+        for (kind, (count, example)) in &self.losses {
+            write!(f, "\n  {} ({}x, first at {})", kind, count, example)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for Checker<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RoundtripChecker")
+            .field("report", &self.report)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn json_checker<'a>() -> Checker<'a> {
+        Checker::new(|v| {
+            let mut buf = Vec::new();
+            value::json::sink_compact(&mut buf).write(v)?;
+            value::json::source(buf.as_slice()).read()
+        })
+    }
+
+    #[test]
+    fn test_lossless() {
+        let mut checker = json_checker();
+        checker.check(&Value::Map(vec![
+            (s("b"), Value::I32(1)),
+            (
+                s("a"),
+                Value::Sequence(vec![Value::from_f64(0.1), Value::Unit]),
+            ),
+        ]));
+        assert!(checker.report().is_lossless());
+        assert_eq!(
+            checker.report().to_string(),
+            "all 1 records round-trip unchanged"
+        );
+    }
+
+    #[test]
+    fn test_losses() {
+        let mut checker = json_checker();
+        checker.check(&Value::Map(vec![
+            (s("bytes"), Value::Bytes(vec![1])),
+            (s("float"), Value::from_f32(0.1)),
+        ]));
+        checker.check(&Value::Map(vec![(Value::I32(1), Value::Bytes(vec![2]))]));
+        checker.check(&Value::Unit);
+
+        assert!(!checker.report().is_lossless());
+        assert_eq!(
+            checker.report().to_string(),
+            "2 of 3 records changed in a round trip:\n  \
+             bytes became sequence (1x, first at .bytes)\n  \
+             float changed (1x, first at .float)\n  \
+             value added (1x, first at .1)\n  \
+             value removed (1x, first at .1)"
+        );
+    }
+
+    #[test]
+    fn test_reordered_keys() {
+        let mut checker = Checker::new(|v| match v {
+            Value::Map(mut entries) => {
+                entries.reverse();
+                Ok(Some(Value::Map(entries)))
+            }
+            v => Ok(Some(v)),
+        });
+        checker.check(&Value::Map(vec![
+            (s("a"), Value::Unit),
+            (s("b"), Value::Unit),
+        ]));
+        assert_eq!(
+            checker.report().to_string(),
+            "1 of 1 records changed in a round trip:\n  \
+             map keys reordered (1x, first at .)"
+        );
+    }
+}
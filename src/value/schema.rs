@@ -0,0 +1,433 @@
+use crate::error;
+use crate::value;
+use serde_json;
+use std::collections;
+use std::fmt;
+
+/// A schema that records are coerced to, in a subset of JSON Schema.
+///
+/// Each schema may have a `type`, either one of `string`, `integer`, `number`, `boolean`,
+/// `null`, `object` and `array` or a list of them; objects may have `properties`, `required`
+/// property names and `"additionalProperties": false`, and arrays may have `items`.  Other
+/// keywords are ignored.
+///
+/// Values that have one of the allowed types are kept as they are.  Otherwise they are coerced
+/// to the first allowed type that they can be converted to: strings are parsed as integers,
+/// numbers or booleans, numbers and booleans are formatted as strings, integral floats become
+/// integers, and empty strings become null.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    types: Vec<Type>,
+    properties: Vec<(String, Schema)>,
+    required: collections::HashSet<String>,
+    additional_properties: bool,
+    items: Option<Box<Schema>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Type {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Null,
+    Object,
+    Array,
+}
+
+/// A source that coerces the records of another source to a schema.
+///
+/// Records that don't match the schema are reported as `error::Error::InvalidRecord` errors,
+/// which carry the record, and the source can keep being read after them.
+pub struct Source<S>(S, Schema);
+
+#[inline]
+pub fn source<S>(inner: S, schema: Schema) -> Source<S>
+where
+    S: value::Source,
+{
+    Source(inner, schema)
+}
+
+impl Schema {
+    pub fn parse_str(json: &str) -> error::Result<Self> {
+        Self::from_json(&serde_json::from_str(json)?)
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> error::Result<Self> {
+        let error = |msg: String| error::Error::Format { msg };
+
+        let fields = match *json {
+            serde_json::Value::Object(ref fields) => fields,
+            // `true` accepts anything
+            serde_json::Value::Bool(true) => return Ok(Self::default()),
+            ref v => return Err(error(format!("expected a schema object, got {}", v))),
+        };
+
+        let types = match fields.get("type") {
+            None => Vec::new(),
+            Some(serde_json::Value::String(t)) => vec![t.parse()?],
+            Some(serde_json::Value::Array(ts)) => ts
+                .iter()
+                .map(|t| match *t {
+                    serde_json::Value::String(ref t) => t.parse(),
+                    ref t => Err(error(format!("expected a type name, got {}", t))),
+                })
+                .collect::<error::Result<_>>()?,
+            Some(t) => return Err(error(format!("expected a type name, got {}", t))),
+        };
+
+        let properties = match fields.get("properties") {
+            None => Vec::new(),
+            Some(serde_json::Value::Object(properties)) => properties
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), Self::from_json(v)?)))
+                .collect::<error::Result<_>>()?,
+            Some(p) => return Err(error(format!("expected properties, got {}", p))),
+        };
+
+        let required = match fields.get("required") {
+            None => collections::HashSet::new(),
+            Some(serde_json::Value::Array(names)) => names
+                .iter()
+                .map(|n| match *n {
+                    serde_json::Value::String(ref n) => Ok(n.clone()),
+                    ref n => Err(error(format!("expected a property name, got {}", n))),
+                })
+                .collect::<error::Result<_>>()?,
+            Some(r) => return Err(error(format!("expected required properties, got {}", r))),
+        };
+
+        let items = match fields.get("items") {
+            None => None,
+            Some(items) => Some(Box::new(Self::from_json(items)?)),
+        };
+
+        Ok(Schema {
+            types,
+            properties,
+            required,
+            additional_properties: fields.get("additionalProperties")
+                != Some(&serde_json::Value::Bool(false)),
+            items,
+        })
+    }
+
+    /// Coerces a value to this schema, or describes why it can't be.
+    pub fn coerce(&self, v: value::Value) -> Result<value::Value, String> {
+        self.coerce_at(v, &mut String::new())
+    }
+
+    fn coerce_at(&self, v: value::Value, path: &mut String) -> Result<value::Value, String> {
+        let v = if self.types.is_empty() || self.types.iter().any(|t| t.matches(&v)) {
+            v
+        } else {
+            match self.types.iter().find_map(|t| t.convert(&v)) {
+                Some(v) => v,
+                None => {
+                    let expected = self
+                        .types
+                        .iter()
+                        .map(|t| t.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" or ");
+                    return Err(format!(
+                        "{}: expected {}, got {}",
+                        if path.is_empty() { "." } else { path },
+                        expected,
+                        serde_json::to_string(&v).unwrap_or_else(|_| v.to_string())
+                    ));
+                }
+            }
+        };
+
+        match v {
+            value::Value::Map(entries) => self.coerce_entries(entries, path),
+            value::Value::Sequence(seq) => match self.items {
+                Some(ref items) => seq
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, v)| {
+                        let len = path.len();
+                        path.push_str(&format!("[{}]", i));
+                        let result = items.coerce_at(v, path);
+                        path.truncate(len);
+                        result
+                    })
+                    .collect::<Result<_, _>>()
+                    .map(value::Value::Sequence),
+                None => Ok(value::Value::Sequence(seq)),
+            },
+            v => Ok(v),
+        }
+    }
+
+    fn coerce_entries(
+        &self,
+        entries: Vec<(value::Value, value::Value)>,
+        path: &mut String,
+    ) -> Result<value::Value, String> {
+        let mut seen = collections::HashSet::new();
+        let mut result = Vec::with_capacity(entries.len());
+        for (k, v) in entries {
+            let key = match k {
+                value::Value::String(ref key) => key.clone(),
+                ref k => k.to_string(),
+            };
+            let v = match self.properties.iter().find(|(name, _)| *name == key) {
+                Some((_, schema)) => {
+                    let len = path.len();
+                    path.push('.');
+                    path.push_str(&key);
+                    let v = schema.coerce_at(v, path)?;
+                    path.truncate(len);
+                    v
+                }
+                None if !self.additional_properties => {
+                    return Err(format!("{}.{}: unexpected property", path, key));
+                }
+                None => v,
+            };
+            seen.insert(key);
+            result.push((k, v));
+        }
+
+        let mut missing = self
+            .required
+            .iter()
+            .filter(|name| !seen.contains(*name))
+            .collect::<Vec<_>>();
+        missing.sort();
+        if let Some(name) = missing.first() {
+            return Err(format!("{}.{}: missing required property", path, name));
+        }
+
+        Ok(value::Value::Map(result))
+    }
+}
+
+impl Type {
+    fn matches(self, v: &value::Value) -> bool {
+        use crate::value::Value;
+
+        match (self, v) {
+            (Type::String, Value::String(_)) | (Type::String, Value::Char(_)) => true,
+            (Type::Integer, v) => is_integer(v),
+            (Type::Number, v) => is_integer(v) || matches!(v, Value::F32(_) | Value::F64(_)),
+            (Type::Boolean, Value::Bool(_)) => true,
+            (Type::Null, Value::Unit) => true,
+            (Type::Object, Value::Map(_)) => true,
+            (Type::Array, Value::Sequence(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn convert(self, v: &value::Value) -> Option<value::Value> {
+        use crate::value::Value;
+
+        match (self, v) {
+            (Type::String, v) if is_integer(v) => Some(Value::String(v.to_string())),
+            (Type::String, Value::F32(_))
+            | (Type::String, Value::F64(_))
+            | (Type::String, Value::Bool(_)) => Some(Value::String(v.to_string())),
+            (Type::Integer, Value::String(s)) => {
+                let s = s.trim();
+                s.parse()
+                    .map(Value::I64)
+                    .or_else(|_| s.parse().map(Value::U64))
+                    .ok()
+            }
+            (Type::Integer, Value::F64(f)) if f.fract() == 0.0 && f.abs() < 9.2e18 => {
+                Some(Value::I64(f.0 as i64))
+            }
+            (Type::Integer, Value::F32(f)) if f.fract() == 0.0 && f.abs() < 9.2e18 => {
+                Some(Value::I64(f.0 as i64))
+            }
+            (Type::Number, Value::String(s)) => s
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|f| f.is_finite())
+                .map(Value::from_f64),
+            (Type::Boolean, Value::String(s)) => match s.trim().to_lowercase().as_str() {
+                "true" => Some(Value::Bool(true)),
+                "false" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            (Type::Null, Value::String(s)) if s.is_empty() => Some(Value::Unit),
+            _ => None,
+        }
+    }
+}
+
+fn is_integer(v: &value::Value) -> bool {
+    use crate::value::Value;
+
+    matches!(
+        v,
+        Value::I8(_)
+            | Value::I16(_)
+            | Value::I32(_)
+            | Value::I64(_)
+            | Value::U8(_)
+            | Value::U16(_)
+            | Value::U32(_)
+            | Value::U64(_)
+    )
+}
+
+impl std::str::FromStr for Type {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> error::Result<Self> {
+        match s {
+            "string" => Ok(Type::String),
+            "integer" => Ok(Type::Integer),
+            "number" => Ok(Type::Number),
+            "boolean" => Ok(Type::Boolean),
+            "null" => Ok(Type::Null),
+            "object" => Ok(Type::Object),
+            "array" => Ok(Type::Array),
+            _ => Err(error::Error::Format {
+                msg: format!("unknown schema type {:?}", s),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Type::String => "string",
+            Type::Integer => "integer",
+            Type::Number => "number",
+            Type::Boolean => "boolean",
+            Type::Null => "null",
+            Type::Object => "object",
+            Type::Array => "array",
+        };
+        f.write_str(name)
+    }
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        match self.0.read()? {
+            Some(record) => match self.1.coerce(record.clone()) {
+                Ok(v) => Ok(Some(v)),
+                Err(msg) => Err(error::Error::InvalidRecord { msg, record }),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Source<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SchemaSource")
+            .field("schema", &self.1)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    const SCHEMA: &str = r#"{
+        "type": "object",
+        "properties": {
+            "id": {"type": "integer"},
+            "name": {"type": "string"},
+            "score": {"type": ["number", "null"]},
+            "active": {"type": "boolean"},
+            "tags": {"type": "array", "items": {"type": "string"}}
+        },
+        "required": ["id"],
+        "additionalProperties": false
+    }"#;
+
+    #[test]
+    fn test_coerce() {
+        let schema = Schema::parse_str(SCHEMA).unwrap();
+        assert_eq!(
+            schema.coerce(Value::Map(vec![
+                (s("id"), s(" 42")),
+                (s("name"), Value::I64(7)),
+                (s("score"), s("")),
+                (s("active"), s("TRUE")),
+                (s("tags"), Value::Sequence(vec![Value::Bool(false)])),
+            ])),
+            Ok(Value::Map(vec![
+                (s("id"), Value::I64(42)),
+                (s("name"), s("7")),
+                (s("score"), Value::Unit),
+                (s("active"), Value::Bool(true)),
+                (s("tags"), Value::Sequence(vec![s("false")])),
+            ]))
+        );
+        assert_eq!(
+            schema.coerce(Value::Map(vec![
+                (s("id"), Value::from_f64(3.0)),
+                (s("score"), s("1.5")),
+            ])),
+            Ok(Value::Map(vec![
+                (s("id"), Value::I64(3)),
+                (s("score"), Value::from_f64(1.5)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_coerce_errors() {
+        let schema = Schema::parse_str(SCHEMA).unwrap();
+        let error = |v| schema.coerce(Value::Map(v)).unwrap_err();
+        assert_eq!(
+            error(vec![(s("id"), s("abc"))]),
+            r#".id: expected integer, got "abc""#
+        );
+        assert_eq!(
+            error(vec![(s("id"), Value::I64(1)), (s("extra"), Value::Unit)]),
+            ".extra: unexpected property"
+        );
+        assert_eq!(error(vec![]), ".id: missing required property");
+        assert_eq!(
+            error(vec![
+                (s("id"), Value::I64(1)),
+                (s("tags"), Value::Sequence(vec![Value::Unit])),
+            ]),
+            ".tags[0]: expected string, got null"
+        );
+        assert!(Schema::parse_str(r#"{"type": "date"}"#).is_err());
+    }
+
+    #[test]
+    fn test_source() {
+        struct Records(Vec<Value>);
+
+        impl value::Source for Records {
+            fn read(&mut self) -> error::Result<Option<Value>> {
+                Ok(self.0.pop())
+            }
+        }
+
+        let schema = Schema::parse_str(r#"{"type": "integer"}"#).unwrap();
+        let mut source = source(Records(vec![s("2"), s("x"), s("1")]), schema);
+        assert_eq!(source.read().unwrap(), Some(Value::I64(1)));
+        match source.read() {
+            Err(error::Error::InvalidRecord { record, .. }) => assert_eq!(record, s("x")),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(source.read().unwrap(), Some(Value::I64(2)));
+        assert_eq!(source.read().unwrap(), None);
+    }
+}
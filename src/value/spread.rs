@@ -0,0 +1,72 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+use std::vec;
+
+/// A source that yields the elements of top-level sequences as individual records.
+///
+/// Records that are not sequences are passed through as-is.
+pub struct Source<S>(S, vec::IntoIter<value::Value>);
+
+#[inline]
+pub fn source<S>(inner: S) -> Source<S>
+where
+    S: value::Source,
+{
+    Source(inner, Vec::new().into_iter())
+}
+
+impl<S> value::Source for Source<S>
+where
+    S: value::Source,
+{
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        loop {
+            if let Some(v) = self.1.next() {
+                return Ok(Some(v));
+            }
+
+            match self.0.read()? {
+                Some(value::Value::Sequence(seq)) => self.1 = seq.into_iter(),
+                other => return Ok(other),
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for Source<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpreadSource")
+            .field("inner", &self.0)
+            .field("remaining", &self.1.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+
+    #[test]
+    fn test_spread() {
+        let mut source = source(value::json::source(&b"[1, [2]] [] 3 [4]"[..]));
+        let mut result = Vec::new();
+        while let Some(v) = source.read().unwrap() {
+            result.push(v);
+        }
+        assert_eq!(
+            result,
+            vec![
+                value::Value::U64(1),
+                value::Value::Sequence(vec![value::Value::U64(2)]),
+                value::Value::U64(3),
+                value::Value::U64(4),
+            ]
+        );
+    }
+}
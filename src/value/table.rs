@@ -0,0 +1,311 @@
+use crate::error;
+use crate::value;
+use serde_json;
+use std::fmt;
+use std::io;
+
+/// A sink that renders records as an aligned plain-text table, like `psql` does.
+///
+/// Map records get one column per key, sequence records one column per index, and other records
+/// a single `value` column.  Since the column widths depend on all records, the table is only
+/// written when the sink is dropped.
+pub struct Sink<W>
+where
+    W: io::Write,
+{
+    w: W,
+    options: Options,
+    records: Vec<value::Value>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Options {
+    /// The columns to show, in order; all columns are shown if not set.
+    pub columns: Option<Vec<String>>,
+    /// Cells wider than this are truncated; there is no limit if not set.
+    pub max_width: Option<usize>,
+}
+
+struct Cell {
+    text: String,
+    numeric: bool,
+}
+
+#[inline]
+pub fn sink<W>(w: W, options: Options) -> Sink<W>
+where
+    W: io::Write,
+{
+    Sink {
+        w,
+        options,
+        records: Vec::new(),
+    }
+}
+
+impl<W> value::Sink for Sink<W>
+where
+    W: io::Write,
+{
+    #[inline]
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        self.records.push(value);
+        Ok(())
+    }
+}
+
+impl<W> Sink<W>
+where
+    W: io::Write,
+{
+    fn render(&mut self) -> io::Result<()> {
+        let columns = match self.options.columns {
+            Some(ref columns) => columns.clone(),
+            None => infer_columns(&self.records),
+        };
+
+        let rows = self
+            .records
+            .iter()
+            .map(|record| {
+                columns
+                    .iter()
+                    .map(|column| cell(column_value(record, column), self.options.max_width))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let widths = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                rows.iter()
+                    .map(|row| row[i].text.chars().count())
+                    .chain(Some(column.chars().count()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+
+        let header = columns
+            .iter()
+            .zip(&widths)
+            .map(|(column, &width)| format!(" {:^width$} ", column, width = width))
+            .collect::<Vec<_>>();
+        writeln!(self.w, "{}", header.join("|").trim_end())?;
+
+        let separator = widths
+            .iter()
+            .map(|&width| "-".repeat(width + 2))
+            .collect::<Vec<_>>();
+        writeln!(self.w, "{}", separator.join("+"))?;
+
+        for row in rows {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| {
+                    if cell.numeric {
+                        format!(" {:>width$} ", cell.text, width = width)
+                    } else {
+                        format!(" {:<width$} ", cell.text, width = width)
+                    }
+                })
+                .collect::<Vec<_>>();
+            writeln!(self.w, "{}", line.join("|").trim_end())?;
+        }
+
+        match self.records.len() {
+            1 => writeln!(self.w, "(1 row)"),
+            n => writeln!(self.w, "({} rows)", n),
+        }
+    }
+}
+
+fn infer_columns(records: &[value::Value]) -> Vec<String> {
+    let mut columns = Vec::new();
+    let mut add = |column: String| {
+        if !columns.contains(&column) {
+            columns.push(column);
+        }
+    };
+
+    for record in records {
+        match *record {
+            value::Value::Map(ref entries) => {
+                for (k, _) in entries {
+                    add(key_name(k));
+                }
+            }
+            value::Value::Sequence(ref seq) => {
+                for i in 0..seq.len() {
+                    add(i.to_string());
+                }
+            }
+            _ => add("value".to_owned()),
+        }
+    }
+    columns
+}
+
+fn column_value<'a>(record: &'a value::Value, column: &str) -> Option<&'a value::Value> {
+    match *record {
+        value::Value::Map(ref entries) => entries
+            .iter()
+            .find(|(k, _)| key_name(k) == column)
+            .map(|(_, v)| v),
+        value::Value::Sequence(ref seq) => column.parse::<usize>().ok().and_then(|i| seq.get(i)),
+        ref v if column == "value" => Some(v),
+        _ => None,
+    }
+}
+
+fn key_name(key: &value::Value) -> String {
+    match *key {
+        value::Value::String(ref s) => s.clone(),
+        ref k => k.to_string(),
+    }
+}
+
+fn cell(value: Option<&value::Value>, max_width: Option<usize>) -> Cell {
+    use crate::value::Value;
+
+    let (text, numeric) = match value {
+        None => (String::new(), false),
+        Some(Value::String(s)) => (s.clone(), false),
+        Some(Value::Char(c)) => (c.to_string(), false),
+        Some(
+            v @ Value::I8(_)
+            | v @ Value::I16(_)
+            | v @ Value::I32(_)
+            | v @ Value::I64(_)
+            | v @ Value::U8(_)
+            | v @ Value::U16(_)
+            | v @ Value::U32(_)
+            | v @ Value::U64(_)
+            | v @ Value::F32(_)
+            | v @ Value::F64(_),
+        ) => (v.to_string(), true),
+        // Nested values (and bytes) are shown as compact JSON
+        Some(v) => (
+            serde_json::to_string(v).unwrap_or_else(|_| v.to_string()),
+            false,
+        ),
+    };
+
+    // Control characters would break the table layout
+    let text = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>();
+
+    let text = match max_width {
+        Some(max_width) if text.chars().count() > max_width => {
+            let mut truncated = text
+                .chars()
+                .take(max_width.saturating_sub(1))
+                .collect::<String>();
+            truncated.push('…');
+            truncated
+        }
+        _ => text,
+    };
+
+    Cell { text, numeric }
+}
+
+impl<W> fmt::Debug for Sink<W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TableSink")
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+impl<W> Drop for Sink<W>
+where
+    W: io::Write,
+{
+    fn drop(&mut self) {
+        match self.render() {
+            Ok(_) => (),
+            Err(error) => panic!("{}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn render(records: Vec<Value>, options: Options) -> String {
+        let mut out = Vec::new();
+        {
+            let mut sink = sink(&mut out, options);
+            for record in records {
+                sink.write(record).unwrap();
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_maps() {
+        let records = vec![
+            Value::Map(vec![(s("name"), s("rq")), (s("stars"), Value::U64(2000))]),
+            Value::Map(vec![
+                (s("name"), s("jq\ttool")),
+                (s("tags"), Value::Sequence(vec![s("a")])),
+            ]),
+        ];
+        assert_eq!(
+            render(records, Options::default()),
+            "  name   | stars | tags\n\
+             ---------+-------+-------\n\
+             \x20rq      |  2000 |\n\
+             \x20jq tool |       | [\"a\"]\n\
+             (2 rows)\n"
+        );
+    }
+
+    #[test]
+    fn test_columns_and_truncation() {
+        let records = vec![Value::Map(vec![
+            (s("a"), s("abcdefgh")),
+            (s("b"), Value::Unit),
+        ])];
+        let options = Options {
+            columns: Some(vec!["b".to_owned(), "c".to_owned(), "a".to_owned()]),
+            max_width: Some(4),
+        };
+        assert_eq!(
+            render(records, options),
+            "  b   | c |  a\n\
+             ------+---+------\n\
+             \x20null |   | abc…\n\
+             (1 row)\n"
+        );
+    }
+
+    #[test]
+    fn test_scalars_and_sequences() {
+        let records = vec![Value::Sequence(vec![Value::Bool(true)]), Value::I32(-1)];
+        assert_eq!(
+            render(records, Options::default()),
+            "  0   | value\n\
+             ------+-------\n\
+             \x20true |\n\
+             \x20     |    -1\n\
+             (2 rows)\n"
+        );
+    }
+}
@@ -0,0 +1,1007 @@
+//! Thrift structs, encoded with the binary or the compact protocol.
+//!
+//! Without an IDL, structs are decoded as maps from their field IDs to their values, with strings
+//! as binaries if they aren't valid UTF-8.  With an IDL, the fields are named, binaries and enums
+//! are decoded as such, and fields that aren't in the IDL are still decoded by their IDs.
+
+use crate::error;
+use crate::value;
+use ordered_float;
+use std::collections;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str;
+
+use self::compact::Read as _;
+
+pub(crate) mod compact;
+
+/// How deeply structs and containers may be nested.
+const MAX_DEPTH: usize = 64;
+
+/// The Thrift protocols that structs can be encoded with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Protocol {
+    /// `TBinaryProtocol`, with fixed-size big-endian numbers.
+    Binary,
+    /// `TCompactProtocol`, with varints and field ID deltas.
+    Compact,
+}
+
+/// The types of a `.thrift` IDL file; includes, services and constants are ignored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Idl {
+    structs: collections::HashMap<String, Vec<IdlField>>,
+    enums: collections::HashMap<String, Vec<(i64, String)>>,
+    typedefs: collections::HashMap<String, IdlType>,
+}
+
+/// A source that reads a stream of structs, one after the other without any framing, like
+/// `TFileTransport` or `TMemoryBuffer` contain them.
+pub struct Source<'a, R>
+where
+    R: io::Read,
+{
+    input: R,
+    protocol: Protocol,
+    root: Option<(&'a Idl, IdlType)>,
+    enum_style: value::enums::Style,
+    offset: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct IdlField {
+    id: i64,
+    name: String,
+    field_type: IdlType,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum IdlType {
+    Base(&'static str),
+    List(Box<IdlType>),
+    Set(Box<IdlType>),
+    Map(Box<IdlType>, Box<IdlType>),
+    Named(String),
+}
+
+/// The types of values on the wire, which are numbered differently by each protocol.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum WireType {
+    Bool,
+    Byte,
+    Double,
+    I16,
+    I32,
+    I64,
+    String,
+    Struct,
+    Map,
+    Set,
+    List,
+    Uuid,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Literal(String),
+    Punct(char),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl str::FromStr for Protocol {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(Protocol::Binary),
+            "compact" => Ok(Protocol::Compact),
+            _ => Err(error::Error::Message(format!(
+                "unknown Thrift protocol {:?}, expected 'binary' or 'compact'",
+                s
+            ))),
+        }
+    }
+}
+
+impl Idl {
+    pub fn parse(idl: &str) -> error::Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(idl)?,
+            pos: 0,
+        };
+        let mut result = Idl::default();
+        while let Some(token) = parser.next() {
+            let keyword = match token {
+                Token::Ident(keyword) => keyword,
+                token => return Err(bad_idl(format!("unexpected {:?}", token))),
+            };
+            match keyword.as_str() {
+                "include" | "cpp_include" => {
+                    parser.next();
+                }
+                "namespace" => {
+                    parser.next();
+                    parser.next();
+                }
+                "typedef" => {
+                    let t = parser.field_type()?;
+                    let name = parser.ident()?;
+                    parser.skip_annotations()?;
+                    parser.separator();
+                    result.typedefs.insert(name, t);
+                }
+                "const" => {
+                    parser.field_type()?;
+                    parser.ident()?;
+                    parser.punct('=')?;
+                    parser.skip_value()?;
+                    parser.separator();
+                }
+                "enum" => {
+                    let name = parser.ident()?;
+                    parser.punct('{')?;
+                    let mut values = Vec::new();
+                    let mut next = 0;
+                    while !parser.eat('}') {
+                        let symbol = parser.ident()?;
+                        if parser.eat('=') {
+                            next = parser.number()?;
+                        }
+                        values.push((next, symbol));
+                        next += 1;
+                        parser.skip_annotations()?;
+                        parser.separator();
+                    }
+                    parser.skip_annotations()?;
+                    result.enums.insert(name, values);
+                }
+                "struct" | "union" | "exception" => {
+                    let name = parser.ident()?;
+                    if parser.peek() == Some(&Token::Ident("xsd_all".to_owned())) {
+                        parser.next();
+                    }
+                    parser.punct('{')?;
+                    let mut fields = Vec::new();
+                    while !parser.eat('}') {
+                        fields.push(parser.field()?);
+                    }
+                    parser.skip_annotations()?;
+                    result.structs.insert(name, fields);
+                }
+                "senum" | "service" => {
+                    parser.ident()?;
+                    if parser.peek() == Some(&Token::Ident("extends".to_owned())) {
+                        parser.next();
+                        parser.ident()?;
+                    }
+                    if parser.peek() != Some(&Token::Punct('{')) {
+                        return Err(bad_idl(format!("expected '{{' after {} name", keyword)));
+                    }
+                    parser.skip_value()?;
+                    parser.skip_annotations()?;
+                }
+                _ => return Err(bad_idl(format!("unexpected {:?}", keyword))),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Follows typedefs, and strips the name of the included file from names like `other.Foo`,
+    /// since all types are looked up in this IDL.
+    fn resolve<'b>(&'b self, mut t: &'b IdlType) -> &'b IdlType {
+        for _ in 0..MAX_DEPTH {
+            match *t {
+                IdlType::Named(ref name) => match self.typedefs.get(local_name(name)) {
+                    Some(target) => t = target,
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+        t
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+#[inline]
+pub fn source<'a, R>(input: R, protocol: Protocol) -> Source<'a, R>
+where
+    R: io::Read,
+{
+    Source {
+        input,
+        protocol,
+        root: None,
+        enum_style: value::enums::Style::default(),
+        offset: 0,
+    }
+}
+
+impl<'a, R> Source<'a, R>
+where
+    R: io::Read,
+{
+    /// Decodes the structs as the struct type with this name in an IDL.
+    pub fn idl(mut self, idl: &'a Idl, name: &str) -> error::Result<Self> {
+        let root = IdlType::Named(name.to_owned());
+        match *idl.resolve(&root) {
+            IdlType::Named(ref n) if idl.structs.contains_key(local_name(n)) => {}
+            _ => {
+                return Err(error::Error::Message(format!(
+                    "unknown Thrift struct type {:?}",
+                    name
+                )))
+            }
+        }
+        self.root = Some((idl, root));
+        Ok(self)
+    }
+
+    /// How to output the values of enums, when decoding with an IDL.
+    pub fn enum_style(mut self, enum_style: value::enums::Style) -> Self {
+        self.enum_style = enum_style;
+        self
+    }
+
+    fn bytes<const N: usize>(&mut self) -> error::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.input
+            .read_exact(&mut bytes)
+            .map_err(|_| self.error("it is truncated".to_owned()))?;
+        self.offset += N as u64;
+        Ok(bytes)
+    }
+
+    fn wire_type(&self, n: u8) -> error::Result<WireType> {
+        let t = match (self.protocol, n) {
+            (Protocol::Binary, 2)
+            | (Protocol::Compact, compact::BOOL_TRUE)
+            | (Protocol::Compact, compact::BOOL_FALSE) => WireType::Bool,
+            (Protocol::Binary, 3) | (Protocol::Compact, compact::BYTE) => WireType::Byte,
+            (Protocol::Binary, 4) | (Protocol::Compact, compact::DOUBLE) => WireType::Double,
+            (Protocol::Binary, 6) | (Protocol::Compact, compact::I16) => WireType::I16,
+            (Protocol::Binary, 8) | (Protocol::Compact, compact::I32) => WireType::I32,
+            (Protocol::Binary, 10) | (Protocol::Compact, compact::I64) => WireType::I64,
+            (Protocol::Binary, 11) | (Protocol::Compact, compact::BINARY) => WireType::String,
+            (Protocol::Binary, 12) | (Protocol::Compact, compact::STRUCT) => WireType::Struct,
+            (Protocol::Binary, 13) | (Protocol::Compact, compact::MAP) => WireType::Map,
+            (Protocol::Binary, 14) | (Protocol::Compact, compact::SET) => WireType::Set,
+            (Protocol::Binary, 15) | (Protocol::Compact, compact::LIST) => WireType::List,
+            (Protocol::Binary, 16) | (Protocol::Compact, compact::UUID) => WireType::Uuid,
+            _ => return Err(self.error(format!("unknown type {}", n))),
+        };
+        Ok(t)
+    }
+
+    fn size(&mut self) -> error::Result<usize> {
+        match self.protocol {
+            Protocol::Binary => {
+                let size = i32::from_be_bytes(self.bytes()?);
+                usize::try_from(size).map_err(|_| self.error(format!("bad size {}", size)))
+            }
+            Protocol::Compact => compact::Read::size(self),
+        }
+    }
+
+    /// The header of a list or a set: the type of its elements and its size.
+    fn collection_header(&mut self) -> error::Result<(WireType, usize)> {
+        match self.protocol {
+            Protocol::Binary => {
+                let t = self.byte()?;
+                Ok((self.wire_type(t)?, self.size()?))
+            }
+            Protocol::Compact => {
+                let (t, size) = self.list_header()?;
+                Ok((self.wire_type(t)?, size))
+            }
+        }
+    }
+
+    /// The header of a map: the types of its keys and values, and its size.
+    fn map_header(&mut self) -> error::Result<(WireType, WireType, usize)> {
+        match self.protocol {
+            Protocol::Binary => {
+                let [k, v] = self.bytes()?;
+                let (k, v) = (self.wire_type(k)?, self.wire_type(v)?);
+                Ok((k, v, self.size()?))
+            }
+            Protocol::Compact => match compact::Read::map_header(self)? {
+                (_, _, 0) => Ok((WireType::Bool, WireType::Bool, 0)),
+                (k, v, size) => Ok((self.wire_type(k)?, self.wire_type(v)?, size)),
+            },
+        }
+    }
+
+    fn value(
+        &mut self,
+        wire_type: WireType,
+        hint: Option<&IdlType>,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        use crate::value::Value;
+
+        if depth > MAX_DEPTH {
+            return Err(self.error("it is nested too deeply".to_owned()));
+        }
+        let root = self.root.as_ref().map(|(idl, _)| *idl);
+        let hint = match (root, hint) {
+            (Some(idl), Some(hint)) => Some(idl.resolve(hint)),
+            _ => None,
+        };
+
+        Ok(match wire_type {
+            WireType::Bool => Value::Bool(self.byte()? == 1),
+            WireType::Byte => Value::I8(self.byte()? as i8),
+            WireType::Double => Value::F64(ordered_float::OrderedFloat(match self.protocol {
+                Protocol::Binary => f64::from_be_bytes(self.bytes()?),
+                Protocol::Compact => f64::from_le_bytes(self.bytes()?),
+            })),
+            WireType::I16 => Value::I16(match self.protocol {
+                Protocol::Binary => i16::from_be_bytes(self.bytes()?),
+                Protocol::Compact => self.zigzag()? as i16,
+            }),
+            WireType::I32 => {
+                let n = match self.protocol {
+                    Protocol::Binary => i32::from_be_bytes(self.bytes()?),
+                    Protocol::Compact => self.zigzag()? as i32,
+                };
+                match (root, hint) {
+                    (Some(idl), Some(IdlType::Named(name))) => self.enum_value(idl, name, n),
+                    _ => Value::I32(n),
+                }
+            }
+            WireType::I64 => Value::I64(match self.protocol {
+                Protocol::Binary => i64::from_be_bytes(self.bytes()?),
+                Protocol::Compact => self.zigzag()?,
+            }),
+            WireType::String => {
+                let len = self.size()?;
+                let mut bytes = Vec::new();
+                io::Read::read_to_end(
+                    &mut io::Read::take(&mut self.input, len as u64),
+                    &mut bytes,
+                )?;
+                if bytes.len() != len {
+                    return Err(self.error("a string is truncated".to_owned()));
+                }
+                self.offset += len as u64;
+                match (hint, String::from_utf8(bytes)) {
+                    (Some(IdlType::Base("binary")), Ok(s)) => Value::Bytes(s.into_bytes()),
+                    (_, Ok(s)) => Value::String(s),
+                    (_, Err(e)) => Value::Bytes(e.into_bytes()),
+                }
+            }
+            WireType::Uuid => {
+                let b = self.bytes::<16>()?;
+                Value::String(format!(
+                    "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-\
+                     {:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+                    b[0],
+                    b[1],
+                    b[2],
+                    b[3],
+                    b[4],
+                    b[5],
+                    b[6],
+                    b[7],
+                    b[8],
+                    b[9],
+                    b[10],
+                    b[11],
+                    b[12],
+                    b[13],
+                    b[14],
+                    b[15]
+                ))
+            }
+            WireType::Struct => self.struct_value(hint, depth + 1)?,
+            WireType::List | WireType::Set => {
+                let (element_type, size) = self.collection_header()?;
+                let element_hint = match hint {
+                    Some(IdlType::List(t)) | Some(IdlType::Set(t)) => Some(&**t),
+                    _ => None,
+                };
+                let mut elements = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    elements.push(self.value(element_type, element_hint, depth + 1)?);
+                }
+                Value::Sequence(elements)
+            }
+            WireType::Map => {
+                let (key_type, value_type, size) = self.map_header()?;
+                let (key_hint, value_hint) = match hint {
+                    Some(IdlType::Map(k, v)) => (Some(&**k), Some(&**v)),
+                    _ => (None, None),
+                };
+                let mut entries = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    let k = self.value(key_type, key_hint, depth + 1)?;
+                    let v = self.value(value_type, value_hint, depth + 1)?;
+                    entries.push((k, v));
+                }
+                Value::Map(entries)
+            }
+        })
+    }
+
+    fn enum_value(&self, idl: &Idl, name: &str, n: i32) -> value::Value {
+        let symbol = idl.enums.get(local_name(name)).and_then(|values| {
+            values
+                .iter()
+                .find(|(v, _)| *v == i64::from(n))
+                .map(|(_, s)| s)
+        });
+        match (self.enum_style, symbol) {
+            (value::enums::Style::Symbol, Some(symbol)) => value::Value::String(symbol.clone()),
+            (value::enums::Style::Qualified, Some(symbol)) => {
+                value::Value::String(format!("{}.{}", local_name(name), symbol))
+            }
+            _ => value::Value::I32(n),
+        }
+    }
+
+    fn struct_value(
+        &mut self,
+        hint: Option<&IdlType>,
+        depth: usize,
+    ) -> error::Result<value::Value> {
+        let root = self.root.as_ref().map(|(idl, _)| *idl);
+        let fields = match (root, hint) {
+            (Some(idl), Some(IdlType::Named(name))) => idl.structs.get(local_name(name)),
+            _ => None,
+        };
+
+        let mut entries = Vec::new();
+        let mut last_id = 0i16;
+        loop {
+            let (id, wire_type, compact_bool) = match self.protocol {
+                Protocol::Binary => {
+                    let header = self.byte()?;
+                    if header == 0 {
+                        break;
+                    }
+                    let wire_type = self.wire_type(header)?;
+                    (i16::from_be_bytes(self.bytes()?), wire_type, None)
+                }
+                Protocol::Compact => match self.field_header(last_id)? {
+                    Some((id, kind)) => {
+                        // Booleans are stored in the types of their fields
+                        let compact_bool = match kind {
+                            compact::BOOL_TRUE => Some(true),
+                            compact::BOOL_FALSE => Some(false),
+                            _ => None,
+                        };
+                        (id, self.wire_type(kind)?, compact_bool)
+                    }
+                    None => break,
+                },
+            };
+            last_id = id;
+
+            let field = fields.and_then(|fields| fields.iter().find(|f| f.id == i64::from(id)));
+            let key = match field {
+                Some(field) => value::Value::String(field.name.clone()),
+                None => value::Value::I16(id),
+            };
+            let v = match compact_bool {
+                Some(b) => value::Value::Bool(b),
+                None => self.value(wire_type, field.map(|f| &f.field_type), depth)?,
+            };
+            entries.push((key, v));
+        }
+        Ok(value::Value::Map(entries))
+    }
+}
+
+impl<'a, R> compact::Read for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn byte(&mut self) -> error::Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn error(&self, msg: String) -> error::Error {
+        error::Error::Format {
+            msg: format!("bad Thrift struct at byte {}: {}", self.offset, msg),
+        }
+    }
+}
+
+impl<'a, R> value::Source for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        // Like protobuf messages, structs can't be empty, since they end with a stop field
+        let mut first = [0];
+        if self.input.read(&mut first)? == 0 {
+            return Ok(None);
+        }
+        let start = self.offset;
+        let mut input = io::Read::chain(&first[..], &mut self.input);
+        let mut source = Source {
+            input: &mut input,
+            protocol: self.protocol,
+            root: self.root.clone(),
+            enum_style: self.enum_style,
+            offset: start,
+        };
+        let hint = source.root.as_ref().map(|(_, root)| root.clone());
+        let v = source.struct_value(hint.as_ref(), 0)?;
+        self.offset = source.offset;
+        Ok(Some(v))
+    }
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn punct(&mut self, c: char) -> error::Result<()> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            token => Err(bad_idl(format!("expected {:?}, got {:?}", c, token))),
+        }
+    }
+
+    fn ident(&mut self) -> error::Result<String> {
+        match self.next() {
+            Some(Token::Ident(ident)) => Ok(ident),
+            token => Err(bad_idl(format!("expected a name, got {:?}", token))),
+        }
+    }
+
+    fn number(&mut self) -> error::Result<i64> {
+        match self.next() {
+            Some(Token::Number(n)) => {
+                let parsed = match n.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16).ok(),
+                    None => n.parse().ok(),
+                };
+                parsed.ok_or_else(|| bad_idl(format!("bad integer {:?}", n)))
+            }
+            token => Err(bad_idl(format!("expected an integer, got {:?}", token))),
+        }
+    }
+
+    fn separator(&mut self) {
+        if !self.eat(',') {
+            self.eat(';');
+        }
+    }
+
+    /// Skips a value, or a block or list in brackets, braces or parentheses.
+    fn skip_value(&mut self) -> error::Result<()> {
+        let mut nesting = 0usize;
+        loop {
+            match self.next() {
+                Some(Token::Punct('[')) | Some(Token::Punct('{')) | Some(Token::Punct('(')) => {
+                    nesting += 1
+                }
+                Some(Token::Punct(']')) | Some(Token::Punct('}')) | Some(Token::Punct(')')) => {
+                    nesting = nesting
+                        .checked_sub(1)
+                        .ok_or_else(|| bad_idl("unbalanced brackets".to_owned()))?
+                }
+                Some(_) => {}
+                None => return Err(bad_idl("unexpected end".to_owned())),
+            }
+            if nesting == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_annotations(&mut self) -> error::Result<()> {
+        if self.peek() == Some(&Token::Punct('(')) {
+            self.skip_value()?;
+        }
+        Ok(())
+    }
+
+    fn cpp_type(&mut self) {
+        if self.peek() == Some(&Token::Ident("cpp_type".to_owned())) {
+            self.pos += 2;
+        }
+    }
+
+    fn field(&mut self) -> error::Result<IdlField> {
+        let id = match self.peek() {
+            Some(Token::Number(_)) => {
+                let id = self.number()?;
+                self.punct(':')?;
+                id
+            }
+            _ => return Err(bad_idl("fields without IDs aren't supported".to_owned())),
+        };
+        if let Some(Token::Ident(ident)) = self.peek() {
+            if ident == "required" || ident == "optional" {
+                self.pos += 1;
+            }
+        }
+        let field_type = self.field_type()?;
+        let name = self.ident()?;
+        if self.eat('=') {
+            self.skip_value()?;
+        }
+        self.skip_annotations()?;
+        self.separator();
+        Ok(IdlField {
+            id,
+            name,
+            field_type,
+        })
+    }
+
+    fn field_type(&mut self) -> error::Result<IdlType> {
+        let name = self.ident()?;
+        let t = match name.as_str() {
+            "list" | "set" => {
+                self.cpp_type();
+                self.punct('<')?;
+                let element = Box::new(self.field_type()?);
+                self.punct('>')?;
+                self.cpp_type();
+                if name == "list" {
+                    IdlType::List(element)
+                } else {
+                    IdlType::Set(element)
+                }
+            }
+            "map" => {
+                self.cpp_type();
+                self.punct('<')?;
+                let k = Box::new(self.field_type()?);
+                self.punct(',')?;
+                let v = Box::new(self.field_type()?);
+                self.punct('>')?;
+                IdlType::Map(k, v)
+            }
+            "bool" => IdlType::Base("bool"),
+            "byte" | "i8" => IdlType::Base("byte"),
+            "i16" => IdlType::Base("i16"),
+            "i32" => IdlType::Base("i32"),
+            "i64" => IdlType::Base("i64"),
+            "double" => IdlType::Base("double"),
+            "string" | "slist" => IdlType::Base("string"),
+            "binary" => IdlType::Base("binary"),
+            "uuid" => IdlType::Base("uuid"),
+            _ => IdlType::Named(name),
+        };
+        self.skip_annotations()?;
+        Ok(t)
+    }
+}
+
+fn tokenize(idl: &str) -> error::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = idl.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '#' => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => last = c,
+                        None => return Err(bad_idl("unterminated comment".to_owned())),
+                    }
+                }
+            }
+            '"' | '\'' => {
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') => literal.extend(chars.next()),
+                        Some(c) => literal.push(c),
+                        None => return Err(bad_idl("unterminated string".to_owned())),
+                    }
+                }
+                tokens.push(Token::Literal(literal));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' => {
+                let mut number = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+' {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '.' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => tokens.push(Token::Punct(c)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn bad_idl(msg: String) -> error::Error {
+    error::Error::Format {
+        msg: format!("bad Thrift IDL: {}", msg),
+    }
+}
+
+impl<'a, R> fmt::Debug for Source<'a, R>
+where
+    R: io::Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThriftSource")
+            .field("protocol", &self.protocol)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    const IDL: &str = r#"
+        namespace java com.example  # a comment
+        include "shared.thrift"
+
+        /* The colors
+           that there are */
+        enum Color { RED = 1, GREEN, BLUE = 0x10 (deprecated = "yes") }
+        typedef list<string> Tags
+        const map<string, i32> LIMITS = {"a": 1, "b": [2]};
+
+        struct Person {
+          1: required string name,
+          2: optional i32 age = 30;
+          3: Color favorite
+          4: Tags tags (python.type = "list")
+          5: binary avatar
+          6: map<string, Person> friends
+        }
+
+        service People extends shared.Base {
+          Person get(1: string name) throws (1: shared.Error e)
+        }
+    "#;
+
+    fn read_all(mut source: Source<&[u8]>) -> error::Result<Vec<Value>> {
+        let mut result = Vec::new();
+        while let Some(v) = source.read()? {
+            result.push(v);
+        }
+        Ok(result)
+    }
+
+    fn s(s: &str) -> Value {
+        Value::String(s.to_owned())
+    }
+
+    #[test]
+    fn test_idl() {
+        let idl = Idl::parse(IDL).unwrap();
+        assert_eq!(
+            idl.enums["Color"],
+            vec![
+                (1, "RED".to_owned()),
+                (2, "GREEN".to_owned()),
+                (16, "BLUE".to_owned())
+            ]
+        );
+        assert_eq!(
+            idl.structs["Person"][3],
+            IdlField {
+                id: 4,
+                name: "tags".to_owned(),
+                field_type: IdlType::Named("Tags".to_owned())
+            }
+        );
+        assert_eq!(
+            idl.resolve(&IdlType::Named("Tags".to_owned())),
+            &IdlType::List(Box::new(IdlType::Base("string")))
+        );
+        assert!(Idl::parse("struct Foo { 1: i32 }").is_err());
+        assert!(Idl::parse("struct Foo { i32 bar }").is_err());
+    }
+
+    /// A Person, with a friend with an unknown field.
+    fn binary() -> Vec<u8> {
+        let mut b = vec![11, 0, 1, 0, 0, 0, 3];
+        b.extend(b"Ada");
+        b.extend(&[8, 0, 2, 0, 0, 0, 36]);
+        b.extend(&[8, 0, 3, 0, 0, 0, 2]);
+        b.extend(&[15, 0, 4, 11, 0, 0, 0, 1, 0, 0, 0, 1, b'x']);
+        b.extend(&[11, 0, 5, 0, 0, 0, 1, 0xff]);
+        b.extend(&[13, 0, 6, 11, 12, 0, 0, 0, 1, 0, 0, 0, 1, b'b']);
+        b.extend(&[2, 0, 9, 1, 0]);
+        b.push(0);
+        b
+    }
+
+    #[test]
+    fn test_binary() {
+        let mut input = binary();
+        input.extend(&[4, 0, 1, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0, 0]);
+        let values = read_all(source(&input[..], Protocol::Binary)).unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Value::Map(vec![
+                    (Value::I16(1), s("Ada")),
+                    (Value::I16(2), Value::I32(36)),
+                    (Value::I16(3), Value::I32(2)),
+                    (Value::I16(4), Value::Sequence(vec![s("x")])),
+                    (Value::I16(5), Value::Bytes(vec![0xff])),
+                    (
+                        Value::I16(6),
+                        Value::Map(vec![(
+                            s("b"),
+                            Value::Map(vec![(Value::I16(9), Value::Bool(true))])
+                        )])
+                    ),
+                ]),
+                Value::Map(vec![(
+                    Value::I16(1),
+                    Value::F64(ordered_float::OrderedFloat(1.5))
+                )]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_binary_idl() {
+        let idl = Idl::parse(IDL).unwrap();
+        let input = binary();
+        let source = source(&input[..], Protocol::Binary)
+            .idl(&idl, "Person")
+            .unwrap();
+        assert_eq!(
+            read_all(source).unwrap(),
+            vec![Value::Map(vec![
+                (s("name"), s("Ada")),
+                (s("age"), Value::I32(36)),
+                (s("favorite"), s("GREEN")),
+                (s("tags"), Value::Sequence(vec![s("x")])),
+                (s("avatar"), Value::Bytes(vec![0xff])),
+                (
+                    s("friends"),
+                    Value::Map(vec![(
+                        s("b"),
+                        Value::Map(vec![(Value::I16(9), Value::Bool(true))])
+                    )])
+                ),
+            ])]
+        );
+
+        let source = super::source(&input[..], Protocol::Binary)
+            .idl(&idl, "Person")
+            .unwrap()
+            .enum_style(value::enums::Style::Qualified);
+        match read_all(source).unwrap()[0] {
+            Value::Map(ref entries) => assert_eq!(entries[2].1, s("Color.GREEN")),
+            ref v => panic!("expected a map, got {:?}", v),
+        }
+
+        assert!(super::source(&input[..], Protocol::Binary)
+            .idl(&idl, "Color")
+            .is_err());
+    }
+
+    #[test]
+    fn test_compact() {
+        let idl = Idl::parse(IDL).unwrap();
+        // Field 1 with a delta, a true boolean as field 9, a list of two booleans as field 30
+        // with an explicit ID, a nested struct with a stop field, and an i64 of -2
+        let mut input = vec![0x18, 3];
+        input.extend(b"Ada");
+        input.extend(&[0x81]);
+        input.extend(&[0x09, 60, 0x21, 1, 2]);
+        input.extend(&[0x1c, 0x15, 4, 0, 0x16, 3]);
+        input.push(0);
+        input.extend(&[0x25, 72, 0x15, 0xff, 0x01, 0]);
+
+        let values = read_all(source(&input[..], Protocol::Compact)).unwrap();
+        assert_eq!(
+            values[0],
+            Value::Map(vec![
+                (Value::I16(1), s("Ada")),
+                (Value::I16(9), Value::Bool(true)),
+                (
+                    Value::I16(30),
+                    Value::Sequence(vec![Value::Bool(true), Value::Bool(false)])
+                ),
+                (
+                    Value::I16(31),
+                    Value::Map(vec![(Value::I16(1), Value::I32(2))])
+                ),
+                (Value::I16(32), Value::I64(-2)),
+            ])
+        );
+
+        let source = source(&input[..], Protocol::Compact)
+            .idl(&idl, "Person")
+            .unwrap();
+        assert_eq!(
+            read_all(source).unwrap()[1],
+            Value::Map(vec![
+                (s("age"), Value::I32(36)),
+                (s("favorite"), Value::I32(-128)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: &[u8], protocol| match read_all(source(input, protocol)) {
+            Err(error::Error::Format { msg }) => msg,
+            r => panic!("expected an error, got {:?}", r),
+        };
+        assert_eq!(
+            error(&[8, 0, 1, 0], Protocol::Binary),
+            "bad Thrift struct at byte 3: it is truncated"
+        );
+        assert_eq!(
+            error(&[99, 0, 1], Protocol::Binary),
+            "bad Thrift struct at byte 1: unknown type 99"
+        );
+        assert_eq!(
+            error(&[15, 0, 1, 8, 0xff, 0xff, 0xff, 0xff], Protocol::Binary),
+            "bad Thrift struct at byte 8: bad size -1"
+        );
+        let mut nested = vec![0x1c; 100];
+        nested.extend(vec![0; 101]);
+        assert_eq!(
+            error(&nested, Protocol::Compact),
+            "bad Thrift struct at byte 66: it is nested too deeply"
+        );
+    }
+}
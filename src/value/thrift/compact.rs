@@ -0,0 +1,261 @@
+//! The Thrift compact protocol, which both Thrift structs and Parquet metadata are encoded with.
+
+use crate::error;
+
+pub const BOOL_TRUE: u8 = 1;
+pub const BOOL_FALSE: u8 = 2;
+pub const BYTE: u8 = 3;
+pub const I16: u8 = 4;
+pub const I32: u8 = 5;
+pub const I64: u8 = 6;
+pub const DOUBLE: u8 = 7;
+pub const BINARY: u8 = 8;
+pub const LIST: u8 = 9;
+pub const SET: u8 = 10;
+pub const MAP: u8 = 11;
+pub const STRUCT: u8 = 12;
+pub const UUID: u8 = 13;
+
+/// A decoder of the compact protocol, for anything that can read bytes and describe errors at
+/// the position it is at.
+pub trait Read {
+    fn byte(&mut self) -> error::Result<u8>;
+
+    fn error(&self, msg: String) -> error::Error;
+
+    fn varint(&mut self) -> error::Result<u64> {
+        let mut n = 0u64;
+        for i in 0..10 {
+            let b = self.byte()?;
+            n |= u64::from(b & 0x7f) << (7 * i);
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(self.error("a varint is too long".to_owned()))
+    }
+
+    fn zigzag(&mut self) -> error::Result<i64> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    /// Reads the size of a binary or a collection.
+    fn size(&mut self) -> error::Result<usize> {
+        let size = self.varint()?;
+        if size > i32::MAX as u64 {
+            return Err(self.error(format!("bad size {}", size)));
+        }
+        Ok(size as usize)
+    }
+
+    /// Reads the header of the struct field after the one with ID `last_id`, returning the ID and
+    /// the type of the field, or `None` for the stop field that ends the struct.  The values of
+    /// boolean fields are their types, `BOOL_TRUE` or `BOOL_FALSE`.
+    fn field_header(&mut self, last_id: i16) -> error::Result<Option<(i16, u8)>> {
+        let header = self.byte()?;
+        if header == 0 {
+            return Ok(None);
+        }
+        let id = match header >> 4 {
+            0 => self.zigzag()? as i16,
+            delta => last_id.wrapping_add(i16::from(delta)),
+        };
+        Ok(Some((id, header & 0x0f)))
+    }
+
+    /// Reads the header of a list or a set: the type of its elements and its size.
+    fn list_header(&mut self) -> error::Result<(u8, usize)> {
+        let header = self.byte()?;
+        let size = match header >> 4 {
+            15 => self.size()?,
+            size => usize::from(size),
+        };
+        Ok((header & 0x0f, size))
+    }
+
+    /// Reads the header of a map: the types of its keys and values, which are 0 if it is empty,
+    /// and its size.
+    fn map_header(&mut self) -> error::Result<(u8, u8, usize)> {
+        let size = self.size()?;
+        if size == 0 {
+            return Ok((0, 0, 0));
+        }
+        let types = self.byte()?;
+        Ok((types >> 4, types & 0x0f, size))
+    }
+}
+
+/// An encoder of the compact protocol, which keeps track of the last field ID of the struct that
+/// is being written.
+#[derive(Default)]
+pub struct Writer {
+    out: Vec<u8>,
+    last: i16,
+}
+
+impl Writer {
+    pub fn bytes(&self) -> &[u8] {
+        &self.out
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.out
+    }
+
+    pub fn header(&mut self, id: i16, kind: u8) {
+        match id - self.last {
+            delta @ 1..=15 => self.out.push((delta as u8) << 4 | kind),
+            _ => {
+                self.out.push(kind);
+                self.zigzag(id.into());
+            }
+        }
+        self.last = id;
+    }
+
+    pub fn zigzag(&mut self, n: i64) {
+        write_varint(((n << 1) ^ (n >> 63)) as u64, &mut self.out);
+    }
+
+    pub fn string(&mut self, bytes: &[u8]) {
+        write_varint(bytes.len() as u64, &mut self.out);
+        self.out.extend_from_slice(bytes);
+    }
+
+    pub fn bool(&mut self, id: i16, b: bool) {
+        self.header(id, if b { BOOL_TRUE } else { BOOL_FALSE });
+    }
+
+    pub fn byte(&mut self, id: i16, n: i8) {
+        self.header(id, BYTE);
+        self.out.push(n as u8);
+    }
+
+    pub fn i32(&mut self, id: i16, n: i32) {
+        self.header(id, I32);
+        self.zigzag(n.into());
+    }
+
+    pub fn i64(&mut self, id: i16, n: i64) {
+        self.header(id, I64);
+        self.zigzag(n);
+    }
+
+    pub fn binary(&mut self, id: i16, bytes: &[u8]) {
+        self.header(id, BINARY);
+        self.string(bytes);
+    }
+
+    pub fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.header(id, LIST);
+        self.list_header(kind, len);
+    }
+
+    /// Writes the header of a list or a set that isn't a struct field.
+    pub fn list_header(&mut self, kind: u8, len: usize) {
+        if len < 15 {
+            self.out.push((len as u8) << 4 | kind);
+        } else {
+            self.out.push(0xf0 | kind);
+            write_varint(len as u64, &mut self.out);
+        }
+    }
+
+    pub fn structure<F>(&mut self, id: i16, f: F)
+    where
+        F: FnOnce(&mut Self),
+    {
+        self.header(id, STRUCT);
+        self.fields(f)
+    }
+
+    /// Writes the fields of a struct and the stop byte that ends it.
+    pub fn fields<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Self),
+    {
+        let last = std::mem::replace(&mut self.last, 0);
+        f(self);
+        self.out.push(0);
+        self.last = last;
+    }
+}
+
+pub fn write_varint(mut n: u64, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Bytes<'a>(&'a [u8]);
+
+    impl<'a> Read for Bytes<'a> {
+        fn byte(&mut self) -> error::Result<u8> {
+            let (&b, rest) = self
+                .0
+                .split_first()
+                .ok_or_else(|| self.error("it is truncated".to_owned()))?;
+            self.0 = rest;
+            Ok(b)
+        }
+
+        fn error(&self, msg: String) -> error::Error {
+            error::Error::Format { msg }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut w = Writer::default();
+        w.fields(|w| {
+            w.i32(1, -3);
+            w.bool(2, true);
+            w.i64(40, 1 << 40);
+            w.list(41, I32, 20);
+            for n in 0..20 {
+                w.zigzag(n);
+            }
+            w.header(42, MAP);
+        });
+        let mut bytes = w.into_bytes();
+        bytes.pop();
+        // A map from "k" to true, and an empty map
+        bytes.extend_from_slice(&[1, BINARY << 4 | BOOL_TRUE, 1, b'k', 1, 0x10 | MAP, 0, 0]);
+
+        let mut r = Bytes(&bytes);
+        assert_eq!(r.field_header(0).unwrap(), Some((1, I32)));
+        assert_eq!(r.zigzag().unwrap(), -3);
+        assert_eq!(r.field_header(1).unwrap(), Some((2, BOOL_TRUE)));
+        assert_eq!(r.field_header(2).unwrap(), Some((40, I64)));
+        assert_eq!(r.zigzag().unwrap(), 1 << 40);
+        assert_eq!(r.field_header(40).unwrap(), Some((41, LIST)));
+        assert_eq!(r.list_header().unwrap(), (I32, 20));
+        for n in 0..20 {
+            assert_eq!(r.zigzag().unwrap(), n);
+        }
+        assert_eq!(r.field_header(41).unwrap(), Some((42, MAP)));
+        assert_eq!(r.map_header().unwrap(), (BINARY, BOOL_TRUE, 1));
+        assert_eq!(r.size().unwrap(), 1);
+        assert_eq!(r.byte().unwrap(), b'k');
+        assert_eq!(r.byte().unwrap(), 1);
+        assert_eq!(r.field_header(42).unwrap(), Some((43, MAP)));
+        assert_eq!(r.map_header().unwrap(), (0, 0, 0));
+        assert_eq!(r.field_header(43).unwrap(), None);
+        assert!(r.byte().is_err());
+    }
+
+    #[test]
+    fn test_bad_varint() {
+        let mut r = Bytes(&[0xff; 11]);
+        assert!(r.varint().is_err());
+        let mut r = Bytes(&[0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert!(r.size().is_err());
+    }
+}
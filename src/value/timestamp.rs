@@ -0,0 +1,474 @@
+use crate::error;
+use crate::value;
+use crate::value::path;
+use std::fmt;
+use std::str;
+
+/// A source that normalizes all recognized timestamps in the records of another source.
+///
+/// Strings are recognized if they are RFC 3339/ISO 8601 dates (`2006-01-02`) or date-times
+/// (`2006-01-02T15:04:05.999Z`, also with a space instead of `T`, without seconds, or without an
+/// offset, in which case the assumed offset is used).  Numbers are only recognized at the paths
+/// in `Options::epoch_fields`, since IDs, amounts and counters look just like epochs: there,
+/// non-negative numbers between `1e8` and `1e11` are seconds and ones between `1e11` and `1e14`
+/// are milliseconds, which covers the years 1973 to 5138.  Map keys are never normalized.
+pub type Source<S> = value::transform::Source<S, Options>;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    pub target: Representation,
+    /// The UTC offset in seconds of timestamps that don't specify one.
+    pub assume_offset: i32,
+    /// The paths of the values whose numbers are epochs.
+    pub epoch_fields: Vec<path::Path>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Representation {
+    /// An RFC 3339 string in UTC, like `2006-01-02T15:04:05.999Z`.
+    Rfc3339,
+    /// An integer number of milliseconds since the Unix epoch.
+    EpochMillis,
+}
+
+/// A point in time as seconds and nanoseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Timestamp {
+    seconds: i64,
+    nanos: u32,
+}
+
+#[inline]
+pub fn source<S>(inner: S, options: Options) -> Source<S>
+where
+    S: value::Source,
+{
+    value::transform::source(inner, options)
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 string in UTC, like
+/// `2006-01-02T15:04:05Z`.
+pub fn rfc3339(seconds: i64) -> String {
+    Timestamp { seconds, nanos: 0 }.to_string()
+}
+
+/// Formats a point in time since the Unix epoch as an RFC 3339 string in UTC, with as many
+/// fractional digits as needed, like `2006-01-02T15:04:05.999Z`.
+pub fn rfc3339_nanos(seconds: i64, nanos: u32) -> String {
+    Timestamp { seconds, nanos }.to_string()
+}
+
+/// Parses an RFC 3339 date-time or an ISO 8601 date, like `2006-01-02T15:04:05.999Z`, into
+/// seconds and nanoseconds since the Unix epoch, assuming UTC if there is no offset.
+pub fn parse_rfc3339(s: &str) -> Option<(i64, u32)> {
+    parse_timestamp(s.as_bytes(), 0).map(|t| (t.seconds, t.nanos))
+}
+
+/// Formats days since the Unix epoch as an ISO 8601 date, like `2006-01-02`.
+pub fn iso_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Parses a time zone like `UTC`, `Z`, `+02:00` or `-0530` into an offset in seconds.
+pub fn parse_offset(s: &str) -> error::Result<i32> {
+    if s == "UTC" || s == "Z" {
+        return Ok(0);
+    }
+    parse_numeric_offset(s.as_bytes()).ok_or_else(|| error::Error::Format {
+        msg: format!("unrecognized time zone: {}", s),
+    })
+}
+
+impl str::FromStr for Representation {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rfc3339" => Ok(Self::Rfc3339),
+            "epoch-millis" => Ok(Self::EpochMillis),
+            _ => Err(error::Error::Message(format!(
+                "unrecognized timestamp representation: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl value::Transform for Options {
+    #[inline]
+    fn transform(&mut self, mut v: value::Value) -> error::Result<Option<value::Value>> {
+        for path in &self.epoch_fields {
+            if let Some(target) = path.select_mut(&mut v) {
+                if let Some(t) = epoch(target) {
+                    *target = represent(t, self.target);
+                }
+            }
+        }
+        Ok(Some(normalize(v, self)))
+    }
+}
+
+/// Normalizes all recognized date/time strings in the value.
+fn normalize(value: value::Value, options: &Options) -> value::Value {
+    use crate::value::Value;
+
+    let timestamp = match value {
+        Value::Sequence(seq) => {
+            return Value::Sequence(seq.into_iter().map(|v| normalize(v, options)).collect())
+        }
+        Value::Map(map) => {
+            return Value::Map(
+                map.into_iter()
+                    .map(|(k, v)| (k, normalize(v, options)))
+                    .collect(),
+            )
+        }
+        Value::String(ref s) => parse_timestamp(s.as_bytes(), options.assume_offset),
+        _ => None,
+    };
+
+    match timestamp {
+        Some(t) => represent(t, options.target),
+        None => value,
+    }
+}
+
+fn epoch(value: &value::Value) -> Option<Timestamp> {
+    use crate::value::Value;
+
+    match *value {
+        Value::I32(v) => from_epoch(f64::from(v)),
+        Value::I64(v) => from_epoch(v as f64),
+        Value::U32(v) => from_epoch(f64::from(v)),
+        Value::U64(v) => from_epoch(v as f64),
+        Value::F32(v) => from_epoch(f64::from(v.0)),
+        Value::F64(v) => from_epoch(v.0),
+        _ => None,
+    }
+}
+
+fn represent(t: Timestamp, target: Representation) -> value::Value {
+    match target {
+        Representation::Rfc3339 => value::Value::String(t.to_string()),
+        Representation::EpochMillis => {
+            value::Value::I64(t.seconds * 1000 + i64::from(t.nanos / 1_000_000))
+        }
+    }
+}
+
+fn from_epoch(v: f64) -> Option<Timestamp> {
+    let (units_per_second, nanos_per_unit) = if (1e8..1e11).contains(&v) {
+        (1.0, 1e9)
+    } else if (1e11..1e14).contains(&v) {
+        (1000.0, 1e6)
+    } else {
+        return None;
+    };
+
+    // Split before scaling to keep integral epochs exact
+    let seconds = (v / units_per_second).trunc();
+    let rest = v - seconds * units_per_second;
+    Some(Timestamp {
+        seconds: seconds as i64,
+        nanos: (rest * nanos_per_unit).round().min(999_999_999.0) as u32,
+    })
+}
+
+fn parse_timestamp(s: &[u8], assume_offset: i32) -> Option<Timestamp> {
+    let year = parse_digits(s.get(0..4)?)?;
+    let month = parse_digits(after(s, 4, b'-', 2)?)?;
+    let day = parse_digits(after(s, 7, b'-', 2)?)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+
+    if s.len() == 10 {
+        return Some(Timestamp {
+            seconds: days * 86400 - i64::from(assume_offset),
+            nanos: 0,
+        });
+    }
+
+    if s[10] != b'T' && s[10] != b't' && s[10] != b' ' {
+        return None;
+    }
+    let hour = parse_digits(s.get(11..13)?)?;
+    let minute = parse_digits(after(s, 13, b':', 2)?)?;
+    let mut rest = &s[16..];
+
+    let mut second = 0;
+    let mut nanos = 0;
+    if rest.first() == Some(&b':') {
+        second = parse_digits(rest.get(1..3)?)?;
+        rest = &rest[3..];
+        if rest.first() == Some(&b'.') || rest.first() == Some(&b',') {
+            let len = rest[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+            if len == 0 {
+                return None;
+            }
+            // Digits beyond nanosecond precision are truncated
+            let digits = &rest[1..1 + len];
+            for i in 0..9 {
+                nanos = nanos * 10 + digits.get(i).map_or(0, |b| u32::from(b - b'0'));
+            }
+            rest = &rest[1 + len..];
+        }
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let offset = match rest {
+        b"" => assume_offset,
+        b"Z" | b"z" => 0,
+        _ => parse_numeric_offset(rest)?,
+    };
+
+    Some(Timestamp {
+        seconds: days * 86400 + hour * 3600 + minute * 60 + second - i64::from(offset),
+        nanos,
+    })
+}
+
+/// Returns the `len` bytes after the separator at `index`.
+fn after(s: &[u8], index: usize, separator: u8, len: usize) -> Option<&[u8]> {
+    if *s.get(index)? == separator {
+        s.get(index + 1..index + 1 + len)
+    } else {
+        None
+    }
+}
+
+fn parse_numeric_offset(s: &[u8]) -> Option<i32> {
+    let sign = match *s.first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let (hours, minutes) = match s.len() {
+        3 => (&s[1..3], &b"00"[..]),
+        5 => (&s[1..3], &s[3..5]),
+        6 if s[3] == b':' => (&s[1..3], &s[4..6]),
+        _ => return None,
+    };
+    let hours = parse_digits(hours)?;
+    let minutes = parse_digits(minutes)?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60) as i32)
+}
+
+fn parse_digits(s: &[u8]) -> Option<i64> {
+    if s.iter().all(u8::is_ascii_digit) {
+        str::from_utf8(s).ok()?.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Conversions between proleptic Gregorian dates and days since the Unix epoch; see
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let days = self.seconds.div_euclid(86400);
+        let second_of_day = self.seconds.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            second_of_day / 3600,
+            second_of_day / 60 % 60,
+            second_of_day % 60
+        )?;
+        if self.nanos % 1_000_000 == 0 && self.nanos != 0 {
+            write!(f, ".{:03}", self.nanos / 1_000_000)?;
+        } else if self.nanos % 1000 == 0 && self.nanos != 0 {
+            write!(f, ".{:06}", self.nanos / 1000)?;
+        } else if self.nanos != 0 {
+            write!(f, ".{:09}", self.nanos)?;
+        }
+        write!(f, "Z")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    const RFC3339: Options = Options {
+        target: Representation::Rfc3339,
+        assume_offset: 0,
+        epoch_fields: Vec::new(),
+    };
+
+    const EPOCH_MILLIS: Options = Options {
+        target: Representation::EpochMillis,
+        assume_offset: 0,
+        epoch_fields: Vec::new(),
+    };
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    #[test]
+    fn test_strings_to_rfc3339() {
+        let cases = [
+            ("2006-01-02T15:04:05Z", "2006-01-02T15:04:05Z"),
+            ("2006-01-02t15:04:05.5+01:00", "2006-01-02T14:04:05.500Z"),
+            ("2006-01-02 15:04", "2006-01-02T15:04:00Z"),
+            (
+                "2006-01-02T15:04:05.000001-0700",
+                "2006-01-02T22:04:05.000001Z",
+            ),
+            ("2006-01-02", "2006-01-02T00:00:00Z"),
+            ("1969-12-31T23:59:59Z", "1969-12-31T23:59:59Z"),
+            ("2000-02-29T00:00:00Z", "2000-02-29T00:00:00Z"),
+        ];
+        for &(input, expected) in &cases {
+            assert_eq!(normalize(s(input), &RFC3339), s(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_unrecognized_strings() {
+        for input in &[
+            "2006-01-02T",
+            "2006-13-02",
+            "2001-02-29",
+            "2006-01-02T24:00:00Z",
+            "2006-01-02T15:04:05+",
+            "2006-01-02T15:04:05.Z",
+            "hello",
+            "",
+        ] {
+            assert_eq!(normalize(s(input), &RFC3339), s(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_epochs() {
+        use crate::value::Transform as _;
+
+        let mut options = Options {
+            epoch_fields: vec![
+                path::Path::parse(".at").unwrap(),
+                path::Path::parse(".millis").unwrap(),
+                path::Path::parse(".small").unwrap(),
+            ],
+            ..RFC3339
+        };
+        let value = Value::Map(vec![
+            (s("at"), Value::I64(1_136_214_245)),
+            (s("millis"), Value::U64(1_136_214_245_123)),
+            (s("small"), Value::I64(42)),
+            (s("id"), Value::I64(1_136_214_245)),
+        ]);
+        assert_eq!(
+            options.transform(value).unwrap(),
+            Some(Value::Map(vec![
+                (s("at"), s("2006-01-02T15:04:05Z")),
+                (s("millis"), s("2006-01-02T15:04:05.123Z")),
+                (s("small"), Value::I64(42)),
+                (s("id"), Value::I64(1_136_214_245)),
+            ]))
+        );
+
+        let mut options = Options {
+            epoch_fields: vec![path::Path::parse(".").unwrap()],
+            ..EPOCH_MILLIS
+        };
+        assert_eq!(
+            options
+                .transform(Value::from_f64(1_136_214_245.25))
+                .unwrap(),
+            Some(Value::I64(1_136_214_245_250))
+        );
+    }
+
+    #[test]
+    fn test_numbers_only_at_epoch_fields() {
+        let value = Value::Sequence(vec![Value::I64(1_136_214_245), Value::U64(4_155_550_100)]);
+        assert_eq!(normalize(value.clone(), &RFC3339), value);
+    }
+
+    #[test]
+    fn test_assume_offset() {
+        let options = Options {
+            target: Representation::EpochMillis,
+            assume_offset: parse_offset("+02:00").unwrap(),
+            epoch_fields: Vec::new(),
+        };
+        assert_eq!(normalize(s("1970-01-01T02:00:00"), &options), Value::I64(0));
+        assert_eq!(
+            normalize(s("1970-01-01T02:00:00Z"), &options),
+            Value::I64(7_200_000)
+        );
+    }
+
+    #[test]
+    fn test_nested_values_but_not_keys() {
+        let value = Value::Map(vec![(
+            s("2006-01-02"),
+            Value::Sequence(vec![s("2006-01-02")]),
+        )]);
+        assert_eq!(
+            normalize(value, &EPOCH_MILLIS),
+            Value::Map(vec![(
+                s("2006-01-02"),
+                Value::Sequence(vec![Value::I64(1_136_160_000_000)])
+            )])
+        );
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("UTC").unwrap(), 0);
+        assert_eq!(parse_offset("-05:30").unwrap(), -19800);
+        assert_eq!(parse_offset("+0100").unwrap(), 3600);
+        assert!(parse_offset("Europe/Stockholm").is_err());
+    }
+}
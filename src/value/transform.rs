@@ -0,0 +1,170 @@
+use crate::error;
+use crate::value;
+use std::fmt;
+
+/// A source that applies a transform to the records of another source, skipping the records that
+/// the transform drops.
+pub struct Source<S, T>(S, T);
+
+/// A transform that applies one transform and then another one.
+#[derive(Clone, Debug)]
+pub struct Chain<A, B>(A, B);
+
+/// A transform that calls a closure.
+pub struct FnTransform<F>(F);
+
+#[inline]
+pub fn source<S, T>(inner: S, transform: T) -> Source<S, T>
+where
+    S: value::Source,
+    T: value::Transform,
+{
+    Source(inner, transform)
+}
+
+#[inline]
+pub fn chain<A, B>(first: A, second: B) -> Chain<A, B>
+where
+    A: value::Transform,
+    B: value::Transform,
+{
+    Chain(first, second)
+}
+
+#[inline]
+pub fn from_fn<F>(f: F) -> FnTransform<F>
+where
+    F: FnMut(value::Value) -> error::Result<Option<value::Value>>,
+{
+    FnTransform(f)
+}
+
+impl<S, T> value::Source for Source<S, T>
+where
+    S: value::Source,
+    T: value::Transform,
+{
+    #[inline]
+    fn read(&mut self) -> error::Result<Option<value::Value>> {
+        while let Some(v) = self.0.read()? {
+            if let Some(v) = self.1.transform(v)? {
+                return Ok(Some(v));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<A, B> value::Transform for Chain<A, B>
+where
+    A: value::Transform,
+    B: value::Transform,
+{
+    #[inline]
+    fn transform(&mut self, v: value::Value) -> error::Result<Option<value::Value>> {
+        match self.0.transform(v)? {
+            Some(v) => self.1.transform(v),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Applies the transforms in order, like nested `Chain`s would.
+impl<T> value::Transform for Vec<T>
+where
+    T: value::Transform,
+{
+    fn transform(&mut self, v: value::Value) -> error::Result<Option<value::Value>> {
+        let mut v = v;
+        for transform in self.iter_mut() {
+            match transform.transform(v)? {
+                Some(next) => v = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(v))
+    }
+}
+
+impl<F> value::Transform for FnTransform<F>
+where
+    F: FnMut(value::Value) -> error::Result<Option<value::Value>>,
+{
+    #[inline]
+    fn transform(&mut self, v: value::Value) -> error::Result<Option<value::Value>> {
+        (self.0)(v)
+    }
+}
+
+impl<S, T> fmt::Debug for Source<S, T>
+where
+    S: fmt::Debug,
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TransformSource")
+            .field("inner", &self.0)
+            .field("transform", &self.1)
+            .finish()
+    }
+}
+
+impl<F> fmt::Debug for FnTransform<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FnTransform").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Source as _;
+    use crate::value::Transform as _;
+    use crate::value::Value;
+
+    fn double() -> impl value::Transform {
+        from_fn(|v| match v {
+            Value::U64(n) => Ok(Some(Value::U64(n * 2))),
+            v => Ok(Some(v)),
+        })
+    }
+
+    fn drop_odd() -> impl value::Transform {
+        from_fn(|v| match v {
+            Value::U64(n) if n % 2 == 1 => Ok(None),
+            v => Ok(Some(v)),
+        })
+    }
+
+    #[test]
+    fn test_chain_order() {
+        let mut drop_then_double = chain(drop_odd(), double());
+        assert_eq!(drop_then_double.transform(Value::U64(1)).unwrap(), None);
+        assert_eq!(
+            drop_then_double.transform(Value::U64(2)).unwrap(),
+            Some(Value::U64(4))
+        );
+
+        let mut double_then_drop: Vec<Box<dyn value::Transform>> =
+            vec![Box::new(double()), Box::new(drop_odd())];
+        assert_eq!(
+            double_then_drop.transform(Value::U64(1)).unwrap(),
+            Some(Value::U64(2))
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        let mut source = source(value::json::source(&b"1 2 3 4"[..]), drop_odd());
+        assert_eq!(source.read().unwrap(), Some(Value::U64(2)));
+        assert_eq!(source.read().unwrap(), Some(Value::U64(4)));
+        assert_eq!(source.read().unwrap(), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let failing = from_fn(|_| Err(error::Error::Message("nope".to_owned())));
+        let mut source = source(value::json::source(&b"1"[..]), failing);
+        assert!(source.read().is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use crate::error;
+use crate::value;
+use serde_json;
+use std::fmt;
+use std::str;
+
+/// What to do with values that the output format can't represent, like null in TOML or nested
+/// maps in CSV.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    /// Fail with an error naming the path and type of the value.
+    Error,
+    /// Leave out the map entry or sequence element, or the whole record if it isn't a map or
+    /// sequence as the format requires.
+    SkipField,
+    /// Replace the value, or the map key, with its JSON representation as a string.
+    Stringify,
+}
+
+/// The values that an output format can't represent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    name: &'static str,
+    record: Record,
+    null: bool,
+    non_string_keys: bool,
+    nested: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Record {
+    Map,
+    Sequence,
+}
+
+/// TOML documents are tables, which can't contain null or have keys that aren't strings.
+pub const TOML: Limits = Limits {
+    name: "TOML",
+    record: Record::Map,
+    null: false,
+    non_string_keys: false,
+    nested: true,
+};
+
+/// CSV rows are sequences of fields, which can't be null, bytes, sequences or maps.
+pub const CSV: Limits = Limits {
+    name: "CSV",
+    record: Record::Sequence,
+    null: false,
+    non_string_keys: true,
+    nested: false,
+};
+
+/// A sink that applies a `Policy` to the values that the format of another sink can't represent
+/// before passing records on.
+pub struct Sink<S>(S, Limits, Policy);
+
+#[inline]
+pub fn sink<S>(inner: S, limits: Limits, policy: Policy) -> Sink<S>
+where
+    S: value::Sink,
+{
+    Sink(inner, limits, policy)
+}
+
+impl<S> value::Sink for Sink<S>
+where
+    S: value::Sink,
+{
+    #[inline]
+    fn write(&mut self, value: value::Value) -> error::Result<()> {
+        match apply(value, self.1, self.2)? {
+            Some(value) => self.0.write(value),
+            None => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn finish(&mut self) -> error::Result<()> {
+        self.0.finish()
+    }
+}
+
+/// Applies the policy to the values of a record that the format can't represent, returning
+/// `None` if the whole record should be skipped.
+pub fn apply(
+    value: value::Value,
+    limits: Limits,
+    policy: Policy,
+) -> error::Result<Option<value::Value>> {
+    use crate::value::Value;
+
+    match (limits.record, value) {
+        (Record::Map, Value::Map(entries)) => {
+            let mut path = Vec::new();
+            Ok(Some(Value::Map(apply_entries(
+                entries, limits, policy, &mut path,
+            )?)))
+        }
+        (Record::Sequence, Value::Sequence(items)) => {
+            let mut path = Vec::new();
+            Ok(Some(Value::Sequence(apply_items(
+                items, limits, policy, &mut path,
+            )?)))
+        }
+        (_, _) if policy == Policy::SkipField => Ok(None),
+        (record, value) => Err(error::Error::Format {
+            msg: format!(
+                "{} can only output records that are {}, not {}",
+                limits.name,
+                match record {
+                    Record::Map => "maps",
+                    Record::Sequence => "sequences",
+                },
+                kind(&value)
+            ),
+        }),
+    }
+}
+
+fn apply_entries(
+    entries: Vec<(value::Value, value::Value)>,
+    limits: Limits,
+    policy: Policy,
+    path: &mut Vec<value::path::Segment>,
+) -> error::Result<Vec<(value::Value, value::Value)>> {
+    let mut result = Vec::with_capacity(entries.len());
+    for (k, v) in entries {
+        let k = match k {
+            value::Value::String(_) => k,
+            k if !limits.non_string_keys => match policy {
+                Policy::Error => {
+                    return Err(error::Error::Format {
+                        msg: format!(
+                            "{} can't represent map keys that are {}, like {} at {}",
+                            limits.name,
+                            kind(&k),
+                            json(&k),
+                            value::path::Path::new(path.clone())
+                        ),
+                    })
+                }
+                Policy::SkipField => continue,
+                Policy::Stringify => value::Value::String(json(&k)),
+            },
+            k => k,
+        };
+
+        let segment = match k {
+            value::Value::String(ref s) => value::path::Segment::Key(s.clone()),
+            ref k => value::path::Segment::Key(json(k)),
+        };
+        path.push(segment);
+        let v = apply_field(v, limits, policy, path)?;
+        path.pop();
+        if let Some(v) = v {
+            result.push((k, v));
+        }
+    }
+    Ok(result)
+}
+
+fn apply_items(
+    items: Vec<value::Value>,
+    limits: Limits,
+    policy: Policy,
+    path: &mut Vec<value::path::Segment>,
+) -> error::Result<Vec<value::Value>> {
+    let mut result = Vec::with_capacity(items.len());
+    for (i, v) in items.into_iter().enumerate() {
+        path.push(value::path::Segment::Index(i as i64));
+        let v = apply_field(v, limits, policy, path)?;
+        path.pop();
+        result.extend(v);
+    }
+    Ok(result)
+}
+
+/// Applies the policy to a value nested inside of a record, returning `None` if it should be
+/// left out.
+fn apply_field(
+    v: value::Value,
+    limits: Limits,
+    policy: Policy,
+    path: &mut Vec<value::path::Segment>,
+) -> error::Result<Option<value::Value>> {
+    use crate::value::Value;
+
+    let representable = match v {
+        Value::Unit => limits.null,
+        Value::Bytes(_) | Value::Sequence(_) | Value::Map(_) => limits.nested,
+        _ => true,
+    };
+    if !representable {
+        return match policy {
+            Policy::Error => Err(error::Error::Format {
+                msg: format!(
+                    "{} can't represent {} at {}; use --on-unrepresentable to skip or \
+                     stringify such values",
+                    limits.name,
+                    kind(&v),
+                    value::path::Path::new(path.clone())
+                ),
+            }),
+            Policy::SkipField => Ok(None),
+            Policy::Stringify => Ok(Some(Value::String(json(&v)))),
+        };
+    }
+
+    Ok(Some(match v {
+        Value::Sequence(items) => Value::Sequence(apply_items(items, limits, policy, path)?),
+        Value::Map(entries) => Value::Map(apply_entries(entries, limits, policy, path)?),
+        v => v,
+    }))
+}
+
+fn kind(v: &value::Value) -> &'static str {
+    use crate::value::Value;
+
+    match *v {
+        Value::Unit => "null",
+        Value::Bool(_) => "booleans",
+        Value::I8(_)
+        | Value::I16(_)
+        | Value::I32(_)
+        | Value::I64(_)
+        | Value::U8(_)
+        | Value::U16(_)
+        | Value::U32(_)
+        | Value::U64(_)
+        | Value::F32(_)
+        | Value::F64(_) => "numbers",
+        Value::Char(_) | Value::String(_) => "strings",
+        Value::Bytes(_) => "bytes",
+        Value::Sequence(_) => "sequences",
+        Value::Map(_) => "maps",
+    }
+}
+
+fn json(v: &value::Value) -> String {
+    serde_json::to_string(v).unwrap_or_else(|_| format!("{:?}", v))
+}
+
+impl str::FromStr for Policy {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Policy::Error),
+            "skip-field" => Ok(Policy::SkipField),
+            "stringify" => Ok(Policy::Stringify),
+            _ => Err(error::Error::Message(format!(
+                "unknown unrepresentable value policy {:?}, expected 'error', 'skip-field' or \
+                 'stringify'",
+                s
+            ))),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Sink<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UnrepresentableSink")
+            .field("inner", &self.0)
+            .field("limits", &self.1)
+            .field("policy", &self.2)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Value;
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn record() -> Value {
+        Value::Map(vec![
+            (s("a"), Value::Unit),
+            (
+                s("b"),
+                Value::Map(vec![
+                    (Value::I64(1), s("one")),
+                    (s("c"), Value::Sequence(vec![Value::I64(2), Value::Unit])),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_skip_field() {
+        assert_eq!(
+            apply(record(), TOML, Policy::SkipField).unwrap(),
+            Some(Value::Map(vec![(
+                s("b"),
+                Value::Map(vec![(s("c"), Value::Sequence(vec![Value::I64(2)]))]),
+            )]))
+        );
+        assert_eq!(apply(Value::I64(1), TOML, Policy::SkipField).unwrap(), None);
+    }
+
+    #[test]
+    fn test_stringify() {
+        assert_eq!(
+            apply(record(), TOML, Policy::Stringify).unwrap(),
+            Some(Value::Map(vec![
+                (s("a"), s("null")),
+                (
+                    s("b"),
+                    Value::Map(vec![
+                        (s("1"), s("one")),
+                        (s("c"), Value::Sequence(vec![Value::I64(2), s("null")])),
+                    ]),
+                ),
+            ]))
+        );
+        assert_eq!(
+            apply(
+                Value::Sequence(vec![
+                    Value::I64(1),
+                    Value::Map(vec![(s("x"), Value::Bool(true))])
+                ]),
+                CSV,
+                Policy::Stringify
+            )
+            .unwrap(),
+            Some(Value::Sequence(vec![Value::I64(1), s(r#"{"x":true}"#)]))
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        let message = |v, limits| match apply(v, limits, Policy::Error) {
+            Err(error::Error::Format { msg }) => msg,
+            r => panic!("expected an error, got {:?}", r),
+        };
+        assert_eq!(
+            message(record(), TOML),
+            "TOML can't represent null at .a; use --on-unrepresentable to skip or stringify \
+             such values"
+        );
+        assert_eq!(
+            message(
+                Value::Map(vec![(s("b"), Value::Map(vec![(Value::I64(1), s("one"))]))]),
+                TOML
+            ),
+            "TOML can't represent map keys that are numbers, like 1 at .b"
+        );
+        assert_eq!(
+            message(Value::Sequence(vec![s("x"), Value::Sequence(vec![])]), CSV),
+            "CSV can't represent sequences at [1]; use --on-unrepresentable to skip or \
+             stringify such values"
+        );
+        assert_eq!(
+            message(record(), CSV),
+            "CSV can only output records that are sequences, not maps"
+        );
+    }
+}
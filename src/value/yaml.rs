@@ -1,13 +1,15 @@
 use crate::error;
 use crate::value;
+use serde_json;
 use serde_yaml;
+use std::collections;
 use std::io;
 
 #[derive(Debug)]
 pub struct Source<R>(Option<R>);
 
 #[derive(Debug)]
-pub struct Sink<W>(W)
+pub struct Sink<W>(W, bool)
 where
     W: io::Write;
 
@@ -24,7 +26,19 @@ pub fn sink<W>(w: W) -> Sink<W>
 where
     W: io::Write,
 {
-    Sink(w)
+    Sink(w, false)
+}
+
+impl<W> Sink<W>
+where
+    W: io::Write,
+{
+    /// Writes repeated maps and sequences only once, marked with an anchor, and refers to that
+    /// anchor with an alias everywhere else.
+    pub fn anchors(mut self, anchors: bool) -> Self {
+        self.1 = anchors;
+        self
+    }
 }
 
 impl<R> value::Source for Source<R>
@@ -50,8 +64,262 @@ where
 {
     #[inline]
     fn write(&mut self, value: value::Value) -> error::Result<()> {
-        serde_yaml::to_writer(&mut self.0, &value)?;
+        let value = bytes_to_sequences(value);
+        if self.1 {
+            let mut emitter = AnchorEmitter::new(&value);
+            emitter.root(&value)?;
+            self.0.write_all(emitter.out.as_bytes())?;
+        } else {
+            serde_yaml::to_writer(&mut self.0, &value)?;
+        }
         self.0.write_all(b"\n")?;
         Ok(())
     }
 }
+
+/// YAML has no native bytes type, so bytes are written as sequences of integers.
+fn bytes_to_sequences(value: value::Value) -> value::Value {
+    match value {
+        value::Value::Bytes(v) => {
+            value::Value::Sequence(v.into_iter().map(value::Value::U8).collect())
+        }
+        value::Value::Sequence(v) => {
+            value::Value::Sequence(v.into_iter().map(bytes_to_sequences).collect())
+        }
+        value::Value::Map(v) => value::Value::Map(
+            v.into_iter()
+                .map(|(k, v)| (bytes_to_sequences(k), bytes_to_sequences(v)))
+                .collect(),
+        ),
+        v => v,
+    }
+}
+
+/// Emits block style YAML with anchors and aliases for repeated subtrees, which `serde_yaml` can't
+/// do.
+struct AnchorEmitter {
+    /// How often each non-empty map or sequence occurs, keyed by its `Debug` representation since
+    /// `Value`'s equality ignores differences that would show in the output, like entry order.
+    counts: collections::HashMap<String, usize>,
+    anchors: collections::HashMap<String, usize>,
+    out: String,
+}
+
+impl AnchorEmitter {
+    fn new(value: &value::Value) -> Self {
+        let mut counts = collections::HashMap::new();
+        count_subtrees(value, &mut counts);
+        AnchorEmitter {
+            counts,
+            anchors: collections::HashMap::new(),
+            out: String::new(),
+        }
+    }
+
+    fn root(&mut self, value: &value::Value) -> error::Result<()> {
+        if is_block(value) {
+            self.block(value, 0)
+        } else {
+            let scalar = flow(value)?;
+            self.out.push_str(&scalar);
+            self.out.push('\n');
+            Ok(())
+        }
+    }
+
+    /// Emits a value after a `key:` or `-` indicator at the given indentation.
+    fn node(&mut self, value: &value::Value, indent: usize, after_dash: bool) -> error::Result<()> {
+        if !is_block(value) {
+            let scalar = flow(value)?;
+            self.out.push(' ');
+            self.out.push_str(&scalar);
+            self.out.push('\n');
+            return Ok(());
+        }
+
+        let key = format!("{:?}", value);
+        if let Some(&id) = self.anchors.get(&key) {
+            self.out.push_str(&format!(" *id{:03}\n", id));
+            return Ok(());
+        }
+
+        if self.counts.get(&key).map_or(false, |&n| n > 1) {
+            let id = self.anchors.len() + 1;
+            self.anchors.insert(key, id);
+            self.out.push_str(&format!(" &id{:03}\n", id));
+            self.indent(indent + 2);
+        } else if after_dash {
+            self.out.push(' ');
+        } else {
+            self.out.push('\n');
+            self.indent(indent + 2);
+        }
+        self.block(value, indent + 2)
+    }
+
+    /// Emits the entries of a non-empty map or sequence, where the indentation of the first entry
+    /// has already been written.
+    fn block(&mut self, value: &value::Value, indent: usize) -> error::Result<()> {
+        match *value {
+            value::Value::Map(ref entries) => {
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.indent(indent);
+                    }
+                    let key = flow(k)?;
+                    self.out.push_str(&key);
+                    self.out.push(':');
+                    self.node(v, indent, false)?;
+                }
+            }
+            value::Value::Sequence(ref seq) => {
+                for (i, v) in seq.iter().enumerate() {
+                    if i > 0 {
+                        self.indent(indent);
+                    }
+                    self.out.push('-');
+                    self.node(v, indent, true)?;
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn indent(&mut self, indent: usize) {
+        self.out.extend(std::iter::repeat(' ').take(indent));
+    }
+}
+
+fn is_block(value: &value::Value) -> bool {
+    match *value {
+        value::Value::Map(ref entries) => !entries.is_empty(),
+        value::Value::Sequence(ref seq) => !seq.is_empty(),
+        _ => false,
+    }
+}
+
+fn count_subtrees(value: &value::Value, counts: &mut collections::HashMap<String, usize>) {
+    if !is_block(value) {
+        return;
+    }
+
+    let count = counts.entry(format!("{:?}", value)).or_insert(0);
+    *count += 1;
+    if *count > 1 {
+        // The children were already counted the first time around
+        return;
+    }
+
+    match *value {
+        value::Value::Map(ref entries) => {
+            for (k, v) in entries {
+                count_subtrees(k, counts);
+                count_subtrees(v, counts);
+            }
+        }
+        value::Value::Sequence(ref seq) => {
+            for v in seq {
+                count_subtrees(v, counts);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Formats a value on a single line, for scalars and map keys.
+fn flow(value: &value::Value) -> error::Result<String> {
+    let result = serde_yaml::to_string(value)?;
+    let result = result.trim_end_matches('\n');
+    if result.contains('\n') {
+        // Block scalars and nested values can't be written inline, but JSON is valid flow YAML
+        Ok(serde_json::to_string(value)?)
+    } else {
+        Ok(result.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::Sink as _;
+    use crate::value::Source as _;
+    use crate::value::Value;
+
+    #[test]
+    fn test_bytes_as_sequences() {
+        let mut out = Vec::new();
+        sink(&mut out)
+            .write(value::Value::Map(vec![(
+                value::Value::String("data".to_owned()),
+                value::Value::Bytes(vec![1, 2]),
+            )]))
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "data:\n- 1\n- 2\n\n");
+    }
+
+    fn s(v: &str) -> Value {
+        Value::String(v.to_owned())
+    }
+
+    fn write_anchored(value: Value) -> String {
+        let mut out = Vec::new();
+        sink(&mut out).anchors(true).write(value).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_anchors() {
+        let server = Value::Map(vec![
+            (s("host"), s("example.com")),
+            (
+                s("ports"),
+                Value::Sequence(vec![Value::U16(80), Value::U16(443)]),
+            ),
+        ]);
+        let value = Value::Map(vec![
+            (s("primary"), server.clone()),
+            (s("backups"), Value::Sequence(vec![server.clone(), server])),
+            (s("note"), s("multi\nline")),
+            (s("empty"), Value::Sequence(vec![])),
+        ]);
+
+        let out = write_anchored(value.clone());
+        assert_eq!(
+            out,
+            "primary: &id001\n\
+             \x20 host: example.com\n\
+             \x20 ports:\n\
+             \x20   - 80\n\
+             \x20   - 443\n\
+             backups:\n\
+             \x20 - *id001\n\
+             \x20 - *id001\n\
+             note: \"multi\\nline\"\n\
+             empty: []\n\n"
+        );
+        assert_eq!(source(out.as_bytes()).read().unwrap(), Some(value));
+    }
+
+    #[test]
+    fn test_anchors_only_for_identical_subtrees() {
+        // Equal according to `Value`, but written differently
+        let value = Value::Sequence(vec![
+            Value::Map(vec![(s("a"), Value::I32(1)), (s("b"), Value::I32(2))]),
+            Value::Map(vec![(s("b"), Value::I32(2)), (s("a"), Value::I32(1))]),
+            Value::Sequence(vec![Value::Unit]),
+            Value::Sequence(vec![Value::Unit]),
+        ]);
+
+        assert_eq!(
+            write_anchored(value),
+            "- a: 1\n\
+             \x20 b: 2\n\
+             - b: 2\n\
+             \x20 a: 1\n\
+             - &id001\n\
+             \x20 - null\n\
+             - *id001\n\n"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::error;
+
+use std::collections;
+use std::fs;
+use std::path;
+use std::time;
+
+/// Finds new and changed files in a directory by polling it.
+///
+/// A file is only reported once it has stopped changing between two polls, so that files that are
+/// still being written are not picked up too early.  Hidden files (starting with `.`) are ignored.
+#[derive(Debug)]
+pub struct Watcher {
+    dir: path::PathBuf,
+    pending: collections::HashMap<path::PathBuf, Stamp>,
+    done: collections::HashMap<path::PathBuf, Stamp>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Stamp {
+    modified: time::SystemTime,
+    len: u64,
+}
+
+impl Watcher {
+    pub fn new<P>(dir: P) -> Self
+    where
+        P: Into<path::PathBuf>,
+    {
+        Watcher {
+            dir: dir.into(),
+            pending: collections::HashMap::new(),
+            done: collections::HashMap::new(),
+        }
+    }
+
+    /// Returns the files that are ready to be processed, in name order.
+    pub fn poll(&mut self) -> error::Result<Vec<path::PathBuf>> {
+        let mut current = collections::HashMap::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                let stamp = Stamp {
+                    modified: metadata.modified()?,
+                    len: metadata.len(),
+                };
+                current.insert(entry.path(), stamp);
+            }
+        }
+
+        let mut ready = Vec::new();
+        for (path, stamp) in &current {
+            if self.done.get(path) == Some(stamp) {
+                continue;
+            }
+            if self.pending.get(path) == Some(stamp) {
+                ready.push(path.clone());
+            }
+        }
+        ready.sort();
+
+        for path in &ready {
+            self.done.insert(path.clone(), current[path]);
+        }
+        // Deleted files are forgotten, so that they are processed again if they reappear
+        self.done.retain(|path, _| current.contains_key(path));
+        self.pending = current;
+
+        Ok(ready)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_poll() {
+        let dir = env::temp_dir().join(format!("rq-watch-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut watcher = Watcher::new(&dir);
+        fs::write(dir.join("b.json"), "1").unwrap();
+        fs::write(dir.join("a.json"), "2").unwrap();
+        fs::write(dir.join(".hidden"), "3").unwrap();
+
+        // Files have to be unchanged between two polls to be ready
+        assert!(watcher.poll().unwrap().is_empty());
+        assert_eq!(
+            watcher.poll().unwrap(),
+            vec![dir.join("a.json"), dir.join("b.json")]
+        );
+        assert!(watcher.poll().unwrap().is_empty());
+
+        fs::write(dir.join("a.json"), "22").unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        assert_eq!(watcher.poll().unwrap(), vec![dir.join("a.json")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
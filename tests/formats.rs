@@ -0,0 +1,271 @@
+//! Round-trips a shared corpus of edge-case records through every format's `Sink` and `Source`.
+//!
+//! The encoded output of each sink is compared against a golden file in `tests/golden`.  Run with
+//! `RQ_UPDATE_GOLDEN=1` to regenerate the golden files after an intentional format change.
+
+use record_query::value;
+use record_query::value::Value;
+use std::env;
+use std::fs;
+use std::path;
+
+fn s(v: &str) -> Value {
+    Value::String(v.to_owned())
+}
+
+fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::Map(entries.into_iter().map(|(k, v)| (s(k), v)).collect())
+}
+
+fn nested(depth: usize) -> Value {
+    (0..depth).fold(Value::Bool(true), |v, i| {
+        if i % 2 == 0 {
+            Value::Sequence(vec![v])
+        } else {
+            map(vec![("n", v)])
+        }
+    })
+}
+
+/// Records that every self-describing format can represent.
+fn corpus() -> Vec<Value> {
+    vec![
+        Value::Unit,
+        Value::Bool(false),
+        Value::I64(i64::MIN),
+        Value::U64(u64::MAX),
+        Value::from_f64(-1.5e300),
+        s(""),
+        s("héllo wörld ✓ 🦀"),
+        s("control \u{0} \t \" \\ characters"),
+        Value::Sequence(vec![]),
+        Value::Map(vec![]),
+        nested(32),
+        map(vec![
+            ("unicode ключ", s("значение")),
+            (
+                "numbers",
+                Value::Sequence(vec![Value::I8(-1), Value::from_f64(0.25)]),
+            ),
+            ("empty", map(vec![])),
+        ]),
+    ]
+}
+
+/// Records that need a binary format to be represented losslessly.
+fn binary_corpus() -> Vec<Value> {
+    vec![
+        Value::Bytes(vec![]),
+        Value::Bytes(vec![0, 1, 2, 0xfe, 0xff]),
+        Value::Map(vec![(Value::I32(1), Value::Bytes(b"one".to_vec()))]),
+    ]
+}
+
+fn write_all<S>(mut sink: S, values: Vec<Value>)
+where
+    S: value::Sink,
+{
+    for v in values {
+        sink.write(v).unwrap();
+    }
+}
+
+fn read_all<S>(mut source: S) -> Vec<Value>
+where
+    S: value::Source,
+{
+    let mut result = Vec::new();
+    while let Some(v) = source.read().unwrap() {
+        result.push(v);
+    }
+    result
+}
+
+fn check_golden(name: &str, actual: &[u8]) {
+    let path = path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(name);
+
+    if env::var_os("RQ_UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+    } else {
+        let expected = fs::read(&path).unwrap();
+        assert!(
+            expected == actual,
+            "output differs from golden file {:?}:\n{}",
+            path,
+            String::from_utf8_lossy(actual)
+        );
+    }
+}
+
+#[test]
+fn test_json() {
+    let mut out = Vec::new();
+    write_all(value::json::sink_compact(&mut out), corpus());
+    check_golden("corpus.json", &out);
+    assert_eq!(read_all(value::json::source(out.as_slice())), corpus());
+}
+
+#[test]
+fn test_json_indented() {
+    let mut out = Vec::new();
+    write_all(value::json::sink_indented(&mut out), corpus());
+    check_golden("corpus.indented.json", &out);
+    assert_eq!(read_all(value::json::source(out.as_slice())), corpus());
+}
+
+#[test]
+fn test_json_raw_strings() {
+    let mut out = Vec::new();
+    write_all(
+        value::json::sink_compact(&mut out).raw_strings(true),
+        vec![
+            s("a \"quoted\"\tstring"),
+            Value::Char('x'),
+            map(vec![("k", s("v"))]),
+        ],
+    );
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "a \"quoted\"\tstring\nx\n{\"k\":\"v\"}\n"
+    );
+}
+
+#[test]
+fn test_cbor() {
+    let mut out = Vec::new();
+    write_all(value::cbor::sink(&mut out), corpus());
+    write_all(value::cbor::sink(&mut out), binary_corpus());
+    check_golden("corpus.cbor", &out);
+
+    let mut expected = corpus();
+    expected.extend(binary_corpus());
+    assert_eq!(read_all(value::cbor::source(out.as_slice())), expected);
+}
+
+#[test]
+fn test_message_pack() {
+    let mut out = Vec::new();
+    write_all(value::messagepack::sink(&mut out), corpus());
+    write_all(value::messagepack::sink(&mut out), binary_corpus());
+    check_golden("corpus.msgpack", &out);
+
+    let mut expected = corpus();
+    expected.extend(binary_corpus());
+    assert_eq!(
+        read_all(value::messagepack::source(out.as_slice())),
+        expected
+    );
+}
+
+#[test]
+fn test_yaml() {
+    // The YAML source reads a single document, so each record is round-tripped on its own
+    let mut golden = Vec::new();
+    for (record, expected) in corpus().into_iter().zip(corpus()) {
+        let mut out = Vec::new();
+        write_all(value::yaml::sink(&mut out), vec![record]);
+        assert_eq!(
+            read_all(value::yaml::source(out.as_slice())),
+            vec![expected]
+        );
+        golden.extend(b"---\n");
+        golden.extend(out);
+    }
+    check_golden("corpus.yaml", &golden);
+}
+
+#[test]
+fn test_toml() {
+    let record = || {
+        map(vec![
+            ("string", s("héllo wörld ✓ 🦀")),
+            ("int", Value::I64(i64::MIN)),
+            ("float", Value::from_f64(0.25)),
+            ("array", Value::Sequence(vec![Value::I64(1), Value::I64(2)])),
+            ("table", map(vec![("empty", map(vec![]))])),
+        ])
+    };
+
+    let mut out = Vec::new();
+    write_all(value::toml::sink(&mut out), vec![record()]);
+    check_golden("record.toml", &out);
+    assert_eq!(
+        read_all(value::toml::source(out.as_slice()).unwrap()),
+        vec![record()]
+    );
+}
+
+#[test]
+fn test_csv() {
+    let mut out = Vec::new();
+    write_all(
+        value::csv::sink(&mut out),
+        vec![
+            Value::Sequence(vec![s("héllo, wörld"), s("with \"quotes\""), s("")]),
+            Value::Sequence(vec![
+                Value::U64(u64::MAX),
+                Value::Bool(true),
+                Value::Char('x'),
+            ]),
+        ],
+    );
+    check_golden("table.csv", &out);
+
+    // CSV values always come back as strings
+    assert_eq!(
+        read_all(value::csv::source(out.as_slice())),
+        vec![
+            Value::Sequence(vec![s("héllo, wörld"), s("with \"quotes\""), s("")]),
+            Value::Sequence(vec![s("18446744073709551615"), s("true"), s("x")]),
+        ]
+    );
+}
+
+#[test]
+fn test_raw() {
+    let lines = || vec![s("héllo wörld ✓ 🦀"), s(""), s("\ttabs\t")];
+
+    let mut out = Vec::new();
+    write_all(value::raw::sink(&mut out), lines());
+    check_golden("lines.txt", &out);
+    assert_eq!(read_all(value::raw::source(out.as_slice())), lines());
+}
+
+#[test]
+fn test_avro() {
+    // Avro containers embed a random sync marker, so there is no golden file
+    let schema = avro_rs::Schema::parse_str(
+        r#"{
+            "type": "record",
+            "name": "Record",
+            "fields": [
+                {"name": "string", "type": "string"},
+                {"name": "long", "type": "long"},
+                {"name": "bytes", "type": "bytes"},
+                {"name": "array", "type": {"type": "array", "items": "double"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let record = || {
+        map(vec![
+            ("string", s("héllo wörld ✓ 🦀")),
+            ("long", Value::I64(i64::MIN)),
+            ("bytes", Value::Bytes(vec![0, 0xff])),
+            ("array", Value::Sequence(vec![Value::from_f64(0.25)])),
+        ])
+    };
+
+    let mut out = Vec::new();
+    write_all(
+        value::avro::sink(&schema, &mut out, avro_rs::Codec::Null).unwrap(),
+        vec![record(), record()],
+    );
+    assert_eq!(
+        read_all(value::avro::source(out.as_slice()).unwrap()),
+        vec![record(), record()]
+    );
+}
@@ -0,0 +1,154 @@
+//! Property tests asserting that encoding and then decoding a `Value` gives back the same value.
+//!
+//! Not every format can represent every value, so each format has a `lossy_*` function that
+//! documents how values are expected to change when round-tripped.  If a format starts losing
+//! more (or less) information than documented, these tests fail.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use record_query::value;
+use record_query::value::Value;
+
+fn scalar() -> BoxedStrategy<Value> {
+    prop_oneof![
+        Just(()).prop_map(|_| Value::Unit),
+        any::<bool>().prop_map(Value::Bool),
+        any::<i8>().prop_map(Value::I8),
+        any::<i16>().prop_map(Value::I16),
+        any::<i32>().prop_map(Value::I32),
+        any::<i64>().prop_map(Value::I64),
+        any::<u8>().prop_map(Value::U8),
+        any::<u16>().prop_map(Value::U16),
+        any::<u32>().prop_map(Value::U32),
+        any::<u64>().prop_map(Value::U64),
+        any::<f32>().prop_map(Value::from_f32),
+        any::<f64>().prop_map(Value::from_f64),
+        any::<char>().prop_map(Value::Char),
+        any::<String>().prop_map(Value::String),
+        vec(any::<u8>(), 0..16).prop_map(Value::Bytes),
+    ]
+    .boxed()
+}
+
+fn string_key() -> BoxedStrategy<Value> {
+    any::<String>().prop_map(Value::String).boxed()
+}
+
+/// Generates arbitrary values, using the given strategy for map keys.
+fn value_with_keys(keys: BoxedStrategy<Value>) -> BoxedStrategy<Value> {
+    scalar()
+        .prop_recursive(4, 64, 8, move |inner| {
+            prop_oneof![
+                vec(inner.clone(), 0..8).prop_map(Value::Sequence),
+                vec((keys.clone(), inner), 0..8).prop_map(Value::Map),
+            ]
+        })
+        .boxed()
+}
+
+/// Applies `f` to every scalar in the value, including map keys.
+fn map_scalars<F>(value: Value, f: &F) -> Value
+where
+    F: Fn(Value) -> Value,
+{
+    match value {
+        Value::Sequence(v) => Value::Sequence(v.into_iter().map(|v| map_scalars(v, f)).collect()),
+        Value::Map(v) => Value::Map(
+            v.into_iter()
+                .map(|(k, v)| (map_scalars(k, f), map_scalars(v, f)))
+                .collect(),
+        ),
+        v => f(v),
+    }
+}
+
+/// Floats are written with the shortest representation that round-trips the float's own width
+/// (as computed by `ryu`), but text formats read all floats back as `f64`.
+fn widen_f32(v: f32) -> Value {
+    Value::from_f64(ryu::Buffer::new().format_finite(v).parse().unwrap())
+}
+
+fn bytes_to_sequence(v: Vec<u8>) -> Value {
+    Value::Sequence(v.into_iter().map(Value::U8).collect())
+}
+
+/// JSON has no chars, bytes or non-finite floats.
+fn lossy_json(value: Value) -> Value {
+    map_scalars(value, &|v| match v {
+        Value::F32(f) if !f.is_finite() => Value::Unit,
+        Value::F64(f) if !f.is_finite() => Value::Unit,
+        Value::F32(f) => widen_f32(f.0),
+        Value::Char(c) => Value::String(c.to_string()),
+        Value::Bytes(b) => bytes_to_sequence(b),
+        v => v,
+    })
+}
+
+/// YAML has no chars or bytes.
+fn lossy_yaml(value: Value) -> Value {
+    map_scalars(value, &|v| match v {
+        Value::F32(f) if f.is_finite() => widen_f32(f.0),
+        Value::Char(c) => Value::String(c.to_string()),
+        Value::Bytes(b) => bytes_to_sequence(b),
+        v => v,
+    })
+}
+
+/// CBOR and MessagePack have no chars.
+fn lossy_binary(value: Value) -> Value {
+    map_scalars(value, &|v| match v {
+        Value::Char(c) => Value::String(c.to_string()),
+        v => v,
+    })
+}
+
+fn write<S>(mut sink: S, value: Value)
+where
+    S: value::Sink,
+{
+    sink.write(value).unwrap();
+}
+
+fn read_all<S>(mut source: S) -> Vec<Value>
+where
+    S: value::Source,
+{
+    let mut result = Vec::new();
+    while let Some(v) = source.read().unwrap() {
+        result.push(v);
+    }
+    result
+}
+
+proptest! {
+    #[test]
+    fn test_json(v in value_with_keys(string_key())) {
+        let mut out = Vec::new();
+        write(value::json::sink_compact(&mut out), v.clone());
+        prop_assert_eq!(read_all(value::json::source(out.as_slice())), vec![lossy_json(v)]);
+    }
+
+    #[test]
+    fn test_cbor(v in value_with_keys(scalar())) {
+        let mut out = Vec::new();
+        write(value::cbor::sink(&mut out), v.clone());
+        prop_assert_eq!(read_all(value::cbor::source(out.as_slice())), vec![lossy_binary(v)]);
+    }
+
+    #[test]
+    fn test_message_pack(v in value_with_keys(scalar())) {
+        let mut out = Vec::new();
+        write(value::messagepack::sink(&mut out), v.clone());
+        prop_assert_eq!(
+            read_all(value::messagepack::source(out.as_slice())),
+            vec![lossy_binary(v)]
+        );
+    }
+
+    #[test]
+    fn test_yaml(v in value_with_keys(string_key())) {
+        let mut out = Vec::new();
+        write(value::yaml::sink(&mut out), v.clone());
+        prop_assert_eq!(read_all(value::yaml::source(out.as_slice())), vec![lossy_yaml(v)]);
+    }
+}